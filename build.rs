@@ -0,0 +1,70 @@
+// Captures information about the build so a running instance can tell which build it is (see `/version`).
+// Everything degrades to "unknown" instead of failing the build, e.g. when building outside a git checkout.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let git_hash = command_output("git", &["rev-parse", "--short=12", "HEAD"]);
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+
+    println!("cargo:rustc-env=PASTRY_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=PASTRY_BUILD_TIMESTAMP={}", build_timestamp());
+    println!("cargo:rustc-env=PASTRY_RUSTC_VERSION={}", rustc_version);
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+// Runs a command and returns its trimmed stdout, or "unknown" if it can't be run or fails.
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|stdout| stdout.trim().to_string())
+        .filter(|stdout| !stdout.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// The current time as an RFC 3339 UTC timestamp, e.g. "2024-01-31T12:00:00Z".
+// Honors SOURCE_DATE_EPOCH for reproducible builds.
+fn build_timestamp() -> String {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0)
+        });
+
+    let days = secs.div_euclid(86400);
+    let time = secs.rem_euclid(86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
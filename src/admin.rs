@@ -0,0 +1,287 @@
+// The maintenance routes of the admin, under “/admin” next to those of the bans, tokens, announcements, review queue
+// and federation (each in its module): backups, the archive, the database report, the purge, the integrity check,
+// the audit log and the tokens colliding with reserved words. Every one needs the admin token, see `require_admin`.
+
+use crate::error::AppError;
+use crate::{admin_template, audit_entry, day_start, escape_html, format_bytes, purge, purge_detail, record_audit};
+use crate::{backup, integrity, require_admin, reserved, store, store_write, telemetry, timestamp, AppState, DbQuery};
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+// Most entries the admin audit log page shows, the newest of those asked for.
+const AUDIT_LOG_LIMIT: i64 = 500;
+
+// The maintenance routes, which `internal_only` keeps to `--internal-bind` when there is one.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/admin/backup"), web::post().to(admin_backup))
+        .route(reserved::route("/admin/archive"), web::get().to(admin_archive))
+        .route(reserved::route("/admin/purge"), web::post().to(admin_purge))
+        .route(reserved::route("/admin/integrity-check"), web::post().to(admin_integrity_check))
+        .route(reserved::route("/admin/db"), web::get().to(admin_db))
+        .route(reserved::route("/admin/audit"), web::get().to(admin_audit))
+        .route(reserved::route("/admin/reserved"), web::get().to(admin_reserved));
+}
+
+// Handles “POST /admin/backup”, an online snapshot of the database made with `VACUUM INTO`.
+// The backup is written to the backup directory under a timestamped name, old backups are pruned if configured.
+// Answers with the path and size of the backup as JSON, or with the backup file itself when `?download=true` is given.
+async fn admin_backup(req: HttpRequest, query: web::Query<BackupQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let db_path = data
+        .store
+        .sqlite_path()
+        .ok_or_else(|| AppError::bad_request("Backups are only supported with the SQLite store"))?
+        .to_path_buf();
+
+    let state = data.clone();
+    let result = web::block(move || {
+        let _running = state.backup_lock.lock().unwrap();
+        backup::run(&db_path, &state.backup_dir, state.settings().backup_keep)
+    })
+    .await;
+
+    let backup = match result {
+        Ok(backup) => backup,
+        Err(actix_web::error::BlockingError::Error(e)) => {
+            eprintln!("Backup failed: {}", e);
+            return Err(AppError::internal(format!("Backup failed: {}", e)));
+        }
+        Err(actix_web::error::BlockingError::Canceled) => {
+            return Err(AppError::internal("Backup failed: the backup thread was canceled"));
+        }
+    };
+    println!("Backup written to {} ({} bytes)", backup.path.display(), backup.size_bytes);
+    let audit = audit_entry(&req, &data, "admin", "backup", None, format!("{} bytes", backup.size_bytes));
+    store_write(&data, move |store| {
+        record_audit(store, audit);
+        Ok(())
+    })
+    .await?;
+
+    if query.download.unwrap_or(false) {
+        let file = NamedFile::open(&backup.path).map_err(|e| AppError::internal(format!("Failed to read backup: {}", e)))?;
+        return file
+            .into_response(&req)
+            .map_err(|e| AppError::internal(format!("Failed to send backup: {}", e)));
+    }
+
+    Ok(HttpResponse::Ok().json(BackupInfo {
+        path: backup.path.display().to_string(),
+        size_bytes: backup.size_bytes,
+        pruned: backup.pruned.iter().map(|path| path.display().to_string()).collect(),
+    }))
+}
+
+// Handles “GET /admin/archive”, how many pastes and bytes of content are in the hot table and in the archive, as JSON.
+async fn admin_archive(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+    Ok(HttpResponse::Ok().json(data.store.archive_stats()?))
+}
+
+// Handles “GET /admin/reserved”, the reserved words and the pastes whose token is one of them, as JSON.
+// Those were created before their word was added to the list: they are still served, their routes come first,
+// but can't be replaced through “PUT /api/pastes/{token}” anymore. Moving one elsewhere is creating it again
+// under another token and deleting it.
+async fn admin_reserved(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+    let mut colliding = Vec::new();
+    for word in reserved::RESERVED {
+        if data.store.exists(word)? {
+            colliding.push(ApiReservedToken {
+                token: word.to_string(),
+                url: format!("/paste/{}", word),
+            });
+        }
+    }
+    Ok(HttpResponse::Ok().json(ApiReserved {
+        reserved: reserved::RESERVED,
+        colliding,
+    }))
+}
+
+// Handles “GET /admin/db”, the sizes of the tables and of the database, its largest pastes and its indexes,
+// to tell when to vacuum or archive. A page by default, the same numbers as JSON with `?format=json`.
+async fn admin_db(req: HttpRequest, query: web::Query<DbQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let stats = data.store.db_stats()?;
+    if query.format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok().json(stats));
+    }
+
+    let _render = telemetry::template("admin_db.html");
+    let html_page = admin_template(&data, include_str!("admin_db.html"))
+        .replace("{{backend}}", data.store.backend())
+        .replace("{{report}}", &escape_html(&db_report(&stats)));
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// The text of the admin database page, every number with its unit.
+fn db_report(stats: &store::DbStats) -> String {
+    let bytes = |bytes: Option<i64>| match bytes {
+        Some(bytes) if bytes < 1024 => format_bytes(bytes),
+        Some(bytes) => format!("{} ({} bytes)", format_bytes(bytes), bytes),
+        None => "n/a".to_string(),
+    };
+
+    let mut report = String::from("Tables\n");
+    for table in &stats.tables {
+        report.push_str(&format!("  {:<24} {:>10} rows\n", table.table, table.rows));
+    }
+    report.push_str(&format!("\nDatabase size    {}\n", bytes(stats.file_bytes)));
+    report.push_str(&format!("WAL file         {}\n", bytes(stats.wal_bytes)));
+    if let (Some(page_size), Some(pages), Some(free_pages)) = (stats.page_size_bytes, stats.pages, stats.free_pages) {
+        report.push_str(&format!(
+            "Pages            {} pages of {} bytes, {} free ({:.1}%, {})\n",
+            pages,
+            page_size,
+            free_pages,
+            free_pages as f64 * 100.0 / pages.max(1) as f64,
+            format_bytes(free_pages * page_size)
+        ));
+    }
+
+    report.push_str("\nLargest pastes\n");
+    for paste in &stats.largest_pastes {
+        report.push_str(&format!("  {:<24} {}\n", paste.token, bytes(Some(paste.bytes))));
+    }
+    report.push_str("\nIndexes\n");
+    for index in &stats.indexes {
+        let scans = match index.scans {
+            Some(scans) => format!("{} scans", scans),
+            None => "scans not counted".to_string(),
+        };
+        report.push_str(&format!("  {:<56} {}\n", format!("{}.{}", index.table, index.index), scans));
+    }
+    report
+}
+
+// Handles “POST /admin/purge”, the cleanup of `purge` on demand, with `?dry_run=true` only counting what it would delete.
+// Answers with the counts as JSON.
+async fn admin_purge(req: HttpRequest, query: web::Query<PurgeQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let dry_run = query.dry_run.unwrap_or(false);
+    let mut audit = audit_entry(&req, &data, "admin", "purge", None, String::new());
+    let counts = store_write(&data, move |store| {
+        let counts = purge(store, dry_run)?;
+        if !dry_run {
+            audit.detail = purge_detail(&counts);
+            record_audit(store, audit);
+        }
+        Ok(counts)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(counts))
+}
+
+// Handles “POST /admin/integrity-check”, the checks of `integrity.rs`, with `?repair=true` repairing what they find.
+// Answers with the report as JSON, which the audit log keeps the counts of.
+async fn admin_integrity_check(req: HttpRequest, query: web::Query<IntegrityQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let repair = query.repair.unwrap_or(false);
+    let mut audit = audit_entry(&req, &data, "admin", "integrity_check", None, String::new());
+    let report = store_write(&data, move |store| {
+        let report = integrity::check(store, repair)?;
+        audit.detail = report.summary();
+        record_audit(store, audit);
+        Ok(report)
+    })
+    .await?;
+    for token in &report.fixed_tokens {
+        data.cache.remove(token);
+    }
+    Ok(HttpResponse::Ok().json(report))
+}
+
+// Handles “GET /admin/audit”, the newest entries of the audit log, `?action=` only those of one action,
+// `?since=` and `?until=` (both YYYY-MM-DD, UTC, included) only those of some days.
+// A page by default, the entries as JSON with `?format=json`.
+async fn admin_audit(req: HttpRequest, query: web::Query<AuditLogQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let audit_query = store::AuditQuery {
+        action: query.action.clone().filter(|action| !action.is_empty()),
+        since: day_start("since", &query.since, 0)?,
+        until: day_start("until", &query.until, 1)?,
+        limit: AUDIT_LOG_LIMIT,
+    };
+    let entries = data.store.audit_log(&audit_query)?;
+    if query.format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok().json(entries));
+    }
+
+    let mut report = String::new();
+    for entry in &entries {
+        let line = format!(
+            "{}  {:<8} {:<8} {:<24} {}",
+            timestamp::absolute(entry.at),
+            entry.actor,
+            entry.action,
+            entry.target.as_deref().unwrap_or("-"),
+            entry.detail
+        );
+        report.push_str(line.trim_end());
+        report.push('\n');
+    }
+    if entries.is_empty() {
+        report.push_str("No entries\n");
+    }
+    let field = |value: &Option<String>| escape_html(value.as_deref().unwrap_or(""));
+    let _render = telemetry::template("admin_audit.html");
+    let html_page = admin_template(&data, include_str!("admin_audit.html"))
+        .replace("{{action}}", &field(&query.action))
+        .replace("{{since}}", &field(&query.since))
+        .replace("{{until}}", &field(&query.until))
+        .replace("{{report}}", &escape_html(&report));
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+#[derive(serde::Serialize)]
+struct ApiReserved {
+    reserved: &'static [&'static str],
+    colliding: Vec<ApiReservedToken>,
+}
+
+#[derive(serde::Serialize)]
+struct ApiReservedToken {
+    token: String,
+    url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct AuditLogQuery {
+    action: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    // "json" for the JSON variant
+    format: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PurgeQuery {
+    dry_run: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+struct IntegrityQuery {
+    repair: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+struct BackupQuery {
+    download: Option<bool>,
+}
+
+#[derive(serde::Serialize)]
+struct BackupInfo {
+    path: String,
+    size_bytes: u64,
+    pruned: Vec<String>,
+}
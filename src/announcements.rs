@@ -0,0 +1,343 @@
+// Announcements of the instance, a maintenance window coming say, shown as a banner on top of the index page and of
+// the pages of pastes while they are on: from `starts_at` until `ends_at`, for good without one. The admin adds,
+// changes and removes them through “/admin/announcements”. Visitors can dismiss one, which is kept in the cookie
+// `announcement_{id}`: a changed message stays dismissed, a new announcement shows. The button is a form posting to
+// “/announcements/{id}/dismiss”, which sets the cookie and sends back to the page, so it needs no JavaScript.
+// Messages take a little markup, `**bold**` and `[a link](https://…)`; everything else is shown as it was written.
+// Pages read them from `Board`, kept in memory like the bans: loaded at startup, again after every change made through
+// this instance and every `REFRESH_INTERVAL` for the changes made through others. An announcement stops showing at its
+// end, the cleanup deletes it later.
+
+use crate::error::AppError;
+use crate::i18n::Texts;
+use crate::store::{self, Announcement};
+use crate::{admin_name, admin_template, audit_entry, escape_html, local_path, refresh_announcements, require_admin};
+use crate::{reserved, store_write, telemetry, timestamp, AppState, Caller, DbQuery};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Most characters of a message.
+pub const MAX_MESSAGE_CHARS: usize = 500;
+
+// How long a dismissal is kept of an announcement without an end, a year.
+const DISMISS_SECS: i64 = 365 * 24 * 60 * 60;
+
+// How an announcement looks, and how loudly it is read out: a warning is a `role="alert"`.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Level {
+    #[default]
+    Info,
+    Warning,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warning => "warning",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Level, String> {
+        match value {
+            "info" => Ok(Level::Info),
+            "warning" => Ok(Level::Warning),
+            _ => Err(format!("\"{}\" is not a level, use \"info\" or \"warning\"", value)),
+        }
+    }
+}
+
+// The message of an announcement as it is stored: trimmed, not empty, at most `MAX_MESSAGE_CHARS` and on one line.
+pub fn check_message(message: &str) -> Result<String, String> {
+    let message = message.trim();
+    if message.is_empty() || message.chars().count() > MAX_MESSAGE_CHARS || message.chars().any(char::is_control) {
+        return Err(format!(
+            "A message is 1 to {} characters, without control characters",
+            MAX_MESSAGE_CHARS
+        ));
+    }
+    Ok(message.to_string())
+}
+
+// The announcements that haven't ended, as the pages read them.
+#[derive(Default)]
+pub struct Board {
+    announcements: Vec<Announcement>,
+}
+
+impl Board {
+    pub fn new(announcements: Vec<Announcement>) -> Board {
+        Board { announcements }
+    }
+
+    // The announcements on at `now`, by when they started.
+    pub fn current(&self, now: i64) -> impl Iterator<Item = &Announcement> {
+        self.announcements.iter().filter(move |announcement| {
+            announcement.starts_at <= now && announcement.ends_at.is_none_or(|ends_at| ends_at > now)
+        })
+    }
+
+    pub fn get(&self, id: i64) -> Option<&Announcement> {
+        self.announcements.iter().find(|announcement| announcement.id == id)
+    }
+
+    // The banners of a page: the announcements on that its visitor hasn't dismissed, nothing without any.
+    pub fn banners(&self, req: &HttpRequest, now: i64, texts: Texts) -> String {
+        let back = crate::back_path(req);
+        self.current(now)
+            .filter(|announcement| !dismissed(req, announcement.id))
+            .map(|announcement| {
+                let level = announcement.level.parse::<Level>().unwrap_or_default();
+                format!(
+                    "<div class=\"announcement announcement-{level}\" role=\"{role}\"><p>{message}</p>\
+                     <form method=\"post\" action=\"/announcements/{id}/dismiss\">\
+                     <input type=\"hidden\" name=\"back\" value=\"{back}\">\
+                     <button type=\"submit\" title=\"{dismiss}\" aria-label=\"{dismiss}\">×</button></form></div>",
+                    level = level.as_str(),
+                    role = if level == Level::Warning { "alert" } else { "status" },
+                    message = render(&announcement.message),
+                    id = announcement.id,
+                    back = escape_html(&back),
+                    dismiss = escape_html(texts.text("announcement.dismiss")),
+                )
+            })
+            .collect()
+    }
+}
+
+pub fn cookie_name(id: i64) -> String {
+    format!("announcement_{}", id)
+}
+
+// Whether the visitor of `req` dismissed the announcement `id`.
+fn dismissed(req: &HttpRequest, id: i64) -> bool {
+    crate::client::cookies(req.headers(), &cookie_name(id)).next().is_some()
+}
+
+// The `Set-Cookie` header dismissing `announcement`, kept until it ends, `Secure` when the request came over HTTPS.
+pub fn dismiss_cookie(announcement: &Announcement, now: i64, secure: bool) -> String {
+    let max_age = announcement.ends_at.map_or(DISMISS_SECS, |ends_at| (ends_at - now).clamp(1, DISMISS_SECS));
+    format!(
+        "{}=dismissed; Max-Age={}; Path=/; SameSite=Lax; HttpOnly{}",
+        cookie_name(announcement.id),
+        max_age,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+// The HTML of a message: `**bold**` and `[label](https://…)` links to http(s) URLs, the rest escaped, markup that
+// doesn't close included. A link can be in bold, nothing else nests.
+pub fn render(message: &str) -> String {
+    let mut html = String::new();
+    let mut rest = message;
+    loop {
+        let bold = rest.find("**").and_then(|start| {
+            let end = start + 2 + rest[start + 2..].find("**")?;
+            Some((start, end)).filter(|_| end > start + 2)
+        });
+        match bold {
+            Some((start, end)) => {
+                html.push_str(&links(&rest[..start]));
+                html.push_str(&format!("<strong>{}</strong>", links(&rest[start + 2..end])));
+                rest = &rest[end + 2..];
+            }
+            None => {
+                html.push_str(&links(rest));
+                return html;
+            }
+        }
+    }
+}
+
+fn links(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('[') {
+        html.push_str(&escape_html(&rest[..open]));
+        let link = rest[open + 1..].split_once("](").and_then(|(label, after)| {
+            let (url, after) = after.split_once(')')?;
+            let safe = (url.starts_with("https://") || url.starts_with("http://"))
+                && !url.chars().any(char::is_whitespace);
+            (safe && !label.is_empty() && !label.contains('[')).then_some((label, url, after))
+        });
+        match link {
+            Some((label, url, after)) => {
+                html.push_str(&format!(
+                    "<a href=\"{}\" class=\"underline\" rel=\"nofollow noopener\">{}</a>",
+                    escape_html(url),
+                    escape_html(label)
+                ));
+                rest = after;
+            }
+            None => {
+                html.push('[');
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    html.push_str(&escape_html(rest));
+    html
+}
+
+// The dismiss button of the banners, and the admin routes of the announcements.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/announcements/{id}/dismiss"), web::post().to(dismiss_announcement))
+        .route(reserved::route("/admin/announcements"), web::get().to(admin_announcements))
+        .route(reserved::route("/admin/announcements"), web::post().to(admin_add_announcement))
+        .route(reserved::route("/admin/announcements/{id}"), web::put().to(admin_update_announcement))
+        .route(reserved::route("/admin/announcements/{id}"), web::delete().to(admin_remove_announcement));
+}
+
+// Handles “POST /announcements/{id}/dismiss”, the button of a banner: keeps the dismissal in a cookie, and sends back to the page of the button. One that ended meanwhile just sends back.
+async fn dismiss_announcement(req: HttpRequest, id: web::Path<i64>, form: web::Form<BackForm>, data: web::Data<AppState>) -> HttpResponse {
+    let secure = req.connection_info().scheme() == "https";
+    let mut response = HttpResponse::SeeOther();
+    response.header("Location", local_path(form.back.as_deref()));
+    if let Some(announcement) = data.announcements.read().unwrap().get(id.into_inner()) {
+        response.header("Set-Cookie", dismiss_cookie(announcement, store::now(), secure));
+    }
+    response.finish()
+}
+
+// Handles “GET /admin/announcements”, the announcements that haven't ended, by when they start. A page by default, the same as JSON with `?format=json`.
+async fn admin_announcements(req: HttpRequest, query: web::Query<DbQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let announcements = data.store.announcements()?;
+    if query.format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok().json(announcements));
+    }
+
+    let now = store::now();
+    let mut report = String::new();
+    for announcement in &announcements {
+        let ends = announcement
+            .ends_at
+            .map_or_else(|| "for good".to_string(), |at| format!("until {}", timestamp::absolute(at)));
+        let when = if announcement.starts_at > now {
+            format!("from {} {}", timestamp::absolute(announcement.starts_at), ends)
+        } else {
+            format!("on {}", ends)
+        };
+        report.push_str(&format!(
+            "{:>4}  {:<8} {}, by {}\n      {}\n",
+            announcement.id, announcement.level, when, announcement.created_by, announcement.message
+        ));
+    }
+    if announcements.is_empty() {
+        report.push_str("No announcements\n");
+    }
+    let _render = telemetry::template("admin_announcements.html");
+    let html_page = admin_template(&data, include_str!("admin_announcements.html")).replace("{{report}}", &escape_html(&report));
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// The announcement a body of “POST /admin/announcements” or “PUT /admin/announcements/{id}” asks for.
+fn new_announcement(body: ApiAnnouncement, caller: &Caller) -> Result<store::NewAnnouncement, AppError> {
+    let message = check_message(&body.message).map_err(AppError::bad_request)?;
+    let level = body
+        .level
+        .as_deref()
+        .map_or(Ok(Level::Info), str::parse::<Level>)
+        .map_err(AppError::bad_request)?;
+    let time = |name: &str, value: Option<&str>| {
+        value
+            .map(|value| chrono::DateTime::parse_from_rfc3339(value.trim()).map(|time| time.timestamp()))
+            .transpose()
+            .map_err(|_| AppError::bad_request(format!("`{}` must be a time like 2024-01-31T12:00:00Z", name)))
+    };
+    let now = store::now();
+    let starts_at = time("starts_at", body.starts_at.as_deref())?.unwrap_or(now);
+    let ends_at = time("ends_at", body.ends_at.as_deref())?;
+    if ends_at.is_some_and(|ends_at| ends_at <= starts_at.max(now)) {
+        return Err(AppError::bad_request("`ends_at` must be after `starts_at` and in the future"));
+    }
+    Ok(store::NewAnnouncement {
+        message,
+        level: level.as_str().to_string(),
+        starts_at,
+        ends_at,
+        created_by: admin_name(caller),
+    })
+}
+
+// Handles “POST /admin/announcements”, an announcement of `{"message": …}`, with a `level` of `info` (the default) or
+// `warning` and the `starts_at` and `ends_at` of its window. Answers 201 with it as “GET /admin/announcements” lists it.
+async fn admin_add_announcement(req: HttpRequest, body: web::Json<ApiAnnouncement>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let caller = require_admin(&req, &data)?;
+    let announcement = new_announcement(body.into_inner(), &caller)?;
+    let audit = audit_entry(&req, &data, "admin", "announce", None, announcement.message.clone());
+    let added = announcement.clone();
+    let id = store_write(&data, move |store| store.add_announcement(&added, Some(&audit))).await?;
+    refresh_announcements(&data)?;
+
+    Ok(HttpResponse::Created().json(store::Announcement {
+        id,
+        message: announcement.message,
+        level: announcement.level,
+        starts_at: announcement.starts_at,
+        ends_at: announcement.ends_at,
+        created_at: store::now(),
+        created_by: announcement.created_by,
+    }))
+}
+
+// Handles “PUT /admin/announcements/{id}”, giving an announcement what the body of “POST /admin/announcements” gives
+// a new one. Answers 200 with it.
+async fn admin_update_announcement(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Json<ApiAnnouncement>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let caller = require_admin(&req, &data)?;
+    let id = id.into_inner();
+    let announcement = new_announcement(body.into_inner(), &caller)?;
+    let audit = audit_entry(&req, &data, "admin", "update_announcement", None, format!("announcement {}: {}", id, announcement.message));
+    if !store_write(&data, move |store| store.update_announcement(id, &announcement, Some(&audit))).await? {
+        return Err(AppError::not_found("No announcement has this id"));
+    }
+    refresh_announcements(&data)?;
+    let updated = data.announcements.read().unwrap().get(id).cloned();
+    match updated {
+        Some(updated) => Ok(HttpResponse::Ok().json(updated)),
+        None => Err(AppError::not_found("No announcement has this id")),
+    }
+}
+
+// Handles “DELETE /admin/announcements/{id}”, taking an announcement down before its end. Answers 204.
+async fn admin_remove_announcement(req: HttpRequest, id: web::Path<i64>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+    let id = id.into_inner();
+    let audit = audit_entry(&req, &data, "admin", "remove_announcement", None, format!("announcement {}", id));
+    if !store_write(&data, move |store| store.remove_announcement(id, Some(&audit))).await? {
+        return Err(AppError::not_found("No announcement has this id"));
+    }
+    refresh_announcements(&data)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize)]
+struct ApiAnnouncement {
+    message: String,
+    // `info` without it
+    level: Option<String>,
+    // RFC 3339, now without it
+    starts_at: Option<String>,
+    // On for good without it
+    ends_at: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct BackForm {
+    back: Option<String>,
+}
@@ -0,0 +1,207 @@
+// A typed client of the JSON API, for programs talking to a pastry server, behind the `client` cargo feature.
+// It sends and reads the structs of `api`, the ones the server itself answers with, so the two can't drift apart,
+// and turns the error envelope of the server into `ClientError::Api`. It runs on the actix runtime, as awc does:
+//
+//     let client = PastryClient::new("https://paste.example.com").with_api_key("pst_…");
+//     let created = client.create(&ApiNewPaste { content: "hello".to_string(), ..Default::default() }).await?;
+//     let paste = client.get(&created.token).await?;
+
+use super::{
+    ApiBatchResult, ApiCreatedPaste, ApiErrorEnvelope, ApiNewBatch, ApiNewPaste, ApiPaste, ApiSearchResults,
+    SearchParams,
+};
+use awc::http::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+// How long a call may take, connecting included.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// Largest answer read, a paste of the default `PASTRY_MAX_PASTE_BYTES` as base64 in its JSON.
+const RESPONSE_LIMIT: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ClientError {
+    // The server answered with an error, as its envelope tells it
+    Api {
+        status: u16,
+        // One of the codes of the API errors, “not_found”, “rate_limited”…
+        code: String,
+        message: String,
+        // The scope an API token was missing, on a 403 for that
+        scope: Option<String>,
+    },
+    // The server could not be reached, or the answer not read
+    Http(String),
+    // An answer that isn't what the route answers, from something else than a pastry server say
+    Decode { status: u16, message: String },
+}
+
+impl ClientError {
+    // The status of the answer, `None` when there was none.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Api { status, .. } | ClientError::Decode { status, .. } => Some(*status),
+            ClientError::Http(_) => None,
+        }
+    }
+
+    // The code of the envelope, `None` for errors that didn't come with one.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            ClientError::Api { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Api { status, code, message, .. } => write!(f, "{} ({} {})", message, status, code),
+            ClientError::Http(message) => write!(f, "The server could not be reached: {}", message),
+            ClientError::Decode { status, message } => write!(f, "Unexpected answer of the server ({}): {}", status, message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+pub struct PastryClient {
+    // Without a trailing slash, the routes start with one
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl PastryClient {
+    // A client of the server at `base_url`, “https://paste.example.com”, sending no API key.
+    pub fn new(base_url: &str) -> PastryClient {
+        PastryClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    // Sends `api_key`, an API token (see “/admin/tokens”) or the admin token, as the bearer token of every call.
+    pub fn with_api_key(mut self, api_key: &str) -> PastryClient {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    // “POST /api/pastes”: the created paste, its secret included.
+    pub async fn create(&self, paste: &ApiNewPaste) -> Result<ApiCreatedPaste, ClientError> {
+        let response = self.request(self.http().post(self.url("/api/pastes", &[])?)).send_json(paste).await;
+        read(response).await
+    }
+
+    // “GET /api/pastes/{token}”, counting a view like any other read.
+    pub async fn get(&self, token: &str) -> Result<ApiPaste, ClientError> {
+        let response = self.request(self.http().get(self.url(&paste_path(token), &[])?)).send().await;
+        read(response).await
+    }
+
+    // “PUT /api/pastes/{token}”: creates the paste at `token`, or replaces the one there with its `key`, see the
+    // server for when. The answer has the secret when the paste was created or replaced.
+    pub async fn update(&self, token: &str, key: Option<&str>, paste: &ApiNewPaste) -> Result<ApiCreatedPaste, ClientError> {
+        let url = self.url(&paste_path(token), &[("key", key)])?;
+        let response = self.request(self.http().put(url)).send_json(paste).await;
+        read(response).await
+    }
+
+    // “DELETE /api/pastes/{token}”, with the `key` of the paste, or an API key with the `delete` scope.
+    pub async fn delete(&self, token: &str, key: Option<&str>) -> Result<(), ClientError> {
+        let url = self.url(&paste_path(token), &[("key", key)])?;
+        let response = self.request(self.http().delete(url)).send().await;
+        let mut response = response.map_err(|e| ClientError::Http(e.to_string()))?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let status = response.status();
+        let body = response.body().limit(RESPONSE_LIMIT).await.map_err(|e| ClientError::Http(e.to_string()))?;
+        Err(error(status, &body))
+    }
+
+    // “GET /api/search”, one page of the public pastes matching `query`.
+    pub async fn search(&self, query: &SearchParams) -> Result<ApiSearchResults, ClientError> {
+        let page = query.page.map(|page| page.to_string());
+        let url = self.url(
+            "/api/search",
+            &[
+                ("q", Some(&query.q)),
+                ("tag", query.tag.as_deref()),
+                ("since", query.since.as_deref()),
+                ("until", query.until.as_deref()),
+                ("page", page.as_deref()),
+            ],
+        )?;
+        let response = self.request(self.http().get(url)).send().await;
+        read(response).await
+    }
+
+    // “POST /api/pastes/batch”: one result per paste of `batch`, in its order.
+    pub async fn batch(&self, batch: &ApiNewBatch) -> Result<Vec<ApiBatchResult>, ClientError> {
+        let response = self.request(self.http().post(self.url("/api/pastes/batch", &[])?)).send_json(batch).await;
+        read(response).await
+    }
+
+    fn http(&self) -> awc::Client {
+        awc::Client::builder().timeout(TIMEOUT).finish()
+    }
+
+    fn request(&self, request: awc::ClientRequest) -> awc::ClientRequest {
+        let request = request.header("User-Agent", concat!("pastry-client/", env!("CARGO_PKG_VERSION")));
+        match &self.api_key {
+            Some(api_key) => request.header("Authorization", format!("Bearer {}", api_key)),
+            None => request,
+        }
+    }
+
+    // The URL of `path` with the parameters that have a value, percent-encoded.
+    fn url(&self, path: &str, params: &[(&str, Option<&str>)]) -> Result<String, ClientError> {
+        let params: Vec<(&str, &str)> = params.iter().filter_map(|(name, value)| Some((*name, (*value)?))).collect();
+        if params.is_empty() {
+            return Ok(format!("{}{}", self.base_url, path));
+        }
+        let query = serde_urlencoded::to_string(params).map_err(|e| ClientError::Http(e.to_string()))?;
+        Ok(format!("{}{}?{}", self.base_url, path, query))
+    }
+}
+
+fn paste_path(token: &str) -> String {
+    format!("/api/pastes/{}", percent_encoding::utf8_percent_encode(token, percent_encoding::NON_ALPHANUMERIC))
+}
+
+// The answer of a call as `T`, or the error of its envelope.
+async fn read<T: serde::de::DeserializeOwned, S>(
+    response: Result<awc::ClientResponse<S>, awc::error::SendRequestError>,
+) -> Result<T, ClientError>
+where
+    S: futures_util::Stream<Item = Result<actix_web::web::Bytes, actix_web::error::PayloadError>> + Unpin,
+{
+    let mut response = response.map_err(|e| ClientError::Http(e.to_string()))?;
+    let status = response.status();
+    let body = response.body().limit(RESPONSE_LIMIT).await.map_err(|e| ClientError::Http(e.to_string()))?;
+    if !status.is_success() {
+        return Err(error(status, &body));
+    }
+    serde_json::from_slice(&body).map_err(|e| ClientError::Decode {
+        status: status.as_u16(),
+        message: e.to_string(),
+    })
+}
+
+// The error of an answer that isn't a success, from its envelope.
+fn error(status: StatusCode, body: &[u8]) -> ClientError {
+    match serde_json::from_slice::<ApiErrorEnvelope>(body) {
+        Ok(envelope) => ClientError::Api {
+            status: status.as_u16(),
+            code: envelope.error.code,
+            message: envelope.error.message,
+            scope: envelope.error.scope,
+        },
+        Err(_) => ClientError::Decode {
+            status: status.as_u16(),
+            message: String::from_utf8_lossy(&body[..body.len().min(200)]).into_owned(),
+        },
+    }
+}
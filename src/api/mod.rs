@@ -0,0 +1,249 @@
+// The wire format of the JSON API under “/api”: what its routes take and answer, written once for the server, which
+// serializes the answers and deserializes the requests, and for clients, which do the reverse (see `client.rs`,
+// behind the `client` cargo feature). The server only adds `#[serde]` attributes here, never its own copies.
+// Timestamps are Unix seconds in the structs and RFC 3339 on the wire, see `timestamp`.
+
+#[cfg(feature = "client")]
+pub mod client;
+
+// The timestamps of the API, RFC 3339 in UTC: “2024-01-31T12:00:00Z”.
+pub mod timestamp {
+    use chrono::{DateTime, SecondsFormat};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn rfc3339(timestamp: i64) -> String {
+        match DateTime::from_timestamp(timestamp, 0) {
+            Some(time) => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            None => "unknown".to_string(),
+        }
+    }
+
+    // For `#[serde(serialize_with = "…")]` on a timestamp.
+    pub fn serialize<S: Serializer>(timestamp: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&rfc3339(*timestamp))
+    }
+
+    // The same for an optional one, `null` when there is none.
+    pub fn serialize_option<S: Serializer>(timestamp: &Option<i64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match timestamp {
+            Some(timestamp) => serialize(timestamp, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    // For `#[serde(deserialize_with = "…")]`, what `serialize` wrote.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&value)
+            .map(|time| time.timestamp())
+            .map_err(|e| serde::de::Error::custom(format!("\"{}\" is not an RFC 3339 time: {}", value, e)))
+    }
+
+    pub fn deserialize_option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(value) => DateTime::parse_from_rfc3339(&value)
+                .map(|time| Some(time.timestamp()))
+                .map_err(|e| serde::de::Error::custom(format!("\"{}\" is not an RFC 3339 time: {}", value, e))),
+            None => Ok(None),
+        }
+    }
+}
+
+// How the content of a paste is written in the API.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Default, Debug)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "base64")]
+    Base64,
+}
+
+// What kind of paste it is in the API, a short link being a `redirect`.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum PasteType {
+    #[default]
+    #[serde(rename = "text")]
+    Text,
+    #[serde(rename = "redirect")]
+    Redirect,
+}
+
+// Whether a paste can be seen by everyone, as the API tells it, see `review.rs` of the server.
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Published,
+    Pending,
+}
+
+impl Status {
+    pub fn of(pending: bool) -> Status {
+        if pending {
+            Status::Pending
+        } else {
+            Status::Published
+        }
+    }
+}
+
+// The body of “POST /api/pastes” and “PUT /api/pastes/{token}”, and of each paste of a batch.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default, Debug)]
+pub struct ApiNewPaste {
+    pub content: String,
+    #[serde(default)]
+    pub encoding: Encoding,
+    #[serde(default, rename = "type")]
+    pub paste_type: PasteType,
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Left out or empty for one made from the content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    // One of the expiries of “/api/limits”, “1h”, “never”…
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    // Overrides PASTRY_NORMALIZE_LINE_ENDINGS for this paste
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalize_line_endings: Option<bool>,
+    // Whether to mirror the paste to a secret gist, see `gist.rs` of the server
+    #[serde(default)]
+    pub mirror: bool,
+    // Whether visitors may comment on the paste, they may unless it's false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comments: Option<bool>,
+}
+
+// The body of “POST /api/pastes/batch”.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default, Debug)]
+pub struct ApiNewBatch {
+    pub pastes: Vec<ApiNewPaste>,
+    // Whether one invalid paste fails the whole batch
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+// One entry of the answer to a batch, the created paste or why it wasn't.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ApiBatchResult {
+    Created(ApiCreatedPaste),
+    Failed { error: ApiBatchError },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiBatchError {
+    // One of the codes of the error envelope
+    pub code: String,
+    pub message: String,
+}
+
+// What creating or replacing a paste answers.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiCreatedPaste {
+    pub token: String,
+    // Only for whoever created the paste or gave its key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub url: String,
+    // Short links have no raw text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_url: Option<String>,
+    #[serde(
+        serialize_with = "timestamp::serialize_option",
+        deserialize_with = "timestamp::deserialize_option",
+        default
+    )]
+    pub expires_at: Option<i64>,
+    pub encoding: Encoding,
+    pub status: Status,
+    // The status page of the mirror to a gist, when one was asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gist: Option<String>,
+}
+
+// A paste as “GET /api/pastes/{token}” and the batch fetches answer it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiPaste {
+    pub token: String,
+    // Left out by batch fetches asked not to include it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    pub encoding: Encoding,
+    #[serde(rename = "type")]
+    pub paste_type: PasteType,
+    pub public: bool,
+    pub status: Status,
+    pub views: i64,
+    #[serde(serialize_with = "timestamp::serialize", deserialize_with = "timestamp::deserialize")]
+    pub created_at: i64,
+    #[serde(
+        serialize_with = "timestamp::serialize_option",
+        deserialize_with = "timestamp::deserialize_option",
+        default
+    )]
+    pub expires_at: Option<i64>,
+    pub tags: Vec<String>,
+    // `null` for a paste without one
+    #[serde(default)]
+    pub title: Option<String>,
+    pub lines: i64,
+    pub chars: i64,
+    pub bytes: i64,
+}
+
+// The query string of “/search” and “/api/search”.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default, Debug)]
+pub struct SearchParams {
+    #[serde(default)]
+    pub q: String,
+    pub tag: Option<String>,
+    // Dates like 2024-01-31, UTC, both included
+    pub since: Option<String>,
+    pub until: Option<String>,
+    // From 1
+    pub page: Option<i64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiSearchResults {
+    pub results: Vec<ApiSearchHit>,
+    pub page: i64,
+    // `null` on the last page
+    pub next_page: Option<i64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiSearchHit {
+    pub token: String,
+    pub url: String,
+    // `null` for a paste without one
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(serialize_with = "timestamp::serialize", deserialize_with = "timestamp::deserialize")]
+    pub created_at: i64,
+    pub lines: i64,
+    pub chars: i64,
+    pub bytes: i64,
+    // The part of the content around the words, as text and as HTML with the words in `<mark>`
+    pub snippet: String,
+    pub snippet_html: String,
+}
+
+// What every error of the API answers: `{"error": {"code": "not_found", "message": "Paste not found"}}`.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiErrorEnvelope {
+    pub error: ApiErrorBody,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiErrorBody {
+    // Machine readable, see `error::ErrorCode` of the server
+    pub code: String,
+    pub message: String,
+    // The scope an API token was missing, on a 403 for that
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
@@ -0,0 +1,248 @@
+// API tokens, the bearer tokens an admin hands out instead of the admin token: each has a label, the scopes it may
+// use, optionally an expiry, and can be revoked. The admin token itself has every scope and never expires.
+// A request proves it holds one with `Authorization: Bearer pst_…`; the database only keeps a SHA-256 of it,
+// so the token is shown once, when it is created. A token can have a daily quota of pastes of its own, which its
+// requests count against instead of the quota of their address (see `check_daily_quota`).
+//
+// The scopes, checked by `authorize` of each route:
+// - `read`: the `GET` routes of `/api`;
+// - `write`: creating and replacing pastes through `/api`, and checking them with `/api/pastes/validate`;
+// - `delete`: `DELETE /api/pastes/{token}` of any paste, without its key;
+// - `admin`: the `/admin` routes, these tokens included.
+// Requests without a token get on `/api` what they always did, unless `PASTRY_API_REQUIRE_TOKEN` is set.
+
+use crate::error::AppError;
+use crate::{admin_template, audit_entry, escape_html, require_admin, AppState, DbQuery, SECONDS_PER_DAY};
+use crate::{reserved, store, store_write, telemetry, timestamp};
+use actix_web::{web, HttpRequest, HttpResponse};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// What every token starts with, so that one found in a log or a repository is easy to recognize.
+pub const PREFIX: &str = "pst_";
+
+// Random characters after the prefix.
+const TOKEN_LEN: usize = 32;
+
+// Most characters of a label.
+pub const MAX_LABEL_CHARS: usize = 100;
+
+// Longest `expires_in_days` of a new token, ten years.
+pub const MAX_EXPIRY_DAYS: i64 = 3650;
+
+// How often, at most, the last use of a token is written to the database.
+pub const LAST_USED_INTERVAL_SECS: i64 = 60;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scope {
+    Read,
+    Write,
+    Delete,
+    Admin,
+}
+
+// In the order they are listed in, the scopes of tokens from before scopes existed.
+pub const ALL: [Scope; 4] = [Scope::Read, Scope::Write, Scope::Delete, Scope::Admin];
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Delete => "delete",
+            Scope::Admin => "admin",
+        }
+    }
+
+    // Whether a token storing the scopes `scopes` (`scopes_string`) has this one.
+    pub fn granted_by(self, scopes: &str) -> bool {
+        scopes.split(',').any(|scope| scope == self.as_str())
+    }
+}
+
+// The scopes asked for a new token, as stored: known ones only, at least one, in the order of `ALL`.
+// Returns a message suitable for showing to the user when they aren't acceptable.
+pub fn parse_scopes(scopes: &[String]) -> Result<String, String> {
+    if let Some(unknown) = scopes.iter().find(|scope| !ALL.iter().any(|known| known.as_str() == scope.as_str())) {
+        return Err(format!("Unknown scope \"{}\", the scopes are read, write, delete and admin", unknown));
+    }
+    let granted: Vec<&str> = ALL
+        .iter()
+        .map(|scope| scope.as_str())
+        .filter(|scope| scopes.iter().any(|asked| asked == scope))
+        .collect();
+    if granted.is_empty() {
+        return Err("A token needs at least one scope".to_string());
+    }
+    Ok(granted.join(","))
+}
+
+// Stored scopes as a JSON list, `["read", "write"]`.
+pub fn serialize_scopes<S: serde::Serializer>(scopes: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(scopes.split(',').filter(|scope| !scope.is_empty()))
+}
+
+// A new random token.
+pub fn new_token() -> String {
+    let random: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LEN)
+        .map(char::from)
+        .collect();
+    format!("{}{}", PREFIX, random)
+}
+
+// What the database stores of a token.
+pub fn hashed(token: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("api-token:{}", token).as_bytes()))
+}
+
+// When each token was last written as used, so that a token used on every request is written once a minute rather
+// than on each of them. `last_used_at` of the store is that much behind at most.
+#[derive(Default)]
+pub struct LastUsed {
+    written: HashMap<i64, i64>,
+}
+
+impl LastUsed {
+    // Whether the use of token `id` at `now` should be written, taking it as written.
+    pub fn due(&mut self, id: i64, now: i64) -> bool {
+        match self.written.get(&id) {
+            Some(written) if now - written < LAST_USED_INTERVAL_SECS => false,
+            _ => {
+                self.written.insert(id, now);
+                true
+            }
+        }
+    }
+}
+
+// The admin routes of the tokens.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/admin/tokens"), web::get().to(admin_tokens))
+        .route(reserved::route("/admin/tokens"), web::post().to(admin_create_token))
+        .route(reserved::route("/admin/tokens/{id}/revoke"), web::post().to(admin_revoke_token));
+}
+
+// Handles “GET /admin/tokens”, the API tokens with their label, scopes and dates, revoked and expired ones included.
+// A page by default, the same as JSON with `?format=json`. Tokens themselves are only shown when they are created.
+async fn admin_tokens(req: HttpRequest, query: web::Query<DbQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let tokens = data.store.api_tokens()?;
+    if query.format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok().json(tokens));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let date = |at: Option<i64>| at.map_or_else(|| "never".to_string(), timestamp::absolute);
+    let mut report = String::new();
+    for token in &tokens {
+        let state = match (token.revoked_at, token.expires_at) {
+            (Some(revoked_at), _) => format!("revoked {}", timestamp::absolute(revoked_at)),
+            (None, Some(expires_at)) if expires_at <= now => "expired".to_string(),
+            (None, _) => "active".to_string(),
+        };
+        let quota = match token.daily_quota {
+            Some(quota) => format!("{} pastes a day", quota),
+            None => "the quota of its address".to_string(),
+        };
+        report.push_str(&format!(
+            "{:>4}  {:<24} {:<24} {}\n      created {}, expires {}, last used {}, {}\n",
+            token.id,
+            token.label,
+            token.scopes.replace(',', ", "),
+            state,
+            timestamp::absolute(token.created_at),
+            date(token.expires_at),
+            date(token.last_used_at),
+            quota
+        ));
+    }
+    if tokens.is_empty() {
+        report.push_str("No tokens\n");
+    }
+    let _render = telemetry::template("admin_tokens.html");
+    let html_page = admin_template(&data, include_str!("admin_tokens.html")).replace("{{report}}", &escape_html(&report));
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// Handles “POST /admin/tokens”, a new API token from
+// `{"label": …, "scopes": […], "expires_in_days": …, "daily_quota": …}`.
+// Answers 201 with the token, the only time it is shown, next to what “GET /admin/tokens” lists of it.
+async fn admin_create_token(req: HttpRequest, body: web::Json<ApiNewToken>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let body = body.into_inner();
+    let label = body.label.trim();
+    if label.is_empty() || label.chars().count() > MAX_LABEL_CHARS || label.chars().any(char::is_control) {
+        return Err(AppError::bad_request(format!(
+            "A token needs a label of at most {} characters, without control characters",
+            MAX_LABEL_CHARS
+        )));
+    }
+    let scopes = parse_scopes(&body.scopes).map_err(AppError::bad_request)?;
+    let expires_at = match body.expires_in_days {
+        Some(days) if !(1..=MAX_EXPIRY_DAYS).contains(&days) => {
+            return Err(AppError::bad_request(format!(
+                "`expires_in_days` must be between 1 and {}",
+                MAX_EXPIRY_DAYS
+            )))
+        }
+        Some(days) => Some(chrono::Utc::now().timestamp() + days * SECONDS_PER_DAY),
+        None => None,
+    };
+    if body.daily_quota.is_some_and(|quota| quota < 1) {
+        return Err(AppError::bad_request("`daily_quota` must be at least 1"));
+    }
+
+    let token = new_token();
+    let new_token = store::NewApiToken {
+        label: label.to_string(),
+        token_hash: hashed(&token),
+        scopes,
+        expires_at,
+        daily_quota: body.daily_quota,
+    };
+    let detail = format!("{} with {}", new_token.label, new_token.scopes);
+    let audit = audit_entry(&req, &data, "admin", "create_token", None, detail);
+    let info = store_write(&data, move |store| {
+        store.create_api_token(&new_token, Some(&audit))?;
+        store.api_token(&new_token.token_hash)
+    })
+    .await?
+    .ok_or_else(|| AppError::internal("The new token could not be read back"))?;
+    Ok(HttpResponse::Created().json(ApiCreatedToken { token, info }))
+}
+
+// Handles “POST /admin/tokens/{id}/revoke”: the token is refused from then on, and stays listed as revoked.
+async fn admin_revoke_token(req: HttpRequest, id: web::Path<i64>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+    let id = id.into_inner();
+    let audit = audit_entry(&req, &data, "admin", "revoke_token", None, format!("token {}", id));
+    if !store_write(&data, move |store| store.revoke_api_token(id, Some(&audit))).await? {
+        return Err(AppError::not_found("No token in effect has this id"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize)]
+struct ApiNewToken {
+    label: String,
+    scopes: Vec<String>,
+    // Never expires without it
+    expires_in_days: Option<i64>,
+    // The quota of the address of each request without it
+    daily_quota: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct ApiCreatedToken {
+    token: String,
+    #[serde(flatten)]
+    info: store::ApiToken,
+}
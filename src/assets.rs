@@ -0,0 +1,113 @@
+// Static files compiled into the binary, served under “/static/{path}”.
+// Embedding them means the pages look the same whatever directory the server is started from,
+// and nothing has to be fetched from a CDN, so they also render without network access.
+// To add a file put it in `src/static/` and give it an entry in `ASSETS`.
+// Operators can override any of them (and add more) with files in `PASTRY_ASSETS_DIR`,
+// the embedded file is only served when the directory doesn't have one with that name.
+// Pages link the assets with a `?v=<hash of the content>` suffix so browsers notice when a file changed.
+
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub struct Asset {
+    pub path: &'static str,
+    pub content_type: &'static str,
+    pub bytes: &'static [u8],
+}
+
+pub const ASSETS: &[Asset] = &[
+    Asset {
+        path: "base.css",
+        content_type: "text/css; charset=utf-8",
+        bytes: include_bytes!("static/base.css"),
+    },
+    Asset {
+        path: "style.css",
+        content_type: "text/css; charset=utf-8",
+        bytes: include_bytes!("static/style.css"),
+    },
+    // Empty, meant to be overridden with extra rules by operators
+    Asset {
+        path: "custom.css",
+        content_type: "text/css; charset=utf-8",
+        bytes: include_bytes!("static/custom.css"),
+    },
+    Asset {
+        path: "ferris.svg",
+        content_type: "image/svg+xml",
+        bytes: include_bytes!("static/ferris.svg"),
+    },
+    Asset {
+        path: "favicon.svg",
+        content_type: "image/svg+xml",
+        bytes: include_bytes!("static/favicon.svg"),
+    },
+    // Tab, the counter and the draft of the paste form, see the file
+    Asset {
+        path: "editor.js",
+        content_type: "text/javascript; charset=utf-8",
+        bytes: include_bytes!("static/editor.js"),
+    },
+    // The copy buttons of the page of a new paste
+    Asset {
+        path: "copy.js",
+        content_type: "text/javascript; charset=utf-8",
+        bytes: include_bytes!("static/copy.js"),
+    },
+];
+
+// Version of every asset, filled in once at startup by `init`.
+static VERSIONS: OnceLock<Vec<(&'static str, String)>> = OnceLock::new();
+
+// The embedded file at `path` (relative to `/static/`), if there is one.
+pub fn get(path: &str) -> Option<&'static Asset> {
+    ASSETS.iter().find(|asset| asset.path == path)
+}
+
+// What is served for `asset`: the file in `dir` when it overrides the embedded one.
+pub fn served(asset: &Asset, dir: Option<&Path>) -> Cow<'static, [u8]> {
+    match dir.and_then(|dir| fs::read(dir.join(asset.path)).ok()) {
+        Some(overridden) => Cow::Owned(overridden),
+        None => Cow::Borrowed(asset.bytes),
+    }
+}
+
+// Computes the version of every asset from the file that will actually be served.
+pub fn init(dir: Option<&Path>) {
+    let versions = ASSETS
+        .iter()
+        .map(|asset| (asset.path, content_hash(&served(asset, dir))))
+        .collect();
+    let _ = VERSIONS.set(versions);
+}
+
+// The version of the asset at `path`, used as its ETag and in its URLs.
+pub fn version(path: &str) -> Option<&'static str> {
+    VERSIONS
+        .get()?
+        .iter()
+        .find(|(asset_path, _)| *asset_path == path)
+        .map(|(_, version)| version.as_str())
+}
+
+// Adds the version to the links of every asset in a rendered page, "/static/style.css" becomes
+// "/static/style.css?v=1a2b3c4d5e6f".
+pub fn versioned(page: &str) -> String {
+    let mut page = page.to_string();
+    for (path, version) in VERSIONS.get().map(Vec::as_slice).unwrap_or(&[]) {
+        page = page.replace(
+            &format!("\"/static/{}\"", path),
+            &format!("\"/static/{}?v={}\"", path, version),
+        );
+    }
+    page
+}
+
+// The first 12 hex digits of the SHA-256 of `bytes`, plenty to tell versions of a file apart.
+fn content_hash(bytes: &[u8]) -> String {
+    let hash = format!("{:x}", Sha256::digest(bytes));
+    hash[..12].to_string()
+}
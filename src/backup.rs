@@ -0,0 +1,109 @@
+// Online backups of the database with `VACUUM INTO`.
+// SQLite writes a consistent snapshot into a new file while the server keeps running,
+// so there is no risk of copying `pastes.db` halfway through a write.
+// Backups always land in the configured backup directory under a name generated here,
+// nothing coming from the request ends up in the path.
+
+use rusqlite::{params, Connection};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const BACKUP_PREFIX: &str = "pastes-";
+const BACKUP_SUFFIX: &str = ".db";
+
+pub struct Backup {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub pruned: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+}
+
+impl std::fmt::Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupError::Io(e) => write!(f, "I/O error: {}", e),
+            BackupError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for BackupError {
+    fn from(e: io::Error) -> BackupError {
+        BackupError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> BackupError {
+        BackupError::Sqlite(e)
+    }
+}
+
+// Writes a snapshot of the database at `db_path` into `backup_dir` as `pastes-<UTC timestamp>.db`,
+// using a connection of its own so the server's connection stays free while the copy runs.
+// When `keep` is non-zero only the `keep` newest backups are kept afterwards.
+// The caller is responsible for not running two backups at the same time.
+pub fn run(db_path: &Path, backup_dir: &Path, keep: usize) -> Result<Backup, BackupError> {
+    fs::create_dir_all(backup_dir)?;
+    let backup_dir = backup_dir.canonicalize()?;
+
+    let conn = Connection::open(db_path)?;
+    let timestamp: String = conn.query_row(
+        "SELECT strftime('%Y%m%dT%H%M%SZ', 'now')",
+        params![],
+        |row| row.get(0),
+    )?;
+
+    let mut path = backup_dir.join(format!("{}{}{}", BACKUP_PREFIX, timestamp, BACKUP_SUFFIX));
+    let mut counter = 1;
+    while path.exists() {
+        path = backup_dir.join(format!("{}{}_{}{}", BACKUP_PREFIX, timestamp, counter, BACKUP_SUFFIX));
+        counter += 1;
+    }
+
+    // VACUUM INTO takes a string literal, not a bound parameter
+    let quoted = path.to_string_lossy().replace('\'', "''");
+    conn.execute_batch(&format!("VACUUM INTO '{}'", quoted))?;
+
+    let size_bytes = fs::metadata(&path)?.len();
+    let pruned = if keep > 0 { prune(&backup_dir, keep)? } else { Vec::new() };
+
+    Ok(Backup {
+        path,
+        size_bytes,
+        pruned,
+    })
+}
+
+// Deletes all but the `keep` newest backups of the directory.
+// Only files named like the backups made by `run` are ever considered.
+fn prune(backup_dir: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut backups: Vec<PathBuf> = fs::read_dir(backup_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX))
+                    .unwrap_or(false)
+        })
+        .collect();
+
+    // The timestamp in the name sorts chronologically
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(keep);
+    let pruned: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+    for path in &pruned {
+        fs::remove_file(path)?;
+    }
+    Ok(pruned)
+}
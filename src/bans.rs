@@ -0,0 +1,434 @@
+// Banned addresses, refused before any request that writes: submitting, the API's POST, PUT and DELETE, comments…
+// The admin routes are left out, so a ban can always be lifted.
+// A ban is either a range in CIDR notation, IPv4 or IPv6, one address being stored as the range of it alone
+// (“/32”, “/128”), or a `client::hashed_ip` from the audit log, which only lasts as long as the salt: a hash can
+// only be banned with `PASTRY_IP_SALT` set, the random salt of a server without one changes on every restart.
+// Temporary bans stop counting at their expiry, the cleanup deletes them later.
+// Requests are checked against `BanList`, kept in memory: loaded at startup, again after every change made
+// through this instance and every `REFRESH_INTERVAL` for the changes made through others.
+
+use crate::error::AppError;
+use crate::{admin_name, admin_template, audit_entry, escape_html, refresh_bans, require_admin, AppState, DbQuery};
+use crate::{reserved, store, store_write, telemetry, timestamp};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Most characters of the reason of a ban.
+pub const MAX_REASON_CHARS: usize = 200;
+
+// Longest `expires_in_hours` of a temporary ban, ten years.
+pub const MAX_EXPIRY_HOURS: i64 = 10 * 365 * 24;
+
+// What a banned client is told. It doesn't say why, nor that it is a ban.
+pub const REFUSED: &str = "This request isn't allowed";
+
+// A range of addresses, “192.0.2.0/24” or “2001:db8::/32”; a single address is a range of one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cidr {
+    // With the bits past the prefix cleared
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    // The range of `ip` alone.
+    pub fn single(ip: IpAddr) -> Cidr {
+        let network = canonical(ip);
+        let prefix = if network.is_ipv4() { 32 } else { 128 };
+        Cidr { network, prefix }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => u32::from(network) == u32::from(ip) & mask_v4(self.prefix),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => u128::from(network) == u128::from(ip) & mask_v6(self.prefix),
+            // A range of IPv6 wider than the IPv4 addresses written as IPv6 has them too
+            (IpAddr::V6(network), IpAddr::V4(ip)) => {
+                u128::from(network) == u128::from(ip.to_ipv6_mapped()) & mask_v6(self.prefix)
+            }
+            (IpAddr::V4(_), IpAddr::V6(_)) => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Cidr, String> {
+        let value = value.trim();
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("\"{}\" is not an IP address or a range like 192.0.2.0/24", value))?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("The prefix of \"{}\" must be between 0 and {}", value, max))?,
+            None => max,
+        };
+        // A range of IPv4 addresses written as IPv6, “::ffff:192.0.2.0/120”, is the IPv4 range it is
+        let (address, prefix) = match canonical(address) {
+            IpAddr::V4(ip) if address.is_ipv6() && prefix >= 96 => (IpAddr::V4(ip), prefix - 96),
+            IpAddr::V4(_) if address.is_ipv6() => (address, prefix),
+            canonical => (canonical, prefix),
+        };
+        let network = match address {
+            IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) & mask_v4(prefix)).into()),
+            IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & mask_v6(prefix)).into()),
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+// IPv4 addresses written as IPv6 (“::ffff:192.0.2.1”, what dual-stack sockets see) as the IPv4 address they are.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+// The bans in effect, as requests are checked against them. Each one with its expiry, if any.
+#[derive(Default)]
+pub struct BanList {
+    hashes: HashMap<String, Option<i64>>,
+    ranges: Vec<(Cidr, Option<i64>)>,
+}
+
+impl BanList {
+    pub fn new(bans: &[crate::store::Ban]) -> BanList {
+        let mut list = BanList::default();
+        for ban in bans {
+            if let Some(ip_hash) = &ban.ip_hash {
+                // Of two bans of the same address, the one lasting the longest
+                let expires_at = list.hashes.entry(ip_hash.clone()).or_insert(ban.expires_at);
+                *expires_at = expires_at.zip(ban.expires_at).map(|(kept, other)| kept.max(other));
+            }
+            match ban.cidr.as_deref().map(str::parse::<Cidr>) {
+                Some(Ok(cidr)) => list.ranges.push((cidr, ban.expires_at)),
+                Some(Err(e)) => eprintln!("Bans: skipping ban {}: {}", ban.id, e),
+                None => {}
+            }
+        }
+        list
+    }
+
+    // Whether the client at `ip`, whose `client::hashed_ip` is `ip_hash`, is banned at `now`.
+    // The hash is only computed when there are banned hashes to compare it with.
+    pub fn bans(&self, ip: Option<IpAddr>, ip_hash: impl FnOnce() -> String, now: i64) -> bool {
+        let in_effect = |expires_at: &Option<i64>| expires_at.is_none_or(|expires_at| expires_at > now);
+        if let Some(ip) = ip {
+            if self.ranges.iter().any(|(cidr, expires_at)| in_effect(expires_at) && cidr.contains(ip)) {
+                return true;
+            }
+        }
+        !self.hashes.is_empty() && self.hashes.get(&ip_hash()).is_some_and(in_effect)
+    }
+}
+
+// The admin routes of the bans.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/admin/bans"), web::get().to(admin_bans))
+        .route(reserved::route("/admin/bans"), web::post().to(admin_add_ban))
+        .route(reserved::route("/admin/bans/{id}"), web::delete().to(admin_remove_ban));
+}
+
+// Handles “GET /admin/bans”, the bans in effect, newest first. A page by default, the same as JSON with `?format=json`.
+async fn admin_bans(req: HttpRequest, query: web::Query<DbQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let bans = data.store.bans()?;
+    if query.format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok().json(bans));
+    }
+
+    let mut report = String::new();
+    for ban in &bans {
+        report.push_str(&format!(
+            "{:>4}  {:<43} {}, by {}\n",
+            ban.id,
+            banned_what(ban.ip_hash.as_deref(), ban.cidr.as_deref()),
+            ban.expires_at.map_or_else(|| "permanent".to_string(), |at| format!("until {}", timestamp::absolute(at))),
+            ban.created_by
+        ));
+        if !ban.reason.is_empty() {
+            report.push_str(&format!("      {}\n", ban.reason));
+        }
+    }
+    if bans.is_empty() {
+        report.push_str("No bans\n");
+    }
+    let _render = telemetry::template("admin_bans.html");
+    let html_page = admin_template(&data, include_str!("admin_bans.html")).replace("{{report}}", &escape_html(&report));
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// Hashes of addresses can only be banned with a configured `PASTRY_IP_SALT`: the random salt of a server without one
+// changes on restart, and a ban of a hash made with it would stop matching anything.
+pub fn hash_bannable(data: &AppState) -> Result<(), AppError> {
+    if data.ip_salt_configured {
+        Ok(())
+    } else {
+        Err(AppError::bad_request(
+            "Hashed addresses can't be banned without PASTRY_IP_SALT, the hashes change on every restart",
+        ))
+    }
+}
+
+// What a ban bans, as the bans page and the audit log show it.
+pub fn banned_what(ip_hash: Option<&str>, cidr: Option<&str>) -> String {
+    match (ip_hash, cidr) {
+        (_, Some(cidr)) => cidr.to_string(),
+        (Some(ip_hash), None) => format!("IP hash {}…", &ip_hash[..ip_hash.len().min(16)]),
+        (None, None) => "nothing".to_string(),
+    }
+}
+
+// Handles “POST /admin/bans”, a ban of one of `{"ip": …}`, `{"cidr": …}` or `{"ip_hash": …}` (of the audit log),
+// with a `reason` and `expires_in_hours` for a temporary one. Answers 201 with the ban as “GET /admin/bans” lists it.
+// One address is stored as the range of it alone, whatever the salt; a hash needs `PASTRY_IP_SALT`, see `hash_bannable`.
+async fn admin_add_ban(req: HttpRequest, body: web::Json<ApiNewBan>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let caller = require_admin(&req, &data)?;
+
+    let body = body.into_inner();
+    let (ip_hash, cidr) = match (body.ip, body.cidr, body.ip_hash) {
+        (Some(ip), None, None) => {
+            let ip: IpAddr = ip
+                .trim()
+                .parse()
+                .map_err(|_| AppError::bad_request(format!("\"{}\" is not an IP address", ip)))?;
+            (None, Some(Cidr::single(ip).to_string()))
+        }
+        (None, Some(cidr), None) => (None, Some(cidr.parse::<Cidr>().map_err(AppError::bad_request)?.to_string())),
+        (None, None, Some(ip_hash)) if ip_hash.len() == 64 && ip_hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            hash_bannable(&data)?;
+            (Some(ip_hash.to_ascii_lowercase()), None)
+        }
+        (None, None, Some(_)) => return Err(AppError::bad_request("`ip_hash` must be 64 hex digits, as in the audit log")),
+        _ => return Err(AppError::bad_request("Give one of `ip`, `cidr` and `ip_hash`")),
+    };
+    let reason = body.reason.unwrap_or_default().trim().to_string();
+    if reason.chars().count() > MAX_REASON_CHARS || reason.chars().any(char::is_control) {
+        return Err(AppError::bad_request(format!(
+            "A reason is at most {} characters, without control characters",
+            MAX_REASON_CHARS
+        )));
+    }
+    let expires_at = match body.expires_in_hours {
+        Some(hours) if !(1..=MAX_EXPIRY_HOURS).contains(&hours) => {
+            return Err(AppError::bad_request(format!(
+                "`expires_in_hours` must be between 1 and {}",
+                MAX_EXPIRY_HOURS
+            )))
+        }
+        Some(hours) => Some(chrono::Utc::now().timestamp() + hours * 60 * 60),
+        None => None,
+    };
+
+    let ban = store::NewBan {
+        ip_hash,
+        cidr,
+        reason,
+        expires_at,
+        created_by: admin_name(&caller),
+    };
+    let mut detail = banned_what(ban.ip_hash.as_deref(), ban.cidr.as_deref());
+    if !ban.reason.is_empty() {
+        detail = format!("{}: {}", detail, ban.reason);
+    }
+    let audit = audit_entry(&req, &data, "admin", "ban", None, detail);
+    let added = ban.clone();
+    let id = store_write(&data, move |store| store.add_ban(&added, Some(&audit))).await?;
+    refresh_bans(&data)?;
+
+    Ok(HttpResponse::Created().json(store::Ban {
+        id,
+        ip_hash: ban.ip_hash,
+        cidr: ban.cidr,
+        reason: ban.reason,
+        created_at: chrono::Utc::now().timestamp(),
+        expires_at: ban.expires_at,
+        created_by: ban.created_by,
+    }))
+}
+
+// Handles “DELETE /admin/bans/{id}”, lifting a ban. Answers 204.
+async fn admin_remove_ban(req: HttpRequest, id: web::Path<i64>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+    let id = id.into_inner();
+    let audit = audit_entry(&req, &data, "admin", "unban", None, format!("ban {}", id));
+    if !store_write(&data, move |store| store.remove_ban(id, Some(&audit))).await? {
+        return Err(AppError::not_found("No ban has this id"));
+    }
+    refresh_bans(&data)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize)]
+struct ApiNewBan {
+    ip: Option<String>,
+    cidr: Option<String>,
+    ip_hash: Option<String>,
+    reason: Option<String>,
+    // Permanent without it
+    expires_in_hours: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Ban;
+
+    fn cidr(value: &str) -> Cidr {
+        value.parse().unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(cidr("192.0.2.0/24").to_string(), "192.0.2.0/24");
+        // The bits past the prefix are cleared
+        assert_eq!(cidr("192.0.2.77/24").to_string(), "192.0.2.0/24");
+        assert_eq!(cidr(" 203.0.113.9 ").to_string(), "203.0.113.9/32");
+        assert_eq!(cidr("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert_eq!(cidr("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(cidr("2001:db8::1").to_string(), "2001:db8::1/128");
+        // IPv4 written as IPv6 is IPv4
+        assert_eq!(cidr("::ffff:192.0.2.1").to_string(), "192.0.2.1/32");
+    }
+
+    #[test]
+    fn refuses_what_isnt_a_range() {
+        for value in ["", "example.com", "192.0.2.0/33", "2001:db8::/129", "192.0.2.0/-1", "192.0.2.0/", "192.0.2/24"] {
+            assert!(value.parse::<Cidr>().is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn matches_ipv4() {
+        let range = cidr("192.0.2.0/24");
+        assert!(range.contains(ip("192.0.2.0")));
+        assert!(range.contains(ip("192.0.2.255")));
+        assert!(!range.contains(ip("192.0.3.0")));
+        assert!(!range.contains(ip("2001:db8::1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("198.51.100.1")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+    }
+
+    #[test]
+    fn matches_ipv6() {
+        let range = cidr("2001:db8::/32");
+        assert!(range.contains(ip("2001:db8::1")));
+        assert!(range.contains(ip("2001:db8:ffff:ffff::")));
+        assert!(!range.contains(ip("2001:db9::")));
+        assert!(!range.contains(ip("192.0.2.1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn matches_mapped_ipv4() {
+        // What a dual-stack socket sees of an IPv4 client
+        assert!(cidr("192.0.2.0/24").contains(ip("::ffff:192.0.2.7")));
+        assert!(cidr("::ffff:192.0.2.0/120").contains(ip("192.0.2.7")));
+        assert!(!cidr("192.0.2.0/24").contains(ip("::ffff:198.51.100.7")));
+        assert_eq!(cidr("::ffff:192.0.2.0/120").to_string(), "192.0.2.0/24");
+        // Wider than the mapped addresses, an IPv6 range
+        assert!(cidr("::/64").contains(ip("192.0.2.7")));
+        assert!(!cidr("2001:db8::/32").contains(ip("192.0.2.7")));
+    }
+
+    #[test]
+    fn single_addresses() {
+        assert_eq!(Cidr::single(ip("198.51.100.7")), cidr("198.51.100.7/32"));
+        assert_eq!(Cidr::single(ip("2001:db8::7")), cidr("2001:db8::7/128"));
+        assert_eq!(Cidr::single(ip("::ffff:198.51.100.7")), cidr("198.51.100.7/32"));
+        let single = Cidr::single(ip("198.51.100.7"));
+        assert!(single.contains(ip("198.51.100.7")) && !single.contains(ip("198.51.100.8")));
+    }
+
+    fn ban(id: i64, ip_hash: Option<&str>, cidr: Option<&str>, expires_at: Option<i64>) -> Ban {
+        Ban {
+            id,
+            ip_hash: ip_hash.map(str::to_string),
+            cidr: cidr.map(str::to_string),
+            reason: String::new(),
+            created_at: 0,
+            expires_at,
+            created_by: "admin".to_string(),
+        }
+    }
+
+    #[test]
+    fn bans_ranges_and_hashes() {
+        let list = BanList::new(&[ban(1, None, Some("192.0.2.0/24"), None), ban(2, Some("abc"), None, None)]);
+        assert!(list.bans(Some(ip("192.0.2.1")), || "other".to_string(), 0));
+        assert!(list.bans(Some(ip("198.51.100.1")), || "abc".to_string(), 0));
+        assert!(list.bans(None, || "abc".to_string(), 0));
+        assert!(!list.bans(Some(ip("198.51.100.1")), || "other".to_string(), 0));
+        assert!(!list.bans(None, || "other".to_string(), 0));
+    }
+
+    #[test]
+    fn expired_bans_stop_counting() {
+        let list = BanList::new(&[ban(1, None, Some("192.0.2.0/24"), Some(100)), ban(2, Some("abc"), None, Some(100))]);
+        assert!(list.bans(Some(ip("192.0.2.1")), String::new, 99));
+        assert!(!list.bans(Some(ip("192.0.2.1")), String::new, 100));
+        assert!(list.bans(None, || "abc".to_string(), 99));
+        assert!(!list.bans(None, || "abc".to_string(), 100));
+    }
+
+    #[test]
+    fn the_longest_ban_of_a_hash_counts() {
+        let temporary = ban(1, Some("abc"), None, Some(100));
+        let longer = ban(2, Some("abc"), None, Some(200));
+        let permanent = ban(3, Some("abc"), None, None);
+        let list = BanList::new(&[temporary.clone(), longer.clone()]);
+        assert!(list.bans(None, || "abc".to_string(), 150));
+        assert!(!list.bans(None, || "abc".to_string(), 200));
+        let list = BanList::new(&[longer, permanent, temporary]);
+        assert!(list.bans(None, || "abc".to_string(), i64::MAX));
+    }
+
+    #[test]
+    fn hashes_are_only_computed_when_needed() {
+        let list = BanList::new(&[ban(1, None, Some("192.0.2.0/24"), None)]);
+        assert!(!list.bans(Some(ip("198.51.100.1")), || panic!("hashed without banned hashes"), 0));
+    }
+
+    #[test]
+    fn broken_ranges_are_skipped() {
+        let list = BanList::new(&[ban(1, None, Some("not a range"), None), ban(2, None, Some("192.0.2.0/24"), None)]);
+        assert!(list.bans(Some(ip("192.0.2.1")), String::new, 0));
+    }
+}
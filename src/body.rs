@@ -0,0 +1,167 @@
+// Bodies of the routes that create pastes, which clients may send compressed.
+// With `Content-Encoding: gzip` or `zstd` the body is decompressed here, into at most as many bytes as an
+// uncompressed body may have: a small body that would expand to gigabytes (a zip bomb) is refused once
+// decompression gets past the limit, without ever holding more than that in memory.
+// actix's own decompression is left out (no `compress` feature), it only checks the size after each chunk
+// and passes encodings it doesn't know through as they are.
+
+use crate::error::{AppError, ErrorCode};
+use actix_web::http::header::CONTENT_ENCODING;
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{HttpMessage, HttpRequest};
+use serde::de::DeserializeOwned;
+use std::io::Read;
+
+// The body as actix read it, with the 413 of a body over `limit` as sent telling which limit that was.
+pub fn received(body: Result<Bytes, actix_web::Error>, limit: usize) -> Result<Bytes, AppError> {
+    body.map_err(|e| {
+        let status = e.as_response_error().status_code();
+        if status == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError::new(
+                ErrorCode::PayloadTooLarge,
+                format!("The body is bigger than the {} bytes accepted as sent", limit),
+            )
+        } else {
+            AppError::new(ErrorCode::from_status(status), e.to_string())
+        }
+    })
+}
+
+// Decompresses `body` as its `Content-Encoding` says, into at most `limit` bytes.
+// No encoding or `identity` leaves it as it is, anything but gzip and zstd is a 415.
+pub fn decode(req: &HttpRequest, body: Bytes, limit: usize) -> Result<Bytes, AppError> {
+    let encoding = match req.headers().get(CONTENT_ENCODING) {
+        Some(value) => value.to_str().unwrap_or("").trim().to_ascii_lowercase(),
+        None => return Ok(body),
+    };
+
+    let decoded = match encoding.as_str() {
+        "" | "identity" => return Ok(body),
+        "gzip" | "x-gzip" => read_limited(flate2::read::MultiGzDecoder::new(&body[..]), limit)?,
+        "zstd" => read_limited(zstd::stream::read::Decoder::new(&body[..]).map_err(undecodable)?, limit)?,
+        other => {
+            return Err(AppError::new(
+                ErrorCode::UnsupportedMediaType,
+                format!("Content-Encoding {:?} is not supported, send the body as is, gzip or zstd", other),
+            ))
+        }
+    };
+    Ok(Bytes::from(decoded))
+}
+
+// A JSON body, decompressed by `decode`. Like actix's `Json` it must be sent as `application/json`.
+pub fn json<T: DeserializeOwned>(req: &HttpRequest, body: Bytes, limit: usize) -> Result<T, AppError> {
+    let content_type = req.content_type();
+    if content_type != "application/json" && !content_type.ends_with("+json") {
+        return Err(AppError::bad_request("The body must be sent as application/json"));
+    }
+    let body = decode(req, body, limit)?;
+    serde_json::from_slice(&body).map_err(|e| AppError::bad_request(format!("Json deserialize error: {}", e)))
+}
+
+// Reads one byte past `limit` at most, enough to tell a body that is too big.
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, AppError> {
+    let mut decoded = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(undecodable)?;
+    if decoded.len() > limit {
+        return Err(AppError::new(
+            ErrorCode::PayloadTooLarge,
+            format!("The body is bigger than the {} bytes accepted once decompressed", limit),
+        ));
+    }
+    Ok(decoded)
+}
+
+fn undecodable(e: std::io::Error) -> AppError {
+    AppError::bad_request(format!("The body could not be decompressed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::io::Write;
+
+    const LIMIT: usize = 1000;
+
+    fn encoded(encoding: &str) -> HttpRequest {
+        TestRequest::default().header("Content-Encoding", encoding).to_http_request()
+    }
+
+    fn gzip(bytes: &[u8]) -> Bytes {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        Bytes::from(encoder.finish().unwrap())
+    }
+
+    fn zstd(bytes: &[u8]) -> Bytes {
+        Bytes::from(zstd::encode_all(bytes, 3).unwrap())
+    }
+
+    #[test]
+    fn decompresses_gzip_and_zstd() {
+        let content = b"hello, compressed world";
+        assert_eq!(decode(&encoded("gzip"), gzip(content), LIMIT).unwrap(), &content[..]);
+        assert_eq!(decode(&encoded("X-Gzip"), gzip(content), LIMIT).unwrap(), &content[..]);
+        assert_eq!(decode(&encoded("zstd"), zstd(content), LIMIT).unwrap(), &content[..]);
+        // Every member of a gzip stream made of several
+        let members = [gzip(b"hello, "), gzip(b"world")].concat();
+        assert_eq!(decode(&encoded("gzip"), Bytes::from(members), LIMIT).unwrap(), &b"hello, world"[..]);
+    }
+
+    #[test]
+    fn identity_is_left_as_it_is() {
+        let body = Bytes::from_static(b"as sent");
+        assert_eq!(decode(&TestRequest::default().to_http_request(), body.clone(), LIMIT).unwrap(), body);
+        assert_eq!(decode(&encoded("identity"), body.clone(), LIMIT).unwrap(), body);
+    }
+
+    #[test]
+    fn bombs_are_refused_once_decompressed() {
+        let bomb = vec![0; 100 * LIMIT];
+        for (encoding, body) in [("gzip", gzip(&bomb)), ("zstd", zstd(&bomb))] {
+            assert!(body.len() < LIMIT, "{}", encoding);
+            let e = decode(&encoded(encoding), body, LIMIT).unwrap_err();
+            assert_eq!(e.code, ErrorCode::PayloadTooLarge, "{}", encoding);
+            assert!(e.message.contains("once decompressed"), "{}", e.message);
+        }
+        // Exactly the limit still fits
+        assert_eq!(decode(&encoded("gzip"), gzip(&bomb[..LIMIT]), LIMIT).unwrap().len(), LIMIT);
+    }
+
+    #[test]
+    fn unknown_encodings_are_415() {
+        for encoding in ["br", "deflate", "gzip, zstd"] {
+            let e = decode(&encoded(encoding), Bytes::from_static(b"{}"), LIMIT).unwrap_err();
+            assert_eq!(e.code, ErrorCode::UnsupportedMediaType, "{}", encoding);
+        }
+    }
+
+    #[test]
+    fn undecodable_bodies_are_400() {
+        let e = decode(&encoded("gzip"), Bytes::from_static(b"not gzip"), LIMIT).unwrap_err();
+        assert_eq!(e.code, ErrorCode::BadRequest);
+    }
+
+    #[test]
+    fn raw_bodies_over_the_limit_are_413_as_sent() {
+        let e = received(Err(actix_web::error::PayloadError::Overflow.into()), LIMIT).unwrap_err();
+        assert_eq!(e.code, ErrorCode::PayloadTooLarge);
+        assert!(e.message.contains("as sent") && e.message.contains("1000"), "{}", e.message);
+        let e = received(Err(actix_web::error::PayloadError::Incomplete(None).into()), LIMIT).unwrap_err();
+        assert_eq!(e.code, ErrorCode::BadRequest);
+    }
+
+    #[test]
+    fn json_must_be_json() {
+        let request = TestRequest::default().header("Content-Type", "text/plain").to_http_request();
+        let e = json::<serde_json::Value>(&request, Bytes::from_static(b"{}"), LIMIT).unwrap_err();
+        assert_eq!(e.code, ErrorCode::BadRequest);
+        let request = TestRequest::default().header("Content-Type", "application/merge-patch+json").to_http_request();
+        assert!(json::<serde_json::Value>(&request, Bytes::from_static(b"{}"), LIMIT).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+// A bounded in-process cache of recently read pastes, consulted before the store.
+// A paste that suddenly gets lots of readers is then served from memory instead of querying the database
+// for every request; only its view counter still goes to the store.
+// Bounded by number of entries and by bytes of content, the least recently used pastes are dropped first.
+// Entries are removed when their paste is deleted and ignored once it has expired.
+
+use crate::store::{now, Content, Paste};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Everything the read paths need to answer without the store.
+#[derive(Clone)]
+pub struct CachedPaste {
+    pub paste: Paste,
+    pub content: Content,
+    pub tags: Vec<String>,
+}
+
+struct Entry {
+    value: CachedPaste,
+    size: usize,
+    // Last use, the key of the entry in `Inner::order`
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    // tick -> token, least recently used first
+    order: BTreeMap<u64, String>,
+    next_tick: u64,
+    bytes: usize,
+}
+
+impl Inner {
+    fn remove(&mut self, token: &str) {
+        if let Some(entry) = self.entries.remove(token) {
+            self.order.remove(&entry.tick);
+            self.bytes -= entry.size;
+        }
+    }
+}
+
+pub struct PasteCache {
+    inner: Mutex<Inner>,
+    max_entries: usize,
+    max_bytes: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// The counters shown on “/metrics”.
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+impl PasteCache {
+    // A `max_entries` of 0 disables the cache.
+    pub fn new(max_entries: usize, max_bytes: usize) -> PasteCache {
+        PasteCache {
+            inner: Mutex::new(Inner::default()),
+            max_entries,
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_entries > 0
+    }
+
+    // The cached paste with this token, unless it isn't cached or has expired since.
+    pub fn get(&self, token: &str) -> Option<CachedPaste> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = now();
+
+        let (value, old_tick) = match inner.entries.get(token) {
+            Some(entry) if entry.value.paste.expires_at.map(|expires_at| expires_at > now).unwrap_or(true) => {
+                (entry.value.clone(), entry.tick)
+            }
+            Some(_) => {
+                inner.remove(token);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+
+        // Move it to the most recently used end
+        let tick = inner.next_tick;
+        inner.next_tick += 1;
+        inner.order.remove(&old_tick);
+        inner.order.insert(tick, token.to_string());
+        inner.entries.get_mut(token).expect("entry was just found").tick = tick;
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(value)
+    }
+
+    // Caches a paste, evicting the least recently used ones to stay within bounds.
+    // Pastes bigger than the whole cache are not cached.
+    pub fn insert(&self, value: CachedPaste) {
+        let size = match &value.content {
+            Content::Inline(content) => content.len(),
+            Content::Binary(data) => data.len(),
+            Content::File(path) => path.as_os_str().len(),
+        };
+        if self.max_entries == 0 || size > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let token = value.paste.token.clone();
+        inner.remove(&token);
+
+        while inner.entries.len() >= self.max_entries || inner.bytes + size > self.max_bytes {
+            let oldest = match inner.order.values().next() {
+                Some(token) => token.clone(),
+                None => break,
+            };
+            inner.remove(&oldest);
+        }
+
+        let tick = inner.next_tick;
+        inner.next_tick += 1;
+        inner.order.insert(tick, token.clone());
+        inner.bytes += size;
+        inner.entries.insert(token, Entry { value, size, tick });
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.inner.lock().unwrap().remove(token);
+    }
+
+    // Keeps the view counter of a cached paste in step with the one in the store.
+    pub fn count_view(&self, token: &str) {
+        if let Some(entry) = self.inner.lock().unwrap().entries.get_mut(token) {
+            entry.value.paste.views += 1;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
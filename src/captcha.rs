@@ -0,0 +1,131 @@
+// A captcha on the form of the index page, hCaptcha or Cloudflare Turnstile, for an instance with
+// `PASTRY_CAPTCHA_SITE_KEY` and `PASTRY_CAPTCHA_SECRET` set. Without them nothing of it is there: no widget, no check.
+// The widget puts its answer in the form, `submit` has the provider verify it before the paste is stored.
+// When the provider can't be reached `PASTRY_CAPTCHA_FAIL_OPEN` decides: off, the form is refused until it is back;
+// on, pastes go through unchecked meanwhile. The API and the preview are never behind the captcha.
+
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+// How long the provider may take to verify an answer, connecting included.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Largest answer read from the provider, a few fields of JSON.
+const RESPONSE_LIMIT: usize = 64 * 1024;
+
+#[derive(Clone, Copy)]
+pub enum Provider {
+    HCaptcha,
+    Turnstile,
+}
+
+impl Provider {
+    fn verify_url(self) -> &'static str {
+        match self {
+            Provider::HCaptcha => "https://api.hcaptcha.com/siteverify",
+            Provider::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Provider, String> {
+        match value {
+            "hcaptcha" => Ok(Provider::HCaptcha),
+            "turnstile" => Ok(Provider::Turnstile),
+            _ => Err(format!("Unknown captcha provider \"{}\", expected hcaptcha or turnstile", value)),
+        }
+    }
+}
+
+pub struct Captcha {
+    pub provider: Provider,
+    site_key: String,
+    secret: String,
+    // `PASTRY_CAPTCHA_VERIFY_URL`, the provider's own unless pointed at a test server
+    verify_url: String,
+    // Whether the form is accepted without a check while the provider can't be reached
+    pub fail_open: bool,
+}
+
+// What the provider said of an answer.
+pub enum Verdict {
+    Passed,
+    // The answer is missing, wrong or used already, with the reason to show
+    Failed(String),
+    // The provider couldn't be asked or gave no usable answer, with why for the log
+    Unavailable(String),
+}
+
+#[derive(serde::Deserialize)]
+struct Verification {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+impl Captcha {
+    pub fn new(provider: Provider, site_key: String, secret: String, verify_url: Option<String>, fail_open: bool) -> Captcha {
+        Captcha {
+            provider,
+            site_key,
+            secret,
+            verify_url: verify_url.unwrap_or_else(|| provider.verify_url().to_string()),
+            fail_open,
+        }
+    }
+
+    // The widget for the form, with the script that draws it.
+    pub fn widget(&self) -> String {
+        let (script, class) = match self.provider {
+            Provider::HCaptcha => ("https://js.hcaptcha.com/1/api.js", "h-captcha"),
+            Provider::Turnstile => ("https://challenges.cloudflare.com/turnstile/v0/api.js", "cf-turnstile"),
+        };
+        format!(
+            "<script src=\"{}\" async defer></script>\n        <div class=\"{} mb-4\" data-sitekey=\"{}\" data-theme=\"dark\"></div>",
+            script,
+            class,
+            crate::escape_html(&self.site_key)
+        )
+    }
+
+    // Asks the provider whether `response`, what the widget put in the form, is a solved captcha.
+    pub async fn verify(&self, response: &str, remote_ip: Option<IpAddr>) -> Verdict {
+        if response.is_empty() {
+            return Verdict::Failed("Solve the captcha before submitting".to_string());
+        }
+
+        let mut form = vec![("secret", self.secret.clone()), ("response", response.to_string())];
+        if let Some(ip) = remote_ip {
+            form.push(("remoteip", ip.to_string()));
+        }
+        if let Provider::HCaptcha = self.provider {
+            form.push(("sitekey", self.site_key.clone()));
+        }
+
+        let client = awc::Client::builder().timeout(VERIFY_TIMEOUT).finish();
+        let mut answer = match client.post(&self.verify_url).send_form(&form).await {
+            Ok(answer) => answer,
+            Err(e) => return Verdict::Unavailable(format!("the provider could not be reached: {}", e)),
+        };
+        if !answer.status().is_success() {
+            return Verdict::Unavailable(format!("the provider answered {}", answer.status()));
+        }
+        let verification = match answer.json::<Verification>().limit(RESPONSE_LIMIT).await {
+            Ok(verification) => verification,
+            Err(e) => return Verdict::Unavailable(format!("the answer of the provider could not be read: {}", e)),
+        };
+
+        if verification.success {
+            return Verdict::Passed;
+        }
+        // Codes about the secret or the site key are ours to fix, not the visitor's
+        if verification.error_codes.iter().any(|code| code.contains("secret") || code.contains("sitekey")) {
+            eprintln!("Captcha: the provider refused the keys of this server: {}", verification.error_codes.join(", "));
+        }
+        Verdict::Failed("The captcha was not solved, or has expired, try again".to_string())
+    }
+}
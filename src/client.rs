@@ -0,0 +1,64 @@
+// Who a request comes from, for the per-client limits.
+// Behind a reverse proxy every connection comes from the proxy, the client is in `X-Forwarded-For` instead;
+// that header is only believed when the connection comes from one of the trusted proxies,
+// anyone else could put whatever they like in it.
+
+use actix_web::http::HeaderMap;
+use actix_web::HttpRequest;
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+
+// Parses the `PASTRY_TRUSTED_PROXIES` setting, a comma separated list of IP addresses.
+pub fn parse_trusted_proxies(value: &str) -> Result<Vec<IpAddr>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.parse().map_err(|_| format!("{:?} is not an IP address", entry)))
+        .collect()
+}
+
+// The IP of the client that sent `req`, `None` when the connection has no peer address.
+// From a trusted proxy, the rightmost address of `X-Forwarded-For` that isn't a trusted proxy itself is the client,
+// the ones left of it were added by the client or by proxies we know nothing about.
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    client_ip_of(req.peer_addr(), req.headers(), trusted_proxies)
+}
+
+// The same from the peer address and the headers of a request, for middlewares that don't have an `HttpRequest`.
+pub fn client_ip_of(peer: Option<SocketAddr>, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = peer?.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| entry.trim().parse().ok())
+        .collect();
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !trusted_proxies.contains(ip))
+        .or_else(|| forwarded.first())
+        .copied()
+        .or(Some(peer))
+}
+
+// What gets stored in place of an IP: a SHA-256 of the server's salt and the address,
+// so the database never holds the addresses themselves.
+pub fn hashed_ip(ip: Option<IpAddr>, salt: &str) -> String {
+    let ip = ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
+    format!("{:x}", Sha256::digest(format!("{}:{}", salt, ip).as_bytes()))
+}
+
+// The values of the cookies called `name` that the `headers` of a request carry, in the order they were sent.
+pub fn cookies<'a>(headers: &'a HeaderMap, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    headers
+        .get_all("Cookie")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(move |pair| pair.trim().strip_prefix(name)?.strip_prefix('='))
+}
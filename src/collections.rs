@@ -0,0 +1,428 @@
+// Collections, a shareable list of existing pastes: “/c/{token}” links each of them with a preview, in the order
+// its creator chose. A collection only names its pastes, it holds none of their content and gives no access a
+// paste link doesn't: whoever has a token can already read the paste.
+// It is made with `POST /api/collections`, answered with a secret like a paste, which edits its list and deletes it
+// (`PUT /api/collections/{token}/pastes` and `DELETE /api/collections/{token}` with `?key=`, or the form of
+// “/c/{token}?key=…”). Its pastes are checked to exist when they are listed; one deleted or expired since shows
+// as unavailable. A collection has an expiry of its own, of the same options as pastes.
+
+use crate::error::AppError;
+use crate::i18n::Texts;
+use crate::store::{self, ContentSize};
+use crate::{api_tokens, assets, openapi, reserved, telemetry, timestamp};
+use crate::{
+    api_paste, authorize, check_daily_quota, check_writable, escape_html, format_size, load_paste, paste_label,
+    parse_expiry, random_string, secret_matches, store_write, AppState, Caller, KeyQuery, Quota, Reader, SECRET_LEN,
+    TOKEN_ATTEMPTS,
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use pastry_crust::api::ApiPaste;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+// Most pastes of one collection.
+pub const MAX_PASTES: usize = 50;
+
+pub const MAX_TITLE_CHARS: usize = 100;
+
+// Characters of a collection token.
+const TOKEN_LEN: usize = 10;
+
+// Characters of a paste shown in the listing, on one line.
+pub const PREVIEW_CHARS: usize = 120;
+
+// A new random collection token.
+pub fn new_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LEN)
+        .map(char::from)
+        .collect()
+}
+
+// The title as stored, trimmed. Returns a message suitable for showing to the user when it is not acceptable.
+pub fn check_title(title: &str) -> Result<String, String> {
+    let title = title.trim();
+    if title.chars().count() > MAX_TITLE_CHARS {
+        return Err(format!("Titles are limited to {} characters", MAX_TITLE_CHARS));
+    }
+    if title.chars().any(char::is_control) {
+        return Err("Titles can't contain control characters".to_string());
+    }
+    Ok(title.to_string())
+}
+
+// The tokens of the pastes of a collection, in order, from what a client sent: each one a token or a link to a
+// paste (“https://…/paste/abc123?key=…” gives “abc123”). Checks their number and that none is there twice;
+// whether they exist is up to the caller.
+pub fn parse_pastes(pastes: &[String]) -> Result<Vec<String>, String> {
+    let tokens: Vec<String> = pastes
+        .iter()
+        .map(|paste| paste_token(paste).to_string())
+        .filter(|token| !token.is_empty())
+        .collect();
+    if tokens.is_empty() {
+        return Err("A collection needs at least one paste".to_string());
+    }
+    if tokens.len() > MAX_PASTES {
+        return Err(format!("A collection holds at most {} pastes", MAX_PASTES));
+    }
+    if let Some((index, token)) = tokens.iter().enumerate().find(|(index, token)| tokens[..*index].contains(token)) {
+        return Err(format!("Paste {} of the list, {}, is already in it", index + 1, token));
+    }
+    Ok(tokens)
+}
+
+// The token of a paste link, or `value` itself when it isn't one.
+fn paste_token(value: &str) -> &str {
+    let value = value.trim();
+    let path = match value.find("/paste/") {
+        Some(start) => &value[start + "/paste/".len()..],
+        None => value,
+    };
+    let end = path.find(['/', '?', '#']).unwrap_or(path.len());
+    &path[..end]
+}
+
+// The first line of a text with something on it, cut at `PREVIEW_CHARS`.
+pub fn preview(text: &str) -> String {
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("");
+    match line.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+// The page of a collection and its forms.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/c/{token}"), web::get().to(collection_page))
+        .route(reserved::route("/c/{token}/edit"), web::post().to(edit_collection))
+        .route(reserved::route("/c/{token}/delete"), web::post().to(delete_collection));
+}
+
+// The routes of collections in the `/api` scope.
+pub fn configure_api(cfg: &mut web::ServiceConfig) {
+    cfg.route(openapi::route("POST", "/collections"), web::post().to(api_create_collection))
+        .route(openapi::route("GET", "/collections/{token}"), web::get().to(api_get_collection))
+        .route(openapi::route("DELETE", "/collections/{token}"), web::delete().to(api_delete_collection))
+        .route(openapi::route("PUT", "/collections/{token}/pastes"), web::put().to(api_set_collection_pastes));
+}
+
+// Handles “POST /api/collections”, a collection of existing pastes.
+// Answers 201 with its token, its secret and the URL of its page. Counts against the daily paste quota.
+async fn api_create_collection(req: HttpRequest, body: web::Json<ApiNewCollection>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Write)?;
+    let body = body.into_inner();
+    let title = check_title(&body.title).map_err(AppError::bad_request)?;
+    let expires_at = parse_expiry(body.expires.as_deref().unwrap_or(""))
+        .map_err(AppError::bad_request)?
+        .map(|seconds| store::now() + seconds);
+    let pastes = collection_pastes(&data, &body.pastes).await?;
+    check_writable(&data)?;
+    check_daily_quota(&req, &data, Quota::Pastes, 1).await?;
+
+    let secret = random_string(SECRET_LEN);
+    let collection_secret = secret.clone();
+    let inserted = store_write(&data, move |store| {
+        for _ in 0..TOKEN_ATTEMPTS {
+            let collection = store::NewCollection {
+                token: new_token(),
+                secret: collection_secret.clone(),
+                title: title.clone(),
+                expires_at,
+                pastes: pastes.clone(),
+            };
+            match store.insert_collection(&collection) {
+                Ok(()) => return Ok(Some(collection.token)),
+                Err(e) if e.is_conflict() => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    })
+    .await?;
+    let token = inserted.ok_or_else(|| AppError::internal("Could not find a free token, try again"))?;
+    let created = ApiCreatedCollection {
+        url: format!("/c/{}", token),
+        token,
+        secret,
+    };
+    Ok(HttpResponse::Created()
+        .header("Location", created.url.as_str())
+        .json(created))
+}
+
+// The tokens of the pastes a collection is made of or edited to, each one checked to exist: one that doesn't, or
+// has expired, is refused with its place in the list.
+async fn collection_pastes(data: &AppState, pastes: &[String]) -> Result<Vec<String>, AppError> {
+    let tokens = parse_pastes(pastes).map_err(AppError::bad_request)?;
+    for (index, token) in tokens.iter().enumerate() {
+        if load_paste(data, token, false, Reader::Anyone).await?.is_none() {
+            return Err(AppError::bad_request(format!(
+                "Paste {} of the list, {}, doesn't exist, has expired or is awaiting review",
+                index + 1,
+                token
+            )));
+        }
+    }
+    Ok(tokens)
+}
+
+// The collection `token`, a 404 when there is none or it has expired.
+fn find_collection(data: &AppState, token: &str) -> Result<store::Collection, AppError> {
+    data.store
+        .collection(token)?
+        .ok_or_else(|| AppError::not_found("Collection not found"))
+}
+
+// The pastes of a collection as the API shows them: each one with its metadata and a preview, or as unavailable
+// when it was deleted or expired since it was added. Reading them counts no view.
+async fn collection_members(data: &AppState, collection: &store::Collection) -> Result<Vec<ApiCollectionMember>, AppError> {
+    let mut members = Vec::with_capacity(collection.pastes.len());
+    for token in &collection.pastes {
+        let cached = match load_paste(data, token, false, Reader::Anyone).await? {
+            Some(cached) => cached,
+            None => {
+                members.push(ApiCollectionMember {
+                    token: token.clone(),
+                    available: false,
+                    url: None,
+                    preview: None,
+                    paste: None,
+                });
+                continue;
+            }
+        };
+        let preview = match &cached.content {
+            store::Content::Inline(text) => Some(preview(text)),
+            // The row keeps the start of what is in the blob file
+            store::Content::File(_) => Some(preview(&cached.paste.content)),
+            store::Content::Binary(_) => None,
+        };
+        let page = if cached.paste.redirect { "/preview" } else { "" };
+        members.push(ApiCollectionMember {
+            token: token.clone(),
+            available: true,
+            url: Some(format!("/paste/{}{}", token, page)),
+            preview,
+            paste: Some(api_paste(cached, false, false)?),
+        });
+    }
+    Ok(members)
+}
+
+async fn api_collection(data: &AppState, collection: &store::Collection) -> Result<ApiCollection, AppError> {
+    Ok(ApiCollection {
+        token: collection.token.clone(),
+        title: collection.title.clone(),
+        url: format!("/c/{}", collection.token),
+        created_at: collection.created_at,
+        updated_at: collection.updated_at,
+        expires_at: collection.expires_at,
+        pastes: collection_members(data, collection).await?,
+    })
+}
+
+// Handles “GET /api/collections/{token}”, the collection with the metadata of its pastes, see `collection_members`.
+async fn api_get_collection(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let collection = find_collection(&data, &token)?;
+    Ok(HttpResponse::Ok().json(api_collection(&data, &collection).await?))
+}
+
+// Handles “PUT /api/collections/{token}/pastes?key=…”, the new list of the pastes of a collection, in their order.
+// Answers with the collection as “GET /api/collections/{token}” does.
+async fn api_set_collection_pastes(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    body: web::Json<ApiCollectionPastes>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Write)?;
+    let collection = find_collection(&data, &token)?;
+    if !query.key.as_deref().is_some_and(|key| secret_matches(&collection.secret, key)) {
+        return Err(AppError::forbidden("A valid key is required to edit this collection"));
+    }
+    let pastes = collection_pastes(&data, &body.pastes).await?;
+    let edited = collection.token.clone();
+    store_write(&data, move |store| store.set_collection_pastes(&edited, &pastes)).await?;
+    let collection = find_collection(&data, &token)?;
+    Ok(HttpResponse::Ok().json(api_collection(&data, &collection).await?))
+}
+
+// Handles “DELETE /api/collections/{token}?key=…”, or with a token having the `delete` scope. Its pastes stay.
+async fn api_delete_collection(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let caller = authorize(&req, &data, api_tokens::Scope::Delete)?;
+    let collection = find_collection(&data, &token)?;
+    if matches!(caller, Caller::Anonymous) && !query.key.as_deref().is_some_and(|key| secret_matches(&collection.secret, key)) {
+        return Err(AppError::forbidden("A valid key is required to delete this collection"));
+    }
+    store_write(&data, move |store| store.delete_collection(&collection.token)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Handles “/c/{token}”, the page of a collection: its pastes in order, linked, with their size and a preview.
+// With `key` its creator also gets the form editing the list and the button deleting the collection.
+async fn collection_page(token: web::Path<String>, query: web::Query<KeyQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let collection = find_collection(&data, &token)?;
+    let members = collection_members(&data, &collection).await?;
+    let key = query.key.as_deref().filter(|key| secret_matches(&collection.secret, key));
+
+    let list_items: String = members
+        .iter()
+        .map(|member| {
+            let token = escape_html(&member.token);
+            let (url, paste) = match (&member.url, &member.paste) {
+                (Some(url), Some(paste)) => (url, paste),
+                _ => return format!("<li>{} &middot; unavailable, deleted or expired</li>", token),
+            };
+            let mut meta = vec![escape_html(&format_size(&ContentSize {
+                lines: paste.lines,
+                chars: paste.chars,
+                bytes: paste.bytes,
+            }, Texts::english()))];
+            meta.push(format!("created {}", timestamp::html(paste.created_at, Texts::english())));
+            if let Some(expires_at) = paste.expires_at {
+                meta.push(format!("expires {}", timestamp::html(expires_at, Texts::english())));
+            }
+            format!(
+                "<li><a href=\"{url}\">{label}</a> &middot; {meta}<span class=\"preview\">{preview}</span></li>",
+                url = escape_html(url),
+                label = paste_label(&member.token, paste.title.as_deref().unwrap_or("")),
+                meta = meta.join(" &middot; "),
+                preview = escape_html(member.preview.as_deref().unwrap_or("")),
+            )
+        })
+        .collect();
+
+    let mut meta = vec![
+        format!("{} pastes", members.len()),
+        format!("created {}", timestamp::html(collection.created_at, Texts::english())),
+    ];
+    if collection.updated_at != collection.created_at {
+        meta.push(format!("updated {}", timestamp::html(collection.updated_at, Texts::english())));
+    }
+    if let Some(expires_at) = collection.expires_at {
+        meta.push(format!("expires {}", timestamp::html(expires_at, Texts::english())));
+    }
+    let manage = match key {
+        Some(key) => format!(
+            "<form method=\"post\" action=\"/c/{token}/edit\" class=\"w-full max-w-2xl mt-6\">\
+             <input type=\"hidden\" name=\"key\" value=\"{key}\">\
+             <label class=\"meta block mb-2\" for=\"pastes\">The pastes, a token or a link per line, in order:</label>\
+             <textarea id=\"pastes\" name=\"pastes\" rows=\"8\" class=\"w-full p-2 border border-gray-600 rounded-md mb-4 bg-black text-white\">{pastes}</textarea>\
+             <button type=\"submit\" class=\"bg-indigo-600 text-white py-1 px-3 rounded-md hover:bg-indigo-700\">Save</button></form>\
+             <form method=\"post\" action=\"/c/{token}/delete\" class=\"mt-4\"><input type=\"hidden\" name=\"key\" value=\"{key}\">\
+             <button type=\"submit\">Delete this collection</button> (its pastes stay)</form>",
+            token = escape_html(&collection.token),
+            key = escape_html(key),
+            pastes = escape_html(&collection.pastes.join("\n")),
+        ),
+        None => String::new(),
+    };
+    let title = if collection.title.is_empty() { format!("Collection {}", collection.token) } else { collection.title.clone() };
+
+    let _render = telemetry::template("collection.html");
+    let html_page = assets::versioned(include_str!("collection.html"))
+        .replace("{{title}}", &escape_html(&title))
+        .replace("{{meta}}", &meta.join(" &middot; "))
+        .replace("{{list_items}}", &list_items)
+        .replace("{{manage}}", &manage);
+
+    let mut response = HttpResponse::Ok();
+    response.content_type("text/html");
+    if key.is_some() {
+        response.header("Cache-Control", "private, no-store");
+    }
+    Ok(response.body(html_page))
+}
+
+// Handles the edit form of “/c/{token}?key=…”, back to that page once saved.
+async fn edit_collection(token: web::Path<String>, form: web::Form<CollectionForm>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let collection = find_collection(&data, &token)?;
+    let key = form.key.as_deref().filter(|key| secret_matches(&collection.secret, key));
+    let key = key.ok_or_else(|| AppError::forbidden("A valid key is required to edit this collection"))?;
+    let lines: Vec<String> = form.pastes.as_deref().unwrap_or("").lines().map(str::to_string).collect();
+    let pastes = collection_pastes(&data, &lines).await?;
+    let edited = collection.token.clone();
+    store_write(&data, move |store| store.set_collection_pastes(&edited, &pastes)).await?;
+    Ok(HttpResponse::SeeOther()
+        .header("Location", format!("/c/{}?key={}", collection.token, key))
+        .finish())
+}
+
+// Handles the delete button of “/c/{token}?key=…”, back to the index page.
+async fn delete_collection(token: web::Path<String>, form: web::Form<CollectionForm>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let collection = find_collection(&data, &token)?;
+    if !form.key.as_deref().is_some_and(|key| secret_matches(&collection.secret, key)) {
+        return Err(AppError::forbidden("A valid key is required to delete this collection"));
+    }
+    store_write(&data, move |store| store.delete_collection(&collection.token)).await?;
+    Ok(HttpResponse::SeeOther().header("Location", "/").finish())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiNewCollection {
+    #[serde(default)]
+    pub title: String,
+    // Tokens or links of pastes, in order
+    pub pastes: Vec<String>,
+    // One of the values of `parse_expiry`
+    pub expires: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiCollectionPastes {
+    pub pastes: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiCreatedCollection {
+    pub token: String,
+    pub secret: String,
+    pub url: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiCollection {
+    pub token: String,
+    pub title: String,
+    pub url: String,
+    #[serde(serialize_with = "timestamp::serialize")]
+    pub created_at: i64,
+    #[serde(serialize_with = "timestamp::serialize")]
+    pub updated_at: i64,
+    #[serde(serialize_with = "timestamp::serialize_option")]
+    pub expires_at: Option<i64>,
+    pub pastes: Vec<ApiCollectionMember>,
+}
+
+// A paste of a collection, only its token when it isn't `available` anymore.
+#[derive(serde::Serialize)]
+pub struct ApiCollectionMember {
+    pub token: String,
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    // The first line of text pastes, the URL of short links
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+    // Without its content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paste: Option<ApiPaste>,
+}
+
+#[derive(serde::Deserialize)]
+struct CollectionForm {
+    key: Option<String>,
+    // A token or a paste link per line
+    pastes: Option<String>,
+}
@@ -0,0 +1,241 @@
+// The comments left on pastes, “line 23 is wrong”: the form under the page of a paste, its creator deleting them,
+// an admin deleting any, and `GET /api/pastes/{token}/comments`.
+// A comment is a name, anything up to `MAX_AUTHOR_CHARS` and “anonymous” when left empty, and a text of up to
+// `MAX_BODY_CHARS`. Both are kept as typed, trimmed, and only ever put in pages escaped.
+
+use crate::error::{self, AppError};
+use crate::i18n::Texts;
+use crate::{api_tokens, escape_html, openapi, reserved, review, store, timestamp};
+use crate::{audit_entry, authorize, check_daily_quota, check_writable, creator_key, require_admin};
+use crate::{store_write, AppState, KeyQuery, Quota};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+pub const MAX_AUTHOR_CHARS: usize = 40;
+pub const MAX_BODY_CHARS: usize = 2000;
+
+// Most comments one paste takes, past them the form is refused.
+pub const MAX_PER_PASTE: usize = 200;
+
+pub const ANONYMOUS: &str = "anonymous";
+
+// The author and text of a comment as they get stored.
+// Returns a message suitable for showing to the user when one of them is not acceptable.
+pub fn check(author: &str, body: &str) -> Result<(String, String), String> {
+    let author = author.trim();
+    let body = body.trim();
+    if author.chars().count() > MAX_AUTHOR_CHARS {
+        return Err(format!("Names are limited to {} characters", MAX_AUTHOR_CHARS));
+    }
+    if body.is_empty() {
+        return Err("A comment needs some text".to_string());
+    }
+    if body.chars().count() > MAX_BODY_CHARS {
+        return Err(format!("Comments are limited to {} characters", MAX_BODY_CHARS));
+    }
+    if author.chars().chain(body.chars()).any(|c| c.is_control() && c != '\n' && c != '\r' && c != '\t') {
+        return Err("Comments can't contain control characters".to_string());
+    }
+
+    let author = if author.is_empty() { ANONYMOUS } else { author };
+    Ok((author.to_string(), body.replace("\r\n", "\n")))
+}
+
+// The routes of the form and the creator's and admin's deletes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/paste/{token}/comments"), web::post().to(add_comment))
+        .route(reserved::route("/paste/{token}/comments/{id}/delete"), web::post().to(delete_comment))
+        .route(reserved::route("/admin/pastes/{token}/comments/{id}"), web::delete().to(admin_delete_comment));
+}
+
+// The comments of a paste in the `/api` scope.
+pub fn configure_api(cfg: &mut web::ServiceConfig) {
+    cfg.route(openapi::route("GET", "/pastes/{token}/comments"), web::get().to(api_comments));
+}
+
+// The comments at the bottom of the page of a paste, oldest first, with the form to add one while the paste takes them;
+// the creator (with `key`, see `creator_key`) also gets a button deleting each. Nothing when comments are off,
+// nor while the paste awaits review, when nobody else could read them.
+// What was typed is escaped, and kept as it was written, line breaks included.
+pub fn comments_section(data: &AppState, paste: &store::Paste, key: Option<&str>, texts: Texts) -> Result<String, AppError> {
+    if !data.settings().comments || paste.pending {
+        return Ok(String::new());
+    }
+    let token = escape_html(&paste.token);
+    let comments = data.store.comments(&paste.token)?;
+
+    let mut html = format!(
+        "<section id=\"comments\" class=\"comments\"><h3>{}</h3>",
+        escape_html(&texts.plural("comments.count", comments.len() as i64))
+    );
+    for comment in &comments {
+        let delete = match key {
+            Some(key) => format!(
+                " · <form method=\"post\" action=\"/paste/{token}/comments/{id}/delete\"><input type=\"hidden\" name=\"key\" value=\"{key}\">\
+                 <button type=\"submit\">{delete}</button></form>",
+                token = token,
+                id = comment.id,
+                key = escape_html(key),
+                delete = escape_html(texts.text("comments.delete")),
+            ),
+            None => String::new(),
+        };
+        html.push_str(&format!(
+            "<div class=\"comment\" id=\"comment-{id}\"><div class=\"meta\">{author} · {created}{delete}</div><p>{body}</p></div>",
+            id = comment.id,
+            author = escape_html(&comment.author),
+            created = timestamp::html(comment.created_at, texts),
+            delete = delete,
+            body = escape_html(&comment.body),
+        ));
+    }
+
+    if !paste.comments {
+        html.push_str(&format!("<p class=\"meta\">{}</p>", escape_html(texts.text("comments.off"))));
+    } else if comments.len() >= MAX_PER_PASTE {
+        html.push_str(&format!("<p class=\"meta\">{}</p>", escape_html(texts.text("comments.full"))));
+    } else {
+        html.push_str(&format!(
+            "<form method=\"post\" action=\"/paste/{token}/comments\">\
+             <input type=\"text\" name=\"author\" maxlength=\"{author_chars}\" placeholder=\"{name_placeholder}\" aria-label=\"{name}\">\
+             <textarea name=\"body\" rows=\"3\" maxlength=\"{body_chars}\" required placeholder=\"{placeholder}\" aria-label=\"{comment}\"></textarea>\
+             <button type=\"submit\">{submit}</button></form>",
+            token = token,
+            author_chars = MAX_AUTHOR_CHARS,
+            body_chars = MAX_BODY_CHARS,
+            name_placeholder = escape_html(texts.text("comments.name-placeholder")),
+            name = escape_html(texts.text("comments.name")),
+            placeholder = escape_html(texts.text("comments.placeholder")),
+            comment = escape_html(texts.text("comments.comment")),
+            submit = escape_html(texts.text("comments.submit")),
+        ));
+    }
+    html.push_str("</section>");
+    Ok(html)
+}
+
+// The paste of the comment routes: 404 when comments are off on this server, the paste is unknown or awaits review.
+// Short links have no page to comment on.
+fn commented_paste(data: &AppState, token: &str) -> Result<store::Paste, AppError> {
+    if !data.settings().comments {
+        return Err(AppError::not_found("Comments are not enabled on this server"));
+    }
+    match data.store.get(token)? {
+        Some(paste) if paste.pending => Err(AppError::not_found(review::AWAITING)),
+        Some(paste) if !paste.redirect => Ok(paste),
+        _ => Err(AppError::not_found("Paste not found")),
+    }
+}
+
+// Handles “POST /paste/{token}/comments”, a comment left with the form under a paste, checked by `check`.
+// A paste created without comments is a 403, one with `MAX_PER_PASTE` of them already a 409.
+// Comments count against a daily quota of their own (`PASTRY_DAILY_COMMENT_QUOTA`). Sends back to the new comment.
+async fn add_comment(
+    req: HttpRequest,
+    token: web::Path<String>,
+    form: web::Form<CommentForm>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let paste = commented_paste(&data, &token)?;
+    if !paste.comments {
+        return Err(AppError::forbidden("Comments are turned off for this paste"));
+    }
+    let (author, body) = check(form.author.as_deref().unwrap_or(""), &form.body).map_err(AppError::bad_request)?;
+    if data.store.comments(&paste.token)?.len() >= MAX_PER_PASTE {
+        return Err(AppError::new(error::ErrorCode::Conflict, "This paste has all the comments it can take"));
+    }
+    check_writable(&data)?;
+    check_daily_quota(&req, &data, Quota::Comments, 1).await?;
+
+    let comment = store::NewComment {
+        token: paste.token.clone(),
+        author,
+        body,
+    };
+    let id = store_write(&data, move |store| store.add_comment(&comment)).await?;
+    Ok(HttpResponse::SeeOther()
+        .header("Location", format!("/paste/{}#comment-{}", paste.token, id))
+        .finish())
+}
+
+// Handles “POST /paste/{token}/comments/{id}/delete”, the creator deleting a comment of their paste with its key
+// or their creator cookie. Sends back to the comments of the page, keeping the key.
+async fn delete_comment(
+    req: HttpRequest,
+    path: web::Path<(String, i64)>,
+    form: web::Form<KeyQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let (token, id) = path.into_inner();
+    let paste = commented_paste(&data, &token)?;
+    let key = creator_key(&req, &data, &paste, form.key.as_deref())
+        .ok_or_else(|| AppError::forbidden("A valid key is required to delete the comments of this paste"))?;
+
+    let audit = audit_entry(&req, &data, "creator", "delete_comment", Some(paste.token.clone()), format!("comment {}", id));
+    let commented = paste.token.clone();
+    if !store_write(&data, move |store| store.delete_comment(&commented, id, Some(&audit))).await? {
+        return Err(AppError::not_found("Comment not found"));
+    }
+    let key = if form.key.is_some() { format!("?key={}", key) } else { String::new() };
+    Ok(HttpResponse::SeeOther()
+        .header("Location", format!("/paste/{}{}#comments", paste.token, key))
+        .finish())
+}
+
+// Handles “DELETE /admin/pastes/{token}/comments/{id}”, an admin deleting any comment, answered with a 204.
+async fn admin_delete_comment(
+    req: HttpRequest,
+    path: web::Path<(String, i64)>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+    let (token, id) = path.into_inner();
+    let audit = audit_entry(&req, &data, "admin", "delete_comment", Some(token.clone()), format!("comment {}", id));
+    if !store_write(&data, move |store| store.delete_comment(&token, id, Some(&audit))).await? {
+        return Err(AppError::not_found("Comment not found"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Handles “GET /api/pastes/{token}/comments”, the comments of a paste as JSON, oldest first, and whether it takes
+// new ones. Reading them doesn't count as a view; the other API routes of a paste never include them.
+async fn api_comments(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let paste = commented_paste(&data, &token)?;
+    let comments = data.store.comments(&paste.token)?;
+    Ok(HttpResponse::Ok().json(ApiComments {
+        open: paste.comments && comments.len() < MAX_PER_PASTE,
+        token: paste.token,
+        comments: comments
+            .into_iter()
+            .map(|comment| ApiComment {
+                id: comment.id,
+                author: comment.author,
+                body: comment.body,
+                created_at: comment.created_at,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiComments {
+    pub token: String,
+    // Whether the paste takes new comments
+    pub open: bool,
+    pub comments: Vec<ApiComment>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiComment {
+    pub id: i64,
+    pub author: String,
+    pub body: String,
+    #[serde(serialize_with = "timestamp::serialize")]
+    pub created_at: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct CommentForm {
+    author: Option<String>,
+    body: String,
+}
@@ -0,0 +1,208 @@
+// The configuration file, a TOML file named by `PASTRY_CONFIG`.
+// Its keys are the environment variables without `PASTRY_`, in lower case: `max_paste_bytes = 1048576` does what
+// `PASTRY_MAX_PASTE_BYTES=1048576` does. A key set in the file wins over its variable, which still applies to the
+// keys the file leaves out.
+// On SIGHUP the file is read again. The keys of `HOT_KEYS` take effect right away, the others (addresses, paths,
+// the database) only on the next start, which is logged. A file that doesn't parse or validate changes nothing.
+
+use crate::captcha;
+use crate::client;
+use crate::db_check;
+use crate::filename;
+use crate::images;
+use crate::redirect;
+use crate::token::TokenGenerator;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub bind: Option<Vec<String>>,
+    pub internal_bind: Option<Vec<String>>,
+    pub db_path: Option<String>,
+    pub db_open_attempts: Option<u32>,
+    pub db_open_backoff_ms: Option<u64>,
+    pub db_timeout_ms: Option<u64>,
+    pub db_write_retry_ms: Option<u64>,
+    pub db_check: Option<String>,
+    pub db_check_failure: Option<String>,
+    pub slow_query_ms: Option<u64>,
+    pub memory_max_pastes: Option<usize>,
+    pub memory_max_bytes: Option<usize>,
+    pub blob_dir: Option<String>,
+    pub blob_threshold: Option<usize>,
+    pub backup_dir: Option<String>,
+    pub backup_keep: Option<usize>,
+    pub assets_dir: Option<String>,
+    pub admin_token: Option<String>,
+    pub ip_salt: Option<String>,
+    pub cookie_secret: Option<String>,
+    pub trusted_proxies: Option<Vec<String>>,
+    pub cache_max_entries: Option<usize>,
+    pub cache_max_bytes: Option<usize>,
+    pub cache_prime: Option<usize>,
+    pub request_timeout_secs: Option<u64>,
+    pub client_timeout_ms: Option<u64>,
+    pub otlp_endpoint: Option<String>,
+    pub max_paste_bytes: Option<usize>,
+    pub max_display_bytes: Option<usize>,
+    pub normalize_line_endings: Option<bool>,
+    pub token_strategy: Option<String>,
+    pub archive_after_days: Option<i64>,
+    pub archive_promote: Option<bool>,
+    pub daily_paste_quota: Option<i64>,
+    pub daily_preview_quota: Option<i64>,
+    pub comments: Option<bool>,
+    pub daily_comment_quota: Option<i64>,
+    pub daily_validate_quota: Option<i64>,
+    pub audit_retention_days: Option<i64>,
+    pub redirect_allow_internal: Option<bool>,
+    pub redirect_mode: Option<String>,
+    pub image_policy: Option<String>,
+    pub image_proxy_allowlist: Option<Vec<String>>,
+    pub image_proxy_max_bytes: Option<usize>,
+    pub api_require_token: Option<bool>,
+    pub download_filename: Option<String>,
+    pub review_pastes: Option<bool>,
+    pub disk_warn_bytes: Option<u64>,
+    pub disk_min_bytes: Option<u64>,
+    pub disk_read_only: Option<bool>,
+    pub disk_check_secs: Option<u64>,
+    pub github_token: Option<String>,
+    pub gist_api_url: Option<String>,
+    pub captcha_provider: Option<String>,
+    pub captcha_site_key: Option<String>,
+    pub captcha_secret: Option<String>,
+    pub captcha_verify_url: Option<String>,
+    pub captcha_fail_open: Option<bool>,
+    pub stats_push_url: Option<String>,
+    pub stats_push_instance: Option<String>,
+    pub stats_push_token: Option<String>,
+    pub stats_push_interval_secs: Option<u64>,
+    pub federation_token: Option<String>,
+}
+
+// The keys a reload applies to the running server.
+pub const HOT_KEYS: &[&str] = &[
+    "backup_keep",
+    "max_paste_bytes",
+    "max_display_bytes",
+    "normalize_line_endings",
+    "token_strategy",
+    "archive_after_days",
+    "archive_promote",
+    "daily_paste_quota",
+    "daily_preview_quota",
+    "comments",
+    "daily_comment_quota",
+    "daily_validate_quota",
+    "audit_retention_days",
+    "redirect_allow_internal",
+    "redirect_mode",
+    "image_policy",
+    "image_proxy_allowlist",
+    "image_proxy_max_bytes",
+    "api_require_token",
+    "download_filename",
+    "review_pastes",
+    "disk_warn_bytes",
+    "disk_min_bytes",
+    "disk_read_only",
+];
+
+impl Config {
+    // Reads and checks the file at `path`. Errors start with the path and name the offending key,
+    // with its line when the file doesn't parse.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.validate().map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(strategy) = &self.token_strategy {
+            strategy
+                .parse::<TokenGenerator>()
+                .map_err(|e| format!("key `token_strategy`: {}", e))?;
+        }
+        if let Some(template) = &self.download_filename {
+            template
+                .parse::<filename::Template>()
+                .map_err(|e| format!("key `download_filename`: {}", e))?;
+        }
+        if let Some(mode) = &self.redirect_mode {
+            mode.parse::<redirect::Mode>().map_err(|e| format!("key `redirect_mode`: {}", e))?;
+        }
+        if let Some(policy) = &self.image_policy {
+            policy.parse::<images::Policy>().map_err(|e| format!("key `image_policy`: {}", e))?;
+        }
+        if let Some(mode) = &self.db_check {
+            mode.parse::<db_check::Mode>().map_err(|e| format!("key `db_check`: {}", e))?;
+        }
+        if let Some(on_failure) = &self.db_check_failure {
+            on_failure
+                .parse::<db_check::OnFailure>()
+                .map_err(|e| format!("key `db_check_failure`: {}", e))?;
+        }
+        if let Some(provider) = &self.captcha_provider {
+            provider
+                .parse::<captcha::Provider>()
+                .map_err(|e| format!("key `captcha_provider`: {}", e))?;
+        }
+        if let Some(proxies) = &self.trusted_proxies {
+            client::parse_trusted_proxies(&proxies.join(",")).map_err(|e| format!("key `trusted_proxies`: {}", e))?;
+        }
+        if self.db_open_attempts == Some(0) {
+            return Err("key `db_open_attempts`: must be at least 1".to_string());
+        }
+        if self.max_paste_bytes == Some(0) {
+            return Err("key `max_paste_bytes`: must be at least 1".to_string());
+        }
+        for (key, value) in [
+            ("archive_after_days", self.archive_after_days),
+            ("daily_paste_quota", self.daily_paste_quota),
+            ("daily_preview_quota", self.daily_preview_quota),
+            ("daily_comment_quota", self.daily_comment_quota),
+            ("daily_validate_quota", self.daily_validate_quota),
+            ("audit_retention_days", self.audit_retention_days),
+        ] {
+            if value.is_some_and(|value| value < 0) {
+                return Err(format!("key `{}`: can't be negative, 0 turns it off", key));
+            }
+        }
+        Ok(())
+    }
+
+    // The keys outside of `HOT_KEYS` that `other` sets differently, set or unset included.
+    pub fn restart_changes(&self, other: &Config) -> Vec<String> {
+        self.changes(other, false)
+    }
+
+    // The keys of `HOT_KEYS` that `other` sets differently, for the audit log of a reload.
+    pub fn hot_changes(&self, other: &Config) -> Vec<String> {
+        self.changes(other, true)
+    }
+
+    fn changes(&self, other: &Config, hot: bool) -> Vec<String> {
+        let (old, new) = (table(self), table(other));
+        old.keys()
+            .chain(new.keys())
+            .filter(|key| HOT_KEYS.contains(&key.as_str()) == hot && old.get(*key) != new.get(*key))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+// The keys set in `config` with their values.
+fn table(config: &Config) -> toml::Table {
+    match toml::Value::try_from(config) {
+        Ok(toml::Value::Table(table)) => table,
+        _ => toml::Table::new(),
+    }
+}
@@ -0,0 +1,88 @@
+// The anonymous creator cookie, “my pastes” without accounts.
+// The first paste submitted through the form gives the browser a random id in the `creator_id` cookie, signed with
+// `PASTRY_COOKIE_SECRET`, and every paste it submits stores a hash of that id. “/mine” lists them, and on the creator's
+// pages and routes a cookie whose hash is the paste's counts as its key.
+// The database only holds the hashes and no answer but `Set-Cookie` carries the id. The cookie is `HttpOnly`,
+// so scripts of the pages can't read it either; clearing it orphans the pastes, whose keys keep working.
+
+use actix_web::HttpRequest;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+pub const COOKIE_NAME: &str = "creator_id";
+
+// Random characters of an id.
+const ID_LEN: usize = 24;
+
+// As long as browsers keep a cookie, they cap `Max-Age` at 400 days. Every submit sets it again.
+const MAX_AGE_SECS: i64 = 400 * 24 * 60 * 60;
+
+// Block size of SHA-256, for the HMAC.
+const BLOCK_LEN: usize = 64;
+
+// A new random id.
+pub fn new_id() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(ID_LEN)
+        .map(char::from)
+        .collect()
+}
+
+// The id in the cookie of `req`, when it carries one signed with `secret`; a forged or damaged cookie is no cookie.
+pub fn from_request(req: &HttpRequest, secret: &str) -> Option<String> {
+    crate::client::cookies(req.headers(), COOKIE_NAME).find_map(|value| verify(value, secret))
+}
+
+// The `Set-Cookie` header giving `id` to the browser, `Secure` when the request came over HTTPS.
+pub fn set_cookie(id: &str, secret: &str, secure: bool) -> String {
+    format!(
+        "{}={}.{}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax{}",
+        COOKIE_NAME,
+        id,
+        signature(id, secret),
+        MAX_AGE_SECS,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+// What a paste stores of the id of its creator.
+pub fn hashed(id: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("creator:{}", id).as_bytes()))
+}
+
+// Whether `id` is the creator of a paste storing `creator`.
+pub fn created(id: Option<&str>, creator: Option<&str>) -> bool {
+    match (id, creator) {
+        (Some(id), Some(creator)) => crate::secret_matches(creator, &hashed(id)),
+        _ => false,
+    }
+}
+
+// The id of a cookie value “id.signature”.
+fn verify(value: &str, secret: &str) -> Option<String> {
+    let (id, signature_given) = value.split_once('.')?;
+    if id.len() != ID_LEN || !id.bytes().all(|byte| byte.is_ascii_alphanumeric()) {
+        return None;
+    }
+    if !crate::secret_matches(&signature(id, secret), signature_given) {
+        return None;
+    }
+    Some(id.to_string())
+}
+
+// HMAC-SHA256 of `id` with `secret`, in hex.
+fn signature(id: &str, secret: &str) -> String {
+    let mut key = [0u8; BLOCK_LEN];
+    if secret.len() > BLOCK_LEN {
+        key[..32].copy_from_slice(&Sha256::digest(secret.as_bytes()));
+    } else {
+        key[..secret.len()].copy_from_slice(secret.as_bytes());
+    }
+
+    let pad = |byte: u8| key.iter().map(|k| k ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(id.as_bytes()).finalize();
+    let outer = Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize();
+    format!("{:x}", outer)
+}
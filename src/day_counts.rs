@@ -0,0 +1,37 @@
+// Counts per client and day (UTC) kept in memory, for the quotas of requests that must not write to the database,
+// like “/api/pastes/validate”. They start over at midnight UTC and on a restart, and each instance has its own.
+
+use std::collections::HashMap;
+
+// Most clients counted in a day. Past them the new ones share one count, so spoofed addresses can't grow the map
+// (they only use up that shared count faster).
+const MAX_CLIENTS: usize = 100_000;
+
+const OTHERS: &str = "";
+
+#[derive(Default)]
+pub struct DayCounts {
+    // The day the counts are for, as whole days since the epoch
+    day: i64,
+    counts: HashMap<String, i64>,
+}
+
+impl DayCounts {
+    // Adds `count` to what `client` used on the day of `now`, and returns the new total.
+    pub fn record(&mut self, client: &str, count: i64, now: i64) -> i64 {
+        let day = now.div_euclid(crate::SECONDS_PER_DAY);
+        if day != self.day {
+            self.day = day;
+            self.counts.clear();
+        }
+
+        let client = if self.counts.len() >= MAX_CLIENTS && !self.counts.contains_key(client) {
+            OTHERS
+        } else {
+            client
+        };
+        let used = self.counts.entry(client.to_string()).or_insert(0);
+        *used += count;
+        *used
+    }
+}
@@ -0,0 +1,89 @@
+// The check of the database file at startup, before the server takes requests, with `PASTRY_DB_CHECK`:
+// - `quick` (the default), SQLite's `PRAGMA quick_check`: every page is read and checked, not the indexes against
+//   their rows, a matter of seconds for most databases;
+// - `full`, `PRAGMA integrity_check`, which checks those too and takes about as long as reading the whole database;
+// - `off`.
+// What a failed check does is `PASTRY_DB_CHECK_FAILURE`: `warn` logs the problems and serves as usual, `read-only`
+// also refuses every write with a 503 so nothing new is stored on damaged pages, `refuse` stops there. Postgres and
+// the memory store have nothing to check. A query finding the database corrupt later on is seen by `store::timed`.
+
+use crate::store::PasteStore;
+use std::str::FromStr;
+use std::time::Instant;
+
+pub const READ_ONLY: &str = "The database failed its check at startup, nothing new can be stored until it is repaired";
+
+// Problems logged, the others are counted.
+const LOGGED_PROBLEMS: usize = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    Quick,
+    Full,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Mode, String> {
+        match value {
+            "off" => Ok(Mode::Off),
+            "quick" => Ok(Mode::Quick),
+            "full" => Ok(Mode::Full),
+            _ => Err(format!("Unknown database check \"{}\", expected quick, full or off", value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OnFailure {
+    Warn,
+    ReadOnly,
+    Refuse,
+}
+
+impl FromStr for OnFailure {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<OnFailure, String> {
+        match value {
+            "warn" => Ok(OnFailure::Warn),
+            "read-only" => Ok(OnFailure::ReadOnly),
+            "refuse" => Ok(OnFailure::Refuse),
+            _ => Err(format!("Unknown database check failure \"{}\", expected warn, read-only or refuse", value)),
+        }
+    }
+}
+
+// Runs the check of `mode`, logging what it found; whether the database passed. A check that can't run at all (the
+// file isn't a database anymore, say) fails it.
+pub fn run(store: &dyn PasteStore, mode: Mode) -> bool {
+    if mode == Mode::Off {
+        return true;
+    }
+    let started = Instant::now();
+    let problems = match store.check_database(mode == Mode::Full) {
+        Ok(problems) => problems,
+        Err(e) => {
+            eprintln!("Database check: could not check the database: {}", e);
+            return false;
+        }
+    };
+    if problems.is_empty() {
+        println!(
+            "Database check: {} check passed in {} ms",
+            if mode == Mode::Full { "full" } else { "quick" },
+            started.elapsed().as_millis()
+        );
+        return true;
+    }
+    eprintln!("Database check: the database is damaged, {} problem(s) found:", problems.len());
+    for problem in problems.iter().take(LOGGED_PROBLEMS) {
+        eprintln!("  {}", problem);
+    }
+    if problems.len() > LOGGED_PROBLEMS {
+        eprintln!("  … and {} more", problems.len() - LOGGED_PROBLEMS);
+    }
+    false
+}
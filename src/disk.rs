@@ -0,0 +1,171 @@
+// Free space on the volumes pastes are written to: the directory of the SQLite database and the blob directory.
+// Every `PASTRY_DISK_CHECK_SECS` the free space of each is read. Below `PASTRY_DISK_WARN_BYTES` on one of them a
+// warning is logged and the admin pages show a banner; below `PASTRY_DISK_MIN_BYTES` the instance also turns
+// read-only (unless `PASTRY_DISK_READ_ONLY=false`), refusing what adds data with a 507 rather than waiting for the
+// database to fail with `SQLITE_FULL`, which takes reads down with it. Reads, deletions and the admin routes go on.
+// The first check that finds enough space again lifts it. Both levels are logged as they change, not at every check.
+// “/metrics” has the free space of each volume and whether the instance is read-only.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+
+// What a request adding data is told while the instance is read-only.
+pub const READ_ONLY: &str = "The server is low on disk space, nothing new can be stored until some is freed";
+
+// One of the volumes the checks read, by a directory on it.
+pub struct Volume {
+    // “database” or “blobs”, as the metrics and the logs name it
+    pub name: &'static str,
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Level {
+    #[default]
+    Enough,
+    // Below the warning level
+    Low,
+    // Below the minimum
+    Full,
+}
+
+#[derive(Clone)]
+pub struct Reading {
+    pub name: &'static str,
+    pub path: PathBuf,
+    // `None` when it couldn't be read, a volume that can't be read doesn't change the level
+    pub available: Option<u64>,
+}
+
+// What the last check found.
+#[derive(Clone, Default)]
+pub struct Status {
+    pub readings: Vec<Reading>,
+    pub level: Level,
+    pub read_only: bool,
+}
+
+// The levels of a check, each 0 for none.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub warn_bytes: u64,
+    pub min_bytes: u64,
+    // Whether being below `min_bytes` makes the instance read-only
+    pub read_only: bool,
+}
+
+pub struct Monitor {
+    volumes: Vec<Volume>,
+    status: RwLock<Status>,
+    // How often the background task checks, 0 for never
+    pub interval: Duration,
+}
+
+impl Monitor {
+    pub fn new(volumes: Vec<Volume>, interval: Duration) -> Monitor {
+        Monitor {
+            volumes,
+            status: RwLock::new(Status::default()),
+            interval,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.read().unwrap().clone()
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.status.read().unwrap().read_only
+    }
+
+    // Reads the free space of every volume and sets the level and the read-only state, logging what changed.
+    pub fn check(&self, limits: Limits) {
+        let previous = self.status();
+        let readings: Vec<Reading> = self
+            .volumes
+            .iter()
+            .map(|volume| {
+                let available = match available_bytes(&volume.path) {
+                    Ok(available) => Some(available),
+                    Err(e) => {
+                        let known = previous.readings.iter().any(|reading| reading.name == volume.name && reading.available.is_some());
+                        if known || previous.readings.is_empty() {
+                            eprintln!("Disk: can't read the free space of {} ({}): {}", volume.name, volume.path.display(), e);
+                        }
+                        None
+                    }
+                };
+                Reading {
+                    name: volume.name,
+                    path: volume.path.clone(),
+                    available,
+                }
+            })
+            .collect();
+
+        let level_of = |available: u64| {
+            if available < limits.min_bytes {
+                Level::Full
+            } else if available < limits.warn_bytes {
+                Level::Low
+            } else {
+                Level::Enough
+            }
+        };
+        let lowest = readings
+            .iter()
+            .filter_map(|reading| Some((reading, reading.available?)))
+            .min_by_key(|(_, available)| *available);
+        let level = lowest.map_or(Level::Enough, |(_, available)| level_of(available));
+        let read_only = level == Level::Full && limits.read_only;
+
+        if level != previous.level || read_only != previous.read_only {
+            match lowest {
+                Some((reading, available)) if level != Level::Enough => eprintln!(
+                    "Disk: {} bytes free on the volume of {} ({}), {}",
+                    available,
+                    reading.name,
+                    reading.path.display(),
+                    if read_only {
+                        "below PASTRY_DISK_MIN_BYTES, refusing new data until space is freed"
+                    } else if level == Level::Full {
+                        "below PASTRY_DISK_MIN_BYTES"
+                    } else {
+                        "below PASTRY_DISK_WARN_BYTES"
+                    }
+                ),
+                _ => eprintln!("Disk: enough free space again{}", if previous.read_only { ", accepting new data" } else { "" }),
+            }
+        }
+
+        *self.status.write().unwrap() = Status {
+            readings,
+            level,
+            read_only,
+        };
+    }
+}
+
+// The bytes an unprivileged process can still write on the volume of `path`.
+#[cfg(unix)]
+pub fn available_bytes(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a C string and `stats` is only read after `statvfs` filled it in
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_bytes(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space is only read on Unix"))
+}
@@ -0,0 +1,474 @@
+// `pastry doctor`: checks the setup before it is relied on, and prints a report of each check passing, warning or
+// failing; the process exits with 1 when one fails. A few of the checks also run at every start of the server, before
+// the database is opened (`STARTUP`), and keep it from starting on a failure.
+// Each check is a function of what it checks, not of the environment, so that each can be run on its own.
+// Nothing is changed by a check: the database isn't migrated nor created, missing directories aren't created,
+// writes are probed with a file deleted right after.
+
+use crate::store::{self, Location};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, message: impl Into<String>) -> Check {
+        Check { name, status, message: message.into() }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  {:<16} {}", self.status, self.name, self.message)
+    }
+}
+
+// What the checks look at, from the configuration file and the environment as the server reads them.
+pub struct Setup<'a> {
+    pub location: &'a Location,
+    pub blob_dir: &'a Path,
+    pub backup_dir: &'a Path,
+    pub assets_dir: Option<&'a Path>,
+    pub admin_token: bool,
+    pub cookie_secret: bool,
+    pub ip_salt: bool,
+    pub captcha_site_key: bool,
+    pub captcha_secret: bool,
+    pub trusted_proxies: &'a str,
+}
+
+// Which checks to run.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scope {
+    // Every check, for `pastry doctor`
+    All,
+    // The ones worth delaying a start for: what would fail later, once the server is serving
+    Startup,
+}
+
+pub fn run(setup: &Setup, scope: Scope) -> Vec<Check> {
+    let mut checks = Vec::new();
+    if let Location::Sqlite(path) = setup.location {
+        checks.push(database_writable(path));
+    }
+    checks.push(schema(setup.location));
+    if let Location::Sqlite(_) = setup.location {
+        checks.push(fts5(store::sqlite::has_fts5()));
+    }
+    if !matches!(setup.location, Location::Memory { .. }) {
+        checks.push(writable_dir("blob directory", setup.blob_dir, Status::Fail));
+    }
+    checks.push(writable_dir("backup directory", setup.backup_dir, Status::Warn));
+    checks.push(assets_dir(setup.assets_dir));
+    if scope == Scope::All {
+        checks.push(trusted_proxies(setup.trusted_proxies));
+        checks.push(captcha(setup.captcha_site_key, setup.captcha_secret));
+        checks.push(secret("cookie secret", setup.cookie_secret, "PASTRY_COOKIE_SECRET", "creator cookies stop working on every restart"));
+        checks.push(secret("ip salt", setup.ip_salt, "PASTRY_IP_SALT", "quotas reset on every restart and hashes of addresses can't be banned"));
+        checks.push(admin_token(setup.admin_token));
+    }
+    checks
+}
+
+// Whether one of `checks` failed.
+pub fn failed(checks: &[Check]) -> bool {
+    checks.iter().any(|check| check.status == Status::Fail)
+}
+
+// The report `pastry doctor` prints: a line per check and a summary.
+pub fn report(checks: &[Check]) -> String {
+    let mut report: String = checks.iter().map(|check| format!("{}\n", check)).collect();
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    report.push_str(&format!(
+        "\n{} passed, {} warnings, {} failed\n",
+        count(Status::Pass),
+        count(Status::Warn),
+        count(Status::Fail)
+    ));
+    report
+}
+
+// The SQLite file can be written, and so can its directory, where SQLite keeps its journal.
+pub fn database_writable(path: &Path) -> Check {
+    const NAME: &str = "database file";
+    if path.exists() {
+        if let Err(e) = OpenOptions::new().write(true).open(path) {
+            return Check::new(NAME, Status::Fail, format!("{} can't be written: {}", path.display(), e));
+        }
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Check::new(NAME, Status::Fail, format!("{} is not a directory", dir.display()));
+    }
+    if let Err(e) = probe_write(dir) {
+        return Check::new(NAME, Status::Fail, format!("Its directory {} can't be written: {}", dir.display(), e));
+    }
+    if path.exists() {
+        Check::new(NAME, Status::Pass, format!("{} is writable", path.display()))
+    } else {
+        Check::new(NAME, Status::Pass, format!("{} will be created", path.display()))
+    }
+}
+
+// The database's schema is one this release can migrate: an older one is brought up to date at the next start,
+// one newer than this release knows of has tables and columns this release would get wrong.
+pub fn schema(location: &Location) -> Check {
+    const NAME: &str = "schema version";
+    let (current, latest) = match store::schema_versions(location) {
+        Ok(versions) => versions,
+        Err(e) => return Check::new(NAME, Status::Fail, format!("The database {} can't be read: {}", location, e)),
+    };
+    match current {
+        _ if matches!(location, Location::Memory { .. }) => Check::new(NAME, Status::Pass, "In memory, nothing to migrate"),
+        None => Check::new(NAME, Status::Pass, format!("No database yet, it will be created at version {}", latest)),
+        Some(current) if current == latest => Check::new(NAME, Status::Pass, format!("Version {}, up to date", current)),
+        Some(current) if current < latest => Check::new(
+            NAME,
+            Status::Pass,
+            format!("Version {}, it will be migrated to {} at the next start", current, latest),
+        ),
+        Some(current) => Check::new(
+            NAME,
+            Status::Fail,
+            format!(
+                "Version {} is newer than this release, which knows of {}; it was migrated by a later release",
+                current, latest
+            ),
+        ),
+    }
+}
+
+// The SQLite library the binary uses has FTS5, for the search index.
+pub fn fts5(available: bool) -> Check {
+    if available {
+        Check::new("fts5", Status::Pass, "SQLite has FTS5")
+    } else {
+        Check::new("fts5", Status::Fail, "SQLite was built without FTS5, which the search index needs")
+    }
+}
+
+// `dir` is a directory that can be written, or can be created. Other users being able to write it is a warning.
+// A problem is `problem`, a failure for a directory the server needs and a warning for one it can do without.
+pub fn writable_dir(name: &'static str, dir: &Path, problem: Status) -> Check {
+    if !dir.exists() {
+        let ancestor = dir.ancestors().skip(1).find(|ancestor| ancestor.as_os_str().is_empty() || ancestor.exists());
+        let ancestor = match ancestor {
+            Some(ancestor) if ancestor.as_os_str().is_empty() => Path::new("."),
+            Some(ancestor) if !ancestor.is_dir() => {
+                return Check::new(
+                    name,
+                    problem,
+                    format!("{} can't be created, {} is not a directory", dir.display(), ancestor.display()),
+                )
+            }
+            Some(ancestor) => ancestor,
+            None => return Check::new(name, problem, format!("{} can't be created", dir.display())),
+        };
+        return match probe_write(ancestor) {
+            Ok(()) => Check::new(name, Status::Pass, format!("{} doesn't exist yet, it will be created", dir.display())),
+            Err(e) => Check::new(
+                name,
+                problem,
+                format!("{} doesn't exist and can't be created in {}: {}", dir.display(), ancestor.display(), e),
+            ),
+        };
+    }
+    if !dir.is_dir() {
+        return Check::new(name, problem, format!("{} is not a directory", dir.display()));
+    }
+    if let Err(e) = probe_write(dir) {
+        return Check::new(name, problem, format!("{} can't be written: {}", dir.display(), e));
+    }
+    if world_writable(dir) {
+        return Check::new(name, Status::Warn, format!("{} can be written by every user", dir.display()));
+    }
+    Check::new(name, Status::Pass, format!("{} is writable", dir.display()))
+}
+
+// The assets directory, when there is one, is a directory that can be read.
+pub fn assets_dir(dir: Option<&Path>) -> Check {
+    const NAME: &str = "assets directory";
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return Check::new(NAME, Status::Pass, "None set, the built-in assets are served"),
+    };
+    if !dir.is_dir() {
+        return Check::new(NAME, Status::Fail, format!("{} is not a directory", dir.display()));
+    }
+    match fs::read_dir(dir) {
+        Ok(_) => Check::new(NAME, Status::Pass, format!("{} is readable", dir.display())),
+        Err(e) => Check::new(NAME, Status::Fail, format!("{} can't be read: {}", dir.display(), e)),
+    }
+}
+
+// `PASTRY_TRUSTED_PROXIES` parses.
+pub fn trusted_proxies(proxies: &str) -> Check {
+    match crate::client::parse_trusted_proxies(proxies) {
+        Ok(proxies) if proxies.is_empty() => Check::new("trusted proxies", Status::Pass, "None, clients connect directly"),
+        Ok(proxies) => Check::new("trusted proxies", Status::Pass, format!("{} trusted", proxies.len())),
+        Err(e) => Check::new("trusted proxies", Status::Fail, e),
+    }
+}
+
+// A captcha has both of its keys, or neither.
+pub fn captcha(site_key: bool, secret: bool) -> Check {
+    match (site_key, secret) {
+        (true, true) => Check::new("captcha", Status::Pass, "Site key and secret set"),
+        (false, false) => Check::new("captcha", Status::Pass, "Off"),
+        _ => Check::new("captcha", Status::Fail, "A captcha needs both PASTRY_CAPTCHA_SITE_KEY and PASTRY_CAPTCHA_SECRET"),
+    }
+}
+
+// A secret that gets a random value for the run when it isn't set, which `without` describes the cost of.
+pub fn secret(name: &'static str, set: bool, variable: &str, without: &str) -> Check {
+    if set {
+        Check::new(name, Status::Pass, "Set")
+    } else {
+        Check::new(name, Status::Warn, format!("No {}, {}", variable, without))
+    }
+}
+
+pub fn admin_token(set: bool) -> Check {
+    if set {
+        Check::new("admin token", Status::Pass, "Set")
+    } else {
+        Check::new("admin token", Status::Pass, "None set, the /admin routes are off")
+    }
+}
+
+// Creates and deletes a file in `dir`.
+fn probe_write(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".pastry-doctor-{}", std::process::id()));
+    OpenOptions::new().write(true).create_new(true).open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+#[cfg(unix)]
+fn world_writable(dir: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(dir).is_ok_and(|metadata| metadata.permissions().mode() & 0o002 != 0)
+}
+
+#[cfg(not(unix))]
+fn world_writable(_dir: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // An empty directory of its own for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pastry-doctor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // A SQLite file in `dir` at the schema `version`.
+    fn database(dir: &Path, version: usize) -> PathBuf {
+        let path = dir.join("pastry.db");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(&format!("PRAGMA user_version = {}", version)).unwrap();
+        path
+    }
+
+    fn setup<'a>(location: &'a Location, dir: &'a Path) -> Setup<'a> {
+        Setup {
+            location,
+            blob_dir: dir,
+            backup_dir: dir,
+            assets_dir: None,
+            admin_token: false,
+            cookie_secret: false,
+            ip_salt: false,
+            captcha_site_key: false,
+            captcha_secret: false,
+            trusted_proxies: "",
+        }
+    }
+
+    #[test]
+    fn the_database_file_and_its_directory_are_written() {
+        let dir = temp_dir("database");
+        let path = dir.join("pastry.db");
+        let check = database_writable(&path);
+        assert_eq!(check.status, Status::Pass, "{}", check);
+        assert!(check.message.ends_with("will be created"), "{}", check);
+        // Nothing is left behind by the probe
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        database(&dir, 1);
+        assert_eq!(database_writable(&path).status, Status::Pass);
+        // A directory where the file should be, and a file where its directory should be
+        assert_eq!(database_writable(&dir).status, Status::Fail);
+        assert_eq!(database_writable(&path.join("pastry.db")).status, Status::Fail);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_schema_is_one_this_release_can_migrate() {
+        let dir = temp_dir("schema");
+        let path = dir.join("pastry.db");
+        let location = Location::Sqlite(path.clone());
+        let latest = store::sqlite::SCHEMA_VERSION;
+        let check = schema(&location);
+        assert_eq!(check.status, Status::Pass, "{}", check);
+        assert!(check.message.starts_with("No database yet"), "{}", check);
+        // Reading the version doesn't create the database
+        assert!(!path.exists());
+
+        database(&dir, 1);
+        let check = schema(&location);
+        assert_eq!(check.status, Status::Pass, "{}", check);
+        assert!(check.message.contains("it will be migrated"), "{}", check);
+        database(&dir, latest);
+        assert_eq!(schema(&location).message, format!("Version {}, up to date", latest));
+        database(&dir, latest + 1);
+        let check = schema(&location);
+        assert_eq!(check.status, Status::Fail, "{}", check);
+        assert!(check.message.contains("newer than this release"), "{}", check);
+
+        fs::write(&path, "not a database, nor anything SQLite can read").unwrap();
+        let check = schema(&location);
+        assert_eq!(check.status, Status::Fail, "{}", check);
+        assert!(check.message.contains("can't be read"), "{}", check);
+        let memory = Location::Memory { max_pastes: 1, max_bytes: 1 };
+        assert_eq!(schema(&memory).status, Status::Pass);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fts5_is_needed() {
+        assert_eq!(fts5(true).status, Status::Pass);
+        assert_eq!(fts5(false).status, Status::Fail);
+        // The SQLite the tests are built with, like the binary, has it
+        assert!(store::sqlite::has_fts5());
+    }
+
+    #[test]
+    fn a_directory_is_written_or_can_be_created() {
+        let dir = temp_dir("directories");
+        let check = writable_dir("blob directory", &dir, Status::Fail);
+        assert_eq!(check.status, Status::Pass, "{}", check);
+        let check = writable_dir("blob directory", &dir.join("blobs/nested"), Status::Fail);
+        assert_eq!(check.status, Status::Pass, "{}", check);
+        assert!(check.message.ends_with("it will be created"), "{}", check);
+        assert!(!dir.join("blobs").exists());
+
+        // A file where the directory, or one of its parents, should be is the problem the check is given
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        assert_eq!(writable_dir("blob directory", &file, Status::Fail).status, Status::Fail);
+        assert_eq!(writable_dir("backup directory", &file, Status::Warn).status, Status::Warn);
+        let check = writable_dir("blob directory", &file.join("blobs"), Status::Fail);
+        assert_eq!(check.status, Status::Fail, "{}", check);
+        assert!(check.message.contains("is not a directory"), "{}", check);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_directory_every_user_writes_is_a_warning() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("world-writable");
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        let check = writable_dir("blob directory", &dir, Status::Fail);
+        assert_eq!(check.status, Status::Warn, "{}", check);
+        assert!(check.message.contains("every user"), "{}", check);
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        assert_eq!(writable_dir("blob directory", &dir, Status::Fail).status, Status::Pass);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_assets_directory_is_optional_but_a_directory() {
+        let dir = temp_dir("assets");
+        assert_eq!(assets_dir(None).status, Status::Pass);
+        assert_eq!(assets_dir(Some(&dir)).status, Status::Pass);
+        assert_eq!(assets_dir(Some(&dir.join("missing"))).status, Status::Fail);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_settings_are_checked() {
+        assert_eq!(trusted_proxies("").message, "None, clients connect directly");
+        assert_eq!(trusted_proxies("10.0.0.1, ::1").message, "2 trusted");
+        assert_eq!(trusted_proxies("10.0.0.1, proxy.internal").status, Status::Fail);
+
+        assert_eq!(captcha(true, true).status, Status::Pass);
+        assert_eq!(captcha(false, false).status, Status::Pass);
+        assert_eq!(captcha(true, false).status, Status::Fail);
+        assert_eq!(captcha(false, true).status, Status::Fail);
+
+        assert_eq!(secret("ip salt", true, "PASTRY_IP_SALT", "quotas reset").status, Status::Pass);
+        let check = secret("ip salt", false, "PASTRY_IP_SALT", "quotas reset");
+        assert_eq!(check.status, Status::Warn);
+        assert_eq!(check.message, "No PASTRY_IP_SALT, quotas reset");
+        assert_eq!(admin_token(false).status, Status::Pass);
+    }
+
+    #[test]
+    fn the_scope_picks_the_checks() {
+        let dir = temp_dir("scope");
+        let location = Location::Sqlite(dir.join("pastry.db"));
+        let names = |checks: &[Check]| checks.iter().map(|check| check.name).collect::<Vec<_>>();
+        let startup = run(&setup(&location, &dir), Scope::Startup);
+        assert_eq!(
+            names(&startup),
+            ["database file", "schema version", "fts5", "blob directory", "backup directory", "assets directory"]
+        );
+        let all = run(&setup(&location, &dir), Scope::All);
+        assert_eq!(&names(&all)[..startup.len()], &names(&startup)[..]);
+        let config = ["trusted proxies", "captcha", "cookie secret", "ip salt", "admin token"];
+        assert_eq!(&names(&all)[startup.len()..], config);
+        assert!(!failed(&all));
+
+        // In memory there is no file nor blob to check
+        let memory = Location::Memory { max_pastes: 1, max_bytes: 1 };
+        let memory = run(&setup(&memory, &dir), Scope::Startup);
+        assert_eq!(names(&memory), ["schema version", "backup directory", "assets directory"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_report_counts_each_status() {
+        let checks = [fts5(true), captcha(true, false), secret("ip salt", false, "PASTRY_IP_SALT", "quotas reset")];
+        assert!(failed(&checks));
+        assert!(!failed(&checks[..1]));
+        let report = report(&checks);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", report);
+        assert!(lines[0].starts_with("pass  fts5 "), "{}", report);
+        assert!(lines[1].starts_with("FAIL  captcha "), "{}", report);
+        assert!(lines[2].starts_with("warn  ip salt "), "{}", report);
+        assert_eq!(lines[4], "1 passed, 1 warnings, 1 failed");
+    }
+}
@@ -0,0 +1,310 @@
+// The error type shared by all handlers.
+// A handler returns `Result<HttpResponse, AppError>` and uses `?` on database calls;
+// on HTML routes the error is rendered as the styled `error.html` page,
+// and the `/api` scope runs every error response through `api_error_response`
+// so API clients always get the JSON error envelope instead:
+//
+//     {"error": {"code": "not_found", "message": "Paste not found"}}
+
+use actix_web::dev::{Body, ServiceResponse};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::error::InternalError;
+use actix_web::http::{HeaderValue, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use crate::i18n::Texts;
+use pastry_crust::api::{ApiErrorBody, ApiErrorEnvelope};
+use std::fmt;
+
+// Every error code an API client can get back, with the HTTP status that goes with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    Gone,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RateLimited,
+    Internal,
+    Unavailable,
+    // Nothing new can be stored for lack of disk space, see `disk.rs`
+    InsufficientStorage,
+}
+
+// Every code, in the order of their statuses, for the OpenAPI document (see `openapi.rs`).
+pub const CODES: [ErrorCode; 12] = [
+    ErrorCode::BadRequest,
+    ErrorCode::Unauthorized,
+    ErrorCode::Forbidden,
+    ErrorCode::NotFound,
+    ErrorCode::Conflict,
+    ErrorCode::Gone,
+    ErrorCode::PayloadTooLarge,
+    ErrorCode::UnsupportedMediaType,
+    ErrorCode::RateLimited,
+    ErrorCode::Internal,
+    ErrorCode::Unavailable,
+    ErrorCode::InsufficientStorage,
+];
+
+impl ErrorCode {
+    // The machine readable name used in the `code` field of the envelope.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::Gone => "gone",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::UnsupportedMediaType => "unsupported_media_type",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Internal => "internal",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::InsufficientStorage => "insufficient_storage",
+        }
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::Gone => StatusCode::GONE,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::InsufficientStorage => StatusCode::INSUFFICIENT_STORAGE,
+        }
+    }
+
+    // Picks the code for an error response that didn't come from an `AppError`,
+    // like the 400 of a query string that doesn't parse or the 404 of an unknown route.
+    pub fn from_status(status: StatusCode) -> ErrorCode {
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => ErrorCode::NotFound,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::GONE => ErrorCode::Gone,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorCode::UnsupportedMediaType,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+            StatusCode::SERVICE_UNAVAILABLE => ErrorCode::Unavailable,
+            StatusCode::INSUFFICIENT_STORAGE => ErrorCode::InsufficientStorage,
+            status if status.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+// How long a client is told to wait (`Retry-After`) when the database stayed locked.
+const BUSY_RETRY_AFTER_SECS: u64 = 1;
+
+#[derive(Debug)]
+pub struct AppError {
+    pub code: ErrorCode,
+    pub message: String,
+    // Seconds sent as `Retry-After` with the error
+    pub retry_after: Option<u64>,
+    // The scope the API token of the request lacks, named in the JSON envelope as `scope`
+    pub scope: Option<&'static str>,
+}
+
+impl AppError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> AppError {
+        AppError {
+            code,
+            message: message.into(),
+            retry_after: None,
+            scope: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> AppError {
+        AppError::new(ErrorCode::BadRequest, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> AppError {
+        AppError::new(ErrorCode::Unauthorized, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> AppError {
+        AppError::new(ErrorCode::Internal, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> AppError {
+        AppError::new(ErrorCode::Forbidden, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> AppError {
+        AppError::new(ErrorCode::NotFound, message)
+    }
+
+    // The 403 of an API token without `scope`.
+    pub fn missing_scope(scope: &'static str) -> AppError {
+        AppError {
+            scope: Some(scope),
+            ..AppError::forbidden(format!("This token doesn't have the \"{}\" scope", scope))
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+// The HTML rendering, used as is by the HTML routes.
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    // In English, `localized` renders it again in the language of the request.
+    fn error_response(&self) -> HttpResponse {
+        self.page(Texts::english())
+    }
+}
+
+impl AppError {
+    // The error page. The message stays as it was written, in English: the API sends the same one.
+    fn page(&self, texts: Texts) -> HttpResponse {
+        let status = self.status_code();
+        let reason = texts.text(&format!("error.reason.{}", self.code.as_str())).to_string();
+        let html_page = crate::assets::versioned(&texts.localize(include_str!("error.html")))
+            .replace("{{status}}", &status.as_u16().to_string())
+            .replace("{{reason}}", &crate::escape_html(&reason))
+            .replace("{{message}}", &crate::escape_html(&self.message));
+
+        with_retry_after(
+            HttpResponse::build(status)
+                .content_type("text/html")
+                .body(html_page),
+            self.retry_after,
+        )
+    }
+}
+
+// Run on every response of the app, the error pages of `AppError` given again in the language `texts` of the
+// request (see `i18n.rs`), whether the error came as the response of a handler or from a middleware. The JSON errors
+// of the API (`ApiError`) are left alone, and so is anything else.
+pub fn localized(
+    texts: Texts,
+    result: Result<ServiceResponse<Body>, actix_web::Error>,
+) -> Result<ServiceResponse<Body>, actix_web::Error> {
+    if texts.code() == Texts::english().code() {
+        return result;
+    }
+    match result {
+        Ok(res) => {
+            let page = match res.response().error().and_then(|error| error.as_error::<AppError>()) {
+                Some(app_error) => app_error.page(texts),
+                None => return Ok(res),
+            };
+            Ok(res.into_response(page))
+        }
+        Err(error) => match error.as_error::<AppError>() {
+            Some(app_error) => Err(InternalError::from_response(app_error.message.clone(), app_error.page(texts)).into()),
+            None => Err(error),
+        },
+    }
+}
+
+// An `AppError` of an API route raised outside of the `/api` scope, by a middleware wrapping the whole app,
+// which `api_error_response` never sees: it renders as the JSON envelope itself.
+#[derive(Debug)]
+pub struct ApiError(pub AppError);
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.0.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        with_retry_after(
+            json_error(self.status_code(), self.0.code, &self.0.message, self.0.scope),
+            self.0.retry_after,
+        )
+    }
+}
+
+// Database errors are logged with their details, the client only learns that something went wrong.
+// A database that stayed locked through the retries of the store is a 503 the client can retry, not a 500.
+// A corrupt one is a 500 like the others, logged apart so it stands out (see `store::timed`).
+impl From<crate::store::StoreError> for AppError {
+    fn from(e: crate::store::StoreError) -> AppError {
+        if e.is_corrupt() {
+            eprintln!("Database corrupt: {}", e);
+            return AppError::internal("Internal server error");
+        }
+        eprintln!("Database error: {}", e);
+        if e.is_busy() {
+            return AppError {
+                retry_after: Some(BUSY_RETRY_AFTER_SECS),
+                ..AppError::new(ErrorCode::Unavailable, "The database is busy, try again in a moment")
+            };
+        }
+        if e.is_full() {
+            return AppError::new(ErrorCode::InsufficientStorage, crate::disk::READ_ONLY);
+        }
+        AppError::new(ErrorCode::Internal, "Internal server error")
+    }
+}
+
+fn with_retry_after(mut response: HttpResponse, retry_after: Option<u64>) -> HttpResponse {
+    if let Some(seconds) = retry_after {
+        response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(seconds));
+    }
+    response
+}
+
+// Builds the JSON error envelope response.
+fn json_error(status: StatusCode, code: ErrorCode, message: &str, scope: Option<&'static str>) -> HttpResponse {
+    HttpResponse::build(status).json(ApiErrorEnvelope {
+        error: ApiErrorBody {
+            code: code.as_str().to_string(),
+            message: message.to_string(),
+            scope: scope.map(str::to_string),
+        },
+    })
+}
+
+// Run on every response of the `/api` scope: success responses go through untouched,
+// error responses are replaced by the JSON envelope, whatever produced them.
+pub fn api_error_response(res: ServiceResponse<Body>) -> ServiceResponse<Body> {
+    let status = res.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return res;
+    }
+
+    let (code, message, retry_after, scope) = match res.response().error() {
+        Some(error) => match error.as_error::<AppError>() {
+            Some(app_error) => (app_error.code, app_error.message.clone(), app_error.retry_after, app_error.scope),
+            None => (ErrorCode::from_status(status), error.to_string(), None, None),
+        },
+        None => (
+            ErrorCode::from_status(status),
+            status.canonical_reason().unwrap_or("Error").to_string(),
+            None,
+            None,
+        ),
+    };
+
+    let response = with_retry_after(json_error(status, code, &message, scope), retry_after);
+    res.into_response(response)
+}
@@ -0,0 +1,190 @@
+// `pastry_crust export-static <dir>`: the public pastes as a static site, for an instance going away whose pastes should
+// stay readable as plain files, from object storage say. `<dir>` gets
+// - `index.html`, the pastes in token order, `INDEX_PAGE` to a page (`index-2.html`…);
+// - `paste/{token}.html`, the page of each paste rendered like “/paste/{token}” (see `PastePage::Snapshot`), and its
+//   raw content as `paste/{token}.txt` (`.bin` for a binary paste);
+// - `static/`, the assets the pages link, relatively, so the directory can be served from anywhere.
+// Only public, published and unexpired pastes are exported, short links aren't. What is written depends on the
+// database alone, so exporting again only writes the files whose hash changed, and an interrupted export goes on
+// where it stopped. Pastes are read `CHUNK` tokens at a time and one by one, whatever the size of the database.
+// Files of pastes gone since an earlier export are left, export to an empty directory to drop them.
+
+use crate::store::Content;
+use crate::{assets, escape_html, format_size, paste_html, paste_label, paste_size, telemetry, timestamp};
+use crate::{AppState, CachedPaste, PastePage, Texts};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CHUNK: i64 = 500;
+const INDEX_PAGE: usize = 1000;
+
+// What an export did.
+pub struct Export {
+    pub pastes: usize,
+    pub written: usize,
+    pub unchanged: usize,
+}
+
+// The files of an export, written only when their content changed.
+struct Output {
+    dir: PathBuf,
+    written: usize,
+    unchanged: usize,
+}
+
+impl Output {
+    // Writes `bytes` to `path` under the directory, through a temporary file so an interrupted export never leaves
+    // half a file behind.
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<(), String> {
+        let target = self.dir.join(path);
+        let unchanged = fs::read(&target)
+            .map(|existing| Sha256::digest(&existing) == Sha256::digest(bytes))
+            .unwrap_or(false);
+        if unchanged {
+            self.unchanged += 1;
+            return Ok(());
+        }
+        let partial = self.dir.join(format!("{}.partial", path));
+        fs::write(&partial, bytes)
+            .and_then(|()| fs::rename(&partial, &target))
+            .map_err(|e| format!("Can't write {}: {}", target.display(), e))?;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+// Exports the public pastes of `data` to `dir`, created if needed; `assets_dir` is `PASTRY_ASSETS_DIR`, whose files
+// are copied in place of the embedded ones like the server serves them.
+pub fn export(data: &AppState, dir: &Path, assets_dir: Option<&Path>) -> Result<Export, String> {
+    for sub in ["paste", "static"] {
+        fs::create_dir_all(dir.join(sub)).map_err(|e| format!("Can't create {}: {}", dir.join(sub).display(), e))?;
+    }
+    let mut output = Output {
+        dir: dir.to_path_buf(),
+        written: 0,
+        unchanged: 0,
+    };
+    for asset in assets::ASSETS {
+        output.write(&format!("static/{}", asset.path), &assets::served(asset, assets_dir))?;
+    }
+
+    let texts = Texts::english();
+    let mut pastes = 0;
+    let mut index = Vec::new();
+    let mut page = 1;
+    let mut after = String::new();
+    loop {
+        let tokens = data.store.paste_tokens(&after, CHUNK).map_err(|e| e.to_string())?;
+        for token in &tokens {
+            let item = match export_paste(data, token, &mut output)? {
+                Some(item) => item,
+                None => continue,
+            };
+            pastes += 1;
+            // A page is written once the first item of the next one shows it isn't the last
+            if index.len() == INDEX_PAGE {
+                output.write(&index_file(page), index_page(&index, page, true, texts).as_bytes())?;
+                index.clear();
+                page += 1;
+            }
+            index.push(item);
+        }
+        match tokens.last() {
+            Some(last) => after = last.clone(),
+            None => break,
+        }
+    }
+    output.write(&index_file(page), index_page(&index, page, false, texts).as_bytes())?;
+
+    Ok(Export {
+        pastes,
+        written: output.written,
+        unchanged: output.unchanged,
+    })
+}
+
+// Writes the page and raw file of the paste `token` when it is exported, and returns its item of the index.
+fn export_paste(data: &AppState, token: &str, output: &mut Output) -> Result<Option<String>, String> {
+    let failed = |e: String| format!("Paste {}: {}", token, e);
+    let (paste, content) = match data.store.open_content(token).map_err(|e| failed(e.to_string()))? {
+        Some((paste, content)) if paste.public && !paste.pending && !paste.redirect => (paste, content),
+        // Expired, deleted since its chunk was read, not public or a short link
+        _ => return Ok(None),
+    };
+    let tags = data.store.tags(token).map_err(|e| failed(e.to_string()))?;
+    let size = paste_size(&paste, &content).map_err(|e| failed(e.to_string()))?;
+
+    let raw = match &content {
+        Content::Binary(_) => format!("{}.bin", paste.token),
+        _ => format!("{}.txt", paste.token),
+    };
+    match &content {
+        Content::Inline(text) => output.write(&format!("paste/{}", raw), text.as_bytes())?,
+        Content::Binary(bytes) => output.write(&format!("paste/{}", raw), bytes)?,
+        Content::File(path) => {
+            let bytes = fs::read(path).map_err(|e| failed(format!("can't read {}: {}", path.display(), e)))?;
+            output.write(&format!("paste/{}", raw), &bytes)?;
+        }
+    }
+
+    let texts = Texts::english();
+    let mut meta = vec![escape_html(&format_size(&size, texts))];
+    if paste.created_at > 0 {
+        meta.push(timestamp::html_absolute(paste.created_at));
+    }
+    // No view count, which would rewrite the index on every export
+    let item = format!(
+        "<li><a href=\"paste/{token}.html\">{label}</a> &middot; {meta}</li>",
+        token = escape_html(&paste.token),
+        label = paste_label(&paste.token, &paste.title),
+        meta = meta.join(" &middot; "),
+    );
+
+    let token = paste.token.clone();
+    let page = paste_html(data, CachedPaste { paste, content, tags }, PastePage::Snapshot { raw: &raw })
+        .map_err(|e| failed(e.to_string()))?;
+    output.write(&format!("paste/{}.html", token), relative(&page, "../").as_bytes())?;
+    Ok(Some(item))
+}
+
+fn index_file(page: usize) -> String {
+    if page == 1 {
+        "index.html".to_string()
+    } else {
+        format!("index-{}.html", page)
+    }
+}
+
+// The page `page` of the index, with `items`, and a link to the next one when `more` follow.
+fn index_page(items: &[String], page: usize, more: bool, texts: Texts) -> String {
+    let mut list_items = if items.is_empty() {
+        format!("<li>{}</li>", escape_html(texts.text("list.snapshot-none")))
+    } else {
+        items.concat()
+    };
+    let mut pages = Vec::new();
+    if page > 1 {
+        pages.push(format!("<a href=\"{}\">{}</a>", index_file(page - 1), escape_html(texts.text("search.previous"))));
+    }
+    if more {
+        pages.push(format!("<a href=\"{}\">{}</a>", index_file(page + 1), escape_html(texts.text("search.next"))));
+    }
+    if !pages.is_empty() {
+        list_items.push_str(&format!("<li>{}</li>", pages.join(" &middot; ")));
+    }
+
+    let _render = telemetry::template("list_pastes.html");
+    let html_page = texts
+        .localize(include_str!("list_pastes.html"))
+        .replace("{{list_title}}", &escape_html(texts.text("list.snapshot")))
+        .replace("{{language}}", "")
+        .replace("{{list_items}}", &list_items);
+    relative(&html_page, "")
+}
+
+// A page of the server made one of the export at `root` of it: the assets and the index page linked relatively.
+fn relative(page: &str, root: &str) -> String {
+    page.replace("\"/static/", &format!("\"{}static/", root))
+        .replace("href=\"/\"", &format!("href=\"{}index.html\"", root))
+}
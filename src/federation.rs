@@ -0,0 +1,256 @@
+// Stats of several instances in one place, for an operator running more than one. Both sides are off by default.
+// An instance with `PASTRY_STATS_PUSH_URL` and `PASTRY_STATS_PUSH_INSTANCE` sends a `Report` of itself there every
+// `PASTRY_STATS_PUSH_INTERVAL_SECS`, the first one at startup, with `PASTRY_STATS_PUSH_TOKEN` as its bearer token.
+// An instance with `PASTRY_FEDERATION_TOKEN` takes them on `POST /api/federation/stats`, keeps the last one of each
+// instance and shows them with their totals on “/admin/federation”.
+// A report is made of counts and names only, never of a paste, a token or an address: `Report` has no field that
+// could hold one, and the receiver refuses any other field. A receiver that is down or refuses the report is logged
+// and asked again at the next interval, nothing is queued meanwhile.
+
+use crate::error::{self, AppError};
+use crate::store::{self, ArchiveStats, InstanceStats};
+use crate::{admin_template, bearer, body, escape_html, format_bytes, require_admin, secret_matches};
+use crate::{openapi, reserved, store_write, telemetry, timestamp, AppState, DbQuery};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::time::Duration;
+
+// How often an instance reports without `PASTRY_STATS_PUSH_INTERVAL_SECS`.
+pub const PUSH_INTERVAL_SECS: u64 = 60 * 60;
+
+// Shortest interval, so that a typo can't have the receiver asked every second.
+const MIN_PUSH_INTERVAL_SECS: u64 = 60;
+
+// How long the receiver may take to answer, connecting included.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Largest report taken, a few numbers and names.
+pub const MAX_REPORT_BYTES: usize = 16 * 1024;
+
+// Longest name of an instance, and of a version or a backend.
+const MAX_NAME_CHARS: usize = 64;
+
+// What an instance tells of itself.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Report {
+    // `PASTRY_STATS_PUSH_INSTANCE`, how the receiver tells instances apart
+    pub instance: String,
+    pub version: String,
+    // “sqlite”, “postgres” or “memory”
+    pub backend: String,
+    // Archived ones included
+    pub pastes: i64,
+    pub archived_pastes: i64,
+    // Of the content, as created
+    pub content_bytes: i64,
+    // Taken by the content, compressed in the archive
+    pub stored_bytes: i64,
+}
+
+impl Report {
+    pub fn new(instance: &str, backend: &str, stats: &ArchiveStats) -> Report {
+        Report {
+            instance: instance.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            backend: backend.to_string(),
+            pastes: stats.hot_pastes + stats.archived_pastes,
+            archived_pastes: stats.archived_pastes,
+            content_bytes: stats.hot_bytes + stats.archived_bytes,
+            stored_bytes: stats.hot_bytes + stats.archived_stored_bytes,
+        }
+    }
+
+    // The report as the receiver keeps it, after checking it. Returns a message suitable for showing to the sender
+    // when it is not acceptable.
+    pub fn into_stats(self, received_at: i64) -> Result<InstanceStats, String> {
+        check_instance(&self.instance)?;
+        for (field, value) in [("version", &self.version), ("backend", &self.backend)] {
+            if value.chars().count() > MAX_NAME_CHARS || value.chars().any(char::is_control) {
+                return Err(format!("The {} is at most {} characters, without control characters", field, MAX_NAME_CHARS));
+            }
+        }
+        for (field, value) in [
+            ("pastes", self.pastes),
+            ("archived_pastes", self.archived_pastes),
+            ("content_bytes", self.content_bytes),
+            ("stored_bytes", self.stored_bytes),
+        ] {
+            if value < 0 {
+                return Err(format!("{} can't be negative", field));
+            }
+        }
+        Ok(InstanceStats {
+            instance: self.instance,
+            version: self.version,
+            backend: self.backend,
+            pastes: self.pastes,
+            archived_pastes: self.archived_pastes,
+            content_bytes: self.content_bytes,
+            stored_bytes: self.stored_bytes,
+            received_at,
+        })
+    }
+}
+
+// An instance name: 1 to `MAX_NAME_CHARS` letters, digits, “-”, “_” and “.”.
+pub fn check_instance(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_CHARS
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(format!(
+            "An instance name is 1 to {} letters, digits, \"-\", \"_\" and \".\"",
+            MAX_NAME_CHARS
+        ));
+    }
+    Ok(())
+}
+
+// Where an instance sends its reports.
+pub struct Pusher {
+    url: String,
+    pub instance: String,
+    token: Option<String>,
+    pub interval: Duration,
+}
+
+impl Pusher {
+    // Checks the URL and the name, returns what is wrong with them otherwise.
+    pub fn new(url: &str, instance: &str, token: Option<String>, interval_secs: u64) -> Result<Pusher, String> {
+        match url::Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+            Ok(_) => return Err(format!("{} is not an http(s) URL", url)),
+            Err(e) => return Err(format!("{} is not a URL: {}", url, e)),
+        }
+        check_instance(instance)?;
+        Ok(Pusher {
+            url: url.to_string(),
+            instance: instance.to_string(),
+            token,
+            interval: Duration::from_secs(interval_secs.max(MIN_PUSH_INTERVAL_SECS)),
+        })
+    }
+
+    // Sends `report`, the error says why the receiver didn't take it.
+    pub async fn push(&self, report: &Report) -> Result<(), String> {
+        let client = awc::Client::builder().timeout(PUSH_TIMEOUT).finish();
+        let mut request = client
+            .post(&self.url)
+            .header("User-Agent", concat!("pastry/", env!("CARGO_PKG_VERSION")));
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        let response = request
+            .send_json(report)
+            .await
+            .map_err(|e| format!("{} could not be reached: {}", self.url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("{} answered {}", self.url, response.status()));
+        }
+        Ok(())
+    }
+}
+
+// The admin page of the reports.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/admin/federation"), web::get().to(admin_federation));
+}
+
+// The reports of other instances in the `/api` scope.
+pub fn configure_api(cfg: &mut web::ServiceConfig) {
+    cfg.route(openapi::route("POST", "/federation/stats"), web::post().to(api_federation_stats));
+}
+
+// Handles “POST /api/federation/stats”, the report of another instance. Answers 204; a 404 when
+// this server takes no reports, without `PASTRY_FEDERATION_TOKEN`, and a 401 without it as the bearer token.
+async fn api_federation_stats(req: HttpRequest, body: Result<web::Bytes, actix_web::Error>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let expected = data
+        .federation_token
+        .as_deref()
+        .ok_or_else(|| AppError::not_found("This server takes no stats, set PASTRY_FEDERATION_TOKEN to enable it"))?;
+    if !bearer(&req).is_some_and(|given| secret_matches(expected, given)) {
+        return Err(AppError::new(error::ErrorCode::Unauthorized, "The federation token is required to send stats"));
+    }
+    let received = body::received(body, MAX_REPORT_BYTES)?;
+    let report: Report = body::json(&req, received, MAX_REPORT_BYTES)?;
+    let stats = report.into_stats(store::now()).map_err(AppError::bad_request)?;
+    store_write(&data, move |store| store.record_instance_stats(&stats)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Serialize)]
+struct FederationTotals {
+    instances: usize,
+    pastes: i64,
+    archived_pastes: i64,
+    content_bytes: i64,
+    stored_bytes: i64,
+}
+
+#[derive(serde::Serialize)]
+struct FederationStats {
+    instances: Vec<InstanceStats>,
+    totals: FederationTotals,
+}
+
+// Handles “GET /admin/federation”, the last report of each instance with the totals of all of them.
+// A page by default, the same numbers as JSON with `?format=json`.
+async fn admin_federation(req: HttpRequest, query: web::Query<DbQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let instances = data.store.instance_stats()?;
+    let totals = FederationTotals {
+        instances: instances.len(),
+        pastes: instances.iter().map(|stats| stats.pastes).sum(),
+        archived_pastes: instances.iter().map(|stats| stats.archived_pastes).sum(),
+        content_bytes: instances.iter().map(|stats| stats.content_bytes).sum(),
+        stored_bytes: instances.iter().map(|stats| stats.stored_bytes).sum(),
+    };
+    if query.format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok().json(FederationStats { instances, totals }));
+    }
+
+    let mut report = String::new();
+    let line = |name: &str, version: &str, pastes: i64, archived: i64, content: i64, stored: i64, received: &str| {
+        format!(
+            "{:<24} {:<10} {:>10} pastes ({} archived)  {} of content, {} stored  {}\n",
+            name,
+            version,
+            pastes,
+            archived,
+            format_bytes(content),
+            format_bytes(stored),
+            received
+        )
+    };
+    for stats in &instances {
+        report.push_str(&line(
+            &format!("{} ({})", stats.instance, stats.backend),
+            &stats.version,
+            stats.pastes,
+            stats.archived_pastes,
+            stats.content_bytes,
+            stats.stored_bytes,
+            &format!("received {}", timestamp::absolute(stats.received_at)),
+        ));
+    }
+    if instances.is_empty() {
+        report.push_str("No reports yet\n");
+    } else {
+        report.push('\n');
+        report.push_str(&line(
+            &format!("{} instances", totals.instances),
+            "",
+            totals.pastes,
+            totals.archived_pastes,
+            totals.content_bytes,
+            totals.stored_bytes,
+            "",
+        ));
+    }
+    let _render = telemetry::template("admin_federation.html");
+    let html_page = admin_template(&data, include_str!("admin_federation.html")).replace("{{report}}", &escape_html(&report));
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
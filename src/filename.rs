@@ -0,0 +1,195 @@
+// The name a downloaded paste is saved as, from `PASTRY_DOWNLOAD_FILENAME`: a template like “{token}.{ext}” (the
+// default) or “{title|token}.{ext}”. Its placeholders:
+// - `token`: the token of the paste;
+// - `title`: its title, its words joined by “_”, empty without one;
+// - `ext`: “txt”, or “bin” for binary pastes;
+// - `date`: the day the paste was created, “2024-01-31”;
+// - `tag`: its first tag, empty without one.
+// `{a|b}` is the first of them that isn't empty. The text around them is kept as it is, ASCII without path separators.
+// Values are reduced to letters, digits, “-”, “_” and “.”, so that no paste names a file outside the download
+// directory, and a name that comes out empty or starting with a dot falls back to “{token}.{ext}”.
+
+use std::fmt;
+use std::str::FromStr;
+
+pub const DEFAULT: &str = "{token}.{ext}";
+
+// What the content of a zip archive of a paste is named (see `archive_paste`), whatever the download is.
+pub const ARCHIVE: &str = "{title|token}.{ext}";
+
+// Longest file name made, in bytes: file systems stop at 255, and some room is left for a “ (1)” of the browser.
+const MAX_LEN: usize = 200;
+
+const FIELDS: &[&str] = &["token", "title", "ext", "date", "tag"];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    // The names of the alternatives, in order
+    Field(Vec<&'static str>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+// What a template is filled with.
+pub struct Fields<'a> {
+    pub token: &'a str,
+    pub title: &'a str,
+    pub binary: bool,
+    pub created_at: i64,
+    pub tags: &'a [String],
+}
+
+impl Template {
+    // The template of `ARCHIVE`.
+    pub fn archive() -> Template {
+        ARCHIVE.parse().expect("the archive template parses")
+    }
+
+    pub fn render(&self, fields: &Fields<'_>) -> String {
+        let ext = if fields.binary { "bin" } else { "txt" };
+        let value = |name: &str| match name {
+            "token" => fields.token.to_string(),
+            "title" => title_words(fields.title),
+            "ext" => ext.to_string(),
+            "date" => crate::timestamp::date(fields.created_at),
+            "tag" => fields.tags.first().cloned().unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Field(names) => {
+                    let chosen = names.iter().map(|field| sanitize(&value(field))).find(|value| !value.is_empty());
+                    name.push_str(&chosen.unwrap_or_default());
+                }
+            }
+        }
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('.') {
+            return format!("{}.{}", sanitize(fields.token), ext);
+        }
+        truncate(name, MAX_LEN).to_string()
+    }
+}
+
+impl Default for Template {
+    fn default() -> Template {
+        DEFAULT.parse().expect("the default template parses")
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("The placeholder at \"{}\" has no closing brace", rest))?;
+                    let names = rest[1..end]
+                        .split('|')
+                        .map(|name| {
+                            let name = name.trim();
+                            FIELDS.iter().copied().find(|field| *field == name).ok_or_else(|| {
+                                format!("Unknown placeholder \"{}\", the placeholders are token, title, ext, date and tag", name)
+                            })
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    parts.push(Part::Field(names));
+                    rest = &rest[end + 1..];
+                }
+                found => {
+                    let end = found.unwrap_or(rest.len());
+                    let text = &rest[..end];
+                    if let Some(c) = text.chars().find(|c| !c.is_ascii() || c.is_ascii_control() || matches!(c, '/' | '\\' | '}')) {
+                        return Err(format!("{:?} can't be part of a file name template", c));
+                    }
+                    parts.push(Part::Text(text.to_string()));
+                    rest = &rest[end..];
+                }
+            }
+        }
+        if !parts.iter().any(|part| matches!(part, Part::Field(_))) {
+            return Err("A file name template needs at least one placeholder, e.g. {token}".to_string());
+        }
+        Ok(Template { source: source.to_string(), parts })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+// `value` with every character but letters, digits, “-”, “_” and “.” as “_”, and no “..”.
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    value.replace("..", "_")
+}
+
+// The words of a title joined by “_”, a word being a run of the characters `sanitize` keeps: “Notes: part 2” is
+// “Notes_part_2” rather than “Notes__part_2”.
+fn title_words(title: &str) -> String {
+    let words: Vec<&str> = title
+        .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.join("_")
+}
+
+// The first `max` bytes of `name`, which is ASCII.
+fn truncate(name: &str, max: usize) -> &str {
+    &name[..name.len().min(max)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, title: &str, binary: bool) -> String {
+        let tags = ["deploy".to_string()];
+        let fields = Fields {
+            token: "aB3dE6gH",
+            title,
+            binary,
+            created_at: 1_706_659_200,
+            tags: &tags,
+        };
+        template.parse::<Template>().unwrap().render(&fields)
+    }
+
+    #[test]
+    fn the_title_names_the_file_when_there_is_one() {
+        assert_eq!(render("{title|token}.{ext}", "Deploy notes", false), "Deploy_notes.txt");
+        assert_eq!(render("{title|token}.{ext}", "", true), "aB3dE6gH.bin");
+        assert_eq!(render("{title}-{date}.{ext}", "  Notes: part 2 (draft)  ", false), "Notes_part_2_draft-2024-01-31.txt");
+        // Nothing of it is kept, the token is the next one
+        assert_eq!(render("{title|token}.{ext}", "Ünïcödé ✓", false), "n_c_d.txt");
+        assert_eq!(render("{title|tag}.{ext}", "…", false), "deploy.txt");
+        assert_eq!(render(ARCHIVE, "../../etc/passwd", false), "____etc_passwd.txt");
+        assert_eq!(render("{title}.{ext}", "", false), "aB3dE6gH.txt");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_refused() {
+        let error = "{name}.{ext}".parse::<Template>().unwrap_err();
+        assert!(error.contains("token, title, ext, date and tag"), "{}", error);
+        assert_eq!(Template::archive().to_string(), ARCHIVE);
+    }
+}
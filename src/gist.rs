@@ -0,0 +1,103 @@
+// Mirroring of pastes to secret GitHub gists, for an instance with `PASTRY_GITHUB_TOKEN` set.
+// Without the token nothing of it is reachable: no button, no `mirror` in the API, no status page.
+// The creator asks for it with the button of their private link, or `"mirror": true` when creating through the API.
+// The paste is then marked pending and the gist created by a task of its own, so no request waits for GitHub.
+// How it went, the URL of the gist or the reason GitHub gave, is kept with the paste (see `PasteStore::gist_mirror`)
+// and shown on “/paste/{token}/gist”.
+
+use actix_web::http::{HeaderMap, StatusCode};
+use chrono::{TimeZone, Utc};
+use std::time::Duration;
+
+// How long a call to GitHub may take, connecting included.
+const GITHUB_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Largest answer read from GitHub. A created gist comes back with its content, which GitHub cuts at 1 MB per file.
+const RESPONSE_LIMIT: usize = 4 * 1024 * 1024;
+
+pub struct GistClient {
+    // `PASTRY_GIST_API_URL`, the GitHub API unless pointed at GitHub Enterprise or a test server
+    api_url: String,
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CreatedGist {
+    html_url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct GithubError {
+    message: String,
+}
+
+impl GistClient {
+    pub fn new(api_url: &str, token: String) -> GistClient {
+        GistClient {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    // Creates a secret gist holding `content` as `filename`, returns the URL of its page.
+    // The error says why in words fit for the status page: the token refused, the rate limit used up…
+    pub async fn create(&self, filename: &str, description: &str, content: &str) -> Result<String, String> {
+        let client = awc::Client::builder().timeout(GITHUB_TIMEOUT).finish();
+        let mut response = client
+            .post(format!("{}/gists", self.api_url))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            // GitHub refuses requests without one
+            .header("User-Agent", concat!("pastry/", env!("CARGO_PKG_VERSION")))
+            .send_json(&serde_json::json!({
+                "description": description,
+                "public": false,
+                "files": { filename: { "content": content } },
+            }))
+            .await
+            .map_err(|e| format!("GitHub could not be reached: {}", e))?;
+
+        let status = response.status();
+        let body = response
+            .body()
+            .limit(RESPONSE_LIMIT)
+            .await
+            .map_err(|e| format!("The answer of GitHub could not be read: {}", e))?;
+        if !status.is_success() {
+            let message = serde_json::from_slice::<GithubError>(&body)
+                .map(|error| error.message)
+                .unwrap_or_default();
+            return Err(describe_failure(status, response.headers(), &message));
+        }
+
+        serde_json::from_slice::<CreatedGist>(&body)
+            .map(|gist| gist.html_url)
+            .map_err(|e| format!("GitHub answered {} without the gist: {}", status, e))
+    }
+}
+
+// What a refusal of GitHub means for the creator, with its message when it gave one.
+fn describe_failure(status: StatusCode, headers: &HeaderMap, message: &str) -> String {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<i64>().ok());
+    let reason = match status {
+        StatusCode::UNAUTHORIZED => "GitHub refused the token of this server".to_string(),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS if header("x-ratelimit-remaining") == Some(0) => {
+            match header("x-ratelimit-reset").and_then(|reset| Utc.timestamp_opt(reset, 0).single()) {
+                Some(reset) => format!("The GitHub rate limit of this server is used up until {}", reset.format("%Y-%m-%d %H:%M UTC")),
+                None => "The GitHub rate limit of this server is used up".to_string(),
+            }
+        }
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS if header("retry-after").is_some() => format!(
+            "GitHub asked to slow down for {} seconds",
+            header("retry-after").unwrap_or_default()
+        ),
+        StatusCode::FORBIDDEN => "The token of this server may not create gists".to_string(),
+        _ => "GitHub refused to create the gist".to_string(),
+    };
+    if message.is_empty() {
+        format!("{} ({})", reason, status.as_u16())
+    } else {
+        format!("{} ({}: {})", reason, status.as_u16(), message)
+    }
+}
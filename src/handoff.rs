@@ -0,0 +1,63 @@
+// The one-time keys of “/paste/{token}/created”, the page the form sends the creator of a new paste to.
+// Submitting used to redirect to “/paste/{token}?key=<secret>”, leaving the secret in the address bar, the history
+// and anything the link was copied from. Now the redirect only carries a one-time key: the first load of the page
+// takes it and shows everything about the new paste, its secret key included, with buttons to copy them; every later
+// load, a refresh, a link copied from the address bar, gets the public part only. The secret was never in a URL.
+// A key dies on its first use or after `LIFETIME_SECS`. The keys are kept in memory, up to `MAX_KEYS`, like those of
+// `idempotency.rs`: a restart forgets them, and so does another instance, which then shows the public part.
+
+use std::collections::{HashMap, VecDeque};
+
+// How long a one-time key can wait for its page, time for a slow redirect and not much more.
+pub const LIFETIME_SECS: i64 = 10 * 60;
+
+// Characters of a one-time key.
+pub const KEY_LEN: usize = 24;
+
+// Most keys kept, past them the oldest are forgotten first.
+const MAX_KEYS: usize = 10_000;
+
+struct Entry {
+    token: String,
+    secret: String,
+    expires_at: i64,
+}
+
+#[derive(Default)]
+pub struct Handoffs {
+    entries: HashMap<String, Entry>,
+    // The keys in the order they were given, to forget the oldest
+    order: VecDeque<String>,
+}
+
+impl Handoffs {
+    // Keeps the secret of the paste `token` for the one to come with `key`.
+    pub fn give(&mut self, key: String, token: &str, secret: &str, now: i64) {
+        self.entries.retain(|_, entry| entry.expires_at > now);
+        while self.entries.len() >= MAX_KEYS {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.order.retain(|key| self.entries.contains_key(key));
+        self.order.push_back(key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                token: token.to_string(),
+                secret: secret.to_string(),
+                expires_at: now + LIFETIME_SECS,
+            },
+        );
+    }
+
+    // The secret kept for `key`, when it is a key of the paste `token` still alive; it is forgotten either way.
+    pub fn take(&mut self, key: &str, token: &str, now: i64) -> Option<String> {
+        let entry = self.entries.remove(key)?;
+        self.order.retain(|kept| kept != key);
+        (entry.token == token && entry.expires_at > now).then_some(entry.secret)
+    }
+}
@@ -0,0 +1,300 @@
+// Translations of the pages people read: the index page and its form, the page of a paste with its comments, the
+// listings (“/popular”, “/mine”, “/tags”, “/search”) and the error page. The admin pages, the API and the text served
+// to command line clients stay in English, and so do error messages, which the API shares. The content of a paste is
+// never translated: templates are translated before anything is put in them.
+// A locale is a resource file of `src/locales/` compiled in, one `key = value` per line (`#` starts a comment),
+// registered in `LOCALES`. A key missing from a locale falls back to English, and one missing from English too shows
+// as the key itself, so a page never fails for a translation. Values are plain text, `{name}` marks where an argument
+// goes and `{count}` the number of a key with plural forms, `key.one` and `key.other`.
+// The locale is the one of the `lang` cookie, set by the switcher at the bottom of the pages (“POST /language”),
+// then the best one of `Accept-Language`, then English.
+
+use actix_web::http::HeaderMap;
+use actix_web::HttpRequest;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+pub const COOKIE_NAME: &str = "lang";
+
+// A year, every use of the switcher sets it again.
+const MAX_AGE_SECS: i64 = 365 * 24 * 60 * 60;
+
+pub struct Locale {
+    // The language tag of `Accept-Language` and of `<html lang>`
+    pub code: &'static str,
+    // As the switcher shows it, in that language
+    pub name: &'static str,
+    source: &'static str,
+}
+
+// English first, it is what every other locale falls back to.
+pub const LOCALES: &[Locale] = &[
+    Locale {
+        code: "en",
+        name: "English",
+        source: include_str!("locales/en.txt"),
+    },
+    Locale {
+        code: "de",
+        name: "Deutsch",
+        source: include_str!("locales/de.txt"),
+    },
+];
+
+// The strings of every locale, in the order of `LOCALES`, read from their files on first use.
+static STRINGS: OnceLock<Vec<HashMap<&'static str, &'static str>>> = OnceLock::new();
+
+fn strings() -> &'static [HashMap<&'static str, &'static str>] {
+    STRINGS.get_or_init(|| LOCALES.iter().map(|locale| parse(locale)).collect())
+}
+
+// Lines without a `=` are a mistake of the file, which is compiled in: better found at the first start than shipped.
+fn parse(locale: &Locale) -> HashMap<&'static str, &'static str> {
+    let mut strings = HashMap::new();
+    for (number, line) in locale.source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .unwrap_or_else(|| panic!("Line {} of locales/{}.txt has no \"=\"", number + 1, locale.code));
+        strings.insert(key.trim(), value.trim());
+    }
+    strings
+}
+
+// Reads every locale, at startup rather than on the first page.
+pub fn init() {
+    strings();
+}
+
+// The strings of one locale, for a page.
+#[derive(Clone, Copy)]
+pub struct Texts {
+    index: usize,
+}
+
+impl Texts {
+    pub fn english() -> Texts {
+        Texts { index: 0 }
+    }
+
+    // The locale `req` asks for, see the top of the file.
+    pub fn of(req: &HttpRequest) -> Texts {
+        Texts::asked(req.headers())
+    }
+
+    // The same from the headers of a request, for middlewares.
+    pub fn asked(headers: &HeaderMap) -> Texts {
+        let cookie = crate::client::cookies(headers, COOKIE_NAME).find_map(find);
+        let accept_language = headers.get("Accept-Language").and_then(|value| value.to_str().ok());
+        Texts {
+            index: cookie
+                .or_else(|| accept_language.and_then(negotiate))
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        LOCALES[self.index].code
+    }
+
+    // The text of `key`, as plain text.
+    pub fn text(self, key: &str) -> &str {
+        let strings = strings();
+        match strings[self.index].get(key).or_else(|| strings[0].get(key)) {
+            Some(text) => text,
+            None => {
+                eprintln!("No text for \"{}\" in locales/en.txt", key);
+                key
+            }
+        }
+    }
+
+    // The text of `key` with its `{name}`s replaced by `args`, as plain text.
+    pub fn fill(self, key: &str, args: &[(&str, &str)]) -> String {
+        let mut text = self.text(key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+
+    // The same for a page: the text escaped, `args` put in as the HTML they already are.
+    // The braces of the text are its own `{name}`s, they are kept.
+    pub fn html(self, key: &str, args: &[(&str, &str)]) -> String {
+        let mut html = crate::escape_html(self.text(key)).replace("&#123;", "{");
+        for (name, value) in args {
+            html = html.replace(&format!("{{{}}}", name), value);
+        }
+        html
+    }
+
+    // The form of `key` for `count`, “1 view” and “3 views”, as plain text.
+    pub fn plural(self, key: &str, count: i64) -> String {
+        let form = if count == 1 { "one" } else { "other" };
+        self.fill(&format!("{}.{}", key, form), &[("count", &count.to_string())])
+    }
+
+    // A template with its `{{t:key}}` replaced by their escaped text and `{{lang}}` by the language tag.
+    // Templates go through here before anything else is put in them.
+    pub fn localize(self, template: &str) -> String {
+        let mut page = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{t:") {
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => break,
+            };
+            page.push_str(&rest[..start]);
+            page.push_str(&crate::escape_html(self.text(&rest[start + "{{t:".len()..end])));
+            rest = &rest[end + "}}".len()..];
+        }
+        page.push_str(rest);
+        page.replace("{{lang}}", self.code())
+    }
+
+    // The form of the pages switching to another locale, sending back to `back`, the path of the page.
+    pub fn switcher(self, back: &str) -> String {
+        let options: String = LOCALES
+            .iter()
+            .enumerate()
+            .map(|(index, locale)| {
+                format!(
+                    "<option value=\"{}\" lang=\"{}\"{}>{}</option>",
+                    locale.code,
+                    locale.code,
+                    if index == self.index { " selected" } else { "" },
+                    crate::escape_html(locale.name)
+                )
+            })
+            .collect();
+        format!(
+            "<form method=\"post\" action=\"/language\" class=\"language\"><input type=\"hidden\" name=\"back\" value=\"{}\">\
+             <select name=\"lang\" aria-label=\"{}\">{}</select> <button type=\"submit\">{}</button></form>",
+            crate::escape_html(back),
+            crate::escape_html(self.text("language.label")),
+            options,
+            crate::escape_html(self.text("language.change"))
+        )
+    }
+}
+
+// The index of the locale of a language tag, “de” or “de-AT” for German, in any case.
+pub fn find(tag: &str) -> Option<usize> {
+    let primary = tag.trim().split('-').next().unwrap_or("");
+    LOCALES.iter().position(|locale| locale.code.eq_ignore_ascii_case(primary))
+}
+
+// The locale an `Accept-Language` header prefers: the tag of the highest quality we have, the first one on a tie.
+// `*` and the tags we don't have are passed over, `None` when none is left.
+fn negotiate(accept_language: &str) -> Option<usize> {
+    let mut best: Option<(f32, usize)> = None;
+    for range in accept_language.split(',') {
+        let mut parts = range.split(';');
+        let index = match find(parts.next().unwrap_or("")) {
+            Some(index) => index,
+            None => continue,
+        };
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality > 0.0 && best.is_none_or(|(best_quality, _)| quality > best_quality) {
+            best = Some((quality, index));
+        }
+    }
+    best.map(|(_, index)| index)
+}
+
+// The `Set-Cookie` header keeping the locale `code`, `Secure` when the request came over HTTPS.
+pub fn set_cookie(code: &str, secure: bool) -> String {
+    format!(
+        "{}={}; Max-Age={}; Path=/; SameSite=Lax{}",
+        COOKIE_NAME,
+        code,
+        MAX_AGE_SECS,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn asked(headers: &[(&str, &str)]) -> &'static str {
+        let mut request = TestRequest::default();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        Texts::asked(request.to_http_request().headers()).code()
+    }
+
+    #[test]
+    fn the_best_language_we_have_is_negotiated() {
+        assert_eq!(asked(&[("Accept-Language", "de")]), "de");
+        assert_eq!(asked(&[("Accept-Language", "de-AT,de;q=0.9,en;q=0.8")]), "de");
+        assert_eq!(asked(&[("Accept-Language", "en-GB,en;q=0.9,de;q=0.8")]), "en");
+        // The highest quality wins wherever it is, the first one on a tie
+        assert_eq!(asked(&[("Accept-Language", "en;q=0.5, de;q=0.7")]), "de");
+        assert_eq!(asked(&[("Accept-Language", "de;q=0.5, en;q=0.5")]), "de");
+        assert_eq!(asked(&[("Accept-Language", "DE-ch")]), "de");
+        // What we don't have is passed over, and so is what is refused
+        assert_eq!(asked(&[("Accept-Language", "fr-FR, fr;q=0.9, de;q=0.3")]), "de");
+        assert_eq!(asked(&[("Accept-Language", "de;q=0, en;q=0.1")]), "en");
+    }
+
+    #[test]
+    fn english_is_the_fallback() {
+        for header in ["", "fr", "*", "de;q=0", ",,;;", "zz-ZZ;q=1.0"] {
+            assert_eq!(asked(&[("Accept-Language", header)]), "en", "{:?}", header);
+        }
+        assert_eq!(asked(&[]), "en");
+        // A quality that doesn't parse leaves the one of a tag without any
+        assert_eq!(asked(&[("Accept-Language", "en;q=0.5, de;q=high")]), "de");
+    }
+
+    #[test]
+    fn the_cookie_comes_before_the_header() {
+        assert_eq!(asked(&[("Cookie", "lang=de"), ("Accept-Language", "en")]), "de");
+        assert_eq!(asked(&[("Cookie", "theme=dark; lang=en"), ("Accept-Language", "de")]), "en");
+        // A cookie of a locale we don't have is the header's to decide
+        assert_eq!(asked(&[("Cookie", "lang=fr"), ("Accept-Language", "de")]), "de");
+    }
+
+    #[test]
+    fn missing_texts_fall_back() {
+        let german = Texts { index: find("de").unwrap() };
+        assert_eq!(german.text("language.change"), "Wechseln");
+        assert_eq!(Texts::english().text("language.change"), "Change");
+        assert_eq!(german.text("no.such.key"), "no.such.key");
+        assert_eq!(Texts::english().localize("<b>{{t:no.such.key}}</b> {{lang}}"), "<b>no.such.key</b> en");
+        // Every key of a translation is one of English, which the pages ask for
+        let english = &strings()[0];
+        for (locale, strings) in LOCALES.iter().zip(strings()).skip(1) {
+            for key in strings.keys() {
+                assert!(english.contains_key(key), "{} of locales/{}.txt isn't in locales/en.txt", key, locale.code);
+            }
+        }
+    }
+
+    #[test]
+    fn texts_are_filled_in() {
+        let german = Texts { index: find("de").unwrap() };
+        assert_eq!(german.plural("time.minute", 1), "1 Minute");
+        assert_eq!(german.plural("time.minute", 5), "5 Minuten");
+        assert_eq!(german.fill("time.ago", &[("amount", "3 Tagen")]), "vor 3 Tagen");
+        // The text is escaped for a page, the arguments are already HTML
+        let page = Texts::english().localize("<p>{{t:index.tagline}}</p>");
+        assert_eq!(page, format!("<p>{}</p>", crate::escape_html(Texts::english().text("index.tagline"))));
+        assert!(german.switcher("/paste/abc").contains("<option value=\"de\" lang=\"de\" selected>Deutsch</option>"));
+    }
+
+    #[test]
+    fn the_cookie_is_kept_a_year() {
+        assert_eq!(set_cookie("de", false), "lang=de; Max-Age=31536000; Path=/; SameSite=Lax");
+        assert!(set_cookie("de", true).ends_with("; Secure"));
+    }
+}
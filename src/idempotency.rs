@@ -0,0 +1,297 @@
+// Requests that create pastes, made safe to send twice: the `Idempotency-Key` header of the API's `POST /api/pastes`
+// and `POST /api/pastes/batch`, and the `nonce` the index page puts in its form, new on every page.
+// The first request with a key claims it, and its answer is kept for `WINDOW_SECS`: the same request again (a
+// double click, a client retrying after a timeout) gets that answer back, with `Idempotent-Replayed: true`, and
+// nothing is created twice. A different request with the key is a 409, and so is the same request while the first
+// one is still being handled. A request that fails lets go of its key, it can be sent again.
+// Keys of the API belong to the caller, its API token or else its address, so that a client can't replay the answer,
+// secret included, of another one that picked the same key.
+// They are kept in memory, up to `MAX_KEYS`: a restart forgets them, and each instance has its own.
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+// How long an answer is kept for the same request.
+pub const WINDOW_SECS: i64 = 60 * 60;
+
+// Most keys kept, past them the oldest are forgotten first.
+const MAX_KEYS: usize = 10_000;
+
+// Longest `Idempotency-Key`.
+pub const MAX_KEY_CHARS: usize = 255;
+
+// Characters of a form nonce.
+pub const NONCE_LEN: usize = 24;
+
+// What the answer to a request was, to send it again.
+#[derive(Clone)]
+pub struct Answer {
+    pub status: StatusCode,
+    pub location: Option<String>,
+    pub set_cookie: Option<String>,
+    // JSON, `None` for the redirect of the form
+    pub body: Option<web::Bytes>,
+}
+
+impl Answer {
+    // An answer of the API, `value` as JSON.
+    pub fn json(status: StatusCode, location: Option<String>, value: &impl serde::Serialize) -> serde_json::Result<Answer> {
+        Ok(Answer {
+            status,
+            location,
+            set_cookie: None,
+            body: Some(web::Bytes::from(serde_json::to_vec(value)?)),
+        })
+    }
+
+    // The response, to the first request or `replayed` to one sent again.
+    pub fn response(&self, replayed: bool) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        if replayed {
+            response.header("Idempotent-Replayed", "true");
+        }
+        if let Some(location) = &self.location {
+            response.header("Location", location.as_str());
+        }
+        if let Some(cookie) = &self.set_cookie {
+            response.header("Set-Cookie", cookie.as_str());
+        }
+        match &self.body {
+            Some(body) => response.content_type("application/json").body(body.clone()),
+            None => response.finish(),
+        }
+    }
+}
+
+enum State {
+    // The first request is being handled
+    Claimed,
+    Answered(Answer),
+}
+
+struct Entry {
+    // Of the request that claimed the key
+    fingerprint: String,
+    expires_at: i64,
+    state: State,
+}
+
+// A request with a key that can go on.
+pub enum Claimed<'a> {
+    // The first one, to answer through the claim
+    New(Claim<'a>),
+    Replay(Answer),
+}
+
+// A request with a key that can't, a 409.
+pub enum Refused {
+    // The key was used by a different request
+    Mismatch,
+    InProgress,
+}
+
+impl Refused {
+    // What the API answers
+    pub fn message(&self) -> &'static str {
+        match self {
+            Refused::Mismatch => "This Idempotency-Key was already used for a different request",
+            Refused::InProgress => "A request with this Idempotency-Key is still being handled, try again in a moment",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Keys {
+    entries: HashMap<String, Entry>,
+    // In the order they were claimed, for evicting
+    order: VecDeque<String>,
+}
+
+// The key a request claimed. `answered` keeps its answer; dropped without one, the request failed and the key is free.
+pub struct Claim<'a> {
+    keys: &'a Mutex<Keys>,
+    key: String,
+    answered: bool,
+}
+
+impl Claim<'_> {
+    pub fn answered(mut self, answer: Answer) {
+        if let Some(entry) = self.keys.lock().unwrap().entries.get_mut(&self.key) {
+            entry.state = State::Answered(answer);
+        }
+        self.answered = true;
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if !self.answered {
+            self.keys.lock().unwrap().entries.remove(&self.key);
+        }
+    }
+}
+
+// The response to the first request with a key, kept for the next ones when there is a claim.
+pub fn respond(claim: Option<Claim<'_>>, answer: Answer) -> HttpResponse {
+    let response = answer.response(false);
+    if let Some(claim) = claim {
+        claim.answered(answer);
+    }
+    response
+}
+
+// Claims `key` for the request of `fingerprint`, at `now`, or tells how it was used.
+pub fn claim<'a>(keys: &'a Mutex<Keys>, key: String, fingerprint: String, now: i64) -> Result<Claimed<'a>, Refused> {
+    let mut locked = keys.lock().unwrap();
+    locked.prune(now);
+    if let Some(entry) = locked.entries.get(&key) {
+        return match &entry.state {
+            _ if entry.fingerprint != fingerprint => Err(Refused::Mismatch),
+            State::Claimed => Err(Refused::InProgress),
+            State::Answered(answer) => Ok(Claimed::Replay(answer.clone())),
+        };
+    }
+
+    while locked.entries.len() >= MAX_KEYS {
+        match locked.order.pop_front() {
+            Some(oldest) => locked.entries.remove(&oldest),
+            None => break,
+        };
+    }
+    locked.entries.insert(
+        key.clone(),
+        Entry {
+            fingerprint,
+            expires_at: now + WINDOW_SECS,
+            state: State::Claimed,
+        },
+    );
+    locked.order.push_back(key.clone());
+    Ok(Claimed::New(Claim { keys, key, answered: false }))
+}
+
+// What tells two requests apart: the route and the body as received.
+pub fn fingerprint(path: &str, body: &[u8]) -> String {
+    format!("{:x}", Sha256::new().chain_update(path.as_bytes()).chain_update([0]).chain_update(body).finalize())
+}
+
+// An `Idempotency-Key` as given, checked: printable ASCII, up to `MAX_KEY_CHARS`.
+pub fn check_key(key: &str) -> Result<&str, String> {
+    if key.is_empty() || key.len() > MAX_KEY_CHARS || !key.bytes().all(|byte| byte.is_ascii_graphic()) {
+        return Err(format!(
+            "An Idempotency-Key is 1 to {} printable ASCII characters without spaces",
+            MAX_KEY_CHARS
+        ));
+    }
+    Ok(key)
+}
+
+impl Keys {
+    // Forgets the keys whose window is over. They are claimed in order, so the expired ones are at the front;
+    // the order may still name keys a failed request gave up, or that were claimed again since.
+    fn prune(&mut self, now: i64) {
+        while let Some(oldest) = self.order.front() {
+            match self.entries.get(oldest) {
+                Some(entry) if entry.expires_at > now => break,
+                Some(_) => {
+                    self.entries.remove(oldest);
+                }
+                None => {}
+            }
+            self.order.pop_front();
+        }
+        // Failed requests leave their keys in the order
+        if self.order.len() > 2 * MAX_KEYS {
+            let Keys { entries, order } = self;
+            order.retain(|key| entries.contains_key(key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer() -> Answer {
+        Answer {
+            status: StatusCode::CREATED,
+            location: Some("/paste/abc".to_string()),
+            set_cookie: None,
+            body: None,
+        }
+    }
+
+    fn claimed(keys: &Mutex<Keys>, key: &str, fingerprint: &str, now: i64) -> &'static str {
+        match claim(keys, key.to_string(), fingerprint.to_string(), now) {
+            Ok(Claimed::New(claim)) => {
+                claim.answered(answer());
+                "new"
+            }
+            Ok(Claimed::Replay(_)) => "replay",
+            Err(Refused::Mismatch) => "mismatch",
+            Err(Refused::InProgress) => "in progress",
+        }
+    }
+
+    #[test]
+    fn the_same_request_is_replayed() {
+        let keys = Mutex::new(Keys::default());
+        assert_eq!(claimed(&keys, "k", "a", 0), "new");
+        assert_eq!(claimed(&keys, "k", "a", 1), "replay");
+        assert_eq!(claimed(&keys, "k", "b", 1), "mismatch");
+        assert_eq!(claimed(&keys, "other", "b", 1), "new");
+    }
+
+    #[test]
+    fn a_request_being_handled_is_in_progress() {
+        let keys = Mutex::new(Keys::default());
+        let first = claim(&keys, "k".to_string(), "a".to_string(), 0);
+        assert!(matches!(first, Ok(Claimed::New(_))));
+        assert_eq!(claimed(&keys, "k", "a", 0), "in progress");
+        assert_eq!(claimed(&keys, "k", "b", 0), "mismatch");
+    }
+
+    #[test]
+    fn a_failed_request_frees_its_key() {
+        let keys = Mutex::new(Keys::default());
+        drop(claim(&keys, "k".to_string(), "a".to_string(), 0));
+        assert_eq!(claimed(&keys, "k", "b", 0), "new");
+    }
+
+    #[test]
+    fn answers_are_kept_for_the_window() {
+        let keys = Mutex::new(Keys::default());
+        assert_eq!(claimed(&keys, "k", "a", 0), "new");
+        assert_eq!(claimed(&keys, "k", "a", WINDOW_SECS - 1), "replay");
+        assert_eq!(claimed(&keys, "k", "b", WINDOW_SECS), "new");
+    }
+
+    #[test]
+    fn replays_say_so() {
+        let first = answer().response(false);
+        assert!(!first.headers().contains_key("Idempotent-Replayed"));
+        let again = answer().response(true);
+        assert_eq!(again.headers().get("Idempotent-Replayed").unwrap(), "true");
+        assert_eq!(again.headers().get("Location").unwrap(), "/paste/abc");
+    }
+
+    #[test]
+    fn fingerprints_tell_routes_and_bodies_apart() {
+        assert_eq!(fingerprint("/api/pastes", b"{}"), fingerprint("/api/pastes", b"{}"));
+        assert_ne!(fingerprint("/api/pastes", b"{}"), fingerprint("/api/pastes/batch", b"{}"));
+        assert_ne!(fingerprint("/a", b"b/c"), fingerprint("/a/b", b"c"));
+    }
+
+    #[test]
+    fn keys_are_printable_ascii() {
+        assert!(check_key("retry-42_abc").is_ok());
+        assert!(check_key(&"k".repeat(MAX_KEY_CHARS)).is_ok());
+        for key in ["", "two words", "é", "tab\t"] {
+            assert!(check_key(key).is_err(), "{:?}", key);
+        }
+        assert!(check_key(&"k".repeat(MAX_KEY_CHARS + 1)).is_err());
+    }
+}
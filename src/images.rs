@@ -0,0 +1,285 @@
+// Images in pastes, which can be as much a tracker as a picture: loading one tells its server who read the paste
+// and when. The links of a paste page (`?links=true`) only show the images their URLs point to as the instance's
+// `PASTRY_IMAGE_POLICY` says: `strip` (the default) leaves them links, `render` puts them in the page as they are,
+// and `proxy` has them fetched by the server through “/imgproxy?url=…”, so readers only ever talk to this instance.
+// Whatever the policy, an `<img>` written in a paste is escaped text like the rest of it, with or without links.
+// The proxy fetches URLs anyone can give it, from inside the network of the server. So that it can't be pointed at
+// the services next to it, it never connects to localhost or a private network: the connector of its client
+// resolves the host itself and refuses a name any address of which is internal, and connects to the addresses it
+// checked rather than resolving the name again. Redirects are followed by hand, `MAX_REDIRECTS` at most, each
+// target going through the same checks as the first URL. Only the image types browsers show without running
+// anything are passed on (no SVG, which can carry scripts), up to `PASTRY_IMAGE_PROXY_MAX_BYTES`, and only from the
+// hosts of `PASTRY_IMAGE_PROXY_ALLOWLIST` when it is set.
+
+use crate::error::{AppError, ErrorCode};
+use crate::{redirect, reserved, AppState};
+use actix_connect::{Connect, ConnectError, TcpConnector};
+use actix_service::Service;
+use actix_web::http::{StatusCode, Uri};
+use actix_web::{web, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use url::{Host, Url};
+
+// How many redirects the proxy follows for one image.
+pub const MAX_REDIRECTS: usize = 3;
+
+// How long connecting to the server of an image may take, resolving its name included.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long fetching an image may take, each redirect on its own.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+// The types of the images passed on, all of them pictures and nothing else.
+const IMAGE_TYPES: &[&str] = &["image/avif", "image/gif", "image/jpeg", "image/png", "image/webp"];
+
+// The extensions of the URLs taken for images on a paste page.
+const IMAGE_EXTENSIONS: &[&str] = &[".avif", ".gif", ".jpeg", ".jpg", ".png", ".webp"];
+
+// How long browsers may keep a proxied image.
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+// What a paste page does with the URLs of images, see `PASTRY_IMAGE_POLICY`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    // The URL stays a link, the page loads nothing from elsewhere
+    #[default]
+    Strip,
+    // The image is shown from its own server
+    Render,
+    // The image is shown from “/imgproxy”
+    Proxy,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Policy, String> {
+        match value {
+            "strip" => Ok(Policy::Strip),
+            "render" => Ok(Policy::Render),
+            "proxy" => Ok(Policy::Proxy),
+            _ => Err(format!("Unknown image policy \"{}\", expected strip, render or proxy", value)),
+        }
+    }
+}
+
+// What the proxy fetches.
+pub struct Limits {
+    // The hosts images may come from, with their subdomains; any host when empty
+    pub allowlist: Vec<String>,
+    // Bigger images are refused
+    pub max_bytes: usize,
+}
+
+// The hosts of `PASTRY_IMAGE_PROXY_ALLOWLIST`, a comma separated list.
+pub fn parse_allowlist(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|host| host.trim().trim_end_matches('.').to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/imgproxy"), web::get().to(image_proxy));
+}
+
+#[derive(serde::Deserialize)]
+struct ProxyQuery {
+    url: String,
+}
+
+// Handles “/imgproxy?url=…”, the image at `url` fetched by `fetch`. Without `PASTRY_IMAGE_POLICY=proxy` there is
+// no such route.
+async fn image_proxy(query: web::Query<ProxyQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let settings = data.settings();
+    if settings.image_policy != Policy::Proxy {
+        return Err(AppError::not_found("The image proxy is turned off on this instance"));
+    }
+    let image = fetch(&query.url, &settings.image_proxy, |address: SocketAddr| !redirect::is_internal_ip(address.ip())).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(image.content_type)
+        .header("Cache-Control", CACHE_CONTROL)
+        .header("X-Content-Type-Options", "nosniff")
+        .header("Content-Security-Policy", "default-src 'none'; sandbox")
+        .body(image.body))
+}
+
+// An image `fetch` got.
+pub struct Image {
+    // One of `IMAGE_TYPES`
+    pub content_type: String,
+    pub body: web::Bytes,
+}
+
+// Fetches the image at `url` within `limits`, connecting only to the addresses `allowed` accepts.
+// The errors are those of the answer of “/imgproxy”: a 400 for what isn't an http(s) URL, a 403 for a host or address
+// it may not fetch from, a 413 for an image too big, a 415 for what isn't an image and a 503 when the server of the
+// image couldn't be reached or didn't answer with one.
+pub async fn fetch<F>(url: &str, limits: &Limits, allowed: F) -> Result<Image, AppError>
+where
+    F: Fn(SocketAddr) -> bool + Clone + 'static,
+{
+    let connector = awc::Connector::new()
+        .connector(CheckedConnector { allowed })
+        .timeout(CONNECT_TIMEOUT)
+        .finish();
+    let client = awc::Client::builder().connector(connector).timeout(FETCH_TIMEOUT).finish();
+
+    let mut url = check_url(Url::parse(url).map_err(|e| AppError::bad_request(format!("Not a valid URL: {}", e)))?, limits)?;
+    for _ in 0..=MAX_REDIRECTS {
+        let mut response = client
+            .get(url.as_str())
+            .header("Accept", IMAGE_TYPES.join(", "))
+            .header("User-Agent", concat!("pastry/", env!("CARGO_PKG_VERSION")))
+            .send()
+            .await
+            .map_err(|e| match e {
+                awc::error::SendRequestError::Connect(awc::error::ConnectError::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    AppError::forbidden(e.to_string())
+                }
+                e => unavailable(format!("The server of the image could not be reached: {}", e)),
+            })?;
+
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get("Location")
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| unavailable(format!("The server of the image answered {} without a Location", status)))?;
+            let target = url
+                .join(location)
+                .map_err(|e| unavailable(format!("The server of the image redirected to an invalid URL: {}", e)))?;
+            url = check_url(target, limits)?;
+            continue;
+        }
+        if status != StatusCode::OK {
+            return Err(unavailable(format!("The server of the image answered {}", status)));
+        }
+
+        let content_type = response
+            .headers()
+            .get("Content-Type")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        if !IMAGE_TYPES.contains(&content_type.as_str()) {
+            let message = format!("Only {} are passed on, not {:?}", IMAGE_TYPES.join(", "), content_type);
+            return Err(AppError::new(ErrorCode::UnsupportedMediaType, message));
+        }
+        let too_large = || {
+            let message = format!("The image is bigger than the {} bytes the proxy fetches", limits.max_bytes);
+            AppError::new(ErrorCode::PayloadTooLarge, message)
+        };
+        let length = response
+            .headers()
+            .get("Content-Length")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if length.is_some_and(|length| length > limits.max_bytes as u64) {
+            return Err(too_large());
+        }
+        // A length the server didn't tell, or told wrong, is cut at the limit all the same
+        let body = response.body().limit(limits.max_bytes).await.map_err(|e| match e {
+            awc::error::PayloadError::Overflow => too_large(),
+            e => unavailable(format!("The image could not be read: {}", e)),
+        })?;
+        return Ok(Image { content_type, body });
+    }
+    Err(AppError::forbidden(format!("The image is behind more than {} redirects", MAX_REDIRECTS)))
+}
+
+fn unavailable(message: String) -> AppError {
+    AppError::new(ErrorCode::Unavailable, message)
+}
+
+// Checks that `url`, the image or a redirect to it, is an http(s) URL the proxy may fetch: its host on the allowlist,
+// and not a name only meaning something inside a network. Where its name leads is checked when connecting.
+fn check_url(url: Url, limits: &Limits) -> Result<Url, AppError> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(AppError::bad_request("Only http:// and https:// images are proxied"));
+    }
+    let host = match url.host() {
+        None => return Err(AppError::bad_request("The URL of the image has no host")),
+        Some(Host::Domain(domain)) if redirect::is_internal_domain(domain) => {
+            return Err(AppError::forbidden(format!("{} is a name of a private network", domain)))
+        }
+        Some(host) => host.to_string().to_ascii_lowercase(),
+    };
+    let listed = |entry: &String| host == *entry || host.ends_with(&format!(".{}", entry));
+    if !limits.allowlist.is_empty() && !limits.allowlist.iter().any(listed) {
+        return Err(AppError::forbidden(format!("Images from {} are not proxied on this instance", host)));
+    }
+    Ok(url)
+}
+
+// The TCP connector of the client of `fetch`: it resolves the host of a connection itself, refuses it when one of
+// its addresses isn't `allowed`, and has the connector of awc connect to the addresses it checked.
+// A refusal is an I/O error of kind `PermissionDenied`, which `fetch` answers with a 403.
+#[derive(Clone)]
+struct CheckedConnector<F> {
+    allowed: F,
+}
+
+impl<F> Service for CheckedConnector<F>
+where
+    F: Fn(SocketAddr) -> bool + Clone + 'static,
+{
+    type Request = Connect<Uri>;
+    type Response = <TcpConnector<Uri> as Service>::Response;
+    type Error = ConnectError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, ConnectError>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), ConnectError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, connect: Connect<Uri>) -> Self::Future {
+        let allowed = self.allowed.clone();
+        Box::pin(async move {
+            // The host of an IPv6 address comes in brackets
+            let host = connect.host().trim_start_matches('[').trim_end_matches(']').to_string();
+            let port = connect.port();
+            let name = host.clone();
+            let addresses = web::block(move || (name.as_str(), port).to_socket_addrs().map(Vec::from_iter))
+                .await
+                .map_err(|e| match e {
+                    actix_web::error::BlockingError::Error(e) => ConnectError::Io(e),
+                    actix_web::error::BlockingError::Canceled => ConnectError::Unresolved,
+                })?;
+            if let Some(address) = addresses.iter().find(|address| !allowed(**address)) {
+                let message = format!("{} is at {}, the proxy doesn't connect to private network addresses", host, address.ip());
+                return Err(ConnectError::Io(io::Error::new(io::ErrorKind::PermissionDenied, message)));
+            }
+            if addresses.is_empty() {
+                return Err(ConnectError::NoRecords);
+            }
+            TcpConnector::new().call(connect.set_addrs(addresses)).await
+        })
+    }
+}
+
+// The `src` of an image of a paste page shown as `policy` says, for its `url` as the page escaped it;
+// `None` when it stays a link, with `Policy::Strip` or a URL that isn't of an image.
+pub fn image_source(escaped_url: &str, policy: Policy) -> Option<String> {
+    if policy == Policy::Strip {
+        return None;
+    }
+    let url = escaped_url.replace("&amp;", "&");
+    let path = url.split(['?', '#']).next().unwrap_or(&url).to_ascii_lowercase();
+    if !IMAGE_EXTENSIONS.iter().any(|extension| path.ends_with(extension)) {
+        return None;
+    }
+    Some(match policy {
+        Policy::Proxy => format!("/imgproxy?url={}", utf8_percent_encode(&url, NON_ALPHANUMERIC)),
+        _ => escaped_url.to_string(),
+    })
+}
@@ -0,0 +1,207 @@
+// `POST /admin/integrity-check` and `pastry fsck`: looks for what partial failures leave behind, and with `repair`
+// removes it. Three kinds of problems:
+// - rows naming a paste that is gone (`store::Orphans`: search index, tags, daily views, comments, gists) or a
+//   collection that is gone (its items), deleted in one transaction per chunk, each row checked again there;
+// - blob files of no paste, older than the grace of `BlobStore`, deleted; and pastes whose blob file is missing,
+//   only reported, there is nothing to bring them back from;
+// - pastes whose recorded size or hash doesn't match their content, recorded again from the content.
+// Tables and pastes are read `CHUNK` tokens at a time, each chunk one short query, so the server keeps serving
+// while a large database is checked. Sizes and hashes not recorded yet are filled in as pastes are read, not here.
+
+use crate::store::{self, Content, ContentSize, Orphans, PasteStore, StoreResult};
+use std::fs;
+
+// Tokens read per query.
+const CHUNK: i64 = 500;
+
+// Problems listed in a report, the counts go on past them.
+const MAX_PROBLEMS: usize = 100;
+
+// The orphans of one kind.
+#[derive(serde::Serialize)]
+pub struct Found {
+    pub kind: &'static str,
+    // Tokens having orphaned rows, or files for “blob_files”
+    pub found: usize,
+    pub removed: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct Report {
+    pub repair: bool,
+    pub orphans: Vec<Found>,
+    pub missing_blobs: usize,
+    pub pastes_checked: usize,
+    pub size_mismatches: usize,
+    pub hash_mismatches: usize,
+    // Whose size or hash was recorded again
+    pub pastes_fixed: usize,
+    // Whose content couldn't be read, a missing blob file or a broken archive
+    pub unreadable_pastes: usize,
+    // The first `MAX_PROBLEMS`, in words
+    pub problems: Vec<String>,
+    // For the cache to forget their old size
+    #[serde(skip)]
+    pub fixed_tokens: Vec<String>,
+}
+
+impl Report {
+    fn problem(&mut self, problem: String) {
+        if self.problems.len() < MAX_PROBLEMS {
+            self.problems.push(problem);
+        }
+    }
+
+    // How many problems were found, repaired or not.
+    pub fn found(&self) -> usize {
+        self.orphans.iter().map(|found| found.found).sum::<usize>()
+            + self.missing_blobs
+            + self.size_mismatches
+            + self.hash_mismatches
+            + self.unreadable_pastes
+    }
+
+    // What the audit log keeps of a check, and the last line of `pastry fsck`.
+    pub fn summary(&self) -> String {
+        let orphans: Vec<String> = self
+            .orphans
+            .iter()
+            .filter(|found| found.found > 0)
+            .map(|found| {
+                if self.repair {
+                    format!("{} {} ({} removed)", found.found, found.kind, found.removed)
+                } else {
+                    format!("{} {}", found.found, found.kind)
+                }
+            })
+            .collect();
+        let orphans = if orphans.is_empty() { "none".to_string() } else { orphans.join(", ") };
+        format!(
+            "orphans: {}; {} missing blobs; {} pastes checked, {} size and {} hash mismatches, {} fixed, {} unreadable",
+            orphans,
+            self.missing_blobs,
+            self.pastes_checked,
+            self.size_mismatches,
+            self.hash_mismatches,
+            self.pastes_fixed,
+            self.unreadable_pastes
+        )
+    }
+}
+
+// Runs every check on `store`, repairing what it finds with `repair`.
+pub fn check(store: &dyn PasteStore, repair: bool) -> StoreResult<Report> {
+    let mut report = Report {
+        repair,
+        orphans: Vec::new(),
+        missing_blobs: 0,
+        pastes_checked: 0,
+        size_mismatches: 0,
+        hash_mismatches: 0,
+        pastes_fixed: 0,
+        unreadable_pastes: 0,
+        problems: Vec::new(),
+        fixed_tokens: Vec::new(),
+    };
+
+    for kind in Orphans::ALL {
+        let mut found = Found { kind: kind.name(), found: 0, removed: 0 };
+        let mut after = String::new();
+        loop {
+            let scan = store.find_orphans(kind, &after, CHUNK)?;
+            found.found += scan.orphans.len();
+            for token in &scan.orphans {
+                report.problem(format!("{} of {}, which is gone", kind.name(), token));
+            }
+            if repair && !scan.orphans.is_empty() {
+                found.removed += store.remove_orphans(kind, &scan.orphans)?;
+            }
+            match scan.last {
+                Some(last) => after = last,
+                None => break,
+            }
+        }
+        report.orphans.push(found);
+    }
+
+    let blobs = store.scan_blobs()?;
+    for hash in &blobs.orphans {
+        report.problem(format!("blob file {} of no paste", hash));
+    }
+    for hash in &blobs.missing {
+        report.problem(format!("blob file {} is missing", hash));
+    }
+    let removed = if repair { store.remove_blobs(&blobs.orphans)? } else { 0 };
+    report.orphans.push(Found {
+        kind: "blob_files",
+        found: blobs.orphans.len(),
+        removed,
+    });
+    report.missing_blobs = blobs.missing.len();
+
+    let mut after = String::new();
+    loop {
+        let tokens = store.paste_tokens(&after, CHUNK)?;
+        for token in &tokens {
+            check_paste(store, token, repair, &mut report)?;
+        }
+        match tokens.last() {
+            Some(last) => after = last.clone(),
+            None => break,
+        }
+    }
+    Ok(report)
+}
+
+// Compares the recorded size and hash of the paste `token` with its content.
+fn check_paste(store: &dyn PasteStore, token: &str, repair: bool, report: &mut Report) -> StoreResult<()> {
+    let (paste, content) = match store.open_content(token) {
+        Ok(Some(found)) => found,
+        // Expired, or deleted since its chunk was read
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            report.unreadable_pastes += 1;
+            report.problem(format!("paste {} can't be read: {}", token, e));
+            return Ok(());
+        }
+    };
+    let (size, hash) = match content {
+        Content::Inline(text) => (ContentSize::of_text(&text), store::content_hash(text.as_bytes())),
+        Content::Binary(data) => (ContentSize::of_binary(&data), store::content_hash(&data)),
+        Content::File(path) => match fs::read_to_string(&path) {
+            Ok(text) => (ContentSize::of_text(&text), store::content_hash(text.as_bytes())),
+            Err(e) => {
+                report.unreadable_pastes += 1;
+                report.problem(format!("paste {} can't be read from {}: {}", token, path.display(), e));
+                return Ok(());
+            }
+        },
+    };
+    report.pastes_checked += 1;
+
+    let wrong_size = paste.size.filter(|recorded| *recorded != size);
+    let wrong_hash = paste.content_hash.as_deref().is_some_and(|recorded| recorded != hash);
+    if let Some(recorded) = wrong_size {
+        report.size_mismatches += 1;
+        report.problem(format!("paste {}: {} recorded, its content has {}", token, describe(recorded), describe(size)));
+    }
+    if wrong_hash {
+        report.hash_mismatches += 1;
+        report.problem(format!("paste {}: its recorded hash isn't the one of its content", token));
+    }
+    if repair && (wrong_size.is_some() || wrong_hash) {
+        if wrong_size.is_some() {
+            store.set_size(token, size)?;
+        }
+        if wrong_hash {
+            store.set_content_hash(token, &hash)?;
+        }
+        report.pastes_fixed += 1;
+        report.fixed_tokens.push(token.to_string());
+    }
+    Ok(())
+}
+
+fn describe(size: ContentSize) -> String {
+    format!("{} lines, {} characters, {} bytes", size.lines, size.chars, size.bytes)
+}
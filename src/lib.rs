@@ -0,0 +1,7 @@
+// The library side of pastry_crust, next to the server of `main.rs`: the wire format of the JSON API, shared by the
+// server and by `client::PastryClient`, a typed client of it behind the `client` cargo feature.
+
+pub mod api;
+
+#[cfg(feature = "client")]
+pub use api::client;
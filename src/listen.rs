@@ -0,0 +1,130 @@
+// Listening sockets handed over by systemd (socket activation).
+// With a `.socket` unit, systemd owns the sockets: it can bind port 80 for a service without privileges,
+// and connections arriving while the service restarts wait in the socket's backlog instead of being refused.
+// systemd passes the sockets as file descriptors from 3 on and announces them with `LISTEN_FDS` and `LISTEN_PID`,
+// which `listenfd` reads. Without them the server binds its own addresses as usual, see `bind`.
+// actix-server 1 deletes the file of a Unix socket whenever it stops accepting for a moment (backpressure),
+// which happens right at startup when systemd queued connections while the service was starting. The file
+// belongs to systemd and nobody can connect without it, so `KeptPath` puts it back from a hard link.
+
+use listenfd::ListenFd;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "tcp {}", addr),
+                Err(_) => write!(f, "tcp (unknown address)"),
+            },
+            Listener::Unix(listener) => match listener.local_addr().ok().and_then(|addr| addr.as_pathname().map(|path| path.display().to_string())) {
+                Some(path) => write!(f, "unix {}", path),
+                None => write!(f, "unix (unnamed)"),
+            },
+        }
+    }
+}
+
+// The sockets passed by systemd, in the order of the socket unit, none when the process wasn't socket activated.
+// Each one must be a TCP or a Unix stream socket, anything else (UDP, a FIFO) is an error.
+pub fn from_systemd() -> io::Result<Vec<Listener>> {
+    let mut fds = ListenFd::from_env();
+    (0..fds.len())
+        .map(|index| {
+            if let Ok(Some(listener)) = fds.take_tcp_listener(index) {
+                return Ok(Listener::Tcp(listener));
+            }
+            match fds.take_unix_listener(index) {
+                Ok(Some(listener)) => Ok(Listener::Unix(listener)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("socket {} passed by systemd (fd {}) is neither a TCP nor a Unix stream socket", index, index + 3),
+                )),
+            }
+        })
+        .collect()
+}
+
+// Pending connections a socket bound by the server holds, the backlog actix uses for its own binds.
+const BACKLOG: i32 = 1024;
+
+// Binds `addr` (“host:port”, “[::1]:port”), trying each address a host name resolves to until one binds.
+// IPv6 sockets are IPv6-only, so that `[::]:8080` and `0.0.0.0:8080` can both be bound on a dual-stack host
+// (otherwise the first one takes the port for both families and the second fails).
+pub fn bind(addr: &str) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        match bind_socket(socket_addr) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the address resolves to nothing")))
+}
+
+fn bind_socket(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+// The path of a Unix socket passed by systemd, with a hard link to the socket next to it
+// (`.<name>.keep`) to restore it from. On exit the path is put back one last time, actix deleted it when stopping
+// and systemd keeps the socket for the next start, and the link is removed.
+pub struct KeptPath {
+    path: PathBuf,
+    keep: PathBuf,
+}
+
+impl KeptPath {
+    // `None` for a socket without a path (abstract or unnamed), actix has nothing to delete then,
+    // and when the link can't be made, which is only logged: the socket works until its path is deleted.
+    pub fn new(listener: &UnixListener) -> Option<KeptPath> {
+        let path = listener.local_addr().ok()?.as_pathname()?.to_path_buf();
+        let name = path.file_name()?.to_string_lossy().into_owned();
+        let keep = path.with_file_name(format!(".{}.keep", name));
+
+        // Left behind by a previous run that didn't exit cleanly, it may point to an older socket
+        let _ = fs::remove_file(&keep);
+        match fs::hard_link(&path, &keep) {
+            Ok(()) => Some(KeptPath { path, keep }),
+            Err(e) => {
+                eprintln!("Socket {} can't be kept, it is gone once actix deletes it: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    // Puts the path of the socket back if it is gone.
+    pub fn restore(&self) {
+        if fs::symlink_metadata(&self.path).is_err() {
+            match fs::hard_link(&self.keep, &self.path) {
+                Ok(()) => println!("Restored socket {}, deleted by actix", self.path.display()),
+                Err(e) => eprintln!("Failed to restore socket {}: {}", self.path.display(), e),
+            }
+        }
+    }
+}
+
+impl Drop for KeptPath {
+    fn drop(&mut self) {
+        self.restore();
+        let _ = fs::remove_file(&self.keep);
+    }
+}
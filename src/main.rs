@@ -1,69 +1,1073 @@
 // If you get a error at first time running this project - Install libsqlite3-dev and sqlite3
 // sudo apt-get install sqlite3 libsqlite3-dev
 
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use rusqlite::{params, Connection};
+mod admin;
+mod announcements;
+mod api_tokens;
+mod assets;
+mod bans;
+mod backup;
+mod body;
+mod cache;
+mod captcha;
+mod client;
+mod collections;
+mod comments;
+mod config;
+mod creator;
+mod day_counts;
+mod db_check;
+mod disk;
+mod doctor;
+mod error;
+mod export_static;
+mod federation;
+mod filename;
+mod i18n;
+mod idempotency;
+mod images;
+mod integrity;
+mod gist;
+mod handoff;
+mod listen;
+mod negotiate;
+mod openapi;
+mod redirect;
+mod range;
+mod reserved;
+mod review;
+mod search;
+mod store;
+mod tags;
+mod telemetry;
+mod text;
+mod timestamp;
+mod title;
+mod token;
+mod version;
+mod wrap;
+mod zip;
+
+#[cfg(test)]
+mod tests;
+
+use actix_web::dev::{Body, Service, ServiceRequest, ServiceResponse, SizedStream};
+use actix_service::ServiceFactory;
+use actix_web::http::{HeaderName, HeaderValue, Method, StatusCode};
+use actix_web::middleware::DefaultHeaders;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::error::BlockingError;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use std::sync::Mutex;
-use actix_files::NamedFile;
-use std::fs;
-
-// This struct holds application state( the database connection ).
-// Mutex ensures that only one thread can access a shared resource
-// It is used for synchronization in concurrent programming.
-// Here it is used to protect access to `Connection` to prevent data races and ensure thread safety.
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use actix_files::Files;
+use cache::{CachedPaste, PasteCache};
+use config::Config;
+use error::AppError;
+use gist::GistClient;
+use pastry_crust::api::{
+    ApiBatchError, ApiBatchResult, ApiCreatedPaste, ApiNewBatch, ApiNewPaste, ApiPaste, Encoding, PasteType,
+};
+use i18n::Texts;
+use token::TokenGenerator;
+use wrap::Wrap;
+use store::timed::{QueryTimings, TimedStore};
+use store::{ContentSize, GistState, HashMatch, NewPaste, PasteStore};
+
+// Bounds for the `days` query parameter of the `/popular` page, and how many pastes it shows.
+const POPULAR_DEFAULT_DAYS: i64 = 7;
+const POPULAR_MAX_DAYS: i64 = store::DAILY_VIEWS_RETENTION_DAYS;
+const POPULAR_LIMIT: i64 = 20;
+
+// How many pastes “/mine” lists, the newest ones.
+const MINE_LIMIT: i64 = 100;
+
+// How many days the per-paste stats page shows.
+const STATS_DAYS: i64 = store::DAILY_VIEWS_RETENTION_DAYS;
+
+// Lengths of the random strings used for paste tokens and for the creator secrets.
+// Cache lifetime of the static files, one year.
+const STATIC_CACHE_CONTROL: &str = "public, max-age=31536000";
+
+// Most pastes one batch of the API may create.
+const BATCH_MAX_PASTES: usize = 100;
+
+// Most pastes one request of the API may fetch.
+const FETCH_MAX_TOKENS: usize = 100;
+
+// Headers of the raw and download responses describing the paste, so mirrors don't need a second request.
+// Listed by “/api/limits”; a header whose value doesn't apply (no expiry, no tags) is left out.
+const METADATA_HEADERS: [&str; 6] = [
+    "X-Paste-Token",
+    "X-Paste-Created-At",
+    "X-Paste-Expires-At",
+    "X-Paste-Views",
+    "X-Paste-Tags",
+    "X-Paste-Content-Hash",
+];
+
+// Shortest start of a content hash “/h/{hash}” takes, and most hashes its 300 page lists when several match.
+const HASH_PREFIX_MIN: usize = 12;
+const HASH_CHOICES_LIMIT: i64 = 20;
+
+// How many tokens are tried before giving up when they are all taken already.
+const TOKEN_ATTEMPTS: usize = 5;
+const SECRET_LEN: usize = 24;
+
+// Defaults of the startup settings, each can be overridden with the environment variable of the same name.
+const PASTRY_BIND: &str = "127.0.0.1:8080";
+const PASTRY_DB_PATH: &str = "pastes.db";
+const PASTRY_DB_OPEN_ATTEMPTS: u32 = 10;
+const PASTRY_DB_OPEN_BACKOFF_MS: u64 = 500;
+const PASTRY_DB_TIMEOUT_MS: u64 = 5000;
+const PASTRY_DB_WRITE_RETRY_MS: u64 = 2000;
+const PASTRY_SLOW_QUERY_MS: u64 = 200;
+const PASTRY_REQUEST_TIMEOUT_SECS: u64 = 30;
+const PASTRY_CLIENT_TIMEOUT_MS: u64 = 5000;
+const PASTRY_BACKUP_DIR: &str = "backups";
+const PASTRY_BACKUP_KEEP: usize = 0;
+const PASTRY_MEMORY_MAX_PASTES: usize = 10_000;
+const PASTRY_MEMORY_MAX_BYTES: usize = 64 * 1024 * 1024;
+const PASTRY_BLOB_DIR: &str = "blobs";
+const PASTRY_BLOB_THRESHOLD: usize = 256 * 1024;
+const PASTRY_MAX_PASTE_BYTES: usize = 8 * 1024 * 1024;
+const PASTRY_MAX_DISPLAY_BYTES: usize = 1024 * 1024;
+const PASTRY_NORMALIZE_LINE_ENDINGS: bool = true;
+const PASTRY_CACHE_MAX_ENTRIES: usize = 1000;
+const PASTRY_CACHE_MAX_BYTES: usize = 32 * 1024 * 1024;
+const PASTRY_CACHE_PRIME: usize = 100;
+const PASTRY_ARCHIVE_AFTER_DAYS: i64 = 0;
+const PASTRY_ARCHIVE_PROMOTE: bool = true;
+const PASTRY_DAILY_PASTE_QUOTA: i64 = 0;
+const PASTRY_DAILY_PREVIEW_QUOTA: i64 = 1000;
+const PASTRY_COMMENTS: bool = true;
+const PASTRY_DAILY_COMMENT_QUOTA: i64 = 50;
+const PASTRY_DAILY_VALIDATE_QUOTA: i64 = 1000;
+const PASTRY_AUDIT_RETENTION_DAYS: i64 = 90;
+const PASTRY_REDIRECT_ALLOW_INTERNAL: bool = false;
+const PASTRY_IMAGE_PROXY_MAX_BYTES: usize = 5 * 1024 * 1024;
+const PASTRY_API_REQUIRE_TOKEN: bool = false;
+const PASTRY_REVIEW_PASTES: bool = false;
+const PASTRY_DISK_WARN_BYTES: u64 = 1024 * 1024 * 1024;
+const PASTRY_DISK_MIN_BYTES: u64 = 256 * 1024 * 1024;
+const PASTRY_DISK_READ_ONLY: bool = true;
+const PASTRY_DISK_CHECK_SECS: u64 = 60;
+const PASTRY_GIST_API_URL: &str = "https://api.github.com";
+const PASTRY_CAPTCHA_FAIL_OPEN: bool = false;
+
+// How many pastes are moved to the archive per transaction, so the store isn't locked for long.
+const ARCHIVE_BATCH: usize = 200;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// How long a mirror to a gist may stay pending before asking again starts it over,
+// for a server stopped while the call to GitHub was under way.
+const GIST_PENDING_STALE_SECS: i64 = 10 * 60;
+
+// How often the background cleanup task runs.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// How often the paths of the Unix sockets passed by systemd are checked, see `listen::KeptPath`.
+const SOCKET_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// This struct holds application state( the paste store ).
+// The store is a trait object, so the handlers work the same whichever database is behind it,
+// each implementation takes care of its own synchronization. It is shared with the blocking pool, where the handlers
+// make their writes (see `store_write`).
+// Next to it live the settings read at startup that handlers need.
 struct AppState {
-    db: Mutex<Connection>,
+    store: Arc<dyn PasteStore>,
+    // Token expected in `Authorization: Bearer <token>` on the admin routes, which are disabled when unset
+    admin_token: Option<String>,
+    backup_dir: PathBuf,
+    // Held while a backup runs so two backups never run at once
+    backup_lock: Mutex<()>,
+    // What a reload of the configuration file changes, read through `settings`
+    settings: RwLock<Arc<Settings>>,
+    cache: PasteCache,
+    // Time taken by the database calls, per statement, for “/metrics”
+    query_timings: Arc<QueryTimings>,
+    // Mixed into the hashes the quota stores instead of IPs
+    ip_salt: String,
+    // Whether `ip_salt` was configured: a random one changes on every restart, and the hashes with it
+    ip_salt_configured: bool,
+    // Signs the creator cookies, see `creator.rs`
+    cookie_secret: String,
+    // What clients used of `Quota::Validations` today, which isn't counted in the database
+    validations: Mutex<day_counts::DayCounts>,
+    // When the uses of each API token were last written, see `api_tokens::LastUsed`
+    token_uses: Mutex<api_tokens::LastUsed>,
+    // The answers of the requests that carried an idempotency key or a form nonce, see `idempotency.rs`
+    idempotency: Mutex<idempotency::Keys>,
+    // The bans requests that write are checked against, see `bans.rs`
+    bans: RwLock<bans::BanList>,
+    // The announcements the pages show, see `announcements.rs`
+    announcements: RwLock<announcements::Board>,
+    // Proxies whose `X-Forwarded-For` tells the client IP, see `client::client_ip`
+    trusted_proxies: Vec<IpAddr>,
+    // Set with `PASTRY_GITHUB_TOKEN`, mirroring pastes to gists is off without it
+    gist: Option<GistClient>,
+    // Set with `PASTRY_CAPTCHA_SITE_KEY` and `PASTRY_CAPTCHA_SECRET`, the form has no captcha without them
+    captcha: Option<captcha::Captcha>,
+    // Set with `PASTRY_FEDERATION_TOKEN`, other instances can't send their stats here without it
+    federation_token: Option<String>,
+    // The free space of the volumes written to, see `disk.rs`
+    disk: disk::Monitor,
+    // The one-time keys of the page a new paste's creator lands on, see `handoff.rs`
+    handoffs: Mutex<handoff::Handoffs>,
+    // Set when the database failed its check at startup with `PASTRY_DB_CHECK_FAILURE=read-only`, see `db_check.rs`
+    database_read_only: bool,
+}
+
+impl AppState {
+    // The settings in effect, a request keeps the set it got even when a reload happens meanwhile.
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+}
+
+// The settings that can change while the server runs, on a reload of the configuration file (see `config.rs`).
+struct Settings {
+    // How many backups to keep, 0 keeps all of them
+    backup_keep: usize,
+    // Bigger pastes are not rendered on their page, only served raw
+    max_display_bytes: usize,
+    // Bigger pastes are refused, whether text or binary
+    max_paste_bytes: usize,
+    token_generator: TokenGenerator,
+    // Whether "\r\n" and "\r" in new text pastes become "\n", unless a request says otherwise
+    normalize_line_endings: bool,
+    // Pastes without a view for this many days are moved to the archive, 0 never archives
+    archive_after_days: i64,
+    // Whether an archived paste goes back to the hot table when it is viewed
+    archive_promote: bool,
+    // How many pastes one client may create per day (UTC), 0 for no limit
+    daily_paste_quota: i64,
+    // How many previews one client may have rendered per day (UTC), 0 for no limit
+    daily_preview_quota: i64,
+    // Whether pastes show and take comments, see `comments.rs`
+    comments: bool,
+    // How many comments one client may leave per day (UTC), 0 for no limit
+    daily_comment_quota: i64,
+    // How many pastes one client may have checked by “/api/pastes/validate” per day (UTC), 0 for no limit
+    daily_validate_quota: i64,
+    // Entries of the audit log older than this many days are deleted by the cleanup, 0 keeps them all
+    audit_retention_days: i64,
+    // Whether short links may point to localhost and private networks
+    redirect_allow_internal: bool,
+    // What following a short link does, see `redirect::Mode`
+    redirect_mode: redirect::Mode,
+    // Whether the images the URLs of a paste point to are shown on its page, see `images.rs`
+    image_policy: images::Policy,
+    // What “/imgproxy” fetches, with `images::Policy::Proxy`
+    image_proxy: images::Limits,
+    // Whether the API answers 401 to requests without a token, see `api_tokens.rs`
+    api_require_token: bool,
+    // The name downloads are saved as, see `filename.rs`
+    download_filename: filename::Template,
+    // Whether new pastes wait for an admin to approve them, see `review.rs`
+    review_pastes: bool,
+    // The free space below which a warning is logged and the instance turns read-only, see `disk.rs`
+    disk: disk::Limits,
+}
+
+impl Settings {
+    fn resolve(config: &Config) -> Settings {
+        Settings {
+            backup_keep: setting(&config.backup_keep, "PASTRY_BACKUP_KEEP", PASTRY_BACKUP_KEEP),
+            max_display_bytes: setting(&config.max_display_bytes, "PASTRY_MAX_DISPLAY_BYTES", PASTRY_MAX_DISPLAY_BYTES),
+            max_paste_bytes: setting(&config.max_paste_bytes, "PASTRY_MAX_PASTE_BYTES", PASTRY_MAX_PASTE_BYTES),
+            normalize_line_endings: setting(
+                &config.normalize_line_endings,
+                "PASTRY_NORMALIZE_LINE_ENDINGS",
+                PASTRY_NORMALIZE_LINE_ENDINGS,
+            ),
+            // Checked when the file was loaded
+            token_generator: match config.token_strategy.as_deref().map(str::parse) {
+                Some(Ok(generator)) => generator,
+                _ => env_or("PASTRY_TOKEN_STRATEGY", TokenGenerator::Alphanumeric),
+            },
+            archive_after_days: setting(&config.archive_after_days, "PASTRY_ARCHIVE_AFTER_DAYS", PASTRY_ARCHIVE_AFTER_DAYS).max(0),
+            archive_promote: setting(&config.archive_promote, "PASTRY_ARCHIVE_PROMOTE", PASTRY_ARCHIVE_PROMOTE),
+            daily_paste_quota: setting(&config.daily_paste_quota, "PASTRY_DAILY_PASTE_QUOTA", PASTRY_DAILY_PASTE_QUOTA).max(0),
+            daily_preview_quota: setting(&config.daily_preview_quota, "PASTRY_DAILY_PREVIEW_QUOTA", PASTRY_DAILY_PREVIEW_QUOTA).max(0),
+            comments: setting(&config.comments, "PASTRY_COMMENTS", PASTRY_COMMENTS),
+            daily_comment_quota: setting(&config.daily_comment_quota, "PASTRY_DAILY_COMMENT_QUOTA", PASTRY_DAILY_COMMENT_QUOTA).max(0),
+            daily_validate_quota: setting(&config.daily_validate_quota, "PASTRY_DAILY_VALIDATE_QUOTA", PASTRY_DAILY_VALIDATE_QUOTA).max(0),
+            audit_retention_days: setting(&config.audit_retention_days, "PASTRY_AUDIT_RETENTION_DAYS", PASTRY_AUDIT_RETENTION_DAYS).max(0),
+            redirect_allow_internal: setting(
+                &config.redirect_allow_internal,
+                "PASTRY_REDIRECT_ALLOW_INTERNAL",
+                PASTRY_REDIRECT_ALLOW_INTERNAL,
+            ),
+            // Checked when the file was loaded
+            redirect_mode: match config.redirect_mode.as_deref().map(str::parse) {
+                Some(Ok(mode)) => mode,
+                _ => env_or("PASTRY_REDIRECT_MODE", redirect::Mode::default()),
+            },
+            // Checked when the file was loaded
+            image_policy: match config.image_policy.as_deref().map(str::parse) {
+                Some(Ok(policy)) => policy,
+                _ => env_or("PASTRY_IMAGE_POLICY", images::Policy::default()),
+            },
+            image_proxy: images::Limits {
+                allowlist: images::parse_allowlist(&match &config.image_proxy_allowlist {
+                    Some(hosts) => hosts.join(","),
+                    None => std::env::var("PASTRY_IMAGE_PROXY_ALLOWLIST").unwrap_or_default(),
+                }),
+                max_bytes: setting(&config.image_proxy_max_bytes, "PASTRY_IMAGE_PROXY_MAX_BYTES", PASTRY_IMAGE_PROXY_MAX_BYTES),
+            },
+            api_require_token: setting(&config.api_require_token, "PASTRY_API_REQUIRE_TOKEN", PASTRY_API_REQUIRE_TOKEN),
+            // Checked when the file was loaded
+            download_filename: match config.download_filename.as_deref().map(str::parse) {
+                Some(Ok(template)) => template,
+                _ => env_or("PASTRY_DOWNLOAD_FILENAME", filename::Template::default()),
+            },
+            review_pastes: setting(&config.review_pastes, "PASTRY_REVIEW_PASTES", PASTRY_REVIEW_PASTES),
+            disk: disk::Limits {
+                warn_bytes: setting(&config.disk_warn_bytes, "PASTRY_DISK_WARN_BYTES", PASTRY_DISK_WARN_BYTES),
+                min_bytes: setting(&config.disk_min_bytes, "PASTRY_DISK_MIN_BYTES", PASTRY_DISK_MIN_BYTES),
+                read_only: setting(&config.disk_read_only, "PASTRY_DISK_READ_ONLY", PASTRY_DISK_READ_ONLY),
+            },
+        }
+    }
+}
+
+// The “/static” routes. With an assets directory its files are served by `Files`
+// (which never lists directories and keeps `..` from leaving the directory),
+// falling back to the embedded files for everything it doesn't have.
+// Pages link the assets with their version in the URL, so browsers may keep them for a long time.
+fn static_routes(cfg: &mut web::ServiceConfig, assets_dir: Option<&Path>) {
+    let scope = web::scope(reserved::route("/static")).wrap(DefaultHeaders::new().header("Cache-Control", STATIC_CACHE_CONTROL));
+    let scope = match assets_dir {
+        Some(dir) => scope.service(
+            Files::new("/", dir)
+                .disable_content_disposition()
+                .default_handler(web::route().to(static_asset)),
+        ),
+        None => scope.default_service(web::route().to(static_asset)),
+    };
+    cfg.service(scope);
+}
+
+// Handles “/static/{path}” with the files embedded by `assets`.
+// Their ETag is their version, a browser that already has the file gets a 304.
+async fn static_asset(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    let path = req.path().trim_start_matches("/static/");
+    let asset = assets::get(path).ok_or_else(|| AppError::not_found("No such file"))?;
+    let etag = format!("\"{}\"", assets::version(asset.path).unwrap_or_default());
+
+    let cached = req
+        .headers()
+        .get("If-None-Match")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(',').any(|tag| tag.trim() == etag))
+        .unwrap_or(false);
+    if cached {
+        return Ok(HttpResponse::NotModified().header("ETag", etag).finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(asset.content_type)
+        .header("ETag", etag)
+        .body(asset.bytes))
 }
 
 // This async function handles the root (”/”) page of the website.
 // Just returns the “index.html” page using the macro that returns the the whole file a string
-async fn index() -> impl Responder {
-    HttpResponse::Ok().body(include_str!("index.html"))
+// with the build information filled into its footer.
+async fn index(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    index_page(&req, &data, &FormValues::default())
+}
+
+// What the form of the index page starts with: nothing, or the paste “/new?from={token}” starts from.
+#[derive(Default)]
+struct FormValues {
+    content: String,
+    // The title given, empty for one made from the content
+    title: String,
+    tags: String,
+    shorten: bool,
+    // Says which paste the form was filled from, empty on a blank form
+    notice: String,
+}
+
+fn index_page(req: &HttpRequest, data: &AppState, values: &FormValues) -> HttpResponse {
+    let texts = Texts::of(req);
+    let popular = format!("<a href=\"/popular\" class=\"underline\">{}</a>", escape_html(texts.text("index.popular")));
+    let _render = telemetry::template("index.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("index.html")))
+        .replace("{{version}}", &escape_html(&version::footer()))
+        .replace("{{public_label}}", &texts.html("index.public", &[("popular", &popular)]))
+        .replace("{{language}}", &texts.switcher(&back_path(req)))
+        .replace("{{template_notice}}", &values.notice)
+        .replace("{{captcha}}", &data.captcha.as_ref().map(captcha::Captcha::widget).unwrap_or_default())
+        .replace("{{title}}", &escape_html(&values.title))
+        .replace("{{tags}}", &escape_html(&values.tags))
+        .replace("{{nonce}}", &random_string(idempotency::NONCE_LEN))
+        .replace("{{shorten}}", if values.shorten { " checked" } else { "" })
+        .replace("{{shorten_hidden}}", if data.settings().redirect_mode == redirect::Mode::Disabled { " hidden" } else { "" })
+        .replace("{{announcements}}", &data.announcements.read().unwrap().banners(req, store::now(), texts))
+        .replace("{{content}}", &escape_html(&values.content));
+
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page)
+}
+
+// Handles “/new?from={token}”, the index page with its form filled from an existing paste: its content, its tags,
+// and for a short link its URL with “Shorten” ticked. Whoever can read the paste can start from it, and submitting
+// creates a new paste like any other, the source is only read (without counting a view).
+// Visibility, expiry and “List publicly” start from their defaults, not from the source.
+// An expired source is a 410 and an unknown one a 404 rather than an empty form; binary pastes can't go in a textarea.
+async fn new_paste(req: HttpRequest, query: web::Query<NewQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let token = match &query.from {
+        Some(token) => token,
+        None => return Ok(index_page(&req, &data, &FormValues::default())),
+    };
+    let CachedPaste { paste, content, tags } = match load_paste(&data, token, false, Reader::Request(&req)).await? {
+        Some(found) => found,
+        None if data.store.exists(token)? => {
+            return Err(AppError::new(
+                error::ErrorCode::Gone,
+                "This paste has expired, there is nothing left to start from",
+            ))
+        }
+        None => return Err(AppError::not_found("Paste not found, there is nothing to start from")),
+    };
+    if matches!(content, store::Content::Binary(_)) {
+        return Err(AppError::bad_request("Binary pastes can't be edited in the form, download them instead"));
+    }
+
+    let from = format!("<a href=\"/paste/{token}\">{token}</a>", token = escape_html(&paste.token));
+    Ok(index_page(&req, &data, &FormValues {
+        content: content.into_string().map_err(store::StoreError::from)?,
+        title: if paste.title_auto { String::new() } else { paste.title.clone() },
+        tags: tags.join(", "),
+        shorten: paste.redirect,
+        notice: format!("<p class=\"meta mb-4\">{}</p>", Texts::of(&req).html("index.from", &[("paste", &from)])),
+    }))
+}
+
+// Handles “/version” and “/api/version”, the build information of the running binary as JSON,
+// along with the token strategy in use.
+async fn version_info(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(VersionInfo {
+        build: version::build_info(),
+        token_strategy: data.settings().token_generator.name(),
+    })
+}
+
+// Handles “/healthz”, for load balancers and monitoring.
+// Answers 200 when the database can be queried and 503 otherwise, along with the build information.
+// A database a query found corrupt stays a 503 until the restart, see `store::timed`.
+async fn healthz(data: web::Data<AppState>) -> impl Responder {
+    let database = match data.store.ping() {
+        Ok(()) => "ok",
+        Err(e) if e.is_corrupt() => "corrupt",
+        Err(_) => "unavailable",
+    };
+    let database_ok = database == "ok";
+
+    let health = Health {
+        status: if database_ok { "ok" } else { "error" },
+        database,
+        build: version::build_info(),
+    };
+
+    if database_ok {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
 }
 
 // This function is asynchronous handler for processing form submissions
-// `token` is the variable that generates a random string
-// `conn` locks the connection to DB using single thread only, to avoid races
-// then executes the INSERT command in ‘pastes’ table with `token` and content
-// Then it redirects to "/paste/token”.
-async fn submit(content: web::Form<FormData>, data: web::Data<AppState>) -> impl Responder {
-    let token: String = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(10)
-        .map(char::from)
-        .collect();
+// The paste is created by `create_paste` from the content, the public flag, its tags and expiry.
+// Then it redirects to “/paste/{token}/created?key=…”, the page showing the creator its secret once, see `handoff.rs`.
+async fn submit(req: HttpRequest, body: Result<web::Bytes, actix_web::Error>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let body = form_body(&req, body, &data)?;
+    let content = parse_form(&body);
+    // A form sent again gets the redirect of the first time, and a replay isn't counted against the quota
+    let nonce = content.as_ref().ok().and_then(|content| content.nonce.clone()).filter(|nonce| !nonce.is_empty());
+    let claim = match nonce {
+        Some(nonce) => {
+            let fingerprint = idempotency::fingerprint(req.path(), &body);
+            match idempotency::claim(&data.idempotency, format!("form:{}", nonce), fingerprint, store::now()) {
+                Ok(idempotency::Claimed::New(claim)) => Some(claim),
+                Ok(idempotency::Claimed::Replay(answer)) => return Ok(answer.response(true)),
+                Err(idempotency::Refused::Mismatch) => {
+                    return Err(AppError::new(
+                        error::ErrorCode::Conflict,
+                        "This form was already submitted with other content, reload the page to submit it again",
+                    ))
+                }
+                Err(idempotency::Refused::InProgress) => {
+                    return Err(AppError::new(error::ErrorCode::Conflict, "This form is already being submitted"))
+                }
+            }
+        }
+        None => None,
+    };
+    check_writable(&data)?;
+    check_daily_quota(&req, &data, Quota::Pastes, 1).await?;
+    let content = content?;
+    if let Some(refused) = check_captcha(&req, &data, &content).await {
+        return Ok(refused);
+    }
+
+    let shorten = content.shorten.is_some();
+    let creator_id = creator::from_request(&req, &data.cookie_secret).unwrap_or_else(creator::new_id);
+    let paste = prepare_paste(
+        &data,
+        None,
+        if shorten {
+            PasteBody::Redirect(content.content)
+        } else {
+            PasteBody::Text(content.content)
+        },
+        content.public.is_some(),
+        content.tags.as_deref().unwrap_or(""),
+        content.expires.as_deref().unwrap_or(""),
+        data.settings().normalize_line_endings,
+    )?;
+    let paste = with_title(paste, content.title.as_deref())?;
+    let paste = for_review(
+        &req,
+        &data,
+        NewPaste {
+            creator: Some(creator::hashed(&creator_id)),
+            comments: content.no_comments.is_none(),
+            ..paste
+        },
+    );
+    let stored = paste.clone();
+    store_write(&data, move |store| store.insert(&stored)).await?;
+
+    // The secret is only shown by the page, never put in a URL
+    let key = random_string(handoff::KEY_LEN);
+    data.handoffs.lock().unwrap().give(key.clone(), &paste.token, &paste.secret, store::now());
+    let secure = req.connection_info().scheme() == "https";
+    let answer = idempotency::Answer {
+        status: StatusCode::SEE_OTHER,
+        location: Some(format!("/paste/{}/created?key={}", paste.token, key)),
+        set_cookie: Some(creator::set_cookie(&creator_id, &data.cookie_secret, secure)),
+        body: None,
+    };
+    Ok(idempotency::respond(claim, answer))
+}
+
+// Has the provider verify the captcha of a form, when the instance has one, see `captcha.rs`.
+// A form that fails it gets the index page back, filled with what was sent and saying why: a 400 for a captcha
+// not solved, a 503 while the provider can't be reached (unless `PASTRY_CAPTCHA_FAIL_OPEN` lets it through).
+async fn check_captcha(req: &HttpRequest, data: &AppState, form: &FormData) -> Option<HttpResponse> {
+    let captcha = data.captcha.as_ref()?;
+    let response = match captcha.provider {
+        captcha::Provider::HCaptcha => form.hcaptcha_response.as_deref(),
+        captcha::Provider::Turnstile => form.turnstile_response.as_deref(),
+    };
+    let (status, message) = match captcha.verify(response.unwrap_or(""), client::client_ip(req, &data.trusted_proxies)).await {
+        captcha::Verdict::Passed => return None,
+        captcha::Verdict::Failed(message) => (StatusCode::BAD_REQUEST, message),
+        captcha::Verdict::Unavailable(reason) if captcha.fail_open => {
+            eprintln!("Captcha not verified, letting the paste through: {}", reason);
+            return None;
+        }
+        captcha::Verdict::Unavailable(reason) => {
+            eprintln!("Captcha not verified, refusing the paste: {}", reason);
+            (StatusCode::SERVICE_UNAVAILABLE, "The captcha can't be checked right now, try again in a moment".to_string())
+        }
+    };
+
+    let mut page = index_page(req, data, &FormValues {
+        content: form.content.clone(),
+        title: form.title.clone().unwrap_or_default(),
+        tags: form.tags.clone().unwrap_or_default(),
+        shorten: form.shorten.is_some(),
+        notice: format!("<p class=\"meta mb-4\">{}</p>", escape_html(&message)),
+    });
+    *page.status_mut() = status;
+    Some(page)
+}
+
+// The daily quotas of a client, each counted on its own in the rows of `PasteStore::record_creation`.
+#[derive(Clone, Copy)]
+enum Quota {
+    // Pastes created, through the form and the API
+    Pastes,
+    // Renderings of “/preview”
+    Previews,
+    // Comments left on pastes
+    Comments,
+    // Pastes checked by “/api/pastes/validate”, counted in memory (`AppState::validations`) rather than in the store
+    Validations,
+}
+
+impl Quota {
+    // 0 for no limit
+    fn limit(self, settings: &Settings) -> i64 {
+        match self {
+            Quota::Pastes => settings.daily_paste_quota,
+            Quota::Previews => settings.daily_preview_quota,
+            Quota::Comments => settings.daily_comment_quota,
+            Quota::Validations => settings.daily_validate_quota,
+        }
+    }
+
+    // Who the store counts for a client, whose pastes keep the bare hash they always had.
+    fn bucket(self, client: String) -> String {
+        match self {
+            Quota::Pastes => client,
+            Quota::Previews => format!("preview:{}", client),
+            Quota::Comments => format!("comment:{}", client),
+            Quota::Validations => format!("validate:{}", client),
+        }
+    }
+
+    fn noun(self) -> &'static str {
+        match self {
+            Quota::Pastes => "pastes",
+            Quota::Previews => "previews",
+            Quota::Comments => "comments",
+            Quota::Validations => "validations",
+        }
+    }
+}
 
-    let conn = data.db.lock().unwrap();
-    conn.execute(
-        "INSERT INTO pastes (token, content) VALUES (?, ?)",
-        params![&token, &content.content],
+// Handles “POST /preview”, the content of the paste the form would create as its page will show it, storing nothing.
+// It takes the same fields as `submit` and refuses what `submit` would refuse, with the same error; otherwise the answer
+// is the fragment `paste_fragment` makes for the page (`?links=true` as on the page), a short link giving its URL.
+// Rendering is real work, previews count against a daily quota of their own (`PASTRY_DAILY_PREVIEW_QUOTA`).
+async fn preview(
+    req: HttpRequest,
+    query: web::Query<PasteQuery>,
+    body: Result<web::Bytes, actix_web::Error>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let form = read_form(&req, body, &data, Quota::Previews).await?;
+    let body = if form.shorten.is_some() {
+        PasteBody::Redirect(form.content)
+    } else {
+        PasteBody::Text(form.content)
+    };
+    let checks = check_paste(
+        &data,
+        Unchecked::Body(body),
+        form.tags.as_deref().unwrap_or(""),
+        form.expires.as_deref().unwrap_or(""),
+        data.settings().normalize_line_endings,
+    );
+    let body = checks.into_body()?;
+
+    let fragment = match body {
+        PasteBody::Redirect(url) => format!("<p>Redirects to <a href=\"{url}\">{url}</a></p>", url = escape_html(&url)),
+        PasteBody::Text(text) => {
+            let size = ContentSize::of_text(&text);
+            let links = query.links.unwrap_or(false);
+            paste_fragment(&data, None, store::Content::Inline(text), &size, links, Wrap::from_request(&req), Texts::of(&req))?
+        }
+        PasteBody::Binary(_) => unreachable!("the form only sends text"),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(fragment))
+}
+
+// The fields of the form of the index page, for `submit` and `preview`, once counted against the `kind` quota.
+// The body is decoded by hand rather than with `web::Form`, to refuse it when it isn't valid UTF-8.
+async fn read_form(req: &HttpRequest, body: Result<web::Bytes, actix_web::Error>, data: &AppState, kind: Quota) -> Result<FormData, AppError> {
+    let body = form_body(req, body, data)?;
+    check_daily_quota(req, data, kind, 1).await?;
+    parse_form(&body)
+}
+
+// The body of a form, decompressed, held to the limit of the settings.
+fn form_body(req: &HttpRequest, body: Result<web::Bytes, actix_web::Error>, data: &AppState) -> Result<web::Bytes, AppError> {
+    let limit = max_form_bytes(data.settings().max_paste_bytes);
+    body::decode(req, body::received(body, limit)?, limit)
+}
+
+fn parse_form(body: &[u8]) -> Result<FormData, AppError> {
+    text::check_urlencoded_utf8(body).map_err(AppError::bad_request)?;
+    serde_urlencoded::from_bytes(body).map_err(|e| AppError::bad_request(e.to_string()))
+}
+
+// A 507 while the instance is read-only for lack of disk space, for the requests that add data, see `disk.rs`.
+// Before the quota, which would count an attempt that can't succeed.
+fn check_writable(data: &AppState) -> Result<(), AppError> {
+    if data.database_read_only {
+        return Err(AppError::new(error::ErrorCode::Unavailable, db_check::READ_ONLY));
+    }
+    if data.disk.read_only() {
+        return Err(AppError::new(error::ErrorCode::InsufficientStorage, disk::READ_ONLY));
+    }
+    Ok(())
+}
+
+// The daily quota of `kind` a request counts against, 0 for none, and who the store counts it for: the pastes of an
+// API token with a `daily_quota` count against it (`authorize` left the token in the request), anything else against
+// the quota of the address of the client.
+fn daily_quota(req: &HttpRequest, data: &AppState, kind: Quota) -> (i64, String) {
+    let token = req.extensions().get::<store::ApiToken>().and_then(|token| Some((token.id, token.daily_quota?)));
+    match (kind, token) {
+        (Quota::Pastes, Some((id, quota))) => (quota, format!("token:{}", id)),
+        _ => (
+            kind.limit(&data.settings()),
+            kind.bucket(client::hashed_ip(client::client_ip(req, &data.trusted_proxies), &data.ip_salt)),
+        ),
+    }
+}
+
+// Counts `count` uses of `kind` against the daily quota of the client, a 429 once it is used up.
+// Every attempt counts, whether the pastes end up created or not.
+// The count is left in the request as a `QuotaState` for the rate limit headers of the API.
+async fn check_daily_quota(req: &HttpRequest, data: &AppState, kind: Quota, count: i64) -> Result<(), AppError> {
+    let (quota, client) = daily_quota(req, data, kind);
+    if quota == 0 {
+        return Ok(());
+    }
+
+    let used = match kind {
+        Quota::Validations => data.validations.lock().unwrap().record(&client, count, store::now()),
+        _ => store_write(data, move |store| store.record_creation(&client, count)).await?,
+    };
+    let resets_at = quota_reset();
+    req.extensions_mut().insert(QuotaState {
+        limit: quota,
+        used,
+        resets_at,
+    });
+    if used <= quota {
+        return Ok(());
+    }
+    Err(quota_reached(kind, quota, resets_at))
+}
+
+// The 429 of a client past the `limit` of `kind`.
+fn quota_reached(kind: Quota, limit: i64, resets_at: i64) -> AppError {
+    AppError::new(
+        error::ErrorCode::RateLimited,
+        format!(
+            "You reached the limit of {} {} per day, it resets at {}",
+            limit,
+            kind.noun(),
+            timestamp::absolute(resets_at)
+        ),
     )
-    .expect("Failed to insert into database");
+}
+
+// What a new paste holds: text, the raw bytes of a binary paste, or the URL of a short link.
+enum PasteBody {
+    Text(String),
+    Binary(Vec<u8>),
+    Redirect(String),
+}
+
+// Checks a new paste and makes the row to store for it, without storing it yet; shared by the form and the API.
+// The checks of `check_paste` come first, the first one failing is answered with its error.
+// Its token is `token` when the client picked one, a random free one otherwise;
+// `secret` is another, longer random string only the creator gets to see.
+// Its title is the one `title::derive` makes, for text pastes: there is none until `with_title` gives one.
+// The paste takes comments, has no creator and isn't held for review, the callers change that.
+fn prepare_paste(
+    data: &AppState,
+    token: Option<String>,
+    body: PasteBody,
+    public: bool,
+    tags_input: &str,
+    expires: &str,
+    normalize_line_endings: bool,
+) -> Result<NewPaste, AppError> {
+    let PasteChecks { content, size, tags: paste_tags, expiry } =
+        check_paste(data, Unchecked::Body(body), tags_input, expires, normalize_line_endings);
+    let body = content.expect("checked with its content")?;
+    size?;
+    let paste_tags = paste_tags?;
+    let expires_in = expiry?;
+
+    let token = match token {
+        Some(token) => token,
+        None => free_token(data)?,
+    };
+    let secret = random_string(SECRET_LEN);
+    let redirect = matches!(body, PasteBody::Redirect(_));
+    let (content, binary, size) = match body {
+        PasteBody::Text(content) | PasteBody::Redirect(content) => {
+            let size = ContentSize::of_text(&content);
+            (content, None, size)
+        }
+        PasteBody::Binary(bytes) => {
+            let size = ContentSize::of_binary(&bytes);
+            (String::new(), Some(bytes), size)
+        }
+    };
+    let content_hash = store::content_hash(binary.as_deref().unwrap_or(content.as_bytes()));
+    let title = if binary.is_none() && !redirect { title::derive(&content) } else { None };
+
+    Ok(NewPaste {
+        token,
+        secret,
+        content,
+        public,
+        tags: paste_tags,
+        expires_at: expires_in.map(|seconds| store::now() + seconds),
+        blob: None,
+        data: binary,
+        size,
+        redirect,
+        content_hash,
+        creator: None,
+        comments: true,
+        pending: false,
+        submitter: None,
+        title_auto: title.is_some(),
+        title: title.unwrap_or_default(),
+    })
+}
+
+// `paste` with the title given with it, after `title::check`; the one made from its content when it's empty.
+fn with_title(paste: NewPaste, title: Option<&str>) -> Result<NewPaste, AppError> {
+    let title = title::check(title.unwrap_or("")).map_err(AppError::bad_request)?;
+    if title.is_empty() {
+        return Ok(paste);
+    }
+    Ok(NewPaste {
+        title,
+        title_auto: false,
+        ..paste
+    })
+}
+
+// `paste` held for review when `review_pastes` is on, keeping the address of `req` until it is decided on, see
+// `review.rs`.
+fn for_review(req: &HttpRequest, data: &AppState, paste: NewPaste) -> NewPaste {
+    if !data.settings().review_pastes {
+        return paste;
+    }
+    NewPaste {
+        pending: true,
+        submitter: Some(client::hashed_ip(client::client_ip(req, &data.trusted_proxies), &data.ip_salt)),
+        ..paste
+    }
+}
 
-    HttpResponse::SeeOther()
-        .header("Location", format!("/paste/{}", token))
-        .finish()
+// What the checks of `check_paste` get of the content of a new paste: the content itself, or only its size in bytes
+// for “/api/pastes/validate”, before sending a large paste.
+enum Unchecked {
+    Body(PasteBody),
+    Size(usize),
+}
+
+// The checks every new paste goes through, each on its own: `submit`, `preview` and the API stop at the first one
+// failing, in this order, “/api/pastes/validate” reports all of them. Sharing them keeps its answers those of the
+// real thing.
+struct PasteChecks {
+    // The content checked by `check_content`, `None` when only its size was given
+    content: Option<Result<PasteBody, AppError>>,
+    // The size against `max_paste_bytes`, once the content is cleaned up
+    size: Result<(), AppError>,
+    tags: Result<Vec<String>, AppError>,
+    // Seconds until the paste expires, `None` for never
+    expiry: Result<Option<i64>, AppError>,
+}
+
+impl PasteChecks {
+    // The checked content, or the error of the first check failing.
+    fn into_body(self) -> Result<PasteBody, AppError> {
+        let body = self.content.expect("checked with its content")?;
+        self.size?;
+        self.tags?;
+        self.expiry?;
+        Ok(body)
+    }
+}
+
+fn check_paste(data: &AppState, unchecked: Unchecked, tags_input: &str, expires: &str, normalize_line_endings: bool) -> PasteChecks {
+    let (content, size) = match unchecked {
+        Unchecked::Body(body) => {
+            let sent = body_len(&body);
+            let content = check_content(data, body, normalize_line_endings);
+            let size = content.as_ref().map(body_len).unwrap_or(sent);
+            (Some(content), size)
+        }
+        Unchecked::Size(size) => (None, size),
+    };
+
+    let max_paste_bytes = data.settings().max_paste_bytes;
+    let size = if size > max_paste_bytes {
+        Err(AppError::new(
+            error::ErrorCode::PayloadTooLarge,
+            format!("Pastes can be at most {} bytes", max_paste_bytes),
+        ))
+    } else {
+        Ok(())
+    };
+
+    PasteChecks {
+        content,
+        size,
+        tags: tags::parse_tags(tags_input).map_err(AppError::bad_request),
+        expiry: parse_expiry(expires).map_err(AppError::bad_request),
+    }
+}
+
+// The content of a new paste, checked: text goes through `text::normalize` (line endings only with
+// `normalize_line_endings`), binary content is kept as is, the URL of a short link has to pass `redirect::check_target`
+// and short links have to be on (`redirect::Mode`).
+fn check_content(data: &AppState, body: PasteBody, normalize_line_endings: bool) -> Result<PasteBody, AppError> {
+    Ok(match body {
+        PasteBody::Text(content) => {
+            PasteBody::Text(text::normalize(content, normalize_line_endings).map_err(AppError::bad_request)?)
+        }
+        PasteBody::Redirect(_) if data.settings().redirect_mode == redirect::Mode::Disabled => {
+            return Err(AppError::bad_request(redirect::DISABLED));
+        }
+        PasteBody::Redirect(url) => {
+            PasteBody::Redirect(redirect::check_target(&url, data.settings().redirect_allow_internal).map_err(AppError::bad_request)?)
+        }
+        binary => binary,
+    })
+}
+
+fn body_len(body: &PasteBody) -> usize {
+    match body {
+        PasteBody::Text(content) | PasteBody::Redirect(content) => content.len(),
+        PasteBody::Binary(bytes) => bytes.len(),
+    }
+}
+
+// The values of the expiry field, with their number of seconds, `None` meaning never.
+const EXPIRY_OPTIONS: &[(&str, Option<i64>)] = &[
+    ("never", None),
+    ("10m", Some(10 * 60)),
+    ("1h", Some(60 * 60)),
+    ("1d", Some(24 * 60 * 60)),
+    ("1w", Some(7 * 24 * 60 * 60)),
+    ("30d", Some(30 * 24 * 60 * 60)),
+];
+
+// The daily quota of the client of a request as `check_daily_quota` or `/api/limits` found it.
+// The `/api` scope turns it into the `X-RateLimit-*` headers of the response, see `rate_limit_headers`.
+#[derive(Clone, Copy)]
+struct QuotaState {
+    limit: i64,
+    // Creations counted today, refused ones included
+    used: i64,
+    resets_at: i64,
+}
+
+impl QuotaState {
+    fn remaining(&self) -> i64 {
+        (self.limit - self.used).max(0)
+    }
+}
+
+// When the daily quotas start over, the next midnight UTC.
+fn quota_reset() -> i64 {
+    (store::now() / SECONDS_PER_DAY + 1) * SECONDS_PER_DAY
+}
+
+// Adds the quota the handler checked to an API response: `X-RateLimit-Limit`, `-Remaining` and `-Reset`
+// (a Unix timestamp), and `Retry-After` in seconds on a 429. Responses of requests that didn't look at the quota
+// get none, there is no quota on reading.
+fn rate_limit_headers(mut res: ServiceResponse<Body>) -> ServiceResponse<Body> {
+    let quota = res.request().extensions().get::<QuotaState>().copied();
+    let quota = match quota {
+        Some(quota) => quota,
+        None => return res,
+    };
+    let rate_limited = res.status() == StatusCode::TOO_MANY_REQUESTS;
+
+    let headers = res.headers_mut();
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(quota.limit));
+    headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(quota.remaining()));
+    headers.insert(HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(quota.resets_at));
+    if rate_limited {
+        headers.insert(HeaderName::from_static("retry-after"), HeaderValue::from((quota.resets_at - store::now()).max(1)));
+    }
+    res
+}
+
+// Turns the value of the expiry field of the form into a number of seconds, `None` meaning never.
+fn parse_expiry(value: &str) -> Result<Option<i64>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    EXPIRY_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, seconds)| *seconds)
+        .ok_or_else(|| format!("Unknown expiry \"{}\"", value))
+}
+
+// A new token from the configured strategy that no paste uses yet and isn't reserved.
+fn free_token(data: &AppState) -> Result<String, AppError> {
+    for _ in 0..TOKEN_ATTEMPTS {
+        let token = data.settings().token_generator.generate();
+        if !reserved::is_reserved(&token) && !data.store.exists(&token)? {
+            return Ok(token);
+        }
+    }
+    Err(AppError::internal("Could not find a free token, try again"))
+}
+
+// Generates a random alphanumeric string of `len` characters.
+fn random_string(len: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
 }
 
 // Above function handle the “/paste”,  
-// `conn` locks the connection to DB.
-// `content` gets the data from the pastes table using a token, gets the content.
+// `content` gets the paste using a token through `view_paste`, which also counts the view (total and per-day).
+// A found paste gets its tags rendered as links.
+// When the `key` query parameter matches the paste's secret the creator also gets the links to its private pages.
+// With `links=true` the URLs in the text become links; it is off by default, code shouldn't get any.
+// An unknown or expired token gets the 404 error page, a short link is a 302 to its URL unless `redirect::Mode` says otherwise.
 // Returns the data in `<pre>` tag
-async fn get_paste(content: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, actix_web::Error> {
-    let conn = data.db.lock().unwrap();
-
-    let paste_content = conn
-        .query_row(
-            "SELECT content FROM pastes WHERE token = ?",
-            params![content.to_string()],
-            |row| row.get::<_, String>(0),
-        )
-        .unwrap_or_else(|_| "Paste not found".to_string());
+// The same URL gives the text alone or the JSON of “/api/pastes/{token}” to the clients asking for them,
+// see `negotiate.rs`, with `Vary` telling caches what the answer depends on (the creator cookie shows the creator's links).
+async fn get_paste(req: HttpRequest, content: web::Path<String>, query: web::Query<PasteQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let header = |name| req.headers().get(name).and_then(|value: &HeaderValue| value.to_str().ok());
+    let (token, representation) = negotiate::negotiate(&content, header("Accept"), header("User-Agent"));
+    let mut response = match representation {
+        negotiate::Representation::Html => paste_page(&req, token, &query, &data).await?,
+        negotiate::Representation::Text => serve_content(&req, &data, token, DispositionType::Inline).await?,
+        negotiate::Representation::Json => {
+            match view_paste(&req, &data, token).await.and_then(|cached| api_paste(cached, true, true)) {
+                Ok(paste) => HttpResponse::Ok().json(paste),
+                Err(e) => error::ApiError(e).error_response(),
+            }
+        }
+    };
+    response
+        .headers_mut()
+        .insert(HeaderName::from_static("vary"), HeaderValue::from_static("Accept, Accept-Language, User-Agent, Cookie"));
+    Ok(response)
+}
+
+// The HTML page of `get_paste`.
+async fn paste_page(req: &HttpRequest, token: &str, query: &PasteQuery, data: &AppState) -> Result<HttpResponse, AppError> {
+    let cached = view_paste(req, data, token).await?;
 
-    let html_page = include_str!("view_paste.html");
-    let html_page = &html_page.replace("{{paste_content}}", &paste_content);
+    if cached.paste.redirect {
+        let target = cached.content.into_string().map_err(store::StoreError::from)?;
+        return match data.settings().redirect_mode {
+            redirect::Mode::Direct => Ok(HttpResponse::Found().header("Location", target).finish()),
+            redirect::Mode::Interstitial => Ok(redirect_interstitial(req, &target)),
+            redirect::Mode::Disabled => Err(AppError::forbidden(redirect::DISABLED)),
+        };
+    }
+
+    let html_page = paste_html(data, cached, PastePage::Live { req, query })?;
 
     // Return the HTML page as an HTTP response
     Ok(HttpResponse::Ok()
@@ -71,44 +1075,3361 @@ async fn get_paste(content: web::Path<String>, data: web::Data<AppState>) -> Res
         .body(html_page))
 }
 
+// Who the page of a paste is rendered for. A visitor of the live server gets the toggles, creator links and comments
+// of their request. A snapshot of `export-static` (see `export_static.rs`) is a file that has to read the same on every
+// export: absolute times, the view count as stored, and no links but `raw`, the raw file written next to it.
+enum PastePage<'a> {
+    Live { req: &'a HttpRequest, query: &'a PasteQuery },
+    Snapshot { raw: &'a str },
+}
+
+// The page of a paste that isn't a short link. A snapshot links the assets as “/static/…” without versions,
+// for `export_static` to make them relative.
+fn paste_html(data: &AppState, cached: CachedPaste, page: PastePage) -> Result<String, AppError> {
+    let CachedPaste {
+        paste,
+        content: paste_content,
+        tags: paste_tags,
+    } = cached;
 
-#[derive(serde::Deserialize)]
-struct FormData {
-    content: String,
+    let size = paste_size(&paste, &paste_content)?;
+    let (links, wrap, texts) = match page {
+        PastePage::Live { req, query } => (query.links.unwrap_or(false), Wrap::from_request(req), Texts::of(req)),
+        PastePage::Snapshot { .. } => (false, Wrap::default(), Texts::english()),
+    };
+    let rendered_content = paste_fragment(data, Some(&paste.token), paste_content, &size, links, wrap, texts)?;
+    let token = escape_html(&paste.token);
+    let time = |timestamp| match page {
+        PastePage::Live { .. } => timestamp::html(timestamp, texts),
+        PastePage::Snapshot { .. } => timestamp::html_absolute(timestamp),
+    };
+
+    let (creator_notice, paste_links, paste_footer, comments, language, announcements) = match page {
+        PastePage::Live { req, query } => {
+            let key = creator_key(req, data, &paste, query.key.as_deref());
+            let mut creator_notice = creator_notice(&paste, key.as_deref(), texts);
+            let gist = gist_links(data, &paste, key.as_deref(), texts)?;
+            let comments = comments::comments_section(data, &paste, key.as_deref(), texts)?;
+            creator_notice.push_str(&gist.creator_form);
+            // Keeps the key in the link so the creator doesn't lose their notice when toggling
+            let links_toggle = format!(
+                "<a href=\"/paste/{}?links={}{}\">{}</a>",
+                token,
+                !links,
+                query
+                    .key
+                    .as_deref()
+                    .map(|key| format!("&amp;key={}", escape_html(key)))
+                    .unwrap_or_default(),
+                escape_html(texts.text(if links { "paste.plain-text" } else { "paste.show-links" })),
+            );
+            let wrap_query = page_query(&[
+                ("mode", Some(wrap.other().as_str())),
+                ("links", Some(if links { "true" } else { "false" })),
+                ("key", query.key.as_deref()),
+            ]);
+            let wrap_toggle = format!(
+                "<a href=\"/paste/{}/wrap?{}\">{}</a>",
+                token,
+                escape_html(&wrap_query),
+                escape_html(texts.text(if wrap == Wrap::Wrap { "paste.scroll" } else { "paste.wrap" })),
+            );
+            let paste_links = format!(
+                "<a href=\"/paste/{token}/raw\">{raw}</a> · <a href=\"/paste/{token}/download\">{download}</a> · \
+                 <a href=\"/paste/{token}/archive.zip\">{zip}</a> · {links_toggle} · {wrap_toggle}{gist_links}",
+                token = token,
+                raw = escape_html(texts.text("paste.raw")),
+                download = escape_html(texts.text("paste.download")),
+                zip = escape_html(texts.text("paste.zip")),
+                links_toggle = links_toggle,
+                wrap_toggle = wrap_toggle,
+                gist_links = gist.links,
+            );
+            let paste_footer = format!(
+                "<a href=\"/paste/{token}/print\">{print}</a> · <a href=\"/new?from={token}\">{template}</a>{hash_link}",
+                token = token,
+                print = escape_html(texts.text("paste.print")),
+                template = escape_html(texts.text("paste.template")),
+                hash_link = hash_link(&paste),
+            );
+            let announcements = data.announcements.read().unwrap().banners(req, store::now(), texts);
+            (creator_notice, paste_links, paste_footer, comments, texts.switcher(&back_path(req)), announcements)
+        }
+        PastePage::Snapshot { raw } => {
+            let paste_links = format!("<a href=\"{}\">{}</a>", escape_html(raw), escape_html(texts.text("paste.raw")));
+            (String::new(), paste_links, String::new(), String::new(), String::new(), String::new())
+        }
+    };
+
+    // A live page counts the view it is making
+    let views = match page {
+        PastePage::Live { .. } => paste.views + i64::from(!paste.pending),
+        PastePage::Snapshot { .. } => paste.views,
+    };
+    let mut meta = vec![
+        escape_html(&texts.plural("paste.views", views)),
+        escape_html(texts.text(visibility(paste.pending, paste.public))),
+        escape_html(&format_size(&size, texts)),
+    ];
+    if paste.created_at > 0 {
+        meta.push(texts.html("paste.created", &[("time", &time(paste.created_at))]));
+    }
+    if let Some(expires_at) = paste.expires_at {
+        meta.push(texts.html("paste.expires", &[("time", &time(expires_at))]));
+    }
+
+    let tag_chips: String = paste_tags
+        .iter()
+        .map(|tag| match page {
+            PastePage::Live { .. } => format!("<a class=\"tag\" href=\"/popular?tag={tag}\">{tag}</a>", tag = escape_html(tag)),
+            PastePage::Snapshot { .. } => format!("<span class=\"tag\">{}</span>", escape_html(tag)),
+        })
+        .collect();
+
+    let paste_title = if paste.title.is_empty() {
+        String::new()
+    } else {
+        format!("<h1 class=\"paste-title\">{}</h1>", escape_html(&paste.title))
+    };
+
+    let _render = telemetry::template("view_paste.html");
+    let html_page = texts.localize(include_str!("view_paste.html"));
+    let html_page = match page {
+        PastePage::Live { .. } => assets::versioned(&html_page),
+        PastePage::Snapshot { .. } => html_page,
+    };
+    let html_page = html_page
+        .replace("{{creator_notice}}", &creator_notice)
+        .replace("{{language}}", &language)
+        .replace("{{paste_meta}}", &meta.join(" · "))
+        .replace("{{paste_tags}}", &tag_chips)
+        .replace("{{paste_links}}", &paste_links)
+        .replace("{{paste_footer}}", &paste_footer)
+        .replace("{{comments}}", &comments)
+        .replace("{{announcements}}", &announcements)
+        .replace("{{page_title}}", &escape_html(if paste.title.is_empty() { "Rustacious" } else { &paste.title }))
+        .replace("{{paste_title}}", &paste_title);
+    // The notices of binary and large pastes link the raw and download routes
+    let rendered_content = match page {
+        PastePage::Live { .. } => rendered_content,
+        PastePage::Snapshot { raw } => rendered_content
+            .replace(&format!("href=\"/paste/{}/raw\"", token), &format!("href=\"{}\"", escape_html(raw)))
+            .replace(&format!("href=\"/paste/{}/download\"", token), &format!("href=\"{}\"", escape_html(raw))),
+    };
+    Ok(html_page.replace("{{paste_content}}", &rendered_content))
 }
 
+// The “/h/{hash}” permalink shown at the bottom of the page of a public paste.
+// Of pastes with the same content the hash leads to the oldest one, which may not be this one.
+fn hash_link(paste: &store::Paste) -> String {
+    match &paste.content_hash {
+        Some(hash) if paste.public => format!(" · <a href=\"/h/{hash}\">/h/{hash}</a>", hash = escape_html(hash)),
+        _ => String::new(),
+    }
+}
 
-// This is the main function of the project,
-// 1. Tries to connect to DB
-// 2. And then tries to Create the pastes table if it does not exists.
-// 3. Creates the Mutex instance of AppState stucture.
-// 4. Declare the HttpServer using Actix_web, with 3 routes and binds it to localhost and port 8080
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let db = Connection::open("pastes.db").expect("Failed to open database");
-    db.execute(
-        "CREATE TABLE IF NOT EXISTS pastes (token TEXT PRIMARY KEY, content TEXT)",
-        params![],
+// The size of a paste, rows stored before sizes were recorded only get their byte count.
+fn paste_size(paste: &store::Paste, content: &store::Content) -> Result<ContentSize, AppError> {
+    match paste.size {
+        Some(size) => Ok(size),
+        None => Ok(ContentSize {
+            bytes: content.len().map_err(store::StoreError::from)? as i64,
+            ..ContentSize::default()
+        }),
+    }
+}
+
+// What the pages show in place of the content of a paste.
+enum RenderedContent {
+    // The text of the paste, to be escaped before it goes in the page
+    Text(String),
+    // A notice linking to raw/download instead
+    Notice(String),
+}
+
+// Binary pastes are never rendered, and past `max_display_bytes` the page would be unusable anyway,
+// both only point to the raw/download links instead.
+// The paste page, the print view and the preview all go through here, so the content ends up in them the same way.
+// `token` is `None` for the preview of a paste not created yet, whose notices have nothing to link to.
+fn render_content(
+    data: &AppState,
+    token: Option<&str>,
+    content: store::Content,
+    size: &ContentSize,
+    texts: Texts,
+) -> Result<RenderedContent, AppError> {
+    let bytes = format_bytes(size.bytes);
+    if matches!(content, store::Content::Binary(_)) {
+        return Ok(RenderedContent::Notice(format!(
+            "<div class=\"binary\">{}{}</div>",
+            texts.html("paste.binary", &[("size", &bytes)]),
+            token
+                .map(|token| format!(
+                    " <a class=\"download\" href=\"/paste/{}/download\">{}</a>",
+                    escape_html(token),
+                    escape_html(texts.text("paste.binary-download"))
+                ))
+                .unwrap_or_default(),
+        )));
+    }
+    if size.bytes > data.settings().max_display_bytes as i64 {
+        let notice = match token {
+            Some(token) => {
+                let link = |route: &str, key: &str| {
+                    format!("<a href=\"/paste/{}/{}\">{}</a>", escape_html(token), route, escape_html(texts.text(key)))
+                };
+                texts.html(
+                    "paste.too-large-links",
+                    &[("size", &bytes), ("raw", &link("raw", "paste.raw")), ("download", &link("download", "paste.download"))],
+                )
+            }
+            None => texts.html("paste.too-large", &[("size", &bytes)]),
+        };
+        return Ok(RenderedContent::Notice(format!("<div class=\"too-large\">{}</div>", notice)));
+    }
+    Ok(RenderedContent::Text(content.into_string().map_err(store::StoreError::from)?))
+}
+
+// The content of a paste as its page shows it, escaped, with the URLs in the text made links when `links` is set.
+// Shared by `get_paste` and `preview`, so a preview is exactly what the page will show.
+fn paste_fragment(
+    data: &AppState,
+    token: Option<&str>,
+    content: store::Content,
+    size: &ContentSize,
+    links: bool,
+    wrap: Wrap,
+    texts: Texts,
+) -> Result<String, AppError> {
+    Ok(match render_content(data, token, content, size, texts)? {
+        RenderedContent::Text(text) if links => {
+            numbered_lines(&text::linkify(&escape_html(&text), data.settings().image_policy), wrap.as_str())
+        }
+        RenderedContent::Text(text) => numbered_lines(&escape_html(&text), wrap.as_str()),
+        RenderedContent::Notice(notice) => notice,
+    })
+}
+
+// The lines of a text in a `<pre class="lines {class}">`, one `<span class="line">` each, which the style sheets
+// number in a gutter. The last line break ends the last line, it doesn't start an empty one.
+fn numbered_lines(text: &str, class: &str) -> String {
+    let lines: String = text
+        .strip_suffix('\n')
+        .unwrap_or(text)
+        .split('\n')
+        .map(|line| format!("<span class=\"line\">{}</span>\n", line))
+        .collect();
+    format!("<pre class=\"lines {}\">{}</pre>", class, lines)
+}
+
+// Handles “/paste/{token}/wrap”, the toggle of the page of a paste between wrapping and scrolling long lines:
+// keeps `mode` in the cookie of `wrap.rs` and sends back to the page, with its `links` and `key`.
+async fn wrap_toggle(req: HttpRequest, token: web::Path<String>, query: web::Query<WrapQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    if !data.store.exists(&token)? {
+        return Err(AppError::not_found("Paste not found"));
+    }
+    let links = query.links.map(|links| if links { "true" } else { "false" });
+    let back = page_query(&[("links", links), ("key", query.key.as_deref())]);
+    let secure = req.connection_info().scheme() == "https";
+    Ok(HttpResponse::SeeOther()
+        .header("Location", format!("/paste/{}{}{}", token, if back.is_empty() { "" } else { "?" }, back))
+        .header("Set-Cookie", query.mode.set_cookie(secure))
+        .finish())
+}
+
+// Handles “POST /language”, the language switcher of the pages (see `i18n.rs`): keeps `lang` in its cookie and sends
+// back to `back`, a path of this server, the index page otherwise.
+async fn set_language(req: HttpRequest, form: web::Form<LanguageForm>) -> Result<HttpResponse, AppError> {
+    let index = i18n::find(&form.lang)
+        .ok_or_else(|| AppError::bad_request(format!("There is no translation to \"{}\"", form.lang)))?;
+    let secure = req.connection_info().scheme() == "https";
+    Ok(HttpResponse::SeeOther()
+        .header("Location", local_path(form.back.as_deref()))
+        .header("Set-Cookie", i18n::set_cookie(i18n::LOCALES[index].code, secure))
+        .finish())
+}
+
+// The page a form sends back to, when it is a path of this site, the index page otherwise.
+fn local_path(back: Option<&str>) -> &str {
+    back.filter(|back| back.starts_with('/') && !back.starts_with("//") && !back.contains('\\'))
+        .filter(|back| !back.chars().any(char::is_control))
+        .unwrap_or("/")
+}
+
+// Where the language switcher of a page sends back to: the page itself, or the index page after a form.
+// HEAD gets the page of GET, to the byte.
+fn back_path(req: &HttpRequest) -> String {
+    match req.uri().path_and_query() {
+        Some(path) if matches!(*req.method(), Method::GET | Method::HEAD) => path.to_string(),
+        _ => "/".to_string(),
+    }
+}
+
+// The query string of a link to a page, with the parameters that have a value, percent-encoded.
+fn page_query(params: &[(&str, Option<&str>)]) -> String {
+    let params: Vec<(&str, &str)> = params.iter().filter_map(|(name, value)| Some((*name, (*value)?))).collect();
+    serde_urlencoded::to_string(params).unwrap_or_default()
+}
+
+// Handles “/paste/{token}/print”, the paste black on white for printing: a small header with the title (the token
+// without one), date and URL, then the content with line numbers, and no navigation.
+// Counts as a view like the paste page.
+async fn print_paste(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let CachedPaste { paste, content, .. } = view_paste(&req, &data, &token).await?;
+    let size = paste_size(&paste, &content)?;
+
+    let rendered_content = match render_content(&data, Some(&paste.token), content, &size, Texts::english())? {
+        // Paper has no scrollbar, lines always wrap
+        RenderedContent::Text(text) => numbered_lines(&escape_html(&text), Wrap::Wrap.as_str()),
+        RenderedContent::Notice(notice) => notice,
+    };
+
+    let connection = req.connection_info();
+    let url = format!("{}://{}/paste/{}", connection.scheme(), connection.host(), paste.token);
+    let mut meta = vec![escape_html(&format_size(&size, Texts::english()))];
+    if paste.created_at > 0 {
+        meta.push(format!("created {}", timestamp::html_absolute(paste.created_at)));
+    }
+
+    let heading = if paste.title.is_empty() { format!("Paste {}", paste.token) } else { paste.title.clone() };
+
+    let _render = telemetry::template("print_paste.html");
+    let html_page = assets::versioned(include_str!("print_paste.html"));
+    let html_page = &html_page
+        .replace("{{paste_meta}}", &meta.join(" · "))
+        .replace("{{url}}", &escape_html(&url))
+        .replace("{{heading}}", &escape_html(&heading))
+        .replace("{{paste_content}}", &rendered_content);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// The key the creator of a paste goes on with: `key` when it is the secret of the paste, the secret itself when the
+// request carries the creator cookie of the paste (see `creator.rs`), `None` for anyone else.
+// The pages of the creator put it in their links and forms as if it had come in the URL.
+fn creator_key(req: &HttpRequest, data: &AppState, paste: &store::Paste, key: Option<&str>) -> Option<String> {
+    match key {
+        Some(key) if secret_matches(&paste.secret, key) => Some(key.to_string()),
+        _ if !paste.secret.is_empty()
+            && creator::created(creator::from_request(req, &data.cookie_secret).as_deref(), paste.creator.as_deref()) =>
+        {
+            Some(paste.secret.clone())
+        }
+        _ => None,
+    }
+}
+
+// What a paste is as the pages list it, the key of its text.
+fn visibility(pending: bool, public: bool) -> &'static str {
+    if pending {
+        "paste.pending"
+    } else if public {
+        "paste.public"
+    } else {
+        "paste.unlisted"
+    }
+}
+
+// The links to the private pages of a paste, shown when the `key` query parameter matches its secret.
+// A paste awaiting review says so first, with the private link that is the only way to it until then.
+fn creator_notice(paste: &store::Paste, key: Option<&str>, texts: Texts) -> String {
+    let key = match key {
+        Some(key) if secret_matches(&paste.secret, key) => escape_html(key),
+        _ => return String::new(),
+    };
+    let token = escape_html(&paste.token);
+    let link = format!("<a href=\"/paste/{token}\">/paste/{token}</a>", token = token);
+    let notice = if paste.pending {
+        let private = format!("<a href=\"/paste/{token}?key={key}\">/paste/{token}?key={key}</a>", token = token, key = key);
+        texts.html("creator.pending", &[("private", &private), ("link", &link)])
+    } else {
+        let stats = format!(
+            "<a href=\"/paste/{}/stats?key={}\">{}</a>",
+            token,
+            key,
+            escape_html(texts.text("creator.stats"))
+        );
+        texts.html("creator.notice", &[("stats", &stats), ("link", &link)])
+    };
+    format!(
+        "<div class=\"creator-notice\">{notice}\
+         <form method=\"post\" action=\"/paste/{token}/delete\"><input type=\"hidden\" name=\"key\" value=\"{key}\"><button type=\"submit\">{delete}</button></form></div>",
+        notice = notice,
+        token = token,
+        key = key,
+        delete = escape_html(texts.text("creator.delete")),
     )
-    .expect("Failed to create table");
+}
 
-    let app_state = web::Data::new(AppState {
-        db: Mutex::new(db),
-    });
+// What the page of a paste shows of its mirror to a gist, nothing unless mirroring is enabled.
+struct GistLinks {
+    // Items of the line of links under the paste, each starting with its separator
+    links: String,
+    // The button asking for a mirror, for the creator while there is none
+    creator_form: String,
+}
+
+// Everyone gets the link to the gist once there is one, the creator also a link to the status page of the mirror,
+// and its button as long as there is no mirror or it failed.
+fn gist_links(data: &AppState, paste: &store::Paste, key: Option<&str>, texts: Texts) -> Result<GistLinks, AppError> {
+    let mut gist = GistLinks {
+        links: String::new(),
+        creator_form: String::new(),
+    };
+    if data.gist.is_none() || !can_mirror(paste) {
+        return Ok(gist);
+    }
+
+    let mirror = data.store.gist_mirror(&paste.token)?.map(|mirror| mirror.state);
+    if let Some(GistState::Mirrored(url)) = &mirror {
+        gist.links = format!(" · <a href=\"{}\">{}</a>", escape_html(url), escape_html(texts.text("paste.gist")));
+    }
+    if let Some(key) = key.filter(|key| secret_matches(&paste.secret, key)) {
+        let token = escape_html(&paste.token);
+        let key = escape_html(key);
+        if mirror.is_some() {
+            gist.links.push_str(&format!(
+                " · <a href=\"/paste/{}/gist?key={}\">{}</a>",
+                token,
+                key,
+                escape_html(texts.text("paste.gist-mirror"))
+            ));
+        }
+        if matches!(mirror, None | Some(GistState::Failed(_))) {
+            gist.creator_form = mirror_form(&token, &key, &escape_html(texts.text("paste.mirror")));
+        }
+    }
+    Ok(gist)
+}
+
+// Only text pastes have something a gist can hold.
+fn can_mirror(paste: &store::Paste) -> bool {
+    !paste.redirect && paste.data.is_none()
+}
+
+// The button posting to “/paste/{token}/gist”, `token` and `key` already escaped.
+fn mirror_form(token: &str, key: &str, label: &str) -> String {
+    format!(
+        "<form method=\"post\" action=\"/paste/{token}/gist\"><input type=\"hidden\" name=\"key\" value=\"{key}\"><button type=\"submit\">{label}</button></form>",
+        token = token,
+        key = key,
+        label = label,
+    )
+}
+
+// Starts mirroring a paste to a gist (see `gist.rs`): marks it pending and leaves the call to GitHub to a task of its own.
+// A paste mirrored already, or being mirrored, is left alone; one whose mirror failed is tried again.
+// The mirror of a paste awaiting review stays pending until it is approved.
+async fn start_gist_mirror(data: &web::Data<AppState>, token: &str) -> Result<(), AppError> {
+    if let Some(mirror) = data.store.gist_mirror(token)? {
+        match mirror.state {
+            GistState::Mirrored(_) => return Ok(()),
+            GistState::Pending if store::now() - mirror.updated_at < GIST_PENDING_STALE_SECS => return Ok(()),
+            GistState::Pending | GistState::Failed(_) => {}
+        }
+    }
+    let pending = token.to_string();
+    if store_write(data, move |store| store.set_gist_mirror(&pending, &GistState::Pending)).await? {
+        actix_web::rt::spawn(mirror_to_gist(data.clone(), token.to_string()));
+    }
+    Ok(())
+}
+
+// The task of `start_gist_mirror`, recording the URL of the gist, or why there is none, for the status page.
+async fn mirror_to_gist(data: web::Data<AppState>, token: String) {
+    let client = match &data.gist {
+        Some(client) => client,
+        None => return,
+    };
+    let state = match data.store.open_content(&token) {
+        // Left pending, `admin_approve` starts it again once the paste is published
+        Ok(Some((paste, _))) if paste.pending => return,
+        Ok(Some((_, content))) => match content.into_string() {
+            Ok(text) => match client.create(&format!("{}.txt", token), &format!("Mirror of paste {}", token), &text).await {
+                Ok(url) => GistState::Mirrored(url),
+                Err(e) => GistState::Failed(e),
+            },
+            Err(e) => GistState::Failed(format!("The content of the paste could not be read: {}", e)),
+        },
+        // Deleted or expired meanwhile, there is nothing left to mirror
+        Ok(None) => return,
+        Err(e) => GistState::Failed(format!("The paste could not be read: {}", e)),
+    };
+
+    if let GistState::Failed(reason) = &state {
+        eprintln!("Mirroring paste {} to a gist failed: {}", token, reason);
+    }
+    let mirrored = token.clone();
+    if let Err(e) = store_write(&data, move |store| store.set_gist_mirror(&mirrored, &state)).await {
+        eprintln!("The mirror of paste {} to a gist could not be recorded: {}", token, e);
+    }
+}
+
+// The paste of one of the mirror routes, with the key of `creator_key`: 404 when mirroring is off or the paste is unknown,
+// 403 without its key, 400 for a paste a gist can't hold.
+fn mirrored_paste(req: &HttpRequest, data: &AppState, token: &str, key: Option<&str>) -> Result<(store::Paste, String), AppError> {
+    if data.gist.is_none() {
+        return Err(AppError::not_found("Mirroring to gists is not enabled on this server"));
+    }
+    let paste = data.store.get(token)?.ok_or_else(|| AppError::not_found("Paste not found"))?;
+    let key = creator_key(req, data, &paste, key)
+        .ok_or_else(|| AppError::forbidden("A valid key is required for the gist mirror of this paste"))?;
+    if !can_mirror(&paste) {
+        return Err(AppError::bad_request("Only text pastes can be mirrored to a gist"));
+    }
+    Ok((paste, key))
+}
+
+// Handles “/paste/{token}/gist”, where the mirror of a paste to a gist stands: under way, the link to the gist,
+// or why it failed with a button to try again. Only for the creator, with the `key` of the paste.
+// The page reloads itself while the mirror is pending.
+async fn paste_gist(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let (paste, key) = mirrored_paste(&req, &data, &token, query.key.as_deref())?;
+    let token = escape_html(&paste.token);
+    let key = escape_html(&key);
+
+    let mirror = data.store.gist_mirror(&paste.token)?;
+    let pending = matches!(mirror, Some(store::GistMirror { state: GistState::Pending, .. }));
+    let status = match mirror {
+        None => format!("<p>This paste is not mirrored.</p>{}", mirror_form(&token, &key, "Mirror to a secret gist")),
+        Some(mirror) => {
+            let since = timestamp::html(mirror.updated_at, Texts::english());
+            match mirror.state {
+                GistState::Pending => format!("<p>The gist is being created, asked {}.</p>", since),
+                GistState::Mirrored(url) => format!(
+                    "<p>Mirrored to <a href=\"{url}\">{url}</a> {since}.</p>",
+                    url = escape_html(&url),
+                    since = since
+                ),
+                GistState::Failed(reason) => format!(
+                    "<p>Mirroring failed {}: {}</p>{}",
+                    since,
+                    escape_html(&reason),
+                    mirror_form(&token, &key, "Try again")
+                ),
+            }
+        }
+    };
+
+    let _render = telemetry::template("paste_gist.html");
+    let html_page = assets::versioned(include_str!("paste_gist.html"))
+        .replace("{{refresh}}", if pending { "<meta http-equiv=\"refresh\" content=\"3\">" } else { "" })
+        .replace("{{token}}", &token)
+        .replace("{{status}}", &status);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// Handles “POST /paste/{token}/gist”, the creator asking for a mirror of their paste with the key from the private link.
+// Sends them to the status page of the mirror.
+async fn mirror_paste(
+    req: HttpRequest,
+    token: web::Path<String>,
+    form: web::Form<KeyQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let (paste, key) = mirrored_paste(&req, &data, &token, form.key.as_deref())?;
+    start_gist_mirror(&data, &paste.token).await?;
+
+    Ok(HttpResponse::SeeOther()
+        .header("Location", format!("/paste/{}/gist?key={}", paste.token, key))
+        .finish())
+}
+
+// Handles “/paste/{token}/created”, where submitting leads, see `handoff.rs`. With the one-time key of the redirect it
+// lists the links of the new paste, its secret key and its private link, each with a copy button, and the delete
+// button; without it, or once it was used, only the public links and the expiry. Counts no view.
+async fn paste_created(req: HttpRequest, token: web::Path<String>, query: web::Query<KeyQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let paste = data
+        .store
+        .get(&token)?
+        .ok_or_else(|| AppError::not_found("Paste not found"))?;
+    let secret = query
+        .key
+        .as_deref()
+        .and_then(|key| data.handoffs.lock().unwrap().take(key, &paste.token, store::now()));
+    if paste.pending && secret.is_none() && !sees_pending(&req, &data, &paste) {
+        return Err(AppError::not_found(review::AWAITING));
+    }
+
+    let texts = Texts::of(&req);
+    let base = {
+        let connection = req.connection_info();
+        format!("{}://{}/paste/{}", connection.scheme(), connection.host(), paste.token)
+    };
+    let row = |label: &str, value: &str| {
+        format!(
+            "<div><dt>{label}</dt><dd><code>{value}</code><button type=\"button\" data-copy=\"{value}\" data-copied=\"{copied}\" hidden>{copy}</button></dd></div>",
+            label = escape_html(texts.text(label)),
+            value = escape_html(value),
+            copy = escape_html(texts.text("created.copy")),
+            copied = escape_html(texts.text("created.copied")),
+        )
+    };
+    // The page of a short link is the redirect itself, its creator's is the preview
+    let own_page = if paste.redirect { format!("{}/preview", base) } else { base.clone() };
+    let mut rows = vec![row("created.link", &base)];
+    if paste.redirect {
+        rows.push(row("created.preview", &own_page));
+    } else {
+        rows.push(row("created.raw", &format!("{}/raw", base)));
+    }
+    if let Some(secret) = &secret {
+        rows.push(row("created.secret", secret));
+        rows.push(row("created.private", &format!("{}?key={}", own_page, secret)));
+    }
+    let expires = match paste.expires_at {
+        Some(expires_at) => timestamp::html(expires_at, texts),
+        None => escape_html(texts.text("created.never")),
+    };
+    rows.push(format!("<div><dt>{}</dt><dd>{}</dd></div>", escape_html(texts.text("created.expires")), expires));
+
+    let notice = match &secret {
+        Some(secret) => {
+            let stats = format!(
+                "<a href=\"/paste/{}/stats?key={}\">{}</a>",
+                escape_html(&paste.token),
+                escape_html(secret),
+                escape_html(texts.text("creator.stats"))
+            );
+            format!(
+                "<div class=\"creator-notice\">{pending}{keep}\
+                 <form method=\"post\" action=\"/paste/{token}/delete\"><input type=\"hidden\" name=\"key\" value=\"{key}\"><button type=\"submit\">{delete}</button></form></div>",
+                pending = if paste.pending {
+                    format!("<p>{}</p>", escape_html(texts.text("created.pending")))
+                } else {
+                    String::new()
+                },
+                keep = texts.html("created.keep", &[("stats", &stats)]),
+                token = escape_html(&paste.token),
+                key = escape_html(secret),
+                delete = escape_html(texts.text("creator.delete")),
+            )
+        }
+        None => format!("<div class=\"meta mb-4\">{}</div>", escape_html(texts.text("created.shown-once"))),
+    };
+
+    let _render = telemetry::template("paste_created.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("paste_created.html")))
+        .replace("{{language}}", &texts.switcher(&back_path(&req)))
+        .replace("{{notice}}", &notice)
+        .replace("{{rows}}", &rows.concat())
+        .replace("{{token}}", &escape_html(&paste.token));
+
+    // Never kept by a cache, nor the one-time key sent on by a link
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .header("Cache-Control", "private, no-store")
+        .header("Referrer-Policy", "no-referrer")
+        .body(html_page))
+}
+
+// Handles “/paste/{token}/preview”, where a short link goes, shown instead of following it.
+// Other pastes have nothing to preview and are sent to their page.
+async fn preview_paste(req: HttpRequest, token: web::Path<String>, query: web::Query<KeyQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let CachedPaste { paste, content, .. } = view_paste(&req, &data, &token).await?;
+    if !paste.redirect {
+        return Ok(HttpResponse::Found()
+            .header("Location", format!("/paste/{}", paste.token))
+            .finish());
+    }
+    let target = content.into_string().map_err(store::StoreError::from)?;
+
+    let texts = Texts::of(&req);
+    let link = format!("<a href=\"/paste/{token}\">/paste/{token}</a>", token = escape_html(&paste.token));
+    let key = creator_key(&req, &data, &paste, query.key.as_deref());
+    let _render = telemetry::template("redirect_preview.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("redirect_preview.html")));
+    let html_page = &html_page
+        .replace("{{creator_notice}}", &creator_notice(&paste, key.as_deref(), texts))
+        .replace("{{short_link}}", &texts.html("paste.short-link", &[("link", &link)]))
+        .replace("{{language}}", &texts.switcher(&back_path(&req)))
+        .replace("{{token}}", &escape_html(&paste.token))
+        .replace("{{target}}", &escape_html(&target));
 
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// The page a short link shows instead of a 302 with `redirect::Mode::Interstitial`, its host in large letters and a
+// link to follow.
+fn redirect_interstitial(req: &HttpRequest, target: &str) -> HttpResponse {
+    let texts = Texts::of(req);
+    let _render = telemetry::template("redirect_interstitial.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("redirect_interstitial.html")))
+        .replace("{{language}}", &texts.switcher(&back_path(req)))
+        .replace("{{host}}", &escape_html(&redirect::host(target)))
+        .replace("{{target}}", &escape_html(target));
+
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .header("Referrer-Policy", "no-referrer")
+        .body(html_page)
+}
+
+// Loads a paste for one of the read routes and counts the view, from the cache when it's there.
+// The paste comes with its view counter as it was before this view.
+// An archived paste is moved back to the hot table, unless `archive_promote` is off.
+// HEAD requests go through the same handlers as GET, so both answer with the same status and headers
+// (actix leaves the body out for HEAD), but they are only a look: no view is counted and nothing moves.
+// An unknown or expired token is a 404, and so is a paste awaiting review for anyone but its creator and the admins.
+async fn view_paste(req: &HttpRequest, data: &AppState, token: &str) -> Result<CachedPaste, AppError> {
+    load_paste(data, token, req.method() != Method::HEAD, Reader::Request(req))
+        .await?
+        .ok_or_else(|| AppError::not_found("Paste not found"))
+}
+
+// Who reads a paste through `load_paste`, for the pastes awaiting review (see `review.rs`).
+#[derive(Clone, Copy)]
+enum Reader<'a> {
+    // Sees no pending paste, as if there were none: the batch fetch, collections
+    Anyone,
+    // Sees a pending paste when it is of its creator (see `creator_key`, the key coming in the query) or of an admin,
+    // anyone else gets a 404 saying why
+    Request(&'a HttpRequest),
+}
+
+// Whether `req` comes from the creator of the pending paste or from an admin.
+fn sees_pending(req: &HttpRequest, data: &AppState, paste: &store::Paste) -> bool {
+    let key = web::Query::<KeyQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().key);
+    creator_key(req, data, paste, key.as_deref()).is_some()
+        || (bearer(req).is_some() && require_admin(req, data).is_ok())
+}
+
+// The part of `view_paste` shared with the batch fetch: the paste, `None` when unknown or expired,
+// and when `viewing`, the view counted and the paste promoted out of the archive.
+// A paste awaiting review goes as `reader` may see it, and counts no view until it is published.
+// A paste stored before content hashes were recorded gets its hash here, the first time it leaves the database.
+async fn load_paste(data: &AppState, token: &str, viewing: bool, reader: Reader<'_>) -> Result<Option<CachedPaste>, AppError> {
+    let cached = match data.cache.get(token) {
+        Some(cached) => cached,
+        None => {
+            let (mut paste, content) = match data.store.open_content(token)? {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            if paste.content_hash.is_none() {
+                let hash = match (&content, &paste.blob) {
+                    // Blob files are already named after it
+                    (store::Content::File(_), Some(blob)) => blob.clone(),
+                    (store::Content::File(path), None) => store::content_hash(&std::fs::read(path).map_err(store::StoreError::from)?),
+                    (store::Content::Inline(text), _) => store::content_hash(text.as_bytes()),
+                    (store::Content::Binary(bytes), _) => store::content_hash(bytes),
+                };
+                let (hashed, recorded) = (paste.token.clone(), hash.clone());
+                store_write(data, move |store| store.set_content_hash(&hashed, &recorded)).await?;
+                paste.content_hash = Some(hash);
+            }
+            if paste.archived && data.settings().archive_promote && viewing {
+                let promoted = paste.token.clone();
+                store_write(data, move |store| store.unarchive(&promoted)).await?;
+            }
+            let tags = data.store.tags(&paste.token)?;
+            let cached = CachedPaste { paste, content, tags };
+            data.cache.insert(cached.clone());
+            cached
+        }
+    };
+
+    if cached.paste.pending {
+        match reader {
+            Reader::Request(req) if sees_pending(req, data, &cached.paste) => {}
+            Reader::Request(_) => return Err(AppError::not_found(review::AWAITING)),
+            Reader::Anyone => return Ok(None),
+        }
+    }
+    if viewing && !cached.paste.pending {
+        let viewed = cached.paste.token.clone();
+        store_write(data, move |store| store.record_view(&viewed)).await?;
+        data.cache.count_view(&cached.paste.token);
+    }
+    Ok(Some(cached))
+}
+
+// Loads the `count` most viewed pastes of the last week into the cache before the server takes requests, so a restart
+// doesn't send the first visitors of each to the database. A paste that fails to load is left for its first visitor.
+async fn prime_cache(data: &AppState, count: usize) {
+    if count == 0 || !data.cache.enabled() {
+        return;
+    }
+    let started = Instant::now();
+    let popular = match data.store.list_popular(POPULAR_DEFAULT_DAYS, None, count as i64) {
+        Ok(popular) => popular,
+        Err(e) => {
+            eprintln!("Could not prime the cache: {}", e);
+            return;
+        }
+    };
+    let mut primed = 0;
+    for listed in &popular {
+        if let Ok(Some(_)) = load_paste(data, &listed.token, false, Reader::Anyone).await {
+            primed += 1;
+        }
+    }
+    println!("Cache primed with {} pastes in {} ms", primed, started.elapsed().as_millis());
+}
+
+// Handles “/paste/{token}/raw”, the content alone as UTF-8 plain text.
+async fn raw_paste(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    serve_content(&req, &data, &token, DispositionType::Inline).await
+}
+
+// Handles “/paste/{token}/download”, the same as raw but offered to save, as `PASTRY_DOWNLOAD_FILENAME` names it.
+async fn download_paste(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    serve_content(&req, &data, &token, DispositionType::Attachment).await
+}
+
+// Handles “/paste/{token}/archive.zip”, the paste as a zip archive streamed while it's made, see `zip.rs`.
+// It holds the content named from its title, its token without one (`filename::ARCHIVE`), dated when the paste was
+// created, and “METADATA.json” with the token, the title, the creation time and the content hash.
+// Counts as a view like the download.
+async fn archive_paste(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let CachedPaste { paste, content, tags } = view_paste(&req, &data, &token).await?;
 
-    //Actually start the http server with 3 routes and at given port 8080
-    HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone())
-            .service(web::resource("/style.css").to(|| {
-                async { NamedFile::open("src/style.css") }
-            }))
-            .route("/", web::get().to(index))
-            .route("/submit", web::post().to(submit))
-            .route("/paste/{token}", web::get().to(get_paste))
+    let name = filename::Template::archive().render(&filename_fields(&paste, &tags, &content));
+    let source = match content {
+        store::Content::File(path) => zip::Source::File(std::fs::File::open(path).map_err(store::StoreError::from)?),
+        store::Content::Inline(text) => zip::Source::Bytes(web::Bytes::from(text)),
+        store::Content::Binary(bytes) => zip::Source::Bytes(web::Bytes::from(bytes)),
+    };
+    let metadata = serde_json::to_vec_pretty(&ArchiveMetadata {
+        token: &paste.token,
+        title: Some(paste.title.as_str()).filter(|title| !title.is_empty()),
+        created_at: paste.created_at,
+        content_hash: paste.content_hash.as_deref(),
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    .map_err(|e| AppError::internal(e.to_string()))?;
+    let entries = vec![
+        zip::Entry {
+            name: zip::entry_name(&name),
+            modified: paste.created_at,
+            source,
+        },
+        zip::Entry {
+            name: "METADATA.json".to_string(),
+            modified: paste.created_at,
+            source: zip::Source::Bytes(web::Bytes::from(metadata)),
+        },
+    ];
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        // Made as it is sent, there is no length to take a range of
+        .header("Accept-Ranges", "none")
+        .set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("{}.zip", paste.token))],
+        })
+        .streaming(zip::stream(entries)))
+}
+
+// What a “/h/{hash}” prefix leads to: the token of the one paste it matches, or the hashes it could be.
+enum HashLookup {
+    Paste(String),
+    Choices(Vec<HashMatch>),
+}
+
+// Looks up a content hash, or the start of one, of `HASH_PREFIX_MIN` to 64 hex digits (in either case).
+// Only public pastes are found, so an unlisted paste can't be found by guessing what it holds.
+// Of several pastes with the same content the oldest one is the match; no match at all is a 404.
+fn lookup_hash(data: &AppState, hash: &str) -> Result<HashLookup, AppError> {
+    let prefix = hash.to_ascii_lowercase();
+    if prefix.len() < HASH_PREFIX_MIN || prefix.len() > 64 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(AppError::bad_request(format!(
+            "A content hash is {} to 64 hex digits",
+            HASH_PREFIX_MIN
+        )));
+    }
+    let mut matches = data.store.find_by_hash(&prefix, HASH_CHOICES_LIMIT)?;
+    match matches.len() {
+        0 => Err(AppError::not_found("No public paste has this content hash")),
+        1 => Ok(HashLookup::Paste(matches.remove(0).token)),
+        _ => Ok(HashLookup::Choices(matches)),
+    }
+}
+
+// The 300 answer to a prefix shared by several hashes, listing them with `suffix` (“/raw”…) kept.
+// At most `HASH_CHOICES_LIMIT` of them, a longer prefix narrows it down.
+fn hash_choices(req: &HttpRequest, matches: &[HashMatch], suffix: &str) -> HttpResponse {
+    let texts = Texts::of(req);
+    let list_items: String = matches
+        .iter()
+        .map(|found| format!("<li><a href=\"/h/{hash}{suffix}\">{hash}</a></li>", hash = escape_html(&found.hash), suffix = suffix))
+        .collect();
+
+    let _render = telemetry::template("list_pastes.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("list_pastes.html")))
+        .replace("{{list_title}}", &escape_html(texts.text("list.hashes")))
+        .replace("{{language}}", &texts.switcher(&back_path(req)))
+        .replace("{{list_items}}", &list_items);
+
+    HttpResponse::MultipleChoices()
+        .content_type("text/html")
+        .body(html_page)
+}
+
+// Handles “/h/{hash}”, the page of the public paste with this content hash, or with a hash starting with it.
+// The same page as “/paste/{token}”, see `lookup_hash` for which paste it is.
+async fn hash_paste(req: HttpRequest, hash: web::Path<String>, query: web::Query<PasteQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    match lookup_hash(&data, &hash)? {
+        HashLookup::Paste(token) => get_paste(req, web::Path::from(token), query, data).await,
+        HashLookup::Choices(matches) => Ok(hash_choices(&req, &matches, "")),
+    }
+}
+
+// Handles “/h/{hash}/raw”.
+async fn hash_raw(req: HttpRequest, hash: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    match lookup_hash(&data, &hash)? {
+        HashLookup::Paste(token) => serve_content(&req, &data, &token, DispositionType::Inline).await,
+        HashLookup::Choices(matches) => Ok(hash_choices(&req, &matches, "/raw")),
+    }
+}
+
+// Handles “/h/{hash}/download”.
+async fn hash_download(req: HttpRequest, hash: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    match lookup_hash(&data, &hash)? {
+        HashLookup::Paste(token) => serve_content(&req, &data, &token, DispositionType::Attachment).await,
+        HashLookup::Choices(matches) => Ok(hash_choices(&req, &matches, "/download")),
+    }
+}
+
+// Sends the content of a paste as a streamed body, so the memory used doesn't grow with the size of the paste:
+// a blob file is read from disk chunk by chunk, content stored in the database is sent in chunks sharing its buffer
+// (text is never bigger than the blob threshold anyway). See `range.rs` for the `Range` requests.
+// The length is known up front either way, so responses carry a Content-Length, HEAD ones included, and the ETag
+// is the content hash in quotes.
+// Binary pastes are sent as “application/octet-stream”. The file name is `PASTRY_DOWNLOAD_FILENAME`'s.
+// The `METADATA_HEADERS` go along, on HEAD responses too. Counts as a view like the HTML page.
+async fn serve_content(req: &HttpRequest, data: &AppState, token: &str, disposition: DispositionType) -> Result<HttpResponse, AppError> {
+    let CachedPaste { paste, content, tags } = view_paste(req, data, token).await?;
+    let metadata = metadata_headers(&paste, &tags, true);
+
+    let content_disposition = ContentDisposition {
+        disposition,
+        parameters: vec![DispositionParam::Filename(download_filename(data, &paste, &tags, &content))],
+    };
+    let (source, content_type) = match content {
+        store::Content::File(path) => {
+            let file = std::fs::File::open(path).map_err(store::StoreError::from)?;
+            (range::Source::File(file), "text/plain; charset=utf-8")
+        }
+        store::Content::Inline(content) => (range::Source::Bytes(web::Bytes::from(content)), "text/plain; charset=utf-8"),
+        store::Content::Binary(bytes) => (range::Source::Bytes(web::Bytes::from(bytes)), "application/octet-stream"),
+    };
+    let length = range::length(&source).map_err(store::StoreError::from)?;
+    let etag = paste.content_hash.as_ref().map(|hash| format!("\"{}\"", hash));
+
+    let (mut builder, start, len) = match range::requested(req, length, etag.as_deref()) {
+        range::Requested::Whole => (HttpResponse::Ok(), 0, length),
+        range::Requested::Part(first, last) => {
+            let mut builder = HttpResponse::PartialContent();
+            builder.header("Content-Range", format!("bytes {}-{}/{}", first, last, length));
+            (builder, first, last - first + 1)
+        }
+        range::Requested::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .header("Accept-Ranges", "bytes")
+                .header("Content-Range", format!("bytes */{}", length))
+                .finish())
+        }
+    };
+    builder
+        .content_type(content_type)
+        .set(content_disposition)
+        .header("Accept-Ranges", "bytes");
+    if let Some(etag) = &etag {
+        builder.header("ETag", etag.as_str());
+    }
+    for (name, value) in metadata {
+        // Tokens, tags, hashes and timestamps are ASCII, a value that isn't can only be left out
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+            builder.header(name, value);
+        }
+    }
+    Ok(builder.body(SizedStream::new(len, range::body(source, start, len))))
+}
+
+// The name `content` of `paste` is saved as, by the template of the settings.
+fn download_filename(data: &AppState, paste: &store::Paste, tags: &[String], content: &store::Content) -> String {
+    data.settings().download_filename.render(&filename_fields(paste, tags, content))
+}
+
+// What the file name templates of `paste` are filled with.
+fn filename_fields<'a>(paste: &'a store::Paste, tags: &'a [String], content: &store::Content) -> filename::Fields<'a> {
+    filename::Fields {
+        token: &paste.token,
+        title: &paste.title,
+        binary: matches!(content, store::Content::Binary(_)),
+        created_at: paste.created_at,
+        tags,
+    }
+}
+
+// The `METADATA_HEADERS` of a paste with their values, views counting this one when `viewed` (never while it is
+// pending, see `load_paste`). A HEAD request counts no view but tells the views its GET would, like the page does.
+fn metadata_headers(paste: &store::Paste, tags: &[String], viewed: bool) -> Vec<(&'static str, String)> {
+    // In the order of the names
+    let values: [Option<String>; METADATA_HEADERS.len()] = [
+        Some(paste.token.clone()),
+        Some(timestamp::rfc3339(paste.created_at)),
+        paste.expires_at.map(timestamp::rfc3339),
+        Some((paste.views + i64::from(viewed && !paste.pending)).to_string()),
+        Some(tags.join(",")).filter(|tags| !tags.is_empty()),
+        paste.content_hash.clone(),
+    ];
+    METADATA_HEADERS
+        .iter()
+        .zip(values)
+        .filter_map(|(name, value)| Some((*name, value?)))
+        .collect()
+}
+
+// Handles “/popular”, the most viewed public pastes over the last `days` days.
+// `days` is clamped between 1 and the retention of the daily view table,
+// `tag` optionally restricts the list to pastes carrying that tag,
+// then every paste is rendered as a list item with a link, a preview and its view count.
+async fn popular(req: HttpRequest, query: web::Query<PopularQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let texts = Texts::of(&req);
+    let days = query
+        .days
+        .unwrap_or(POPULAR_DEFAULT_DAYS)
+        .clamp(1, POPULAR_MAX_DAYS);
+
+    let tag = query.tag.as_deref().map(str::to_lowercase);
+    if let Some(tag) = &tag {
+        tags::validate_tag(tag).map_err(AppError::bad_request)?;
+    }
+
+    let pastes = data.store.list_popular(days, tag.as_deref(), POPULAR_LIMIT)?;
+
+    let list_items: String = pastes
+        .iter()
+        .map(|paste| {
+            format!(
+                "<li><a href=\"/paste/{token}\">{label}</a> &middot; {views}{size}<span class=\"preview\">{preview}</span></li>",
+                token = escape_html(&paste.token),
+                label = paste_label(&paste.token, &paste.title),
+                views = escape_html(&texts.plural("list.views", paste.views)),
+                size = paste.size.map(|size| format!(" &middot; {}", escape_html(&format_size(&size, texts)))).unwrap_or_default(),
+                preview = escape_html(&paste.preview),
+            )
+        })
+        .collect();
+
+    let list_items = if list_items.is_empty() {
+        format!("<li>{}</li>", escape_html(texts.text("list.popular-none")))
+    } else {
+        list_items
+    };
+
+    let days = days.to_string();
+    let list_title = match &tag {
+        Some(tag) => texts.html("list.popular-tag", &[("tag", &escape_html(tag)), ("days", &days)]),
+        None => texts.html("list.popular", &[("days", &days)]),
+    };
+
+    let _render = telemetry::template("list_pastes.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("list_pastes.html")))
+        .replace("{{list_title}}", &list_title)
+        .replace("{{language}}", &texts.switcher(&back_path(&req)))
+        .replace("{{list_items}}", &list_items);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// What a listing links a paste by, escaped: its title, its token without one.
+fn paste_label(token: &str, title: &str) -> String {
+    escape_html(if title.is_empty() { token } else { title })
+}
+
+// Handles “/mine”, the pastes submitted from this browser, known by its creator cookie (see `creator.rs`), newest first.
+// Each comes with what its creator can do: its page (with the links of the creator, the cookie standing in for the key),
+// its stats, the form started from it, and deleting it. Without a cookie there is nothing to list, which the page says.
+// Only that browser ever sees the page, so it isn't cached anywhere.
+async fn mine(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let texts = Texts::of(&req);
+    let id = creator::from_request(&req, &data.cookie_secret);
+    let pastes = match &id {
+        Some(id) => data.store.list_created(&creator::hashed(id), MINE_LIMIT)?,
+        None => Vec::new(),
+    };
+
+    let list_items: String = pastes
+        .iter()
+        .map(|paste| {
+            let token = escape_html(&paste.token);
+            let mut meta = vec![
+                escape_html(texts.text(visibility(paste.pending, paste.public))),
+                escape_html(&texts.plural("list.views", paste.views)),
+            ];
+            if let Some(size) = &paste.size {
+                meta.push(escape_html(&format_size(size, texts)));
+            }
+            if paste.created_at > 0 {
+                meta.push(texts.html("paste.created", &[("time", &timestamp::html(paste.created_at, texts))]));
+            }
+            if let Some(expires_at) = paste.expires_at {
+                meta.push(texts.html("paste.expires", &[("time", &timestamp::html(expires_at, texts))]));
+            }
+            let page = if paste.redirect { "/preview" } else { "" };
+            let mut actions = vec![format!("<a href=\"/paste/{}/stats\">{}</a>", token, escape_html(texts.text("list.stats")))];
+            if !paste.binary {
+                actions.push(format!("<a href=\"/new?from={}\">{}</a>", token, escape_html(texts.text("paste.template"))));
+            }
+            format!(
+                "<li><a href=\"/paste/{token}{page}\">{label}</a> &middot; {meta}<br>{actions} &middot; \
+                 <form method=\"post\" action=\"/paste/{token}/delete\"><input type=\"hidden\" name=\"back\" value=\"mine\">\
+                 <button type=\"submit\">{delete}</button></form><span class=\"preview\">{preview}</span></li>",
+                token = token,
+                label = paste_label(&paste.token, &paste.title),
+                page = page,
+                meta = meta.join(" &middot; "),
+                actions = actions.join(" &middot; "),
+                delete = escape_html(texts.text("list.delete")),
+                preview = escape_html(&paste.preview),
+            )
+        })
+        .collect();
+
+    let list_items = match id {
+        _ if !list_items.is_empty() => list_items,
+        Some(_) => format!("<li>{}</li>", escape_html(texts.text("list.mine-gone"))),
+        None => format!("<li>{}</li>", escape_html(texts.text("list.mine-none"))),
+    };
+
+    let _render = telemetry::template("list_pastes.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("list_pastes.html")))
+        .replace("{{list_title}}", &escape_html(texts.text("list.mine")))
+        .replace("{{language}}", &texts.switcher(&back_path(&req)))
+        .replace("{{list_items}}", &list_items);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .header("Cache-Control", "private, no-store")
+        .body(html_page))
+}
+
+// Handles “/tags”, every tag used by public pastes with its number of pastes, most used first.
+// Each tag links to the popular page filtered on it.
+async fn tag_list(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let texts = Texts::of(&req);
+    let counts = data.store.tag_counts()?;
+
+    let list_items: String = counts
+        .iter()
+        .map(|(tag, uses)| {
+            format!(
+                "<li><a class=\"tag\" href=\"/popular?tag={tag}\">{tag}</a> &middot; {uses}</li>",
+                tag = escape_html(tag),
+                uses = escape_html(&texts.plural("list.tag-uses", *uses)),
+            )
+        })
+        .collect();
+
+    let list_items = if list_items.is_empty() {
+        format!("<li>{}</li>", escape_html(texts.text("list.tags-none")))
+    } else {
+        list_items
+    };
+
+    let _render = telemetry::template("list_pastes.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("list_pastes.html")))
+        .replace("{{list_title}}", &escape_html(texts.text("list.tags")))
+        .replace("{{language}}", &texts.switcher(&back_path(&req)))
+        .replace("{{list_items}}", &list_items);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// Handles “/paste/{token}/stats”, the views per day of a paste over the last `STATS_DAYS` days, drawn as an ASCII chart.
+// Only for the creator: the `key` query parameter has to match the paste's secret.
+async fn paste_stats(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let days = authorized_daily_views(&req, &token, &query, &data)?;
+
+    let max_views = days.iter().map(|(_, views)| *views).max().unwrap_or(0).max(1);
+    let chart: String = days
+        .iter()
+        .map(|(day, views)| {
+            let bar_len = (views * 50 + max_views - 1) / max_views;
+            format!("{} | {} {}\n", day, "#".repeat(bar_len as usize), views)
+        })
+        .collect();
+
+    let _render = telemetry::template("paste_stats.html");
+    let html_page = assets::versioned(include_str!("paste_stats.html"))
+        .replace("{{token}}", &escape_html(&token))
+        .replace("{{days}}", &STATS_DAYS.to_string())
+        .replace("{{chart}}", &chart);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// Handles “POST /api/pastes”, creating a paste from JSON.
+// `encoding` is "utf-8" (the default) or "base64", which creates a binary paste from the decoded bytes.
+// Answers 201 with the token, the secret and the URL of the new paste.
+async fn api_create_paste(req: HttpRequest, body: Result<web::Bytes, actix_web::Error>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Write)?;
+    let limit = max_json_bytes(data.settings().max_paste_bytes);
+    let received = body::received(body, limit)?;
+    let claim = match claim_idempotency_key(&req, &data, &received)? {
+        Some(idempotency::Claimed::Replay(answer)) => return Ok(answer.response(true)),
+        Some(idempotency::Claimed::New(claim)) => Some(claim),
+        None => None,
+    };
+    let body: ApiNewPaste = body::json(&req, received, limit)?;
+    check_writable(&data)?;
+    check_daily_quota(&req, &data, Quota::Pastes, 1).await?;
+    let (encoding, mirror) = (body.encoding, body.mirror);
+    let paste = prepare_api_paste(&req, &data, None, body)?;
+    let stored = paste.clone();
+    store_write(&data, move |store| store.insert(&stored)).await?;
+
+    let mut created = created_paste(&paste, Some(paste.secret.clone()), encoding);
+    if mirror {
+        api_mirror(&data, &mut created).await?;
+    }
+    let answer = idempotency::Answer::json(StatusCode::CREATED, Some(created.url.clone()), &created)
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    Ok(idempotency::respond(claim, answer))
+}
+
+// The claim of the `Idempotency-Key` of a request creating pastes, `None` without one, or the answer it got the first
+// time. The key belongs to the API token of the request, to its client's address without one.
+fn claim_idempotency_key<'a>(req: &HttpRequest, data: &'a AppState, body: &[u8]) -> Result<Option<idempotency::Claimed<'a>>, AppError> {
+    let key = match req.headers().get("Idempotency-Key") {
+        Some(key) => key
+            .to_str()
+            .map_err(|e| e.to_string())
+            .and_then(idempotency::check_key)
+            .map_err(AppError::bad_request)?,
+        None => return Ok(None),
+    };
+    let owner = match bearer(req) {
+        Some(token) => api_tokens::hashed(token),
+        None => client::hashed_ip(client::client_ip(req, &data.trusted_proxies), &data.ip_salt),
+    };
+    let fingerprint = idempotency::fingerprint(req.path(), body);
+    match idempotency::claim(&data.idempotency, format!("api:{}:{}", owner, key), fingerprint, store::now()) {
+        Ok(claimed) => Ok(Some(claimed)),
+        Err(refused) => Err(AppError::new(error::ErrorCode::Conflict, refused.message())),
+    }
+}
+
+// Handles “PUT /api/pastes/{token}”, creating a paste at a token picked by the client, see `token::check_chosen`,
+// so publishing the same paste again is harmless:
+// - a free token gets the paste, answered with 201 like “POST /api/pastes”;
+// - a paste with the same content there already is left alone, 200 without its secret;
+// - a different paste there is a 409, unless `key` is its secret or the request carries its creator cookie:
+//   then it's replaced, keeping that secret and creator, and its title unless one is given or it was made up.
+// Of two requests racing for a free token only one inserts, the primary key turns the other away
+// and it gets the answer for the paste that won.
+async fn api_put_paste(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    body: Result<web::Bytes, actix_web::Error>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Write)?;
+    let token = token.into_inner();
+    token::check_chosen(&token).map_err(AppError::bad_request)?;
+    let limit = max_json_bytes(data.settings().max_paste_bytes);
+    let body: ApiNewPaste = body::json(&req, body::received(body, limit)?, limit)?;
+    check_writable(&data)?;
+    check_daily_quota(&req, &data, Quota::Pastes, 1).await?;
+    let (encoding, mirror) = (body.encoding, body.mirror);
+    let titled = body.title.as_deref().is_some_and(|title| !title.trim().is_empty());
+    let mut paste = prepare_api_paste(&req, &data, Some(token.clone()), body)?;
+
+    let (existing, content) = match data.store.open_content(&token)? {
+        Some(existing) => existing,
+        // The error of the insert comes back as it is, a conflict is told apart
+        None => match store_write(&data, {
+            let stored = paste.clone();
+            move |store| Ok(store.insert(&stored))
+        })
+        .await?
+        {
+            Ok(()) => return put_answer(&data, HttpResponse::Created(), paste, encoding, mirror).await,
+            Err(e) if e.is_conflict() => match data.store.open_content(&token)? {
+                Some(existing) => existing,
+                // What's in the way is an expired paste the cleanup hasn't deleted yet
+                None => {
+                    let stored = paste.clone();
+                    store_write(&data, move |store| store.replace(&stored, None)).await?;
+                    return put_answer(&data, HttpResponse::Created(), paste, encoding, mirror).await;
+                }
+            },
+            Err(e) => return Err(e.into()),
+        },
+    };
+
+    let same = existing.redirect == paste.redirect
+        && match (content, &paste.data) {
+            (store::Content::Binary(bytes), Some(data)) => bytes == *data,
+            (store::Content::Binary(_), None) | (_, Some(_)) => false,
+            (text, None) => text.into_string().map_err(store::StoreError::from)? == paste.content,
+        };
+    if same {
+        paste.secret = String::new();
+        paste.pending = existing.pending;
+        return put_answer(&data, HttpResponse::Ok(), paste, encoding, mirror).await;
+    }
+    if creator_key(&req, &data, &existing, query.key.as_deref()).is_none() {
+        return Err(AppError::new(
+            error::ErrorCode::Conflict,
+            "A different paste already has this token, give its key to replace it",
+        ));
+    }
+
+    paste.secret = existing.secret;
+    paste.creator = existing.creator;
+    // A title someone gave stays, one made from the content is made again from the new one
+    if !titled && !existing.title_auto && !existing.title.is_empty() {
+        paste.title = existing.title;
+        paste.title_auto = false;
+    }
+    let audit = audit_entry(&req, &data, "creator", "replace", Some(token.clone()), String::new());
+    let stored = paste.clone();
+    store_write(&data, move |store| store.replace(&stored, Some(&audit))).await?;
+    data.cache.remove(&token);
+    put_answer(&data, HttpResponse::Ok(), paste, encoding, mirror).await
+}
+
+// The answer of `api_put_paste`, the secret left out when it's empty, once the mirror asked for is started.
+async fn put_answer(
+    data: &web::Data<AppState>,
+    mut response: actix_web::dev::HttpResponseBuilder,
+    paste: NewPaste,
+    encoding: Encoding,
+    mirror: bool,
+) -> Result<HttpResponse, AppError> {
+    let secret = Some(paste.secret.clone()).filter(|secret| !secret.is_empty());
+    let mut created = created_paste(&paste, secret, encoding);
+    if mirror {
+        api_mirror(data, &mut created).await?;
+    }
+    Ok(response.header("Location", created.url.as_str()).json(created))
+}
+
+// Starts the mirror to a gist asked for with `mirror`, linking its status page in the answer when it carries the secret.
+async fn api_mirror(data: &web::Data<AppState>, created: &mut ApiCreatedPaste) -> Result<(), AppError> {
+    start_gist_mirror(data, &created.token).await?;
+    if let Some(secret) = &created.secret {
+        created.gist = Some(format!("/paste/{}/gist?key={}", created.token, secret));
+    }
+    Ok(())
+}
+
+// Handles “POST /api/pastes/batch”, up to `BATCH_MAX_PASTES` pastes in one request, stored in one transaction.
+// Each paste is checked like on “POST /api/pastes”, and the answer is an array with one result per paste,
+// in the same order: its token, secret and URL, or its error. The valid pastes are stored even when others aren't,
+// unless `atomic` is set: then the first invalid paste fails the whole batch with its error and nothing is stored.
+// A batch with too many pastes, or more content than one paste may have, is a 413 stating the limits.
+// Every paste of the batch counts against the daily quota.
+async fn api_create_batch(req: HttpRequest, body: Result<web::Bytes, actix_web::Error>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Write)?;
+    let max_paste_bytes = data.settings().max_paste_bytes;
+    let limit = max_json_bytes(max_paste_bytes);
+    let received = body::received(body, limit)?;
+    let claim = match claim_idempotency_key(&req, &data, &received)? {
+        Some(idempotency::Claimed::Replay(answer)) => return Ok(answer.response(true)),
+        Some(idempotency::Claimed::New(claim)) => Some(claim),
+        None => None,
+    };
+    let batch: ApiNewBatch = body::json(&req, received, limit)?;
+    let content_bytes: usize = batch.pastes.iter().map(|paste| paste.content.len()).sum();
+    if batch.pastes.len() > BATCH_MAX_PASTES || content_bytes > max_paste_bytes {
+        return Err(AppError::new(
+            error::ErrorCode::PayloadTooLarge,
+            format!(
+                "A batch can hold at most {} pastes and {} bytes of content in total",
+                BATCH_MAX_PASTES, max_paste_bytes
+            ),
+        ));
+    }
+    check_writable(&data)?;
+    check_daily_quota(&req, &data, Quota::Pastes, batch.pastes.len() as i64).await?;
+
+    let prepared: Vec<(Encoding, bool, Result<NewPaste, AppError>)> = batch
+        .pastes
+        .into_iter()
+        .map(|paste| (paste.encoding, paste.mirror, prepare_api_paste(&req, &data, None, paste)))
+        .collect();
+    if batch.atomic {
+        if let Some((index, (_, _, Err(e)))) = prepared.iter().enumerate().find(|(_, (_, _, paste))| paste.is_err()) {
+            return Err(AppError::new(e.code, format!("Paste {} of the batch: {}", index + 1, e.message)));
+        }
+    }
+
+    let mut valid = Vec::new();
+    let mut results = Vec::with_capacity(prepared.len());
+    // Indexes in `results` of the pastes to mirror, once they are stored
+    let mut mirrors = Vec::new();
+    for (encoding, mirror, paste) in prepared {
+        match paste {
+            Ok(paste) => {
+                if mirror {
+                    mirrors.push(results.len());
+                }
+                results.push(ApiBatchResult::Created(created_paste(&paste, Some(paste.secret.clone()), encoding)));
+                valid.push(paste);
+            }
+            Err(e) => results.push(ApiBatchResult::Failed {
+                error: ApiBatchError {
+                    code: e.code.as_str().to_string(),
+                    message: e.message,
+                },
+            }),
+        }
+    }
+    store_write(&data, move |store| store.insert_batch(&valid)).await?;
+    for index in mirrors {
+        if let ApiBatchResult::Created(created) = &mut results[index] {
+            api_mirror(&data, created).await?;
+        }
+    }
+
+    let answer = idempotency::Answer::json(StatusCode::OK, None, &results).map_err(|e| AppError::internal(e.to_string()))?;
+    Ok(idempotency::respond(claim, answer))
+}
+
+// What the API answers for a paste it created or replaced, what the page of a new paste shows (see `handoff.rs`).
+fn created_paste(paste: &NewPaste, secret: Option<String>, encoding: Encoding) -> ApiCreatedPaste {
+    ApiCreatedPaste {
+        token: paste.token.clone(),
+        secret,
+        url: format!("/paste/{}", paste.token),
+        raw_url: (!paste.redirect).then(|| format!("/paste/{}/raw", paste.token)),
+        expires_at: paste.expires_at,
+        encoding,
+        status: review::Status::of(paste.pending),
+        gist: None,
+    }
+}
+
+// Checks a paste sent to the API and makes its row, see `prepare_paste`, after `check_mirror` and `api_body`.
+fn prepare_api_paste(req: &HttpRequest, data: &AppState, token: Option<String>, body: ApiNewPaste) -> Result<NewPaste, AppError> {
+    check_mirror(data, body.mirror, body.paste_type, body.encoding)?;
+    let paste_body = api_body(body.paste_type, body.encoding, body.content)?;
+    let paste = prepare_paste(
+        data,
+        token,
+        paste_body,
+        body.public,
+        &body.tags.join(","),
+        body.expires.as_deref().unwrap_or(""),
+        body.normalize_line_endings.unwrap_or(data.settings().normalize_line_endings),
+    )?;
+    let paste = with_title(paste, body.title.as_deref())?;
+    Ok(for_review(
+        req,
+        data,
+        NewPaste {
+            comments: body.comments.unwrap_or(true),
+            ..paste
+        },
+    ))
+}
+
+// Asking for a mirror is refused for a paste that can't have one, or when mirroring is off.
+fn check_mirror(data: &AppState, mirror: bool, paste_type: PasteType, encoding: Encoding) -> Result<(), AppError> {
+    if !mirror {
+        return Ok(());
+    }
+    if data.gist.is_none() {
+        return Err(AppError::bad_request("Mirroring to gists is not enabled on this server"));
+    }
+    if paste_type == PasteType::Redirect || matches!(encoding, Encoding::Base64) {
+        return Err(AppError::bad_request("Only text pastes can be mirrored to a gist"));
+    }
+    Ok(())
+}
+
+// The content of a paste sent to the API as `type` and `encoding` say, base64 decoded for a binary paste.
+fn api_body(paste_type: PasteType, encoding: Encoding, content: String) -> Result<PasteBody, AppError> {
+    Ok(match (paste_type, encoding) {
+        (PasteType::Redirect, Encoding::Utf8) => PasteBody::Redirect(content),
+        (PasteType::Redirect, Encoding::Base64) => {
+            return Err(AppError::bad_request("A redirect holds a URL, it can't be base64-encoded"));
+        }
+        (PasteType::Text, Encoding::Utf8) => PasteBody::Text(content),
+        (PasteType::Text, Encoding::Base64) => PasteBody::Binary(
+            base64::decode(&content).map_err(|e| AppError::bad_request(format!("The content is not valid base64: {}", e)))?,
+        ),
+    })
+}
+
+// Handles “POST /api/pastes/validate”, running the checks creating the paste would run, without creating it
+// or writing anything: the JSON of “POST /api/pastes” (`token` for the checks of a `PUT`), or instead of `content`
+// only its `size` in bytes (decoded, for base64) and optionally its `sha256`, so a large paste can be checked before
+// sending it. The answer says which checks passed, failed or couldn't run without the content (`skipped`),
+// and where the client stands with the daily paste quota, which is only looked at.
+// Given the content hash, `public_copy` is the “/h/{hash}” link of a public paste with this content already.
+// Checks count against a quota of their own, `PASTRY_DAILY_VALIDATE_QUOTA`, kept in memory.
+async fn api_validate_paste(req: HttpRequest, body: Result<web::Bytes, actix_web::Error>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Write)?;
+    let settings = data.settings();
+    let limit = max_json_bytes(settings.max_paste_bytes);
+    let body: ApiValidatePaste = body::json(&req, body::received(body, limit)?, limit)?;
+    let unchecked = match (body.content, body.size) {
+        (Some(content), None) if body.sha256.is_none() => Some(content),
+        (None, Some(_)) => None,
+        _ => return Err(AppError::bad_request("Give either the content, or its size and optionally its sha256")),
+    };
+    let sha256 = match body.sha256 {
+        Some(hash) if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => Some(hash.to_ascii_lowercase()),
+        Some(_) => return Err(AppError::bad_request("The sha256 of the content is 64 hex digits")),
+        None => None,
+    };
+    check_daily_quota(&req, &data, Quota::Validations, 1).await?;
+
+    let mut checks = Vec::new();
+    if let Some(token) = &body.token {
+        checks.push(ApiCheck::new("token", Some(token::check_chosen(token).map_err(AppError::bad_request))));
+    }
+    checks.push(ApiCheck::new("mirror", Some(check_mirror(&data, body.mirror, body.paste_type, body.encoding))));
+
+    // Content that can't be decoded has nothing more to check, its size included
+    let (decoding, unchecked) = match unchecked {
+        Some(content) => match api_body(body.paste_type, body.encoding, content) {
+            Ok(paste_body) => (Some(Ok(())), Some(Unchecked::Body(paste_body))),
+            Err(e) => (Some(Err(e)), None),
+        },
+        None => (None, body.size.map(Unchecked::Size)),
+    };
+    let decoded = unchecked.is_some();
+    let PasteChecks { content, size, tags, expiry } = check_paste(
+        &data,
+        unchecked.unwrap_or(Unchecked::Size(0)),
+        &body.tags.join(","),
+        body.expires.as_deref().unwrap_or(""),
+        body.normalize_line_endings.unwrap_or(settings.normalize_line_endings),
+    );
+    let (size_bytes, hash) = match &content {
+        Some(Ok(checked)) => {
+            let bytes = match checked {
+                PasteBody::Text(text) | PasteBody::Redirect(text) => text.as_bytes(),
+                PasteBody::Binary(bytes) => bytes,
+            };
+            (Some(bytes.len()), Some(store::content_hash(bytes)))
+        }
+        Some(Err(_)) => (None, None),
+        None => (body.size.filter(|_| decoded), sha256),
+    };
+    checks.push(ApiCheck::new("encoding", decoding));
+    checks.push(ApiCheck::new("content", content.map(|content| content.map(|_| ()))));
+    checks.push(ApiCheck::new("size", Some(size).filter(|_| decoded)));
+    checks.push(ApiCheck::new("tags", Some(tags.map(|_| ()))));
+    let title = title::check(body.title.as_deref().unwrap_or("")).map_err(AppError::bad_request);
+    checks.push(ApiCheck::new("title", Some(title.map(|_| ()))));
+    checks.push(ApiCheck::new("expiry", Some(expiry.map(|_| ()))));
+
+    let quota = if settings.daily_paste_quota == 0 {
+        None
+    } else {
+        let client = client::hashed_ip(client::client_ip(&req, &data.trusted_proxies), &data.ip_salt);
+        let used = data.store.creations_today(&client)?;
+        let resets_at = quota_reset();
+        let quota = QuotaState {
+            limit: settings.daily_paste_quota,
+            used,
+            resets_at,
+        };
+        let left = if quota.remaining() > 0 {
+            Ok(())
+        } else {
+            Err(quota_reached(Quota::Pastes, quota.limit, resets_at))
+        };
+        checks.push(ApiCheck::new("quota", Some(left)));
+        Some(ApiQuota {
+            limit: quota.limit,
+            remaining: quota.remaining(),
+            resets_at,
+        })
+    };
+
+    let public_copy = match &hash {
+        Some(hash) => data.store.find_by_hash(hash, 1)?.first().map(|found| format!("/h/{}", found.hash)),
+        None => None,
+    };
+    Ok(HttpResponse::Ok().json(ApiValidation {
+        valid: checks.iter().all(|check| check.status != "failed"),
+        checks,
+        size: size_bytes,
+        content_hash: hash,
+        public_copy,
+        max_paste_bytes: settings.max_paste_bytes,
+        daily_paste_quota: quota,
+    }))
+}
+
+// Handles “/api/pastes/{token}”, a paste with its content and tags as JSON, counting as a view.
+// Binary pastes come base64-encoded, `encoding` tells which one it is.
+async fn api_get_paste(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let cached = view_paste(&req, &data, &token).await?;
+    Ok(HttpResponse::Ok().json(api_paste(cached, true, true)?))
+}
+
+// Handles “/api/h/{hash}”, the public paste with this content hash as “/api/pastes/{token}” returns it.
+// A prefix matching several hashes is a 300 listing them:
+//
+//     {"matches": [{"hash": "…", "url": "/api/h/…"}, …]}
+async fn api_hash_paste(req: HttpRequest, hash: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    match lookup_hash(&data, &hash)? {
+        HashLookup::Paste(token) => api_get_paste(req, web::Path::from(token), data).await,
+        HashLookup::Choices(matches) => {
+            let matches: Vec<ApiHashMatch> = matches
+                .into_iter()
+                .map(|found| ApiHashMatch {
+                    url: format!("/api/h/{}", found.hash),
+                    hash: found.hash,
+                })
+                .collect();
+            Ok(HttpResponse::MultipleChoices().json(ApiHashMatches { matches }))
+        }
+    }
+}
+
+// Handles “GET /api/pastes?tokens=a,b,c”, several pastes in one request, and “POST /api/pastes/fetch”
+// with the tokens in a JSON array for lists too long for a URL.
+// The answer maps each token to its paste as “GET /api/pastes/{token}” returns it, or to null when it's
+// unknown or expired. With `include_content=false` the content is left out, and reading only the metadata
+// doesn't count as a view; with the content, each paste counts a view like a single fetch.
+// More than `FETCH_MAX_TOKENS` tokens are refused.
+async fn api_fetch_pastes(req: HttpRequest, query: web::Query<FetchQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let tokens: Vec<String> = query
+        .tokens
+        .split(',')
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+        .collect();
+    fetch_pastes(&data, tokens, query.include_content.unwrap_or(true)).await
+}
+
+async fn api_fetch_pastes_post(req: HttpRequest, body: web::Json<ApiFetch>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let body = body.into_inner();
+    fetch_pastes(&data, body.tokens, body.include_content.unwrap_or(true)).await
+}
+
+async fn fetch_pastes(data: &AppState, tokens: Vec<String>, include_content: bool) -> Result<HttpResponse, AppError> {
+    let tokens: BTreeSet<String> = tokens.into_iter().collect();
+    if tokens.len() > FETCH_MAX_TOKENS {
+        return Err(AppError::bad_request(format!(
+            "At most {} pastes can be fetched at once",
+            FETCH_MAX_TOKENS
+        )));
+    }
+
+    let mut pastes = BTreeMap::new();
+    for token in tokens {
+        let paste = match load_paste(data, &token, include_content, Reader::Anyone).await? {
+            Some(cached) => Some(api_paste(cached, include_content, include_content)?),
+            None => None,
+        };
+        pastes.insert(token, paste);
+    }
+    Ok(HttpResponse::Ok().json(pastes))
+}
+
+// A paste as the API returns it, without the content unless `include_content`.
+// `viewed` is whether this request counted a view, which the counter loaded before it doesn't have yet; HEAD
+// requests pass it too, to answer what GET would.
+fn api_paste(cached: CachedPaste, include_content: bool, viewed: bool) -> Result<ApiPaste, AppError> {
+    let CachedPaste {
+        paste,
+        content,
+        tags: paste_tags,
+    } = cached;
+
+    // Text is only read when it's asked for, or for rows that don't have their size yet
+    let (content, encoding, size) = match (content, paste.size) {
+        (store::Content::Binary(bytes), size) => {
+            let size = size.unwrap_or_else(|| ContentSize::of_binary(&bytes));
+            (Some(base64::encode(bytes)).filter(|_| include_content), Encoding::Base64, size)
+        }
+        (_, Some(size)) if !include_content => (None, Encoding::Utf8, size),
+        (text, size) => {
+            let text = text.into_string().map_err(store::StoreError::from)?;
+            let size = size.unwrap_or_else(|| ContentSize::of_text(&text));
+            (Some(text).filter(|_| include_content), Encoding::Utf8, size)
+        }
+    };
+
+    Ok(ApiPaste {
+        token: paste.token,
+        content,
+        encoding,
+        paste_type: if paste.redirect { PasteType::Redirect } else { PasteType::Text },
+        public: paste.public,
+        status: review::Status::of(paste.pending),
+        views: paste.views + i64::from(viewed && !paste.pending),
+        created_at: paste.created_at,
+        expires_at: paste.expires_at,
+        tags: paste_tags,
+        title: Some(paste.title).filter(|title| !title.is_empty()),
+        lines: size.lines,
+        chars: size.chars,
+        bytes: size.bytes,
+    })
+}
+
+// Handles “/api/limits”, what the server accepts from the client asking: paste and batch sizes, the expiry values
+// and its daily quota with what is left of it (that of its API token when it has one, see `daily_quota`), as the
+// settings in effect have them.
+// The quota also comes as `X-RateLimit-*` headers, the same ones creating a paste answers with.
+async fn api_limits(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let settings = data.settings();
+    let (limit, client) = daily_quota(&req, &data, Quota::Pastes);
+    let quota = if limit == 0 {
+        None
+    } else {
+        let quota = QuotaState {
+            limit,
+            used: data.store.creations_today(&client)?,
+            resets_at: quota_reset(),
+        };
+        req.extensions_mut().insert(quota);
+        Some(ApiQuota {
+            limit: quota.limit,
+            remaining: quota.remaining(),
+            resets_at: quota.resets_at,
+        })
+    };
+
+    Ok(HttpResponse::Ok().json(ApiLimits {
+        max_paste_bytes: settings.max_paste_bytes,
+        max_batch_pastes: BATCH_MAX_PASTES,
+        max_fetch_tokens: FETCH_MAX_TOKENS,
+        expiry_options: EXPIRY_OPTIONS
+            .iter()
+            .map(|(name, seconds)| ApiExpiry { name, seconds: *seconds })
+            .collect(),
+        metadata_headers: &METADATA_HEADERS,
+        daily_paste_quota: quota,
+    }))
+}
+
+// Handles “/api/pastes/{token}/stats”, the same numbers as the stats page as JSON.
+async fn api_paste_stats(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let days = authorized_daily_views(&req, &token, &query, &data)?;
+
+    Ok(HttpResponse::Ok().json(PasteStats {
+        token: token.to_string(),
+        timezone: "UTC",
+        days: days
+            .into_iter()
+            .map(|(date, views)| DayViews { date, views })
+            .collect(),
+    }))
+}
+
+// Shared part of the stats handlers: 404 for an unknown paste, 403 when the key is missing or wrong
+// (and the creator cookie doesn't stand in for it), otherwise the views per day of the paste.
+fn authorized_daily_views(
+    req: &HttpRequest,
+    token: &str,
+    query: &KeyQuery,
+    data: &web::Data<AppState>,
+) -> Result<Vec<(String, i64)>, AppError> {
+    let paste = data
+        .store
+        .get(token)?
+        .ok_or_else(|| AppError::not_found("Paste not found"))?;
+    if creator_key(req, data, &paste, query.key.as_deref()).is_none() {
+        return Err(AppError::forbidden("A valid key is required to see the stats of this paste"));
+    }
+
+    Ok(data.store.daily_views(token, STATS_DAYS)?)
+}
+
+// Handles “POST /paste/{token}/delete”, the creator deleting their paste with the secret from the private link,
+// or with their creator cookie. Redirects to the index page once the paste is gone, to “/mine” when `back` asks for it.
+async fn delete_paste(
+    req: HttpRequest,
+    token: web::Path<String>,
+    form: web::Form<DeleteForm>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let paste = data
+        .store
+        .get(&token)?
+        .ok_or_else(|| AppError::not_found("Paste not found"))?;
+    if creator_key(&req, &data, &paste, form.key.as_deref()).is_none() {
+        return Err(AppError::forbidden("A valid key is required to delete this paste"));
+    }
+
+    let audit = audit_entry(&req, &data, "creator", "delete", Some(paste.token.clone()), String::new());
+    let deleted = paste.token.clone();
+    store_write(&data, move |store| store.delete(&deleted, Some(&audit))).await?;
+    data.cache.remove(&paste.token);
+
+    let back = if form.back.as_deref() == Some("mine") { "/mine" } else { "/" };
+    Ok(HttpResponse::SeeOther().header("Location", back).finish())
+}
+
+// Handles “DELETE /api/pastes/{token}”, deleting a paste with its `key` (or the creator cookie), or with any
+// token having the `delete` scope. Answers 204 once the paste is gone.
+async fn api_delete_paste(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let caller = authorize(&req, &data, api_tokens::Scope::Delete)?;
+    let paste = data
+        .store
+        .get(&token)?
+        .ok_or_else(|| AppError::not_found("Paste not found"))?;
+    let (actor, detail) = match caller {
+        Caller::Admin => ("admin", String::new()),
+        Caller::Token(api_token) => ("token", format!("token {} ({})", api_token.id, api_token.label)),
+        Caller::Anonymous => {
+            if creator_key(&req, &data, &paste, query.key.as_deref()).is_none() {
+                return Err(AppError::forbidden("A valid key is required to delete this paste"));
+            }
+            ("creator", String::new())
+        }
+    };
+
+    let audit = audit_entry(&req, &data, actor, "delete", Some(paste.token.clone()), detail);
+    let deleted = paste.token.clone();
+    store_write(&data, move |store| store.delete(&deleted, Some(&audit))).await?;
+    data.cache.remove(&paste.token);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Handles “/metrics”, counters and the times of the database calls in the Prometheus text format.
+async fn metrics(data: web::Data<AppState>) -> HttpResponse {
+    let cache = data.cache.stats();
+    let mut body = format!(
+        "# HELP pastry_cache_hits_total Paste reads answered from the cache.\n\
+         # TYPE pastry_cache_hits_total counter\n\
+         pastry_cache_hits_total {}\n\
+         # HELP pastry_cache_misses_total Paste reads that had to go to the store.\n\
+         # TYPE pastry_cache_misses_total counter\n\
+         pastry_cache_misses_total {}\n\
+         # HELP pastry_cache_entries Pastes currently in the cache.\n\
+         # TYPE pastry_cache_entries gauge\n\
+         pastry_cache_entries {}\n\
+         # HELP pastry_cache_bytes Bytes of content currently in the cache.\n\
+         # TYPE pastry_cache_bytes gauge\n\
+         pastry_cache_bytes {}\n",
+        cache.hits, cache.misses, cache.entries, cache.bytes,
+    );
+    let disk = data.disk.status();
+    body.push_str(
+        "# HELP pastry_disk_available_bytes Free space on the volumes written to, at the last check.\n\
+         # TYPE pastry_disk_available_bytes gauge\n",
+    );
+    for reading in &disk.readings {
+        if let Some(available) = reading.available {
+            body.push_str(&format!("pastry_disk_available_bytes{{volume=\"{}\"}} {}\n", reading.name, available));
+        }
+    }
+    body.push_str(&format!(
+        "# HELP pastry_disk_read_only Whether new data is refused for lack of disk space.\n\
+         # TYPE pastry_disk_read_only gauge\n\
+         pastry_disk_read_only {}\n",
+        u8::from(disk.read_only),
+    ));
+    body.push_str(&data.query_timings.render());
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+// An entry of the audit log for an action of a request, with the salted hash of its client's IP.
+fn audit_entry(
+    req: &HttpRequest,
+    data: &AppState,
+    actor: &'static str,
+    action: &'static str,
+    target: Option<String>,
+    detail: String,
+) -> store::AuditEntry {
+    store::AuditEntry {
+        actor,
+        action,
+        target,
+        ip_hash: Some(client::hashed_ip(client::client_ip(req, &data.trusted_proxies), &data.ip_salt)),
+        detail,
+    }
+}
+
+// Runs the write `op` on the store on the blocking pool rather than on the worker. A write finding the SQLite database
+// locked by another connection waits, for the busy timeout of the connection then `PASTRY_DB_WRITE_RETRY_MS` (see
+// `SqliteStore::write`), and a call to Postgres waits for the network: the worker serves its other connections
+// meanwhile. The handlers make their writes through here, with what `op` needs moved into it.
+async fn store_write<T, F>(data: &AppState, op: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn PasteStore) -> store::StoreResult<T> + Send + 'static,
+{
+    let store = data.store.clone();
+    match web::block(move || op(store.as_ref())).await {
+        Ok(value) => Ok(value),
+        Err(BlockingError::Error(e)) => Err(e.into()),
+        Err(BlockingError::Canceled) => Err(AppError::internal("The database call was canceled")),
+    }
+}
+
+// Adds an entry to the audit log for an action already done, which a failure to log doesn't undo.
+fn record_audit(store: &dyn PasteStore, entry: store::AuditEntry) {
+    if let Err(e) = store.record_audit(&entry) {
+        eprintln!("Audit log: failed to record `{}` by {}: {}", entry.action, entry.actor, e);
+    }
+}
+
+// Who a request of the API or of the admin routes comes from, after its `Authorization: Bearer` header.
+enum Caller {
+    Anonymous,
+    // The admin token, which has every scope
+    Admin,
+    Token(store::ApiToken),
+}
+
+// The token in the `Authorization: Bearer` header of a request.
+fn bearer(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// The caller of a request, a 401 when it carries a token that is neither the admin token nor an API token in effect.
+// Uses of API tokens are written at most once a minute, see `api_tokens::LastUsed`.
+fn caller(req: &HttpRequest, data: &AppState) -> Result<Caller, AppError> {
+    let given = match bearer(req) {
+        Some(given) => given,
+        None => return Ok(Caller::Anonymous),
+    };
+    if let Some(admin_token) = &data.admin_token {
+        if secret_matches(admin_token, given) {
+            return Ok(Caller::Admin);
+        }
+    }
+
+    let known = if given.starts_with(api_tokens::PREFIX) {
+        data.store.api_token(&api_tokens::hashed(given))?
+    } else {
+        None
+    };
+    let token = known.ok_or_else(|| AppError::unauthorized("Unknown token"))?;
+    let now = chrono::Utc::now().timestamp();
+    if token.revoked_at.is_some() {
+        return Err(AppError::unauthorized("This token was revoked"));
+    }
+    if token.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(AppError::unauthorized("This token has expired"));
+    }
+
+    if data.token_uses.lock().unwrap().due(token.id, now) {
+        // Recorded on the blocking pool without waiting, the request doesn't need it
+        let (store, id) = (data.store.clone(), token.id);
+        actix_web::rt::spawn(async move {
+            if let Err(BlockingError::Error(e)) = web::block(move || store.touch_api_token(id, now)).await {
+                eprintln!("API tokens: failed to record a use of token {}: {}", id, e);
+            }
+        });
+    }
+    Ok(Caller::Token(token))
+}
+
+// Checks that the caller of a request may use `scope`, answering 403 naming the scope to a token without it.
+// Requests without a token may use every scope but `admin`, unless `PASTRY_API_REQUIRE_TOKEN` is set.
+fn authorize(req: &HttpRequest, data: &AppState, scope: api_tokens::Scope) -> Result<Caller, AppError> {
+    let caller = caller(req, data)?;
+    match &caller {
+        Caller::Admin => {}
+        // Kept for `daily_quota`
+        Caller::Token(token) if scope.granted_by(&token.scopes) => {
+            req.extensions_mut().insert(token.clone());
+        }
+        Caller::Token(_) => return Err(AppError::missing_scope(scope.as_str())),
+        Caller::Anonymous if scope == api_tokens::Scope::Admin => {
+            return Err(AppError::unauthorized("A valid admin token is required"))
+        }
+        Caller::Anonymous if data.settings().api_require_token => {
+            return Err(AppError::unauthorized(format!("A token with the \"{}\" scope is required", scope.as_str())))
+        }
+        Caller::Anonymous => {}
+    }
+    Ok(caller)
+}
+
+// Checks that a request carries the admin token, or an API token with the `admin` scope, and returns which.
+// Admin routes answer 403 when no admin token is configured and 401 when the request doesn't carry the right one.
+fn require_admin(req: &HttpRequest, data: &AppState) -> Result<Caller, AppError> {
+    if data.admin_token.is_none() {
+        return Err(AppError::forbidden("Admin routes are disabled, set PASTRY_ADMIN_TOKEN to enable them"));
+    }
+    authorize(req, data, api_tokens::Scope::Admin)
+}
+
+// An admin page, with the banner telling when the disk runs low on top, see `disk.rs`.
+fn admin_template(data: &AppState, template: &str) -> String {
+    let disk = data.disk.status();
+    let banner = match disk.level {
+        disk::Level::Enough => String::new(),
+        level => {
+            let volumes: Vec<String> = disk
+                .readings
+                .iter()
+                .filter_map(|reading| Some(format!("{} {} free", reading.name, format_bytes(reading.available? as i64))))
+                .collect();
+            let state = if disk.read_only {
+                "new pastes, comments and collections are refused until space is freed"
+            } else if level == disk::Level::Full {
+                "below PASTRY_DISK_MIN_BYTES"
+            } else {
+                "below PASTRY_DISK_WARN_BYTES"
+            };
+            format!(
+                "<div class=\"disk-banner\" role=\"alert\">Low disk space ({}): {}.</div>",
+                escape_html(&volumes.join(", ")),
+                state
+            )
+        }
+    };
+    assets::versioned(template).replace("{{disk_banner}}", &banner)
+}
+
+// Who an admin is in what they record, “admin” for the admin token and “token <label>” for an API token.
+fn admin_name(caller: &Caller) -> String {
+    match caller {
+        Caller::Token(token) => format!("token {}", token.label),
+        Caller::Admin | Caller::Anonymous => "admin".to_string(),
+    }
+}
+
+// The timestamp of the start of the day `value` of the query parameter `name` (e.g. 2024-01-31, UTC),
+// or of `days_after` days after it; none when it's missing or empty.
+fn day_start(name: &str, value: &Option<String>, days_after: i64) -> Result<Option<i64>, AppError> {
+    match value.as_deref().filter(|value| !value.is_empty()) {
+        Some(value) => chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(|day| Some((day + chrono::Duration::days(days_after)).and_time(chrono::NaiveTime::MIN).and_utc().timestamp()))
+            .map_err(|_| AppError::bad_request(format!("`{}` must be a date like 2024-01-31", name))),
+        None => Ok(None),
+    }
+}
+
+// Handles “/api/openapi.json”, the OpenAPI description of the API, see `openapi.rs`.
+async fn api_openapi() -> HttpResponse {
+    HttpResponse::Ok().json(openapi::document())
+}
+
+// Fallback of the `/api` scope, so unknown API routes get the JSON 404 as well.
+async fn api_not_found() -> Result<HttpResponse, AppError> {
+    Err(AppError::not_found("No such API route"))
+}
+
+// Compares a paste's secret with the key given in a request.
+// An empty secret (pastes created before secrets existed) never matches,
+// and the comparison looks at every byte so its timing doesn't tell how much of the key was right.
+fn secret_matches(secret: &str, key: &str) -> bool {
+    if secret.is_empty() || secret.len() != key.len() {
+        return false;
+    }
+    secret
+        .bytes()
+        .zip(key.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+// The size line of pages, e.g. "87 lines, 3.2 KB", or "binary, 3.2 KB"
+// (only binary pastes have bytes but no lines).
+fn format_size(size: &ContentSize, texts: Texts) -> String {
+    let bytes = if size.bytes < 1024 { texts.plural("size.bytes", size.bytes) } else { format_bytes(size.bytes) };
+    if size.lines == 0 && size.bytes > 0 {
+        return texts.fill("size.binary", &[("bytes", &bytes)]);
+    }
+    format!("{}, {}", texts.plural("size.lines", size.lines), bytes)
+}
+
+// A byte count for people, e.g. "512 bytes", "3.2 KB", "1.5 MB" (powers of 1024).
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// Escapes the characters that have a meaning in HTML, so user content can be put inside a page.
+// Braces too: the pages are filled one `{{placeholder}}` after the other, and user text put in before another
+// placeholder could otherwise name it.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '{' => escaped.push_str("&#123;"),
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Deletes the expired pastes, the per-day view rows and the creation counts past their retention, all in one
+// transaction, then gives the freed space back where the store can. With `dry_run` only counts what would be deleted.
+fn purge(store: &dyn PasteStore, dry_run: bool) -> store::StoreResult<store::PurgeCounts> {
+    if dry_run {
+        return store.purge_preview();
+    }
+    let mut counts = store::PurgeCounts::default();
+    store.with_tx(&mut || {
+        counts = store::PurgeCounts {
+            expired_pastes: store.purge_expired()?,
+            daily_views: store.prune_daily_views()?,
+            quotas: store.prune_quotas()?,
+        };
+        Ok(())
+    })?;
+    store.reclaim_space()?;
+    Ok(counts)
+}
+
+// The detail of a purge in the audit log.
+fn purge_detail(counts: &store::PurgeCounts) -> String {
+    format!(
+        "{} expired pastes, {} daily view rows, {} quota rows",
+        counts.expired_pastes, counts.daily_views, counts.quotas
+    )
+}
+
+// `pastry_crust purge [--dry-run]`: runs `purge` once on the configured database and returns the exit code.
+// It goes through the same store as the server, so it can run while the server is up: SQLite locks the file
+// for each write, opening the database retries a lock like at startup, and a lock met during the purge stops it
+// with a hint instead of waiting.
+fn run_purge(store: &dyn PasteStore, dry_run: bool) -> i32 {
+    match purge(store, dry_run) {
+        Ok(counts) => {
+            if !dry_run {
+                let entry = store::AuditEntry {
+                    actor: "cli",
+                    action: "purge",
+                    target: None,
+                    ip_hash: None,
+                    detail: purge_detail(&counts),
+                };
+                record_audit(store, entry);
+            }
+            let verb = if dry_run { "Would delete" } else { "Deleted" };
+            println!("{} {} expired pastes", verb, counts.expired_pastes);
+            println!("{} {} old daily view rows", verb, counts.daily_views);
+            println!("{} {} old quota rows", verb, counts.quotas);
+            0
+        }
+        Err(e) if e.is_busy() => {
+            eprintln!("The database is locked by another connection, probably the running server.");
+            eprintln!("Run the purge again in a moment, or use POST /admin/purge on the server.");
+            1
+        }
+        Err(e) => {
+            eprintln!("Purge failed: {}", e);
+            1
+        }
+    }
+}
+
+// `pastry_crust fsck [--repair]`: runs `integrity::check` once on the configured database, prints what it found and
+// returns the exit code: 1 while problems remain, those found without `--repair` and those it can't repair.
+// Like `purge` it can run while the server is up.
+fn run_fsck(store: &dyn PasteStore, repair: bool) -> i32 {
+    match integrity::check(store, repair) {
+        Ok(report) => {
+            record_audit(store, store::AuditEntry {
+                actor: "cli",
+                action: "integrity_check",
+                target: None,
+                ip_hash: None,
+                detail: report.summary(),
+            });
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            if report.problems.len() < report.found() {
+                println!("… and {} more", report.found() - report.problems.len());
+            }
+            println!("{}", report.summary());
+            let unrepaired = report.missing_blobs + report.unreadable_pastes;
+            if unrepaired > 0 || (!repair && report.found() > 0) {
+                1
+            } else {
+                0
+            }
+        }
+        Err(e) if e.is_busy() => {
+            eprintln!("The database is locked by another connection, probably the running server.");
+            eprintln!("Run the check again in a moment, or use POST /admin/integrity-check on the server.");
+            1
+        }
+        Err(e) => {
+            eprintln!("Integrity check failed: {}", e);
+            1
+        }
+    }
+}
+
+// `pastry_crust export-static <dir>`: runs `export_static::export` once to `dir` and returns the exit code.
+// Like `purge` it can run while the server is up, and only reads the database.
+fn run_export_static(data: &AppState, dir: Option<&str>, assets_dir: Option<&Path>) -> i32 {
+    let dir = match dir {
+        Some(dir) => Path::new(dir),
+        None => {
+            eprintln!("Usage: pastry_crust export-static <dir>");
+            return 1;
+        }
+    };
+    match export_static::export(data, dir, assets_dir) {
+        Ok(export) => {
+            println!(
+                "Exported {} public pastes to {}: {} files written, {} unchanged",
+                export.pastes,
+                dir.display(),
+                export.written,
+                export.unchanged
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("Export failed: {}", e);
+            1
+        }
+    }
+}
+
+// Background task that keeps the database from growing forever.
+// Every `CLEANUP_INTERVAL` it deletes the expired pastes and the per-day view counters older than the retention window.
+// The deletes run on the blocking pool like the writes of the handlers (see `store_write`).
+async fn cleanup_task(data: web::Data<AppState>) {
+    loop {
+        let pass = data.clone();
+        let _ = web::block(move || -> Result<(), ()> {
+            cleanup(&pass);
+            Ok(())
+        })
+        .await;
+        actix_web::rt::time::delay_for(CLEANUP_INTERVAL).await;
+    }
+}
+
+// One pass of `cleanup_task`.
+fn cleanup(data: &AppState) {
+    match data.store.purge_expired() {
+        Ok(removed) => println!("Cleanup: removed {} expired pastes", removed),
+        Err(e) => eprintln!("Cleanup of expired pastes failed: {}", e),
+    }
+    match data.store.prune_daily_views() {
+        Ok(removed) => println!("Cleanup: removed {} old daily view rows", removed),
+        Err(e) => eprintln!("Cleanup of daily views failed: {}", e),
+    }
+    match data.store.prune_quotas() {
+        Ok(removed) => println!("Cleanup: removed {} old quota rows", removed),
+        Err(e) => eprintln!("Cleanup of quota rows failed: {}", e),
+    }
+    match data.store.prune_bans() {
+        Ok(removed) => println!("Cleanup: removed {} expired bans", removed),
+        Err(e) => eprintln!("Cleanup of expired bans failed: {}", e),
+    }
+    match data.store.prune_announcements() {
+        Ok(removed) => println!("Cleanup: removed {} ended announcements", removed),
+        Err(e) => eprintln!("Cleanup of ended announcements failed: {}", e),
+    }
+    match data.store.prune_collections() {
+        Ok(removed) => println!("Cleanup: removed {} expired collections", removed),
+        Err(e) => eprintln!("Cleanup of expired collections failed: {}", e),
+    }
+    if data.settings().archive_after_days > 0 {
+        match archive_idle(data) {
+            Ok(archived) => println!("Cleanup: archived {} idle pastes", archived),
+            Err(e) => eprintln!("Archiving idle pastes failed: {}", e),
+        }
+    }
+    let audit_retention_days = data.settings().audit_retention_days;
+    if audit_retention_days > 0 {
+        match data.store.prune_audit_log(store::now() - audit_retention_days * 24 * 60 * 60) {
+            Ok(removed) => println!("Cleanup: removed {} old audit log entries", removed),
+            Err(e) => eprintln!("Cleanup of the audit log failed: {}", e),
+        }
+    }
+}
+
+// Background task that reloads the bans every `bans::REFRESH_INTERVAL`, the first time at startup.
+async fn refresh_bans_task(data: web::Data<AppState>) {
+    let mut interval = actix_web::rt::time::interval(bans::REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = refresh_bans(&data) {
+            eprintln!("Bans: failed to load them: {}", e);
+        }
+    }
+}
+
+// Background task that reloads the announcements every `announcements::REFRESH_INTERVAL`, the first time at startup.
+async fn refresh_announcements_task(data: web::Data<AppState>) {
+    let mut interval = actix_web::rt::time::interval(announcements::REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = refresh_announcements(&data) {
+            eprintln!("Announcements: failed to load them: {}", e);
+        }
+    }
+}
+
+// Background task checking the free disk space every `PASTRY_DISK_CHECK_SECS`, the first time at startup.
+async fn disk_check_task(data: web::Data<AppState>) {
+    let mut interval = actix_web::rt::time::interval(data.disk.interval);
+    loop {
+        interval.tick().await;
+        data.disk.check(data.settings().disk);
+    }
+}
+
+// Background task sending the stats of this server to another instance every `pusher.interval`, the first time at
+// startup, see `federation.rs`. A report that doesn't go through is logged and not sent again.
+async fn push_stats_task(data: web::Data<AppState>, pusher: federation::Pusher) {
+    let mut interval = actix_web::rt::time::interval(pusher.interval);
+    loop {
+        interval.tick().await;
+        let report = match data.store.archive_stats() {
+            Ok(stats) => federation::Report::new(&pusher.instance, data.store.backend(), &stats),
+            Err(e) => {
+                eprintln!("Stats push: failed to count the pastes: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = pusher.push(&report).await {
+            eprintln!("Stats push: {}", e);
+        }
+    }
+}
+
+// Replaces the bans requests are checked against with those of the store.
+fn refresh_bans(data: &AppState) -> Result<(), AppError> {
+    let list = bans::BanList::new(&data.store.bans()?);
+    *data.bans.write().unwrap() = list;
+    Ok(())
+}
+
+// Replaces the announcements the pages show with those of the store.
+fn refresh_announcements(data: &AppState) -> Result<(), AppError> {
+    let board = announcements::Board::new(data.store.announcements()?);
+    *data.announcements.write().unwrap() = board;
+    Ok(())
+}
+
+// Whether the ban list refuses a request: one that may write (any method but GET, HEAD and OPTIONS) from a banned
+// client, outside the admin routes.
+fn banned(req: &actix_web::dev::ServiceRequest, data: &AppState) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) || req.path().starts_with("/admin/") {
+        return false;
+    }
+    let ip = client::client_ip_of(req.peer_addr(), req.headers(), &data.trusted_proxies);
+    let ip_hash = || client::hashed_ip(ip, &data.ip_salt);
+    data.bans.read().unwrap().bans(ip, ip_hash, chrono::Utc::now().timestamp())
+}
+
+#[derive(serde::Deserialize)]
+struct FormData {
+    content: String,
+    // Empty or left out for one made from the content, see `title.rs`
+    title: Option<String>,
+    // Checkbox, only sent by the browser when ticked
+    public: Option<String>,
+    // Comma-separated list of tags
+    tags: Option<String>,
+    // One of the values of `parse_expiry`
+    expires: Option<String>,
+    // Checkbox, makes the paste a short link to the URL it holds
+    shorten: Option<String>,
+    // Checkbox, turns comments off for the paste
+    no_comments: Option<String>,
+    // New on every index page, see `idempotency.rs`
+    nonce: Option<String>,
+    // What the captcha widget of the provider puts in the form, see `check_captcha`
+    #[serde(rename = "h-captcha-response")]
+    hcaptcha_response: Option<String>,
+    #[serde(rename = "cf-turnstile-response")]
+    turnstile_response: Option<String>,
+}
+
+// A paste to check with “/api/pastes/validate”, the fields of `ApiNewPaste` with `content` or `size`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ApiValidatePaste {
+    content: Option<String>,
+    // Bytes of the content, instead of it
+    size: Option<usize>,
+    // Hex SHA-256 of the content, along with `size`
+    sha256: Option<String>,
+    // The token a `PUT` would create the paste at
+    token: Option<String>,
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default, rename = "type")]
+    paste_type: PasteType,
+    #[serde(default)]
+    tags: Vec<String>,
+    title: Option<String>,
+    expires: Option<String>,
+    normalize_line_endings: Option<bool>,
+    #[serde(default)]
+    mirror: bool,
+}
+
+#[derive(serde::Serialize)]
+struct ApiHashMatches {
+    matches: Vec<ApiHashMatch>,
+}
+
+#[derive(serde::Serialize)]
+struct ApiHashMatch {
+    hash: String,
+    url: String,
+}
+
+#[derive(serde::Serialize)]
+struct ApiLimits {
+    max_paste_bytes: usize,
+    max_batch_pastes: usize,
+    max_fetch_tokens: usize,
+    expiry_options: Vec<ApiExpiry>,
+    // The headers describing the paste on raw and download responses
+    metadata_headers: &'static [&'static str],
+    // `null` when there is no quota
+    daily_paste_quota: Option<ApiQuota>,
+}
+
+#[derive(serde::Serialize)]
+struct ApiValidation {
+    // Whether no check failed
+    valid: bool,
+    checks: Vec<ApiCheck>,
+    // Bytes of the paste as it would be stored, `null` when the content didn't pass its checks
+    size: Option<usize>,
+    content_hash: Option<String>,
+    public_copy: Option<String>,
+    max_paste_bytes: usize,
+    // `null` when there is no quota
+    daily_paste_quota: Option<ApiQuota>,
+}
+
+#[derive(serde::Serialize)]
+struct ApiCheck {
+    name: &'static str,
+    // "passed", "failed", or "skipped" for a check of the content without it (only its size was given,
+    // or it couldn't be decoded)
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ApiBatchError>,
+}
+
+impl ApiCheck {
+    // The outcome of the check `name`, `None` when it didn't run.
+    fn new(name: &'static str, outcome: Option<Result<(), AppError>>) -> ApiCheck {
+        let (status, error) = match outcome {
+            Some(Ok(())) => ("passed", None),
+            Some(Err(e)) => (
+                "failed",
+                Some(ApiBatchError {
+                    code: e.code.as_str().to_string(),
+                    message: e.message,
+                }),
+            ),
+            None => ("skipped", None),
+        };
+        ApiCheck { name, status, error }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ApiExpiry {
+    name: &'static str,
+    // `null` for never
+    seconds: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct ApiQuota {
+    limit: i64,
+    remaining: i64,
+    #[serde(serialize_with = "timestamp::serialize")]
+    resets_at: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FetchQuery {
+    // Comma separated
+    tokens: String,
+    include_content: Option<bool>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ApiFetch {
+    tokens: Vec<String>,
+    include_content: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+struct DbQuery {
+    // "json" for the JSON variant
+    format: Option<String>,
+}
+
+// “METADATA.json” of a zip archive of a paste.
+#[derive(serde::Serialize)]
+struct ArchiveMetadata<'a> {
+    token: &'a str,
+    // `null` for a paste without one
+    title: Option<&'a str>,
+    #[serde(serialize_with = "timestamp::serialize")]
+    created_at: i64,
+    content_hash: Option<&'a str>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct KeyQuery {
+    key: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct DeleteForm {
+    key: Option<String>,
+    // "mine" to go back to “/mine” rather than to the index page
+    back: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct NewQuery {
+    // Token of the paste to fill the form from
+    from: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct WrapQuery {
+    mode: Wrap,
+    key: Option<String>,
+    links: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+struct LanguageForm {
+    lang: String,
+    back: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PasteQuery {
+    key: Option<String>,
+    // Whether URLs in the text are turned into links
+    links: Option<bool>,
+}
+
+#[derive(serde::Serialize)]
+struct PasteStats {
+    token: String,
+    timezone: &'static str,
+    days: Vec<DayViews>,
+}
+
+#[derive(serde::Serialize)]
+struct DayViews {
+    date: String,
+    views: i64,
+}
+
+#[derive(serde::Serialize)]
+struct VersionInfo {
+    #[serde(flatten)]
+    build: version::BuildInfo,
+    token_strategy: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct Health {
+    status: &'static str,
+    database: &'static str,
+    build: version::BuildInfo,
+}
+
+#[derive(serde::Deserialize)]
+struct PopularQuery {
+    days: Option<i64>,
+    tag: Option<String>,
+}
+
+
+
+// Moves the pastes not viewed for `archive_after_days` days to the archive, one batch at a time.
+fn archive_idle(data: &AppState) -> store::StoreResult<usize> {
+    let idle_since = store::now() - data.settings().archive_after_days * SECONDS_PER_DAY;
+    let mut archived = 0;
+    loop {
+        let moved = data.store.archive_idle(idle_since, ARCHIVE_BATCH)?;
+        archived += moved;
+        if moved < ARCHIVE_BATCH {
+            return Ok(archived);
+        }
+    }
+}
+
+// Reads an environment variable, falling back to `default` when it's unset or doesn't parse.
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Ignoring {}={:?}, it is not a valid value", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+// The values of `flag` on the command line, `--bind 0.0.0.0:8080` or `--bind=0.0.0.0:8080`, which can be repeated.
+// `None` when it isn't given, an error when it is given without a value.
+fn arg_values(flag: &str) -> Result<Option<Vec<String>>, String> {
+    let mut values = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            values.push(args.next().ok_or_else(|| format!("{} needs an address", flag))?);
+        } else if let Some(value) = arg.strip_prefix(flag).and_then(|rest| rest.strip_prefix('=')) {
+            values.push(value.to_string());
+        }
+    }
+    Ok(if values.is_empty() { None } else { Some(values) })
+}
+
+// Addresses to listen on: those of `flag` on the command line, otherwise those of the configuration file,
+// otherwise the comma separated ones of the environment variable `name`, otherwise `default`.
+fn bind_addresses(flag: &str, file: &Option<Vec<String>>, name: &str, default: &[&str]) -> Vec<String> {
+    let from_args = match arg_values(flag) {
+        Ok(values) => values,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match from_args.or_else(|| file.clone()) {
+        Some(addrs) => addrs,
+        None => match std::env::var(name) {
+            Ok(addrs) => addrs.split(',').map(str::trim).filter(|addr| !addr.is_empty()).map(str::to_string).collect(),
+            Err(_) => default.iter().map(|addr| addr.to_string()).collect(),
+        },
+    }
+}
+
+// Binds every address of `addrs` or exits, naming the address that failed: serving on only some of them
+// would go unnoticed.
+fn bind_or_exit(addrs: &[String]) -> Vec<std::net::TcpListener> {
+    addrs
+        .iter()
+        .map(|addr| match listen::bind(addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Can't listen on {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        })
+        .collect()
+}
+
+// The routes only answered on the internal listeners when there are some (`PASTRY_INTERNAL_BIND`).
+fn internal_only(path: &str) -> bool {
+    path == "/metrics" || path.starts_with("/admin/")
+}
+
+// A setting from the configuration file when it sets it, otherwise from its environment variable.
+fn setting<T: std::str::FromStr + Clone>(file: &Option<T>, name: &str, default: T) -> T {
+    match file {
+        Some(value) => value.clone(),
+        None => env_or(name, default),
+    }
+}
+
+// Reads the configuration file again on every SIGHUP, see `config.rs`.
+// Only `Settings` change, a key that needs a restart is logged and keeps its value until then.
+// actix keeps limiting bodies to what `started_max_paste_bytes` allowed, the limit at startup.
+async fn reload_on_hangup(data: web::Data<AppState>, path: Option<PathBuf>, mut config: Config, started_max_paste_bytes: usize) {
+    let mut hangups = match actix_web::rt::signal::unix::signal(actix_web::rt::signal::unix::SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            eprintln!("Can't listen for SIGHUP, the configuration won't be reloaded: {}", e);
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        let path = match &path {
+            Some(path) => path,
+            None => {
+                println!("SIGHUP received, but there is no configuration file to reload (see PASTRY_CONFIG)");
+                continue;
+            }
+        };
+        let reloaded = match Config::load(path) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                eprintln!("Configuration not reloaded, keeping the current one: {}", e);
+                continue;
+            }
+        };
+
+        for key in config.restart_changes(&reloaded) {
+            println!("Configuration reload: `{}` changed, ignored until the next restart", key);
+        }
+        let changed = config.hot_changes(&reloaded);
+        let settings = Settings::resolve(&reloaded);
+        if settings.max_paste_bytes > started_max_paste_bytes {
+            println!(
+                "Configuration reload: bodies bigger than what `max_paste_bytes` = {} allowed are refused until the next restart",
+                started_max_paste_bytes
+            );
+        }
+        *data.settings.write().unwrap() = Arc::new(settings);
+        config = reloaded;
+        println!("Configuration reloaded from {}", path.display());
+        if !changed.is_empty() {
+            let entry = store::AuditEntry {
+                actor: "sighup",
+                action: "reload",
+                target: None,
+                ip_hash: None,
+                detail: changed.join(", "),
+            };
+            let _ = store_write(&data, move |store| {
+                record_audit(store, entry);
+                Ok(())
+            })
+            .await;
+        }
+    }
+}
+
+// actix only accepts 16 KiB of form data by default, far below what the blob store is meant for.
+// Bodies get some room on top of the paste limit, which `create_paste` checks on the decoded content:
+// percent-encoding can make form content three times bigger, base64 makes JSON content a third bigger.
+// Compressed bodies are held to the same limits, as sent and once decompressed.
+fn max_form_bytes(max_paste_bytes: usize) -> usize {
+    max_paste_bytes * 3 + 64 * 1024
+}
+
+fn max_json_bytes(max_paste_bytes: usize) -> usize {
+    max_paste_bytes / 3 * 4 + 64 * 1024
+}
+
+// What the app of every worker is built with, fixed at startup.
+#[derive(Clone)]
+struct AppSetup {
+    // The addresses of `--internal-bind`, the only ones serving admin and metrics when there are some
+    internal_addrs: Vec<SocketAddr>,
+    request_timeout: Duration,
+    max_form_bytes: usize,
+    max_json_bytes: usize,
+    assets_dir: Option<PathBuf>,
+}
+
+// The app of a worker: every route, behind the middleware all requests go through.
+fn app(
+    app_state: web::Data<AppState>,
+    setup: &AppSetup,
+) -> App<impl ServiceFactory<Config = (), Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error, InitError = ()>, Body> {
+    let internal_addrs = setup.internal_addrs.clone();
+    let request_timeout = setup.request_timeout;
+    let (max_form_bytes, max_json_bytes) = (setup.max_form_bytes, setup.max_json_bytes);
+    let assets_dir = setup.assets_dir.as_deref();
+    App::new()
+        .app_data(app_state.clone())
+        // Banned clients are turned away before the handler runs, see `bans.rs`
+        .wrap_fn(|req, srv| {
+            let refused = req.app_data::<web::Data<AppState>>().is_some_and(|data| banned(&req, data));
+            let api = req.path() == "/api" || req.path().starts_with("/api/");
+            let response = if refused { None } else { Some(srv.call(req)) };
+            async move {
+                match response {
+                    Some(response) => response.await,
+                    None if api => Err(error::ApiError(AppError::forbidden(bans::REFUSED)).into()),
+                    None => Err(AppError::forbidden(bans::REFUSED).into()),
+                }
+            }
+        })
+        // actix tells which listener a request came in on by its address
+        .wrap_fn(move |req, srv| {
+            let refused = !internal_addrs.is_empty()
+                && internal_only(req.path())
+                && !internal_addrs.contains(&req.app_config().local_addr());
+            let response = if refused { None } else { Some(srv.call(req)) };
+            async move {
+                match response {
+                    Some(response) => response.await,
+                    None => Err(AppError::not_found("Page not found").into()),
+                }
+            }
+        })
+        // Every request has `PASTRY_REQUEST_TIMEOUT_SECS` to be answered, reading its body included, or gets a 503.
+        // The admin routes are left out, a backup can take longer, and streamed bodies are sent after the handler
+        // returned, so long downloads aren't cut. The timer can only fire while the handler waits (on the client,
+        // on a blocking thread, the store writes among them, see `store_write`): a store read holds the worker until
+        // it returns, `PASTRY_DB_TIMEOUT_MS` bounds that.
+        .wrap_fn(move |req, srv| {
+            let started = Instant::now();
+            let method = req.method().clone();
+            let path = req.path().to_string();
+            let response = srv.call(req);
+            async move {
+                if request_timeout.as_secs() == 0 || path.starts_with("/admin/") {
+                    return response.await;
+                }
+                match actix_web::rt::time::timeout(request_timeout, response).await {
+                    Ok(response) => response,
+                    Err(_) => {
+                        eprintln!("{} {} aborted after {:.1?}", method, path, started.elapsed());
+                        let error = AppError::new(
+                            error::ErrorCode::Unavailable,
+                            format!("The request took longer than {} seconds", request_timeout.as_secs()),
+                        );
+                        if path == "/api" || path.starts_with("/api/") {
+                            Err(error::ApiError(error).into())
+                        } else {
+                            Err(error.into())
+                        }
+                    }
+                }
+            }
+        })
+        // Error pages in the language of the request, see `error::localized`
+        .wrap_fn(|req, srv| {
+            let texts = Texts::asked(req.headers());
+            let response = srv.call(req);
+            async move { error::localized(texts, response.await) }
+        })
+        // Outermost, so the span of a request also covers the timeout above
+        .wrap_fn(|req, srv| {
+            let span = telemetry::request_span(&req);
+            telemetry::traced(span, srv.call(req))
+        })
+        .app_data(web::PayloadConfig::new(max_form_bytes))
+        .app_data(web::FormConfig::default().limit(max_form_bytes).error_handler(|err, _req| {
+            AppError::new(error::ErrorCode::from_status(err.status_code()), err.to_string()).into()
+        }))
+        .configure(|cfg| static_routes(cfg, assets_dir))
+        // Where the stylesheet used to be served from, kept for old links
+        .route(
+            reserved::route("/style.css"),
+            web::get().to(|| HttpResponse::MovedPermanently().header("Location", "/static/style.css").finish()),
+        )
+        .route(reserved::route("/"), web::get().to(index))
+        .route(reserved::route("/new"), web::get().to(new_paste))
+        .route(reserved::route("/submit"), web::post().to(submit))
+        .route(reserved::route("/preview"), web::post().to(preview))
+        .route(reserved::route("/paste/{token}"), web::get().to(get_paste))
+        .route(reserved::route("/paste/{token}"), web::head().to(get_paste))
+        .route(reserved::route("/paste/{token}/raw"), web::get().to(raw_paste))
+        .route(reserved::route("/paste/{token}/raw"), web::head().to(raw_paste))
+        .route(reserved::route("/paste/{token}/download"), web::get().to(download_paste))
+        .route(reserved::route("/paste/{token}/download"), web::head().to(download_paste))
+        // The short forms of raw and download, which links and tools name too
+        .route(reserved::route("/raw/{token}"), web::get().to(raw_paste))
+        .route(reserved::route("/raw/{token}"), web::head().to(raw_paste))
+        .route(reserved::route("/download/{token}"), web::get().to(download_paste))
+        .route(reserved::route("/download/{token}"), web::head().to(download_paste))
+        .route(reserved::route("/paste/{token}/archive.zip"), web::get().to(archive_paste))
+        .route(reserved::route("/paste/{token}/archive.zip"), web::head().to(archive_paste))
+        .route(reserved::route("/paste/{token}/print"), web::get().to(print_paste))
+        .route(reserved::route("/paste/{token}/preview"), web::get().to(preview_paste))
+        .route(reserved::route("/paste/{token}/stats"), web::get().to(paste_stats))
+        .route(reserved::route("/paste/{token}/delete"), web::post().to(delete_paste))
+        .route(reserved::route("/paste/{token}/created"), web::get().to(paste_created))
+        .route(reserved::route("/paste/{token}/wrap"), web::get().to(wrap_toggle))
+        .route(reserved::route("/language"), web::post().to(set_language))
+        .configure(announcements::configure)
+        .configure(comments::configure)
+        .route(reserved::route("/paste/{token}/gist"), web::get().to(paste_gist))
+        .route(reserved::route("/paste/{token}/gist"), web::post().to(mirror_paste))
+        .route(reserved::route("/h/{hash}"), web::get().to(hash_paste))
+        .route(reserved::route("/h/{hash}"), web::head().to(hash_paste))
+        .route(reserved::route("/h/{hash}/raw"), web::get().to(hash_raw))
+        .route(reserved::route("/h/{hash}/raw"), web::head().to(hash_raw))
+        .route(reserved::route("/h/{hash}/download"), web::get().to(hash_download))
+        .route(reserved::route("/h/{hash}/download"), web::head().to(hash_download))
+        .configure(collections::configure)
+        .configure(images::configure)
+        .route(reserved::route("/popular"), web::get().to(popular))
+        .route(reserved::route("/tags"), web::get().to(tag_list))
+        .route(reserved::route("/mine"), web::get().to(mine))
+        .configure(search::configure)
+        .route(reserved::route("/version"), web::get().to(version_info))
+        .route(reserved::route("/healthz"), web::get().to(healthz))
+        .route(reserved::route("/metrics"), web::get().to(metrics))
+        .configure(admin::configure)
+        .configure(api_tokens::configure)
+        .configure(bans::configure)
+        .configure(federation::configure)
+        .configure(review::configure)
+        // Every response of the API goes through `api_error_response`, errors become the JSON envelope.
+        // Its routes go through `openapi::route`, which refuses one the OpenAPI document doesn't describe
+        .service(
+            web::scope(reserved::route("/api"))
+                .wrap_fn(|req, srv| {
+                    let response = srv.call(req);
+                    async { Ok(rate_limit_headers(error::api_error_response(response.await?))) }
+                })
+                .app_data(web::PayloadConfig::new(max_json_bytes))
+                .app_data(web::JsonConfig::default().limit(max_json_bytes))
+                .route(openapi::route("GET", "/openapi.json"), web::get().to(api_openapi))
+                .route(openapi::route("GET", "/version"), web::get().to(version_info))
+                .route(openapi::route("GET", "/limits"), web::get().to(api_limits))
+                .configure(search::configure_api)
+                .route(openapi::route("GET", "/pastes"), web::get().to(api_fetch_pastes))
+                .route(openapi::route("POST", "/pastes"), web::post().to(api_create_paste))
+                // Before “/pastes/{token}”, which would take them for tokens
+                .route(openapi::route("POST", "/pastes/batch"), web::post().to(api_create_batch))
+                .route(openapi::route("POST", "/pastes/validate"), web::post().to(api_validate_paste))
+                .route(openapi::route("POST", "/pastes/fetch"), web::post().to(api_fetch_pastes_post))
+                .route(openapi::route("GET", "/pastes/{token}"), web::get().to(api_get_paste))
+                .route(openapi::route("PUT", "/pastes/{token}"), web::put().to(api_put_paste))
+                .route(openapi::route("DELETE", "/pastes/{token}"), web::delete().to(api_delete_paste))
+                .route(openapi::route("HEAD", "/pastes/{token}"), web::head().to(api_get_paste))
+                .route(openapi::route("GET", "/pastes/{token}/stats"), web::get().to(api_paste_stats))
+                .configure(comments::configure_api)
+                .configure(collections::configure_api)
+                .configure(federation::configure_api)
+                .route(openapi::route("GET", "/h/{hash}"), web::get().to(api_hash_paste))
+                .route(openapi::route("HEAD", "/h/{hash}"), web::head().to(api_hash_paste))
+                .default_service(web::route().to(api_not_found)),
+        )
+}
+
+// This is the main function of the project,
+// 0. Checks the directories and the database's schema version (`doctor::Scope::Startup`), `pastry doctor` runs every check.
+// 1. Tries to connect to DB (a SQLite file, or Postgres when `PASTRY_DB_PATH` is a postgres:// URL), checks it's writable
+//    With `--ephemeral` everything is kept in memory instead and lost on exit.
+// 2. And then migrates the schema to the latest version (creating the tables if they do not exist).
+//    Both are retried for a while, the database may live on a volume that gets mounted late at boot,
+//    and the process exits with an error once the retries are exhausted.
+//    Pastes bigger than `PASTRY_BLOB_THRESHOLD` bytes are kept as files in `PASTRY_BLOB_DIR`,
+//    which is created if needed and has to be writable (not used with `--ephemeral`).
+// 3. Creates the instance of AppState stucture.
+// 4. Starts the cleanup task in the background.
+// 5. Declare the HttpServer using Actix_web, with its routes, on the sockets passed by systemd when socket activated,
+//    otherwise on the addresses of `--bind`/`PASTRY_BIND` (localhost and port 8080 by default),
+//    and on the internal addresses of `--internal-bind`/`PASTRY_INTERNAL_BIND`, the only ones serving admin and metrics.
+//    Every address has to bind, or the server doesn't start.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let config_path = std::env::var_os("PASTRY_CONFIG").map(PathBuf::from);
+    let config = match &config_path {
+        Some(path) => match Config::load(path) {
+            Ok(config) => {
+                println!("Using configuration file {}", path.display());
+                config
+            }
+            Err(e) => {
+                eprintln!("Invalid configuration file {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Config::default(),
+    };
+
+    let location = if std::env::args().skip(1).any(|arg| arg == "--ephemeral") {
+        store::Location::Memory {
+            max_pastes: setting(&config.memory_max_pastes, "PASTRY_MEMORY_MAX_PASTES", PASTRY_MEMORY_MAX_PASTES),
+            max_bytes: setting(&config.memory_max_bytes, "PASTRY_MEMORY_MAX_BYTES", PASTRY_MEMORY_MAX_BYTES),
+        }
+    } else {
+        store::Location::parse(
+            &setting(&config.db_path, "PASTRY_DB_PATH", PASTRY_DB_PATH.to_string()),
+            &std::env::current_dir()?,
+        )
+    };
+    let blob_dir = PathBuf::from(setting(&config.blob_dir, "PASTRY_BLOB_DIR", PASTRY_BLOB_DIR.to_string()));
+    let backup_dir = PathBuf::from(setting(&config.backup_dir, "PASTRY_BACKUP_DIR", PASTRY_BACKUP_DIR.to_string()));
+    let assets_dir = match &config.assets_dir {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => std::env::var_os("PASTRY_ASSETS_DIR").map(PathBuf::from),
+    };
+    let configured = |file: &Option<String>, name| file.clone().or_else(|| std::env::var(name).ok()).filter(|value| !value.is_empty());
+    let trusted_proxies = match &config.trusted_proxies {
+        Some(proxies) => proxies.join(","),
+        None => std::env::var("PASTRY_TRUSTED_PROXIES").unwrap_or_default(),
+    };
+
+    let doctor = std::env::args().nth(1).as_deref() == Some("doctor");
+    let setup = doctor::Setup {
+        location: &location,
+        blob_dir: &blob_dir,
+        backup_dir: &backup_dir,
+        assets_dir: assets_dir.as_deref(),
+        admin_token: configured(&config.admin_token, "PASTRY_ADMIN_TOKEN").is_some(),
+        cookie_secret: configured(&config.cookie_secret, "PASTRY_COOKIE_SECRET").is_some(),
+        ip_salt: configured(&config.ip_salt, "PASTRY_IP_SALT").is_some(),
+        captcha_site_key: configured(&config.captcha_site_key, "PASTRY_CAPTCHA_SITE_KEY").is_some(),
+        captcha_secret: configured(&config.captcha_secret, "PASTRY_CAPTCHA_SECRET").is_some(),
+        trusted_proxies: &trusted_proxies,
+    };
+    if doctor {
+        let checks = doctor::run(&setup, doctor::Scope::All);
+        print!("{}", doctor::report(&checks));
+        std::process::exit(if doctor::failed(&checks) { 1 } else { 0 });
+    }
+    let checks = doctor::run(&setup, doctor::Scope::Startup);
+    for check in checks.iter().filter(|check| check.status != doctor::Status::Pass) {
+        eprintln!("{}", check);
+    }
+    if doctor::failed(&checks) {
+        eprintln!("Not starting, `pastry doctor` checks the rest of the setup");
+        std::process::exit(1);
+    }
+
+    let attempts = setting(&config.db_open_attempts, "PASTRY_DB_OPEN_ATTEMPTS", PASTRY_DB_OPEN_ATTEMPTS).max(1);
+    let backoff = Duration::from_millis(setting(&config.db_open_backoff_ms, "PASTRY_DB_OPEN_BACKOFF_MS", PASTRY_DB_OPEN_BACKOFF_MS));
+
+    let db_timeout = Duration::from_millis(setting(&config.db_timeout_ms, "PASTRY_DB_TIMEOUT_MS", PASTRY_DB_TIMEOUT_MS));
+
+    let write_retry = Duration::from_millis(setting(&config.db_write_retry_ms, "PASTRY_DB_WRITE_RETRY_MS", PASTRY_DB_WRITE_RETRY_MS));
+
+    let paste_store = match store::open_with_retry(&location, attempts, backoff, db_timeout, write_retry) {
+        Ok(paste_store) => paste_store,
+        Err(e) => {
+            eprintln!(
+                "Failed to open database {} after {} attempts: {}",
+                location, attempts, e
+            );
+            if e.is_busy() {
+                eprintln!("Another process keeps the database locked, e.g. a server or a purge running on it.");
+            }
+            std::process::exit(1);
+        }
+    };
+    println!("Using {} store: {}", paste_store.backend(), location);
+    let trace_exporter = telemetry::init(config.otlp_endpoint.clone().or_else(|| std::env::var("PASTRY_OTLP_ENDPOINT").ok()));
+
+    // Times the calls to the database itself, the blob files are left out
+    let query_timings = Arc::new(QueryTimings::default());
+    let slow_query = Duration::from_millis(setting(&config.slow_query_ms, "PASTRY_SLOW_QUERY_MS", PASTRY_SLOW_QUERY_MS));
+    let paste_store: Box<dyn PasteStore> = Box::new(TimedStore::new(paste_store, query_timings.clone(), slow_query));
+
+    let paste_store: Box<dyn PasteStore> = if let store::Location::Memory { .. } = location {
+        paste_store
+    } else {
+        let threshold = setting(&config.blob_threshold, "PASTRY_BLOB_THRESHOLD", PASTRY_BLOB_THRESHOLD);
+        match store::blobs::BlobStore::new(paste_store, &blob_dir, threshold) {
+            Ok(blob_store) => Box::new(blob_store),
+            Err(e) => {
+                eprintln!("Blob directory {} is not usable: {}", blob_dir.display(), e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    if std::env::args().nth(1).as_deref() == Some("purge") {
+        let dry_run = std::env::args().skip(2).any(|arg| arg == "--dry-run");
+        std::process::exit(run_purge(paste_store.as_ref(), dry_run));
+    }
+    if std::env::args().nth(1).as_deref() == Some("fsck") {
+        let repair = std::env::args().skip(2).any(|arg| arg == "--repair");
+        std::process::exit(run_fsck(paste_store.as_ref(), repair));
+    }
+
+    let db_check = match config.db_check.as_deref().map(str::parse) {
+        // Checked when the file was loaded
+        Some(Ok(mode)) => mode,
+        _ => env_or("PASTRY_DB_CHECK", db_check::Mode::Quick),
+    };
+    let database_read_only = if db_check::run(paste_store.as_ref(), db_check) {
+        false
+    } else {
+        let on_failure = match config.db_check_failure.as_deref().map(str::parse) {
+            Some(Ok(on_failure)) => on_failure,
+            _ => env_or("PASTRY_DB_CHECK_FAILURE", db_check::OnFailure::Warn),
+        };
+        match on_failure {
+            db_check::OnFailure::Refuse => {
+                eprintln!("Not starting on a damaged database, see “Corrupt Database” in the README");
+                std::process::exit(1);
+            }
+            db_check::OnFailure::ReadOnly => {
+                eprintln!("Serving the database read-only until it is repaired");
+                true
+            }
+            db_check::OnFailure::Warn => {
+                eprintln!("Serving the damaged database as usual, some pastes may fail to load");
+                false
+            }
+        }
+    };
+
+    let trusted_proxies = match client::parse_trusted_proxies(&trusted_proxies) {
+        Ok(trusted_proxies) => trusted_proxies,
+        Err(e) => {
+            eprintln!("Invalid PASTRY_TRUSTED_PROXIES: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let captcha = match (
+        configured(&config.captcha_site_key, "PASTRY_CAPTCHA_SITE_KEY"),
+        configured(&config.captcha_secret, "PASTRY_CAPTCHA_SECRET"),
+    ) {
+        (Some(site_key), Some(secret)) => {
+            let provider = match config.captcha_provider.as_deref().map(str::parse) {
+                // Checked when the file was loaded
+                Some(Ok(provider)) => provider,
+                _ => env_or("PASTRY_CAPTCHA_PROVIDER", captcha::Provider::HCaptcha),
+            };
+            Some(captcha::Captcha::new(
+                provider,
+                site_key,
+                secret,
+                configured(&config.captcha_verify_url, "PASTRY_CAPTCHA_VERIFY_URL"),
+                setting(&config.captcha_fail_open, "PASTRY_CAPTCHA_FAIL_OPEN", PASTRY_CAPTCHA_FAIL_OPEN),
+            ))
+        }
+        (None, None) => None,
+        _ => {
+            eprintln!("A captcha needs both PASTRY_CAPTCHA_SITE_KEY and PASTRY_CAPTCHA_SECRET");
+            std::process::exit(1);
+        }
+    };
+
+    let stats_pusher = match (
+        configured(&config.stats_push_url, "PASTRY_STATS_PUSH_URL"),
+        configured(&config.stats_push_instance, "PASTRY_STATS_PUSH_INSTANCE"),
+    ) {
+        (Some(url), Some(instance)) => {
+            let interval = setting(&config.stats_push_interval_secs, "PASTRY_STATS_PUSH_INTERVAL_SECS", federation::PUSH_INTERVAL_SECS);
+            match federation::Pusher::new(&url, &instance, configured(&config.stats_push_token, "PASTRY_STATS_PUSH_TOKEN"), interval) {
+                Ok(pusher) => Some(pusher),
+                Err(e) => {
+                    eprintln!("Can't push stats: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        (None, None) => None,
+        _ => {
+            eprintln!("Pushing stats needs both PASTRY_STATS_PUSH_URL and PASTRY_STATS_PUSH_INSTANCE");
+            std::process::exit(1);
+        }
+    };
+
+    // Postgres keeps its data on a disk of its own, the memory store has no blobs either
+    let mut volumes = Vec::new();
+    if let store::Location::Sqlite(path) = &location {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        volumes.push(disk::Volume {
+            name: "database",
+            path: dir.to_path_buf(),
+        });
+    }
+    if !matches!(location, store::Location::Memory { .. }) {
+        volumes.push(disk::Volume {
+            name: "blobs",
+            path: blob_dir.clone(),
+        });
+    }
+    let disk_check = Duration::from_secs(setting(&config.disk_check_secs, "PASTRY_DISK_CHECK_SECS", PASTRY_DISK_CHECK_SECS));
+
+    let cookie_secret = match config
+        .cookie_secret
+        .clone()
+        .or_else(|| std::env::var("PASTRY_COOKIE_SECRET").ok())
+        .filter(|secret| !secret.is_empty())
+    {
+        Some(secret) => secret,
+        None => {
+            println!("No PASTRY_COOKIE_SECRET set, creator cookies will stop working on the next restart");
+            random_string(32)
+        }
+    };
+
+    let app_state = web::Data::new(AppState {
+        store: Arc::from(paste_store),
+        admin_token: config
+            .admin_token
+            .clone()
+            .or_else(|| std::env::var("PASTRY_ADMIN_TOKEN").ok())
+            .filter(|token| !token.is_empty()),
+        backup_dir,
+        backup_lock: Mutex::new(()),
+        settings: RwLock::new(Arc::new(Settings::resolve(&config))),
+        // Without a configured salt the hashes change on every restart: the quotas reset, and hashes can't be banned
+        ip_salt: configured(&config.ip_salt, "PASTRY_IP_SALT").unwrap_or_else(|| random_string(32)),
+        ip_salt_configured: configured(&config.ip_salt, "PASTRY_IP_SALT").is_some(),
+        cookie_secret,
+        validations: Mutex::new(day_counts::DayCounts::default()),
+        token_uses: Mutex::new(api_tokens::LastUsed::default()),
+        idempotency: Mutex::new(idempotency::Keys::default()),
+        bans: RwLock::new(bans::BanList::default()),
+        announcements: RwLock::new(announcements::Board::default()),
+        trusted_proxies,
+        cache: PasteCache::new(
+            setting(&config.cache_max_entries, "PASTRY_CACHE_MAX_ENTRIES", PASTRY_CACHE_MAX_ENTRIES),
+            setting(&config.cache_max_bytes, "PASTRY_CACHE_MAX_BYTES", PASTRY_CACHE_MAX_BYTES),
+        ),
+        query_timings,
+        gist: config
+            .github_token
+            .clone()
+            .or_else(|| std::env::var("PASTRY_GITHUB_TOKEN").ok())
+            .filter(|token| !token.is_empty())
+            .map(|token| GistClient::new(&setting(&config.gist_api_url, "PASTRY_GIST_API_URL", PASTRY_GIST_API_URL.to_string()), token)),
+        captcha,
+        federation_token: configured(&config.federation_token, "PASTRY_FEDERATION_TOKEN"),
+        disk: disk::Monitor::new(volumes, disk_check),
+        handoffs: Mutex::new(handoff::Handoffs::default()),
+        database_read_only,
+    });
+
+    // After the state, whose rendering of the pages it shares
+    if std::env::args().nth(1).as_deref() == Some("export-static") {
+        std::process::exit(run_export_static(&app_state, std::env::args().nth(2).as_deref(), assets_dir.as_deref()));
+    }
+
+    prime_cache(&app_state, setting(&config.cache_prime, "PASTRY_CACHE_PRIME", PASTRY_CACHE_PRIME)).await;
+
+    actix_web::rt::spawn(cleanup_task(app_state.clone()));
+    actix_web::rt::spawn(refresh_bans_task(app_state.clone()));
+    actix_web::rt::spawn(refresh_announcements_task(app_state.clone()));
+    if !app_state.disk.interval.is_zero() {
+        actix_web::rt::spawn(disk_check_task(app_state.clone()));
+    }
+    if let Some(pusher) = stats_pusher {
+        println!("Pushing stats to another instance as {} every {} seconds", pusher.instance, pusher.interval.as_secs());
+        actix_web::rt::spawn(push_stats_task(app_state.clone(), pusher));
+    }
+
+    // Checked by the doctor's startup checks
+    assets::init(assets_dir.as_deref());
+    i18n::init();
+
+    // Bodies are held to the limit at startup as actix reads them, a reload raising `max_paste_bytes` needs a restart for them
+    let started_max_paste_bytes = app_state.settings().max_paste_bytes;
+    let max_form_bytes = max_form_bytes(started_max_paste_bytes);
+    let max_json_bytes = max_json_bytes(started_max_paste_bytes);
+    let request_timeout = Duration::from_secs(setting(&config.request_timeout_secs, "PASTRY_REQUEST_TIMEOUT_SECS", PASTRY_REQUEST_TIMEOUT_SECS));
+    let client_timeout = setting(&config.client_timeout_ms, "PASTRY_CLIENT_TIMEOUT_MS", PASTRY_CLIENT_TIMEOUT_MS);
+
+    let listeners = match listen::from_systemd() {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            eprintln!("Can't use the sockets passed by systemd: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let socket_activated = !listeners.is_empty();
+    let listeners = if socket_activated {
+        listeners
+    } else {
+        let binds = bind_addresses("--bind", &config.bind, "PASTRY_BIND", &[PASTRY_BIND]);
+        bind_or_exit(&binds).into_iter().map(listen::Listener::Tcp).collect()
+    };
+    let internal_binds = bind_addresses("--internal-bind", &config.internal_bind, "PASTRY_INTERNAL_BIND", &[]);
+    let internal_listeners = bind_or_exit(&internal_binds);
+    let internal_addrs: Vec<SocketAddr> = internal_listeners
+        .iter()
+        .map(|listener| listener.local_addr())
+        .collect::<std::io::Result<_>>()?;
+    actix_web::rt::spawn(reload_on_hangup(app_state.clone(), config_path, config, started_max_paste_bytes));
+
+    //Actually start the http server with its routes and at given port 8080
+    let setup = AppSetup {
+        internal_addrs,
+        request_timeout,
+        max_form_bytes,
+        max_json_bytes,
+        assets_dir,
+    };
+    let app = move || app(app_state.clone(), &setup);
+    // Built once here first: `reserved::route` panics on a route with a word it doesn't know, and `openapi::route` on
+    // one missing from the document, better on this thread at startup than in every worker as it starts
+    drop(app());
+    let server = HttpServer::new(app).client_timeout(client_timeout);
+
+    let mut kept_paths = Vec::new();
+    let mut server = server;
+    for listener in listeners {
+        if socket_activated {
+            println!("Listening on {}, passed by systemd", listener);
+        } else {
+            println!("Listening on {}", listener);
+        }
+        server = match listener {
+            listen::Listener::Tcp(listener) => server.listen(listener)?,
+            listen::Listener::Unix(listener) => {
+                if let Some(kept) = listen::KeptPath::new(&listener) {
+                    kept_paths.push(kept);
+                }
+                server.listen_uds(listener)?
+            }
+        };
+    }
+    for listener in internal_listeners {
+        println!("Listening on tcp {}, internal", listener.local_addr()?);
+        server = server.listen(listener)?;
+    }
+    let server = server.run();
+    if !kept_paths.is_empty() {
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(SOCKET_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                kept_paths.iter().for_each(listen::KeptPath::restore);
+            }
+        });
+    }
+    server.await?;
+
+    if let Some(exporter) = trace_exporter {
+        exporter.shutdown();
+    }
+    Ok(())
 }
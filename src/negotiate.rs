@@ -0,0 +1,136 @@
+// Which representation of a paste “/paste/{token}” answers with: the page, the text alone or the JSON of the API.
+// A `.txt` or `.json` suffix on the token decides, then the Accept header, then the User-Agent:
+// command line clients send `Accept: */*` without preferring HTML, and get the text.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Representation {
+    Html,
+    Text,
+    Json,
+}
+
+// User-Agent prefixes of the clients that get the text when their Accept header doesn't say.
+const TEXT_CLIENTS: &[&str] = &["curl/", "wget/", "httpie/", "xh/", "fetch/", "powershell/"];
+
+// The token without its suffix, and the representation asked for.
+pub fn negotiate<'a>(path: &'a str, accept: Option<&str>, user_agent: Option<&str>) -> (&'a str, Representation) {
+    if let Some(token) = path.strip_suffix(".txt") {
+        return (token, Representation::Text);
+    }
+    if let Some(token) = path.strip_suffix(".json") {
+        return (token, Representation::Json);
+    }
+
+    if let Some(preferred) = accept.and_then(preferred) {
+        return (path, preferred);
+    }
+    let user_agent = user_agent.unwrap_or("").to_ascii_lowercase();
+    if TEXT_CLIENTS.iter().any(|client| user_agent.starts_with(client)) {
+        (path, Representation::Text)
+    } else {
+        (path, Representation::Html)
+    }
+}
+
+// The representation an Accept header names with the highest quality, the page on a tie.
+// None when it names none of them, only wildcards and other types like `*/*`, which leaves it to the User-Agent.
+fn preferred(accept: &str) -> Option<Representation> {
+    let mut best: Option<(f32, Representation)> = None;
+    for range in accept.split(',') {
+        let mut parts = range.split(';');
+        let representation = match parts.next().unwrap_or("").trim().to_ascii_lowercase().as_str() {
+            "text/html" | "application/xhtml+xml" => Representation::Html,
+            "text/plain" => Representation::Text,
+            "application/json" => Representation::Json,
+            _ => continue,
+        };
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let better = match best {
+            None => true,
+            Some((best_quality, best_representation)) => {
+                quality > best_quality || (quality == best_quality && representation == Representation::Html && best_representation != Representation::Html)
+            }
+        };
+        if quality > 0.0 && better {
+            best = Some((quality, representation));
+        }
+    }
+    best.map(|(_, representation)| representation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Representation::*;
+
+    const BROWSER: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0";
+    const BROWSER_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+    fn representation(accept: Option<&str>, user_agent: Option<&str>) -> Representation {
+        negotiate("abc", accept, user_agent).1
+    }
+
+    #[test]
+    fn suffixes_win() {
+        assert_eq!(negotiate("abc.txt", Some(BROWSER_ACCEPT), Some(BROWSER)), ("abc", Text));
+        assert_eq!(negotiate("abc.json", Some("text/html"), Some(BROWSER)), ("abc", Json));
+        assert_eq!(negotiate("abc.txt", None, Some("curl/8.5.0")), ("abc", Text));
+        assert_eq!(negotiate("abc", None, None), ("abc", Html));
+    }
+
+    #[test]
+    fn named_types() {
+        assert_eq!(representation(Some("text/html"), None), Html);
+        assert_eq!(representation(Some("application/xhtml+xml"), None), Html);
+        assert_eq!(representation(Some("text/plain"), Some(BROWSER)), Text);
+        assert_eq!(representation(Some("application/json"), Some(BROWSER)), Json);
+        assert_eq!(representation(Some("Application/JSON ; charset=utf-8"), None), Json);
+        assert_eq!(representation(Some(BROWSER_ACCEPT), Some("curl/8.5.0")), Html);
+    }
+
+    #[test]
+    fn highest_quality_wins() {
+        assert_eq!(representation(Some("text/html;q=0.5, application/json"), None), Json);
+        assert_eq!(representation(Some("text/plain;q=0.9, application/json;q=0.8"), None), Text);
+        assert_eq!(representation(Some("application/json; q=0.1, text/plain; q=0.2"), None), Text);
+    }
+
+    #[test]
+    fn ties_go_to_the_page_then_to_the_first() {
+        assert_eq!(representation(Some("application/json, text/html"), None), Html);
+        assert_eq!(representation(Some("text/plain;q=0.5, text/html;q=0.5"), None), Html);
+        assert_eq!(representation(Some("text/plain, application/json"), None), Text);
+        assert_eq!(representation(Some("application/json, text/plain"), None), Json);
+    }
+
+    #[test]
+    fn refused_types_are_left_out() {
+        assert_eq!(representation(Some("text/html;q=0, text/plain"), Some(BROWSER)), Text);
+        assert_eq!(representation(Some("text/html;q=0, application/json;q=0.1"), None), Json);
+        // Refusing the page alone says nothing of the others, the User-Agent decides
+        assert_eq!(representation(Some("text/html;q=0"), Some("curl/8.5.0")), Text);
+        assert_eq!(representation(Some("text/html;q=0"), Some(BROWSER)), Html);
+    }
+
+    #[test]
+    fn wildcards_leave_it_to_the_user_agent() {
+        for user_agent in ["curl/8.5.0", "Wget/1.21.4", "HTTPie/3.2.2", "xh/0.22.0", "PowerShell/7.4"] {
+            assert_eq!(representation(Some("*/*"), Some(user_agent)), Text, "{}", user_agent);
+            assert_eq!(representation(None, Some(user_agent)), Text, "{}", user_agent);
+        }
+        assert_eq!(representation(Some("*/*"), Some(BROWSER)), Html);
+        assert_eq!(representation(Some("text/*, image/png"), Some(BROWSER)), Html);
+        assert_eq!(representation(None, None), Html);
+        // A browser that happens to name curl somewhere
+        assert_eq!(representation(Some("*/*"), Some("Mozilla/5.0 curl/8.5.0")), Html);
+    }
+
+    #[test]
+    fn broken_qualities_count_as_1() {
+        assert_eq!(representation(Some("text/plain;q=high, application/json;q=0.9"), None), Text);
+        assert_eq!(representation(Some(",;, text/plain"), None), Text);
+    }
+}
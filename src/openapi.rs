@@ -0,0 +1,895 @@
+// The OpenAPI 3 description of the JSON API under “/api”, served at “/api/openapi.json”.
+// The schemas of the bodies and query strings aren't written by hand: each one is read off a sample of the struct
+// the handlers serialize or deserialize, so that the field names and renames are the ones on the wire. A request
+// field is required when the struct doesn't deserialize without it, and nullable when it takes a `null`; an answer
+// comes with a second sample that has none of the optional fields: the ones it leaves out aren't required, the
+// ones it has as `null` are nullable. A field added to a struct has to be added to its sample, the compiler sees
+// to that, and shows up in the document. What the structs can't tell, the enumerations, the routes with their
+// statuses, parameters and tokens, is written here.
+// The routes of the `/api` scope are registered through `route`, which refuses, at startup, one the document
+// doesn't describe.
+
+use crate::api_tokens::Scope;
+use crate::error::{self, ErrorCode};
+use crate::collections::{ApiCollection, ApiCollectionMember, ApiCollectionPastes, ApiCreatedCollection, ApiNewCollection};
+use crate::comments::{ApiComment, ApiComments};
+use crate::{
+    federation, version, ApiCheck, ApiExpiry, ApiFetch, ApiHashMatch, ApiHashMatches, ApiLimits, ApiQuota,
+    ApiValidatePaste, ApiValidation, DayViews, FetchQuery, KeyQuery, PasteStats, VersionInfo, EXPIRY_OPTIONS,
+    METADATA_HEADERS,
+};
+use pastry_crust::api::{
+    ApiBatchError, ApiBatchResult, ApiCreatedPaste, ApiErrorBody, ApiErrorEnvelope, ApiNewBatch, ApiNewPaste,
+    ApiPaste, ApiSearchHit, ApiSearchResults, Encoding, PasteType, SearchParams, Status,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+// Built on the first call, which `route` makes at startup.
+static DOCUMENT: OnceLock<Value> = OnceLock::new();
+
+pub fn document() -> &'static Value {
+    DOCUMENT.get_or_init(build)
+}
+
+// The path of a route of the `/api` scope, “/pastes/{token}”, after checking its words like `reserved::route` does
+// and that the document describes it with `method`. Panics on one it doesn't, the route has to be added to `paths`.
+pub fn route(method: &str, path: &'static str) -> &'static str {
+    let path = crate::reserved::route(path);
+    let described = document()["paths"]
+        .get(format!("/api{}", path))
+        .and_then(|item| item.get(method.to_ascii_lowercase()));
+    assert!(
+        described.is_some(),
+        "The route {} /api{} is missing from the OpenAPI document, see openapi.rs",
+        method,
+        path
+    );
+    path
+}
+
+fn build() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "pastry",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "The JSON API of a pastry server. Every error is answered with the error envelope, \
+                            whose `code` tells what went wrong. Requests without a token may use every route but \
+                            the ones needing the `admin` scope, unless the server sets PASTRY_API_REQUIRE_TOKEN.",
+        },
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),
+            "responses": error_responses(),
+            "headers": {
+                "X-RateLimit-Limit": header("How many uses of the daily quota the client has, per day (UTC)"),
+                "X-RateLimit-Remaining": header("How many it has left today"),
+                "X-RateLimit-Reset": header("When the quota starts over, a Unix timestamp"),
+                "Retry-After": header("Seconds until the quota starts over"),
+            },
+            "securitySchemes": {
+                "apiToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "bearerFormat": "pst_…",
+                    "description": "An API token created by the admin, with the scopes `read`, `write`, `delete` \
+                                    and `admin` it was given. Each operation names the one it needs as \
+                                    `x-pastry-scope`; a token without it gets a 403 naming it as `scope`.",
+                },
+                "adminToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "PASTRY_ADMIN_TOKEN, which has every scope",
+                },
+                "federationToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "PASTRY_FEDERATION_TOKEN, shared by the instances sending their stats",
+                },
+            },
+        },
+    })
+}
+
+// Who may call an operation.
+#[derive(Clone, Copy)]
+enum Access {
+    Anyone,
+    // A token with the scope, the admin token, or no token at all unless tokens are required
+    Scope(Scope),
+    Federation,
+}
+
+// An operation of a path, as `paths` writes them.
+struct Operation {
+    operation: Value,
+    // Whether it counts against a daily quota and answers with the `X-RateLimit-*` headers
+    quota: bool,
+}
+
+impl Operation {
+    fn new(id: &str, summary: &str, access: Access) -> Operation {
+        let (security, scope) = match access {
+            Access::Anyone => (json!([]), None),
+            Access::Scope(scope) => (json!([{"apiToken": []}, {"adminToken": []}, {}]), Some(scope.as_str())),
+            Access::Federation => (json!([{"federationToken": []}]), None),
+        };
+        let mut operation = json!({
+            "operationId": id,
+            "summary": summary,
+            "security": security,
+            "responses": {"default": {"$ref": "#/components/responses/error"}},
+        });
+        if let Some(scope) = scope {
+            operation["x-pastry-scope"] = json!(scope);
+        }
+        let operation = Operation { operation, quota: false };
+        match access {
+            Access::Anyone => operation,
+            Access::Scope(_) => operation.errors(&[ErrorCode::Unauthorized, ErrorCode::Forbidden]),
+            Access::Federation => operation.errors(&[ErrorCode::Unauthorized]),
+        }
+    }
+
+    fn describe(mut self, description: &str) -> Operation {
+        self.operation["description"] = json!(description);
+        self
+    }
+
+    fn parameters(mut self, parameters: Vec<Value>) -> Operation {
+        let all = self.operation["parameters"].as_array().cloned().unwrap_or_default();
+        self.operation["parameters"] = json!(all.into_iter().chain(parameters).collect::<Vec<_>>());
+        self
+    }
+
+    fn body(mut self, schema: Value) -> Operation {
+        self.operation["requestBody"] = json!({
+            "required": true,
+            "content": {"application/json": {"schema": schema}},
+        });
+        self
+    }
+
+    // An answer of the operation, with a JSON body of `schema` unless it is `None`.
+    fn answer(mut self, status: u16, description: &str, schema: Option<Value>) -> Operation {
+        let mut response = json!({"description": description});
+        if let Some(schema) = schema {
+            response["content"] = json!({"application/json": {"schema": schema}});
+        }
+        self.operation["responses"][status.to_string()] = response;
+        self
+    }
+
+    fn errors(mut self, codes: &[ErrorCode]) -> Operation {
+        for code in codes {
+            self.operation["responses"][code.status().as_u16().to_string()] =
+                json!({"$ref": format!("#/components/responses/{}", code.as_str())});
+        }
+        self
+    }
+
+    // Counts against a daily quota: its 429, and the `X-RateLimit-*` headers on its successes.
+    fn quota(mut self) -> Operation {
+        self.quota = true;
+        self.errors(&[ErrorCode::RateLimited])
+    }
+
+    fn into_value(mut self) -> Value {
+        if self.quota {
+            let responses = self.operation["responses"].as_object_mut().expect("responses are an object");
+            for (_, response) in responses.iter_mut().filter(|(status, _)| status.starts_with('2')) {
+                response["headers"] = rate_limit_headers(false);
+            }
+        }
+        self.operation
+    }
+}
+
+fn paths() -> Value {
+    let key = || query_parameters(KeyQuery { key: Some("secret".to_string()) });
+    let idempotency_key = json!({
+        "name": "Idempotency-Key",
+        "in": "header",
+        "required": false,
+        "description": "Makes a retry of the request get the first answer instead of creating the pastes again",
+        "schema": {"type": "string"},
+    });
+    let pastes_by_token = json!({
+        "type": "object",
+        "description": "Each token asked for, with its paste or `null` when it's unknown or expired",
+        "additionalProperties": nullable(reference("ApiPaste")),
+    });
+    let writes = [ErrorCode::BadRequest, ErrorCode::PayloadTooLarge, ErrorCode::UnsupportedMediaType];
+
+    let operations = vec![
+        (
+            "/openapi.json",
+            "get",
+            Operation::new("openapi", "This document", Access::Anyone).answer(
+                200,
+                "The OpenAPI description of the API",
+                Some(json!({"type": "object"})),
+            ),
+        ),
+        (
+            "/version",
+            "get",
+            Operation::new("version", "The build of the server and its token strategy", Access::Anyone).answer(
+                200,
+                "The build information",
+                Some(reference("Version")),
+            ),
+        ),
+        (
+            "/limits",
+            "get",
+            Operation::new("limits", "What the server accepts, and the quota left", Access::Scope(Scope::Read))
+                .answer(200, "The limits", Some(reference("ApiLimits")))
+                .quota(),
+        ),
+        (
+            "/search",
+            "get",
+            Operation::new("search", "One page of the public pastes matching a query", Access::Scope(Scope::Read))
+                .parameters(query_parameters(SearchParams {
+                    q: "hello".to_string(),
+                    tag: Some("rust".to_string()),
+                    since: Some("2024-01-01".to_string()),
+                    until: Some("2024-01-31".to_string()),
+                    page: Some(1),
+                }))
+                .answer(200, "The matching pastes", Some(reference("ApiSearchResults")))
+                .errors(&[ErrorCode::BadRequest]),
+        ),
+        (
+            "/pastes",
+            "get",
+            Operation::new("fetchPastes", "Several pastes in one request", Access::Scope(Scope::Read))
+                .describe("With the content, each paste counts a view")
+                .parameters(query_parameters(FetchQuery {
+                    tokens: "aB3dE6gH,xY9zW8vU".to_string(),
+                    include_content: Some(true),
+                }))
+                .answer(200, "The pastes", Some(pastes_by_token.clone()))
+                .errors(&[ErrorCode::BadRequest]),
+        ),
+        (
+            "/pastes",
+            "post",
+            Operation::new("createPaste", "Creates a paste", Access::Scope(Scope::Write))
+                .parameters(vec![idempotency_key.clone()])
+                .body(reference("ApiNewPaste"))
+                .answer(201, "The created paste, with its secret", Some(reference("ApiCreatedPaste")))
+                .errors(&writes)
+                .errors(&[ErrorCode::Conflict, ErrorCode::InsufficientStorage])
+                .quota(),
+        ),
+        (
+            "/pastes/batch",
+            "post",
+            Operation::new("createBatch", "Creates several pastes", Access::Scope(Scope::Write))
+                .describe("Each paste gets its own result, in order, unless the batch is atomic")
+                .parameters(vec![idempotency_key])
+                .body(reference("ApiNewBatch"))
+                .answer(200, "A result per paste", Some(array(reference("ApiBatchResult"))))
+                .errors(&writes)
+                .errors(&[ErrorCode::Conflict, ErrorCode::InsufficientStorage])
+                .quota(),
+        ),
+        (
+            "/pastes/validate",
+            "post",
+            Operation::new("validatePaste", "Checks a paste without creating it", Access::Scope(Scope::Write))
+                .body(reference("ApiValidatePaste"))
+                .answer(200, "The outcome of each check", Some(reference("ApiValidation")))
+                .errors(&writes)
+                .quota(),
+        ),
+        (
+            "/pastes/fetch",
+            "post",
+            Operation::new("fetchPastesPost", "Several pastes, the tokens in the body", Access::Scope(Scope::Read))
+                .body(reference("ApiFetch"))
+                .answer(200, "The pastes", Some(pastes_by_token))
+                .errors(&[ErrorCode::BadRequest]),
+        ),
+        (
+            "/pastes/{token}",
+            "get",
+            Operation::new("getPaste", "A paste, counting a view", Access::Scope(Scope::Read))
+                .answer(200, "The paste", Some(reference("ApiPaste")))
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/pastes/{token}",
+            "head",
+            Operation::new("headPaste", "Whether a paste is there", Access::Scope(Scope::Read))
+                .answer(200, "The paste is there", None)
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/pastes/{token}",
+            "put",
+            Operation::new("putPaste", "Creates a paste at a token, or replaces it", Access::Scope(Scope::Write))
+                .describe("A different paste at the token is only replaced with its `key`, a 409 otherwise")
+                .parameters(key())
+                .body(reference("ApiNewPaste"))
+                .answer(201, "The created paste, with its secret", Some(reference("ApiCreatedPaste")))
+                .answer(
+                    200,
+                    "The replaced paste with its secret, or the same paste already there without it",
+                    Some(reference("ApiCreatedPaste")),
+                )
+                .errors(&writes)
+                .errors(&[ErrorCode::Conflict, ErrorCode::InsufficientStorage])
+                .quota(),
+        ),
+        (
+            "/pastes/{token}",
+            "delete",
+            Operation::new("deletePaste", "Deletes a paste", Access::Scope(Scope::Delete))
+                .describe("With the `key` of the paste, or a token with the `delete` scope")
+                .parameters(key())
+                .answer(204, "The paste is gone", None)
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/pastes/{token}/stats",
+            "get",
+            Operation::new("pasteStats", "The views of a paste per day", Access::Scope(Scope::Read))
+                .parameters(key())
+                .answer(200, "The views", Some(reference("PasteStats")))
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/pastes/{token}/comments",
+            "get",
+            Operation::new("comments", "The comments of a paste, oldest first", Access::Scope(Scope::Read))
+                .answer(200, "The comments", Some(reference("ApiComments")))
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/collections",
+            "post",
+            Operation::new("createCollection", "Creates a collection of pastes", Access::Scope(Scope::Write))
+                .body(reference("ApiNewCollection"))
+                .answer(201, "The created collection, with its secret", Some(reference("ApiCreatedCollection")))
+                .errors(&writes)
+                .errors(&[ErrorCode::InsufficientStorage])
+                .quota(),
+        ),
+        (
+            "/collections/{token}",
+            "get",
+            Operation::new("getCollection", "A collection with its pastes", Access::Scope(Scope::Read))
+                .answer(200, "The collection", Some(reference("ApiCollection")))
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/collections/{token}",
+            "delete",
+            Operation::new("deleteCollection", "Deletes a collection, not its pastes", Access::Scope(Scope::Delete))
+                .parameters(key())
+                .answer(204, "The collection is gone", None)
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/collections/{token}/pastes",
+            "put",
+            Operation::new("setCollectionPastes", "Replaces the pastes of a collection", Access::Scope(Scope::Write))
+                .parameters(key())
+                .body(reference("ApiCollectionPastes"))
+                .answer(200, "The collection", Some(reference("ApiCollection")))
+                .errors(&[ErrorCode::BadRequest, ErrorCode::NotFound]),
+        ),
+        (
+            "/federation/stats",
+            "post",
+            Operation::new("federationStats", "The report of another instance", Access::Federation)
+                .describe("Only with PASTRY_FEDERATION_TOKEN, a 404 otherwise")
+                .body(reference("FederationReport"))
+                .answer(204, "The report is recorded", None)
+                .errors(&[ErrorCode::BadRequest, ErrorCode::NotFound, ErrorCode::PayloadTooLarge]),
+        ),
+        (
+            "/h/{hash}",
+            "get",
+            Operation::new("hashPaste", "The public paste with a content hash", Access::Scope(Scope::Read))
+                .answer(200, "The paste", Some(reference("ApiPaste")))
+                .answer(300, "The hashes a prefix matches", Some(reference("ApiHashMatches")))
+                .errors(&[ErrorCode::NotFound]),
+        ),
+        (
+            "/h/{hash}",
+            "head",
+            Operation::new("headHashPaste", "Whether a public paste has a content hash", Access::Scope(Scope::Read))
+                .answer(200, "There is one", None)
+                .answer(300, "The prefix matches several hashes", None)
+                .errors(&[ErrorCode::NotFound]),
+        ),
+    ];
+
+    let mut paths = Map::new();
+    for (path, method, operation) in operations {
+        let item = paths.entry(format!("/api{}", path)).or_insert_with(|| path_item(path));
+        item[method] = operation.into_value();
+    }
+    Value::Object(paths)
+}
+
+// A path with the parameters of its segments.
+fn path_item(path: &str) -> Value {
+    let parameters: Vec<Value> = path
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            let description = match name {
+                "hash" => "The SHA-256 of the content in hex, or a prefix of it",
+                _ if path.starts_with("/collections") => "The token of the collection",
+                _ => "The token of the paste",
+            };
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "description": description,
+                "schema": {"type": "string"},
+            })
+        })
+        .collect();
+    if parameters.is_empty() {
+        json!({})
+    } else {
+        json!({"parameters": parameters})
+    }
+}
+
+fn schemas() -> Value {
+    let encodings = strings(&[Encoding::Utf8, Encoding::Base64]);
+    let types = strings(&[PasteType::Text, PasteType::Redirect]);
+    let statuses = strings(&[Status::Published, Status::Pending]);
+    let expiries = strings(&EXPIRY_OPTIONS.iter().map(|(name, _)| *name).collect::<Vec<_>>());
+    let codes = strings(&error::CODES.iter().map(|code| code.as_str()).collect::<Vec<_>>());
+
+    let new_paste = request(ApiNewPaste {
+        content: "print(\"hello\")".to_string(),
+        encoding: Encoding::Utf8,
+        paste_type: PasteType::Text,
+        public: true,
+        tags: vec!["python".to_string()],
+        title: Some("Hello".to_string()),
+        expires: Some("1d".to_string()),
+        normalize_line_endings: Some(true),
+        mirror: false,
+        comments: Some(true),
+    });
+    let created_paste = |full: bool| ApiCreatedPaste {
+        token: "aB3dE6gH".to_string(),
+        secret: full.then(|| "s3cr3t".to_string()),
+        url: "/paste/aB3dE6gH".to_string(),
+        raw_url: full.then(|| "/paste/aB3dE6gH/raw".to_string()),
+        expires_at: full.then_some(1_706_745_600),
+        encoding: Encoding::Utf8,
+        status: Status::Published,
+        gist: full.then(|| "/paste/aB3dE6gH/gist".to_string()),
+    };
+    let search_hit = |full: bool| ApiSearchHit {
+        token: "aB3dE6gH".to_string(),
+        url: "/paste/aB3dE6gH".to_string(),
+        title: full.then(|| "Hello".to_string()),
+        created_at: 1_706_659_200,
+        lines: 1,
+        chars: 14,
+        bytes: 14,
+        snippet: "print(\"hello\")".to_string(),
+        snippet_html: "print(\"<mark>hello</mark>\")".to_string(),
+    };
+    let search_results = |full: bool| ApiSearchResults {
+        results: vec![search_hit(full)],
+        page: 1,
+        next_page: full.then_some(2),
+    };
+    let batch_error = || ApiBatchError {
+        code: "bad_request".to_string(),
+        message: "Tags are limited to 5".to_string(),
+    };
+    let error_body = |full: bool| ApiErrorBody {
+        code: "forbidden".to_string(),
+        message: "This token lacks the \"write\" scope".to_string(),
+        scope: full.then(|| "write".to_string()),
+    };
+    let version = || VersionInfo {
+        build: version::build_info(),
+        token_strategy: "alphanumeric",
+    };
+    let quota = || ApiQuota {
+        limit: 100,
+        remaining: 99,
+        resets_at: 1_706_745_600,
+    };
+    let limits = |full: bool| ApiLimits {
+        max_paste_bytes: 1_048_576,
+        max_batch_pastes: 50,
+        max_fetch_tokens: 100,
+        expiry_options: vec![ApiExpiry {
+            name: "1h",
+            seconds: full.then_some(3600),
+        }],
+        metadata_headers: &METADATA_HEADERS,
+        daily_paste_quota: full.then(quota),
+    };
+    let check = |full: bool| ApiCheck {
+        name: "content",
+        status: "failed",
+        error: full.then(batch_error),
+    };
+    let validation = |full: bool| ApiValidation {
+        valid: false,
+        checks: vec![check(true)],
+        size: full.then_some(14),
+        content_hash: full.then(|| "9b71d224bd62f378".to_string()),
+        public_copy: full.then(|| "/h/9b71d224bd62f378".to_string()),
+        max_paste_bytes: 1_048_576,
+        daily_paste_quota: full.then(quota),
+    };
+    let paste_stats = || PasteStats {
+        token: "aB3dE6gH".to_string(),
+        timezone: "UTC",
+        days: vec![DayViews {
+            date: "2024-01-31".to_string(),
+            views: 3,
+        }],
+    };
+    let comments = || ApiComments {
+        token: "aB3dE6gH".to_string(),
+        open: true,
+        comments: vec![ApiComment {
+            id: 1,
+            author: "Anonymous".to_string(),
+            body: "Thanks!".to_string(),
+            created_at: 1_706_659_200,
+        }],
+    };
+    let created_collection = || ApiCreatedCollection {
+        token: "cD4eF5gH".to_string(),
+        secret: "s3cr3t".to_string(),
+        url: "/c/cD4eF5gH".to_string(),
+    };
+    let member = |full: bool| ApiCollectionMember {
+        token: "aB3dE6gH".to_string(),
+        available: true,
+        url: full.then(|| "/paste/aB3dE6gH".to_string()),
+        preview: full.then(|| "print(\"hello\")".to_string()),
+        paste: full.then(|| paste(false)),
+    };
+    let collection = |full: bool| ApiCollection {
+        token: "cD4eF5gH".to_string(),
+        title: "Snippets".to_string(),
+        url: "/c/cD4eF5gH".to_string(),
+        created_at: 1_706_659_200,
+        updated_at: 1_706_659_200,
+        expires_at: full.then_some(1_706_745_600),
+        pastes: vec![member(true)],
+    };
+    let hash_matches = || ApiHashMatches {
+        matches: vec![ApiHashMatch {
+            hash: "9b71d224bd62f378".to_string(),
+            url: "/api/h/9b71d224bd62f378".to_string(),
+        }],
+    };
+
+    json!({
+        "ApiNewPaste": with(
+            with(with(new_paste, "encoding", encodings.clone()), "type", types.clone()),
+            "expires",
+            expiries.clone(),
+        ),
+        "ApiNewBatch": with(
+            request(ApiNewBatch { pastes: vec![ApiNewPaste::default()], atomic: true }),
+            "pastes",
+            array(reference("ApiNewPaste")),
+        ),
+        "ApiCreatedPaste": with(
+            with(answer(created_paste(true), created_paste(false)), "encoding", encodings.clone()),
+            "status",
+            statuses.clone(),
+        ),
+        "ApiBatchResult": {"oneOf": [reference("ApiCreatedPaste"), reference("ApiBatchFailure")]},
+        "ApiBatchFailure": with(
+            answer(ApiBatchResult::Failed { error: batch_error() }, ApiBatchResult::Failed { error: batch_error() }),
+            "error",
+            reference("ApiBatchError"),
+        ),
+        "ApiBatchError": with(answer(batch_error(), batch_error()), "code", codes.clone()),
+        "ApiPaste": with(
+            with(with(answer(paste(true), paste(false)), "encoding", encodings.clone()), "type", types),
+            "status",
+            statuses,
+        ),
+        "ApiSearchResults": with(
+            answer(search_results(true), search_results(false)),
+            "results",
+            array(reference("ApiSearchHit")),
+        ),
+        "ApiSearchHit": answer(search_hit(true), search_hit(false)),
+        "ApiErrorEnvelope": with(
+            answer(ApiErrorEnvelope { error: error_body(true) }, ApiErrorEnvelope { error: error_body(false) }),
+            "error",
+            reference("ApiErrorBody"),
+        ),
+        "ApiErrorBody": with(answer(error_body(true), error_body(false)), "code", codes),
+        "Version": answer(version(), version()),
+        "ApiLimits": with(answer(limits(true), limits(false)), "daily_paste_quota", reference("ApiQuota")),
+        "ApiQuota": answer(quota(), quota()),
+        "ApiValidatePaste": with(
+            with(
+                request(ApiValidatePaste {
+                    content: Some("print(\"hello\")".to_string()),
+                    size: Some(14),
+                    sha256: Some("9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca7".to_string()),
+                    token: Some("hello".to_string()),
+                    encoding: Encoding::Utf8,
+                    paste_type: PasteType::Text,
+                    tags: vec!["python".to_string()],
+                    title: Some("Hello".to_string()),
+                    expires: Some("1d".to_string()),
+                    normalize_line_endings: Some(true),
+                    mirror: false,
+                }),
+                "encoding",
+                encodings,
+            ),
+            "expires",
+            expiries.clone(),
+        ),
+        "ApiValidation": with(
+            with(answer(validation(true), validation(false)), "checks", array(reference("ApiCheck"))),
+            "daily_paste_quota",
+            reference("ApiQuota"),
+        ),
+        "ApiCheck": with(
+            with(answer(check(true), check(false)), "status", strings(&["passed", "failed", "skipped"])),
+            "error",
+            reference("ApiBatchError"),
+        ),
+        "ApiFetch": request(ApiFetch {
+            tokens: vec!["aB3dE6gH".to_string()],
+            include_content: Some(true),
+        }),
+        "PasteStats": answer(paste_stats(), paste_stats()),
+        "ApiComments": answer(comments(), comments()),
+        "ApiNewCollection": with(
+            request(ApiNewCollection {
+                title: "Snippets".to_string(),
+                pastes: vec!["aB3dE6gH".to_string()],
+                expires: Some("1w".to_string()),
+            }),
+            "expires",
+            expiries,
+        ),
+        "ApiCollectionPastes": request(ApiCollectionPastes { pastes: vec!["aB3dE6gH".to_string()] }),
+        "ApiCreatedCollection": answer(created_collection(), created_collection()),
+        "ApiCollection": with(
+            answer(collection(true), collection(false)),
+            "pastes",
+            array(reference("ApiCollectionMember")),
+        ),
+        "ApiCollectionMember": with(answer(member(true), member(false)), "paste", reference("ApiPaste")),
+        "ApiHashMatches": answer(hash_matches(), hash_matches()),
+        "FederationReport": request(federation::Report {
+            instance: "paste.example.com".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            backend: "sqlite".to_string(),
+            pastes: 1200,
+            archived_pastes: 300,
+            content_bytes: 5_000_000,
+            stored_bytes: 3_000_000,
+        }),
+    })
+}
+
+// A paste as the API answers it, without its optional fields unless `full`.
+fn paste(full: bool) -> ApiPaste {
+    ApiPaste {
+        token: "aB3dE6gH".to_string(),
+        content: full.then(|| "print(\"hello\")".to_string()),
+        encoding: Encoding::Utf8,
+        paste_type: PasteType::Text,
+        public: true,
+        status: Status::Published,
+        views: 3,
+        created_at: 1_706_659_200,
+        expires_at: full.then_some(1_706_745_600),
+        tags: vec!["python".to_string()],
+        title: full.then(|| "Hello".to_string()),
+        lines: 1,
+        chars: 14,
+        bytes: 14,
+    }
+}
+
+// The answers with the error envelope, one per code, and `error` for any of them.
+fn error_responses() -> Value {
+    let envelope = json!({"application/json": {"schema": reference("ApiErrorEnvelope")}});
+    let mut responses = Map::new();
+    for code in error::CODES {
+        let mut response = json!({
+            "description": format!("`{}`", code.as_str()),
+            "content": envelope,
+        });
+        if code == ErrorCode::RateLimited {
+            response["description"] = json!("`rate_limited`, the daily quota is used up");
+            response["headers"] = rate_limit_headers(true);
+        }
+        responses.insert(code.as_str().to_string(), response);
+    }
+    responses.insert(
+        "error".to_string(),
+        json!({"description": "An error, its `code` tells which", "content": envelope}),
+    );
+    Value::Object(responses)
+}
+
+fn header(description: &str) -> Value {
+    json!({"description": description, "schema": {"type": "integer"}})
+}
+
+// The headers describing the daily quota, see `rate_limit_headers` of the server.
+fn rate_limit_headers(limited: bool) -> Value {
+    let mut headers = json!({
+        "X-RateLimit-Limit": {"$ref": "#/components/headers/X-RateLimit-Limit"},
+        "X-RateLimit-Remaining": {"$ref": "#/components/headers/X-RateLimit-Remaining"},
+        "X-RateLimit-Reset": {"$ref": "#/components/headers/X-RateLimit-Reset"},
+    });
+    if limited {
+        headers["Retry-After"] = json!({"$ref": "#/components/headers/Retry-After"});
+    }
+    headers
+}
+
+// The query parameters of the struct a query string deserializes to, see `request`.
+fn query_parameters<T: Serialize + DeserializeOwned>(full: T) -> Vec<Value> {
+    let schema = request(full);
+    let required = schema["required"].as_array().cloned().unwrap_or_default();
+    let properties = schema["properties"].as_object().cloned().unwrap_or_default();
+    properties
+        .into_iter()
+        .map(|(name, mut property)| {
+            if let Some(property) = property.as_object_mut() {
+                property.remove("nullable");
+            }
+            json!({
+                "name": name,
+                "in": "query",
+                "required": required.contains(&json!(name)),
+                "schema": property,
+            })
+        })
+        .collect()
+}
+
+// The schema of a request from a sample with every field, asking the struct which ones it can do without.
+fn request<T: Serialize + DeserializeOwned>(full: T) -> Value {
+    let full = sample(full);
+    let fields = full.as_object().expect("requests are structs");
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for (name, value) in fields {
+        let mut schema = shape(value);
+        let mut without = fields.clone();
+        without.remove(name);
+        if serde_json::from_value::<T>(Value::Object(without.clone())).is_err() {
+            required.push(name.clone());
+        }
+        without.insert(name.clone(), Value::Null);
+        if serde_json::from_value::<T>(Value::Object(without)).is_ok() {
+            schema["nullable"] = json!(true);
+        }
+        properties.insert(name.clone(), schema);
+    }
+    object(properties, required)
+}
+
+// The schema of an answer from a sample with every field and one with none of the optional ones.
+fn answer<T: Serialize>(full: T, bare: T) -> Value {
+    described(&sample(full), Some(&sample(bare)))
+}
+
+// The schema of `full`, with what `bare` leaves out or has as `null` optional or nullable, in objects inside too.
+// An object without its bare counterpart has all its fields required.
+fn described(full: &Value, bare: Option<&Value>) -> Value {
+    match (full, bare) {
+        (Value::Object(fields), Some(Value::Object(bare_fields))) => {
+            let mut properties = Map::new();
+            let mut required = Vec::new();
+            for (name, value) in fields {
+                let bare_value = bare_fields.get(name);
+                let mut schema = described(value, bare_value.filter(|value| !value.is_null()));
+                match bare_value {
+                    Some(Value::Null) => {
+                        schema["nullable"] = json!(true);
+                        required.push(name.clone());
+                    }
+                    Some(_) => required.push(name.clone()),
+                    None => {}
+                }
+                properties.insert(name.clone(), schema);
+            }
+            object(properties, required)
+        }
+        (Value::Object(_), _) => described(full, Some(full)),
+        (Value::Array(items), bare) => {
+            let bare_item = bare.and_then(Value::as_array).and_then(|items| items.first());
+            json!({
+                "type": "array",
+                "items": items.first().map_or(json!({}), |item| described(item, bare_item)),
+            })
+        }
+        _ => shape(full),
+    }
+}
+
+// The schema of a value: its JSON type, RFC 3339 strings as `date-time`, arrays by their first item, and objects
+// with all their fields required.
+fn shape(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({}),
+        Value::Bool(_) => json!({"type": "boolean"}),
+        Value::Number(number) if number.is_f64() => json!({"type": "number"}),
+        Value::Number(_) => json!({"type": "integer"}),
+        Value::String(text) if chrono::DateTime::parse_from_rfc3339(text).is_ok() => {
+            json!({"type": "string", "format": "date-time"})
+        }
+        Value::String(_) => json!({"type": "string"}),
+        Value::Array(items) => json!({"type": "array", "items": items.first().map_or(json!({}), shape)}),
+        Value::Object(_) => described(value, Some(value)),
+    }
+}
+
+fn object(properties: Map<String, Value>, required: Vec<String>) -> Value {
+    let mut schema = json!({"type": "object", "properties": properties});
+    // OpenAPI 3.0 wants no list rather than an empty one
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+// `schema` with its property `name` replaced, by a reference or an enumeration, staying nullable if it was.
+fn with(mut schema: Value, name: &str, replacement: Value) -> Value {
+    let property = &mut schema["properties"][name];
+    assert!(property.is_object(), "No property \"{}\" to replace in the OpenAPI document", name);
+    let nullable = property["nullable"] == json!(true);
+    *property = if nullable { self::nullable(replacement) } else { replacement };
+    schema
+}
+
+fn nullable(schema: Value) -> Value {
+    if schema.get("$ref").is_some() {
+        json!({"allOf": [schema], "nullable": true})
+    } else {
+        let mut schema = schema;
+        schema["nullable"] = json!(true);
+        schema
+    }
+}
+
+fn reference(name: &str) -> Value {
+    json!({"$ref": format!("#/components/schemas/{}", name)})
+}
+
+fn array(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+// A string among `values`, as they serialize.
+fn strings<T: Serialize>(values: &[T]) -> Value {
+    json!({"type": "string", "enum": values.iter().map(sample).collect::<Vec<_>>()})
+}
+
+fn sample<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).expect("the samples of the OpenAPI document serialize")
+}
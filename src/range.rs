@@ -0,0 +1,181 @@
+// Byte ranges of the content routes (“/raw”, “/download”), so that `curl -C -` and download managers can resume.
+// One range per request: `bytes=0-499`, `bytes=500-` or `bytes=-500` (the last 500 bytes). Several ranges, a unit
+// other than bytes or a header that doesn't parse get the whole content, as if there was no `Range` at all.
+// A range starting past the end is a 416. `If-Range` with anything but the ETag of the content (its hash in quotes)
+// gets the whole content too, the part of a paste that changed since would be a part of something else.
+// Responses made as they are sent (the zip archive) have no length to take a range of and answer `Accept-Ranges: none`.
+
+use actix_web::{web, HttpRequest};
+use futures_util::stream::{self, Stream};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Size of the chunks raw and download responses are sent in.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+// The part of the content a request asked for.
+#[derive(PartialEq, Debug)]
+pub enum Requested {
+    Whole,
+    // First and last byte, both included
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+// Where the content of a response comes from.
+pub enum Source {
+    Bytes(web::Bytes),
+    File(File),
+}
+
+// How many bytes `source` has.
+pub fn length(source: &Source) -> io::Result<u64> {
+    match source {
+        Source::Bytes(bytes) => Ok(bytes.len() as u64),
+        Source::File(file) => Ok(file.metadata()?.len()),
+    }
+}
+
+// What `req` asks for of content of `length` bytes whose ETag is `etag`.
+pub fn requested(req: &HttpRequest, length: u64, etag: Option<&str>) -> Requested {
+    let range = match req.headers().get("Range").and_then(|value| value.to_str().ok()) {
+        Some(range) => range,
+        None => return Requested::Whole,
+    };
+    if let Some(if_range) = req.headers().get("If-Range") {
+        if etag.is_none() || if_range.to_str().ok() != etag {
+            return Requested::Whole;
+        }
+    }
+    parse(range, length)
+}
+
+// A `Range` header, see the top of the file.
+fn parse(range: &str, length: u64) -> Requested {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Requested::Whole,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Requested::Whole,
+    };
+    let number = |bound: &str| bound.parse::<u64>().ok();
+
+    if first.is_empty() {
+        return match number(last) {
+            Some(suffix) if suffix > 0 && length > 0 => Requested::Part(length.saturating_sub(suffix), length - 1),
+            Some(_) => Requested::Unsatisfiable,
+            None => Requested::Whole,
+        };
+    }
+    let first = match number(first) {
+        Some(first) => first,
+        None => return Requested::Whole,
+    };
+    let last = match number(last) {
+        Some(last) if last >= first => last.min(length.saturating_sub(1)),
+        None if last.is_empty() => length.saturating_sub(1),
+        _ => return Requested::Whole,
+    };
+    if first >= length {
+        Requested::Unsatisfiable
+    } else {
+        Requested::Part(first, last)
+    }
+}
+
+// The bytes of `source` from `start`, `len` of them, as a stream of chunks. Files are read on the blocking thread pool.
+pub fn body(source: Source, start: u64, len: u64) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> + Unpin {
+    Box::pin(stream::unfold((source, start, len), |(source, offset, left)| async move {
+        if left == 0 {
+            return None;
+        }
+        let want = left.min(CHUNK_BYTES as u64);
+        match source {
+            Source::Bytes(bytes) => {
+                let chunk = bytes.slice(offset as usize..(offset + want) as usize);
+                Some((Ok(chunk), (Source::Bytes(bytes), offset + want, left - want)))
+            }
+            Source::File(mut file) => {
+                let result = web::block(move || {
+                    file.seek(SeekFrom::Start(offset))?;
+                    let mut chunk = vec![0; want as usize];
+                    file.read_exact(&mut chunk)?;
+                    Ok::<_, io::Error>((file, chunk))
+                })
+                .await;
+                match result {
+                    Ok((file, chunk)) => Some((Ok(web::Bytes::from(chunk)), (Source::File(file), offset + want, left - want))),
+                    // The file is gone or got shorter, the response can only stop
+                    Err(e) => {
+                        let error = actix_web::error::ErrorInternalServerError(e.to_string());
+                        Some((Err(error), (Source::Bytes(web::Bytes::new()), offset, 0)))
+                    }
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn parses_one_range() {
+        assert_eq!(parse("bytes=0-499", 1000), Requested::Part(0, 499));
+        assert_eq!(parse("bytes=0-", 1000), Requested::Part(0, 999));
+        assert_eq!(parse("bytes=500-", 1000), Requested::Part(500, 999));
+        assert_eq!(parse(" bytes= 10-10 ", 1000), Requested::Part(10, 10));
+        // The last byte past the end is the end
+        assert_eq!(parse("bytes=900-5000", 1000), Requested::Part(900, 999));
+    }
+
+    #[test]
+    fn parses_suffixes() {
+        assert_eq!(parse("bytes=-500", 1000), Requested::Part(500, 999));
+        assert_eq!(parse("bytes=-5000", 1000), Requested::Part(0, 999));
+        assert_eq!(parse("bytes=-0", 1000), Requested::Unsatisfiable);
+    }
+
+    #[test]
+    fn nothing_of_empty_content_is_satisfiable() {
+        assert_eq!(parse("bytes=0-", 0), Requested::Unsatisfiable);
+        assert_eq!(parse("bytes=0-10", 0), Requested::Unsatisfiable);
+        assert_eq!(parse("bytes=-500", 0), Requested::Unsatisfiable);
+    }
+
+    #[test]
+    fn starting_past_the_end_is_unsatisfiable() {
+        assert_eq!(parse("bytes=1000-", 1000), Requested::Unsatisfiable);
+        assert_eq!(parse("bytes=2000-3000", 1000), Requested::Unsatisfiable);
+    }
+
+    #[test]
+    fn other_ranges_get_the_whole_content() {
+        for range in ["bytes=500-100", "bytes=0-10,20-30", "items=0-10", "bytes=", "bytes=-", "bytes=a-b", "bytes=10"] {
+            assert_eq!(parse(range, 1000), Requested::Whole, "{}", range);
+        }
+    }
+
+    #[test]
+    fn if_range_needs_the_etag() {
+        let request = |if_range: Option<&str>| {
+            let request = TestRequest::default().header("Range", "bytes=0-9");
+            match if_range {
+                Some(if_range) => request.header("If-Range", if_range).to_http_request(),
+                None => request.to_http_request(),
+            }
+        };
+        assert_eq!(requested(&request(None), 100, Some("\"abc\"")), Requested::Part(0, 9));
+        assert_eq!(requested(&request(Some("\"abc\"")), 100, Some("\"abc\"")), Requested::Part(0, 9));
+        assert_eq!(requested(&request(Some("\"old\"")), 100, Some("\"abc\"")), Requested::Whole);
+        // A date, or content without an ETag
+        assert_eq!(requested(&request(Some("Wed, 21 Oct 2015 07:28:00 GMT")), 100, Some("\"abc\"")), Requested::Whole);
+        assert_eq!(requested(&request(Some("\"abc\"")), 100, None), Requested::Whole);
+        let without_range = TestRequest::default().to_http_request();
+        assert_eq!(requested(&without_range, 100, Some("\"abc\"")), Requested::Whole);
+    }
+}
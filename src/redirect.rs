@@ -0,0 +1,150 @@
+// Checks of the target of short links (redirect pastes).
+// A short link hides where it goes, so only plain http(s) URLs are accepted: never `javascript:` or `data:`,
+// and by default nothing pointing at localhost or a private network, so a link from here can't be used
+// to send a visitor's browser to the services of their own network.
+// What following a short link does is the instance's `PASTRY_REDIRECT_MODE`, as a short link can just as well send
+// someone to a phishing page made to look like the site they came from.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use url::{Host, Url};
+
+// Why a short link is refused, or not followed, with `Mode::Disabled`.
+pub const DISABLED: &str = "Short links are turned off on this instance";
+
+// What “/paste/{token}” does for a short link.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    // A 302 to the target, the link is followed without a look
+    #[default]
+    Direct,
+    // A page naming the host the link goes to, with a link to follow
+    Interstitial,
+    // No short link can be created, and those there are get a 403 instead of being followed
+    Disabled,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Mode, String> {
+        match value {
+            "direct" => Ok(Mode::Direct),
+            "interstitial" => Ok(Mode::Interstitial),
+            "disabled" => Ok(Mode::Disabled),
+            _ => Err(format!("Unknown redirect mode \"{}\", expected direct, interstitial or disabled", value)),
+        }
+    }
+}
+
+// The host of a target `check_target` accepted, as the interstitial shows it. International names are in their
+// ASCII form (“xn--…”), so a name can't pass for another with letters of another script that look the same.
+pub fn host(target: &str) -> String {
+    Url::parse(target)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+// Checks that `content` is exactly one acceptable http(s) URL, surrounding whitespace aside,
+// and returns it as it will be redirected to.
+// With `allow_internal`, hosts on localhost and private networks are accepted too.
+pub fn check_target(content: &str, allow_internal: bool) -> Result<String, String> {
+    let target = content.trim();
+    if target.is_empty() || target.chars().any(char::is_whitespace) {
+        return Err("A short link must be exactly one URL".to_string());
+    }
+
+    let url = Url::parse(target).map_err(|e| format!("{:?} is not a valid URL: {}", target, e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("Short links can only point to http:// and https:// URLs".to_string());
+    }
+
+    let internal = match url.host() {
+        None => return Err("A short link needs a host name".to_string()),
+        Some(Host::Domain(domain)) => is_internal_domain(domain),
+        Some(Host::Ipv4(ip)) => is_internal_ip(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_internal_ip(IpAddr::V6(ip)),
+    };
+    if internal && !allow_internal {
+        return Err("Short links can't point to localhost or private network addresses".to_string());
+    }
+
+    Ok(url.to_string())
+}
+
+// Names that only mean something inside a network.
+pub fn is_internal_domain(domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    domain == "localhost"
+        || !domain.contains('.')
+        || [".localhost", ".local", ".internal", ".lan", ".home.arpa"]
+            .iter()
+            .any(|suffix| domain.ends_with(suffix))
+}
+
+// Addresses of localhost and private networks, IPv4 ones written as IPv6 included. The image proxy refuses to
+// connect to them too (see `images.rs`).
+pub fn is_internal_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal_ipv4(ip),
+            None => is_internal_ipv6(ip),
+        },
+    }
+}
+
+fn is_internal_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_internal_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // NAT64, 64:ff9b::/96, reaches the IPv4 address in its last 32 bits
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_internal_ipv4(Ipv4Addr::from(u128::from(ip) as u32));
+    }
+    let first = segments[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // Unique local, fc00::/7, and link-local, fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn internal(ip: &str) -> bool {
+        is_internal_ip(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn internal_addresses_are_found_however_they_are_written() {
+        for ip in ["127.0.0.1", "10.1.2.3", "192.168.0.1", "100.64.0.1", "::1", "fd00::1", "fe80::1"] {
+            assert!(internal(ip), "{}", ip);
+        }
+        for ip in ["::ffff:127.0.0.1", "::ffff:10.1.2.3", "64:ff9b::7f00:1", "64:ff9b::a01:203", "64:ff9b::c0a8:1"] {
+            assert!(internal(ip), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1::1", "::ffff:93.184.216.34", "64:ff9b::5db8:d822"] {
+            assert!(!internal(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn short_links_to_nat64_addresses_of_private_networks_are_refused() {
+        assert!(check_target("http://[64:ff9b::a00:1]/", false).is_err());
+        assert!(check_target("http://[64:ff9b::a00:1]/", true).is_ok());
+        assert!(check_target("http://[64:ff9b::5db8:d822]/", false).is_ok());
+    }
+}
@@ -0,0 +1,35 @@
+// Words no paste token may be, in one place for every path a token comes in by: the tokens clients pick
+// (`token::check_chosen`) and the generated ones (`free_token` draws again).
+// They are every word of every route, so that a token can never be read as a route: today next to
+// “/api/pastes/{token}” (“batch”, “fetch”), and anywhere should tokens ever get shorter URLs (“/{token}”).
+// Routes are registered through `route`, which refuses, at startup, a route with a word missing here.
+// A few words are kept for routes to come.
+
+pub const RESERVED: &[&str] = &[
+    "about", "admin", "announcements", "api", "approve", "archive", "audit", "backup", "bans", "batch", "c",
+    "collections", "comments", "created", "db", "delete", "dismiss", "download", "edit", "federation", "fetch", "gist",
+    "h", "healthz", "help", "imgproxy", "integrity-check", "language", "limits", "login", "logout", "metrics", "mine",
+    "new", "openapi", "paste", "pastes", "popular", "preview", "print", "purge", "raw", "reject", "reserved", "review",
+    "revoke", "s", "search", "static", "stats", "style", "submit", "tags", "tokens", "validate", "version", "wrap",
+];
+
+// Whether `token` is one of the reserved words, in any case.
+pub fn is_reserved(token: &str) -> bool {
+    RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(token))
+}
+
+// The path of a route, after checking that each of its words is reserved: “/paste/{token}/archive.zip” needs
+// “paste” and “archive”, parameters and extensions don't count. Panics on one that isn't, there is nothing to do
+// about it but adding the word to `RESERVED`.
+pub fn route(path: &'static str) -> &'static str {
+    for segment in path.split('/').filter(|segment| !segment.is_empty() && !segment.starts_with('{')) {
+        let word = segment.split('.').next().unwrap_or(segment);
+        assert!(
+            is_reserved(word),
+            "The route {} uses \"{}\", which is missing from reserved::RESERVED",
+            path,
+            word
+        );
+    }
+    path
+}
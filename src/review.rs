@@ -0,0 +1,159 @@
+// Pastes held for review before anyone sees them, for instances where a moderator reads everything first (a
+// classroom, say). Off by default; with `PASTRY_REVIEW_PASTES` every new paste, from the form or the API, and every
+// paste replaced through the API, is stored pending. Its creator lands on the page of a new paste, told that it awaits
+// review, and keeps seeing it through its private link (“/paste/{token}?key=…”) or the creator cookie; anyone else
+// gets a 404 on every route reading it, and it is left out of the listings, the search, “/h/{hash}”, the batch
+// fetch and collections. The API shows `"status": "pending"` to those who may see it.
+// “/admin/review” lists the pending pastes, oldest first. `POST /admin/review/{token}/approve` publishes one as if it
+// had just been created, starting the gist mirror asked for meanwhile; `POST /admin/review/{token}/reject` deletes
+// it, with `?ban=true` also banning the address it came from, which is only kept while the paste is pending.
+// Turning the setting off holds no new paste, the pending ones stay pending until an admin decides.
+
+use crate::error::AppError;
+use crate::store::{self, GistState};
+use crate::{admin_name, admin_template, audit_entry, escape_html, format_bytes, mirror_to_gist, refresh_bans};
+use crate::{bans, collections, require_admin, reserved, store_write, telemetry, timestamp, AppState, DbQuery};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+// Most pastes the queue lists, the oldest ones; deciding on them brings the next ones.
+pub const QUEUE_LIMIT: i64 = 500;
+
+// What anyone but the creator and the admins is told of a pending paste.
+pub const AWAITING: &str = "This paste is awaiting review";
+
+// Whether a paste can be seen by everyone, as the API tells it.
+pub use pastry_crust::api::Status;
+
+// The reason of the ban of the address a rejected paste came from.
+pub fn ban_reason(token: &str) -> String {
+    format!("Paste {} rejected in review", token)
+}
+
+// The admin routes of the review queue.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/admin/review"), web::get().to(admin_review))
+        .route(reserved::route("/admin/review/{token}/approve"), web::post().to(admin_approve))
+        .route(reserved::route("/admin/review/{token}/reject"), web::post().to(admin_reject));
+}
+
+// Handles “GET /admin/review”, the pastes awaiting review, oldest first. A page by default, the same
+// as JSON with `?format=json`.
+async fn admin_review(req: HttpRequest, query: web::Query<DbQuery>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+
+    let pending = data.store.pending_pastes(QUEUE_LIMIT)?;
+    if query.format.as_deref() == Some("json") {
+        return Ok(HttpResponse::Ok().json(pending));
+    }
+
+    let mut report = String::new();
+    for paste in &pending {
+        let kind = match (paste.redirect, paste.binary) {
+            (true, _) => "redirect",
+            (false, true) => "binary",
+            (false, false) => "text",
+        };
+        report.push_str(&format!(
+            "{:<12} {}  {:>10}  {}, {}{}, from {}\n",
+            paste.token,
+            timestamp::absolute(paste.created_at),
+            format_bytes(paste.bytes),
+            kind,
+            if paste.public { "public" } else { "unlisted" },
+            paste
+                .expires_at
+                .map_or_else(String::new, |at| format!(", expires {}", timestamp::absolute(at))),
+            paste.submitter.as_deref().map_or("unknown", |submitter| &submitter[..submitter.len().min(16)])
+        ));
+        let preview = collections::preview(&paste.preview);
+        if !preview.is_empty() {
+            report.push_str(&format!("             {}\n", preview));
+        }
+    }
+    if pending.is_empty() {
+        report.push_str("No paste awaits review\n");
+    }
+    let _render = telemetry::template("admin_review.html");
+    let html_page = admin_template(&data, include_str!("admin_review.html")).replace("{{report}}", &escape_html(&report));
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// Handles “POST /admin/review/{token}/approve”, publishing a paste awaiting review. Answers 204, a 404 when the paste
+// isn't pending.
+async fn admin_approve(req: HttpRequest, token: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    require_admin(&req, &data)?;
+    let token = token.into_inner();
+    let audit = audit_entry(&req, &data, "admin", "approve", Some(token.clone()), String::new());
+    let approved = token.clone();
+    if !store_write(&data, move |store| store.approve(&approved, Some(&audit))).await? {
+        return Err(AppError::not_found("No paste awaiting review has this token"));
+    }
+    data.cache.remove(&token);
+
+    // The mirror asked for at creation was left pending by `mirror_to_gist`
+    if data.gist.is_some() && matches!(data.store.gist_mirror(&token)?, Some(store::GistMirror { state: GistState::Pending, .. })) {
+        actix_web::rt::spawn(mirror_to_gist(data.clone(), token));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Handles “POST /admin/review/{token}/reject”, deleting a paste awaiting review, and with `?ban=true` banning the
+// address it was submitted from as well, by its hash: a 400 without `PASTRY_IP_SALT`, before anything is deleted.
+// Answers 204, a 404 when the paste isn't pending.
+async fn admin_reject(
+    req: HttpRequest,
+    token: web::Path<String>,
+    query: web::Query<ReviewQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let caller = require_admin(&req, &data)?;
+    let ban = query.ban.unwrap_or(false);
+    if ban {
+        bans::hash_bannable(&data)?;
+    }
+    let token = token.into_inner();
+    let audit = audit_entry(&req, &data, "admin", "reject", Some(token.clone()), String::new());
+    let mut ban_audit = audit_entry(&req, &data, "admin", "ban", None, String::new());
+    let created_by = admin_name(&caller);
+    let rejected_token = token.clone();
+    // The paste is rejected and its submitter banned together, or neither is
+    let rejected = store_write(&data, move |store| {
+        let mut rejected = None;
+        store.with_tx(&mut || {
+            rejected = store.reject(&rejected_token, Some(&audit))?;
+            if let (true, Some(Some(ip_hash))) = (ban, &rejected) {
+                let ban = store::NewBan {
+                    ip_hash: Some(ip_hash.clone()),
+                    cidr: None,
+                    reason: ban_reason(&rejected_token),
+                    expires_at: None,
+                    created_by: created_by.clone(),
+                };
+                ban_audit.detail = format!("{}: {}", bans::banned_what(ban.ip_hash.as_deref(), None), ban.reason);
+                store.add_ban(&ban, Some(&ban_audit))?;
+            }
+            Ok(())
+        })?;
+        Ok(rejected)
+    })
+    .await?;
+    let submitter = rejected.ok_or_else(|| AppError::not_found("No paste awaiting review has this token"))?;
+    data.cache.remove(&token);
+
+    if ban {
+        if submitter.is_none() {
+            return Err(AppError::bad_request(
+                "The paste was rejected, but the address it came from is unknown and can't be banned",
+            ));
+        }
+        refresh_bans(&data)?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize)]
+struct ReviewQuery {
+    ban: Option<bool>,
+}
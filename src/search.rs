@@ -0,0 +1,218 @@
+// The query of “/search” and “/api/search”, and the snippets of its results.
+// A query is words separated by spaces, every one of them has to be in a paste for it to be found;
+// a word holding punctuation (“foo.bar”, “can't”) is looked for as the run of its parts.
+// Nothing typed is an operator, quotes and stars included, so no query can fail to parse.
+
+use crate::error::AppError;
+use crate::i18n::Texts;
+use crate::store::{self, SNIPPET_END, SNIPPET_START};
+use crate::{api_tokens, assets, openapi, reserved, tags, telemetry, timestamp};
+use crate::{authorize, back_path, day_start, escape_html, format_size, paste_label, AppState};
+use actix_web::{web, HttpRequest, HttpResponse};
+use pastry_crust::api::{ApiSearchHit, ApiSearchResults, SearchParams};
+
+// Longest query taken, in characters, and most words of it looked for.
+pub const MAX_QUERY_CHARS: usize = 200;
+pub const MAX_TERMS: usize = 10;
+
+// Results per page of “/search” and “/api/search”, and the last page they go to.
+const PAGE_SIZE: i64 = 20;
+const MAX_PAGE: i64 = 50;
+
+// Turns what was typed into the terms of a `SearchQuery`, lowercased and deduplicated.
+// Words without a letter or a digit can't match anything and are dropped; an empty list means there is nothing to look for.
+// Returns a message suitable for showing to the user when the query is too long.
+pub fn parse_terms(query: &str) -> Result<Vec<String>, String> {
+    if query.chars().count() > MAX_QUERY_CHARS {
+        return Err(format!("Search queries are limited to {} characters", MAX_QUERY_CHARS));
+    }
+
+    let mut terms: Vec<String> = Vec::new();
+    for word in query.split_whitespace() {
+        let term = word.to_lowercase();
+        if !term.chars().any(char::is_alphanumeric) || terms.contains(&term) {
+            continue;
+        }
+        terms.push(term);
+    }
+
+    if terms.len() > MAX_TERMS {
+        return Err(format!("Search at most {} words at once", MAX_TERMS));
+    }
+    Ok(terms)
+}
+
+// A snippet as the store returns it, as HTML: the text escaped and the terms in `<mark>`.
+// A content holding the marker characters itself can't leave a `<mark>` open or close one it didn't open.
+pub fn snippet_html(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len() + 32);
+    let mut open = false;
+    for part in snippet.split_inclusive([SNIPPET_START, SNIPPET_END]) {
+        let (text, marker) = match part.strip_suffix([SNIPPET_START, SNIPPET_END]) {
+            Some(text) => (text, part.chars().last()),
+            None => (part, None),
+        };
+        html.push_str(&crate::escape_html(text));
+        match marker {
+            Some(SNIPPET_START) if !open => {
+                html.push_str("<mark>");
+                open = true;
+            }
+            Some(SNIPPET_END) if open => {
+                html.push_str("</mark>");
+                open = false;
+            }
+            _ => {}
+        }
+    }
+    if open {
+        html.push_str("</mark>");
+    }
+    html
+}
+
+// The same snippet as plain text, without the markers.
+pub fn snippet_text(snippet: &str) -> String {
+    snippet.chars().filter(|c| *c != SNIPPET_START && *c != SNIPPET_END).collect()
+}
+
+// The page of the search.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(reserved::route("/search"), web::get().to(search_page));
+}
+
+// The search in the `/api` scope.
+pub fn configure_api(cfg: &mut web::ServiceConfig) {
+    cfg.route(openapi::route("GET", "/search"), web::get().to(api_search));
+}
+
+// Runs the search `params` asks for, the same for the page and the API: the hits of its page and whether
+// there is a next one, `None` when there is nothing to look for.
+fn run_search(params: &SearchParams, data: &AppState) -> Result<Option<(Vec<store::SearchHit>, bool)>, AppError> {
+    let terms = parse_terms(&params.q).map_err(AppError::bad_request)?;
+    let tag = params.tag.as_deref().filter(|tag| !tag.is_empty()).map(str::to_lowercase);
+    if let Some(tag) = &tag {
+        tags::validate_tag(tag).map_err(AppError::bad_request)?;
+    }
+    let page = params.page.unwrap_or(1);
+    if !(1..=MAX_PAGE).contains(&page) {
+        return Err(AppError::bad_request(format!("`page` must be between 1 and {}", MAX_PAGE)));
+    }
+    let query = store::SearchQuery {
+        terms,
+        tag,
+        since: day_start("since", &params.since, 0)?,
+        until: day_start("until", &params.until, 1)?,
+        // One more than shown, to know whether there is a next page
+        limit: PAGE_SIZE + 1,
+        offset: (page - 1) * PAGE_SIZE,
+    };
+    if query.terms.is_empty() {
+        return Ok(None);
+    }
+
+    let mut hits = data.store.search(&query)?;
+    let more = hits.len() as i64 > PAGE_SIZE && page < MAX_PAGE;
+    hits.truncate(PAGE_SIZE as usize);
+    Ok(Some((hits, more)))
+}
+
+// Handles “/search”, the public pastes holding every word of `q`, the best matches first, with the words marked
+// in a snippet of each; `tag`, `since` and `until` narrow it down. Without a query it's only the form.
+async fn search_page(req: HttpRequest, query: web::Query<SearchParams>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let texts = Texts::of(&req);
+    let results = run_search(&query, &data)?;
+    let page = query.page.unwrap_or(1);
+
+    let (list_items, pages) = match results {
+        None => (String::new(), String::new()),
+        Some((hits, more)) => {
+            let list_items: String = hits
+                .iter()
+                .map(|hit| {
+                    format!(
+                        "<li><a href=\"/paste/{token}\">{label}</a> &middot; {created}{size}<span class=\"preview snippet\">{snippet}</span></li>",
+                        token = escape_html(&hit.token),
+                        label = paste_label(&hit.token, &hit.title),
+                        created = timestamp::html(hit.created_at, texts),
+                        size = hit.size.map(|size| format!(" &middot; {}", escape_html(&format_size(&size, texts)))).unwrap_or_default(),
+                        snippet = snippet_html(&hit.snippet),
+                    )
+                })
+                .collect();
+            let list_items = if list_items.is_empty() {
+                format!("<li>{}</li>", escape_html(texts.text("search.none")))
+            } else {
+                list_items
+            };
+
+            let link = |page: i64, text: &str| {
+                let params = SearchParams {
+                    page: Some(page),
+                    q: query.q.clone(),
+                    tag: query.tag.clone().filter(|tag| !tag.is_empty()),
+                    since: query.since.clone().filter(|since| !since.is_empty()),
+                    until: query.until.clone().filter(|until| !until.is_empty()),
+                };
+                format!(
+                    "<a class=\"underline\" href=\"/search?{}\">{}</a>",
+                    escape_html(&serde_urlencoded::to_string(&params).unwrap_or_default()),
+                    text
+                )
+            };
+            let mut pages = Vec::new();
+            if page > 1 {
+                pages.push(link(page - 1, &escape_html(texts.text("search.previous"))));
+            }
+            if more {
+                pages.push(link(page + 1, &escape_html(texts.text("search.next"))));
+            }
+            (list_items, pages.join(" &middot; "))
+        }
+    };
+
+    // What was typed goes in first, `escape_html` escapes its braces so it can't name another placeholder
+    let field = |value: Option<&str>| escape_html(value.unwrap_or(""));
+    let _render = telemetry::template("search.html");
+    let html_page = assets::versioned(&texts.localize(include_str!("search.html")))
+        .replace("{{q}}", &field(Some(&query.q)))
+        .replace("{{tag}}", &field(query.tag.as_deref()))
+        .replace("{{since}}", &field(query.since.as_deref()))
+        .replace("{{until}}", &field(query.until.as_deref()))
+        .replace("{{pages}}", &pages)
+        .replace("{{language}}", &texts.switcher(&back_path(&req)))
+        .replace("{{list_items}}", &list_items);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_page))
+}
+
+// Handles “/api/search”, the results of “/search” for the same query string, as JSON. `q` is required.
+async fn api_search(req: HttpRequest, query: web::Query<SearchParams>, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    authorize(&req, &data, api_tokens::Scope::Read)?;
+    let page = query.page.unwrap_or(1);
+    let (hits, more) = run_search(&query, &data)?.ok_or_else(|| AppError::bad_request("`q` must hold a word to look for"))?;
+    let results = hits
+        .into_iter()
+        .map(|hit| {
+            let size = hit.size.unwrap_or_default();
+            ApiSearchHit {
+                url: format!("/paste/{}", hit.token),
+                token: hit.token,
+                title: Some(hit.title).filter(|title| !title.is_empty()),
+                created_at: hit.created_at,
+                lines: size.lines,
+                chars: size.chars,
+                bytes: size.bytes,
+                snippet: snippet_text(&hit.snippet),
+                snippet_html: snippet_html(&hit.snippet),
+            }
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(ApiSearchResults {
+        results,
+        page,
+        next_page: if more { Some(page + 1) } else { None },
+    }))
+}
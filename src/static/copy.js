@@ -0,0 +1,20 @@
+// The copy buttons of the page of a new paste: each copies its `data-copy`, then says so for a moment with its
+// `data-copied`. They stay hidden without JavaScript or a clipboard, the values can still be selected by hand.
+(function () {
+    if (!navigator.clipboard) {
+        return;
+    }
+    var buttons = document.querySelectorAll('button[data-copy]');
+    Array.prototype.forEach.call(buttons, function (button) {
+        var label = button.textContent;
+        button.hidden = false;
+        button.addEventListener('click', function () {
+            navigator.clipboard.writeText(button.getAttribute('data-copy')).then(function () {
+                button.textContent = button.getAttribute('data-copied');
+                setTimeout(function () {
+                    button.textContent = label;
+                }, 1500);
+            });
+        });
+    });
+})();
@@ -0,0 +1,173 @@
+// Niceties of the textarea of the paste form, on top of a form that works the same without them:
+// - Tab indents (Shift+Tab dedents) the line or the selected lines; Escape then Tab leaves the textarea as usual;
+// - Ctrl+Enter (Cmd+Enter) submits;
+// - a counter of characters, lines and bytes, checked against `max_paste_bytes` of /api/limits;
+// - the draft is kept in localStorage as it is typed and brought back when the page is opened again,
+//   with a "discard draft" link; submitting the form forgets it.
+(function () {
+    var form = document.getElementById('paste-form');
+    if (!form) {
+        return;
+    }
+    var content = form.elements.content;
+    var title = form.elements.title;
+    var tags = form.elements.tags;
+    var counter = document.getElementById('counter');
+    var draftKey = 'pastry-draft';
+    var maxBytes = null;
+    var tabLeaves = false;
+
+    function storage() {
+        try {
+            return window.localStorage;
+        } catch (e) {
+            return null;
+        }
+    }
+
+    function bytesOf(text) {
+        return window.TextEncoder ? new TextEncoder().encode(text).length : unescape(encodeURIComponent(text)).length;
+    }
+
+    function formatBytes(bytes) {
+        if (bytes < 1024) {
+            return bytes + ' bytes';
+        }
+        var units = ['KB', 'MB', 'GB'];
+        var value = bytes / 1024;
+        var unit = 0;
+        while (value >= 1024 && unit + 1 < units.length) {
+            value /= 1024;
+            unit += 1;
+        }
+        return value.toFixed(1) + ' ' + units[unit];
+    }
+
+    function updateCounter() {
+        var text = content.value;
+        var lines = text === '' ? 0 : text.split('\n').length - (text.charAt(text.length - 1) === '\n' ? 1 : 0);
+        var bytes = bytesOf(text);
+        var size = formatBytes(bytes);
+        if (maxBytes !== null) {
+            size += ' of ' + formatBytes(maxBytes);
+        }
+        counter.querySelector('.count').textContent =
+            text.length + ' characters · ' + lines + (lines === 1 ? ' line · ' : ' lines · ') + size;
+        counter.className = maxBytes !== null && bytes > maxBytes ? 'meta mb-4 over' : 'meta mb-4';
+        counter.title = maxBytes !== null && bytes > maxBytes ? 'Too large, the server will refuse it' : '';
+    }
+
+    function saveDraft() {
+        var store = storage();
+        if (!store) {
+            return;
+        }
+        if (content.value === '' && title.value === '' && tags.value === '') {
+            store.removeItem(draftKey);
+        } else {
+            store.setItem(draftKey, JSON.stringify({ content: content.value, title: title.value, tags: tags.value }));
+        }
+    }
+
+    function restoreDraft() {
+        var store = storage();
+        var draft = null;
+        try {
+            draft = store && JSON.parse(store.getItem(draftKey));
+        } catch (e) {
+            draft = null;
+        }
+        var discard = counter.querySelector('.discard');
+        // A form filled by the server (“/new?from=”, a refused submit) keeps what it was given
+        if (draft && draft.content && content.value.replace(/\s/g, '') === '') {
+            content.value = draft.content;
+            title.value = draft.title || '';
+            tags.value = draft.tags || '';
+            discard.hidden = false;
+        }
+        discard.querySelector('a').addEventListener('click', function (event) {
+            event.preventDefault();
+            if (store) {
+                store.removeItem(draftKey);
+            }
+            content.value = '';
+            title.value = '';
+            tags.value = '';
+            discard.hidden = true;
+            updateCounter();
+            content.focus();
+        });
+    }
+
+    // Indents the lines touched by the selection, or inserts a tab at the caret
+    function indent(dedent) {
+        var value = content.value;
+        var start = content.selectionStart;
+        var end = content.selectionEnd;
+        if (!dedent && value.slice(start, end).indexOf('\n') === -1) {
+            content.setRangeText('\t', start, end, 'end');
+            return;
+        }
+        var lineStart = value.lastIndexOf('\n', start - 1) + 1;
+        var lines = value.slice(lineStart, end).split('\n');
+        var changed = lines.map(function (line) {
+            if (!dedent) {
+                return '\t' + line;
+            }
+            return line.replace(/^(\t| {1,4})/, '');
+        });
+        var replacement = changed.join('\n');
+        content.setRangeText(replacement, lineStart, end, 'preserve');
+        content.setSelectionRange(lineStart, lineStart + replacement.length);
+    }
+
+    content.addEventListener('keydown', function (event) {
+        if (event.key === 'Escape') {
+            tabLeaves = true;
+            return;
+        }
+        if (event.key === 'Tab' && !tabLeaves && !event.ctrlKey && !event.altKey && !event.metaKey) {
+            event.preventDefault();
+            indent(event.shiftKey);
+            content.dispatchEvent(new Event('input'));
+            return;
+        }
+        tabLeaves = false;
+        if (event.key === 'Enter' && (event.ctrlKey || event.metaKey)) {
+            event.preventDefault();
+            if (form.requestSubmit) {
+                form.requestSubmit();
+            } else {
+                form.submit();
+            }
+        }
+    });
+    content.addEventListener('blur', function () {
+        tabLeaves = false;
+    });
+    content.addEventListener('input', function () {
+        updateCounter();
+        saveDraft();
+    });
+    title.addEventListener('input', saveDraft);
+    tags.addEventListener('input', saveDraft);
+    form.addEventListener('submit', function () {
+        var store = storage();
+        if (store) {
+            store.removeItem(draftKey);
+        }
+    });
+
+    counter.hidden = false;
+    restoreDraft();
+    updateCounter();
+    fetch('/api/limits')
+        .then(function (response) { return response.ok ? response.json() : null; })
+        .then(function (limits) {
+            if (limits && typeof limits.max_paste_bytes === 'number') {
+                maxBytes = limits.max_paste_bytes;
+                updateCounter();
+            }
+        })
+        .catch(function () {});
+})();
@@ -0,0 +1,615 @@
+// Keeps large paste contents out of the database.
+// `BlobStore` wraps another store: contents above `threshold` bytes are written to a file named after
+// their SHA-256 hash under the blobs directory (`blobs/ab/abcdef…`), and the row only keeps the hash
+// and a short preview for the listings. Reads load the file back transparently, so handlers don't notice.
+// Identical contents share one file; a file is removed once no paste refers to it anymore,
+// and `purge_expired` (run by the cleanup task) also sweeps files left behind without a row.
+
+use super::{
+    content_hash, Announcement, ApiToken, ArchiveStats, AuditEntry, AuditQuery, AuditRecord, Ban, BlobScan, Collection,
+    Comment, Content, ContentSize, CreatedPaste, DbStats, GistMirror, GistState, HashMatch, InstanceStats, ListedPaste,
+    NewAnnouncement, NewApiToken, NewBan, NewCollection, NewComment, NewPaste, OrphanScan, Orphans, Paste, PasteStore,
+    PendingPaste, PurgeCounts, SearchHit, SearchQuery, StoreResult,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, SystemTime};
+
+// How many characters of a blob paste are kept inline as its preview.
+const PREVIEW_CHARS: usize = 100;
+
+// Files without a row younger than this are left alone by the sweep,
+// they may belong to a paste whose row is being inserted right now.
+const ORPHAN_GRACE: Duration = Duration::from_secs(60 * 60);
+
+// The name of a blob file of no paste, and its path.
+type OrphanFile = (String, PathBuf);
+
+pub struct BlobStore {
+    inner: Box<dyn PasteStore>,
+    dir: PathBuf,
+    threshold: usize,
+    // What the threads inside `with_tx` leave for after their transaction, by thread
+    deferred: Mutex<HashMap<ThreadId, Deferred>>,
+}
+
+// The files a transaction of `with_tx` would remove: they go once it is stored, and stay when it is rolled back with
+// the deletions of their rows.
+#[derive(Default)]
+struct Deferred {
+    // The blobs to release
+    blobs: Vec<String>,
+    // Whether to sweep the orphans
+    sweep: bool,
+}
+
+// The thread inside `with_tx`, taken out of `BlobStore::deferred` however it leaves.
+struct Deferring<'a> {
+    store: &'a BlobStore,
+    thread: ThreadId,
+}
+
+impl Deferring<'_> {
+    fn take(&self) -> Deferred {
+        self.store.deferred.lock().unwrap().remove(&self.thread).unwrap_or_default()
+    }
+}
+
+impl Drop for Deferring<'_> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+impl BlobStore {
+    // Wraps `inner`, creating the blobs directory if needed and checking that files can be written to it.
+    pub fn new(inner: Box<dyn PasteStore>, dir: &Path, threshold: usize) -> io::Result<BlobStore> {
+        fs::create_dir_all(dir)?;
+        let probe = dir.join(".write-check");
+        fs::write(&probe, b"ok")?;
+        fs::remove_file(&probe)?;
+
+        Ok(BlobStore {
+            inner,
+            dir: dir.to_path_buf(),
+            threshold,
+            deferred: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Where the blob with this hash lives.
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..2]).join(hash)
+    }
+
+    // Writes `content` under its hash unless an identical blob already exists, returns the hash.
+    // The file is written under a temporary name and renamed, so a reader never sees half a blob.
+    fn write_blob(&self, content: &str) -> io::Result<String> {
+        let hash = content_hash(content.as_bytes());
+        let path = self.blob_path(&hash);
+        if path.exists() {
+            return Ok(hash);
+        }
+
+        let parent = path.parent().expect("blob paths always have a parent");
+        fs::create_dir_all(parent)?;
+        let temp = parent.join(format!(".{}.tmp", hash));
+        {
+            let mut file = fs::File::create(&temp)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp, &path)?;
+        Ok(hash)
+    }
+
+    // The row stored for a new paste: as it is, or with its content moved to a blob file when it's big.
+    fn row_of(&self, paste: &NewPaste) -> io::Result<NewPaste> {
+        if paste.content.len() <= self.threshold {
+            return Ok(paste.clone());
+        }
+        Ok(NewPaste {
+            content: paste.content.chars().take(PREVIEW_CHARS).collect(),
+            blob: Some(self.write_blob(&paste.content)?),
+            data: None,
+            ..paste.clone()
+        })
+    }
+
+    // Calls `defer` with what the transaction of this thread leaves for after it, returns whether there is one.
+    fn defer(&self, defer: impl FnOnce(&mut Deferred)) -> bool {
+        match self.deferred.lock().unwrap().get_mut(&thread::current().id()) {
+            Some(deferred) => {
+                defer(deferred);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Removes the blob file unless some paste still refers to it.
+    fn release_blob(&self, hash: &str) -> StoreResult<()> {
+        if self.defer(|deferred| deferred.blobs.push(hash.to_string())) {
+            return Ok(());
+        }
+        if !self.inner.blob_in_use(hash)? {
+            match fs::remove_file(self.blob_path(hash)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    // Cleans up after a failed insert, the sweep will get whatever can't be removed now.
+    fn release_blobs(&self, hashes: &[String]) {
+        for hash in hashes {
+            let _ = self.release_blob(hash);
+        }
+    }
+
+    // Blob pastes stored before sizes were recorded get theirs computed from the file, once.
+    fn record_size(&self, token: &str, content: &str) -> StoreResult<ContentSize> {
+        let size = ContentSize::of_text(content);
+        self.inner.set_size(token, size)?;
+        Ok(size)
+    }
+
+    // Sweeps the orphans, see `sweep_orphans`, and logs the files it removed.
+    fn sweep(&self) -> StoreResult<()> {
+        if self.defer(|deferred| deferred.sweep = true) {
+            return Ok(());
+        }
+        let removed_files = self.sweep_orphans()?;
+        if removed_files > 0 {
+            println!("Cleanup: removed {} orphaned blob files", removed_files);
+        }
+        Ok(())
+    }
+
+    // Compares the blob files with the hashes referenced by the rows:
+    // removes files no row refers to (older than `ORPHAN_GRACE`) and logs rows whose file is missing.
+    // Returns how many files were removed.
+    fn sweep_orphans(&self) -> StoreResult<usize> {
+        let referenced: HashSet<String> = self.inner.blob_hashes()?.into_iter().collect();
+        let (orphans, on_disk) = self.list_orphans(&referenced)?;
+        for (_, path) in &orphans {
+            fs::remove_file(path)?;
+        }
+
+        for hash in referenced.difference(&on_disk) {
+            eprintln!("Blob {} is referenced by a paste but missing from {}", hash, self.dir.display());
+        }
+
+        Ok(orphans.len())
+    }
+
+    // The files of the blobs directory outside of `referenced` older than `ORPHAN_GRACE`, with their paths,
+    // and the names of the ones of `referenced` that are there.
+    fn list_orphans(&self, referenced: &HashSet<String>) -> io::Result<(Vec<OrphanFile>, HashSet<String>)> {
+        let mut orphans = Vec::new();
+        let mut on_disk = HashSet::new();
+
+        for prefix in fs::read_dir(&self.dir)? {
+            let prefix = prefix?.path();
+            if !prefix.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&prefix)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if referenced.contains(&name) {
+                    on_disk.insert(name);
+                    continue;
+                }
+
+                let age = entry
+                    .metadata()?
+                    .modified()
+                    .ok()
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                    .unwrap_or_default();
+                if age >= ORPHAN_GRACE {
+                    orphans.push((name, entry.path()));
+                }
+            }
+        }
+        orphans.sort();
+        Ok((orphans, on_disk))
+    }
+}
+
+impl PasteStore for BlobStore {
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
+    }
+
+    fn ping(&self) -> StoreResult<()> {
+        self.inner.ping()
+    }
+
+    // The files of the pastes the transaction deletes are removed after it, see `Deferred`.
+    fn with_tx(&self, op: &mut dyn FnMut() -> StoreResult<()>) -> StoreResult<()> {
+        let thread = thread::current().id();
+        if self.deferred.lock().unwrap().contains_key(&thread) {
+            return self.inner.with_tx(op);
+        }
+        self.deferred.lock().unwrap().insert(thread, Deferred::default());
+        let deferring = Deferring { store: self, thread };
+        let result = self.inner.with_tx(op);
+        let deferred = deferring.take();
+        drop(deferring);
+        result?;
+        for hash in &deferred.blobs {
+            self.release_blob(hash)?;
+        }
+        if deferred.sweep {
+            self.sweep()?;
+        }
+        Ok(())
+    }
+
+    fn check_database(&self, full: bool) -> StoreResult<Vec<String>> {
+        self.inner.check_database(full)
+    }
+
+    // Small contents go inline as usual, large ones to a blob file with only a preview in the row.
+    // All the files are written before the rows are inserted, in one batch.
+    fn insert_batch(&self, pastes: &[NewPaste]) -> StoreResult<()> {
+        let mut rows = Vec::with_capacity(pastes.len());
+        let mut hashes = Vec::new();
+        for paste in pastes {
+            match self.row_of(paste) {
+                Ok(row) => {
+                    hashes.extend(row.blob.clone());
+                    rows.push(row);
+                }
+                Err(e) => {
+                    self.release_blobs(&hashes);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let result = self.inner.insert_batch(&rows);
+        if result.is_err() {
+            // Don't leave files behind for rows that don't exist
+            self.release_blobs(&hashes);
+        }
+        result
+    }
+
+    fn get(&self, token: &str) -> StoreResult<Option<Paste>> {
+        let mut paste = match self.inner.get(token)? {
+            Some(paste) => paste,
+            None => return Ok(None),
+        };
+        if let Some(hash) = &paste.blob {
+            paste.content = fs::read_to_string(self.blob_path(hash))?;
+            if paste.size.is_none() {
+                paste.size = Some(self.record_size(&paste.token, &paste.content)?);
+            }
+        }
+        Ok(Some(paste))
+    }
+
+    fn open_content(&self, token: &str) -> StoreResult<Option<(Paste, Content)>> {
+        let mut paste = match self.inner.get(token)? {
+            Some(paste) => paste,
+            None => return Ok(None),
+        };
+        let content = match (&paste.blob, paste.data.take()) {
+            (Some(hash), _) => Content::File(self.blob_path(hash)),
+            (None, Some(data)) => Content::Binary(data),
+            (None, None) => Content::Inline(std::mem::take(&mut paste.content)),
+        };
+        paste.content.clear();
+
+        if let (None, Content::File(path)) = (paste.size, &content) {
+            paste.size = Some(self.record_size(&paste.token, &fs::read_to_string(path)?)?);
+        }
+        Ok(Some((paste, content)))
+    }
+
+    fn set_size(&self, token: &str, size: ContentSize) -> StoreResult<()> {
+        self.inner.set_size(token, size)
+    }
+
+    fn set_content_hash(&self, token: &str, hash: &str) -> StoreResult<()> {
+        self.inner.set_content_hash(token, hash)
+    }
+
+    fn find_by_hash(&self, prefix: &str, limit: i64) -> StoreResult<Vec<HashMatch>> {
+        self.inner.find_by_hash(prefix, limit)
+    }
+
+    fn exists(&self, token: &str) -> StoreResult<bool> {
+        self.inner.exists(token)
+    }
+
+    fn pending_pastes(&self, limit: i64) -> StoreResult<Vec<PendingPaste>> {
+        self.inner.pending_pastes(limit)
+    }
+
+    fn approve(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.inner.approve(token, audit)
+    }
+
+    fn reject(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<Option<Option<String>>> {
+        let blob = self.inner.get(token)?.and_then(|paste| paste.blob);
+        let rejected = self.inner.reject(token, audit)?;
+        if let (Some(hash), Some(_)) = (blob, &rejected) {
+            self.release_blob(&hash)?;
+        }
+        Ok(rejected)
+    }
+
+    fn replace(&self, paste: &NewPaste, audit: Option<&AuditEntry>) -> StoreResult<()> {
+        let old_blob = self.inner.get(paste.token.as_str())?.and_then(|paste| paste.blob);
+        let row = self.row_of(paste)?;
+        match self.inner.replace(&row, audit) {
+            Ok(()) => {
+                if let Some(hash) = old_blob {
+                    self.release_blob(&hash)?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.release_blobs(&row.blob.into_iter().collect::<Vec<_>>());
+                Err(e)
+            }
+        }
+    }
+
+    fn delete(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let blob = self.inner.get(token)?.and_then(|paste| paste.blob);
+        let deleted = self.inner.delete(token, audit)?;
+        if let Some(hash) = blob {
+            self.release_blob(&hash)?;
+        }
+        Ok(deleted)
+    }
+
+    fn record_view(&self, token: &str) -> StoreResult<()> {
+        self.inner.record_view(token)
+    }
+
+    fn tags(&self, token: &str) -> StoreResult<Vec<String>> {
+        self.inner.tags(token)
+    }
+
+    fn list_popular(&self, days: i64, tag: Option<&str>, limit: i64) -> StoreResult<Vec<ListedPaste>> {
+        self.inner.list_popular(days, tag, limit)
+    }
+
+    fn add_comment(&self, comment: &NewComment) -> StoreResult<i64> {
+        self.inner.add_comment(comment)
+    }
+
+    fn comments(&self, token: &str) -> StoreResult<Vec<Comment>> {
+        self.inner.comments(token)
+    }
+
+    fn delete_comment(&self, token: &str, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.inner.delete_comment(token, id, audit)
+    }
+
+    fn create_api_token(&self, token: &NewApiToken, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.inner.create_api_token(token, audit)
+    }
+
+    fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>> {
+        self.inner.api_token(token_hash)
+    }
+
+    fn api_tokens(&self) -> StoreResult<Vec<ApiToken>> {
+        self.inner.api_tokens()
+    }
+
+    fn revoke_api_token(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.inner.revoke_api_token(id, audit)
+    }
+
+    fn touch_api_token(&self, id: i64, at: i64) -> StoreResult<()> {
+        self.inner.touch_api_token(id, at)
+    }
+
+    fn add_ban(&self, ban: &NewBan, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.inner.add_ban(ban, audit)
+    }
+
+    fn bans(&self) -> StoreResult<Vec<Ban>> {
+        self.inner.bans()
+    }
+
+    fn remove_ban(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.inner.remove_ban(id, audit)
+    }
+
+    fn prune_bans(&self) -> StoreResult<usize> {
+        self.inner.prune_bans()
+    }
+
+    fn add_announcement(&self, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.inner.add_announcement(announcement, audit)
+    }
+
+    fn announcements(&self) -> StoreResult<Vec<Announcement>> {
+        self.inner.announcements()
+    }
+
+    fn update_announcement(&self, id: i64, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.inner.update_announcement(id, announcement, audit)
+    }
+
+    fn remove_announcement(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.inner.remove_announcement(id, audit)
+    }
+
+    fn prune_announcements(&self) -> StoreResult<usize> {
+        self.inner.prune_announcements()
+    }
+
+    fn insert_collection(&self, collection: &NewCollection) -> StoreResult<()> {
+        self.inner.insert_collection(collection)
+    }
+
+    fn collection(&self, token: &str) -> StoreResult<Option<Collection>> {
+        self.inner.collection(token)
+    }
+
+    fn set_collection_pastes(&self, token: &str, pastes: &[String]) -> StoreResult<bool> {
+        self.inner.set_collection_pastes(token, pastes)
+    }
+
+    fn delete_collection(&self, token: &str) -> StoreResult<bool> {
+        self.inner.delete_collection(token)
+    }
+
+    fn prune_collections(&self) -> StoreResult<usize> {
+        self.inner.prune_collections()
+    }
+
+    fn record_instance_stats(&self, stats: &InstanceStats) -> StoreResult<()> {
+        self.inner.record_instance_stats(stats)
+    }
+
+    fn instance_stats(&self) -> StoreResult<Vec<InstanceStats>> {
+        self.inner.instance_stats()
+    }
+
+    fn find_orphans(&self, kind: Orphans, after: &str, limit: i64) -> StoreResult<OrphanScan> {
+        self.inner.find_orphans(kind, after, limit)
+    }
+
+    fn remove_orphans(&self, kind: Orphans, tokens: &[String]) -> StoreResult<usize> {
+        self.inner.remove_orphans(kind, tokens)
+    }
+
+    fn paste_tokens(&self, after: &str, limit: i64) -> StoreResult<Vec<String>> {
+        self.inner.paste_tokens(after, limit)
+    }
+
+    fn scan_blobs(&self) -> StoreResult<BlobScan> {
+        let referenced: HashSet<String> = self.inner.blob_hashes()?.into_iter().collect();
+        let (orphans, on_disk) = self.list_orphans(&referenced)?;
+        let mut missing: Vec<String> = referenced.difference(&on_disk).cloned().collect();
+        missing.sort();
+        Ok(BlobScan {
+            orphans: orphans.iter().map(|(hash, _)| hash.clone()).collect(),
+            missing,
+        })
+    }
+
+    // Lists the orphans again, so that a file a paste refers to since the scan stays.
+    fn remove_blobs(&self, hashes: &[String]) -> StoreResult<usize> {
+        let referenced: HashSet<String> = self.inner.blob_hashes()?.into_iter().collect();
+        let (orphans, _) = self.list_orphans(&referenced)?;
+        let mut removed = 0;
+        for (_, path) in orphans.iter().filter(|(name, _)| hashes.contains(name)) {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn list_created(&self, creator: &str, limit: i64) -> StoreResult<Vec<CreatedPaste>> {
+        self.inner.list_created(creator, limit)
+    }
+
+    fn tag_counts(&self) -> StoreResult<Vec<(String, i64)>> {
+        self.inner.tag_counts()
+    }
+
+    fn daily_views(&self, token: &str, days: i64) -> StoreResult<Vec<(String, i64)>> {
+        self.inner.daily_views(token, days)
+    }
+
+    fn gist_mirror(&self, token: &str) -> StoreResult<Option<GistMirror>> {
+        self.inner.gist_mirror(token)
+    }
+
+    fn set_gist_mirror(&self, token: &str, state: &GistState) -> StoreResult<bool> {
+        self.inner.set_gist_mirror(token, state)
+    }
+
+    // Deleting the expired rows leaves their blob files behind, the sweep takes care of them.
+    fn purge_expired(&self) -> StoreResult<usize> {
+        let purged = self.inner.purge_expired()?;
+        self.sweep()?;
+        Ok(purged)
+    }
+
+    fn prune_daily_views(&self) -> StoreResult<usize> {
+        self.inner.prune_daily_views()
+    }
+
+    fn purge_preview(&self) -> StoreResult<PurgeCounts> {
+        self.inner.purge_preview()
+    }
+
+    fn reclaim_space(&self) -> StoreResult<()> {
+        self.inner.reclaim_space()
+    }
+
+    fn blob_in_use(&self, hash: &str) -> StoreResult<bool> {
+        self.inner.blob_in_use(hash)
+    }
+
+    fn blob_hashes(&self) -> StoreResult<Vec<String>> {
+        self.inner.blob_hashes()
+    }
+
+    fn record_creation(&self, client: &str, pastes: i64) -> StoreResult<i64> {
+        self.inner.record_creation(client, pastes)
+    }
+
+    fn creations_today(&self, client: &str) -> StoreResult<i64> {
+        self.inner.creations_today(client)
+    }
+
+    fn prune_quotas(&self) -> StoreResult<usize> {
+        self.inner.prune_quotas()
+    }
+
+    fn record_audit(&self, entry: &AuditEntry) -> StoreResult<()> {
+        self.inner.record_audit(entry)
+    }
+
+    fn audit_log(&self, query: &AuditQuery) -> StoreResult<Vec<AuditRecord>> {
+        self.inner.audit_log(query)
+    }
+
+    fn prune_audit_log(&self, before: i64) -> StoreResult<usize> {
+        self.inner.prune_audit_log(before)
+    }
+
+    fn search(&self, query: &SearchQuery) -> StoreResult<Vec<SearchHit>> {
+        self.inner.search(query)
+    }
+
+    fn archive_idle(&self, idle_since: i64, limit: usize) -> StoreResult<usize> {
+        self.inner.archive_idle(idle_since, limit)
+    }
+
+    fn unarchive(&self, token: &str) -> StoreResult<bool> {
+        self.inner.unarchive(token)
+    }
+
+    fn archive_stats(&self) -> StoreResult<ArchiveStats> {
+        self.inner.archive_stats()
+    }
+
+    fn db_stats(&self) -> StoreResult<DbStats> {
+        self.inner.db_stats()
+    }
+
+    fn sqlite_path(&self) -> Option<&Path> {
+        self.inner.sqlite_path()
+    }
+}
@@ -0,0 +1,1132 @@
+// An in-memory implementation of `PasteStore`, used when the server is started with `--ephemeral`.
+// Nothing touches the disk and everything is gone when the process stops, which suits demo instances.
+// It follows the same rules as the SQLite store (expiry, public listings, daily views),
+// and keeps its size bounded: once `max_pastes` pastes or `max_bytes` bytes of content are stored,
+// the oldest pastes are dropped to make room for new ones.
+
+use super::{
+    day_string, fill_days, now, today, window_start, Announcement, ApiToken, ArchiveStats, AuditEntry, AuditQuery,
+    AuditRecord, Ban, Collection, Comment, ContentSize, CreatedPaste, DbStats, GistMirror, GistState, HashMatch,
+    InstanceStats, ListedPaste, NewAnnouncement, NewApiToken, NewBan, NewCollection, NewComment, NewPaste, OrphanScan,
+    Orphans, Paste, PasteBytes, PasteStore, PendingPaste, PurgeCounts, SearchHit, SearchQuery, StoreError, StoreResult,
+    TableRows,
+    DAILY_VIEWS_RETENTION_DAYS, DB_STATS_TOP, QUOTA_RETENTION_DAYS, SNIPPET_ELLIPSIS, SNIPPET_END, SNIPPET_START,
+    SNIPPET_WORDS,
+};
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[derive(Clone)]
+struct StoredPaste {
+    paste: Paste,
+    tags: Vec<String>,
+    gist: Option<GistMirror>,
+    // Oldest first
+    comments: Vec<Comment>,
+    // Of a pending paste, see `NewPaste::submitter`
+    submitter: Option<String>,
+    // Insertion order, used to find the oldest paste to evict
+    seq: u64,
+}
+
+#[derive(Clone, Default)]
+struct Inner {
+    pastes: HashMap<String, StoredPaste>,
+    // seq -> token, oldest first
+    order: BTreeMap<u64, String>,
+    // (token, day) -> views
+    daily_views: HashMap<(String, String), i64>,
+    // (client, day) -> pastes created
+    creations: HashMap<(String, String), i64>,
+    // oldest first
+    audit: Vec<AuditRecord>,
+    // token hash -> token, see `api_tokens.rs`
+    api_tokens: HashMap<String, ApiToken>,
+    // by id
+    bans: BTreeMap<i64, Ban>,
+    // by id
+    announcements: BTreeMap<i64, Announcement>,
+    // by token
+    collections: HashMap<String, Collection>,
+    // The reports of other instances by name, see `federation.rs`
+    instances: BTreeMap<String, InstanceStats>,
+    next_seq: u64,
+    next_comment_id: i64,
+    content_bytes: usize,
+}
+
+impl Inner {
+    fn is_live(paste: &Paste, now: i64) -> bool {
+        paste.expires_at.map(|expires_at| expires_at > now).unwrap_or(true)
+    }
+
+    fn audit(&mut self, entry: &AuditEntry) {
+        let id = self.audit.last().map_or(1, |record| record.id + 1);
+        self.audit.push(AuditRecord {
+            id,
+            at: now(),
+            actor: entry.actor.to_string(),
+            action: entry.action.to_string(),
+            target: entry.target.clone(),
+            ip_hash: entry.ip_hash.clone(),
+            detail: entry.detail.clone(),
+        });
+    }
+
+    fn remove(&mut self, token: &str) -> bool {
+        match self.pastes.remove(token) {
+            Some(stored) => {
+                self.order.remove(&stored.seq);
+                self.content_bytes -= paste_bytes(&stored.paste);
+                self.daily_views.retain(|(view_token, _), _| view_token != token);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Bytes of content a paste counts for against `max_bytes`, text or binary.
+fn paste_bytes(paste: &Paste) -> usize {
+    paste.content.len() + paste.data.as_ref().map_or(0, Vec::len)
+}
+
+// The words of `text` with their byte ranges, as the SQLite index splits it: runs of letters and digits.
+fn words(text: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(begin)) => {
+                words.push((begin, index));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        words.push((begin, text.len()));
+    }
+    words
+}
+
+fn lowercase_words(text: &str) -> Vec<String> {
+    words(text).into_iter().map(|(start, end)| text[start..end].to_lowercase()).collect()
+}
+
+// Matches `terms` against `content` the way `SqliteStore::search` does, each term as the run of its words:
+// the number of times they were found and the snippet, `None` when one of them isn't there.
+fn search_content(content: &str, terms: &[Vec<String>]) -> Option<(usize, String)> {
+    let ranges = words(content);
+    let lower: Vec<String> = ranges.iter().map(|(start, end)| content[*start..*end].to_lowercase()).collect();
+    let mut matched = vec![false; ranges.len()];
+    let mut found = 0;
+    for term in terms {
+        let mut term_found = false;
+        for start in 0..lower.len().saturating_sub(term.len() - 1) {
+            if lower[start..start + term.len()] == term[..] {
+                matched[start..start + term.len()].iter_mut().for_each(|word| *word = true);
+                term_found = true;
+                found += 1;
+            }
+        }
+        if !term_found {
+            return None;
+        }
+    }
+
+    // A window of `SNIPPET_WORDS` words with the first match a third of the way in
+    let first = matched.iter().position(|word| *word).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_WORDS / 3);
+    let end = (start + SNIPPET_WORDS).min(ranges.len());
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str(SNIPPET_ELLIPSIS);
+    }
+    let mut written = if start > 0 { ranges[start].0 } else { 0 };
+    for index in start..end {
+        let (word_start, word_end) = ranges[index];
+        snippet.push_str(&content[written..word_start]);
+        if matched[index] {
+            snippet.push(SNIPPET_START);
+            snippet.push_str(&content[word_start..word_end]);
+            snippet.push(SNIPPET_END);
+        } else {
+            snippet.push_str(&content[word_start..word_end]);
+        }
+        written = word_end;
+    }
+    if end < ranges.len() {
+        snippet.push_str(SNIPPET_ELLIPSIS);
+    } else {
+        snippet.push_str(&content[written..]);
+    }
+    Some((found, snippet))
+}
+
+pub struct MemoryStore {
+    inner: RwLock<Inner>,
+    // Taken by every call, and by `with_tx` for all of the calls it makes, which the thread holding it takes again
+    tx: ReentrantMutex<()>,
+    max_pastes: usize,
+    max_bytes: usize,
+}
+
+// `Inner` locked for one call, see `MemoryStore::tx`.
+struct Locked<'a, G> {
+    inner: G,
+    _tx: ReentrantMutexGuard<'a, ()>,
+}
+
+impl<G: Deref<Target = Inner>> Deref for Locked<'_, G> {
+    type Target = Inner;
+
+    fn deref(&self) -> &Inner {
+        &self.inner
+    }
+}
+
+impl<G: DerefMut<Target = Inner>> DerefMut for Locked<'_, G> {
+    fn deref_mut(&mut self) -> &mut Inner {
+        &mut self.inner
+    }
+}
+
+impl MemoryStore {
+    pub fn new(max_pastes: usize, max_bytes: usize) -> MemoryStore {
+        MemoryStore {
+            inner: RwLock::new(Inner::default()),
+            tx: ReentrantMutex::new(()),
+            max_pastes: max_pastes.max(1),
+            max_bytes,
+        }
+    }
+
+    fn read(&self) -> Locked<'_, RwLockReadGuard<'_, Inner>> {
+        let tx = self.tx.lock();
+        Locked {
+            inner: self.inner.read().unwrap(),
+            _tx: tx,
+        }
+    }
+
+    fn write(&self) -> Locked<'_, RwLockWriteGuard<'_, Inner>> {
+        let tx = self.tx.lock();
+        Locked {
+            inner: self.inner.write().unwrap(),
+            _tx: tx,
+        }
+    }
+
+    // Stores one paste, dropping the oldest ones first when it wouldn't fit.
+    fn insert_one(&self, inner: &mut Inner, paste: &NewPaste) {
+        let new_size = paste.content.len() + paste.data.as_ref().map_or(0, Vec::len);
+
+        // Make room by dropping the oldest pastes
+        while !inner.pastes.is_empty()
+            && (inner.pastes.len() >= self.max_pastes || inner.content_bytes + new_size > self.max_bytes)
+        {
+            let oldest = match inner.order.values().next() {
+                Some(token) => token.clone(),
+                None => break,
+            };
+            inner.remove(&oldest);
+        }
+
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner.order.insert(seq, paste.token.clone());
+        inner.content_bytes += new_size;
+        inner.pastes.insert(
+            paste.token.clone(),
+            StoredPaste {
+                paste: Paste {
+                    token: paste.token.clone(),
+                    secret: paste.secret.clone(),
+                    content: paste.content.clone(),
+                    public: paste.public,
+                    views: 0,
+                    created_at: now(),
+                    expires_at: paste.expires_at,
+                    blob: paste.blob.clone(),
+                    data: paste.data.clone(),
+                    size: Some(paste.size),
+                    archived: false,
+                    redirect: paste.redirect,
+                    content_hash: Some(paste.content_hash.clone()),
+                    creator: paste.creator.clone(),
+                    comments: paste.comments,
+                    pending: paste.pending,
+                    title: paste.title.clone(),
+                    title_auto: paste.title_auto,
+                },
+                tags: paste.tags.clone(),
+                gist: None,
+                comments: Vec::new(),
+                submitter: paste.submitter.clone(),
+                seq,
+            },
+        );
+    }
+}
+
+impl PasteStore for MemoryStore {
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    fn ping(&self) -> StoreResult<()> {
+        Ok(())
+    }
+
+    fn check_database(&self, _full: bool) -> StoreResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    // Everything is copied first, and put back when `op` fails.
+    fn with_tx(&self, op: &mut dyn FnMut() -> StoreResult<()>) -> StoreResult<()> {
+        let _tx = self.tx.lock();
+        let before = self.inner.read().unwrap().clone();
+        let result = op();
+        if result.is_err() {
+            *self.inner.write().unwrap() = before;
+        }
+        result
+    }
+
+    // Taken tokens are checked up front, after that nothing can fail half way,
+    // so a batch is simply inserted one paste after the other.
+    fn insert_batch(&self, pastes: &[NewPaste]) -> StoreResult<()> {
+        let mut inner = self.write();
+        for (index, paste) in pastes.iter().enumerate() {
+            if inner.pastes.contains_key(&paste.token) || pastes[..index].iter().any(|other| other.token == paste.token) {
+                return Err(StoreError::Duplicate(paste.token.clone()));
+            }
+        }
+        for paste in pastes {
+            self.insert_one(&mut inner, paste);
+        }
+        Ok(())
+    }
+
+    fn get(&self, token: &str) -> StoreResult<Option<Paste>> {
+        let inner = self.read();
+        let now = now();
+        Ok(inner
+            .pastes
+            .get(token)
+            .filter(|stored| Inner::is_live(&stored.paste, now))
+            .map(|stored| stored.paste.clone()))
+    }
+
+    fn set_size(&self, token: &str, size: ContentSize) -> StoreResult<()> {
+        if let Some(stored) = self.write().pastes.get_mut(token) {
+            stored.paste.size = Some(size);
+        }
+        Ok(())
+    }
+
+    fn set_content_hash(&self, token: &str, hash: &str) -> StoreResult<()> {
+        if let Some(stored) = self.write().pastes.get_mut(token) {
+            stored.paste.content_hash = Some(hash.to_string());
+        }
+        Ok(())
+    }
+
+    fn find_by_hash(&self, prefix: &str, limit: i64) -> StoreResult<Vec<HashMatch>> {
+        let inner = self.read();
+        let now = now();
+
+        // Oldest paste of each hash, by creation then token like the database backends
+        let mut oldest: BTreeMap<&str, &Paste> = BTreeMap::new();
+        for stored in inner.pastes.values() {
+            let paste = &stored.paste;
+            let hash = match &paste.content_hash {
+                Some(hash) if hash.starts_with(prefix) => hash.as_str(),
+                _ => continue,
+            };
+            if !paste.public || paste.pending || !Inner::is_live(paste, now) {
+                continue;
+            }
+            let entry = oldest.entry(hash).or_insert(paste);
+            if (paste.created_at, &paste.token) < (entry.created_at, &entry.token) {
+                *entry = paste;
+            }
+        }
+
+        Ok(oldest
+            .into_iter()
+            .take(limit.max(0) as usize)
+            .map(|(hash, paste)| HashMatch {
+                hash: hash.to_string(),
+                token: paste.token.clone(),
+            })
+            .collect())
+    }
+
+    fn exists(&self, token: &str) -> StoreResult<bool> {
+        Ok(self.read().pastes.contains_key(token))
+    }
+
+    fn delete(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let mut inner = self.write();
+        let deleted = inner.remove(token);
+        if let Some(entry) = audit.filter(|_| deleted) {
+            inner.audit(entry);
+        }
+        Ok(deleted)
+    }
+
+    fn replace(&self, paste: &NewPaste, audit: Option<&AuditEntry>) -> StoreResult<()> {
+        let mut inner = self.write();
+        inner.remove(&paste.token);
+        self.insert_one(&mut inner, paste);
+        if let Some(entry) = audit {
+            inner.audit(entry);
+        }
+        Ok(())
+    }
+
+    fn pending_pastes(&self, limit: i64) -> StoreResult<Vec<PendingPaste>> {
+        let inner = self.read();
+        let now = now();
+
+        let mut pending: Vec<&StoredPaste> = inner
+            .pastes
+            .values()
+            .filter(|stored| stored.paste.pending && Inner::is_live(&stored.paste, now))
+            .collect();
+        pending.sort_by_key(|stored| stored.seq);
+        Ok(pending
+            .into_iter()
+            .take(limit.max(0) as usize)
+            .map(|stored| PendingPaste {
+                token: stored.paste.token.clone(),
+                preview: stored.paste.content.chars().take(100).collect(),
+                bytes: stored.paste.size.map_or(0, |size| size.bytes),
+                public: stored.paste.public,
+                redirect: stored.paste.redirect,
+                binary: stored.paste.data.is_some(),
+                created_at: stored.paste.created_at,
+                expires_at: stored.paste.expires_at,
+                submitter: stored.submitter.clone(),
+            })
+            .collect())
+    }
+
+    fn approve(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let mut inner = self.write();
+        match inner.pastes.get_mut(token) {
+            Some(stored) if stored.paste.pending => {
+                stored.paste.pending = false;
+                stored.submitter = None;
+            }
+            _ => return Ok(false),
+        }
+        if let Some(entry) = audit {
+            inner.audit(entry);
+        }
+        Ok(true)
+    }
+
+    fn reject(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<Option<Option<String>>> {
+        let mut inner = self.write();
+        let submitter = match inner.pastes.get(token) {
+            Some(stored) if stored.paste.pending => stored.submitter.clone(),
+            _ => return Ok(None),
+        };
+        inner.remove(token);
+        if let Some(entry) = audit {
+            inner.audit(entry);
+        }
+        Ok(Some(submitter))
+    }
+
+    fn record_view(&self, token: &str) -> StoreResult<()> {
+        let mut inner = self.write();
+        match inner.pastes.get_mut(token) {
+            Some(stored) => stored.paste.views += 1,
+            None => return Ok(()),
+        }
+        *inner
+            .daily_views
+            .entry((token.to_string(), day_string(today())))
+            .or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn tags(&self, token: &str) -> StoreResult<Vec<String>> {
+        let inner = self.read();
+        let mut tags = inner
+            .pastes
+            .get(token)
+            .map(|stored| stored.tags.clone())
+            .unwrap_or_default();
+        tags.sort();
+        Ok(tags)
+    }
+
+    fn list_popular(&self, days: i64, tag: Option<&str>, limit: i64) -> StoreResult<Vec<ListedPaste>> {
+        let inner = self.read();
+        let start = window_start(days);
+        let now = now();
+
+        let mut window_views: HashMap<&str, i64> = HashMap::new();
+        for ((token, day), views) in &inner.daily_views {
+            if *day >= start {
+                *window_views.entry(token.as_str()).or_insert(0) += views;
+            }
+        }
+
+        let mut listed: Vec<ListedPaste> = window_views
+            .into_iter()
+            .filter_map(|(token, views)| {
+                let stored = inner.pastes.get(token)?;
+                let matches_tag = tag.map(|tag| stored.tags.iter().any(|t| t == tag)).unwrap_or(true);
+                if !stored.paste.public || stored.paste.pending || !Inner::is_live(&stored.paste, now) || !matches_tag {
+                    return None;
+                }
+                Some(ListedPaste {
+                    token: token.to_string(),
+                    preview: stored.paste.content.chars().take(100).collect(),
+                    size: stored.paste.size,
+                    views,
+                    title: stored.paste.title.clone(),
+                })
+            })
+            .collect();
+
+        listed.sort_by(|a, b| b.views.cmp(&a.views).then_with(|| a.token.cmp(&b.token)));
+        listed.truncate(limit.max(0) as usize);
+        Ok(listed)
+    }
+
+    fn add_comment(&self, comment: &NewComment) -> StoreResult<i64> {
+        let mut inner = self.write();
+        inner.next_comment_id += 1;
+        let id = inner.next_comment_id;
+        if let Some(stored) = inner.pastes.get_mut(&comment.token) {
+            stored.comments.push(Comment {
+                id,
+                author: comment.author.clone(),
+                body: comment.body.clone(),
+                created_at: now(),
+            });
+        }
+        Ok(id)
+    }
+
+    fn comments(&self, token: &str) -> StoreResult<Vec<Comment>> {
+        let inner = self.read();
+        Ok(inner.pastes.get(token).map(|stored| stored.comments.clone()).unwrap_or_default())
+    }
+
+    fn delete_comment(&self, token: &str, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let mut inner = self.write();
+        let deleted = match inner.pastes.get_mut(token) {
+            Some(stored) => {
+                let before = stored.comments.len();
+                stored.comments.retain(|comment| comment.id != id);
+                stored.comments.len() < before
+            }
+            None => false,
+        };
+        if let Some(entry) = audit.filter(|_| deleted) {
+            inner.audit(entry);
+        }
+        Ok(deleted)
+    }
+
+    fn create_api_token(&self, token: &NewApiToken, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        let mut inner = self.write();
+        let id = inner.api_tokens.values().map(|stored| stored.id).max().unwrap_or(0) + 1;
+        inner.api_tokens.insert(
+            token.token_hash.clone(),
+            ApiToken {
+                id,
+                label: token.label.clone(),
+                scopes: token.scopes.clone(),
+                created_at: now(),
+                expires_at: token.expires_at,
+                last_used_at: None,
+                revoked_at: None,
+                daily_quota: token.daily_quota,
+            },
+        );
+        if let Some(entry) = audit {
+            inner.audit(entry);
+        }
+        Ok(id)
+    }
+
+    fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>> {
+        Ok(self.read().api_tokens.get(token_hash).cloned())
+    }
+
+    fn api_tokens(&self) -> StoreResult<Vec<ApiToken>> {
+        let mut tokens: Vec<ApiToken> = self.read().api_tokens.values().cloned().collect();
+        tokens.sort_by_key(|token| std::cmp::Reverse(token.id));
+        Ok(tokens)
+    }
+
+    fn revoke_api_token(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let mut inner = self.write();
+        let revoked = match inner.api_tokens.values_mut().find(|token| token.id == id && token.revoked_at.is_none()) {
+            Some(token) => {
+                token.revoked_at = Some(now());
+                true
+            }
+            None => false,
+        };
+        if let Some(entry) = audit.filter(|_| revoked) {
+            inner.audit(entry);
+        }
+        Ok(revoked)
+    }
+
+    fn touch_api_token(&self, id: i64, at: i64) -> StoreResult<()> {
+        let mut inner = self.write();
+        if let Some(token) = inner.api_tokens.values_mut().find(|token| token.id == id) {
+            token.last_used_at = Some(at);
+        }
+        Ok(())
+    }
+
+    fn add_ban(&self, ban: &NewBan, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        let mut inner = self.write();
+        let id = inner.bans.keys().next_back().map_or(1, |last| last + 1);
+        inner.bans.insert(
+            id,
+            Ban {
+                id,
+                ip_hash: ban.ip_hash.clone(),
+                cidr: ban.cidr.clone(),
+                reason: ban.reason.clone(),
+                created_at: now(),
+                expires_at: ban.expires_at,
+                created_by: ban.created_by.clone(),
+            },
+        );
+        if let Some(entry) = audit {
+            inner.audit(entry);
+        }
+        Ok(id)
+    }
+
+    fn bans(&self) -> StoreResult<Vec<Ban>> {
+        let now = now();
+        let inner = self.read();
+        Ok(inner
+            .bans
+            .values()
+            .rev()
+            .filter(|ban| ban.expires_at.is_none_or(|expires_at| expires_at > now))
+            .cloned()
+            .collect())
+    }
+
+    fn remove_ban(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let mut inner = self.write();
+        let removed = inner.bans.remove(&id).is_some();
+        if let Some(entry) = audit.filter(|_| removed) {
+            inner.audit(entry);
+        }
+        Ok(removed)
+    }
+
+    fn prune_bans(&self) -> StoreResult<usize> {
+        let now = now();
+        let mut inner = self.write();
+        let before = inner.bans.len();
+        inner.bans.retain(|_, ban| ban.expires_at.is_none_or(|expires_at| expires_at > now));
+        Ok(before - inner.bans.len())
+    }
+
+    fn add_announcement(&self, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        let mut inner = self.write();
+        let id = inner.announcements.keys().next_back().map_or(1, |last| last + 1);
+        inner.announcements.insert(
+            id,
+            Announcement {
+                id,
+                message: announcement.message.clone(),
+                level: announcement.level.clone(),
+                starts_at: announcement.starts_at,
+                ends_at: announcement.ends_at,
+                created_at: now(),
+                created_by: announcement.created_by.clone(),
+            },
+        );
+        if let Some(entry) = audit {
+            inner.audit(entry);
+        }
+        Ok(id)
+    }
+
+    fn announcements(&self) -> StoreResult<Vec<Announcement>> {
+        let now = now();
+        let inner = self.read();
+        let mut announcements: Vec<Announcement> = inner
+            .announcements
+            .values()
+            .filter(|announcement| announcement.ends_at.is_none_or(|ends_at| ends_at > now))
+            .cloned()
+            .collect();
+        announcements.sort_by_key(|announcement| (announcement.starts_at, announcement.id));
+        Ok(announcements)
+    }
+
+    fn update_announcement(&self, id: i64, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let mut inner = self.write();
+        match inner.announcements.get_mut(&id) {
+            Some(stored) => {
+                stored.message = announcement.message.clone();
+                stored.level = announcement.level.clone();
+                stored.starts_at = announcement.starts_at;
+                stored.ends_at = announcement.ends_at;
+            }
+            None => return Ok(false),
+        }
+        if let Some(entry) = audit {
+            inner.audit(entry);
+        }
+        Ok(true)
+    }
+
+    fn remove_announcement(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        let mut inner = self.write();
+        let removed = inner.announcements.remove(&id).is_some();
+        if let Some(entry) = audit.filter(|_| removed) {
+            inner.audit(entry);
+        }
+        Ok(removed)
+    }
+
+    fn prune_announcements(&self) -> StoreResult<usize> {
+        let now = now();
+        let mut inner = self.write();
+        let before = inner.announcements.len();
+        inner.announcements.retain(|_, announcement| announcement.ends_at.is_none_or(|ends_at| ends_at > now));
+        Ok(before - inner.announcements.len())
+    }
+
+    fn insert_collection(&self, collection: &NewCollection) -> StoreResult<()> {
+        let mut inner = self.write();
+        let now = now();
+        let live = inner
+            .collections
+            .get(&collection.token)
+            .is_some_and(|existing| existing.expires_at.is_none_or(|expires_at| expires_at > now));
+        if live {
+            return Err(StoreError::Duplicate(collection.token.clone()));
+        }
+        inner.collections.insert(
+            collection.token.clone(),
+            Collection {
+                token: collection.token.clone(),
+                secret: collection.secret.clone(),
+                title: collection.title.clone(),
+                created_at: now,
+                updated_at: now,
+                expires_at: collection.expires_at,
+                pastes: collection.pastes.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    fn collection(&self, token: &str) -> StoreResult<Option<Collection>> {
+        let now = now();
+        let inner = self.read();
+        Ok(inner
+            .collections
+            .get(token)
+            .filter(|collection| collection.expires_at.is_none_or(|expires_at| expires_at > now))
+            .cloned())
+    }
+
+    fn set_collection_pastes(&self, token: &str, pastes: &[String]) -> StoreResult<bool> {
+        let mut inner = self.write();
+        match inner.collections.get_mut(token) {
+            Some(collection) => {
+                collection.pastes = pastes.to_vec();
+                collection.updated_at = now();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn delete_collection(&self, token: &str) -> StoreResult<bool> {
+        Ok(self.write().collections.remove(token).is_some())
+    }
+
+    fn prune_collections(&self) -> StoreResult<usize> {
+        let now = now();
+        let mut inner = self.write();
+        let before = inner.collections.len();
+        inner
+            .collections
+            .retain(|_, collection| collection.expires_at.is_none_or(|expires_at| expires_at > now));
+        Ok(before - inner.collections.len())
+    }
+
+    fn record_instance_stats(&self, stats: &InstanceStats) -> StoreResult<()> {
+        self.write().instances.insert(stats.instance.clone(), stats.clone());
+        Ok(())
+    }
+
+    fn instance_stats(&self) -> StoreResult<Vec<InstanceStats>> {
+        Ok(self.read().instances.values().cloned().collect())
+    }
+
+    // Tags, comments and gists are kept with their paste, and the pastes of a collection with it: only the daily
+    // views, kept apart, can outlive a paste.
+    fn find_orphans(&self, kind: Orphans, after: &str, limit: i64) -> StoreResult<OrphanScan> {
+        if kind != Orphans::DailyViews {
+            return Ok(OrphanScan { orphans: Vec::new(), last: None });
+        }
+        let inner = self.read();
+        let mut tokens: Vec<&String> = inner.daily_views.keys().map(|(token, _)| token).filter(|token| token.as_str() > after).collect();
+        tokens.sort();
+        tokens.dedup();
+        tokens.truncate(limit.max(0) as usize);
+        Ok(OrphanScan {
+            last: tokens.last().map(|token| token.to_string()),
+            orphans: tokens
+                .into_iter()
+                .filter(|token| !inner.pastes.contains_key(*token))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn remove_orphans(&self, kind: Orphans, tokens: &[String]) -> StoreResult<usize> {
+        if kind != Orphans::DailyViews {
+            return Ok(0);
+        }
+        let mut inner = self.write();
+        let orphans: Vec<&String> = tokens.iter().filter(|token| !inner.pastes.contains_key(*token)).collect();
+        let removed = orphans
+            .iter()
+            .filter(|token| inner.daily_views.keys().any(|(viewed, _)| viewed == **token))
+            .count();
+        inner.daily_views.retain(|(token, _), _| !orphans.contains(&token));
+        Ok(removed)
+    }
+
+    fn paste_tokens(&self, after: &str, limit: i64) -> StoreResult<Vec<String>> {
+        let inner = self.read();
+        let mut tokens: Vec<String> = inner.pastes.keys().filter(|token| token.as_str() > after).cloned().collect();
+        tokens.sort();
+        tokens.truncate(limit.max(0) as usize);
+        Ok(tokens)
+    }
+
+    fn list_created(&self, creator: &str, limit: i64) -> StoreResult<Vec<CreatedPaste>> {
+        let inner = self.read();
+        let now = now();
+
+        let mut created: Vec<&StoredPaste> = inner
+            .pastes
+            .values()
+            .filter(|stored| stored.paste.creator.as_deref() == Some(creator) && Inner::is_live(&stored.paste, now))
+            .collect();
+        // Newest first, then by token like SQLite
+        created.sort_by(|a, b| b.paste.created_at.cmp(&a.paste.created_at).then_with(|| a.paste.token.cmp(&b.paste.token)));
+        Ok(created
+            .into_iter()
+            .take(limit.max(0) as usize)
+            .map(|stored| CreatedPaste {
+                token: stored.paste.token.clone(),
+                preview: stored.paste.content.chars().take(100).collect(),
+                size: stored.paste.size,
+                views: stored.paste.views,
+                public: stored.paste.public,
+                redirect: stored.paste.redirect,
+                binary: stored.paste.data.is_some(),
+                created_at: stored.paste.created_at,
+                expires_at: stored.paste.expires_at,
+                pending: stored.paste.pending,
+                title: stored.paste.title.clone(),
+            })
+            .collect())
+    }
+
+    fn tag_counts(&self) -> StoreResult<Vec<(String, i64)>> {
+        let inner = self.read();
+        let now = now();
+
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for stored in inner.pastes.values() {
+            if stored.paste.public && !stored.paste.pending && Inner::is_live(&stored.paste, now) {
+                for tag in &stored.tags {
+                    *counts.entry(tag.as_str()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut counts: Vec<(String, i64)> = counts
+            .into_iter()
+            .map(|(tag, uses)| (tag.to_string(), uses))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(counts)
+    }
+
+    fn daily_views(&self, token: &str, days: i64) -> StoreResult<Vec<(String, i64)>> {
+        let inner = self.read();
+        let start = window_start(days);
+        let rows = inner
+            .daily_views
+            .iter()
+            .filter(|((view_token, day), _)| view_token == token && *day >= start)
+            .map(|((_, day), views)| (day.clone(), *views))
+            .collect();
+        Ok(fill_days(days, rows))
+    }
+
+    fn gist_mirror(&self, token: &str) -> StoreResult<Option<GistMirror>> {
+        let inner = self.read();
+        Ok(inner.pastes.get(token).and_then(|stored| stored.gist.clone()))
+    }
+
+    fn set_gist_mirror(&self, token: &str, state: &GistState) -> StoreResult<bool> {
+        let mut inner = self.write();
+        match inner.pastes.get_mut(token) {
+            Some(stored) => {
+                stored.gist = Some(GistMirror {
+                    state: state.clone(),
+                    updated_at: now(),
+                });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn purge_expired(&self) -> StoreResult<usize> {
+        let mut inner = self.write();
+        let now = now();
+        let expired: Vec<String> = inner
+            .pastes
+            .values()
+            .filter(|stored| !Inner::is_live(&stored.paste, now))
+            .map(|stored| stored.paste.token.clone())
+            .collect();
+        for token in &expired {
+            inner.remove(token);
+        }
+        Ok(expired.len())
+    }
+
+    fn prune_daily_views(&self) -> StoreResult<usize> {
+        let mut inner = self.write();
+        let start = window_start(DAILY_VIEWS_RETENTION_DAYS);
+        let before = inner.daily_views.len();
+        inner.daily_views.retain(|(_, day), _| *day >= start);
+        Ok(before - inner.daily_views.len())
+    }
+
+    fn purge_preview(&self) -> StoreResult<PurgeCounts> {
+        let inner = self.read();
+        let now = now();
+        let daily_views_start = window_start(DAILY_VIEWS_RETENTION_DAYS);
+        let quotas_start = window_start(QUOTA_RETENTION_DAYS);
+        Ok(PurgeCounts {
+            expired_pastes: inner
+                .pastes
+                .values()
+                .filter(|stored| !Inner::is_live(&stored.paste, now))
+                .count(),
+            daily_views: inner.daily_views.keys().filter(|(_, day)| *day < daily_views_start).count(),
+            quotas: inner.creations.keys().filter(|(_, day)| *day < quotas_start).count(),
+        })
+    }
+
+    fn blob_in_use(&self, hash: &str) -> StoreResult<bool> {
+        let inner = self.read();
+        Ok(inner
+            .pastes
+            .values()
+            .any(|stored| stored.paste.blob.as_deref() == Some(hash)))
+    }
+
+    fn blob_hashes(&self) -> StoreResult<Vec<String>> {
+        let inner = self.read();
+        let mut hashes: Vec<String> = inner
+            .pastes
+            .values()
+            .filter_map(|stored| stored.paste.blob.clone())
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    fn record_creation(&self, client: &str, pastes: i64) -> StoreResult<i64> {
+        let mut inner = self.write();
+        let total = inner
+            .creations
+            .entry((client.to_string(), day_string(today())))
+            .or_insert(0);
+        *total += pastes;
+        Ok(*total)
+    }
+
+    fn creations_today(&self, client: &str) -> StoreResult<i64> {
+        let inner = self.read();
+        Ok(inner
+            .creations
+            .get(&(client.to_string(), day_string(today())))
+            .copied()
+            .unwrap_or(0))
+    }
+
+    fn prune_quotas(&self) -> StoreResult<usize> {
+        let mut inner = self.write();
+        let start = window_start(QUOTA_RETENTION_DAYS);
+        let before = inner.creations.len();
+        inner.creations.retain(|(_, day), _| *day >= start);
+        Ok(before - inner.creations.len())
+    }
+
+    fn record_audit(&self, entry: &AuditEntry) -> StoreResult<()> {
+        self.write().audit(entry);
+        Ok(())
+    }
+
+    fn audit_log(&self, query: &AuditQuery) -> StoreResult<Vec<AuditRecord>> {
+        let inner = self.read();
+        Ok(inner
+            .audit
+            .iter()
+            .rev()
+            .filter(|record| query.action.as_deref().is_none_or(|action| record.action == action))
+            .filter(|record| query.since.is_none_or(|since| record.at >= since))
+            .filter(|record| query.until.is_none_or(|until| record.at < until))
+            .take(query.limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn prune_audit_log(&self, before: i64) -> StoreResult<usize> {
+        let mut inner = self.write();
+        let kept = inner.audit.len();
+        inner.audit.retain(|record| record.at >= before);
+        Ok(kept - inner.audit.len())
+    }
+
+    // Every paste is read, which suits the size of a demo instance; the most matches first, then the newest.
+    // Unlike the SQLite index, accents are kept: “cafe” doesn't find “café”.
+    fn search(&self, query: &SearchQuery) -> StoreResult<Vec<SearchHit>> {
+        let inner = self.read();
+        let now = now();
+        let terms: Vec<Vec<String>> = query.terms.iter().map(|term| lowercase_words(term)).filter(|words| !words.is_empty()).collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut hits: Vec<(usize, SearchHit)> = inner
+            .pastes
+            .values()
+            .filter(|stored| stored.paste.public && !stored.paste.pending && stored.paste.data.is_none() && !stored.paste.redirect)
+            .filter(|stored| Inner::is_live(&stored.paste, now))
+            .filter(|stored| query.tag.as_deref().is_none_or(|tag| stored.tags.iter().any(|t| t == tag)))
+            .filter(|stored| query.since.is_none_or(|since| stored.paste.created_at >= since))
+            .filter(|stored| query.until.is_none_or(|until| stored.paste.created_at < until))
+            .filter_map(|stored| {
+                let (found, snippet) = search_content(&stored.paste.content, &terms)?;
+                Some((
+                    found,
+                    SearchHit {
+                        token: stored.paste.token.clone(),
+                        title: stored.paste.title.clone(),
+                        created_at: stored.paste.created_at,
+                        size: stored.paste.size,
+                        snippet,
+                    },
+                ))
+            })
+            .collect();
+
+        hits.sort_by(|(a_found, a), (b_found, b)| {
+            b_found.cmp(a_found).then_with(|| b.created_at.cmp(&a.created_at)).then_with(|| a.token.cmp(&b.token))
+        });
+        Ok(hits
+            .into_iter()
+            .skip(query.offset.max(0) as usize)
+            .take(query.limit.max(0) as usize)
+            .map(|(_, hit)| hit)
+            .collect())
+    }
+
+    // Nothing here outlives the process, so nothing gets old enough to be worth archiving.
+    fn archive_idle(&self, _idle_since: i64, _limit: usize) -> StoreResult<usize> {
+        Ok(0)
+    }
+
+    fn unarchive(&self, _token: &str) -> StoreResult<bool> {
+        Ok(false)
+    }
+
+    fn archive_stats(&self) -> StoreResult<ArchiveStats> {
+        let inner = self.read();
+        Ok(ArchiveStats {
+            hot_pastes: inner.pastes.len() as i64,
+            hot_bytes: inner.content_bytes as i64,
+            archived_pastes: 0,
+            archived_bytes: 0,
+            archived_stored_bytes: 0,
+        })
+    }
+
+    // The maps stand in for the tables, there is no file and no index.
+    fn db_stats(&self) -> StoreResult<DbStats> {
+        let inner = self.read();
+        let mut largest_pastes: Vec<PasteBytes> = inner
+            .pastes
+            .values()
+            .filter_map(|stored| {
+                Some(PasteBytes {
+                    token: stored.paste.token.clone(),
+                    bytes: stored.paste.size?.bytes,
+                })
+            })
+            .collect();
+        largest_pastes.sort_by_key(|paste| std::cmp::Reverse(paste.bytes));
+        largest_pastes.truncate(DB_STATS_TOP as usize);
+
+        let table = |table: &str, rows: usize| TableRows {
+            table: table.to_string(),
+            rows: rows as i64,
+        };
+        Ok(DbStats {
+            tables: vec![
+                table("announcements", inner.announcements.len()),
+                table("api_tokens", inner.api_tokens.len()),
+                table("audit_log", inner.audit.len()),
+                table("banned_ips", inner.bans.len()),
+                table("collection_items", inner.collections.values().map(|collection| collection.pastes.len()).sum()),
+                table("collections", inner.collections.len()),
+                table("comments", inner.pastes.values().map(|stored| stored.comments.len()).sum()),
+                table("instance_stats", inner.instances.len()),
+                table("ip_quota", inner.creations.len()),
+                table("paste_gists", inner.pastes.values().filter(|stored| stored.gist.is_some()).count()),
+                table("paste_tags", inner.pastes.values().map(|stored| stored.tags.len()).sum()),
+                table("paste_views_daily", inner.daily_views.len()),
+                table("pastes", inner.pastes.len()),
+            ],
+            largest_pastes,
+            ..DbStats::default()
+        })
+    }
+
+    fn sqlite_path(&self) -> Option<&Path> {
+        None
+    }
+}
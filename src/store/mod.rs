@@ -0,0 +1,1169 @@
+// Data access of the application.
+// Handlers only ever talk to a `PasteStore` and don't know which database is behind it:
+// SQLite (`sqlite.rs`, the default), Postgres (`postgres.rs`, behind the `postgres` cargo feature)
+// or plain memory (`memory.rs`, for `--ephemeral` demo instances).
+// `open_with_retry` picks the implementation from the database location.
+
+pub mod blobs;
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+pub mod timed;
+
+use chrono::{Duration as DateDuration, NaiveDate, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// How many days of per-day view counters we keep around before the cleanup task deletes them.
+pub const DAILY_VIEWS_RETENTION_DAYS: i64 = 30;
+
+// How many days of per-client creation counts are kept, the quota only ever looks at today's.
+pub const QUOTA_RETENTION_DAYS: i64 = 2;
+
+// Length of the lists of the admin database page.
+pub const DB_STATS_TOP: i64 = 20;
+
+// Size of the content of a paste, computed once when it is created (see `text::measure`).
+// Binary pastes have no lines or characters, only bytes.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct ContentSize {
+    pub lines: i64,
+    pub chars: i64,
+    pub bytes: i64,
+}
+
+impl ContentSize {
+    // Lines are the "\n" in the text, plus one for a last line without a trailing newline:
+    // "" has 0 lines, "a" and "a\n" have 1, "\n\n" has 2, "a\r\nb" has 2 (a lone "\r" doesn't end a line).
+    // Characters are Unicode scalar values, so "é" is one character and two bytes.
+    pub fn of_text(text: &str) -> ContentSize {
+        let newlines = text.bytes().filter(|byte| *byte == b'\n').count();
+        let unterminated = !text.is_empty() && !text.ends_with('\n');
+        ContentSize {
+            lines: (newlines + unterminated as usize) as i64,
+            chars: text.chars().count() as i64,
+            bytes: text.len() as i64,
+        }
+    }
+
+    pub fn of_binary(data: &[u8]) -> ContentSize {
+        ContentSize {
+            lines: 0,
+            chars: 0,
+            bytes: data.len() as i64,
+        }
+    }
+
+    // From the three nullable columns of a row, `None` unless all of them are set.
+    pub fn from_columns(lines: Option<i64>, chars: Option<i64>, bytes: Option<i64>) -> Option<ContentSize> {
+        Some(ContentSize {
+            lines: lines?,
+            chars: chars?,
+            bytes: bytes?,
+        })
+    }
+}
+
+// A paste about to be created.
+#[derive(Clone)]
+pub struct NewPaste {
+    pub token: String,
+    pub secret: String,
+    pub content: String,
+    pub public: bool,
+    // Already validated by `tags::parse_tags`
+    pub tags: Vec<String>,
+    // Unix timestamp after which the paste is gone, `None` to keep it forever
+    pub expires_at: Option<i64>,
+    // Hash of the blob file holding the content, set by `BlobStore` for large pastes
+    // whose row then only keeps a preview of the content
+    pub blob: Option<String>,
+    // The bytes of a binary paste, whose `content` is then empty
+    pub data: Option<Vec<u8>>,
+    pub size: ContentSize,
+    // A short link, whose `content` is the URL it redirects to
+    pub redirect: bool,
+    // `content_hash` of the full content, text or binary, for “/h/{hash}”
+    pub content_hash: String,
+    // `creator::hashed` of the creator cookie of the browser that submitted it, see `creator.rs`
+    pub creator: Option<String>,
+    // Whether visitors may comment on it
+    pub comments: bool,
+    // Held for review until an admin approves it, see `review.rs`
+    pub pending: bool,
+    // `client::hashed_ip` of the address that submitted a pending paste, for a ban should it be rejected
+    pub submitter: Option<String>,
+    // Empty for none, see `title.rs`
+    pub title: String,
+    // Made from the content rather than given, made again when the paste is replaced
+    pub title_auto: bool,
+}
+
+// A stored paste. Timestamps are Unix timestamps (UTC).
+#[derive(Clone)]
+pub struct Paste {
+    pub token: String,
+    // Empty for pastes created before secrets existed, never matches any key
+    pub secret: String,
+    pub content: String,
+    pub public: bool,
+    pub views: i64,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub blob: Option<String>,
+    pub data: Option<Vec<u8>>,
+    // Only `None` for large pastes stored before sizes were recorded,
+    // `BlobStore` fills it in on their first read
+    pub size: Option<ContentSize>,
+    // Read back from the archive table, see `PasteStore::archive_idle`
+    pub archived: bool,
+    pub redirect: bool,
+    // Only `None` for pastes stored before hashes were recorded, filled in the next time they are read
+    pub content_hash: Option<String>,
+    // Never shown, only compared with the cookie of a request
+    pub creator: Option<String>,
+    pub comments: bool,
+    // Awaiting review, only its creator and the admins see it; archived pastes never are
+    pub pending: bool,
+    pub title: String,
+    pub title_auto: bool,
+}
+
+// Where the full content of a paste is, as returned by `PasteStore::open_content`.
+// Large pastes stay in their blob file so they can be streamed without loading them into memory.
+#[derive(Clone)]
+pub enum Content {
+    Inline(String),
+    File(PathBuf),
+    Binary(Vec<u8>),
+}
+
+impl Content {
+    // Size of the content in bytes.
+    pub fn len(&self) -> std::io::Result<u64> {
+        match self {
+            Content::Inline(content) => Ok(content.len() as u64),
+            Content::File(path) => Ok(std::fs::metadata(path)?.len()),
+            Content::Binary(data) => Ok(data.len() as u64),
+        }
+    }
+
+    // Loads the whole content of a text paste, reading the file if there is one.
+    pub fn into_string(self) -> std::io::Result<String> {
+        match self {
+            Content::Inline(content) => Ok(content),
+            Content::File(path) => std::fs::read_to_string(path),
+            Content::Binary(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "binary pastes have no text content",
+            )),
+        }
+    }
+}
+
+// One row of a listing page: the token, the first few characters of the content
+// and the number of views counted for it.
+pub struct ListedPaste {
+    pub token: String,
+    pub preview: String,
+    pub size: Option<ContentSize>,
+    pub views: i64,
+    pub title: String,
+}
+
+// A comment about to be added to a paste, already checked by `comments::check`.
+pub struct NewComment {
+    pub token: String,
+    pub author: String,
+    pub body: String,
+}
+
+// A comment of a paste, `id` counting up over every comment of the database.
+#[derive(Clone)]
+pub struct Comment {
+    pub id: i64,
+    pub author: String,
+    pub body: String,
+    pub created_at: i64,
+}
+
+// An API token about to be stored, see `api_tokens.rs`; the store only ever sees the hash of the token.
+pub struct NewApiToken {
+    pub label: String,
+    pub token_hash: String,
+    // Comma-separated, from `api_tokens::parse_scopes`
+    pub scopes: String,
+    pub expires_at: Option<i64>,
+    // Pastes it may create a day in place of the quota of its address, see `check_daily_quota`
+    pub daily_quota: Option<i64>,
+}
+
+// A ban to record, see `bans.rs`. One of `ip_hash` and `cidr` is set.
+#[derive(Clone)]
+pub struct NewBan {
+    // `client::hashed_ip` of a banned address
+    pub ip_hash: Option<String>,
+    // A banned range, as `bans::Cidr` writes it
+    pub cidr: Option<String>,
+    pub reason: String,
+    pub expires_at: Option<i64>,
+    // `admin`, or the label of the API token, as “token ci”
+    pub created_by: String,
+}
+
+// A ban as stored.
+#[derive(Clone, serde::Serialize)]
+pub struct Ban {
+    pub id: i64,
+    pub ip_hash: Option<String>,
+    pub cidr: Option<String>,
+    pub reason: String,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub created_at: i64,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub expires_at: Option<i64>,
+    pub created_by: String,
+}
+
+// An announcement about to be stored, or the new values of one, see `announcements.rs`.
+#[derive(Clone)]
+pub struct NewAnnouncement {
+    pub message: String,
+    // `info` or `warning`, see `announcements::Level`
+    pub level: String,
+    pub starts_at: i64,
+    // On for good without one
+    pub ends_at: Option<i64>,
+    // `admin`, or the label of the API token, as “token ci”
+    pub created_by: String,
+}
+
+// An announcement as stored.
+#[derive(Clone, serde::Serialize)]
+pub struct Announcement {
+    pub id: i64,
+    pub message: String,
+    pub level: String,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub starts_at: i64,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub ends_at: Option<i64>,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub created_at: i64,
+    pub created_by: String,
+}
+
+// A collection about to be stored, see `collections.rs`. Its pastes were checked to exist when it was made.
+pub struct NewCollection {
+    pub token: String,
+    pub secret: String,
+    pub title: String,
+    pub expires_at: Option<i64>,
+    // Tokens, in the order they are listed in
+    pub pastes: Vec<String>,
+}
+
+// A collection as stored. Its pastes are tokens, some of which may have expired or been deleted since.
+#[derive(Clone)]
+pub struct Collection {
+    pub token: String,
+    pub secret: String,
+    pub title: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub expires_at: Option<i64>,
+    pub pastes: Vec<String>,
+}
+
+// The last report of another instance, see `federation.rs`.
+#[derive(Clone, serde::Serialize)]
+pub struct InstanceStats {
+    pub instance: String,
+    pub version: String,
+    pub backend: String,
+    pub pastes: i64,
+    pub archived_pastes: i64,
+    pub content_bytes: i64,
+    pub stored_bytes: i64,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub received_at: i64,
+}
+
+// The rows naming a paste, or a collection, by token that can outlive it after a partial failure, see `integrity.rs`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Orphans {
+    // The search index, with SQLite its rows and the tokens they stand for
+    SearchIndex,
+    Tags,
+    DailyViews,
+    Comments,
+    Gists,
+    // The pastes listed by a collection that is gone
+    CollectionItems,
+}
+
+impl Orphans {
+    pub const ALL: [Orphans; 6] = [
+        Orphans::SearchIndex,
+        Orphans::Tags,
+        Orphans::DailyViews,
+        Orphans::Comments,
+        Orphans::Gists,
+        Orphans::CollectionItems,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Orphans::SearchIndex => "search_index",
+            Orphans::Tags => "tags",
+            Orphans::DailyViews => "daily_views",
+            Orphans::Comments => "comments",
+            Orphans::Gists => "gists",
+            Orphans::CollectionItems => "collection_items",
+        }
+    }
+}
+
+// A chunk of the scan of `PasteStore::find_orphans`.
+pub struct OrphanScan {
+    // The tokens of the chunk whose rows are orphans
+    pub orphans: Vec<String>,
+    // Where the next chunk starts, `None` once the table is done
+    pub last: Option<String>,
+}
+
+// The blob files of `BlobStore` that don't match the pastes, see `PasteStore::scan_blobs`.
+#[derive(Default)]
+pub struct BlobScan {
+    // Of no paste, past the grace period for files being written
+    pub orphans: Vec<String>,
+    // Of a paste without a file
+    pub missing: Vec<String>,
+}
+
+// An API token as stored, revoked and expired ones included.
+#[derive(Clone, serde::Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub label: String,
+    #[serde(serialize_with = "crate::api_tokens::serialize_scopes")]
+    pub scopes: String,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub created_at: i64,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub expires_at: Option<i64>,
+    // Written at most once a minute, see `api_tokens::LastUsed`
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub last_used_at: Option<i64>,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub revoked_at: Option<i64>,
+    pub daily_quota: Option<i64>,
+}
+
+// One row of “/mine”: a paste of a creator, hot or archived (whose preview is empty).
+pub struct CreatedPaste {
+    pub token: String,
+    pub preview: String,
+    pub size: Option<ContentSize>,
+    pub views: i64,
+    pub public: bool,
+    pub redirect: bool,
+    pub binary: bool,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub pending: bool,
+    pub title: String,
+}
+
+// One paste of the review queue, see `review.rs`.
+#[derive(serde::Serialize)]
+pub struct PendingPaste {
+    pub token: String,
+    // The first 100 characters, empty for a binary paste
+    pub preview: String,
+    pub bytes: i64,
+    pub public: bool,
+    pub redirect: bool,
+    pub binary: bool,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub created_at: i64,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub expires_at: Option<i64>,
+    // `client::hashed_ip` of the address it was submitted from, `None` for one made without an address
+    pub submitter: Option<String>,
+}
+
+// How many pastes, and how many bytes of content, are in the hot table and in the archive.
+// `archived_stored_bytes` is what the compressed contents actually take.
+#[derive(serde::Serialize)]
+pub struct ArchiveStats {
+    pub hot_pastes: i64,
+    pub hot_bytes: i64,
+    pub archived_pastes: i64,
+    pub archived_bytes: i64,
+    pub archived_stored_bytes: i64,
+}
+
+// Where the mirror of a paste to a GitHub gist stands, see `gist.rs`.
+#[derive(Clone)]
+pub enum GistState {
+    // Asked for, the gist isn't created yet
+    Pending,
+    // The page of the gist on GitHub
+    Mirrored(String),
+    // What GitHub, or the connection to it, answered
+    Failed(String),
+}
+
+impl GistState {
+    // The value of the `status` column, the URL or error going in columns of their own.
+    pub fn status(&self) -> &'static str {
+        match self {
+            GistState::Pending => "pending",
+            GistState::Mirrored(_) => "mirrored",
+            GistState::Failed(_) => "failed",
+        }
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            GistState::Mirrored(url) => Some(url),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            GistState::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    // Back from the three columns, an unknown status reading as a failure.
+    pub fn from_columns(status: &str, url: Option<String>, error: Option<String>) -> GistState {
+        match (status, url) {
+            ("pending", _) => GistState::Pending,
+            ("mirrored", Some(url)) => GistState::Mirrored(url),
+            _ => GistState::Failed(error.unwrap_or_else(|| format!("Unknown mirror status {}", status))),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GistMirror {
+    pub state: GistState,
+    // When the state last changed
+    pub updated_at: i64,
+}
+
+// A paste found by `PasteStore::find_by_hash`.
+pub struct HashMatch {
+    pub hash: String,
+    pub token: String,
+}
+
+// An action for the audit log: who deleted, replaced or purged what, see `PasteStore::record_audit`.
+// Nothing of the content of a paste goes in.
+pub struct AuditEntry {
+    // `admin` (the admin token), `creator` (the secret of the paste), `cli` (a command) or `sighup` (a reload)
+    pub actor: &'static str,
+    // `delete`, `replace`, `purge`, `backup` or `reload`
+    pub action: &'static str,
+    // The token of the paste acted on
+    pub target: Option<String>,
+    // `client::hashed_ip` of the request, none for commands and signals
+    pub ip_hash: Option<String>,
+    // Anything else worth knowing: counts, the keys a reload changed…
+    pub detail: String,
+}
+
+// An entry as the audit log returns it, with its timestamp.
+#[derive(serde::Serialize, Clone)]
+pub struct AuditRecord {
+    pub id: i64,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub at: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip_hash: Option<String>,
+    pub detail: String,
+}
+
+// Which entries `PasteStore::audit_log` returns: of one action, from `since` and before `until`, the newest `limit`.
+pub struct AuditQuery {
+    pub action: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: i64,
+}
+
+// What `PasteStore::search` looks for: public pastes holding every one of `terms`, optionally only the ones carrying
+// `tag` and created from `since` and before `until`. The best matches first, `limit` of them after skipping `offset`.
+pub struct SearchQuery {
+    // Words as typed, each one matched as a whole word (or phrase, when it holds punctuation), case-insensitively
+    pub terms: Vec<String>,
+    pub tag: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// A paste found by `PasteStore::search`, with the part of its content around the terms.
+pub struct SearchHit {
+    pub token: String,
+    // Empty for none
+    pub title: String,
+    pub created_at: i64,
+    pub size: Option<ContentSize>,
+    // Plain text, every term found wrapped between `SNIPPET_START` and `SNIPPET_END` and `SNIPPET_ELLIPSIS`
+    // where the content was cut; `search::snippet_html` makes HTML of it
+    pub snippet: String,
+}
+
+// Around the terms of a snippet: control characters, which can't be matched by a term nor mixed up with the text
+// once `search::snippet_html` has escaped it.
+pub const SNIPPET_START: char = '\u{2}';
+pub const SNIPPET_END: char = '\u{3}';
+pub const SNIPPET_ELLIPSIS: &str = "…";
+
+// About how many words of content a snippet shows.
+pub const SNIPPET_WORDS: usize = 24;
+
+// How many rows a purge deleted, or would delete on a dry run: expired pastes,
+// per-day view rows older than `DAILY_VIEWS_RETENTION_DAYS` and creation counts older than `QUOTA_RETENTION_DAYS`.
+#[derive(serde::Serialize, Default)]
+pub struct PurgeCounts {
+    pub expired_pastes: usize,
+    pub daily_views: usize,
+    pub quotas: usize,
+}
+
+// What the admin database page shows. Every number has its unit in its name, `*_bytes`, `rows` or `pages`,
+// and is `None` where the backend has no such thing (no WAL file with Postgres, no file at all in memory).
+#[derive(serde::Serialize, Default)]
+pub struct DbStats {
+    pub tables: Vec<TableRows>,
+    pub file_bytes: Option<i64>,
+    pub wal_bytes: Option<i64>,
+    pub page_size_bytes: Option<i64>,
+    pub pages: Option<i64>,
+    pub free_pages: Option<i64>,
+    // The `DB_STATS_TOP` biggest pastes of the hot table, biggest first
+    pub largest_pastes: Vec<PasteBytes>,
+    pub indexes: Vec<IndexUsage>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TableRows {
+    pub table: String,
+    pub rows: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct PasteBytes {
+    pub token: String,
+    pub bytes: i64,
+}
+
+// `scans` is how often the index was used since the statistics were reset, SQLite doesn't count that.
+#[derive(serde::Serialize)]
+pub struct IndexUsage {
+    pub table: String,
+    pub index: String,
+    pub scans: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Sqlite(rusqlite::Error),
+    #[cfg(feature = "postgres")]
+    Postgres(::postgres::Error),
+    // Reading or writing a blob file
+    Io(std::io::Error),
+    // A database location that can't be used, e.g. a postgres URL without the postgres feature
+    #[cfg_attr(feature = "postgres", allow(dead_code))]
+    Config(String),
+    // A token already taken, from the memory store; the databases report it through their primary key
+    Duplicate(String),
+    // What `TimedStore::ping` answers once a query found the database corrupt
+    Corrupt(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            #[cfg(feature = "postgres")]
+            StoreError::Postgres(e) => write!(f, "Postgres error: {}", e),
+            StoreError::Io(e) => write!(f, "I/O error: {}", e),
+            StoreError::Config(message) => write!(f, "{}", message),
+            StoreError::Duplicate(token) => write!(f, "The token {} is already taken", token),
+            StoreError::Corrupt(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl StoreError {
+    // Whether the database was locked by another connection, e.g. the server while a command runs next to it.
+    pub fn is_busy(&self) -> bool {
+        match self {
+            StoreError::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            _ => false,
+        }
+    }
+
+    // Whether the disk of the database or of the blobs is full.
+    pub fn is_full(&self) -> bool {
+        match self {
+            StoreError::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => e.code == rusqlite::ErrorCode::DiskFull,
+            #[cfg(feature = "postgres")]
+            StoreError::Postgres(e) => e.code() == Some(&::postgres::error::SqlState::DISK_FULL),
+            StoreError::Io(e) => e.kind() == std::io::ErrorKind::StorageFull,
+            _ => false,
+        }
+    }
+
+    // Whether the database is damaged: a page of the SQLite file that doesn't read, a file that isn't a database
+    // anymore, or what Postgres reports of its data and its indexes.
+    pub fn is_corrupt(&self) -> bool {
+        match self {
+            StoreError::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase
+            ),
+            #[cfg(feature = "postgres")]
+            StoreError::Postgres(e) => matches!(
+                e.code(),
+                Some(&::postgres::error::SqlState::DATA_CORRUPTED) | Some(&::postgres::error::SqlState::INDEX_CORRUPTED)
+            ),
+            StoreError::Corrupt(_) => true,
+            _ => false,
+        }
+    }
+
+    // Whether a paste couldn't be inserted because its token is taken.
+    pub fn is_conflict(&self) -> bool {
+        match self {
+            StoreError::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => e.code == rusqlite::ErrorCode::ConstraintViolation,
+            #[cfg(feature = "postgres")]
+            StoreError::Postgres(e) => e.code() == Some(&::postgres::error::SqlState::UNIQUE_VIOLATION),
+            StoreError::Duplicate(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> StoreError {
+        StoreError::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> StoreError {
+        StoreError::Io(e)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<::postgres::Error> for StoreError {
+    fn from(e: ::postgres::Error) -> StoreError {
+        StoreError::Postgres(e)
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+// Everything the handlers need from the database.
+// Expired pastes are treated as gone by every method even before `purge_expired` deletes them,
+// and only public pastes ever show up in `list_popular` and `tag_counts`.
+// Each method is one operation, stored whole or not at all: one failing at any statement leaves no row of it behind,
+// in any table. The SQL stores run the ones writing several rows as one transaction, the memory store checks before
+// it changes anything, and the audit entry an operation takes is written with it. Operations that have to be stored
+// together, like a purge or the rejection of a paste with the ban of its submitter, go through `with_tx`.
+pub trait PasteStore: Send + Sync {
+    // Name of the backend, for logs.
+    fn backend(&self) -> &'static str;
+
+    // Cheap query telling whether the database answers, for health checks.
+    fn ping(&self) -> StoreResult<()>;
+
+    // What the check of the database file at startup finds, see `db_check.rs`: the lines of SQLite's
+    // `PRAGMA quick_check`, or of the slower `integrity_check` reading every row and index when `full`, none for a
+    // sound file. Postgres checks its pages as it reads them and the memory store has no file, they find nothing.
+    fn check_database(&self, full: bool) -> StoreResult<Vec<String>>;
+
+    // Runs `op` as one transaction: the calls it makes to this store are stored together when it returns `Ok`, and
+    // none of them when it or any of them fails. The calls of other threads stay out of it: they wait for it to end,
+    // or run beside it on another connection of Postgres. A `with_tx` inside `op` joins the outer one. `op` may run again when the SQLite database was busy, so it must
+    // not do more than call the store. A store wrapping another one (see `blobs.rs`) passes `op` to the inner one,
+    // and the calls of `op` are made on the outer one, like the handlers make them: on `AppState::store`.
+    fn with_tx(&self, op: &mut dyn FnMut() -> StoreResult<()>) -> StoreResult<()>;
+
+    // Stores a new paste along with its tags. Fails with an error for which `is_conflict` holds
+    // when the token is already taken.
+    fn insert(&self, paste: &NewPaste) -> StoreResult<()> {
+        self.insert_batch(std::slice::from_ref(paste))
+    }
+
+    // Stores several new pastes in one transaction: either all of them end up stored or none does.
+    fn insert_batch(&self, pastes: &[NewPaste]) -> StoreResult<()>;
+
+    // Returns the paste with this token, `None` when it doesn't exist or has expired.
+    fn get(&self, token: &str) -> StoreResult<Option<Paste>>;
+
+    // Records the size of a paste that doesn't have one yet, or that was wrong, archived ones included.
+    fn set_size(&self, token: &str, size: ContentSize) -> StoreResult<()>;
+
+    // Records the hash of the content of a paste that doesn't have one yet, see `content_hash`.
+    fn set_content_hash(&self, token: &str, hash: &str) -> StoreResult<()>;
+
+    // The public pastes, hot or archived, whose content hash starts with `prefix` (lower case hex), one per hash:
+    // the oldest paste with it, the one its hash always leads to. At most `limit` hashes, in order.
+    fn find_by_hash(&self, prefix: &str, limit: i64) -> StoreResult<Vec<HashMatch>>;
+
+    // Whether a paste with this token is stored, even an expired one not purged yet.
+    fn exists(&self, token: &str) -> StoreResult<bool>;
+
+    // Like `get`, but hands out the content separately without reading blob files, see `Content`.
+    // The `content` of the returned paste is left empty.
+    fn open_content(&self, token: &str) -> StoreResult<Option<(Paste, Content)>> {
+        Ok(self.get(token)?.map(|mut paste| {
+            let content = match paste.data.take() {
+                Some(data) => Content::Binary(data),
+                None => Content::Inline(std::mem::take(&mut paste.content)),
+            };
+            (paste, content)
+        }))
+    }
+
+    // Deletes a paste and everything attached to it, returns whether it existed.
+    // `audit` goes to the audit log in the same transaction.
+    fn delete(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool>;
+
+    // Returns the pastes awaiting review, expired ones left out, oldest first.
+    fn pending_pastes(&self, limit: i64) -> StoreResult<Vec<PendingPaste>>;
+
+    // Publishes a paste awaiting review: it shows in the listings and the search from now on, and its submitter is
+    // forgotten. `audit` goes to the audit log in the same transaction. False when no paste `token` is pending.
+    fn approve(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool>;
+
+    // Deletes a paste awaiting review like `delete`, returns the submitter it had, `None` when no paste `token` is
+    // pending. `audit` goes to the audit log in the same transaction.
+    fn reject(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<Option<Option<String>>>;
+
+    // Puts `paste` in the place of the paste with the same token, hot, archived or expired, in one transaction.
+    // The old paste goes with its tags and views, as with `delete`, and `audit` with the same transaction.
+    fn replace(&self, paste: &NewPaste, audit: Option<&AuditEntry>) -> StoreResult<()>;
+
+    // Counts one view of a paste, both in its total counter and in today's (UTC) bucket,
+    // and remembers when it was last viewed.
+    fn record_view(&self, token: &str) -> StoreResult<()>;
+
+    // Returns the tags of a paste in alphabetical order.
+    fn tags(&self, token: &str) -> StoreResult<Vec<String>>;
+
+    // Returns the most viewed public pastes over the last `days` days (today included),
+    // ordered by the number of views in that window, optionally only the ones carrying `tag`.
+    fn list_popular(&self, days: i64, tag: Option<&str>, limit: i64) -> StoreResult<Vec<ListedPaste>>;
+
+    // Adds a comment to a paste, returning its id. The caller checks that the paste exists and takes comments.
+    fn add_comment(&self, comment: &NewComment) -> StoreResult<i64>;
+
+    // Returns the comments of a paste, oldest first. They go with the paste when it is deleted, replaced or expires.
+    fn comments(&self, token: &str) -> StoreResult<Vec<Comment>>;
+
+    // Deletes one comment of a paste, recording `audit` with it; false when the paste has no comment `id`.
+    fn delete_comment(&self, token: &str, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool>;
+
+    // Stores a new API token, recording `audit` with it, and returns its id.
+    fn create_api_token(&self, token: &NewApiToken, audit: Option<&AuditEntry>) -> StoreResult<i64>;
+
+    // Returns the API token whose token has the hash `token_hash`, revoked and expired ones included.
+    fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>>;
+
+    // Returns every API token, newest first.
+    fn api_tokens(&self) -> StoreResult<Vec<ApiToken>>;
+
+    // Revokes an API token, recording `audit` with it; false when there is no such token or it was revoked already.
+    fn revoke_api_token(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool>;
+
+    // Records a use of an API token at `at`.
+    fn touch_api_token(&self, id: i64, at: i64) -> StoreResult<()>;
+
+    // Records a ban, with `audit` in the same transaction, and returns its id.
+    fn add_ban(&self, ban: &NewBan, audit: Option<&AuditEntry>) -> StoreResult<i64>;
+
+    // Returns the bans that haven't expired, newest first.
+    fn bans(&self) -> StoreResult<Vec<Ban>>;
+
+    // Lifts a ban, recording `audit` with it; false when there is no such ban.
+    fn remove_ban(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool>;
+
+    // Deletes the bans that have expired, returns how many were deleted.
+    fn prune_bans(&self) -> StoreResult<usize>;
+
+    // Stores an announcement, with `audit` in the same transaction, and returns its id.
+    fn add_announcement(&self, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<i64>;
+
+    // Returns the announcements that haven't ended, those still to come included, by when they start.
+    fn announcements(&self) -> StoreResult<Vec<Announcement>>;
+
+    // Gives an announcement the message, level, start and end of `announcement`, its `created_by` stays; records
+    // `audit` with it. False when there is no such announcement.
+    fn update_announcement(&self, id: i64, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<bool>;
+
+    // Deletes an announcement, recording `audit` with it; false when there is no such announcement.
+    fn remove_announcement(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool>;
+
+    // Deletes the announcements that have ended, returns how many were deleted.
+    fn prune_announcements(&self) -> StoreResult<usize>;
+
+    // Stores a new collection, a conflict when its token is taken.
+    fn insert_collection(&self, collection: &NewCollection) -> StoreResult<()>;
+
+    // Returns a collection that hasn't expired.
+    fn collection(&self, token: &str) -> StoreResult<Option<Collection>>;
+
+    // Replaces the pastes of a collection, in order; false when there is no such collection.
+    fn set_collection_pastes(&self, token: &str, pastes: &[String]) -> StoreResult<bool>;
+
+    // Deletes a collection, its pastes stay; false when there is no such collection.
+    fn delete_collection(&self, token: &str) -> StoreResult<bool>;
+
+    // Deletes the collections that have expired, returns how many were deleted.
+    fn prune_collections(&self) -> StoreResult<usize>;
+
+    // Keeps the report of an instance, in place of its previous one.
+    fn record_instance_stats(&self, stats: &InstanceStats) -> StoreResult<()>;
+
+    // Returns the last report of each instance, by name.
+    fn instance_stats(&self) -> StoreResult<Vec<InstanceStats>>;
+
+    // Looks at the rows of `kind` of the next `limit` tokens after `after` (all of them with ""), in order, and returns
+    // those of them whose paste (or collection) is gone. Each call is one short query, the scan of a large table goes
+    // chunk by chunk.
+    fn find_orphans(&self, kind: Orphans, after: &str, limit: i64) -> StoreResult<OrphanScan>;
+
+    // Deletes the rows of `kind` of `tokens` that are still orphans, in one transaction; returns how many tokens had some.
+    fn remove_orphans(&self, kind: Orphans, tokens: &[String]) -> StoreResult<usize>;
+
+    // Returns up to `limit` tokens of pastes after `after`, hot and archived, expired ones included, in order.
+    fn paste_tokens(&self, after: &str, limit: i64) -> StoreResult<Vec<String>>;
+
+    // The blob files that don't match the pastes, nothing for a store without them.
+    fn scan_blobs(&self) -> StoreResult<BlobScan> {
+        Ok(BlobScan::default())
+    }
+
+    // Deletes the blob files of `hashes` that still belong to no paste, returns how many were deleted.
+    fn remove_blobs(&self, _hashes: &[String]) -> StoreResult<usize> {
+        Ok(0)
+    }
+
+    // Returns the pastes storing `creator`, expired ones left out, newest first, pending ones included.
+    fn list_created(&self, creator: &str, limit: i64) -> StoreResult<Vec<CreatedPaste>>;
+
+    // Returns every tag used by at least one public paste with the number of public pastes using it,
+    // most used first. Here and in every listing, a paste awaiting review isn't public yet.
+    fn tag_counts(&self) -> StoreResult<Vec<(String, i64)>>;
+
+    // Returns the views of a paste for each of the last `days` days (UTC, oldest first, today last),
+    // days without any view included with a count of 0.
+    fn daily_views(&self, token: &str, days: i64) -> StoreResult<Vec<(String, i64)>>;
+
+    // Where mirroring the paste to a gist stands, `None` when it was never asked for.
+    fn gist_mirror(&self, token: &str) -> StoreResult<Option<GistMirror>>;
+
+    // Records where mirroring the paste to a gist stands, returns false when there is no such paste (anymore).
+    // Goes with the paste when it is deleted or replaced.
+    fn set_gist_mirror(&self, token: &str, state: &GistState) -> StoreResult<bool>;
+
+    // Deletes the pastes whose expiry has passed, returns how many were deleted.
+    fn purge_expired(&self) -> StoreResult<usize>;
+
+    // Deletes the per-day view rows older than `DAILY_VIEWS_RETENTION_DAYS`, returns how many were deleted.
+    fn prune_daily_views(&self) -> StoreResult<usize>;
+
+    // How many rows `purge_expired`, `prune_daily_views` and `prune_quotas` would delete right now.
+    fn purge_preview(&self) -> StoreResult<PurgeCounts>;
+
+    // Gives the pages freed by deletions back to the filesystem, where the backend can do that online.
+    fn reclaim_space(&self) -> StoreResult<()> {
+        Ok(())
+    }
+
+    // Whether any paste, expired or not, still has its content in the blob `hash`.
+    fn blob_in_use(&self, hash: &str) -> StoreResult<bool>;
+
+    // Every blob hash referenced by a paste, expired or not.
+    fn blob_hashes(&self) -> StoreResult<Vec<String>>;
+
+    // Counts `pastes` pastes created today (UTC) by `client`, a hashed IP, and returns today's count including them.
+    fn record_creation(&self, client: &str, pastes: i64) -> StoreResult<i64>;
+
+    // Today's count of `client` as `record_creation` keeps it, without counting anything.
+    fn creations_today(&self, client: &str) -> StoreResult<i64>;
+
+    // Deletes the creation counts older than `QUOTA_RETENTION_DAYS`, returns how many rows were deleted.
+    fn prune_quotas(&self) -> StoreResult<usize>;
+
+    // Adds an entry to the audit log, for actions that aren't a single `delete` or `replace`.
+    fn record_audit(&self, entry: &AuditEntry) -> StoreResult<()>;
+
+    // The entries of the audit log `query` asks for, newest first.
+    fn audit_log(&self, query: &AuditQuery) -> StoreResult<Vec<AuditRecord>>;
+
+    // Deletes the audit log entries from before `before`, returns how many were deleted.
+    fn prune_audit_log(&self, before: i64) -> StoreResult<usize>;
+
+    // The public text pastes, hot or archived, matching `query`, see `SearchQuery`. Short links are never found,
+    // and of a paste whose content is in a blob file only the start kept in its row is searched.
+    fn search(&self, query: &SearchQuery) -> StoreResult<Vec<SearchHit>>;
+
+    // Moves up to `limit` pastes not viewed (or created, if never viewed) since `idle_since` into the archive,
+    // where their content is kept compressed, returns how many were moved.
+    // Only pastes that never expire and whose content isn't in a blob file are archived.
+    // Archived pastes are still returned by `get`, `exists` and `tags` and still count views,
+    // but don't show up in `list_popular` or `tag_counts` anymore.
+    fn archive_idle(&self, idle_since: i64, limit: usize) -> StoreResult<usize>;
+
+    // Moves an archived paste back to the hot table, returns whether it was archived.
+    fn unarchive(&self, token: &str) -> StoreResult<bool>;
+
+    // How much is in the hot table and in the archive, for the admin.
+    fn archive_stats(&self) -> StoreResult<ArchiveStats>;
+
+    // Sizes of the tables and of the database, for the admin. Only reads, and never the contents themselves.
+    fn db_stats(&self) -> StoreResult<DbStats>;
+
+    // Path of the database file, only for backends that have one (used by backups).
+    fn sqlite_path(&self) -> Option<&Path>;
+}
+
+// Where the data lives, parsed from the `PASTRY_DB_PATH` setting, or memory with `--ephemeral`.
+pub enum Location {
+    Sqlite(PathBuf),
+    Postgres(String),
+    // Bounded by a number of pastes and a number of bytes of content
+    Memory { max_pastes: usize, max_bytes: usize },
+}
+
+impl Location {
+    // `postgres://` and `postgresql://` URLs select Postgres, anything else is a SQLite file path,
+    // resolved against `cwd` when relative.
+    pub fn parse(value: &str, cwd: &Path) -> Location {
+        if value.starts_with("postgres://") || value.starts_with("postgresql://") {
+            Location::Postgres(value.to_string())
+        } else {
+            Location::Sqlite(cwd.join(value.strip_prefix("sqlite://").unwrap_or(value)))
+        }
+    }
+}
+
+// The schema version of the database at `location` and the one this release migrates it to, read without migrating
+// or creating anything; `None` when there is no database yet. Memory stores have no schema, 0 of 0.
+pub fn schema_versions(location: &Location) -> StoreResult<(Option<usize>, usize)> {
+    match location {
+        Location::Sqlite(path) => Ok((sqlite::schema_version(path)?, sqlite::SCHEMA_VERSION)),
+        #[cfg(feature = "postgres")]
+        Location::Postgres(url) => Ok((postgres::schema_version(url)?, postgres::SCHEMA_VERSION)),
+        #[cfg(not(feature = "postgres"))]
+        Location::Postgres(_) => Err(StoreError::Config("This build has no Postgres support, it needs the postgres feature".to_string())),
+        Location::Memory { .. } => Ok((Some(0), 0)),
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Sqlite(path) => write!(f, "{}", path.display()),
+            // The URL may hold a password, only show where it points to
+            Location::Postgres(url) => match url.rsplit_once('@') {
+                Some((_, host)) => write!(f, "postgres://…@{}", host),
+                None => write!(f, "{}", url),
+            },
+            Location::Memory { max_pastes, max_bytes } => {
+                write!(f, "in memory (at most {} pastes, {} bytes)", max_pastes, max_bytes)
+            }
+        }
+    }
+}
+
+// Opens the store at `location` (creating or migrating the schema), retrying when it fails.
+// Meant for startup, where the volume or server holding the database may not be ready yet:
+// every failed attempt is logged and followed by a pause that doubles each time (capped at 30 seconds),
+// and the error of the last attempt is returned once `attempts` attempts have failed.
+// Each database call then waits at most `timeout` for a lock (SQLite) or for its statement (Postgres),
+// and SQLite writes that still found the database locked are tried again for up to `write_retry`.
+pub fn open_with_retry(
+    location: &Location,
+    attempts: u32,
+    backoff: Duration,
+    timeout: Duration,
+    write_retry: Duration,
+) -> StoreResult<Box<dyn PasteStore>> {
+    let mut delay = backoff;
+    let mut attempt = 1;
+
+    loop {
+        match open(location, timeout, write_retry) {
+            Ok(store) => return Ok(store),
+            Err(StoreError::Config(message)) => return Err(StoreError::Config(message)),
+            // Waiting won't repair it
+            Err(e) if e.is_corrupt() => return Err(e),
+            Err(e) if attempt < attempts => {
+                eprintln!(
+                    "Database {} not ready (attempt {}/{}): {}, retrying in {:?}",
+                    location, attempt, attempts, e, delay
+                );
+                thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_secs(30));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn open(location: &Location, timeout: Duration, write_retry: Duration) -> StoreResult<Box<dyn PasteStore>> {
+    match location {
+        Location::Sqlite(path) => Ok(Box::new(sqlite::SqliteStore::open(path, timeout, write_retry)?)),
+        Location::Memory { max_pastes, max_bytes } => Ok(Box::new(memory::MemoryStore::new(*max_pastes, *max_bytes))),
+        #[cfg(feature = "postgres")]
+        Location::Postgres(url) => Ok(Box::new(postgres::PostgresStore::open(url, timeout)?)),
+        #[cfg(not(feature = "postgres"))]
+        Location::Postgres(_) => Err(StoreError::Config(
+            "This build has no Postgres support, rebuild with `--features postgres`".to_string(),
+        )),
+    }
+}
+
+// zstd level of archived contents, cheap enough to run under the database lock even for the largest inline pastes.
+const ARCHIVE_ZSTD_LEVEL: i32 = 9;
+
+// Compresses the content of a paste for the archive, the bytes of binary pastes or the text of the others.
+pub fn compress_content(content: &str, data: Option<&[u8]>) -> std::io::Result<Vec<u8>> {
+    zstd::encode_all(data.unwrap_or(content.as_bytes()), ARCHIVE_ZSTD_LEVEL)
+}
+
+// The other way around: the (content, data) pair of a paste, as stored in the hot table.
+pub fn decompress_content(compressed: &[u8], binary: bool) -> std::io::Result<(String, Option<Vec<u8>>)> {
+    let bytes = zstd::decode_all(compressed)?;
+    if binary {
+        return Ok((String::new(), Some(bytes)));
+    }
+    let content = String::from_utf8(bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok((content, None))
+}
+
+// SHA-256 of the content of a paste in lower case hex, which also names its blob file.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+// The end of the range of hashes starting with `prefix`, for an indexed `hash >= prefix AND hash < end`:
+// 'g' comes after every hex digit.
+pub fn hash_prefix_end(prefix: &str) -> String {
+    format!("{}g", prefix)
+}
+
+// The current time as a Unix timestamp.
+pub fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+// Today's date in UTC, the bucket views are counted in.
+pub fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+// Formats a date the way it's stored in `paste_views_daily`, e.g. "2024-01-31".
+pub fn day_string(day: NaiveDate) -> String {
+    day.format("%Y-%m-%d").to_string()
+}
+
+// The first day of a window of `days` days ending today.
+pub fn window_start(days: i64) -> String {
+    day_string(today() - DateDuration::days(days - 1))
+}
+
+// Turns the stored (day, views) rows of a window of `days` days ending today into one entry per day,
+// oldest first, with 0 for the days that have no row.
+pub fn fill_days(days: i64, rows: Vec<(String, i64)>) -> Vec<(String, i64)> {
+    let rows: HashMap<String, i64> = rows.into_iter().collect();
+    let today = today();
+
+    (0..days)
+        .rev()
+        .map(|ago| {
+            let day = day_string(today - DateDuration::days(ago));
+            let views = rows.get(&day).copied().unwrap_or(0);
+            (day, views)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(text: &str) -> (i64, i64, i64) {
+        let size = ContentSize::of_text(text);
+        (size.lines, size.chars, size.bytes)
+    }
+
+    #[test]
+    fn empty_text_has_nothing() {
+        assert_eq!(ContentSize::of_text(""), ContentSize::default());
+    }
+
+    #[test]
+    fn lines_end_with_newlines() {
+        assert_eq!(size("a"), (1, 1, 1));
+        assert_eq!(size("a\n"), (1, 2, 2));
+        assert_eq!(size("a\nb"), (2, 3, 3));
+        assert_eq!(size("\n"), (1, 1, 1));
+        assert_eq!(size("\n\n\n"), (3, 3, 3));
+    }
+
+    #[test]
+    fn crlf_is_one_line_end() {
+        assert_eq!(size("a\r\nb"), (2, 4, 4));
+        assert_eq!(size("a\r\nb\r\n"), (2, 6, 6));
+        // A lone "\r" doesn't end a line
+        assert_eq!(size("a\rb"), (1, 3, 3));
+    }
+
+    #[test]
+    fn characters_are_scalar_values() {
+        assert_eq!(size("é"), (1, 1, 2));
+        assert_eq!(size("日本語\n"), (1, 4, 10));
+        assert_eq!(size("🦀"), (1, 1, 4));
+        // "e" and a combining accent are two
+        assert_eq!(size("e\u{301}"), (1, 2, 3));
+    }
+
+    #[test]
+    fn binary_has_only_bytes() {
+        let size = ContentSize::of_binary(b"\n\n\xff");
+        assert_eq!((size.lines, size.chars, size.bytes), (0, 0, 3));
+    }
+
+    #[test]
+    fn columns_need_all_three() {
+        assert_eq!(ContentSize::from_columns(Some(1), Some(2), Some(3)).map(|size| size.bytes), Some(3));
+        assert_eq!(ContentSize::from_columns(None, Some(2), Some(3)), None);
+        assert_eq!(ContentSize::from_columns(Some(1), Some(2), None), None);
+    }
+}
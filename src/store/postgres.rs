@@ -0,0 +1,1562 @@
+// The Postgres implementation of `PasteStore`, compiled with the `postgres` cargo feature.
+// Lets several instances of the server share one database.
+// Same tables and semantics as the SQLite store, the schema version is kept in `pastry_schema_version`.
+// The client is the blocking `postgres` crate rather than `tokio-postgres` or `sqlx`: `tokio-postgres` needs tokio 1,
+// which can't drive its connections from the tokio 0.2 of actix-web 3, and `PasteStore` is synchronous like rusqlite,
+// so an async client would be waited for on the blocking pool all the same. The server makes its writes there (see
+// `store_write` in `main.rs`), and the store keeps `CONNECTIONS` connections so that many calls run at once.
+
+use super::{
+    compress_content, day_string, decompress_content, fill_days, hash_prefix_end, now, today, window_start,
+    Announcement, ApiToken, ArchiveStats, AuditEntry, AuditQuery, AuditRecord, Ban, Collection, Comment, ContentSize,
+    CreatedPaste, DbStats, GistMirror, GistState, HashMatch, IndexUsage, InstanceStats, ListedPaste, NewAnnouncement,
+    NewApiToken, NewBan, NewCollection, NewComment, NewPaste, OrphanScan, Orphans, Paste, PasteBytes, PasteStore,
+    PendingPaste, PurgeCounts, SearchHit, SearchQuery, StoreResult, TableRows,
+    DAILY_VIEWS_RETENTION_DAYS, DB_STATS_TOP, QUOTA_RETENTION_DAYS, SNIPPET_END, SNIPPET_START, SNIPPET_WORDS,
+};
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use postgres::{Client, GenericClient, NoTls};
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::time::Duration;
+
+// Each migration is a batch of SQL statements, applied in order.
+// Never edit an existing entry, only append new ones at the end.
+const MIGRATIONS: &[&str] = &[
+    // 1: the schema as of the introduction of the Postgres store
+    "CREATE TABLE IF NOT EXISTS pastes (
+         token TEXT PRIMARY KEY,
+         secret TEXT,
+         content TEXT NOT NULL,
+         public BOOLEAN NOT NULL DEFAULT FALSE,
+         views BIGINT NOT NULL DEFAULT 0,
+         created_at BIGINT NOT NULL,
+         expires_at BIGINT
+     );
+     CREATE INDEX IF NOT EXISTS pastes_expires_at ON pastes (expires_at);
+     CREATE TABLE IF NOT EXISTS paste_views_daily (
+         token TEXT NOT NULL,
+         day TEXT NOT NULL,
+         views BIGINT NOT NULL DEFAULT 0,
+         PRIMARY KEY (token, day)
+     );
+     CREATE TABLE IF NOT EXISTS paste_tags (
+         token TEXT NOT NULL,
+         tag TEXT NOT NULL,
+         PRIMARY KEY (token, tag)
+     );
+     CREATE INDEX IF NOT EXISTS paste_tags_tag ON paste_tags (tag);",
+    // 2: large contents live in blob files, the row keeps the hash of the file
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS blob TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_blob ON pastes (blob);",
+    // 3: binary pastes keep their bytes here, with an empty `content`
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS data BYTEA;",
+    // 4: line, character and byte counts, computed for existing rows except blob ones (done on their first read).
+    // A last line without a trailing newline still counts as a line
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS line_count BIGINT;
+     ALTER TABLE pastes ADD COLUMN IF NOT EXISTS char_count BIGINT;
+     ALTER TABLE pastes ADD COLUMN IF NOT EXISTS byte_size BIGINT;
+     UPDATE pastes SET
+         line_count = CASE WHEN data IS NOT NULL THEN 0 ELSE
+             length(content) - length(replace(content, chr(10), ''))
+             + CASE WHEN content <> '' AND right(content, 1) <> chr(10) THEN 1 ELSE 0 END END,
+         char_count = CASE WHEN data IS NOT NULL THEN 0 ELSE length(content) END,
+         byte_size = CASE WHEN data IS NOT NULL THEN octet_length(data) ELSE octet_length(content) END
+     WHERE blob IS NULL;",
+    // 5: time of the last view, taken from the daily views still around for existing rows,
+    // and the archive of idle pastes with their content compressed with zstd
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS last_viewed_at BIGINT;
+     UPDATE pastes SET last_viewed_at =
+         (SELECT EXTRACT(EPOCH FROM MAX(d.day)::DATE)::BIGINT FROM paste_views_daily d WHERE d.token = pastes.token);
+     CREATE TABLE IF NOT EXISTS archived_pastes (
+         token TEXT PRIMARY KEY,
+         secret TEXT,
+         content BYTEA NOT NULL,
+         is_binary BOOLEAN NOT NULL,
+         public BOOLEAN NOT NULL,
+         views BIGINT NOT NULL,
+         created_at BIGINT NOT NULL,
+         line_count BIGINT,
+         char_count BIGINT,
+         byte_size BIGINT,
+         archived_at BIGINT NOT NULL
+     );",
+    // 6: pastes created per client (a salted hash of its IP) and day, for the daily quota
+    "CREATE TABLE IF NOT EXISTS ip_quota (
+         client TEXT NOT NULL,
+         day TEXT NOT NULL,
+         pastes BIGINT NOT NULL DEFAULT 0,
+         PRIMARY KEY (client, day)
+     );",
+    // 7: short links, whose content is the URL they redirect to
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS redirect BOOLEAN NOT NULL DEFAULT FALSE;
+     ALTER TABLE archived_pastes ADD COLUMN IF NOT EXISTS redirect BOOLEAN NOT NULL DEFAULT FALSE;",
+    // 8: the largest pastes of the admin database page, without reading every row
+    "CREATE INDEX IF NOT EXISTS pastes_byte_size ON pastes (byte_size);",
+    // 9: mirrors of pastes to GitHub gists, kept apart so archiving a paste doesn't have to carry them
+    "CREATE TABLE IF NOT EXISTS paste_gists (
+         token TEXT PRIMARY KEY,
+         status TEXT NOT NULL,
+         url TEXT,
+         error TEXT,
+         updated_at BIGINT NOT NULL
+     );",
+    // 10: SHA-256 of the content, for “/h/{hash}”; rows from before get theirs when they are next read
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS content_hash TEXT;
+     ALTER TABLE archived_pastes ADD COLUMN IF NOT EXISTS content_hash TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_content_hash ON pastes (content_hash);
+     CREATE INDEX IF NOT EXISTS archived_pastes_content_hash ON archived_pastes (content_hash);",
+    // 11: the audit log of deletions, replacements, purges and reloads
+    "CREATE TABLE IF NOT EXISTS audit_log (
+         id BIGSERIAL PRIMARY KEY,
+         at BIGINT NOT NULL,
+         actor TEXT NOT NULL,
+         action TEXT NOT NULL,
+         target TEXT,
+         ip_hash TEXT,
+         detail TEXT NOT NULL DEFAULT ''
+     );
+     CREATE INDEX IF NOT EXISTS audit_log_at ON audit_log (at);",
+    // 12: the text of the public text pastes for “/search”, with a full-text index, filled with the hot ones.
+    // It stays when a paste is archived; pastes archived before this version are compressed and not indexed
+    "CREATE TABLE IF NOT EXISTS paste_search (
+         token TEXT PRIMARY KEY,
+         content TEXT NOT NULL
+     );
+     CREATE INDEX IF NOT EXISTS paste_search_content ON paste_search USING GIN (to_tsvector('simple', content));
+     INSERT INTO paste_search (token, content)
+         SELECT token, content FROM pastes WHERE public AND data IS NULL AND NOT redirect
+     ON CONFLICT (token) DO NOTHING;",
+    // 13: the hashed creator cookie of the pastes submitted with one, for “/mine”
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS creator TEXT;
+     ALTER TABLE archived_pastes ADD COLUMN IF NOT EXISTS creator TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_creator ON pastes (creator);
+     CREATE INDEX IF NOT EXISTS archived_pastes_creator ON archived_pastes (creator);",
+    // 14: comments on pastes, and whether a paste takes them; `delete_paste` removes the comments of a paste with it
+    "CREATE TABLE IF NOT EXISTS comments (
+         id BIGSERIAL PRIMARY KEY,
+         token TEXT NOT NULL,
+         author TEXT NOT NULL,
+         body TEXT NOT NULL,
+         created_at BIGINT NOT NULL
+     );
+     CREATE INDEX IF NOT EXISTS comments_token ON comments (token);
+     ALTER TABLE pastes ADD COLUMN IF NOT EXISTS comments BOOLEAN NOT NULL DEFAULT TRUE;
+     ALTER TABLE archived_pastes ADD COLUMN IF NOT EXISTS comments BOOLEAN NOT NULL DEFAULT TRUE;",
+    // 15: API tokens with their scopes, a row without scopes may use all of them
+    "CREATE TABLE IF NOT EXISTS api_tokens (
+         id BIGSERIAL PRIMARY KEY,
+         label TEXT NOT NULL,
+         token_hash TEXT NOT NULL UNIQUE,
+         scopes TEXT NOT NULL DEFAULT 'read,write,delete,admin',
+         created_at BIGINT NOT NULL,
+         expires_at BIGINT,
+         last_used_at BIGINT,
+         revoked_at BIGINT
+     );",
+    // 16: banned addresses and ranges, see `bans.rs`
+    "CREATE TABLE IF NOT EXISTS banned_ips (
+         id BIGSERIAL PRIMARY KEY,
+         ip_hash TEXT,
+         cidr TEXT,
+         reason TEXT NOT NULL,
+         created_at BIGINT NOT NULL,
+         expires_at BIGINT,
+         created_by TEXT NOT NULL
+     );",
+    // 17: collections of pastes, see `collections.rs`, items naming pastes by token without a foreign key
+    "CREATE TABLE IF NOT EXISTS collections (
+         token TEXT PRIMARY KEY,
+         secret TEXT NOT NULL,
+         title TEXT NOT NULL DEFAULT '',
+         created_at BIGINT NOT NULL,
+         updated_at BIGINT NOT NULL,
+         expires_at BIGINT
+     );
+     CREATE TABLE IF NOT EXISTS collection_items (
+         collection TEXT NOT NULL,
+         position BIGINT NOT NULL,
+         paste TEXT NOT NULL,
+         PRIMARY KEY (collection, position)
+     );",
+    // 18: the last report of each instance sending its stats here, see `federation.rs`
+    "CREATE TABLE IF NOT EXISTS instance_stats (
+         instance TEXT PRIMARY KEY,
+         version TEXT NOT NULL,
+         backend TEXT NOT NULL,
+         pastes BIGINT NOT NULL,
+         archived_pastes BIGINT NOT NULL,
+         content_bytes BIGINT NOT NULL,
+         stored_bytes BIGINT NOT NULL,
+         received_at BIGINT NOT NULL
+     );",
+    // 19: pastes held for review, with the address they came from while they are, see `review.rs`
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS pending BOOLEAN NOT NULL DEFAULT FALSE;
+     ALTER TABLE pastes ADD COLUMN IF NOT EXISTS submitter TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_pending ON pastes (created_at) WHERE pending;",
+    // 20: announcements shown on the pages of the instance, see `announcements.rs`
+    "CREATE TABLE IF NOT EXISTS announcements (
+         id BIGSERIAL PRIMARY KEY,
+         message TEXT NOT NULL,
+         level TEXT NOT NULL DEFAULT 'info',
+         starts_at BIGINT NOT NULL,
+         ends_at BIGINT,
+         created_at BIGINT NOT NULL,
+         created_by TEXT NOT NULL
+     );",
+    // 21: titles of pastes, and whether each was made from the content, see `title.rs`
+    "ALTER TABLE pastes ADD COLUMN IF NOT EXISTS title TEXT NOT NULL DEFAULT '';
+     ALTER TABLE pastes ADD COLUMN IF NOT EXISTS title_auto BOOLEAN NOT NULL DEFAULT FALSE;
+     ALTER TABLE archived_pastes ADD COLUMN IF NOT EXISTS title TEXT NOT NULL DEFAULT '';
+     ALTER TABLE archived_pastes ADD COLUMN IF NOT EXISTS title_auto BOOLEAN NOT NULL DEFAULT FALSE;",
+    // 22: the daily quota of pastes of an API token, see `check_daily_quota`
+    "ALTER TABLE api_tokens ADD COLUMN IF NOT EXISTS daily_quota BIGINT;",
+];
+
+// The version `migrate` brings a database to.
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+// The schema version of the database at `url`, read without migrating it; `None` before the first migration.
+pub fn schema_version(url: &str) -> Result<Option<usize>, postgres::Error> {
+    let mut client = Client::connect(url, NoTls)?;
+    let exists: bool = client
+        .query_one("SELECT to_regclass('pastry_schema_version') IS NOT NULL", &[])?
+        .get(0);
+    if !exists {
+        return Ok(None);
+    }
+    let row = client.query_opt("SELECT version FROM pastry_schema_version WHERE id = 1", &[])?;
+    Ok(row.map(|row| row.get::<_, i32>(0) as usize))
+}
+
+// The connections of a store, each one a session of the Postgres server.
+const CONNECTIONS: usize = 4;
+
+pub struct PostgresStore {
+    // A thread holding one of them can take it again: the calls of `with_tx` run on it, inside its transaction
+    conns: Vec<ReentrantMutex<Connection>>,
+}
+
+struct Connection {
+    client: RefCell<Client>,
+    // How many transactions of `Tx` are open, the outermost one and its savepoints
+    depth: Cell<usize>,
+}
+
+impl PostgresStore {
+    // Connects to the database at `url` and migrates it.
+    // A statement running longer than `statement_timeout` is canceled by the server and fails.
+    pub fn open(url: &str, statement_timeout: Duration) -> Result<PostgresStore, postgres::Error> {
+        let mut conns = Vec::with_capacity(CONNECTIONS);
+        for _ in 0..CONNECTIONS {
+            let mut client = Client::connect(url, NoTls)?;
+            client.batch_execute(&format!("SET statement_timeout = {}", statement_timeout.as_millis()))?;
+            if conns.is_empty() {
+                migrate(&mut client)?;
+            }
+            conns.push(ReentrantMutex::new(Connection {
+                client: RefCell::new(client),
+                depth: Cell::new(0),
+            }));
+        }
+        Ok(PostgresStore { conns })
+    }
+
+    // The connection of a call: the one this thread holds already, inside `with_tx`, else a free one, else one picked
+    // by the thread to wait for.
+    fn conn(&self) -> ReentrantMutexGuard<'_, Connection> {
+        if let Some(held) = self.conns.iter().find(|conn| conn.is_owned_by_current_thread()) {
+            return held.lock();
+        }
+        if let Some(free) = self.conns.iter().find_map(|conn| conn.try_lock()) {
+            return free;
+        }
+        let mut hasher = DefaultHasher::new();
+        std::thread::current().id().hash(&mut hasher);
+        self.conns[hasher.finish() as usize % self.conns.len()].lock()
+    }
+
+    // Runs `op` as one transaction: committed when it returns `Ok`, rolled back with every row it wrote when it fails
+    // at any statement, see `SqliteStore::transaction`.
+    fn transaction<T>(&self, op: impl FnOnce(&mut Client) -> StoreResult<T>) -> StoreResult<T> {
+        let conn = self.conn();
+        let mut tx = Tx::begin(&conn)?;
+        let result = op(&mut conn.client.borrow_mut())?;
+        tx.end(true)?;
+        Ok(result)
+    }
+}
+
+// A transaction of the connection, or a savepoint of the one open. Rolled back when it is dropped without being
+// ended, on an error or a panic, so the connection never stays in a transaction.
+struct Tx<'a> {
+    conn: &'a Connection,
+    open: bool,
+}
+
+impl<'a> Tx<'a> {
+    fn begin(conn: &'a Connection) -> Result<Tx<'a>, postgres::Error> {
+        let depth = conn.depth.get();
+        conn.client.borrow_mut().batch_execute(if depth == 0 { "BEGIN" } else { "SAVEPOINT store" })?;
+        conn.depth.set(depth + 1);
+        Ok(Tx { conn, open: true })
+    }
+
+    // Commits, or releases the savepoint, else rolls back. A failed statement leaves the transaction aborted,
+    // committing it then rolls back too and fails.
+    fn end(&mut self, commit: bool) -> Result<(), postgres::Error> {
+        self.open = false;
+        let depth = self.conn.depth.get() - 1;
+        self.conn.depth.set(depth);
+        let mut client = self.conn.client.borrow_mut();
+        match (commit, depth) {
+            (true, 0) => client.batch_execute("COMMIT"),
+            (false, 0) => client.batch_execute("ROLLBACK"),
+            (true, _) => client.batch_execute("RELEASE store").inspect_err(|_| {
+                let _ = client.batch_execute("ROLLBACK TO store; RELEASE store");
+            }),
+            (false, _) => client.batch_execute("ROLLBACK TO store; RELEASE store"),
+        }
+    }
+}
+
+impl Drop for Tx<'_> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.end(false);
+        }
+    }
+}
+
+// Brings the database up to the latest schema version, one transaction per migration.
+// The version row is locked while migrating so two instances starting at once don't both run a migration.
+fn migrate(client: &mut Client) -> Result<(), postgres::Error> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS pastry_schema_version (id INTEGER PRIMARY KEY, version INTEGER NOT NULL);
+         INSERT INTO pastry_schema_version (id, version) VALUES (1, 0) ON CONFLICT (id) DO NOTHING;",
+    )?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate() {
+        let mut tx = client.transaction()?;
+        let current: i32 = tx
+            .query_one("SELECT version FROM pastry_schema_version WHERE id = 1 FOR UPDATE", &[])?
+            .get(0);
+        if current as usize > index {
+            continue;
+        }
+        tx.batch_execute(migration)?;
+        tx.execute(
+            "UPDATE pastry_schema_version SET version = $1 WHERE id = 1",
+            &[&(index as i32 + 1)],
+        )?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+// Deletes a paste, hot or archived, and the rows attached to it.
+// Inserts a new paste and its tags into the hot table.
+fn insert_paste(client: &mut impl GenericClient, paste: &NewPaste) -> Result<(), postgres::Error> {
+    client.execute(
+        "INSERT INTO pastes
+             (token, secret, content, public, created_at, expires_at, blob, data, line_count, char_count, byte_size, redirect,
+              content_hash, creator, comments, pending, submitter, title, title_auto)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+        &[
+            &paste.token,
+            &paste.secret,
+            &paste.content,
+            &paste.public,
+            &now(),
+            &paste.expires_at,
+            &paste.blob,
+            &paste.data,
+            &paste.size.lines,
+            &paste.size.chars,
+            &paste.size.bytes,
+            &paste.redirect,
+            &paste.content_hash,
+            &paste.creator,
+            &paste.comments,
+            &paste.pending,
+            &paste.submitter,
+            &paste.title,
+            &paste.title_auto,
+        ],
+    )?;
+    for tag in &paste.tags {
+        client.execute(
+            "INSERT INTO paste_tags (token, tag) VALUES ($1, $2)",
+            &[&paste.token, tag],
+        )?;
+    }
+    // A pending paste is indexed when it is approved
+    if paste.public && paste.data.is_none() && !paste.redirect && !paste.pending {
+        client.execute(
+            "INSERT INTO paste_search (token, content) VALUES ($1, $2)",
+            &[&paste.token, &paste.content],
+        )?;
+    }
+    Ok(())
+}
+
+fn delete_paste(client: &mut impl GenericClient, token: &str) -> Result<u64, postgres::Error> {
+    client.execute("DELETE FROM paste_tags WHERE token = $1", &[&token])?;
+    client.execute("DELETE FROM paste_views_daily WHERE token = $1", &[&token])?;
+    client.execute("DELETE FROM paste_gists WHERE token = $1", &[&token])?;
+    client.execute("DELETE FROM comments WHERE token = $1", &[&token])?;
+    client.execute("DELETE FROM paste_search WHERE token = $1", &[&token])?;
+    let archived = client.execute("DELETE FROM archived_pastes WHERE token = $1", &[&token])?;
+    Ok(client.execute("DELETE FROM pastes WHERE token = $1", &[&token])? + archived)
+}
+
+// Inserts the pastes of a collection, numbered from 0 in their order.
+// The table and the token column of the rows of `kind`.
+fn orphan_rows(kind: Orphans) -> (&'static str, &'static str) {
+    match kind {
+        Orphans::SearchIndex => ("paste_search", "token"),
+        Orphans::Tags => ("paste_tags", "token"),
+        Orphans::DailyViews => ("paste_views_daily", "token"),
+        Orphans::Comments => ("comments", "token"),
+        Orphans::Gists => ("paste_gists", "token"),
+        Orphans::CollectionItems => ("collection_items", "collection"),
+    }
+}
+
+// Whether the paste (or the collection) of the rows of `kind` of the token `token`, an SQL expression, is there.
+fn orphan_owner_exists(kind: Orphans, token: &str) -> String {
+    match kind {
+        Orphans::CollectionItems => format!("EXISTS (SELECT 1 FROM collections WHERE token = {})", token),
+        _ => format!(
+            "EXISTS (SELECT 1 FROM pastes WHERE token = {0}) OR EXISTS (SELECT 1 FROM archived_pastes WHERE token = {0})",
+            token
+        ),
+    }
+}
+
+fn insert_collection_items(client: &mut impl GenericClient, collection: &str, pastes: &[String]) -> Result<(), postgres::Error> {
+    for (position, paste) in pastes.iter().enumerate() {
+        client.execute(
+            "INSERT INTO collection_items (collection, position, paste) VALUES ($1, $2, $3)",
+            &[&collection, &(position as i64), paste],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_audit(client: &mut impl GenericClient, entry: &AuditEntry) -> Result<(), postgres::Error> {
+    client.execute(
+        "INSERT INTO audit_log (at, actor, action, target, ip_hash, detail) VALUES ($1, $2, $3, $4, $5, $6)",
+        &[&now(), &entry.actor, &entry.action, &entry.target, &entry.ip_hash, &entry.detail],
+    )?;
+    Ok(())
+}
+
+// The columns `api_token_row` reads.
+const API_TOKEN_COLUMNS: &str = "id, label, scopes, created_at, expires_at, last_used_at, revoked_at, daily_quota";
+
+fn api_token_row(row: &postgres::Row) -> ApiToken {
+    ApiToken {
+        id: row.get(0),
+        label: row.get(1),
+        scopes: row.get(2),
+        created_at: row.get(3),
+        expires_at: row.get(4),
+        last_used_at: row.get(5),
+        revoked_at: row.get(6),
+        daily_quota: row.get(7),
+    }
+}
+
+// The columns `ban_row` reads.
+const BAN_COLUMNS: &str = "id, ip_hash, cidr, reason, created_at, expires_at, created_by";
+
+fn ban_row(row: &postgres::Row) -> Ban {
+    Ban {
+        id: row.get(0),
+        ip_hash: row.get(1),
+        cidr: row.get(2),
+        reason: row.get(3),
+        created_at: row.get(4),
+        expires_at: row.get(5),
+        created_by: row.get(6),
+    }
+}
+
+// The columns `announcement_row` reads.
+const ANNOUNCEMENT_COLUMNS: &str = "id, message, level, starts_at, ends_at, created_at, created_by";
+
+fn announcement_row(row: &postgres::Row) -> Announcement {
+    Announcement {
+        id: row.get(0),
+        message: row.get(1),
+        level: row.get(2),
+        starts_at: row.get(3),
+        ends_at: row.get(4),
+        created_at: row.get(5),
+        created_by: row.get(6),
+    }
+}
+
+// Reads an archived paste, decompressing its content.
+fn get_archived(client: &mut impl GenericClient, token: &str) -> StoreResult<Option<Paste>> {
+    let row = match client.query_opt(
+        "SELECT token, COALESCE(secret, ''), content, is_binary, public, views, created_at, line_count, char_count, byte_size,
+                redirect, content_hash, creator, comments, title, title_auto
+         FROM archived_pastes
+         WHERE token = $1",
+        &[&token],
+    )? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let compressed: Vec<u8> = row.get(2);
+    let (content, data) = decompress_content(&compressed, row.get(3))?;
+    Ok(Some(Paste {
+        token: row.get(0),
+        secret: row.get(1),
+        content,
+        public: row.get(4),
+        views: row.get(5),
+        created_at: row.get(6),
+        expires_at: None,
+        blob: None,
+        data,
+        size: ContentSize::from_columns(row.get(7), row.get(8), row.get(9)),
+        archived: true,
+        redirect: row.get(10),
+        content_hash: row.get(11),
+        creator: row.get(12),
+        comments: row.get(13),
+        pending: false,
+        title: row.get(14),
+        title_auto: row.get(15),
+    }))
+}
+
+impl PasteStore for PostgresStore {
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn ping(&self) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        client.query_one("SELECT 1", &[])?;
+        Ok(())
+    }
+
+    fn with_tx(&self, op: &mut dyn FnMut() -> StoreResult<()>) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut tx = Tx::begin(&conn)?;
+        op()?;
+        tx.end(true)?;
+        Ok(())
+    }
+
+    fn check_database(&self, _full: bool) -> StoreResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn insert_batch(&self, pastes: &[NewPaste]) -> StoreResult<()> {
+        self.transaction(|tx| {
+            for paste in pastes {
+                insert_paste(tx, paste)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get(&self, token: &str) -> StoreResult<Option<Paste>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let row = client.query_opt(
+            "SELECT token, COALESCE(secret, ''), content, public, views, created_at, expires_at, blob, data,
+                    line_count, char_count, byte_size, redirect, content_hash, creator, comments, pending, title, title_auto
+             FROM pastes
+             WHERE token = $1 AND (expires_at IS NULL OR expires_at > $2)",
+            &[&token, &now()],
+        )?;
+        let paste = row.map(|row| Paste {
+            token: row.get(0),
+            secret: row.get(1),
+            content: row.get(2),
+            public: row.get(3),
+            views: row.get(4),
+            created_at: row.get(5),
+            expires_at: row.get(6),
+            blob: row.get(7),
+            data: row.get(8),
+            size: ContentSize::from_columns(row.get(9), row.get(10), row.get(11)),
+            archived: false,
+            redirect: row.get(12),
+            content_hash: row.get(13),
+            creator: row.get(14),
+            comments: row.get(15),
+            pending: row.get(16),
+            title: row.get(17),
+            title_auto: row.get(18),
+        });
+        match paste {
+            Some(paste) => Ok(Some(paste)),
+            None => get_archived(&mut *client, token),
+        }
+    }
+
+    fn set_size(&self, token: &str, size: ContentSize) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        client.execute(
+            "UPDATE pastes SET line_count = $1, char_count = $2, byte_size = $3 WHERE token = $4",
+            &[&size.lines, &size.chars, &size.bytes, &token],
+        )?;
+        client.execute(
+            "UPDATE archived_pastes SET line_count = $1, char_count = $2, byte_size = $3 WHERE token = $4",
+            &[&size.lines, &size.chars, &size.bytes, &token],
+        )?;
+        Ok(())
+    }
+
+    fn set_content_hash(&self, token: &str, hash: &str) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        client.execute("UPDATE pastes SET content_hash = $1 WHERE token = $2", &[&hash, &token])?;
+        client.execute("UPDATE archived_pastes SET content_hash = $1 WHERE token = $2", &[&hash, &token])?;
+        Ok(())
+    }
+
+    fn find_by_hash(&self, prefix: &str, limit: i64) -> StoreResult<Vec<HashMatch>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT DISTINCT ON (content_hash) content_hash, token FROM (
+                 SELECT content_hash, token, created_at FROM pastes
+                 WHERE content_hash >= $1 AND content_hash < $2 AND public AND NOT pending
+                   AND (expires_at IS NULL OR expires_at > $3)
+                 UNION ALL
+                 SELECT content_hash, token, created_at FROM archived_pastes
+                 WHERE content_hash >= $1 AND content_hash < $2 AND public
+             ) matches
+             ORDER BY content_hash, created_at, token
+             LIMIT $4",
+            &[&prefix, &hash_prefix_end(prefix), &now(), &limit],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| HashMatch {
+                hash: row.get(0),
+                token: row.get(1),
+            })
+            .collect())
+    }
+
+    fn exists(&self, token: &str) -> StoreResult<bool> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        Ok(client
+            .query_opt(
+                "SELECT 1 FROM pastes WHERE token = $1 UNION ALL SELECT 1 FROM archived_pastes WHERE token = $1 LIMIT 1",
+                &[&token],
+            )?
+            .is_some())
+    }
+
+    fn delete(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let deleted = delete_paste(tx, token)?;
+            if let Some(entry) = audit.filter(|_| deleted > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(deleted > 0)
+        })
+    }
+
+    fn replace(&self, paste: &NewPaste, audit: Option<&AuditEntry>) -> StoreResult<()> {
+        self.transaction(|tx| {
+            delete_paste(tx, &paste.token)?;
+            insert_paste(tx, paste)?;
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn pending_pastes(&self, limit: i64) -> StoreResult<Vec<PendingPaste>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT token, substr(content, 1, 100), COALESCE(byte_size, 0), public, redirect, data IS NOT NULL, created_at,
+                    expires_at, submitter
+             FROM pastes
+             WHERE pending AND (expires_at IS NULL OR expires_at > $1)
+             ORDER BY created_at, token
+             LIMIT $2",
+            &[&now(), &limit],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| PendingPaste {
+                token: row.get(0),
+                preview: row.get(1),
+                bytes: row.get(2),
+                public: row.get(3),
+                redirect: row.get(4),
+                binary: row.get(5),
+                created_at: row.get(6),
+                expires_at: row.get(7),
+                submitter: row.get(8),
+            })
+            .collect())
+    }
+
+    fn approve(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let row = tx.query_opt(
+                "UPDATE pastes SET pending = FALSE, submitter = NULL WHERE token = $1 AND pending
+                 RETURNING public AND data IS NULL AND NOT redirect, content",
+                &[&token],
+            )?;
+            let row = match row {
+                Some(row) => row,
+                None => return Ok(false),
+            };
+            if row.get::<_, bool>(0) {
+                let content: String = row.get(1);
+                tx.execute("INSERT INTO paste_search (token, content) VALUES ($1, $2)", &[&token, &content])?;
+            }
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn reject(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<Option<Option<String>>> {
+        self.transaction(|tx| {
+            let submitter = match tx.query_opt("SELECT submitter FROM pastes WHERE token = $1 AND pending", &[&token])? {
+                Some(row) => row.get::<_, Option<String>>(0),
+                None => return Ok(None),
+            };
+            delete_paste(tx, token)?;
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(Some(submitter))
+        })
+    }
+
+    fn record_view(&self, token: &str) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        client.execute(
+            "UPDATE pastes SET views = views + 1, last_viewed_at = $2 WHERE token = $1",
+            &[&token, &now()],
+        )?;
+        client.execute("UPDATE archived_pastes SET views = views + 1 WHERE token = $1", &[&token])?;
+        client.execute(
+            "INSERT INTO paste_views_daily (token, day, views) VALUES ($1, $2, 1)
+             ON CONFLICT (token, day) DO UPDATE SET views = paste_views_daily.views + 1",
+            &[&token, &day_string(today())],
+        )?;
+        Ok(())
+    }
+
+    fn tags(&self, token: &str) -> StoreResult<Vec<String>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query("SELECT tag FROM paste_tags WHERE token = $1 ORDER BY tag", &[&token])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn list_popular(&self, days: i64, tag: Option<&str>, limit: i64) -> StoreResult<Vec<ListedPaste>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT p.token, substr(p.content, 1, 100), SUM(d.views)::BIGINT AS window_views,
+                    p.line_count, p.char_count, p.byte_size, p.title
+             FROM paste_views_daily d
+             JOIN pastes p ON p.token = d.token
+             WHERE p.public AND NOT p.pending AND d.day >= $1
+               AND (p.expires_at IS NULL OR p.expires_at > $2)
+               AND ($3::TEXT IS NULL OR EXISTS (SELECT 1 FROM paste_tags t WHERE t.token = p.token AND t.tag = $3))
+             GROUP BY p.token
+             ORDER BY window_views DESC, p.token
+             LIMIT $4",
+            &[&window_start(days), &now(), &tag, &limit],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| ListedPaste {
+                token: row.get(0),
+                preview: row.get(1),
+                views: row.get(2),
+                size: ContentSize::from_columns(row.get(3), row.get(4), row.get(5)),
+                title: row.get(6),
+            })
+            .collect())
+    }
+
+    fn add_comment(&self, comment: &NewComment) -> StoreResult<i64> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let row = client.query_one(
+            "INSERT INTO comments (token, author, body, created_at) VALUES ($1, $2, $3, $4) RETURNING id",
+            &[&comment.token, &comment.author, &comment.body, &now()],
+        )?;
+        Ok(row.get(0))
+    }
+
+    fn comments(&self, token: &str) -> StoreResult<Vec<Comment>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query("SELECT id, author, body, created_at FROM comments WHERE token = $1 ORDER BY id", &[&token])?;
+        Ok(rows
+            .iter()
+            .map(|row| Comment {
+                id: row.get(0),
+                author: row.get(1),
+                body: row.get(2),
+                created_at: row.get(3),
+            })
+            .collect())
+    }
+
+    fn delete_comment(&self, token: &str, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let deleted = tx.execute("DELETE FROM comments WHERE token = $1 AND id = $2", &[&token, &id])?;
+            if let Some(entry) = audit.filter(|_| deleted > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(deleted > 0)
+        })
+    }
+
+    fn create_api_token(&self, token: &NewApiToken, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.transaction(|tx| {
+            let row = tx.query_one(
+                "INSERT INTO api_tokens (label, token_hash, scopes, created_at, expires_at, daily_quota)
+                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                &[&token.label, &token.token_hash, &token.scopes, &now(), &token.expires_at, &token.daily_quota],
+            )?;
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(row.get(0))
+        })
+    }
+
+    fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let row = client.query_opt(
+            &format!("SELECT {} FROM api_tokens WHERE token_hash = $1", API_TOKEN_COLUMNS) as &str,
+            &[&token_hash],
+        )?;
+        Ok(row.as_ref().map(api_token_row))
+    }
+
+    fn api_tokens(&self) -> StoreResult<Vec<ApiToken>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(&format!("SELECT {} FROM api_tokens ORDER BY id DESC", API_TOKEN_COLUMNS) as &str, &[])?;
+        Ok(rows.iter().map(api_token_row).collect())
+    }
+
+    fn revoke_api_token(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let revoked = tx.execute(
+                "UPDATE api_tokens SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL",
+                &[&now(), &id],
+            )?;
+            if let Some(entry) = audit.filter(|_| revoked > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(revoked > 0)
+        })
+    }
+
+    fn touch_api_token(&self, id: i64, at: i64) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        client.execute("UPDATE api_tokens SET last_used_at = $1 WHERE id = $2", &[&at, &id])?;
+        Ok(())
+    }
+
+    fn add_ban(&self, ban: &NewBan, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.transaction(|tx| {
+            let row = tx.query_one(
+                "INSERT INTO banned_ips (ip_hash, cidr, reason, created_at, expires_at, created_by)
+                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                &[&ban.ip_hash, &ban.cidr, &ban.reason, &now(), &ban.expires_at, &ban.created_by],
+            )?;
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(row.get(0))
+        })
+    }
+
+    fn bans(&self) -> StoreResult<Vec<Ban>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            &format!(
+                "SELECT {} FROM banned_ips WHERE expires_at IS NULL OR expires_at > $1 ORDER BY id DESC",
+                BAN_COLUMNS
+            ) as &str,
+            &[&now()],
+        )?;
+        Ok(rows.iter().map(ban_row).collect())
+    }
+
+    fn remove_ban(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let removed = tx.execute("DELETE FROM banned_ips WHERE id = $1", &[&id])?;
+            if let Some(entry) = audit.filter(|_| removed > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(removed > 0)
+        })
+    }
+
+    fn prune_bans(&self) -> StoreResult<usize> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        Ok(client.execute("DELETE FROM banned_ips WHERE expires_at <= $1", &[&now()])? as usize)
+    }
+
+    fn add_announcement(&self, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.transaction(|tx| {
+            let row = tx.query_one(
+                "INSERT INTO announcements (message, level, starts_at, ends_at, created_at, created_by)
+                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                &[
+                    &announcement.message,
+                    &announcement.level,
+                    &announcement.starts_at,
+                    &announcement.ends_at,
+                    &now(),
+                    &announcement.created_by,
+                ],
+            )?;
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(row.get(0))
+        })
+    }
+
+    fn announcements(&self) -> StoreResult<Vec<Announcement>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            &format!(
+                "SELECT {} FROM announcements WHERE ends_at IS NULL OR ends_at > $1 ORDER BY starts_at, id",
+                ANNOUNCEMENT_COLUMNS
+            ) as &str,
+            &[&now()],
+        )?;
+        Ok(rows.iter().map(announcement_row).collect())
+    }
+
+    fn update_announcement(&self, id: i64, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let updated = tx.execute(
+                "UPDATE announcements SET message = $1, level = $2, starts_at = $3, ends_at = $4 WHERE id = $5",
+                &[&announcement.message, &announcement.level, &announcement.starts_at, &announcement.ends_at, &id],
+            )?;
+            if let Some(entry) = audit.filter(|_| updated > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(updated > 0)
+        })
+    }
+
+    fn remove_announcement(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let removed = tx.execute("DELETE FROM announcements WHERE id = $1", &[&id])?;
+            if let Some(entry) = audit.filter(|_| removed > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(removed > 0)
+        })
+    }
+
+    fn prune_announcements(&self) -> StoreResult<usize> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        Ok(client.execute("DELETE FROM announcements WHERE ends_at <= $1", &[&now()])? as usize)
+    }
+
+    fn insert_collection(&self, collection: &NewCollection) -> StoreResult<()> {
+        self.transaction(|tx| {
+            let now = now();
+            tx.execute(
+                "INSERT INTO collections (token, secret, title, created_at, updated_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6)",
+                &[&collection.token, &collection.secret, &collection.title, &now, &now, &collection.expires_at],
+            )?;
+            insert_collection_items(tx, &collection.token, &collection.pastes)?;
+            Ok(())
+        })
+    }
+
+    fn collection(&self, token: &str) -> StoreResult<Option<Collection>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let row = client.query_opt(
+            "SELECT token, secret, title, created_at, updated_at, expires_at FROM collections
+             WHERE token = $1 AND (expires_at IS NULL OR expires_at > $2)",
+            &[&token, &now()],
+        )?;
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let pastes = client
+            .query("SELECT paste FROM collection_items WHERE collection = $1 ORDER BY position", &[&token])?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        Ok(Some(Collection {
+            token: row.get(0),
+            secret: row.get(1),
+            title: row.get(2),
+            created_at: row.get(3),
+            updated_at: row.get(4),
+            expires_at: row.get(5),
+            pastes,
+        }))
+    }
+
+    fn set_collection_pastes(&self, token: &str, pastes: &[String]) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let updated = tx.execute("UPDATE collections SET updated_at = $1 WHERE token = $2", &[&now(), &token])?;
+            if updated > 0 {
+                tx.execute("DELETE FROM collection_items WHERE collection = $1", &[&token])?;
+                insert_collection_items(tx, token, pastes)?;
+            }
+            Ok(updated > 0)
+        })
+    }
+
+    fn delete_collection(&self, token: &str) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let deleted = tx.execute("DELETE FROM collections WHERE token = $1", &[&token])?;
+            tx.execute("DELETE FROM collection_items WHERE collection = $1", &[&token])?;
+            Ok(deleted > 0)
+        })
+    }
+
+    fn prune_collections(&self) -> StoreResult<usize> {
+        self.transaction(|tx| {
+            let now = now();
+            tx.execute(
+                "DELETE FROM collection_items WHERE collection IN (SELECT token FROM collections WHERE expires_at <= $1)",
+                &[&now],
+            )?;
+            let deleted = tx.execute("DELETE FROM collections WHERE expires_at <= $1", &[&now])?;
+            Ok(deleted as usize)
+        })
+    }
+
+    fn record_instance_stats(&self, stats: &InstanceStats) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        client.execute(
+            "INSERT INTO instance_stats
+                 (instance, version, backend, pastes, archived_pastes, content_bytes, stored_bytes, received_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (instance) DO UPDATE SET
+                 version = EXCLUDED.version, backend = EXCLUDED.backend, pastes = EXCLUDED.pastes,
+                 archived_pastes = EXCLUDED.archived_pastes, content_bytes = EXCLUDED.content_bytes,
+                 stored_bytes = EXCLUDED.stored_bytes, received_at = EXCLUDED.received_at",
+            &[
+                &stats.instance,
+                &stats.version,
+                &stats.backend,
+                &stats.pastes,
+                &stats.archived_pastes,
+                &stats.content_bytes,
+                &stats.stored_bytes,
+                &stats.received_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn find_orphans(&self, kind: Orphans, after: &str, limit: i64) -> StoreResult<OrphanScan> {
+        let (table, column) = orphan_rows(kind);
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            format!(
+                "SELECT token, {exists} FROM (
+                     SELECT DISTINCT {column} AS token FROM {table} WHERE {column} > $1 ORDER BY {column} LIMIT $2
+                 ) chunk ORDER BY token",
+                exists = orphan_owner_exists(kind, "chunk.token"),
+                column = column,
+                table = table,
+            )
+            .as_str(),
+            &[&after, &limit],
+        )?;
+        Ok(OrphanScan {
+            last: rows.last().map(|row| row.get(0)),
+            orphans: rows.iter().filter(|row| !row.get::<_, bool>(1)).map(|row| row.get(0)).collect(),
+        })
+    }
+
+    fn remove_orphans(&self, kind: Orphans, tokens: &[String]) -> StoreResult<usize> {
+        let (table, column) = orphan_rows(kind);
+        let delete = format!("DELETE FROM {} WHERE {} = $1 AND NOT ({})", table, column, orphan_owner_exists(kind, "$1"));
+        self.transaction(|tx| {
+            let mut removed = 0;
+            for token in tokens {
+                if tx.execute(delete.as_str(), &[token])? > 0 {
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        })
+    }
+
+    fn paste_tokens(&self, after: &str, limit: i64) -> StoreResult<Vec<String>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT token FROM pastes WHERE token > $1
+             UNION SELECT token FROM archived_pastes WHERE token > $1
+             ORDER BY token LIMIT $2",
+            &[&after, &limit],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn instance_stats(&self) -> StoreResult<Vec<InstanceStats>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT instance, version, backend, pastes, archived_pastes, content_bytes, stored_bytes, received_at
+             FROM instance_stats ORDER BY instance",
+            &[],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| InstanceStats {
+                instance: row.get(0),
+                version: row.get(1),
+                backend: row.get(2),
+                pastes: row.get(3),
+                archived_pastes: row.get(4),
+                content_bytes: row.get(5),
+                stored_bytes: row.get(6),
+                received_at: row.get(7),
+            })
+            .collect())
+    }
+
+    fn list_created(&self, creator: &str, limit: i64) -> StoreResult<Vec<CreatedPaste>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT token, substr(content, 1, 100), line_count, char_count, byte_size, views, public, redirect, data IS NOT NULL,
+                    created_at, expires_at, pending, title
+             FROM pastes
+             WHERE creator = $1 AND (expires_at IS NULL OR expires_at > $2)
+             UNION ALL
+             SELECT token, '', line_count, char_count, byte_size, views, public, redirect, is_binary, created_at, NULL, FALSE, title
+             FROM archived_pastes
+             WHERE creator = $1
+             ORDER BY created_at DESC, token
+             LIMIT $3",
+            &[&creator, &now(), &limit],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| CreatedPaste {
+                token: row.get(0),
+                preview: row.get(1),
+                size: ContentSize::from_columns(row.get(2), row.get(3), row.get(4)),
+                views: row.get(5),
+                public: row.get(6),
+                redirect: row.get(7),
+                binary: row.get(8),
+                created_at: row.get(9),
+                expires_at: row.get(10),
+                pending: row.get(11),
+                title: row.get(12),
+            })
+            .collect())
+    }
+
+    fn tag_counts(&self) -> StoreResult<Vec<(String, i64)>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT t.tag, COUNT(*) AS uses
+             FROM paste_tags t
+             JOIN pastes p ON p.token = t.token
+             WHERE p.public AND NOT p.pending AND (p.expires_at IS NULL OR p.expires_at > $1)
+             GROUP BY t.tag
+             ORDER BY uses DESC, t.tag",
+            &[&now()],
+        )?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn daily_views(&self, token: &str, days: i64) -> StoreResult<Vec<(String, i64)>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT day, views FROM paste_views_daily WHERE token = $1 AND day >= $2",
+            &[&token, &window_start(days)],
+        )?;
+        Ok(fill_days(days, rows.iter().map(|row| (row.get(0), row.get(1))).collect()))
+    }
+
+    fn gist_mirror(&self, token: &str) -> StoreResult<Option<GistMirror>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let row = client.query_opt(
+            "SELECT status, url, error, updated_at FROM paste_gists WHERE token = $1",
+            &[&token],
+        )?;
+        Ok(row.map(|row| GistMirror {
+            state: GistState::from_columns(row.get(0), row.get(1), row.get(2)),
+            updated_at: row.get(3),
+        }))
+    }
+
+    fn set_gist_mirror(&self, token: &str, state: &GistState) -> StoreResult<bool> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let written = client.execute(
+            "INSERT INTO paste_gists (token, status, url, error, updated_at)
+             SELECT $1, $2, $3, $4, $5
+             WHERE EXISTS (SELECT 1 FROM pastes WHERE token = $1 UNION ALL SELECT 1 FROM archived_pastes WHERE token = $1)
+             ON CONFLICT (token) DO UPDATE SET status = $2, url = $3, error = $4, updated_at = $5",
+            &[&token, &state.status(), &state.url(), &state.error(), &now()],
+        )?;
+        Ok(written > 0)
+    }
+
+    fn purge_expired(&self) -> StoreResult<usize> {
+        self.transaction(|tx| {
+            let expired: Vec<String> = tx
+                .query("SELECT token FROM pastes WHERE expires_at <= $1", &[&now()])?
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+            for token in &expired {
+                delete_paste(tx, token)?;
+            }
+            Ok(expired.len())
+        })
+    }
+
+    fn prune_daily_views(&self) -> StoreResult<usize> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let removed = client.execute(
+            "DELETE FROM paste_views_daily WHERE day < $1",
+            &[&window_start(DAILY_VIEWS_RETENTION_DAYS)],
+        )?;
+        Ok(removed as usize)
+    }
+
+    fn purge_preview(&self) -> StoreResult<PurgeCounts> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let row = client.query_one(
+            "SELECT (SELECT COUNT(*) FROM pastes WHERE expires_at <= $1),
+                    (SELECT COUNT(*) FROM paste_views_daily WHERE day < $2),
+                    (SELECT COUNT(*) FROM ip_quota WHERE day < $3)",
+            &[
+                &now(),
+                &window_start(DAILY_VIEWS_RETENTION_DAYS),
+                &window_start(QUOTA_RETENTION_DAYS),
+            ],
+        )?;
+        Ok(PurgeCounts {
+            expired_pastes: row.get::<_, i64>(0) as usize,
+            daily_views: row.get::<_, i64>(1) as usize,
+            quotas: row.get::<_, i64>(2) as usize,
+        })
+    }
+
+    fn blob_in_use(&self, hash: &str) -> StoreResult<bool> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        Ok(client
+            .query_opt("SELECT 1 FROM pastes WHERE blob = $1 LIMIT 1", &[&hash])?
+            .is_some())
+    }
+
+    fn blob_hashes(&self) -> StoreResult<Vec<String>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query("SELECT DISTINCT blob FROM pastes WHERE blob IS NOT NULL", &[])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn record_creation(&self, client: &str, pastes: i64) -> StoreResult<i64> {
+        let lock = self.conn();
+        let mut conn = lock.client.borrow_mut();
+        let row = conn.query_one(
+            "INSERT INTO ip_quota (client, day, pastes) VALUES ($1, $2, $3)
+             ON CONFLICT (client, day) DO UPDATE SET pastes = ip_quota.pastes + $3
+             RETURNING pastes",
+            &[&client, &day_string(today()), &pastes],
+        )?;
+        Ok(row.get(0))
+    }
+
+    fn creations_today(&self, client: &str) -> StoreResult<i64> {
+        let lock = self.conn();
+        let mut conn = lock.client.borrow_mut();
+        let row = conn.query_opt(
+            "SELECT pastes FROM ip_quota WHERE client = $1 AND day = $2",
+            &[&client, &day_string(today())],
+        )?;
+        Ok(row.map(|row| row.get(0)).unwrap_or(0))
+    }
+
+    fn prune_quotas(&self) -> StoreResult<usize> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let removed = client.execute(
+            "DELETE FROM ip_quota WHERE day < $1",
+            &[&window_start(QUOTA_RETENTION_DAYS)],
+        )?;
+        Ok(removed as usize)
+    }
+
+    fn record_audit(&self, entry: &AuditEntry) -> StoreResult<()> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        Ok(insert_audit(&mut *client, entry)?)
+    }
+
+    fn audit_log(&self, query: &AuditQuery) -> StoreResult<Vec<AuditRecord>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let rows = client.query(
+            "SELECT id, at, actor, action, target, ip_hash, detail FROM audit_log
+             WHERE ($1::TEXT IS NULL OR action = $1) AND ($2::BIGINT IS NULL OR at >= $2) AND ($3::BIGINT IS NULL OR at < $3)
+             ORDER BY id DESC
+             LIMIT $4",
+            &[&query.action, &query.since, &query.until, &query.limit],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| AuditRecord {
+                id: row.get(0),
+                at: row.get(1),
+                actor: row.get(2),
+                action: row.get(3),
+                target: row.get(4),
+                ip_hash: row.get(5),
+                detail: row.get(6),
+            })
+            .collect())
+    }
+
+    fn prune_audit_log(&self, before: i64) -> StoreResult<usize> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let removed = client.execute("DELETE FROM audit_log WHERE at < $1", &[&before])?;
+        Ok(removed as usize)
+    }
+
+    // Each term is a phrase of the `simple` configuration (no stemming, no stop words), all of them required.
+    // `ts_headline` marks the terms, without an ellipsis where it cut the content.
+    fn search(&self, query: &SearchQuery) -> StoreResult<Vec<SearchHit>> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let headline = format!(
+            "StartSel={}, StopSel={}, MaxWords={}, MinWords={}",
+            SNIPPET_START,
+            SNIPPET_END,
+            SNIPPET_WORDS,
+            SNIPPET_WORDS / 2
+        );
+        let rows = client.query(
+            "WITH q AS (
+                 SELECT string_agg('(' || phraseto_tsquery('simple', term)::TEXT || ')', ' & ')::TSQUERY AS query
+                 FROM unnest($1::TEXT[]) AS term
+             )
+             SELECT s.token, COALESCE(p.created_at, a.created_at), COALESCE(p.line_count, a.line_count),
+                    COALESCE(p.char_count, a.char_count), COALESCE(p.byte_size, a.byte_size),
+                    ts_headline('simple', s.content, q.query, $2), COALESCE(p.title, a.title)
+             FROM paste_search s
+             CROSS JOIN q
+             LEFT JOIN pastes p ON p.token = s.token
+             LEFT JOIN archived_pastes a ON a.token = s.token
+             WHERE to_tsvector('simple', s.content) @@ q.query
+               AND ((p.token IS NOT NULL AND (p.expires_at IS NULL OR p.expires_at > $3)) OR a.token IS NOT NULL)
+               AND ($4::TEXT IS NULL OR EXISTS (SELECT 1 FROM paste_tags g WHERE g.token = s.token AND g.tag = $4))
+               AND ($5::BIGINT IS NULL OR COALESCE(p.created_at, a.created_at) >= $5)
+               AND ($6::BIGINT IS NULL OR COALESCE(p.created_at, a.created_at) < $6)
+             ORDER BY ts_rank(to_tsvector('simple', s.content), q.query) DESC, s.token
+             LIMIT $7 OFFSET $8",
+            &[&query.terms, &headline, &now(), &query.tag, &query.since, &query.until, &query.limit, &query.offset],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| SearchHit {
+                token: row.get(0),
+                title: row.get(6),
+                created_at: row.get(1),
+                size: ContentSize::from_columns(row.get(2), row.get(3), row.get(4)),
+                snippet: row.get(5),
+            })
+            .collect())
+    }
+
+    // Contents are compressed while the lock is held, `ARCHIVE_ZSTD_LEVEL` keeps that short.
+    fn archive_idle(&self, idle_since: i64, limit: usize) -> StoreResult<usize> {
+        self.transaction(|tx| {
+            let idle = tx.query(
+                "SELECT token, content, data FROM pastes
+                 WHERE blob IS NULL AND expires_at IS NULL AND NOT pending AND COALESCE(last_viewed_at, created_at) < $1
+                 LIMIT $2",
+                &[&idle_since, &(limit as i64)],
+            )?;
+
+            for row in &idle {
+                let token: String = row.get(0);
+                let content: String = row.get(1);
+                let data: Option<Vec<u8>> = row.get(2);
+                let compressed = compress_content(&content, data.as_deref())?;
+                tx.execute(
+                    "INSERT INTO archived_pastes
+                         (token, secret, content, is_binary, public, views, created_at, line_count, char_count, byte_size, archived_at, redirect,
+                          content_hash, creator, comments, title, title_auto)
+                     SELECT token, secret, $1, data IS NOT NULL, public, views, created_at, line_count, char_count, byte_size, $2, redirect,
+                            content_hash, creator, comments, title, title_auto
+                     FROM pastes WHERE token = $3",
+                    &[&compressed, &now(), &token],
+                )?;
+                tx.execute("DELETE FROM pastes WHERE token = $1", &[&token])?;
+            }
+            Ok(idle.len())
+        })
+    }
+
+    // The paste comes back as just viewed, so it isn't archived again right away.
+    fn unarchive(&self, token: &str) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let paste = match get_archived(tx, token)? {
+                Some(paste) => paste,
+                None => return Ok(false),
+            };
+            let size = paste.size.unwrap_or_default();
+            tx.execute(
+                "INSERT INTO pastes
+                     (token, secret, content, public, views, created_at, data, line_count, char_count, byte_size, last_viewed_at, redirect,
+                      content_hash, creator, comments, title, title_auto)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)",
+                &[
+                    &paste.token,
+                    &paste.secret,
+                    &paste.content,
+                    &paste.public,
+                    &paste.views,
+                    &paste.created_at,
+                    &paste.data,
+                    &size.lines,
+                    &size.chars,
+                    &size.bytes,
+                    &now(),
+                    &paste.redirect,
+                    &paste.content_hash,
+                    &paste.creator,
+                    &paste.comments,
+                    &paste.title,
+                    &paste.title_auto,
+                ],
+            )?;
+            tx.execute("DELETE FROM archived_pastes WHERE token = $1", &[&token])?;
+            Ok(true)
+        })
+    }
+
+    fn archive_stats(&self) -> StoreResult<ArchiveStats> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let hot = client.query_one("SELECT COUNT(*), COALESCE(SUM(byte_size), 0)::BIGINT FROM pastes", &[])?;
+        let archived = client.query_one(
+            "SELECT COUNT(*), COALESCE(SUM(byte_size), 0)::BIGINT, COALESCE(SUM(octet_length(content)), 0)::BIGINT
+             FROM archived_pastes",
+            &[],
+        )?;
+        Ok(ArchiveStats {
+            hot_pastes: hot.get(0),
+            hot_bytes: hot.get(1),
+            archived_pastes: archived.get(0),
+            archived_bytes: archived.get(1),
+            archived_stored_bytes: archived.get(2),
+        })
+    }
+
+    // Postgres has no single file or WAL size to report, and its pages are per table, so only the total size is given.
+    fn db_stats(&self) -> StoreResult<DbStats> {
+        let conn = self.conn();
+        let mut client = conn.client.borrow_mut();
+        let names: Vec<String> = client
+            .query(
+                "SELECT tablename::TEXT FROM pg_tables WHERE schemaname = current_schema() ORDER BY tablename",
+                &[],
+            )?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        let mut tables = Vec::with_capacity(names.len());
+        for table in names {
+            let row = client.query_one(&*format!("SELECT COUNT(*) FROM \"{}\"", table.replace('"', "\"\"")), &[])?;
+            tables.push(TableRows { table, rows: row.get(0) });
+        }
+
+        let largest_pastes = client
+            .query(
+                "SELECT token, byte_size FROM pastes WHERE byte_size IS NOT NULL ORDER BY byte_size DESC LIMIT $1",
+                &[&DB_STATS_TOP],
+            )?
+            .iter()
+            .map(|row| PasteBytes {
+                token: row.get(0),
+                bytes: row.get(1),
+            })
+            .collect();
+        let indexes = client
+            .query(
+                "SELECT relname::TEXT, indexrelname::TEXT, idx_scan FROM pg_stat_user_indexes
+                 WHERE schemaname = current_schema() ORDER BY relname, indexrelname",
+                &[],
+            )?
+            .iter()
+            .map(|row| IndexUsage {
+                table: row.get(0),
+                index: row.get(1),
+                scans: row.get(2),
+            })
+            .collect();
+        let file_bytes = client.query_one("SELECT pg_database_size(current_database())", &[])?.get(0);
+
+        Ok(DbStats {
+            tables,
+            file_bytes: Some(file_bytes),
+            largest_pastes,
+            indexes,
+            ..DbStats::default()
+        })
+    }
+
+    fn sqlite_path(&self) -> Option<&Path> {
+        None
+    }
+}
@@ -0,0 +1,1624 @@
+// The SQLite implementation of `PasteStore`, the default backend.
+// Everything that touches the layout of `pastes.db` lives in this file.
+// The schema is versioned through SQLite's `PRAGMA user_version`, every entry of
+// `MIGRATIONS` moves the database one version forward, so an old `pastes.db`
+// created by a previous release gets upgraded in place on startup.
+
+use super::{
+    compress_content, decompress_content, fill_days, hash_prefix_end, now, today, day_string, window_start,
+    Announcement, ApiToken, ArchiveStats, AuditEntry, AuditQuery, AuditRecord, Ban, Collection, Comment, ContentSize,
+    CreatedPaste, DbStats, GistMirror, GistState, HashMatch, IndexUsage, InstanceStats, ListedPaste, NewAnnouncement,
+    NewApiToken, NewBan, NewCollection, NewComment, NewPaste, OrphanScan, Orphans, Paste, PasteBytes, PasteStore,
+    PendingPaste, PurgeCounts, SearchHit, SearchQuery, StoreResult, TableRows,
+    DAILY_VIEWS_RETENTION_DAYS, DB_STATS_TOP, QUOTA_RETENTION_DAYS, SNIPPET_ELLIPSIS, SNIPPET_END, SNIPPET_START,
+    SNIPPET_WORDS,
+};
+use rand::Rng;
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Each migration is a batch of SQL statements, applied in order.
+// Never edit an existing entry, only append new ones at the end.
+const MIGRATIONS: &[&str] = &[
+    // 1: the original table
+    "CREATE TABLE IF NOT EXISTS pastes (token TEXT PRIMARY KEY, content TEXT);",
+    // 2: view counters, public listing flag and per-day view tracking
+    "ALTER TABLE pastes ADD COLUMN views INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE pastes ADD COLUMN public INTEGER NOT NULL DEFAULT 0;
+     CREATE TABLE IF NOT EXISTS paste_views_daily (
+         token TEXT NOT NULL,
+         day TEXT NOT NULL,
+         views INTEGER NOT NULL DEFAULT 0,
+         PRIMARY KEY (token, day)
+     );",
+    // 3: tags, stored normalized (lowercase) in a join table
+    "CREATE TABLE IF NOT EXISTS paste_tags (
+         token TEXT NOT NULL,
+         tag TEXT NOT NULL,
+         PRIMARY KEY (token, tag)
+     );
+     CREATE INDEX IF NOT EXISTS paste_tags_tag ON paste_tags (tag);",
+    // 4: secret handed to the creator of a paste, unlocks its private pages
+    "ALTER TABLE pastes ADD COLUMN secret TEXT;",
+    // 5: creation and expiry timestamps (Unix seconds), rows from before this have a creation time of 0
+    "ALTER TABLE pastes ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE pastes ADD COLUMN expires_at INTEGER;
+     CREATE INDEX IF NOT EXISTS pastes_expires_at ON pastes (expires_at);",
+    // 6: large contents live in blob files, the row keeps the hash of the file
+    "ALTER TABLE pastes ADD COLUMN blob TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_blob ON pastes (blob);",
+    // 7: binary pastes keep their bytes here, with an empty `content`
+    "ALTER TABLE pastes ADD COLUMN data BLOB;",
+    // 8: line, character and byte counts, computed for existing rows except blob ones (done on their first read).
+    // A last line without a trailing newline still counts as a line
+    "ALTER TABLE pastes ADD COLUMN line_count INTEGER;
+     ALTER TABLE pastes ADD COLUMN char_count INTEGER;
+     ALTER TABLE pastes ADD COLUMN byte_size INTEGER;
+     UPDATE pastes SET
+         line_count = CASE WHEN data IS NOT NULL THEN 0 ELSE
+             length(COALESCE(content, '')) - length(replace(COALESCE(content, ''), char(10), ''))
+             + (COALESCE(content, '') <> '' AND substr(content, -1) <> char(10)) END,
+         char_count = CASE WHEN data IS NOT NULL THEN 0 ELSE length(COALESCE(content, '')) END,
+         byte_size = CASE WHEN data IS NOT NULL THEN length(data) ELSE length(CAST(COALESCE(content, '') AS BLOB)) END
+     WHERE blob IS NULL;",
+    // 9: time of the last view, taken from the daily views still around for existing rows,
+    // and the archive of idle pastes with their content compressed with zstd
+    "ALTER TABLE pastes ADD COLUMN last_viewed_at INTEGER;
+     UPDATE pastes SET last_viewed_at =
+         (SELECT CAST(strftime('%s', MAX(d.day)) AS INTEGER) FROM paste_views_daily d WHERE d.token = pastes.token);
+     CREATE TABLE IF NOT EXISTS archived_pastes (
+         token TEXT PRIMARY KEY,
+         secret TEXT,
+         content BLOB NOT NULL,
+         is_binary INTEGER NOT NULL,
+         public INTEGER NOT NULL,
+         views INTEGER NOT NULL,
+         created_at INTEGER NOT NULL,
+         line_count INTEGER,
+         char_count INTEGER,
+         byte_size INTEGER,
+         archived_at INTEGER NOT NULL
+     );",
+    // 10: pastes created per client (a salted hash of its IP) and day, for the daily quota
+    "CREATE TABLE IF NOT EXISTS ip_quota (
+         client TEXT NOT NULL,
+         day TEXT NOT NULL,
+         pastes INTEGER NOT NULL DEFAULT 0,
+         PRIMARY KEY (client, day)
+     );",
+    // 11: short links, whose content is the URL they redirect to
+    "ALTER TABLE pastes ADD COLUMN redirect INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE archived_pastes ADD COLUMN redirect INTEGER NOT NULL DEFAULT 0;",
+    // 12: the largest pastes of the admin database page, without reading every row
+    "CREATE INDEX IF NOT EXISTS pastes_byte_size ON pastes (byte_size);",
+    // 13: mirrors of pastes to GitHub gists, kept apart so archiving a paste doesn't have to carry them
+    "CREATE TABLE IF NOT EXISTS paste_gists (
+         token TEXT PRIMARY KEY,
+         status TEXT NOT NULL,
+         url TEXT,
+         error TEXT,
+         updated_at INTEGER NOT NULL
+     );",
+    // 14: SHA-256 of the content, for “/h/{hash}”; rows from before get theirs when they are next read
+    "ALTER TABLE pastes ADD COLUMN content_hash TEXT;
+     ALTER TABLE archived_pastes ADD COLUMN content_hash TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_content_hash ON pastes (content_hash);
+     CREATE INDEX IF NOT EXISTS archived_pastes_content_hash ON archived_pastes (content_hash);",
+    // 15: the audit log of deletions, replacements, purges and reloads
+    "CREATE TABLE IF NOT EXISTS audit_log (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         at INTEGER NOT NULL,
+         actor TEXT NOT NULL,
+         action TEXT NOT NULL,
+         target TEXT,
+         ip_hash TEXT,
+         detail TEXT NOT NULL DEFAULT ''
+     );
+     CREATE INDEX IF NOT EXISTS audit_log_at ON audit_log (at);",
+    // 16: full-text index of the public text pastes (FTS5) for “/search”, filled with the hot ones.
+    // Its rowids are the ids of `paste_search_tokens`, which ties them to a token that stays the same when a paste
+    // is archived; pastes archived before this version are compressed and not indexed
+    "CREATE TABLE IF NOT EXISTS paste_search_tokens (
+         id INTEGER PRIMARY KEY,
+         token TEXT NOT NULL UNIQUE
+     );
+     CREATE VIRTUAL TABLE IF NOT EXISTS paste_search USING fts5 (content, tokenize = 'unicode61 remove_diacritics 2');
+     INSERT INTO paste_search_tokens (token)
+         SELECT token FROM pastes WHERE public AND data IS NULL AND NOT redirect;
+     INSERT INTO paste_search (rowid, content)
+         SELECT t.id, COALESCE(p.content, '') FROM paste_search_tokens t JOIN pastes p ON p.token = t.token;",
+    // 17: the hashed creator cookie of the pastes submitted with one, for “/mine”
+    "ALTER TABLE pastes ADD COLUMN creator TEXT;
+     ALTER TABLE archived_pastes ADD COLUMN creator TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_creator ON pastes (creator);
+     CREATE INDEX IF NOT EXISTS archived_pastes_creator ON archived_pastes (creator);",
+    // 18: comments on pastes, and whether a paste takes them. `token` isn't a foreign key since the paste may be
+    // in either table, `delete_paste` removes the comments of a paste with it
+    "CREATE TABLE IF NOT EXISTS comments (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         token TEXT NOT NULL,
+         author TEXT NOT NULL,
+         body TEXT NOT NULL,
+         created_at INTEGER NOT NULL
+     );
+     CREATE INDEX IF NOT EXISTS comments_token ON comments (token);
+     ALTER TABLE pastes ADD COLUMN comments INTEGER NOT NULL DEFAULT 1;
+     ALTER TABLE archived_pastes ADD COLUMN comments INTEGER NOT NULL DEFAULT 1;",
+    // 19: API tokens with their scopes, a row without scopes may use all of them
+    "CREATE TABLE IF NOT EXISTS api_tokens (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         label TEXT NOT NULL,
+         token_hash TEXT NOT NULL UNIQUE,
+         scopes TEXT NOT NULL DEFAULT 'read,write,delete,admin',
+         created_at INTEGER NOT NULL,
+         expires_at INTEGER,
+         last_used_at INTEGER,
+         revoked_at INTEGER
+     );",
+    // 20: banned addresses and ranges, see `bans.rs`
+    "CREATE TABLE IF NOT EXISTS banned_ips (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         ip_hash TEXT,
+         cidr TEXT,
+         reason TEXT NOT NULL,
+         created_at INTEGER NOT NULL,
+         expires_at INTEGER,
+         created_by TEXT NOT NULL
+     );",
+    // 21: collections of pastes, see `collections.rs`. Their items name pastes by token without a foreign key,
+    // a paste deleted from a collection's list shows as unavailable
+    "CREATE TABLE IF NOT EXISTS collections (
+         token TEXT PRIMARY KEY,
+         secret TEXT NOT NULL,
+         title TEXT NOT NULL DEFAULT '',
+         created_at INTEGER NOT NULL,
+         updated_at INTEGER NOT NULL,
+         expires_at INTEGER
+     );
+     CREATE TABLE IF NOT EXISTS collection_items (
+         collection TEXT NOT NULL,
+         position INTEGER NOT NULL,
+         paste TEXT NOT NULL,
+         PRIMARY KEY (collection, position)
+     );",
+    // 22: the last report of each instance sending its stats here, see `federation.rs`
+    "CREATE TABLE IF NOT EXISTS instance_stats (
+         instance TEXT PRIMARY KEY,
+         version TEXT NOT NULL,
+         backend TEXT NOT NULL,
+         pastes INTEGER NOT NULL,
+         archived_pastes INTEGER NOT NULL,
+         content_bytes INTEGER NOT NULL,
+         stored_bytes INTEGER NOT NULL,
+         received_at INTEGER NOT NULL
+     );",
+    // 23: pastes held for review, with the address they came from while they are, see `review.rs`
+    "ALTER TABLE pastes ADD COLUMN pending INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE pastes ADD COLUMN submitter TEXT;
+     CREATE INDEX IF NOT EXISTS pastes_pending ON pastes (created_at) WHERE pending;",
+    // 24: announcements shown on the pages of the instance, see `announcements.rs`
+    "CREATE TABLE IF NOT EXISTS announcements (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         message TEXT NOT NULL,
+         level TEXT NOT NULL DEFAULT 'info',
+         starts_at INTEGER NOT NULL,
+         ends_at INTEGER,
+         created_at INTEGER NOT NULL,
+         created_by TEXT NOT NULL
+     );",
+    // 25: titles of pastes, and whether each was made from the content, see `title.rs`
+    "ALTER TABLE pastes ADD COLUMN title TEXT NOT NULL DEFAULT '';
+     ALTER TABLE pastes ADD COLUMN title_auto INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE archived_pastes ADD COLUMN title TEXT NOT NULL DEFAULT '';
+     ALTER TABLE archived_pastes ADD COLUMN title_auto INTEGER NOT NULL DEFAULT 0;",
+    // 26: the daily quota of pastes of an API token, see `check_daily_quota`
+    "ALTER TABLE api_tokens ADD COLUMN daily_quota INTEGER;",
+];
+
+// The version `migrate` brings a database to.
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+// The `user_version` of the database at `path`, read without migrating it; `None` when the file doesn't exist.
+pub fn schema_version(path: &Path) -> rusqlite::Result<Option<usize>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    Ok(Some(conn.query_row("PRAGMA user_version", params![], |row| row.get(0))?))
+}
+
+// Whether the SQLite library has FTS5, which the search index needs.
+pub fn has_fts5() -> bool {
+    Connection::open_in_memory()
+        .and_then(|conn| conn.execute_batch("CREATE VIRTUAL TABLE probe USING fts5 (content)"))
+        .is_ok()
+}
+
+// Pauses between the attempts of a write that found the database locked, see `SqliteStore::write`:
+// doubling from the first up to the last, each one cut by a random part of up to half,
+// so writers that failed together don't all try again at the same moment.
+const RETRY_FIRST_PAUSE: Duration = Duration::from_millis(10);
+const RETRY_MAX_PAUSE: Duration = Duration::from_millis(250);
+
+// Most problems `check_database` lists.
+const CHECK_PROBLEMS: usize = 100;
+
+// The connection lives behind a lock only one thread holds at a time, which that thread can take again: the calls
+// of `with_tx` run on the connection it holds, inside its transaction.
+pub struct SqliteStore {
+    conn: ReentrantMutex<Connection>,
+    path: PathBuf,
+    write_retry: Duration,
+}
+
+impl SqliteStore {
+    // Opens the file, checks it can be written and migrates it.
+    // A call that finds the database locked by another connection waits up to `busy_timeout`, then fails;
+    // writes are then tried again for up to `write_retry`.
+    pub fn open(path: &Path, busy_timeout: Duration, write_retry: Duration) -> rusqlite::Result<SqliteStore> {
+        let mut conn = Connection::open(path)?;
+        conn.busy_timeout(busy_timeout)?;
+        check_writable(&conn)?;
+        migrate(&mut conn)?;
+        Ok(SqliteStore {
+            conn: ReentrantMutex::new(conn),
+            path: path.to_path_buf(),
+            write_retry,
+        })
+    }
+
+    // The connection, also after a thread panicked holding it: its `Savepoint` rolled back what it was writing, so
+    // the others go on using it with no transaction left open.
+    fn conn(&self) -> ReentrantMutexGuard<'_, Connection> {
+        self.conn.lock()
+    }
+
+    // Runs the write `op`, again after a pause as long as it fails with the database busy or locked by another
+    // connection (a purge or a backup run next to the server, say) and `write_retry` hasn't passed since the first
+    // attempt; then its error is returned, which the handlers answer with a 503.
+    // The connection is released during the pauses. `op` must write all or nothing, in one statement or transaction,
+    // so running it again can't apply it twice. Inside a transaction, that of `with_tx`, `op` runs once and its error
+    // goes up to the outermost one, which is what runs again.
+    // The pauses block the calling thread: the server makes its writes on the blocking pool (see `store_write` in
+    // `main.rs`), not on the workers.
+    fn write<T>(&self, mut op: impl FnMut(&Connection) -> StoreResult<T>) -> StoreResult<T> {
+        let deadline = Instant::now() + self.write_retry;
+        let mut pause = RETRY_FIRST_PAUSE;
+        loop {
+            let conn = self.conn();
+            let result = op(&conn);
+            let outermost = conn.is_autocommit();
+            drop(conn);
+            match result {
+                Err(e) if e.is_busy() && outermost && Instant::now() + pause < deadline => {
+                    thread::sleep(pause.mul_f64(rand::thread_rng().gen_range(0.5..=1.0)));
+                    pause = (pause * 2).min(RETRY_MAX_PAUSE);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Runs `op` as one transaction through `write`: committed when it returns `Ok`, rolled back with every row it
+    // wrote when it fails at any statement, so that an operation is stored whole or not at all. Every operation
+    // writing more than one row goes through here: a paste with its tags and search entry, an archived one moved
+    // between the tables, a purge…, and the audit entry of an operation with the operation. Inside another
+    // transaction, it is a savepoint of that one.
+    fn transaction<T>(&self, mut op: impl FnMut(&Connection) -> StoreResult<T>) -> StoreResult<T> {
+        self.write(|conn| {
+            let savepoint = Savepoint::new(conn)?;
+            let result = op(conn)?;
+            savepoint.release()?;
+            Ok(result)
+        })
+    }
+}
+
+// A savepoint of the connection, the transaction itself when there is none yet. One dropped without being released,
+// on an error or a panic, is rolled back, so the connection never stays in a transaction.
+struct Savepoint<'a> {
+    conn: &'a Connection,
+    outermost: bool,
+}
+
+impl<'a> Savepoint<'a> {
+    fn new(conn: &'a Connection) -> rusqlite::Result<Savepoint<'a>> {
+        let outermost = conn.is_autocommit();
+        conn.execute_batch("SAVEPOINT store")?;
+        Ok(Savepoint { conn, outermost })
+    }
+
+    fn release(self) -> rusqlite::Result<()> {
+        // Releasing the outermost savepoint commits, which can fail with the database busy and the transaction open
+        self.conn.execute_batch("RELEASE store")?;
+        std::mem::forget(self);
+        Ok(())
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        let rollback = if self.outermost { "ROLLBACK" } else { "ROLLBACK TO store; RELEASE store" };
+        let _ = self.conn.execute_batch(rollback);
+    }
+}
+
+// SQLite quietly opens a file on a read-only mount in read-only mode, which would only show up on the first submit.
+// Rewriting `user_version` with its own value is a harmless write that fails right away in that case.
+fn check_writable(conn: &Connection) -> rusqlite::Result<()> {
+    let current: i64 = conn.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+    conn.execute_batch(&format!("PRAGMA user_version = {}", current))
+}
+
+// Brings the database up to the latest schema version.
+// Reads the current `user_version`, runs every migration after it inside one transaction each
+// and bumps the version so the same migration never runs twice.
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let current: usize = conn.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+// The table and the token column of the rows of `kind`.
+fn orphan_rows(kind: Orphans) -> (&'static str, &'static str) {
+    match kind {
+        Orphans::SearchIndex => ("paste_search_tokens", "token"),
+        Orphans::Tags => ("paste_tags", "token"),
+        Orphans::DailyViews => ("paste_views_daily", "token"),
+        Orphans::Comments => ("comments", "token"),
+        Orphans::Gists => ("paste_gists", "token"),
+        Orphans::CollectionItems => ("collection_items", "collection"),
+    }
+}
+
+// Whether the paste (or the collection) of the rows of `kind` of the token `token`, an SQL expression, is there.
+fn orphan_owner_exists(kind: Orphans, token: &str) -> String {
+    match kind {
+        Orphans::CollectionItems => format!("EXISTS (SELECT 1 FROM collections WHERE token = {})", token),
+        _ => format!(
+            "EXISTS (SELECT 1 FROM pastes WHERE token = {0}) OR EXISTS (SELECT 1 FROM archived_pastes WHERE token = {0})",
+            token
+        ),
+    }
+}
+
+// Inserts the pastes of a collection, numbered from 0 in their order.
+fn insert_collection_items(conn: &Connection, collection: &str, pastes: &[String]) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("INSERT INTO collection_items (collection, position, paste) VALUES (?, ?, ?)")?;
+    for (position, paste) in pastes.iter().enumerate() {
+        stmt.execute(params![collection, position as i64, paste])?;
+    }
+    Ok(())
+}
+
+// Inserts a new paste and its tags into the hot table.
+fn insert_paste(conn: &Connection, paste: &NewPaste) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO pastes
+             (token, secret, content, public, created_at, expires_at, blob, data, line_count, char_count, byte_size, redirect,
+              content_hash, creator, comments, pending, submitter, title, title_auto)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            &paste.token,
+            &paste.secret,
+            &paste.content,
+            paste.public,
+            now(),
+            paste.expires_at,
+            &paste.blob,
+            &paste.data,
+            paste.size.lines,
+            paste.size.chars,
+            paste.size.bytes,
+            paste.redirect,
+            &paste.content_hash,
+            &paste.creator,
+            paste.comments,
+            paste.pending,
+            &paste.submitter,
+            &paste.title,
+            paste.title_auto,
+        ],
+    )?;
+    for tag in &paste.tags {
+        conn.execute(
+            "INSERT INTO paste_tags (token, tag) VALUES (?, ?)",
+            params![&paste.token, tag],
+        )?;
+    }
+    // A pending paste is indexed when it is approved
+    if paste.public && paste.data.is_none() && !paste.redirect && !paste.pending {
+        index_paste(conn, &paste.token)?;
+    }
+    Ok(())
+}
+
+// Adds the content of the row of a paste to the search index, for “/search”.
+fn index_paste(conn: &Connection, token: &str) -> rusqlite::Result<()> {
+    conn.execute("INSERT INTO paste_search_tokens (token) VALUES (?)", params![token])?;
+    conn.execute(
+        "INSERT INTO paste_search (rowid, content) SELECT last_insert_rowid(), content FROM pastes WHERE token = ?",
+        params![token],
+    )?;
+    Ok(())
+}
+
+// Deletes a paste, hot or archived, and the rows attached to it.
+fn delete_paste(conn: &Connection, token: &str) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM paste_tags WHERE token = ?", params![token])?;
+    conn.execute("DELETE FROM paste_views_daily WHERE token = ?", params![token])?;
+    conn.execute("DELETE FROM paste_gists WHERE token = ?", params![token])?;
+    conn.execute("DELETE FROM comments WHERE token = ?", params![token])?;
+    conn.execute(
+        "DELETE FROM paste_search WHERE rowid = (SELECT id FROM paste_search_tokens WHERE token = ?)",
+        params![token],
+    )?;
+    conn.execute("DELETE FROM paste_search_tokens WHERE token = ?", params![token])?;
+    let archived = conn.execute("DELETE FROM archived_pastes WHERE token = ?", params![token])?;
+    Ok(conn.execute("DELETE FROM pastes WHERE token = ?", params![token])? + archived)
+}
+
+// The terms of a search as an FTS5 query: each one a quoted phrase, so that nothing typed is read as an operator,
+// and all of them required.
+fn fts_query(terms: &[String]) -> String {
+    terms
+        .iter()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn insert_audit(conn: &Connection, entry: &AuditEntry) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO audit_log (at, actor, action, target, ip_hash, detail) VALUES (?, ?, ?, ?, ?, ?)",
+        params![now(), entry.actor, entry.action, &entry.target, &entry.ip_hash, &entry.detail],
+    )?;
+    Ok(())
+}
+
+// The columns `announcement_row` reads.
+const ANNOUNCEMENT_COLUMNS: &str = "id, message, level, starts_at, ends_at, created_at, created_by";
+
+fn announcement_row(row: &rusqlite::Row) -> rusqlite::Result<Announcement> {
+    Ok(Announcement {
+        id: row.get(0)?,
+        message: row.get(1)?,
+        level: row.get(2)?,
+        starts_at: row.get(3)?,
+        ends_at: row.get(4)?,
+        created_at: row.get(5)?,
+        created_by: row.get(6)?,
+    })
+}
+
+// Reads an archived paste, decompressing its content.
+// The columns `api_token_row` reads.
+const API_TOKEN_COLUMNS: &str = "id, label, scopes, created_at, expires_at, last_used_at, revoked_at, daily_quota";
+
+fn api_token_row(row: &rusqlite::Row) -> rusqlite::Result<ApiToken> {
+    Ok(ApiToken {
+        id: row.get(0)?,
+        label: row.get(1)?,
+        scopes: row.get(2)?,
+        created_at: row.get(3)?,
+        expires_at: row.get(4)?,
+        last_used_at: row.get(5)?,
+        revoked_at: row.get(6)?,
+        daily_quota: row.get(7)?,
+    })
+}
+
+// The columns `ban_row` reads.
+const BAN_COLUMNS: &str = "id, ip_hash, cidr, reason, created_at, expires_at, created_by";
+
+fn ban_row(row: &rusqlite::Row) -> rusqlite::Result<Ban> {
+    Ok(Ban {
+        id: row.get(0)?,
+        ip_hash: row.get(1)?,
+        cidr: row.get(2)?,
+        reason: row.get(3)?,
+        created_at: row.get(4)?,
+        expires_at: row.get(5)?,
+        created_by: row.get(6)?,
+    })
+}
+
+fn get_archived(conn: &Connection, token: &str) -> StoreResult<Option<Paste>> {
+    let row = conn
+        .query_row(
+            "SELECT token, COALESCE(secret, ''), content, is_binary, public, views, created_at, line_count, char_count, byte_size,
+                    redirect, content_hash, creator, comments, title, title_auto
+             FROM archived_pastes
+             WHERE token = ?",
+            params![token],
+            |row| {
+                let paste = Paste {
+                    token: row.get(0)?,
+                    secret: row.get(1)?,
+                    content: String::new(),
+                    public: row.get(4)?,
+                    views: row.get(5)?,
+                    created_at: row.get(6)?,
+                    expires_at: None,
+                    blob: None,
+                    data: None,
+                    size: ContentSize::from_columns(row.get(7)?, row.get(8)?, row.get(9)?),
+                    archived: true,
+                    redirect: row.get(10)?,
+                    content_hash: row.get(11)?,
+                    creator: row.get(12)?,
+                    comments: row.get(13)?,
+                    pending: false,
+                    title: row.get(14)?,
+                    title_auto: row.get(15)?,
+                };
+                Ok((paste, row.get::<_, Vec<u8>>(2)?, row.get::<_, bool>(3)?))
+            },
+        )
+        .optional()?;
+
+    match row {
+        Some((mut paste, compressed, binary)) => {
+            let (content, data) = decompress_content(&compressed, binary)?;
+            paste.content = content;
+            paste.data = data;
+            Ok(Some(paste))
+        }
+        None => Ok(None),
+    }
+}
+
+impl PasteStore for SqliteStore {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn ping(&self) -> StoreResult<()> {
+        let conn = self.conn();
+        conn.query_row("SELECT 1", params![], |row| row.get::<_, i64>(0))?;
+        Ok(())
+    }
+
+    fn with_tx(&self, op: &mut dyn FnMut() -> StoreResult<()>) -> StoreResult<()> {
+        self.transaction(|_| op())
+    }
+
+    // At most `CHECK_PROBLEMS` lines, SQLite stops there.
+    fn check_database(&self, full: bool) -> StoreResult<Vec<String>> {
+        let pragma = if full { "integrity_check" } else { "quick_check" };
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!("PRAGMA {}({})", pragma, CHECK_PROBLEMS))?;
+        let lines = stmt
+            .query_map(params![], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(lines.into_iter().filter(|line| line != "ok").collect())
+    }
+
+    fn insert_batch(&self, pastes: &[NewPaste]) -> StoreResult<()> {
+        self.transaction(|tx| {
+            for paste in pastes {
+                insert_paste(tx, paste)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get(&self, token: &str) -> StoreResult<Option<Paste>> {
+        let conn = self.conn();
+        let paste = conn
+            .query_row(
+                "SELECT token, COALESCE(secret, ''), COALESCE(content, ''), public, views, created_at, expires_at, blob, data,
+                        line_count, char_count, byte_size, redirect, content_hash, creator, comments, pending, title,
+                        title_auto
+                 FROM pastes
+                 WHERE token = ? AND (expires_at IS NULL OR expires_at > ?)",
+                params![token, now()],
+                |row| {
+                    Ok(Paste {
+                        token: row.get(0)?,
+                        secret: row.get(1)?,
+                        content: row.get(2)?,
+                        public: row.get(3)?,
+                        views: row.get(4)?,
+                        created_at: row.get(5)?,
+                        expires_at: row.get(6)?,
+                        blob: row.get(7)?,
+                        data: row.get(8)?,
+                        size: ContentSize::from_columns(row.get(9)?, row.get(10)?, row.get(11)?),
+                        archived: false,
+                        redirect: row.get(12)?,
+                        content_hash: row.get(13)?,
+                        creator: row.get(14)?,
+                        comments: row.get(15)?,
+                        pending: row.get(16)?,
+                        title: row.get(17)?,
+                        title_auto: row.get(18)?,
+                    })
+                },
+            )
+            .optional()?;
+        match paste {
+            Some(paste) => Ok(Some(paste)),
+            None => get_archived(&conn, token),
+        }
+    }
+
+    fn set_size(&self, token: &str, size: ContentSize) -> StoreResult<()> {
+        self.write(|conn| {
+            conn.execute(
+                "UPDATE pastes SET line_count = ?, char_count = ?, byte_size = ? WHERE token = ?",
+                params![size.lines, size.chars, size.bytes, token],
+            )?;
+            conn.execute(
+                "UPDATE archived_pastes SET line_count = ?, char_count = ?, byte_size = ? WHERE token = ?",
+                params![size.lines, size.chars, size.bytes, token],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn set_content_hash(&self, token: &str, hash: &str) -> StoreResult<()> {
+        self.write(|conn| {
+            conn.execute("UPDATE pastes SET content_hash = ? WHERE token = ?", params![hash, token])?;
+            conn.execute("UPDATE archived_pastes SET content_hash = ? WHERE token = ?", params![hash, token])?;
+            Ok(())
+        })
+    }
+
+    // SQLite takes the token of the row with the `MIN` of its group.
+    fn find_by_hash(&self, prefix: &str, limit: i64) -> StoreResult<Vec<HashMatch>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT content_hash, token, MIN(created_at) FROM (
+                 SELECT content_hash, token, created_at FROM pastes
+                 WHERE content_hash >= ?1 AND content_hash < ?2 AND public AND NOT pending
+                   AND (expires_at IS NULL OR expires_at > ?3)
+                 UNION ALL
+                 SELECT content_hash, token, created_at FROM archived_pastes
+                 WHERE content_hash >= ?1 AND content_hash < ?2 AND public
+             )
+             GROUP BY content_hash
+             ORDER BY content_hash
+             LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(params![prefix, hash_prefix_end(prefix), now(), limit], |row| {
+            Ok(HashMatch {
+                hash: row.get(0)?,
+                token: row.get(1)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn exists(&self, token: &str) -> StoreResult<bool> {
+        let conn = self.conn();
+        let exists = conn
+            .query_row(
+                "SELECT 1 FROM pastes WHERE token = ?1 UNION ALL SELECT 1 FROM archived_pastes WHERE token = ?1",
+                params![token],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        Ok(exists)
+    }
+
+    fn delete(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let deleted = delete_paste(tx, token)?;
+            if let Some(entry) = audit.filter(|_| deleted > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(deleted > 0)
+        })
+    }
+
+    fn replace(&self, paste: &NewPaste, audit: Option<&AuditEntry>) -> StoreResult<()> {
+        self.transaction(|tx| {
+            delete_paste(tx, &paste.token)?;
+            insert_paste(tx, paste)?;
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn pending_pastes(&self, limit: i64) -> StoreResult<Vec<PendingPaste>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT token, substr(content, 1, 100), COALESCE(byte_size, 0), public, redirect, data IS NOT NULL, created_at,
+                    expires_at, submitter
+             FROM pastes
+             WHERE pending AND (expires_at IS NULL OR expires_at > ?)
+             ORDER BY created_at, token
+             LIMIT ?",
+        )?;
+        let rows = stmt.query_map(params![now(), limit], |row| {
+            Ok(PendingPaste {
+                token: row.get(0)?,
+                preview: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                bytes: row.get(2)?,
+                public: row.get(3)?,
+                redirect: row.get(4)?,
+                binary: row.get(5)?,
+                created_at: row.get(6)?,
+                expires_at: row.get(7)?,
+                submitter: row.get(8)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn approve(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let index = tx
+                .query_row(
+                    "SELECT public AND data IS NULL AND NOT redirect FROM pastes WHERE token = ? AND pending",
+                    params![token],
+                    |row| row.get::<_, bool>(0),
+                )
+                .optional()?;
+            let index = match index {
+                Some(index) => index,
+                None => return Ok(false),
+            };
+            tx.execute("UPDATE pastes SET pending = 0, submitter = NULL WHERE token = ?", params![token])?;
+            if index {
+                index_paste(tx, token)?;
+            }
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn reject(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<Option<Option<String>>> {
+        self.transaction(|tx| {
+            let submitter = tx
+                .query_row(
+                    "SELECT submitter FROM pastes WHERE token = ? AND pending",
+                    params![token],
+                    |row| row.get::<_, Option<String>>(0),
+                )
+                .optional()?;
+            if submitter.is_none() {
+                return Ok(None);
+            }
+            delete_paste(tx, token)?;
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(submitter)
+        })
+    }
+
+    fn record_view(&self, token: &str) -> StoreResult<()> {
+        self.transaction(|tx| {
+            // In one transaction, so that a retry can't count the view twice
+            tx.execute(
+                "UPDATE pastes SET views = views + 1, last_viewed_at = ? WHERE token = ?",
+                params![now(), token],
+            )?;
+            tx.execute(
+                "UPDATE archived_pastes SET views = views + 1 WHERE token = ?",
+                params![token],
+            )?;
+            tx.execute(
+                "INSERT INTO paste_views_daily (token, day, views) VALUES (?, ?, 1)
+                 ON CONFLICT(token, day) DO UPDATE SET views = views + 1",
+                params![token, day_string(today())],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn tags(&self, token: &str) -> StoreResult<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT tag FROM paste_tags WHERE token = ? ORDER BY tag")?;
+        let rows = stmt.query_map(params![token], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn list_popular(&self, days: i64, tag: Option<&str>, limit: i64) -> StoreResult<Vec<ListedPaste>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT p.token, substr(p.content, 1, 100), SUM(d.views) AS window_views,
+                    p.line_count, p.char_count, p.byte_size, p.title
+             FROM paste_views_daily d
+             JOIN pastes p ON p.token = d.token
+             WHERE p.public = 1 AND NOT p.pending AND d.day >= ?1
+               AND (p.expires_at IS NULL OR p.expires_at > ?2)
+               AND (?3 IS NULL OR EXISTS (SELECT 1 FROM paste_tags t WHERE t.token = p.token AND t.tag = ?3))
+             GROUP BY p.token
+             ORDER BY window_views DESC, p.token
+             LIMIT ?4",
+        )?;
+
+        let rows = stmt.query_map(params![window_start(days), now(), tag, limit], |row| {
+            Ok(ListedPaste {
+                token: row.get(0)?,
+                preview: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                views: row.get(2)?,
+                size: ContentSize::from_columns(row.get(3)?, row.get(4)?, row.get(5)?),
+                title: row.get(6)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn add_comment(&self, comment: &NewComment) -> StoreResult<i64> {
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO comments (token, author, body, created_at) VALUES (?, ?, ?, ?)",
+                params![&comment.token, &comment.author, &comment.body, now()],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    fn comments(&self, token: &str) -> StoreResult<Vec<Comment>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT id, author, body, created_at FROM comments WHERE token = ? ORDER BY id")?;
+        let rows = stmt.query_map(params![token], |row| {
+            Ok(Comment {
+                id: row.get(0)?,
+                author: row.get(1)?,
+                body: row.get(2)?,
+                created_at: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn delete_comment(&self, token: &str, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let deleted = tx.execute("DELETE FROM comments WHERE token = ? AND id = ?", params![token, id])?;
+            if let Some(entry) = audit.filter(|_| deleted > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(deleted > 0)
+        })
+    }
+
+    fn create_api_token(&self, token: &NewApiToken, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.transaction(|tx| {
+            tx.execute(
+                "INSERT INTO api_tokens (label, token_hash, scopes, created_at, expires_at, daily_quota)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                params![&token.label, &token.token_hash, &token.scopes, now(), token.expires_at, token.daily_quota],
+            )?;
+            let id = tx.last_insert_rowid();
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(id)
+        })
+    }
+
+    fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>> {
+        let conn = self.conn();
+        Ok(conn
+            .query_row(
+                &format!("SELECT {} FROM api_tokens WHERE token_hash = ?", API_TOKEN_COLUMNS),
+                params![token_hash],
+                api_token_row,
+            )
+            .optional()?)
+    }
+
+    fn api_tokens(&self) -> StoreResult<Vec<ApiToken>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM api_tokens ORDER BY id DESC", API_TOKEN_COLUMNS))?;
+        let rows = stmt.query_map(params![], api_token_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn revoke_api_token(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let revoked = tx.execute(
+                "UPDATE api_tokens SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL",
+                params![now(), id],
+            )?;
+            if let Some(entry) = audit.filter(|_| revoked > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(revoked > 0)
+        })
+    }
+
+    fn touch_api_token(&self, id: i64, at: i64) -> StoreResult<()> {
+        self.write(|conn| {
+            conn.execute("UPDATE api_tokens SET last_used_at = ? WHERE id = ?", params![at, id])?;
+            Ok(())
+        })
+    }
+
+    fn add_ban(&self, ban: &NewBan, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.transaction(|tx| {
+            tx.execute(
+                "INSERT INTO banned_ips (ip_hash, cidr, reason, created_at, expires_at, created_by) VALUES (?, ?, ?, ?, ?, ?)",
+                params![&ban.ip_hash, &ban.cidr, &ban.reason, now(), ban.expires_at, &ban.created_by],
+            )?;
+            let id = tx.last_insert_rowid();
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(id)
+        })
+    }
+
+    fn bans(&self) -> StoreResult<Vec<Ban>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM banned_ips WHERE expires_at IS NULL OR expires_at > ? ORDER BY id DESC",
+            BAN_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![now()], ban_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn remove_ban(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let removed = tx.execute("DELETE FROM banned_ips WHERE id = ?", params![id])?;
+            if let Some(entry) = audit.filter(|_| removed > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(removed > 0)
+        })
+    }
+
+    fn prune_bans(&self) -> StoreResult<usize> {
+        self.write(|conn| Ok(conn.execute("DELETE FROM banned_ips WHERE expires_at <= ?", params![now()])?))
+    }
+
+    fn add_announcement(&self, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.transaction(|tx| {
+            tx.execute(
+                "INSERT INTO announcements (message, level, starts_at, ends_at, created_at, created_by) VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    &announcement.message,
+                    &announcement.level,
+                    announcement.starts_at,
+                    announcement.ends_at,
+                    now(),
+                    &announcement.created_by
+                ],
+            )?;
+            let id = tx.last_insert_rowid();
+            if let Some(entry) = audit {
+                insert_audit(tx, entry)?;
+            }
+            Ok(id)
+        })
+    }
+
+    fn announcements(&self) -> StoreResult<Vec<Announcement>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM announcements WHERE ends_at IS NULL OR ends_at > ? ORDER BY starts_at, id",
+            ANNOUNCEMENT_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![now()], announcement_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn update_announcement(&self, id: i64, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let updated = tx.execute(
+                "UPDATE announcements SET message = ?, level = ?, starts_at = ?, ends_at = ? WHERE id = ?",
+                params![&announcement.message, &announcement.level, announcement.starts_at, announcement.ends_at, id],
+            )?;
+            if let Some(entry) = audit.filter(|_| updated > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(updated > 0)
+        })
+    }
+
+    fn remove_announcement(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let removed = tx.execute("DELETE FROM announcements WHERE id = ?", params![id])?;
+            if let Some(entry) = audit.filter(|_| removed > 0) {
+                insert_audit(tx, entry)?;
+            }
+            Ok(removed > 0)
+        })
+    }
+
+    fn prune_announcements(&self) -> StoreResult<usize> {
+        self.write(|conn| Ok(conn.execute("DELETE FROM announcements WHERE ends_at <= ?", params![now()])?))
+    }
+
+    fn insert_collection(&self, collection: &NewCollection) -> StoreResult<()> {
+        self.transaction(|tx| {
+            let now = now();
+            tx.execute(
+                "INSERT INTO collections (token, secret, title, created_at, updated_at, expires_at) VALUES (?, ?, ?, ?, ?, ?)",
+                params![&collection.token, &collection.secret, &collection.title, now, now, collection.expires_at],
+            )?;
+            insert_collection_items(tx, &collection.token, &collection.pastes)?;
+            Ok(())
+        })
+    }
+
+    fn collection(&self, token: &str) -> StoreResult<Option<Collection>> {
+        let conn = self.conn();
+        let collection = conn
+            .query_row(
+                "SELECT token, secret, title, created_at, updated_at, expires_at FROM collections
+                 WHERE token = ? AND (expires_at IS NULL OR expires_at > ?)",
+                params![token, now()],
+                |row| {
+                    Ok(Collection {
+                        token: row.get(0)?,
+                        secret: row.get(1)?,
+                        title: row.get(2)?,
+                        created_at: row.get(3)?,
+                        updated_at: row.get(4)?,
+                        expires_at: row.get(5)?,
+                        pastes: Vec::new(),
+                    })
+                },
+            )
+            .optional()?;
+        let mut collection = match collection {
+            Some(collection) => collection,
+            None => return Ok(None),
+        };
+        let mut stmt = conn.prepare("SELECT paste FROM collection_items WHERE collection = ? ORDER BY position")?;
+        let pastes = stmt.query_map(params![token], |row| row.get(0))?;
+        collection.pastes = pastes.collect::<rusqlite::Result<_>>()?;
+        Ok(Some(collection))
+    }
+
+    fn set_collection_pastes(&self, token: &str, pastes: &[String]) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let updated = tx.execute("UPDATE collections SET updated_at = ? WHERE token = ?", params![now(), token])?;
+            if updated > 0 {
+                tx.execute("DELETE FROM collection_items WHERE collection = ?", params![token])?;
+                insert_collection_items(tx, token, pastes)?;
+            }
+            Ok(updated > 0)
+        })
+    }
+
+    fn delete_collection(&self, token: &str) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let deleted = tx.execute("DELETE FROM collections WHERE token = ?", params![token])?;
+            tx.execute("DELETE FROM collection_items WHERE collection = ?", params![token])?;
+            Ok(deleted > 0)
+        })
+    }
+
+    fn prune_collections(&self) -> StoreResult<usize> {
+        self.transaction(|tx| {
+            let now = now();
+            tx.execute(
+                "DELETE FROM collection_items WHERE collection IN (SELECT token FROM collections WHERE expires_at <= ?)",
+                params![now],
+            )?;
+            let deleted = tx.execute("DELETE FROM collections WHERE expires_at <= ?", params![now])?;
+            Ok(deleted)
+        })
+    }
+
+    fn record_instance_stats(&self, stats: &InstanceStats) -> StoreResult<()> {
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO instance_stats
+                     (instance, version, backend, pastes, archived_pastes, content_bytes, stored_bytes, received_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    &stats.instance,
+                    &stats.version,
+                    &stats.backend,
+                    stats.pastes,
+                    stats.archived_pastes,
+                    stats.content_bytes,
+                    stats.stored_bytes,
+                    stats.received_at
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn find_orphans(&self, kind: Orphans, after: &str, limit: i64) -> StoreResult<OrphanScan> {
+        let (table, column) = orphan_rows(kind);
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT token, {exists} FROM (
+                 SELECT DISTINCT {column} AS token FROM {table} WHERE {column} > ? ORDER BY {column} LIMIT ?
+             ) chunk ORDER BY token",
+            exists = orphan_owner_exists(kind, "chunk.token"),
+            column = column,
+            table = table,
+        ))?;
+        let rows = stmt.query_map(params![after, limit], |row| Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?)))?;
+        let rows = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(OrphanScan {
+            last: rows.last().map(|(token, _)| token.clone()),
+            orphans: rows.into_iter().filter(|(_, exists)| !exists).map(|(token, _)| token).collect(),
+        })
+    }
+
+    fn remove_orphans(&self, kind: Orphans, tokens: &[String]) -> StoreResult<usize> {
+        let (table, column) = orphan_rows(kind);
+        let orphan = format!("{} = ?1 AND NOT ({})", column, orphan_owner_exists(kind, "?1"));
+        self.transaction(|tx| {
+            let mut removed = 0;
+            for token in tokens {
+                if kind == Orphans::SearchIndex {
+                    tx.execute(
+                        &format!("DELETE FROM paste_search WHERE rowid IN (SELECT id FROM paste_search_tokens WHERE {})", orphan),
+                        params![token],
+                    )?;
+                }
+                if tx.execute(&format!("DELETE FROM {} WHERE {}", table, orphan), params![token])? > 0 {
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        })
+    }
+
+    fn paste_tokens(&self, after: &str, limit: i64) -> StoreResult<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT token FROM pastes WHERE token > ?1
+             UNION SELECT token FROM archived_pastes WHERE token > ?1
+             ORDER BY token LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![after, limit], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn instance_stats(&self) -> StoreResult<Vec<InstanceStats>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT instance, version, backend, pastes, archived_pastes, content_bytes, stored_bytes, received_at
+             FROM instance_stats ORDER BY instance",
+        )?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok(InstanceStats {
+                instance: row.get(0)?,
+                version: row.get(1)?,
+                backend: row.get(2)?,
+                pastes: row.get(3)?,
+                archived_pastes: row.get(4)?,
+                content_bytes: row.get(5)?,
+                stored_bytes: row.get(6)?,
+                received_at: row.get(7)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn list_created(&self, creator: &str, limit: i64) -> StoreResult<Vec<CreatedPaste>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT token, substr(content, 1, 100), line_count, char_count, byte_size, views, public, redirect, data IS NOT NULL,
+                    created_at, expires_at, pending, title
+             FROM pastes
+             WHERE creator = ?1 AND (expires_at IS NULL OR expires_at > ?2)
+             UNION ALL
+             SELECT token, '', line_count, char_count, byte_size, views, public, redirect, is_binary, created_at, NULL, 0, title
+             FROM archived_pastes
+             WHERE creator = ?1
+             ORDER BY created_at DESC, token
+             LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![creator, now(), limit], |row| {
+            Ok(CreatedPaste {
+                token: row.get(0)?,
+                preview: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                size: ContentSize::from_columns(row.get(2)?, row.get(3)?, row.get(4)?),
+                views: row.get(5)?,
+                public: row.get(6)?,
+                redirect: row.get(7)?,
+                binary: row.get(8)?,
+                created_at: row.get(9)?,
+                expires_at: row.get(10)?,
+                pending: row.get(11)?,
+                title: row.get(12)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn tag_counts(&self) -> StoreResult<Vec<(String, i64)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT t.tag, COUNT(*) AS uses
+             FROM paste_tags t
+             JOIN pastes p ON p.token = t.token
+             WHERE p.public = 1 AND NOT p.pending AND (p.expires_at IS NULL OR p.expires_at > ?)
+             GROUP BY t.tag
+             ORDER BY uses DESC, t.tag",
+        )?;
+        let rows = stmt.query_map(params![now()], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn daily_views(&self, token: &str, days: i64) -> StoreResult<Vec<(String, i64)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT day, views FROM paste_views_daily WHERE token = ? AND day >= ?")?;
+        let rows = stmt
+            .query_map(params![token, window_start(days)], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(fill_days(days, rows))
+    }
+
+    fn gist_mirror(&self, token: &str) -> StoreResult<Option<GistMirror>> {
+        let conn = self.conn();
+        let mirror = conn
+            .query_row(
+                "SELECT status, url, error, updated_at FROM paste_gists WHERE token = ?",
+                params![token],
+                |row| {
+                    let status: String = row.get(0)?;
+                    Ok(GistMirror {
+                        state: GistState::from_columns(&status, row.get(1)?, row.get(2)?),
+                        updated_at: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(mirror)
+    }
+
+    fn set_gist_mirror(&self, token: &str, state: &GistState) -> StoreResult<bool> {
+        self.write(|conn| {
+            let written = conn.execute(
+                "INSERT INTO paste_gists (token, status, url, error, updated_at)
+                 SELECT ?1, ?2, ?3, ?4, ?5
+                 WHERE EXISTS (SELECT 1 FROM pastes WHERE token = ?1 UNION ALL SELECT 1 FROM archived_pastes WHERE token = ?1)
+                 ON CONFLICT(token) DO UPDATE SET status = ?2, url = ?3, error = ?4, updated_at = ?5",
+                params![token, state.status(), state.url(), state.error(), now()],
+            )?;
+            Ok(written > 0)
+        })
+    }
+
+    fn purge_expired(&self) -> StoreResult<usize> {
+        self.transaction(|tx| {
+            let expired: Vec<String> = {
+                let mut stmt = tx.prepare("SELECT token FROM pastes WHERE expires_at <= ?")?;
+                let rows = stmt.query_map(params![now()], |row| row.get(0))?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            for token in &expired {
+                delete_paste(tx, token)?;
+            }
+            Ok(expired.len())
+        })
+    }
+
+    fn prune_daily_views(&self) -> StoreResult<usize> {
+        self.write(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM paste_views_daily WHERE day < ?",
+                params![window_start(DAILY_VIEWS_RETENTION_DAYS)],
+            )?;
+            Ok(removed)
+        })
+    }
+
+    fn purge_preview(&self) -> StoreResult<PurgeCounts> {
+        let conn = self.conn();
+        let (expired_pastes, daily_views, quotas): (i64, i64, i64) = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM pastes WHERE expires_at <= ?1),
+                    (SELECT COUNT(*) FROM paste_views_daily WHERE day < ?2),
+                    (SELECT COUNT(*) FROM ip_quota WHERE day < ?3)",
+            params![
+                now(),
+                window_start(DAILY_VIEWS_RETENTION_DAYS),
+                window_start(QUOTA_RETENTION_DAYS)
+            ],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(PurgeCounts {
+            expired_pastes: expired_pastes as usize,
+            daily_views: daily_views as usize,
+            quotas: quotas as usize,
+        })
+    }
+
+    // Only does something in databases created with `auto_vacuum = INCREMENTAL`,
+    // for the others freed pages are reused by later writes and only a full `VACUUM` shrinks the file.
+    fn reclaim_space(&self) -> StoreResult<()> {
+        self.write(|conn| {
+            conn.execute_batch("PRAGMA incremental_vacuum")?;
+            Ok(())
+        })
+    }
+
+    fn blob_in_use(&self, hash: &str) -> StoreResult<bool> {
+        let conn = self.conn();
+        let in_use = conn
+            .query_row("SELECT 1 FROM pastes WHERE blob = ? LIMIT 1", params![hash], |_| Ok(()))
+            .optional()?
+            .is_some();
+        Ok(in_use)
+    }
+
+    fn blob_hashes(&self) -> StoreResult<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT DISTINCT blob FROM pastes WHERE blob IS NOT NULL")?;
+        let rows = stmt.query_map(params![], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn record_creation(&self, client: &str, pastes: i64) -> StoreResult<i64> {
+        let day = day_string(today());
+        self.transaction(|tx| {
+            tx.execute(
+                "INSERT INTO ip_quota (client, day, pastes) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(client, day) DO UPDATE SET pastes = pastes + ?3",
+                params![client, day, pastes],
+            )?;
+            let total = tx.query_row(
+                "SELECT pastes FROM ip_quota WHERE client = ? AND day = ?",
+                params![client, day],
+                |row| row.get(0),
+            )?;
+            Ok(total)
+        })
+    }
+
+    fn creations_today(&self, client: &str) -> StoreResult<i64> {
+        let conn = self.conn();
+        let total = conn
+            .query_row(
+                "SELECT pastes FROM ip_quota WHERE client = ? AND day = ?",
+                params![client, day_string(today())],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(total.unwrap_or(0))
+    }
+
+    fn prune_quotas(&self) -> StoreResult<usize> {
+        self.write(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM ip_quota WHERE day < ?",
+                params![window_start(QUOTA_RETENTION_DAYS)],
+            )?;
+            Ok(removed)
+        })
+    }
+
+    fn record_audit(&self, entry: &AuditEntry) -> StoreResult<()> {
+        self.write(|conn| Ok(insert_audit(conn, entry)?))
+    }
+
+    fn audit_log(&self, query: &AuditQuery) -> StoreResult<Vec<AuditRecord>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, at, actor, action, target, ip_hash, detail FROM audit_log
+             WHERE (?1 IS NULL OR action = ?1) AND (?2 IS NULL OR at >= ?2) AND (?3 IS NULL OR at < ?3)
+             ORDER BY id DESC
+             LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(params![&query.action, query.since, query.until, query.limit], |row| {
+            Ok(AuditRecord {
+                id: row.get(0)?,
+                at: row.get(1)?,
+                actor: row.get(2)?,
+                action: row.get(3)?,
+                target: row.get(4)?,
+                ip_hash: row.get(5)?,
+                detail: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn prune_audit_log(&self, before: i64) -> StoreResult<usize> {
+        self.write(|conn| Ok(conn.execute("DELETE FROM audit_log WHERE at < ?", params![before])?))
+    }
+
+    // The index finds and ranks the matches (bm25), the filters only look at the rows it found.
+    fn search(&self, query: &SearchQuery) -> StoreResult<Vec<SearchHit>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT t.token, COALESCE(p.created_at, a.created_at), COALESCE(p.line_count, a.line_count),
+                    COALESCE(p.char_count, a.char_count), COALESCE(p.byte_size, a.byte_size),
+                    snippet(paste_search, 0, ?2, ?3, ?4, ?5), COALESCE(p.title, a.title)
+             FROM paste_search
+             JOIN paste_search_tokens t ON t.id = paste_search.rowid
+             LEFT JOIN pastes p ON p.token = t.token
+             LEFT JOIN archived_pastes a ON a.token = t.token
+             WHERE paste_search MATCH ?1
+               AND ((p.token IS NOT NULL AND (p.expires_at IS NULL OR p.expires_at > ?6)) OR a.token IS NOT NULL)
+               AND (?7 IS NULL OR EXISTS (SELECT 1 FROM paste_tags g WHERE g.token = t.token AND g.tag = ?7))
+               AND (?8 IS NULL OR COALESCE(p.created_at, a.created_at) >= ?8)
+               AND (?9 IS NULL OR COALESCE(p.created_at, a.created_at) < ?9)
+             ORDER BY rank
+             LIMIT ?10 OFFSET ?11",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                fts_query(&query.terms),
+                SNIPPET_START.to_string(),
+                SNIPPET_END.to_string(),
+                SNIPPET_ELLIPSIS,
+                SNIPPET_WORDS as i64,
+                now(),
+                &query.tag,
+                query.since,
+                query.until,
+                query.limit,
+                query.offset,
+            ],
+            |row| {
+                Ok(SearchHit {
+                    token: row.get(0)?,
+                    title: row.get(6)?,
+                    created_at: row.get(1)?,
+                    size: ContentSize::from_columns(row.get(2)?, row.get(3)?, row.get(4)?),
+                    snippet: row.get(5)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Contents are compressed while the lock is held, `ARCHIVE_ZSTD_LEVEL` keeps that short.
+    fn archive_idle(&self, idle_since: i64, limit: usize) -> StoreResult<usize> {
+        self.transaction(|tx| {
+            let idle: Vec<(String, String, Option<Vec<u8>>)> = {
+                let mut stmt = tx.prepare(
+                    "SELECT token, COALESCE(content, ''), data FROM pastes
+                     WHERE blob IS NULL AND expires_at IS NULL AND NOT pending AND COALESCE(last_viewed_at, created_at) < ?
+                     LIMIT ?",
+                )?;
+                let rows = stmt.query_map(params![idle_since, limit as i64], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+
+            for (token, content, data) in &idle {
+                let compressed = compress_content(content, data.as_deref())?;
+                tx.execute(
+                    "INSERT INTO archived_pastes
+                         (token, secret, content, is_binary, public, views, created_at, line_count, char_count, byte_size, archived_at, redirect,
+                          content_hash, creator, comments, title, title_auto)
+                     SELECT token, secret, ?, data IS NOT NULL, public, views, created_at, line_count, char_count, byte_size, ?, redirect,
+                            content_hash, creator, comments, title, title_auto
+                     FROM pastes WHERE token = ?",
+                    params![compressed, now(), token],
+                )?;
+                tx.execute("DELETE FROM pastes WHERE token = ?", params![token])?;
+            }
+            Ok(idle.len())
+        })
+    }
+
+    // The paste comes back as just viewed, so it isn't archived again right away.
+    fn unarchive(&self, token: &str) -> StoreResult<bool> {
+        self.transaction(|tx| {
+            let paste = match get_archived(tx, token)? {
+                Some(paste) => paste,
+                None => return Ok(false),
+            };
+            let size = paste.size.unwrap_or_default();
+            tx.execute(
+                "INSERT INTO pastes
+                     (token, secret, content, public, views, created_at, data, line_count, char_count, byte_size, last_viewed_at, redirect,
+                      content_hash, creator, comments, title, title_auto)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    &paste.token,
+                    &paste.secret,
+                    &paste.content,
+                    paste.public,
+                    paste.views,
+                    paste.created_at,
+                    &paste.data,
+                    size.lines,
+                    size.chars,
+                    size.bytes,
+                    now(),
+                    paste.redirect,
+                    &paste.content_hash,
+                    &paste.creator,
+                    paste.comments,
+                    &paste.title,
+                    paste.title_auto,
+                ],
+            )?;
+            tx.execute("DELETE FROM archived_pastes WHERE token = ?", params![token])?;
+            Ok(true)
+        })
+    }
+
+    fn archive_stats(&self) -> StoreResult<ArchiveStats> {
+        let conn = self.conn();
+        let (hot_pastes, hot_bytes) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(byte_size), 0) FROM pastes",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (archived_pastes, archived_bytes, archived_stored_bytes) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(byte_size), 0), COALESCE(SUM(length(content)), 0) FROM archived_pastes",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(ArchiveStats {
+            hot_pastes,
+            hot_bytes,
+            archived_pastes,
+            archived_bytes,
+            archived_stored_bytes,
+        })
+    }
+
+    // SQLite keeps no usage counters for indexes, they are only listed.
+    fn db_stats(&self) -> StoreResult<DbStats> {
+        let conn = self.conn();
+        let names: Vec<String> = {
+            let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")?;
+            let rows = stmt.query_map(params![], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let mut tables = Vec::with_capacity(names.len());
+        for table in names {
+            let rows = conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table.replace('"', "\"\"")), params![], |row| row.get(0))?;
+            tables.push(TableRows { table, rows });
+        }
+
+        let largest_pastes = {
+            let mut stmt = conn.prepare("SELECT token, byte_size FROM pastes WHERE byte_size IS NOT NULL ORDER BY byte_size DESC LIMIT ?")?;
+            let rows = stmt.query_map(params![DB_STATS_TOP], |row| {
+                Ok(PasteBytes {
+                    token: row.get(0)?,
+                    bytes: row.get(1)?,
+                })
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let indexes = {
+            let mut stmt = conn.prepare("SELECT tbl_name, name FROM sqlite_master WHERE type = 'index' ORDER BY tbl_name, name")?;
+            let rows = stmt.query_map(params![], |row| {
+                Ok(IndexUsage {
+                    table: row.get(0)?,
+                    index: row.get(1)?,
+                    scans: None,
+                })
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), params![], |row| row.get(0));
+        let file_size = |path: &Path| std::fs::metadata(path).ok().map(|metadata| metadata.len() as i64);
+        let mut wal_path = self.path.clone().into_os_string();
+        wal_path.push("-wal");
+        Ok(DbStats {
+            tables,
+            file_bytes: file_size(&self.path),
+            wal_bytes: file_size(Path::new(&wal_path)),
+            page_size_bytes: Some(pragma("page_size")?),
+            pages: Some(pragma("page_count")?),
+            free_pages: Some(pragma("freelist_count")?),
+            largest_pastes,
+            indexes,
+        })
+    }
+
+    fn sqlite_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
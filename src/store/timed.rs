@@ -0,0 +1,507 @@
+// Timing of the database calls.
+// `TimedStore` wraps the store of the backend, so every query is timed without the handlers doing anything:
+// the time of each call goes into a histogram per statement, shown on “/metrics”, and a call slower than
+// the threshold is logged with the name of its statement, a summary of its parameters (tokens, counts and
+// sizes, never contents or client hashes) and how long it took.
+// When nothing is slow a call only costs reading the clock twice and a short lock to count it.
+// Every call passing here, it also notices the first one finding the database corrupt (`StoreError::is_corrupt`):
+// that is logged, and `ping` fails from then on, so that “/healthz” answers 503 and load balancers take the instance
+// out until it is restarted on a repaired database. The other calls go on, those reading sound pages still work.
+
+use super::{
+    Announcement, ApiToken, ArchiveStats, AuditEntry, AuditQuery, AuditRecord, Ban, BlobScan, Collection, Comment,
+    Content, ContentSize, CreatedPaste, DbStats, GistMirror, GistState, HashMatch, InstanceStats, ListedPaste,
+    NewAnnouncement, NewApiToken, NewBan, NewCollection, NewComment, NewPaste, OrphanScan, Orphans, Paste, PasteStore,
+    PendingPaste, PurgeCounts, SearchHit, SearchQuery, StoreError, StoreResult,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Default)]
+struct Histogram {
+    // Calls at or below each bound of `BUCKETS`, not cumulated
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+// The histograms of all statements, shared between the store and the metrics endpoint.
+#[derive(Default)]
+pub struct QueryTimings {
+    statements: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl QueryTimings {
+    fn record(&self, statement: &'static str, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut statements = self.statements.lock().unwrap();
+        let histogram = statements.entry(statement).or_default();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    // The histograms in the Prometheus text format, as `pastry_db_query_duration_seconds`.
+    pub fn render(&self) -> String {
+        let statements = self.statements.lock().unwrap();
+        let mut out = String::from(
+            "# HELP pastry_db_query_duration_seconds Time taken by the database calls, per statement.\n\
+             # TYPE pastry_db_query_duration_seconds histogram\n",
+        );
+        for (statement, histogram) in statements.iter() {
+            let mut cumulated = 0;
+            for (bound, calls) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulated += calls;
+                let _ = writeln!(
+                    out,
+                    "pastry_db_query_duration_seconds_bucket{{statement=\"{}\",le=\"{}\"}} {}",
+                    statement, bound, cumulated
+                );
+            }
+            let _ = writeln!(
+                out,
+                "pastry_db_query_duration_seconds_bucket{{statement=\"{}\",le=\"+Inf\"}} {}",
+                statement, histogram.count
+            );
+            let _ = writeln!(out, "pastry_db_query_duration_seconds_sum{{statement=\"{}\"}} {}", statement, histogram.sum);
+            let _ = writeln!(out, "pastry_db_query_duration_seconds_count{{statement=\"{}\"}} {}", statement, histogram.count);
+        }
+        out
+    }
+}
+
+pub struct TimedStore {
+    inner: Box<dyn PasteStore>,
+    timings: Arc<QueryTimings>,
+    // Calls taking longer are logged, zero logs none
+    slow: Duration,
+    // Set by the first call that found the database corrupt
+    corrupt: AtomicBool,
+}
+
+impl TimedStore {
+    pub fn new(inner: Box<dyn PasteStore>, timings: Arc<QueryTimings>, slow: Duration) -> TimedStore {
+        TimedStore {
+            inner,
+            timings,
+            slow,
+            corrupt: AtomicBool::new(false),
+        }
+    }
+
+    // Runs `call` on the inner store, timing it as `statement`.
+    // `params` is only formatted when the call is slow enough to be logged.
+    fn timed<T>(
+        &self,
+        statement: &'static str,
+        params: impl FnOnce() -> String,
+        call: impl FnOnce(&dyn PasteStore) -> StoreResult<T>,
+    ) -> StoreResult<T> {
+        let _span = crate::telemetry::db_call(statement, self.inner.backend());
+        let start = Instant::now();
+        let result = call(self.inner.as_ref());
+        let elapsed = start.elapsed();
+
+        self.timings.record(statement, elapsed);
+        if !self.slow.is_zero() && elapsed > self.slow {
+            eprintln!(
+                "Slow database call: {} ({}) took {} ms{}",
+                statement,
+                params(),
+                elapsed.as_millis(),
+                if result.is_err() { ", and failed" } else { "" }
+            );
+        }
+        if let Err(e) = &result {
+            if e.is_corrupt() && !self.corrupt.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "Database corrupt: {} failed with \"{}\", /healthz answers 503 from now on. Stop the server and \
+                     repair the database or restore a backup, see “Corrupt Database” in the README",
+                    statement, e
+                );
+            }
+        }
+        result
+    }
+}
+
+fn no_params() -> String {
+    String::new()
+}
+
+fn total_bytes(pastes: &[NewPaste]) -> usize {
+    pastes.iter().map(|paste| paste.content.len() + paste.data.as_ref().map_or(0, Vec::len)).sum()
+}
+
+impl PasteStore for TimedStore {
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
+    }
+
+    fn ping(&self) -> StoreResult<()> {
+        if self.corrupt.load(Ordering::Relaxed) {
+            return Err(StoreError::Corrupt("A query found the database corrupt".to_string()));
+        }
+        self.timed("ping", no_params, |store| store.ping())
+    }
+
+    fn check_database(&self, full: bool) -> StoreResult<Vec<String>> {
+        self.timed("check_database", || format!("full {}", full), |store| store.check_database(full))
+    }
+
+    // As a whole, the calls made inside are timed too
+    fn with_tx(&self, op: &mut dyn FnMut() -> StoreResult<()>) -> StoreResult<()> {
+        self.timed("with_tx", no_params, |store| store.with_tx(op))
+    }
+
+    fn insert(&self, paste: &NewPaste) -> StoreResult<()> {
+        self.timed(
+            "insert",
+            || format!("token {}, {} bytes", paste.token, total_bytes(std::slice::from_ref(paste))),
+            |store| store.insert(paste),
+        )
+    }
+
+    fn insert_batch(&self, pastes: &[NewPaste]) -> StoreResult<()> {
+        self.timed(
+            "insert_batch",
+            || format!("{} pastes, {} bytes", pastes.len(), total_bytes(pastes)),
+            |store| store.insert_batch(pastes),
+        )
+    }
+
+    fn get(&self, token: &str) -> StoreResult<Option<Paste>> {
+        self.timed("get", || format!("token {}", token), |store| store.get(token))
+    }
+
+    fn open_content(&self, token: &str) -> StoreResult<Option<(Paste, Content)>> {
+        self.timed("open_content", || format!("token {}", token), |store| store.open_content(token))
+    }
+
+    fn set_size(&self, token: &str, size: ContentSize) -> StoreResult<()> {
+        self.timed("set_size", || format!("token {}", token), |store| store.set_size(token, size))
+    }
+
+    fn set_content_hash(&self, token: &str, hash: &str) -> StoreResult<()> {
+        self.timed("set_content_hash", || format!("token {}", token), |store| store.set_content_hash(token, hash))
+    }
+
+    fn find_by_hash(&self, prefix: &str, limit: i64) -> StoreResult<Vec<HashMatch>> {
+        self.timed("find_by_hash", || format!("prefix {}, limit {}", prefix, limit), |store| store.find_by_hash(prefix, limit))
+    }
+
+    fn exists(&self, token: &str) -> StoreResult<bool> {
+        self.timed("exists", || format!("token {}", token), |store| store.exists(token))
+    }
+
+    fn delete(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.timed("delete", || format!("token {}", token), |store| store.delete(token, audit))
+    }
+
+    fn pending_pastes(&self, limit: i64) -> StoreResult<Vec<PendingPaste>> {
+        self.timed("pending_pastes", || format!("limit {}", limit), |store| store.pending_pastes(limit))
+    }
+
+    fn approve(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.timed("approve", || format!("token {}", token), |store| store.approve(token, audit))
+    }
+
+    fn reject(&self, token: &str, audit: Option<&AuditEntry>) -> StoreResult<Option<Option<String>>> {
+        self.timed("reject", || format!("token {}", token), |store| store.reject(token, audit))
+    }
+
+    fn replace(&self, paste: &NewPaste, audit: Option<&AuditEntry>) -> StoreResult<()> {
+        self.timed(
+            "replace",
+            || format!("token {}, {} bytes", paste.token, total_bytes(std::slice::from_ref(paste))),
+            |store| store.replace(paste, audit),
+        )
+    }
+
+    fn record_view(&self, token: &str) -> StoreResult<()> {
+        self.timed("record_view", || format!("token {}", token), |store| store.record_view(token))
+    }
+
+    fn tags(&self, token: &str) -> StoreResult<Vec<String>> {
+        self.timed("tags", || format!("token {}", token), |store| store.tags(token))
+    }
+
+    fn list_popular(&self, days: i64, tag: Option<&str>, limit: i64) -> StoreResult<Vec<ListedPaste>> {
+        self.timed(
+            "list_popular",
+            || format!("{} days, tag {:?}, limit {}", days, tag, limit),
+            |store| store.list_popular(days, tag, limit),
+        )
+    }
+
+    fn add_comment(&self, comment: &NewComment) -> StoreResult<i64> {
+        self.timed("add_comment", || format!("token {}", comment.token), |store| store.add_comment(comment))
+    }
+
+    fn comments(&self, token: &str) -> StoreResult<Vec<Comment>> {
+        self.timed("comments", || format!("token {}", token), |store| store.comments(token))
+    }
+
+    fn delete_comment(&self, token: &str, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.timed(
+            "delete_comment",
+            || format!("token {}, comment {}", token, id),
+            |store| store.delete_comment(token, id, audit),
+        )
+    }
+
+    fn create_api_token(&self, token: &NewApiToken, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.timed("create_api_token", || format!("scopes {}", token.scopes), |store| store.create_api_token(token, audit))
+    }
+
+    fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>> {
+        self.timed("api_token", no_params, |store| store.api_token(token_hash))
+    }
+
+    fn api_tokens(&self) -> StoreResult<Vec<ApiToken>> {
+        self.timed("api_tokens", no_params, |store| store.api_tokens())
+    }
+
+    fn revoke_api_token(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.timed("revoke_api_token", || format!("token {}", id), |store| store.revoke_api_token(id, audit))
+    }
+
+    fn touch_api_token(&self, id: i64, at: i64) -> StoreResult<()> {
+        self.timed("touch_api_token", || format!("token {}", id), |store| store.touch_api_token(id, at))
+    }
+
+    fn add_ban(&self, ban: &NewBan, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.timed("add_ban", || format!("by {}", ban.created_by), |store| store.add_ban(ban, audit))
+    }
+
+    fn bans(&self) -> StoreResult<Vec<Ban>> {
+        self.timed("bans", no_params, |store| store.bans())
+    }
+
+    fn remove_ban(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.timed("remove_ban", || format!("ban {}", id), |store| store.remove_ban(id, audit))
+    }
+
+    fn prune_bans(&self) -> StoreResult<usize> {
+        self.timed("prune_bans", no_params, |store| store.prune_bans())
+    }
+
+    fn add_announcement(&self, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<i64> {
+        self.timed(
+            "add_announcement",
+            || format!("by {}", announcement.created_by),
+            |store| store.add_announcement(announcement, audit),
+        )
+    }
+
+    fn announcements(&self) -> StoreResult<Vec<Announcement>> {
+        self.timed("announcements", no_params, |store| store.announcements())
+    }
+
+    fn update_announcement(&self, id: i64, announcement: &NewAnnouncement, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.timed(
+            "update_announcement",
+            || format!("announcement {}", id),
+            |store| store.update_announcement(id, announcement, audit),
+        )
+    }
+
+    fn remove_announcement(&self, id: i64, audit: Option<&AuditEntry>) -> StoreResult<bool> {
+        self.timed("remove_announcement", || format!("announcement {}", id), |store| store.remove_announcement(id, audit))
+    }
+
+    fn prune_announcements(&self) -> StoreResult<usize> {
+        self.timed("prune_announcements", no_params, |store| store.prune_announcements())
+    }
+
+    fn insert_collection(&self, collection: &NewCollection) -> StoreResult<()> {
+        self.timed(
+            "insert_collection",
+            || format!("token {}, {} pastes", collection.token, collection.pastes.len()),
+            |store| store.insert_collection(collection),
+        )
+    }
+
+    fn collection(&self, token: &str) -> StoreResult<Option<Collection>> {
+        self.timed("collection", || format!("token {}", token), |store| store.collection(token))
+    }
+
+    fn set_collection_pastes(&self, token: &str, pastes: &[String]) -> StoreResult<bool> {
+        self.timed(
+            "set_collection_pastes",
+            || format!("token {}, {} pastes", token, pastes.len()),
+            |store| store.set_collection_pastes(token, pastes),
+        )
+    }
+
+    fn delete_collection(&self, token: &str) -> StoreResult<bool> {
+        self.timed("delete_collection", || format!("token {}", token), |store| store.delete_collection(token))
+    }
+
+    fn prune_collections(&self) -> StoreResult<usize> {
+        self.timed("prune_collections", no_params, |store| store.prune_collections())
+    }
+
+    fn record_instance_stats(&self, stats: &InstanceStats) -> StoreResult<()> {
+        self.timed(
+            "record_instance_stats",
+            || format!("instance {}", stats.instance),
+            |store| store.record_instance_stats(stats),
+        )
+    }
+
+    fn instance_stats(&self) -> StoreResult<Vec<InstanceStats>> {
+        self.timed("instance_stats", no_params, |store| store.instance_stats())
+    }
+
+    fn find_orphans(&self, kind: Orphans, after: &str, limit: i64) -> StoreResult<OrphanScan> {
+        self.timed(
+            "find_orphans",
+            || format!("{}, limit {}", kind.name(), limit),
+            |store| store.find_orphans(kind, after, limit),
+        )
+    }
+
+    fn remove_orphans(&self, kind: Orphans, tokens: &[String]) -> StoreResult<usize> {
+        self.timed(
+            "remove_orphans",
+            || format!("{}, {} tokens", kind.name(), tokens.len()),
+            |store| store.remove_orphans(kind, tokens),
+        )
+    }
+
+    fn paste_tokens(&self, after: &str, limit: i64) -> StoreResult<Vec<String>> {
+        self.timed("paste_tokens", || format!("limit {}", limit), |store| store.paste_tokens(after, limit))
+    }
+
+    fn scan_blobs(&self) -> StoreResult<BlobScan> {
+        self.timed("scan_blobs", no_params, |store| store.scan_blobs())
+    }
+
+    fn remove_blobs(&self, hashes: &[String]) -> StoreResult<usize> {
+        self.timed("remove_blobs", || format!("{} files", hashes.len()), |store| store.remove_blobs(hashes))
+    }
+
+    fn list_created(&self, creator: &str, limit: i64) -> StoreResult<Vec<CreatedPaste>> {
+        self.timed("list_created", || format!("limit {}", limit), |store| store.list_created(creator, limit))
+    }
+
+    fn tag_counts(&self) -> StoreResult<Vec<(String, i64)>> {
+        self.timed("tag_counts", no_params, |store| store.tag_counts())
+    }
+
+    fn daily_views(&self, token: &str, days: i64) -> StoreResult<Vec<(String, i64)>> {
+        self.timed(
+            "daily_views",
+            || format!("token {}, {} days", token, days),
+            |store| store.daily_views(token, days),
+        )
+    }
+
+    fn gist_mirror(&self, token: &str) -> StoreResult<Option<GistMirror>> {
+        self.timed("gist_mirror", || format!("token {}", token), |store| store.gist_mirror(token))
+    }
+
+    fn set_gist_mirror(&self, token: &str, state: &GistState) -> StoreResult<bool> {
+        self.timed(
+            "set_gist_mirror",
+            || format!("token {}, {}", token, state.status()),
+            |store| store.set_gist_mirror(token, state),
+        )
+    }
+
+    fn purge_expired(&self) -> StoreResult<usize> {
+        self.timed("purge_expired", no_params, |store| store.purge_expired())
+    }
+
+    fn prune_daily_views(&self) -> StoreResult<usize> {
+        self.timed("prune_daily_views", no_params, |store| store.prune_daily_views())
+    }
+
+    fn purge_preview(&self) -> StoreResult<PurgeCounts> {
+        self.timed("purge_preview", no_params, |store| store.purge_preview())
+    }
+
+    fn reclaim_space(&self) -> StoreResult<()> {
+        self.timed("reclaim_space", no_params, |store| store.reclaim_space())
+    }
+
+    fn blob_in_use(&self, hash: &str) -> StoreResult<bool> {
+        self.timed("blob_in_use", || format!("blob {}", hash), |store| store.blob_in_use(hash))
+    }
+
+    fn blob_hashes(&self) -> StoreResult<Vec<String>> {
+        self.timed("blob_hashes", no_params, |store| store.blob_hashes())
+    }
+
+    // The client is a salted hash of an IP, left out of the log
+    fn record_creation(&self, client: &str, pastes: i64) -> StoreResult<i64> {
+        self.timed(
+            "record_creation",
+            || format!("{} pastes", pastes),
+            |store| store.record_creation(client, pastes),
+        )
+    }
+
+    fn creations_today(&self, client: &str) -> StoreResult<i64> {
+        self.timed("creations_today", no_params, |store| store.creations_today(client))
+    }
+
+    fn prune_quotas(&self) -> StoreResult<usize> {
+        self.timed("prune_quotas", no_params, |store| store.prune_quotas())
+    }
+
+    fn record_audit(&self, entry: &AuditEntry) -> StoreResult<()> {
+        self.timed("record_audit", || format!("{} by {}", entry.action, entry.actor), |store| store.record_audit(entry))
+    }
+
+    fn audit_log(&self, query: &AuditQuery) -> StoreResult<Vec<AuditRecord>> {
+        self.timed("audit_log", || format!("limit {}", query.limit), |store| store.audit_log(query))
+    }
+
+    fn prune_audit_log(&self, before: i64) -> StoreResult<usize> {
+        self.timed("prune_audit_log", || format!("before {}", before), |store| store.prune_audit_log(before))
+    }
+
+    // The terms are what was typed, only their number is logged
+    fn search(&self, query: &SearchQuery) -> StoreResult<Vec<SearchHit>> {
+        self.timed(
+            "search",
+            || format!("{} terms, tag {:?}, limit {}, offset {}", query.terms.len(), query.tag, query.limit, query.offset),
+            |store| store.search(query),
+        )
+    }
+
+    fn archive_idle(&self, idle_since: i64, limit: usize) -> StoreResult<usize> {
+        self.timed(
+            "archive_idle",
+            || format!("idle since {}, limit {}", idle_since, limit),
+            |store| store.archive_idle(idle_since, limit),
+        )
+    }
+
+    fn unarchive(&self, token: &str) -> StoreResult<bool> {
+        self.timed("unarchive", || format!("token {}", token), |store| store.unarchive(token))
+    }
+
+    fn archive_stats(&self) -> StoreResult<ArchiveStats> {
+        self.timed("archive_stats", no_params, |store| store.archive_stats())
+    }
+
+    fn db_stats(&self) -> StoreResult<DbStats> {
+        self.timed("db_stats", no_params, |store| store.db_stats())
+    }
+
+    fn sqlite_path(&self) -> Option<&Path> {
+        self.inner.sqlite_path()
+    }
+}
@@ -0,0 +1,45 @@
+// Validation of the tags a paste can carry.
+// Tags come in as a comma-separated string from the form, get trimmed, lowercased and deduplicated,
+// and only short tags made of letters, digits and a few separators are accepted,
+// so they can be put in URLs and pages without any escaping surprises.
+
+pub const MAX_TAGS: usize = 5;
+pub const MAX_TAG_LEN: usize = 24;
+
+// Turns user input like "Rust, web ,rust" into ["rust", "web"].
+// Returns a message suitable for showing to the user when the input is not acceptable.
+pub fn parse_tags(input: &str) -> Result<Vec<String>, String> {
+    let mut tags: Vec<String> = Vec::new();
+
+    for raw in input.split(',') {
+        let tag = raw.trim().to_lowercase();
+        if tag.is_empty() || tags.contains(&tag) {
+            continue;
+        }
+        validate_tag(&tag)?;
+        tags.push(tag);
+    }
+
+    if tags.len() > MAX_TAGS {
+        return Err(format!("A paste can have at most {} tags", MAX_TAGS));
+    }
+
+    Ok(tags)
+}
+
+// Checks a single, already normalized tag: 1 to MAX_TAG_LEN characters out of [a-z0-9+#._-].
+pub fn validate_tag(tag: &str) -> Result<(), String> {
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LEN {
+        return Err(format!("Tags must be between 1 and {} characters long", MAX_TAG_LEN));
+    }
+    if !tag
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+#._-".contains(c))
+    {
+        return Err(format!(
+            "Tag \"{}\" may only contain letters, digits and + # . _ -",
+            tag
+        ));
+    }
+    Ok(())
+}
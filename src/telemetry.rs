@@ -0,0 +1,183 @@
+// Optional export of traces to an OpenTelemetry collector (Tempo, Jaeger…) over OTLP/HTTP.
+// It needs the `otel` cargo feature, and `PASTRY_OTLP_ENDPOINT` set to the collector at runtime.
+// Every request gets a span with its method, route, status and the paste token when the route has one.
+// An incoming `traceparent` header makes it part of the caller's trace. The database calls (see `store/timed.rs`)
+// and the rendering of the pages are child spans of it. Store calls running on a blocking thread
+// (backups, purges) end up as traces of their own.
+// Without the feature every function here does nothing, and the rest of the code calls them all the same.
+
+#[cfg(feature = "otel")]
+pub use traced::*;
+#[cfg(not(feature = "otel"))]
+pub use untraced::*;
+
+#[cfg(feature = "otel")]
+mod traced {
+    use actix_web::dev::{ServiceRequest, ServiceResponse};
+    use actix_web::http::HeaderMap;
+    use opentelemetry::propagation::Extractor;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use std::future::Future;
+    use tracing::field::Empty;
+    use tracing::Instrument;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    pub type Guard = tracing::span::EnteredSpan;
+    pub type RequestSpan = tracing::Span;
+
+    // Sends the spans to the collector until `shutdown`.
+    pub struct Exporter(SdkTracerProvider);
+
+    impl Exporter {
+        // Sends the spans still waiting, for the last requests before an exit.
+        pub fn shutdown(self) {
+            if let Err(e) = self.0.shutdown() {
+                eprintln!("Failed to send the last traces: {}", e);
+            }
+        }
+    }
+
+    // Starts sending traces to `endpoint` (`PASTRY_OTLP_ENDPOINT`), when it is set.
+    // The spans are sent in batches from a thread of their own, a collector that is down only loses traces.
+    pub fn init(endpoint: Option<String>) -> Option<Exporter> {
+        let endpoint = endpoint.filter(|endpoint| !endpoint.is_empty())?;
+
+        let exporter = match opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint.as_str())
+            .build()
+        {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                eprintln!("Traces are not exported, the OTLP exporter for {} failed: {}", endpoint, e);
+                return None;
+            }
+        };
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(opentelemetry_sdk::Resource::builder().with_service_name("pastry").build())
+            .build();
+
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("pastry")));
+        if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+            eprintln!("Traces are not exported: {}", e);
+            return None;
+        }
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+        println!("Exporting traces to {}", endpoint);
+        Some(Exporter(provider))
+    }
+
+    // The span of a request, in the trace of its `traceparent` header when it has one.
+    // Route, status and token are only known once it was answered, `traced` fills them in.
+    pub fn request_span(req: &ServiceRequest) -> RequestSpan {
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %req.method(),
+            otel.kind = "server",
+            otel.status_code = Empty,
+            http.request.method = %req.method(),
+            url.path = req.path(),
+            http.route = Empty,
+            http.response.status_code = Empty,
+            paste.token = Empty,
+        );
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&Headers(req.headers())));
+        span.set_parent(parent);
+        span
+    }
+
+    // Runs the handling of a request in its span.
+    pub async fn traced<B>(
+        span: RequestSpan,
+        response: impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>,
+    ) -> Result<ServiceResponse<B>, actix_web::Error> {
+        let result = response.instrument(span.clone()).await;
+
+        let status = match &result {
+            Ok(response) => {
+                let req = response.request();
+                if let Some(route) = req.match_pattern() {
+                    span.record("otel.name", format!("{} {}", req.method(), route).as_str());
+                    span.record("http.route", route.as_str());
+                }
+                if let Some(token) = req.match_info().get("token") {
+                    span.record("paste.token", token);
+                }
+                response.status()
+            }
+            Err(e) => e.as_response_error().status_code(),
+        };
+        // An integer as the semantic conventions want it, the exporter makes a string of a `u16`
+        span.record("http.response.status_code", i64::from(status.as_u16()));
+        if status.is_server_error() {
+            span.record("otel.status_code", "ERROR");
+        }
+        result
+    }
+
+    // The span of a database call, `statement` being the name of the store method.
+    pub fn db_call(statement: &'static str, backend: &'static str) -> Guard {
+        tracing::info_span!("db", otel.name = statement, otel.kind = "client", db.system.name = backend, db.operation.name = statement)
+            .entered()
+    }
+
+    // The span of the rendering of a page from its template.
+    pub fn template(name: &'static str) -> Guard {
+        tracing::info_span!("render", otel.name = name, template = name).entered()
+    }
+
+    struct Headers<'a>(&'a HeaderMap);
+
+    impl Extractor for Headers<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|name| name.as_str()).collect()
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod untraced {
+    use actix_web::dev::ServiceRequest;
+
+    pub struct Guard;
+    pub struct RequestSpan;
+    pub struct Exporter;
+
+    impl Exporter {
+        pub fn shutdown(self) {}
+    }
+
+    pub fn init(endpoint: Option<String>) -> Option<Exporter> {
+        if endpoint.is_some_and(|endpoint| !endpoint.is_empty()) {
+            eprintln!("PASTRY_OTLP_ENDPOINT is ignored, this build has no trace export, rebuild with `--features otel`");
+        }
+        None
+    }
+
+    pub fn request_span(_req: &ServiceRequest) -> RequestSpan {
+        RequestSpan
+    }
+
+    pub fn traced<F>(_span: RequestSpan, response: F) -> F {
+        response
+    }
+
+    pub fn db_call(_statement: &'static str, _backend: &'static str) -> Guard {
+        Guard
+    }
+
+    pub fn template(_name: &'static str) -> Guard {
+        Guard
+    }
+}
@@ -0,0 +1,162 @@
+// Every failure of the API answers the JSON envelope of `error.rs`, with the code of its status, and never the HTML
+// error page, whichever part of the server turned the request away: a handler, an extractor, the routing, a
+// middleware.
+
+use super::*;
+use crate::store::Ban;
+
+// A request the API refuses, and the status it gets.
+struct Failure {
+    name: &'static str,
+    method: Method,
+    path: &'static str,
+    headers: &'static [(&'static str, &'static str)],
+    body: &'static str,
+    status: u16,
+}
+
+const JSON: (&str, &str) = ("Content-Type", "application/json");
+
+fn failures() -> Vec<Failure> {
+    let failure = |name, method, path, headers, body, status| Failure {
+        name,
+        method,
+        path,
+        headers,
+        body,
+        status,
+    };
+    vec![
+        failure("unknown paste", Method::GET, "/api/pastes/nosuchpaste", &[], "", 404),
+        failure("unknown paste, HEAD", Method::HEAD, "/api/pastes/nosuchpaste", &[], "", 404),
+        failure("unknown route", Method::GET, "/api/nothing/here", &[], "", 404),
+        failure("method of no route", Method::PATCH, "/api/pastes", &[], "", 404),
+        failure("deleting an unknown paste", Method::DELETE, "/api/pastes/nosuchpaste", &[], "", 404),
+        failure("unknown collection", Method::GET, "/api/collections/nosuchcollection", &[], "", 404),
+        failure("federation off", Method::POST, "/api/federation/stats", &[JSON], "{}", 404),
+        failure("broken JSON", Method::POST, "/api/pastes", &[JSON], "{\"content\":", 400),
+        failure("JSON of the wrong shape", Method::POST, "/api/pastes", &[JSON], "{\"content\":1}", 400),
+        failure("not JSON", Method::POST, "/api/pastes", &[("Content-Type", "text/plain")], "hello", 400),
+        failure("unknown expiry", Method::POST, "/api/pastes", &[JSON], "{\"content\":\"a\",\"expires\":\"1y\"}", 400),
+        failure("broken batch", Method::POST, "/api/pastes/batch", &[JSON], "[", 400),
+        failure("broken validation", Method::POST, "/api/pastes/validate", &[JSON], "[", 400),
+        // Read by the `web::Json` extractor rather than `body::json`
+        failure("broken fetch", Method::POST, "/api/pastes/fetch", &[JSON], "{", 400),
+        failure("broken collection", Method::POST, "/api/collections", &[JSON], "{", 400),
+        failure("invalid chosen token", Method::PUT, "/api/pastes/a%20b", &[JSON], "{\"content\":\"a\"}", 400),
+        failure("search without words", Method::GET, "/api/search", &[], "", 400),
+        failure("unknown encoding", Method::POST, "/api/pastes", &[JSON, ("Content-Encoding", "br")], "{}", 415),
+        failure("undecodable gzip", Method::POST, "/api/pastes", &[JSON, ("Content-Encoding", "gzip")], "{}", 400),
+        failure("bad idempotency key", Method::POST, "/api/pastes", &[JSON, ("Idempotency-Key", "two words")], "{}", 400),
+    ]
+}
+
+// The status and `code` of an envelope, and that it is one.
+fn envelope(answer: &Answer) -> (u16, String) {
+    let status = answer.status.as_u16();
+    let body = answer.text();
+    assert!(answer.content_type().starts_with("application/json"), "{} {}: {}", status, answer.content_type(), body);
+    assert!(!body.contains("<html") && !body.contains("<!DOCTYPE"), "{}: {}", status, body);
+    let json = answer.json();
+    assert!(json["error"]["message"].as_str().is_some_and(|message| !message.is_empty()), "{}: {}", status, body);
+    (status, json["error"]["code"].as_str().unwrap_or_default().to_string())
+}
+
+fn code_of(status: u16) -> String {
+    error::ErrorCode::from_status(StatusCode::from_u16(status).unwrap()).as_str().to_string()
+}
+
+#[actix_rt::test]
+async fn every_failure_is_a_json_envelope() {
+    let data = state();
+    for failure in failures() {
+        let mut request = request().method(failure.method.clone()).uri(failure.path).set_payload(failure.body);
+        for (name, value) in failure.headers {
+            request = request.header(*name, *value);
+        }
+        let answer = call(&data, request).await;
+        assert_eq!(answer.status.as_u16(), failure.status, "{}", failure.name);
+        // The server leaves out the body of a HEAD, which the app still answers
+        if failure.method == Method::HEAD {
+            assert!(answer.content_type().starts_with("application/json"), "{}", failure.name);
+            continue;
+        }
+        assert_eq!(envelope(&answer).1, code_of(failure.status), "{}", failure.name);
+    }
+}
+
+#[actix_rt::test]
+async fn too_big_bodies_are_413() {
+    let data = state_with(Config {
+        max_paste_bytes: Some(1000),
+        ..Config::default()
+    });
+    let content = "a".repeat(max_json_bytes(1000) + 1);
+    let body = serde_json::json!({ "content": content }).to_string();
+    let answer = call(&data, request().method(Method::POST).uri("/api/pastes").header(JSON.0, JSON.1).set_payload(body)).await;
+    assert_eq!(envelope(&answer), (413, "payload_too_large".to_string()));
+}
+
+#[actix_rt::test]
+async fn missing_token_is_401() {
+    let data = state_with(Config {
+        api_require_token: Some(true),
+        ..Config::default()
+    });
+    let answer = call(&data, request().uri("/api/pastes/nosuchpaste")).await;
+    assert_eq!(envelope(&answer), (401, "unauthorized".to_string()));
+    let answer = call(&data, request().uri("/api/pastes/nosuchpaste").header("Authorization", "Bearer nosuchtoken")).await;
+    assert_eq!(envelope(&answer).1, code_of(401));
+}
+
+#[actix_rt::test]
+async fn wrong_secret_is_403() {
+    let data = state();
+    let body = "{\"content\":\"hello\"}";
+    let answer = call(&data, request().method(Method::POST).uri("/api/pastes").header(JSON.0, JSON.1).set_payload(body)).await;
+    assert_eq!(answer.status, StatusCode::CREATED);
+    let token = answer.json()["token"].as_str().unwrap().to_string();
+    let uri = format!("/api/pastes/{}?key=wrong", token);
+    let answer = call(&data, request().method(Method::DELETE).uri(&uri)).await;
+    assert_eq!(envelope(&answer), (403, "forbidden".to_string()));
+}
+
+#[actix_rt::test]
+async fn reached_quota_is_429() {
+    let data = state_with(Config {
+        daily_paste_quota: Some(1),
+        ..Config::default()
+    });
+    let create = || request().method(Method::POST).uri("/api/pastes").header(JSON.0, JSON.1).set_payload("{\"content\":\"a\"}");
+    assert_eq!(call(&data, create()).await.status, StatusCode::CREATED);
+    let answer = call(&data, create()).await;
+    assert!(answer.header("Retry-After").is_some());
+    assert_eq!(envelope(&answer), (429, "rate_limited".to_string()));
+}
+
+#[actix_rt::test]
+async fn banned_client_is_403() {
+    let data = state();
+    *data.bans.write().unwrap() = crate::bans::BanList::new(&[Ban {
+        id: 1,
+        ip_hash: None,
+        cidr: Some("203.0.113.0/24".to_string()),
+        reason: String::new(),
+        created_at: 0,
+        expires_at: None,
+        created_by: String::new(),
+    }]);
+    let answer = call(&data, request().method(Method::POST).uri("/api/pastes").header(JSON.0, JSON.1).set_payload("{}")).await;
+    assert_eq!(envelope(&answer), (403, "forbidden".to_string()));
+}
+
+#[actix_rt::test]
+async fn read_only_database_is_503() {
+    let data = state();
+    let data = web::Data::new(AppState {
+        database_read_only: true,
+        ..Arc::try_unwrap(data.into_inner()).ok().unwrap()
+    });
+    let answer = call(&data, request().method(Method::POST).uri("/api/pastes").header(JSON.0, JSON.1).set_payload("{\"content\":\"a\"}")).await;
+    assert_eq!(envelope(&answer), (503, "unavailable".to_string()));
+}
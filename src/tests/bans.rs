@@ -0,0 +1,75 @@
+// Bans through the admin routes: single addresses stay banned whatever the salt, hashes need a configured one.
+
+use super::*;
+
+fn add_ban(body: serde_json::Value) -> TestRequest {
+    admin_request()
+        .method(Method::POST)
+        .uri("/admin/bans")
+        .header("Content-Type", "application/json")
+        .set_payload(body.to_string())
+}
+
+#[actix_rt::test]
+async fn single_addresses_are_stored_as_ranges() {
+    let data = state_with(admin_config());
+    let answer = call(&data, add_ban(serde_json::json!({ "ip": "203.0.113.7" }))).await;
+    assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+    assert_eq!(answer.json()["cidr"], "203.0.113.7/32");
+    assert!(answer.json()["ip_hash"].is_null());
+    let answer = call(&data, add_ban(serde_json::json!({ "ip": "2001:db8::7" }))).await;
+    assert_eq!(answer.json()["cidr"], "2001:db8::7/128");
+
+    // From `PEER`, whatever the salt of the server that serves it
+    let answer = call(&data, create_request(serde_json::json!({ "content": "spam" }))).await;
+    assert_eq!(answer.status, StatusCode::FORBIDDEN);
+    let restarted = state_with(Config {
+        ip_salt: Some("another salt".to_string()),
+        ..admin_config()
+    });
+    let bans = data.store.bans().unwrap();
+    *restarted.bans.write().unwrap() = crate::bans::BanList::new(&bans);
+    let answer = call(&restarted, create_request(serde_json::json!({ "content": "spam" }))).await;
+    assert_eq!(answer.status, StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn hashes_need_a_configured_salt() {
+    let hash = serde_json::json!({ "ip_hash": "a".repeat(64) });
+    let data = state_with(admin_config());
+    let answer = call(&data, add_ban(hash.clone())).await;
+    assert_eq!(answer.status, StatusCode::BAD_REQUEST);
+    assert!(answer.text().contains("PASTRY_IP_SALT"));
+    assert!(data.store.bans().unwrap().is_empty());
+
+    let salted = state_with(Config {
+        ip_salt: Some("salt".to_string()),
+        ..admin_config()
+    });
+    assert_eq!(call(&salted, add_ban(hash)).await.status, StatusCode::CREATED);
+}
+
+#[actix_rt::test]
+async fn rejecting_with_a_ban_needs_a_configured_salt() {
+    let reviewed = |ip_salt: Option<&str>| {
+        state_with(Config {
+            review_pastes: Some(true),
+            ip_salt: ip_salt.map(str::to_string),
+            ..admin_config()
+        })
+    };
+    let reject = |token: &str| admin_request().method(Method::POST).uri(&format!("/admin/review/{}/reject?ban=true", token));
+
+    let data = reviewed(None);
+    let token = create(&data, serde_json::json!({ "content": "spam" })).await["token"].as_str().unwrap().to_string();
+    assert_eq!(call(&data, reject(&token)).await.status, StatusCode::BAD_REQUEST);
+    // Still pending, nothing was done
+    assert_eq!(data.store.pending_pastes(10).unwrap().len(), 1);
+
+    let data = reviewed(Some("salt"));
+    let token = create(&data, serde_json::json!({ "content": "spam" })).await["token"].as_str().unwrap().to_string();
+    assert_eq!(call(&data, reject(&token)).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(data.store.bans().unwrap().len(), 1);
+    let answer = call(&data, create_request(serde_json::json!({ "content": "more spam" }))).await;
+    assert_eq!(answer.status, StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,112 @@
+// `export_static::export` of a small database on SQLite: the pastes that are exported and the ones that aren't, the
+// pages compared with the ones “/paste/{token}” answers for the same pastes, and an export run again over the first.
+
+use super::*;
+use std::fs;
+use std::path::Path;
+
+// The part of `page` from `start` up to `end`, both included.
+fn section<'a>(page: &'a str, start: &str, end: &str) -> &'a str {
+    let from = page.find(start).unwrap_or_else(|| panic!("No {:?} in {}", start, page));
+    let to = from + page[from..].find(end).unwrap_or_else(|| panic!("No {:?} after {:?} in {}", end, start, page));
+    &page[from..to + end.len()]
+}
+
+// `page` as it is written for the export, from what the server answers.
+fn without_versions(page: &str) -> String {
+    let mut page = page.to_string();
+    while let Some(start) = page.find("?v=") {
+        let end = start + page[start..].find('"').unwrap();
+        page.replace_range(start..end, "");
+    }
+    page.replace("\"/static/", "\"../static/")
+}
+
+async fn create_token(data: &web::Data<AppState>, paste: serde_json::Value) -> String {
+    create(data, paste).await["token"].as_str().unwrap().to_string()
+}
+
+#[actix_rt::test]
+async fn the_exported_pages_are_the_ones_of_the_server() {
+    let dir = std::env::temp_dir().join(format!("pastry-tests-export-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("pastry.db");
+    let store = crate::store::sqlite::SqliteStore::open(&path, Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+    let data = state_with_store(Config::default(), Box::new(store));
+
+    let code = "fn main() {\n    println!(\"<exported> & \\\"quoted\\\"\");\n}\n";
+    let exported = [
+        create_token(&data, serde_json::json!({ "content": code, "public": true, "title": "Main", "tags": ["rust"] }))
+            .await,
+        create_token(&data, serde_json::json!({ "content": "plain words\nover two lines", "public": true })).await,
+        create_token(&data, serde_json::json!({ "content": "AAEC/w==", "encoding": "base64", "public": true })).await,
+    ];
+    let private = create_token(&data, serde_json::json!({ "content": "private" })).await;
+    let link = serde_json::json!({ "content": "https://example.com/", "type": "redirect", "public": true });
+    let link = create_token(&data, link).await;
+    let expired = create_token(&data, serde_json::json!({ "content": "expired", "public": true })).await;
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute("UPDATE pastes SET expires_at = 1 WHERE token = ?", [&expired]).unwrap();
+
+    let out = dir.join("site");
+    let export = crate::export_static::export(&data, &out, None).unwrap();
+    assert_eq!(export.pastes, 3);
+    assert_eq!(export.unchanged, 0);
+
+    // A second export over the first writes nothing, one after a change rewrites that paste alone. Before the pages
+    // are seen below, each view changes the count on a page
+    let again = crate::export_static::export(&data, &out, None).unwrap();
+    assert_eq!((again.pastes, again.written, again.unchanged), (3, 0, export.written));
+    conn.execute("UPDATE pastes SET title = 'Renamed' WHERE token = ?", [&exported[1]]).unwrap();
+    data.cache.remove(&exported[1]);
+    let changed = crate::export_static::export(&data, &out, None).unwrap();
+    assert_eq!(changed.written, 2, "the page of the paste and the index");
+
+    let index = fs::read_to_string(out.join("index.html")).unwrap();
+    for token in &exported {
+        assert!(index.contains(&format!("<a href=\"paste/{}.html\">", token)), "{}: {}", token, index);
+    }
+    for token in [&private, &link, &expired] {
+        assert!(!index.contains(token.as_str()), "{}: {}", token, index);
+        assert!(!out.join("paste").join(format!("{}.html", token)).exists(), "{}", token);
+    }
+
+    for token in &exported {
+        let file = fs::read_to_string(out.join("paste").join(format!("{}.html", token))).unwrap();
+        let live = call(&data, request().uri(&format!("/paste/{}", token))).await;
+        assert_eq!(live.status, StatusCode::OK);
+        let live = without_versions(&live.text());
+
+        // What is read of the paste is the server's to the byte, the raw and download links aside
+        let raw = if token == &exported[2] { format!("{}.bin", token) } else { format!("{}.txt", token) };
+        let content = section(&live, "<div class=\"paste-content mb-6\">", "<footer")
+            .replace(&format!("href=\"/paste/{}/raw\"", token), &format!("href=\"{}\"", raw))
+            .replace(&format!("href=\"/paste/{}/download\"", token), &format!("href=\"{}\"", raw));
+        assert_eq!(section(&file, "<div class=\"paste-content mb-6\">", "<footer"), content, "{}", token);
+        for (start, end) in [("<!DOCTYPE html>", "</title>"), ("<link href=", "</h2>")] {
+            assert_eq!(section(&file, start, end), section(&live, start, end), "{}", token);
+        }
+
+        let stored = fs::read(out.join("paste").join(&raw)).unwrap();
+        let answer = call(&data, request().uri(&format!("/paste/{}/raw", token))).await;
+        assert_eq!(&stored[..], &answer.body[..], "{}", token);
+    }
+    let code_page = fs::read_to_string(out.join("paste").join(format!("{}.html", exported[0]))).unwrap();
+    assert!(code_page.contains("<h1 class=\"paste-title\">Main</h1>"), "{}", code_page);
+    assert!(code_page.contains("<span class=\"tag\">rust</span>"), "{}", code_page);
+    // Served with no policy of the server, the files hold the content escaped
+    assert!(code_page.contains("println!(&quot;&lt;exported&gt; &amp; \\&quot;quoted\\&quot;&quot;);"), "{}", code_page);
+    assert!(!code_page.contains("<exported>"), "{}", code_page);
+
+    // Every asset the pages link is there, relatively
+    for page in [&index, &code_page] {
+        assert!(!page.contains("\"/static/"), "{}", page);
+    }
+    for asset in section(&code_page, "<head>", "</head>").split("static/").skip(1) {
+        let file = &asset[..asset.find('"').unwrap()];
+        assert!(Path::new(&out.join("static").join(file)).is_file(), "{}", file);
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
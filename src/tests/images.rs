@@ -0,0 +1,211 @@
+// The images of paste pages and the proxy of `images.rs`. `fetch` is tried against a server on localhost standing in
+// for the internet, the one address it is let to connect to, which answers with redirects to localhost and images too
+// big; the route itself with the checks of a real server, which refuses localhost.
+
+use super::*;
+use crate::error::ErrorCode;
+use crate::images::{self, Limits};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The `max_bytes` of the tests.
+const MAX_BYTES: usize = 1024;
+
+// A server answering each request with what `answer` makes of its path, on a thread of its own.
+fn serve(answer: impl Fn(&str) -> Vec<u8> + Send + 'static) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let path = read_path(&mut stream);
+            let _ = stream.write_all(&answer(&path));
+        }
+    });
+    address
+}
+
+// The path of the request coming in on `stream`, once its headers are read.
+fn read_path(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    head.split(' ').nth(1).unwrap_or("/").to_string()
+}
+
+fn answer(status: &str, headers: &[(&str, String)], body: &[u8]) -> Vec<u8> {
+    let mut answer = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for (name, value) in headers {
+        answer.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    answer.push_str("\r\n");
+    let mut answer = answer.into_bytes();
+    answer.extend_from_slice(body);
+    answer
+}
+
+// A server on localhost that nothing may reach, and how many connections it got.
+fn private_server() -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counted = connections.clone();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            counted.fetch_add(1, Ordering::SeqCst);
+            read_path(&mut stream);
+            let _ = stream.write_all(&answer("200 OK", &[("Content-Type", "image/png".to_string())], b"secret"));
+        }
+    });
+    (port, connections)
+}
+
+// The server of the images of the tests, with those redirecting to `private`.
+fn image_server(private: u16) -> SocketAddr {
+    serve(move |path| {
+        let redirect = |location: String| answer("302 Found", &[("Location", location), ("Content-Length", "0".to_string())], b"");
+        let png = |body: &[u8]| {
+            answer("200 OK", &[("Content-Type", "image/png".to_string()), ("Content-Length", body.len().to_string())], body)
+        };
+        match path {
+            "/cat.png" => png(b"\x89PNG cat"),
+            "/moved.png" => redirect("/cat.png".to_string()),
+            "/to-localhost.png" => redirect(format!("http://localhost:{}/secret.png", private)),
+            "/to-loopback.png" => redirect(format!("http://127.0.0.1:{}/secret.png", private)),
+            "/to-mapped.png" => redirect(format!("http://[::ffff:127.0.0.1]:{}/secret.png", private)),
+            "/loop.png" => redirect("/loop.png".to_string()),
+            "/big.png" => answer(
+                "200 OK",
+                &[("Content-Type", "image/png".to_string()), ("Content-Length", (MAX_BYTES + 1).to_string())],
+                &vec![0; MAX_BYTES + 1],
+            ),
+            // Without a length, in chunks smaller than the limit
+            "/big-chunked.png" => {
+                let chunk = format!("{:x}\r\n{}\r\n", MAX_BYTES / 2, "x".repeat(MAX_BYTES / 2));
+                let body = format!("{}0\r\n\r\n", chunk.repeat(4));
+                answer(
+                    "200 OK",
+                    &[("Content-Type", "image/png".to_string()), ("Transfer-Encoding", "chunked".to_string())],
+                    body.as_bytes(),
+                )
+            }
+            "/drawing.svg" => answer("200 OK", &[("Content-Type", "image/svg+xml".to_string())], b"<svg onload=\"alert(1)\"/>"),
+            _ => answer("404 Not Found", &[("Content-Length", "0".to_string())], b""),
+        }
+    })
+}
+
+fn limits() -> Limits {
+    Limits {
+        allowlist: Vec::new(),
+        max_bytes: MAX_BYTES,
+    }
+}
+
+// `fetch` of `path` on `server`, the one address it may connect to.
+async fn fetch(server: SocketAddr, path: &str, limits: &Limits) -> Result<images::Image, AppError> {
+    images::fetch(&format!("http://{}{}", server, path), limits, move |address: SocketAddr| address == server).await
+}
+
+#[actix_rt::test]
+async fn an_image_is_fetched_through_its_redirects() {
+    let (private, _) = private_server();
+    let server = image_server(private);
+    for path in ["/cat.png", "/moved.png"] {
+        let image = fetch(server, path, &limits()).await.unwrap_or_else(|e| panic!("{}: {}", path, e.message));
+        assert_eq!(image.content_type, "image/png");
+        assert_eq!(&image.body[..], b"\x89PNG cat");
+    }
+}
+
+#[actix_rt::test]
+async fn a_redirect_to_localhost_is_refused() {
+    let (private, connections) = private_server();
+    let server = image_server(private);
+    for path in ["/to-localhost.png", "/to-loopback.png", "/to-mapped.png"] {
+        let error = fetch(server, path, &limits()).await.err().unwrap_or_else(|| panic!("{} was fetched", path));
+        assert_eq!(error.code, ErrorCode::Forbidden, "{}: {}", path, error.message);
+    }
+    let error = fetch(server, "/loop.png", &limits()).await.err().unwrap();
+    assert_eq!(error.code, ErrorCode::Forbidden, "{}", error.message);
+    assert_eq!(connections.load(Ordering::SeqCst), 0);
+}
+
+#[actix_rt::test]
+async fn an_oversized_image_is_refused() {
+    let (private, _) = private_server();
+    let server = image_server(private);
+    for path in ["/big.png", "/big-chunked.png"] {
+        let error = fetch(server, path, &limits()).await.err().unwrap_or_else(|| panic!("{} was fetched", path));
+        assert_eq!(error.code, ErrorCode::PayloadTooLarge, "{}: {}", path, error.message);
+    }
+}
+
+#[actix_rt::test]
+async fn only_pictures_from_the_allowlist_are_fetched() {
+    let (private, _) = private_server();
+    let server = image_server(private);
+    let error = fetch(server, "/drawing.svg", &limits()).await.err().unwrap();
+    assert_eq!(error.code, ErrorCode::UnsupportedMediaType, "{}", error.message);
+
+    let listed = Limits {
+        allowlist: images::parse_allowlist("images.example, 127.0.0.1"),
+        ..limits()
+    };
+    assert!(fetch(server, "/cat.png", &listed).await.is_ok());
+    let unlisted = Limits {
+        allowlist: images::parse_allowlist("images.example"),
+        ..limits()
+    };
+    let error = fetch(server, "/cat.png", &unlisted).await.err().unwrap();
+    assert_eq!(error.code, ErrorCode::Forbidden, "{}", error.message);
+}
+
+#[actix_rt::test]
+async fn the_proxy_follows_the_policy() {
+    let (private, connections) = private_server();
+    let url = format!("/imgproxy?url=http%3A%2F%2F127.0.0.1%3A{}%2Fsecret.png", private);
+    assert_eq!(call(&state(), request().uri(&url)).await.status, StatusCode::NOT_FOUND);
+
+    let data = state_with(Config {
+        image_policy: Some("proxy".to_string()),
+        ..Config::default()
+    });
+    let answer = call(&data, request().uri(&url)).await;
+    assert_eq!(answer.status, StatusCode::FORBIDDEN, "{}", answer.text());
+    let answer = call(&data, request().uri("/imgproxy?url=ftp%3A%2F%2Fimages.example%2Fcat.png")).await;
+    assert_eq!(answer.status, StatusCode::BAD_REQUEST, "{}", answer.text());
+    assert_eq!(connections.load(Ordering::SeqCst), 0);
+
+    // The page of a paste loads its images from the proxy, and from nowhere with the default policy
+    for (data, image) in [(data, Some("/imgproxy?url=https%3A%2F%2Fimages%2Eexample%2Fcat%2Epng")), (state(), None)] {
+        let paste = create(&data, serde_json::json!({ "content": "look: https://images.example/cat.png" })).await;
+        let page = call(&data, request().uri(&format!("/paste/{}?links=true", paste["token"].as_str().unwrap()))).await;
+        let page = page.text();
+        match image {
+            Some(source) => assert!(page.contains(&format!("<img class=\"image\" src=\"{}\"", source)), "{}", page),
+            None => assert!(!page.contains("<img class=\"image\""), "{}", page),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn an_image_tag_in_a_paste_is_text_whatever_the_policy() {
+    for policy in ["strip", "render", "proxy"] {
+        let data = state_with(Config {
+            image_policy: Some(policy.to_string()),
+            ..Config::default()
+        });
+        let content = "<img src=\"https://tracker.example/pixel.gif?reader=1\">";
+        let paste = create(&data, serde_json::json!({ "content": content })).await;
+        let token = paste["token"].as_str().unwrap();
+        for uri in [format!("/paste/{}", token), format!("/paste/{}?links=true", token)] {
+            let page = call(&data, request().uri(&uri)).await.text();
+            assert!(page.contains("&lt;img src=&quot;"), "{} {}: {}", policy, uri, page);
+            assert!(!page.contains("<img src=\"https://tracker"), "{} {}: {}", policy, uri, page);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// `POST /admin/integrity-check` over SQLite with blob files: each kind of orphan is planted next to pastes that are
+// fine, found by the check, and removed by `?repair=true` without touching the rows of the pastes still there.
+
+use super::*;
+use crate::store::blobs::BlobStore;
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+// The token no paste nor collection has.
+const GONE: &str = "gone0000";
+
+// Contents above this go to blob files in the test.
+const THRESHOLD: usize = 64;
+
+fn check_request(repair: bool) -> TestRequest {
+    let uri = if repair { "/admin/integrity-check?repair=true" } else { "/admin/integrity-check" };
+    admin_request().method(Method::POST).uri(uri)
+}
+
+// The `found` and `removed` of the orphans of `kind` in `report`.
+fn orphans(report: &serde_json::Value, kind: &str) -> (u64, u64) {
+    let found = report["orphans"].as_array().unwrap().iter().find(|found| found["kind"] == kind).unwrap();
+    (found["found"].as_u64().unwrap(), found["removed"].as_u64().unwrap())
+}
+
+fn count(conn: &Connection, sql: &str) -> i64 {
+    conn.query_row(sql, [], |row| row.get(0)).unwrap()
+}
+
+// Rows of `GONE` in every table naming a paste or a collection, and the row of its search index.
+fn plant_rows(conn: &Connection) {
+    conn.execute_batch(&format!(
+        "INSERT INTO paste_search_tokens (token) VALUES ('{gone}');
+         INSERT INTO paste_search (rowid, content)
+             SELECT id, 'orphaned words' FROM paste_search_tokens WHERE token = '{gone}';
+         INSERT INTO paste_tags (token, tag) VALUES ('{gone}', 'rust');
+         INSERT INTO paste_views_daily (token, day, views) VALUES ('{gone}', '2026-01-01', 3);
+         INSERT INTO comments (token, author, body, created_at) VALUES ('{gone}', 'someone', 'hello', 0);
+         INSERT INTO paste_gists (token, status, updated_at) VALUES ('{gone}', 'done', 0);
+         INSERT INTO collection_items (collection, position, paste) VALUES ('{gone}', 0, '{gone}');",
+        gone = GONE
+    ))
+    .unwrap();
+}
+
+// A file in the blobs directory no paste refers to, older than the grace of the sweep.
+fn plant_blob(blobs: &Path) -> String {
+    let hash = "ab".repeat(32);
+    let dir = blobs.join(&hash[..2]);
+    fs::create_dir_all(&dir).unwrap();
+    let file = fs::File::create(dir.join(&hash)).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60)).unwrap();
+    hash
+}
+
+#[actix_rt::test]
+async fn each_kind_of_orphan_is_found_and_repaired() {
+    let dir = std::env::temp_dir().join(format!("pastry-tests-integrity-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let blobs = dir.join("blobs");
+    fs::create_dir_all(&blobs).unwrap();
+    let path = dir.join("pastry.db");
+    let sqlite = crate::store::sqlite::SqliteStore::open(&path, Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+    let store = BlobStore::new(Box::new(sqlite), &blobs, THRESHOLD).unwrap();
+    let data = state_with_store(admin_config(), Box::new(store));
+
+    // Pastes that are fine, with rows of each kind of their own, and two that aren't
+    let kept = create(&data, serde_json::json!({ "content": "kept words", "public": true, "tags": ["rust"] })).await;
+    let kept = kept["token"].as_str().unwrap().to_string();
+    assert_eq!(call(&data, request().uri(&format!("/paste/{}", kept))).await.status, StatusCode::OK);
+    let resized = create(&data, serde_json::json!({ "content": "resized" })).await["token"].as_str().unwrap().to_string();
+    let large = create(&data, serde_json::json!({ "content": "large ".repeat(THRESHOLD) })).await;
+    let large = large["token"].as_str().unwrap().to_string();
+
+    let conn = Connection::open(&path).unwrap();
+    plant_rows(&conn);
+    let stray = plant_blob(&blobs);
+    conn.execute("UPDATE pastes SET byte_size = 1, content_hash = ? WHERE token = ?", params!["0".repeat(64), resized])
+        .unwrap();
+    let blob: String = conn.query_row("SELECT blob FROM pastes WHERE token = ?", params![large], |row| row.get(0)).unwrap();
+    fs::remove_file(blobs.join(&blob[..2]).join(&blob)).unwrap();
+
+    let answer = call(&data, check_request(false)).await;
+    assert_eq!(answer.status, StatusCode::OK, "{}", answer.text());
+    let report = answer.json();
+    for kind in ["search_index", "tags", "daily_views", "comments", "gists", "collection_items", "blob_files"] {
+        assert_eq!(orphans(&report, kind), (1, 0), "{}: {}", kind, report);
+    }
+    assert_eq!(report["missing_blobs"], 1, "{}", report);
+    assert_eq!(report["size_mismatches"], 1, "{}", report);
+    assert_eq!(report["hash_mismatches"], 1, "{}", report);
+    assert_eq!(report["unreadable_pastes"], 1, "{}", report);
+    assert_eq!(report["pastes_fixed"], 0, "{}", report);
+    // Only reported, nothing is removed
+    assert_eq!(count(&conn, &format!("SELECT COUNT(*) FROM paste_tags WHERE token = '{}'", GONE)), 1);
+    assert!(blobs.join(&stray[..2]).join(&stray).exists());
+
+    let report = call(&data, check_request(true)).await.json();
+    for kind in ["search_index", "tags", "daily_views", "comments", "gists", "collection_items", "blob_files"] {
+        assert_eq!(orphans(&report, kind), (1, 1), "{}: {}", kind, report);
+    }
+    assert_eq!(report["pastes_fixed"], 1, "{}", report);
+    for table in ["paste_search_tokens", "paste_tags", "paste_views_daily", "comments", "paste_gists"] {
+        assert_eq!(count(&conn, &format!("SELECT COUNT(*) FROM {} WHERE token = '{}'", table, GONE)), 0, "{}", table);
+    }
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM collection_items"), 0);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM paste_search WHERE paste_search MATCH 'orphaned'"), 0);
+    assert!(!blobs.join(&stray[..2]).join(&stray).exists());
+    // The rows of the paste that was fine are all there, and the other got its size and hash back
+    assert_eq!(count(&conn, &format!("SELECT COUNT(*) FROM paste_tags WHERE token = '{}'", kept)), 1);
+    assert_eq!(count(&conn, &format!("SELECT COUNT(*) FROM paste_views_daily WHERE token = '{}'", kept)), 1);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM paste_search WHERE paste_search MATCH 'kept'"), 1);
+    assert_eq!(count(&conn, &format!("SELECT byte_size FROM pastes WHERE token = '{}'", resized)), 7);
+    let raw = call(&data, request().uri(&format!("/paste/{}/raw", resized))).await;
+    assert_eq!(raw.text(), "resized");
+
+    // What is left is the paste whose blob file is gone, there is nothing to bring it back from
+    let report = call(&data, check_request(true)).await.json();
+    for kind in ["search_index", "tags", "daily_views", "comments", "gists", "collection_items", "blob_files"] {
+        assert_eq!(orphans(&report, kind), (0, 0), "{}: {}", kind, report);
+    }
+    assert_eq!((report["missing_blobs"].as_u64(), report["unreadable_pastes"].as_u64()), (Some(1), Some(1)), "{}", report);
+    assert_eq!((report["size_mismatches"].as_u64(), report["hash_mismatches"].as_u64()), (Some(0), Some(0)), "{}", report);
+
+    // Each run is in the audit log, with its counts
+    let audit = call(&data, admin_request().uri("/admin/audit?action=integrity_check&format=json")).await.json();
+    let details: Vec<&str> = audit.as_array().unwrap().iter().map(|entry| entry["detail"].as_str().unwrap()).collect();
+    assert_eq!(details.len(), 3, "{:?}", details);
+    assert!(details.iter().any(|detail| detail.contains("1 tags (1 removed)")), "{:?}", details);
+    assert!(details.iter().any(|detail| detail.starts_with("orphans: none; 1 missing blobs")), "{:?}", details);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[actix_rt::test]
+async fn the_check_is_for_admins() {
+    let data = state_with(admin_config());
+    let answer = call(&data, request().method(Method::POST).uri("/admin/integrity-check?repair=true")).await;
+    assert_eq!(answer.status, StatusCode::UNAUTHORIZED, "{}", answer.text());
+    let report = call(&data, check_request(true)).await.json();
+    assert_eq!(report["repair"], true);
+    assert_eq!(report["pastes_checked"], 0);
+}
@@ -0,0 +1,81 @@
+// The language of the pages, see `i18n.rs`: the `lang` cookie the switcher sets, then `Accept-Language`, then
+// English, while the content of the pastes stays as it was written.
+
+use super::*;
+
+// The page at `uri` asked for with `headers`.
+async fn page(data: &web::Data<AppState>, uri: &str, headers: &[(&str, &str)]) -> Answer {
+    let mut request = request().uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    call(data, request).await
+}
+
+const GERMAN_TAGLINE: &str = "Ein minimalistischer Pastebin, neu geschrieben in Rust!";
+
+#[actix_rt::test]
+async fn the_index_page_speaks_the_language_asked_for() {
+    let data = state();
+    let german = page(&data, "/", &[("Accept-Language", "de-AT,de;q=0.9,en;q=0.8")]).await.text();
+    assert!(german.contains("<html lang=\"de\">"), "{}", german);
+    assert!(german.contains(GERMAN_TAGLINE), "{}", german);
+
+    let english_asked: [&[(&str, &str)]; 3] =
+        [&[("Accept-Language", "fr-FR,fr;q=0.9")], &[], &[("Accept-Language", "de"), ("Cookie", "lang=en")]];
+    for headers in english_asked {
+        let english = page(&data, "/", headers).await.text();
+        assert!(english.contains("<html lang=\"en\">"), "{:?}: {}", headers, english);
+        assert!(!english.contains(GERMAN_TAGLINE), "{:?}", headers);
+    }
+    let cookie = page(&data, "/", &[("Accept-Language", "en"), ("Cookie", "lang=de")]).await.text();
+    assert!(cookie.contains("<html lang=\"de\">"), "{}", cookie);
+}
+
+#[actix_rt::test]
+async fn the_switcher_sets_the_cookie() {
+    let data = state();
+    let switch = |lang: &str, back: &str| {
+        request()
+            .method(Method::POST)
+            .uri("/language")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .set_payload(format!("lang={}&back={}", lang, back))
+    };
+    let answer = call(&data, switch("de", "%2Ftags")).await;
+    assert_eq!(answer.status, StatusCode::SEE_OTHER, "{}", answer.text());
+    assert_eq!(answer.header("Location"), Some("/tags"));
+    let cookie = answer.header("Set-Cookie").unwrap().to_string();
+    assert!(cookie.starts_with("lang=de;"), "{}", cookie);
+
+    // The cookie it set is the one the pages follow
+    let tags = page(&data, "/tags", &[("Cookie", cookie.split(';').next().unwrap())]).await.text();
+    assert!(tags.contains("<html lang=\"de\">"), "{}", tags);
+
+    // Only back to this site
+    let answer = call(&data, switch("en", "%2F%2Fevil.example")).await;
+    assert_eq!(answer.header("Location"), Some("/"));
+    let answer = call(&data, switch("fr", "%2F")).await;
+    assert_eq!(answer.status, StatusCode::BAD_REQUEST, "{}", answer.text());
+}
+
+#[actix_rt::test]
+async fn errors_are_translated_but_not_pastes() {
+    let data = state();
+    let missing = page(&data, "/paste/nothere00", &[("Accept-Language", "de")]).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    assert!(missing.text().contains("<html lang=\"de\">"), "{}", missing.text());
+    // The API stays in English
+    let api = call(&data, request().uri("/api/pastes/nothere00").header("Accept-Language", "de")).await;
+    assert_eq!(api.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.json()["error"]["message"], "Paste not found", "{}", api.text());
+
+    let content = "Never expires, {{lang}} {{t:index.tagline}}";
+    let paste = create(&data, serde_json::json!({ "content": content })).await;
+    let token = paste["token"].as_str().unwrap();
+    let view = page(&data, &format!("/paste/{}", token), &[("Accept-Language", "de")]).await.text();
+    assert!(view.contains("<html lang=\"de\">"), "{}", view);
+    assert!(view.contains(&crate::escape_html(content)), "{}", view);
+    let raw = page(&data, &format!("/paste/{}/raw", token), &[("Accept-Language", "de")]).await;
+    assert_eq!(raw.text(), content);
+}
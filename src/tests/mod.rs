@@ -0,0 +1,176 @@
+// The tests of the app: each one builds it over a `MemoryStore`, like a worker of `--ephemeral` does, and sends it
+// requests through `actix_web::test`, without a socket. The tests of the pure modules are next to their code.
+
+mod api_errors;
+mod bans;
+mod export_static;
+mod images;
+mod integrity;
+mod languages;
+mod openapi;
+mod paste_pages;
+mod placeholders;
+mod quotas;
+mod raw_headers;
+mod reserved;
+mod store_contract;
+mod submit;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod titles;
+mod transactions;
+
+use crate::store::memory::MemoryStore;
+use crate::*;
+use actix_web::http::Method;
+use actix_web::test::{self, TestRequest};
+
+// The address requests come from, unless a test says otherwise.
+pub const PEER: &str = "203.0.113.7:40000";
+
+// The admin token of `admin_config`.
+pub const ADMIN_TOKEN: &str = "test admin token";
+
+// The state of a server with the default configuration.
+pub fn state() -> web::Data<AppState> {
+    state_with(Config::default())
+}
+
+// A configuration with the admin routes on, `ADMIN_TOKEN` being their token.
+pub fn admin_config() -> Config {
+    Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..Config::default()
+    }
+}
+
+// The state of a server started with `config`, the settings not in it default like they do without the variables.
+pub fn state_with(config: Config) -> web::Data<AppState> {
+    let memory = MemoryStore::new(PASTRY_MEMORY_MAX_PASTES, PASTRY_MEMORY_MAX_BYTES);
+    state_with_store(config, Box::new(memory))
+}
+
+// The state of a server started with `config` over `store` instead of a `MemoryStore`.
+pub fn state_with_store(config: Config, store: Box<dyn PasteStore>) -> web::Data<AppState> {
+    let query_timings = Arc::new(QueryTimings::default());
+    web::Data::new(AppState {
+        store: Arc::new(TimedStore::new(store, query_timings.clone(), Duration::from_secs(60))),
+        admin_token: config.admin_token.clone(),
+        backup_dir: std::env::temp_dir().join("pastry-tests-backups"),
+        backup_lock: Mutex::new(()),
+        settings: RwLock::new(Arc::new(Settings::resolve(&config))),
+        ip_salt: config.ip_salt.clone().unwrap_or_else(|| "test salt".to_string()),
+        ip_salt_configured: config.ip_salt.is_some(),
+        cookie_secret: "test cookie secret".to_string(),
+        validations: Mutex::new(day_counts::DayCounts::default()),
+        token_uses: Mutex::new(api_tokens::LastUsed::default()),
+        idempotency: Mutex::new(idempotency::Keys::default()),
+        bans: RwLock::new(crate::bans::BanList::default()),
+        announcements: RwLock::new(announcements::Board::default()),
+        trusted_proxies: Vec::new(),
+        cache: PasteCache::new(PASTRY_CACHE_MAX_ENTRIES, PASTRY_CACHE_MAX_BYTES),
+        query_timings,
+        gist: None,
+        captcha: None,
+        federation_token: None,
+        disk: disk::Monitor::new(Vec::new(), Duration::ZERO),
+        handoffs: Mutex::new(handoff::Handoffs::default()),
+        database_read_only: false,
+    })
+}
+
+// What the server answered to a request.
+pub struct Answer {
+    pub status: StatusCode,
+    pub headers: actix_web::http::HeaderMap,
+    pub body: web::Bytes,
+}
+
+impl Answer {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    // The `Content-Type`, empty without one.
+    pub fn content_type(&self) -> &str {
+        self.header("Content-Type").unwrap_or("")
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8(self.body.to_vec()).unwrap()
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| panic!("{}: {}", e, self.text()))
+    }
+}
+
+// Sends `request` to the app of a worker over `data`. Everything a server keeps between requests lives in `data`,
+// so a test sends several requests to the same server by passing the same one.
+pub async fn call(data: &web::Data<AppState>, request: TestRequest) -> Answer {
+    assets::init(None);
+    i18n::init();
+    let setup = AppSetup {
+        internal_addrs: Vec::new(),
+        request_timeout: Duration::from_secs(PASTRY_REQUEST_TIMEOUT_SECS),
+        max_form_bytes: max_form_bytes(data.settings().max_paste_bytes),
+        max_json_bytes: max_json_bytes(data.settings().max_paste_bytes),
+        assets_dir: None,
+    };
+    let mut service = test::init_service(app(data.clone(), &setup)).await;
+    match service.call(request.to_request()).await {
+        Ok(response) => Answer {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: test::read_body(response).await,
+        },
+        // A middleware's error, which the server answers itself as its `error_response`
+        Err(e) => {
+            let mut response = HttpResponse::from_error(e);
+            let body = match response.take_body() {
+                actix_web::dev::ResponseBody::Body(Body::Bytes(bytes)) => bytes,
+                _ => web::Bytes::new(),
+            };
+            Answer {
+                status: response.status(),
+                headers: response.headers().clone(),
+                body,
+            }
+        }
+    }
+}
+
+// A request from `PEER`.
+pub fn request() -> TestRequest {
+    TestRequest::default().peer_addr(PEER.parse().unwrap())
+}
+
+// A request of the admin, with `ADMIN_TOKEN`.
+pub fn admin_request() -> TestRequest {
+    request().header("Authorization", format!("Bearer {}", ADMIN_TOKEN))
+}
+
+// `POST /api/pastes` of `body`, a JSON `ApiNewPaste`.
+pub fn create_request(body: serde_json::Value) -> TestRequest {
+    request()
+        .method(Method::POST)
+        .uri("/api/pastes")
+        .header("Content-Type", "application/json")
+        .set_payload(body.to_string())
+}
+
+// `POST /submit` of the form fields `fields`, as a browser sends them.
+pub fn submit_request(fields: &[(&str, &str)]) -> TestRequest {
+    request()
+        .method(Method::POST)
+        .uri("/submit")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .set_payload(serde_urlencoded::to_string(fields).unwrap())
+}
+
+// Creates a paste through the API, and returns what it answered: its token, secret and URLs.
+pub async fn create(data: &web::Data<AppState>, body: serde_json::Value) -> serde_json::Value {
+    let answer = call(data, create_request(body)).await;
+    assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+    answer.json()
+}
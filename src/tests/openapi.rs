@@ -0,0 +1,156 @@
+// The OpenAPI document of `openapi.rs`: that it is a well-formed OpenAPI 3.0 document, and that it describes the
+// routes of the `/api` scope, no more and no fewer (`openapi::route` refuses the routes it misses at startup).
+// The document is checked by hand, the parts clients rely on: the version, that each `$ref` resolves, the answers and
+// parameter schemas. It isn't validated against the OpenAPI 3.0 meta-schema, which isn't embedded in the tree; a
+// field the specification doesn't know, or a wrong type of one these tests don't read, would go unnoticed.
+
+use super::*;
+use serde_json::Value;
+use std::collections::HashSet;
+
+const METHODS: &[&str] = &["get", "put", "post", "delete", "head", "patch", "options"];
+
+// The value a `$ref` of the document points to, which has to be in it.
+fn resolve<'a>(document: &'a Value, value: &'a Value) -> &'a Value {
+    match value.get("$ref").and_then(Value::as_str) {
+        Some(reference) => {
+            let pointer = reference.strip_prefix('#').unwrap_or_else(|| panic!("{} isn't in the document", reference));
+            document.pointer(pointer).unwrap_or_else(|| panic!("{} points to nothing", reference))
+        }
+        None => value,
+    }
+}
+
+// Every `$ref` under `value`.
+fn references<'a>(value: &'a Value, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            if map.contains_key("$ref") {
+                found.push(value);
+            }
+            map.values().for_each(|value| references(value, found));
+        }
+        Value::Array(items) => items.iter().for_each(|value| references(value, found)),
+        _ => {}
+    }
+}
+
+// The operations of the document, as their path, method and operation.
+fn operations(document: &Value) -> Vec<(&str, &str, &Value)> {
+    let mut operations = Vec::new();
+    for (path, item) in document["paths"].as_object().unwrap() {
+        for (method, operation) in item.as_object().unwrap() {
+            if METHODS.contains(&method.as_str()) {
+                operations.push((path.as_str(), method.as_str(), operation));
+            }
+        }
+    }
+    operations
+}
+
+#[test]
+fn the_document_is_valid_openapi() {
+    let document = crate::openapi::document();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3.0."));
+    assert!(document["info"]["title"].is_string());
+    assert!(document["info"]["version"].is_string());
+
+    let mut found = Vec::new();
+    references(document, &mut found);
+    assert!(!found.is_empty());
+    for reference in found {
+        resolve(document, reference);
+    }
+
+    let schemes = document["components"]["securitySchemes"].as_object().unwrap();
+    let mut ids = HashSet::new();
+    for (path, method, operation) in operations(document) {
+        let name = format!("{} {}", method, path);
+        assert!(path.starts_with("/api/"), "{}", name);
+        let id = operation["operationId"].as_str().unwrap_or_else(|| panic!("{} has no operationId", name));
+        assert!(ids.insert(id), "{}: operationId {} is taken", name, id);
+
+        let responses = operation["responses"].as_object().unwrap();
+        assert!(!responses.is_empty(), "{}", name);
+        for (status, response) in responses {
+            let valid = status == "default" || status.parse::<u16>().is_ok_and(|code| (100..600).contains(&code));
+            assert!(valid, "{}: status {}", name, status);
+            assert!(resolve(document, response)["description"].is_string(), "{}: {}", name, status);
+        }
+
+        for requirement in operation["security"].as_array().unwrap() {
+            for scheme in requirement.as_object().unwrap().keys() {
+                assert!(schemes.contains_key(scheme), "{}: scheme {}", name, scheme);
+            }
+        }
+        if let Some(scope) = operation.get("x-pastry-scope") {
+            assert!(api_tokens::parse_scopes(&[scope.as_str().unwrap().to_string()]).is_ok(), "{}", name);
+        }
+
+        // The parameters of the path item and those of the operation
+        let parameters: Vec<&Value> = [&document["paths"][path]["parameters"], &operation["parameters"]]
+            .iter()
+            .filter_map(|parameters| parameters.as_array())
+            .flatten()
+            .map(|parameter| resolve(document, parameter))
+            .collect();
+        for parameter in &parameters {
+            let place = parameter["in"].as_str().unwrap();
+            assert!(["query", "header", "path", "cookie"].contains(&place), "{}: in {}", name, place);
+            assert!(parameter["name"].is_string(), "{}", name);
+            assert!(parameter.get("schema").is_some() || parameter.get("content").is_some(), "{}", name);
+        }
+        let templated: HashSet<&str> = path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+            .collect();
+        let in_path: HashSet<&str> = parameters
+            .iter()
+            .filter(|parameter| parameter["in"] == "path")
+            .inspect(|parameter| assert_eq!(parameter["required"], true, "{}", name))
+            .map(|parameter| parameter["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(in_path, templated, "{}", name);
+
+        if let Some(body) = operation.get("requestBody") {
+            assert!(resolve(document, body)["content"].is_object(), "{}", name);
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn the_document_is_served() {
+    let answer = call(&state(), request().uri("/api/openapi.json")).await;
+    assert_eq!(answer.status, StatusCode::OK);
+    assert_eq!(&answer.json(), crate::openapi::document());
+}
+
+// Each operation of the document reaches a handler of its own rather than the fallback of the scope, whatever the
+// handler then makes of a request without a body or token.
+#[actix_rt::test]
+async fn every_operation_is_routed() {
+    let data = state();
+    let fallback = call(&data, request().uri("/api/nosuchroute")).await;
+    assert_eq!(fallback.status, StatusCode::NOT_FOUND);
+    let fallback = fallback.json()["error"]["message"].clone();
+
+    for (path, method, _) in operations(crate::openapi::document()) {
+        let uri = path
+            .split('/')
+            .map(|segment| if segment.starts_with('{') { "nosuchpaste" } else { segment })
+            .collect::<Vec<_>>()
+            .join("/");
+        let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes()).unwrap();
+        let answer = call(&data, request().method(method.clone()).uri(&uri)).await;
+        assert_ne!(answer.status, StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, path);
+        if !answer.status.is_success() {
+            assert_ne!(answer.json()["error"]["message"], fallback, "{} {} isn't routed", method, path);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "missing from the OpenAPI document")]
+fn an_undocumented_route_is_refused() {
+    crate::openapi::route("POST", "/pastes/{token}/stats");
+}
@@ -0,0 +1,59 @@
+// The pages showing the content of a paste, with markup in it: the paste page with and without links, the print
+// view and the preview of the form, which have to show the markup as text rather than run it.
+
+use super::*;
+
+const MARKUP: &str = "<script>alert(1)</script>\n<img src=\"https://tracker.example/pixel.png\"> & more";
+
+// The line of `MARKUP` as the pages show it, escaped.
+const ESCAPED: &str = "<span class=\"line\">&lt;script&gt;alert(1)&lt;/script&gt;</span>";
+
+#[actix_rt::test]
+async fn the_paste_page_escapes_the_content() {
+    let data = state();
+    let token = create(&data, serde_json::json!({ "content": MARKUP })).await["token"].as_str().unwrap().to_string();
+    for uri in [format!("/paste/{}", token), format!("/paste/{}?links=true", token)] {
+        let page = call(&data, request().uri(&uri)).await;
+        assert_eq!(page.status, StatusCode::OK);
+        let page = page.text();
+        assert!(page.contains(ESCAPED), "{}: {}", uri, page);
+        // The URL in it may be a link, the tag around it stays text
+        assert!(page.contains("&lt;img src=&quot;"), "{}: {}", uri, page);
+        assert!(page.contains("&quot;&gt; &amp; more</span>"), "{}: {}", uri, page);
+        assert!(!page.contains("<script>alert"), "{}: {}", uri, page);
+        assert!(!page.contains("<img src=\"https://tracker"), "{}: {}", uri, page);
+    }
+}
+
+#[actix_rt::test]
+async fn the_print_view_escapes_the_content() {
+    let data = state();
+    let token = create(&data, serde_json::json!({ "content": MARKUP })).await["token"].as_str().unwrap().to_string();
+    let page = call(&data, request().uri(&format!("/paste/{}/print", token))).await;
+    assert_eq!(page.status, StatusCode::OK);
+    let page = page.text();
+    assert!(page.contains(ESCAPED), "{}", page);
+    assert!(page.contains("&lt;img src=&quot;https://tracker.example/pixel.png&quot;&gt; &amp; more"), "{}", page);
+    assert!(!page.contains("<script>alert"), "{}", page);
+}
+
+#[actix_rt::test]
+async fn the_preview_is_the_content_of_the_page() {
+    let data = state();
+    let token = create(&data, serde_json::json!({ "content": MARKUP })).await["token"].as_str().unwrap().to_string();
+    for links in [false, true] {
+        let preview = request()
+            .method(Method::POST)
+            .uri(if links { "/preview?links=true" } else { "/preview" })
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .set_payload(serde_urlencoded::to_string([("content", MARKUP)]).unwrap());
+        let preview = call(&data, preview).await;
+        assert_eq!(preview.status, StatusCode::OK, "{}", preview.text());
+        assert_eq!(preview.header("Content-Security-Policy"), None);
+        let fragment = preview.text();
+        assert!(fragment.contains(ESCAPED), "{}", fragment);
+
+        let page = call(&data, request().uri(&format!("/paste/{}?links={}", token, links))).await.text();
+        assert!(page.contains(&fragment), "{}: {}", fragment, page);
+    }
+}
@@ -0,0 +1,61 @@
+// User text naming a placeholder of the template it goes in: the pages are filled one `{{placeholder}}` after the
+// other, and what comments, titles and collections hold has to come out as typed, not as another part of the page.
+
+use super::*;
+
+const CONTENT: &str = "the content of the paste";
+
+#[actix_rt::test]
+async fn a_comment_naming_a_placeholder_is_text() {
+    let data = state_with(Config {
+        comments: Some(true),
+        ..Config::default()
+    });
+    let paste = serde_json::json!({ "content": CONTENT, "title": "Notes" });
+    let token = create(&data, paste).await["token"].as_str().unwrap().to_string();
+    let comment = request()
+        .method(Method::POST)
+        .uri(&format!("/paste/{}/comments", token))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .set_payload(serde_urlencoded::to_string([("author", "{{announcements}}"), ("body", "{{paste_content}}")]).unwrap());
+    assert_eq!(call(&data, comment).await.status, StatusCode::SEE_OTHER);
+
+    let page = call(&data, request().uri(&format!("/paste/{}", token))).await.text();
+    assert_eq!(page.matches(CONTENT).count(), 1, "{}", page);
+    assert!(page.contains("&#123;&#123;paste_content}}"), "{}", page);
+    assert!(page.contains("&#123;&#123;announcements}}"), "{}", page);
+}
+
+#[actix_rt::test]
+async fn a_title_naming_a_placeholder_is_text() {
+    let data = state();
+    let paste = serde_json::json!({ "content": CONTENT, "title": "{{paste_content}} {{comments}}" });
+    let token = create(&data, paste).await["token"].as_str().unwrap().to_string();
+    for uri in [format!("/paste/{}", token), format!("/paste/{}/print", token)] {
+        let page = call(&data, request().uri(&uri)).await.text();
+        assert_eq!(page.matches(CONTENT).count(), 1, "{}: {}", uri, page);
+        assert!(page.contains("&#123;&#123;paste_content}} &#123;&#123;comments}}"), "{}: {}", uri, page);
+    }
+}
+
+#[actix_rt::test]
+async fn a_collection_title_naming_a_placeholder_is_text() {
+    let data = state();
+    let token = create(&data, serde_json::json!({ "content": CONTENT })).await["token"].as_str().unwrap().to_string();
+    let collection = request()
+        .method(Method::POST)
+        .uri("/api/collections")
+        .header("Content-Type", "application/json")
+        .set_payload(serde_json::json!({ "title": "{{manage}} {{list_items}}", "pastes": [token] }).to_string());
+    let collection = call(&data, collection).await;
+    assert_eq!(collection.status, StatusCode::CREATED, "{}", collection.text());
+    let collection = collection.json();
+    let (url, secret) = (collection["url"].as_str().unwrap(), collection["secret"].as_str().unwrap());
+
+    // Without the key the form to manage it isn't there, whatever the title says
+    let page = call(&data, request().uri(url)).await.text();
+    assert!(page.contains("&#123;&#123;manage}} &#123;&#123;list_items}}"), "{}", page);
+    assert!(!page.contains(secret), "{}", page);
+    assert!(!page.contains("action=\"/c/"), "{}", page);
+    assert_eq!(page.matches(&format!("href=\"/paste/{}\"", token)).count(), 1, "{}", page);
+}
@@ -0,0 +1,115 @@
+// The daily quotas of `check_daily_quota`: that of the address of a client, and that of an API token having its own,
+// with the `X-RateLimit-*` headers telling what is left of them.
+
+use super::*;
+
+// Creates an API token with `body`, a JSON `ApiNewToken`, and returns it.
+async fn create_token(data: &web::Data<AppState>, body: serde_json::Value) -> String {
+    let request = admin_request()
+        .method(Method::POST)
+        .uri("/admin/tokens")
+        .header("Content-Type", "application/json")
+        .set_payload(body.to_string());
+    let answer = call(data, request).await;
+    assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+    answer.json()["token"].as_str().unwrap().to_string()
+}
+
+// `create_request` of a paste with `token`.
+fn create_with(token: &str) -> TestRequest {
+    create_request(serde_json::json!({ "content": "counted" })).header("Authorization", format!("Bearer {}", token))
+}
+
+#[actix_rt::test]
+async fn a_token_with_a_quota_counts_against_it() {
+    let data = state_with(Config {
+        daily_paste_quota: Some(1),
+        ..admin_config()
+    });
+    let quota = create_token(&data, serde_json::json!({ "label": "ci", "scopes": ["read", "write"], "daily_quota": 3 })).await;
+    let plain = create_token(&data, serde_json::json!({ "label": "plain", "scopes": ["write"] })).await;
+
+    for remaining in ["2", "1", "0"] {
+        let answer = call(&data, create_with(&quota)).await;
+        assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+        assert_eq!(answer.header("X-RateLimit-Limit"), Some("3"));
+        assert_eq!(answer.header("X-RateLimit-Remaining"), Some(remaining));
+    }
+    let answer = call(&data, create_with(&quota)).await;
+    assert_eq!(answer.status, StatusCode::TOO_MANY_REQUESTS, "{}", answer.text());
+
+    // The address of the requests still has its own pastes, and a token without a quota counts against them
+    let answer = call(&data, create_with(&plain)).await;
+    assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+    assert_eq!(answer.header("X-RateLimit-Limit"), Some("1"));
+    let answer = call(&data, create_request(serde_json::json!({ "content": "anonymous" }))).await;
+    assert_eq!(answer.status, StatusCode::TOO_MANY_REQUESTS, "{}", answer.text());
+
+    let limits = call(&data, request().uri("/api/limits").header("Authorization", format!("Bearer {}", quota))).await;
+    assert_eq!(limits.json()["daily_paste_quota"]["limit"], 3);
+    assert_eq!(limits.json()["daily_paste_quota"]["remaining"], 0);
+
+    let tokens = call(&data, admin_request().uri("/admin/tokens?format=json")).await.json();
+    let quotas: Vec<&serde_json::Value> = tokens.as_array().unwrap().iter().map(|token| &token["daily_quota"]).collect();
+    assert_eq!(quotas, [&serde_json::Value::Null, &serde_json::json!(3)]);
+}
+
+#[actix_rt::test]
+async fn a_quota_below_one_is_refused() {
+    let data = state_with(admin_config());
+    let request = admin_request()
+        .method(Method::POST)
+        .uri("/admin/tokens")
+        .header("Content-Type", "application/json")
+        .set_payload(serde_json::json!({ "label": "ci", "scopes": ["write"], "daily_quota": 0 }).to_string());
+    let answer = call(&data, request).await;
+    assert_eq!(answer.status, StatusCode::BAD_REQUEST, "{}", answer.text());
+}
+
+// The headers follow the count of the store as it is used up, and start over with the day. The day passing is the
+// rows of the store moved to a day gone by, on SQLite.
+#[actix_rt::test]
+async fn the_remaining_pastes_count_down_and_reset() {
+    let path = std::env::temp_dir().join(format!("pastry-tests-quota-reset-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let store = crate::store::sqlite::SqliteStore::open(&path, Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+    let data = state_with_store(
+        Config {
+            daily_paste_quota: Some(3),
+            ..Config::default()
+        },
+        Box::new(store),
+    );
+    let paste = || create_request(serde_json::json!({ "content": "counted" }));
+
+    let midnight = |reset: &str| {
+        let reset: i64 = reset.parse().unwrap();
+        let now = store::now();
+        assert!(reset > now && reset <= now + SECONDS_PER_DAY && reset % SECONDS_PER_DAY == 0, "{} at {}", reset, now);
+        reset
+    };
+    for remaining in ["2", "1", "0"] {
+        let answer = call(&data, paste()).await;
+        assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+        assert_eq!(answer.header("X-RateLimit-Limit"), Some("3"));
+        assert_eq!(answer.header("X-RateLimit-Remaining"), Some(remaining));
+        midnight(answer.header("X-RateLimit-Reset").unwrap());
+    }
+    let answer = call(&data, paste()).await;
+    assert_eq!(answer.status, StatusCode::TOO_MANY_REQUESTS, "{}", answer.text());
+    assert_eq!(answer.header("X-RateLimit-Remaining"), Some("0"));
+    let reset = midnight(answer.header("X-RateLimit-Reset").unwrap());
+    let retry_after: i64 = answer.header("Retry-After").unwrap().parse().unwrap();
+    assert!((reset - store::now() - retry_after).abs() <= 1, "Retry-After {} for a reset at {}", retry_after, reset);
+    let limits = call(&data, request().uri("/api/limits")).await;
+    assert_eq!(limits.json()["daily_paste_quota"]["remaining"], 0);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute_batch("UPDATE ip_quota SET day = '2000-01-01'").unwrap();
+    let limits = call(&data, request().uri("/api/limits")).await;
+    assert_eq!(limits.json()["daily_paste_quota"]["remaining"], 3);
+    let answer = call(&data, paste()).await;
+    assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+    assert_eq!(answer.header("X-RateLimit-Remaining"), Some("2"));
+    let _ = std::fs::remove_file(&path);
+}
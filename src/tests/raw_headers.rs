@@ -0,0 +1,76 @@
+// The `X-Paste-*` headers of raw and download responses (`METADATA_HEADERS`): what they hold and in what form, that
+// those that don't apply are left out, and that HEAD has them too.
+
+use super::*;
+use chrono::DateTime;
+
+// The `X-Paste-*` headers of `answer`, by name.
+fn paste_headers(answer: &Answer) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = answer
+        .headers
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-paste-"))
+        .map(|(name, value)| (name.as_str().to_string(), value.to_str().expect("an ASCII value").to_string()))
+        .collect();
+    headers.sort();
+    headers
+}
+
+fn value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+}
+
+#[actix_rt::test]
+async fn raw_responses_describe_the_paste() {
+    let data = state();
+    let paste = serde_json::json!({ "content": "described", "public": true, "expires": "1h", "tags": ["rust", "notes"] });
+    let token = create(&data, paste).await["token"].as_str().unwrap().to_string();
+    let limits = call(&data, request().uri("/api/limits")).await.json();
+    let mut listed: Vec<String> = limits["metadata_headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|name| name.as_str().unwrap().to_ascii_lowercase())
+        .collect();
+    listed.sort();
+
+    for (views, route) in [(1, "raw"), (2, "download")] {
+        // HEAD tells what GET will, as it counts no view (the server leaves out its body, see `tests/head.rs`)
+        let head = call(&data, request().method(Method::HEAD).uri(&format!("/paste/{}/{}", token, route))).await;
+        assert_eq!(head.status, StatusCode::OK);
+        let answer = call(&data, request().uri(&format!("/paste/{}/{}", token, route))).await;
+        assert_eq!(answer.status, StatusCode::OK);
+        let headers = paste_headers(&answer);
+        assert_eq!(paste_headers(&head), headers, "HEAD {}", route);
+        let names: Vec<&String> = headers.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, listed.iter().collect::<Vec<_>>(), "{}", route);
+
+        assert_eq!(value(&headers, "x-paste-token"), Some(token.as_str()));
+        let created = DateTime::parse_from_rfc3339(value(&headers, "x-paste-created-at").unwrap()).unwrap();
+        let expires = DateTime::parse_from_rfc3339(value(&headers, "x-paste-expires-at").unwrap()).unwrap();
+        assert_eq!((expires - created).num_seconds(), 60 * 60);
+        assert!((created.timestamp() - store::now()).abs() < 60);
+        assert_eq!(value(&headers, "x-paste-views"), Some(views.to_string().as_str()));
+        assert_eq!(value(&headers, "x-paste-tags"), Some("notes,rust"));
+        let hash = value(&headers, "x-paste-content-hash").unwrap();
+        assert_eq!(hash, store::content_hash(b"described"));
+    }
+    let by_hash = call(&data, request().uri(&format!("/h/{}/raw", store::content_hash(b"described")))).await;
+    assert_eq!(value(&paste_headers(&by_hash), "x-paste-token"), Some(token.as_str()));
+}
+
+#[actix_rt::test]
+async fn what_does_not_apply_is_left_out() {
+    let data = state();
+    let token = create(&data, serde_json::json!({ "content": "plain" })).await["token"].as_str().unwrap().to_string();
+    for route in ["raw", "download"] {
+        let answer = call(&data, request().uri(&format!("/paste/{}/{}", token, route))).await;
+        let headers = paste_headers(&answer);
+        let names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["x-paste-content-hash", "x-paste-created-at", "x-paste-token", "x-paste-views"], "{}", route);
+    }
+
+    let missing = call(&data, request().uri("/paste/nosuchpaste/raw")).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    assert!(paste_headers(&missing).is_empty());
+}
@@ -0,0 +1,59 @@
+// The reserved words of `reserved.rs`: a route with a word missing from them is refused when the app is built, no
+// client may pick one as its token, and the pastes that had one before it was reserved are listed for the admin.
+
+use super::store_contract::paste;
+use super::*;
+
+#[test]
+#[should_panic(expected = "missing from reserved::RESERVED")]
+fn a_route_with_an_unreserved_word_is_refused() {
+    crate::reserved::route("/paste/{token}/nosuchroute");
+}
+
+// Building the app registers every route through `reserved::route`, as the server does once at startup
+#[actix_rt::test]
+async fn every_route_of_the_app_is_reserved() {
+    assert_eq!(call(&state(), request().uri("/healthz")).await.status, StatusCode::OK);
+}
+
+// The route words pass, so do parameters and extensions; words are reserved in any case
+#[test]
+fn the_words_of_a_route_are_checked() {
+    assert_eq!(crate::reserved::route("/paste/{token}/archive.zip"), "/paste/{token}/archive.zip");
+    assert!(crate::reserved::is_reserved("Admin"));
+    assert!(!crate::reserved::is_reserved("zip"));
+}
+
+#[actix_rt::test]
+async fn a_reserved_word_is_no_token() {
+    let data = state();
+    for word in ["admin", "RAW", "imgproxy"] {
+        let put = request()
+            .method(Method::PUT)
+            .uri(&format!("/api/pastes/{}", word))
+            .header("Content-Type", "application/json")
+            .set_payload(serde_json::json!({ "content": "taken" }).to_string());
+        let answer = call(&data, put).await;
+        assert_eq!(answer.status, StatusCode::BAD_REQUEST, "{}: {}", word, answer.text());
+        assert!(!data.store.exists(word).unwrap());
+    }
+}
+
+// A paste whose token was free when it was created, reserved since
+#[actix_rt::test]
+async fn the_pastes_of_reserved_words_are_listed() {
+    let data = state_with(admin_config());
+    data.store.insert(&paste("imgproxy", "older than the route")).unwrap();
+    data.store.insert(&paste("unreserved", "not listed")).unwrap();
+
+    let answer = call(&data, admin_request().uri("/admin/reserved")).await;
+    assert_eq!(answer.status, StatusCode::OK);
+    let listing = answer.json();
+    assert!(listing["reserved"].as_array().unwrap().iter().any(|word| word == "imgproxy"));
+    assert_eq!(listing["colliding"], serde_json::json!([{ "token": "imgproxy", "url": "/paste/imgproxy" }]));
+
+    // Still served, it just can't be replaced
+    let raw = call(&data, request().uri("/paste/imgproxy/raw")).await;
+    assert_eq!(raw.text(), "older than the route");
+    assert_eq!(call(&data, request().uri("/admin/reserved")).await.status, StatusCode::UNAUTHORIZED);
+}
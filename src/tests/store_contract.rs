@@ -0,0 +1,341 @@
+// The contract of `PasteStore`, what the handlers rely on whichever backend is active: each test runs against the
+// memory store and a SQLite file, and against Postgres when `PASTRY_TEST_POSTGRES_URL` gives a database to use (with
+// the `postgres` feature), each test in a schema of its own there.
+
+use super::*;
+use crate::store::memory::MemoryStore;
+use crate::store::sqlite::SqliteStore;
+use crate::store::{ContentSize, NewPaste, StoreError};
+
+// A store of `backends`, and what to remove once the test is done with it.
+struct Backend {
+    name: &'static str,
+    store: Box<dyn PasteStore>,
+    cleanup: Option<Box<dyn FnOnce()>>,
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.cleanup.take() {
+            cleanup();
+        }
+    }
+}
+
+// A new empty store of each backend for the test `name`.
+fn backends(name: &str) -> Vec<Backend> {
+    let path = std::env::temp_dir().join(format!("pastry-tests-contract-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sqlite = SqliteStore::open(&path, Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+    let backends = vec![
+        Backend {
+            name: "memory",
+            store: Box::new(MemoryStore::new(PASTRY_MEMORY_MAX_PASTES, PASTRY_MEMORY_MAX_BYTES)),
+            cleanup: None,
+        },
+        Backend {
+            name: "sqlite",
+            store: Box::new(sqlite),
+            cleanup: Some(Box::new(move || {
+                let _ = std::fs::remove_file(&path);
+            })),
+        },
+    ];
+    #[cfg(feature = "postgres")]
+    let backends = {
+        let postgres = std::env::var("PASTRY_TEST_POSTGRES_URL").ok().map(|url| postgres_backend(&url, name));
+        backends.into_iter().chain(postgres).collect()
+    };
+    backends
+}
+
+// The schema `pastry_test_{name}` of the database at `url`, made again empty, as a store.
+#[cfg(feature = "postgres")]
+fn postgres_backend(url: &str, name: &str) -> Backend {
+    let schema = format!("pastry_test_{}_{}", name.replace('-', "_"), std::process::id());
+    let mut client = ::postgres::Client::connect(url, ::postgres::NoTls).unwrap();
+    client
+        .batch_execute(&format!("DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0};", schema))
+        .unwrap();
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let in_schema = format!("{}{}options=-csearch_path%3D{}", url, separator, schema);
+    Backend {
+        name: "postgres",
+        store: Box::new(crate::store::postgres::PostgresStore::open(&in_schema, Duration::from_secs(5)).unwrap()),
+        cleanup: Some(Box::new(move || {
+            let _ = client.batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema));
+        })),
+    }
+}
+
+// A public text paste `token` saying `content`, kept forever, the row the store gets with no check of the handlers.
+pub fn paste(token: &str, content: &str) -> NewPaste {
+    NewPaste {
+        token: token.to_string(),
+        secret: format!("secret of {}", token),
+        content: content.to_string(),
+        public: true,
+        tags: Vec::new(),
+        expires_at: None,
+        blob: None,
+        data: None,
+        size: ContentSize::of_text(content),
+        redirect: false,
+        content_hash: store::content_hash(content.as_bytes()),
+        creator: None,
+        comments: true,
+        pending: false,
+        submitter: None,
+        title: String::new(),
+        title_auto: false,
+    }
+}
+
+fn tokens<'a>(tokens: impl IntoIterator<Item = &'a String>) -> Vec<&'a str> {
+    tokens.into_iter().map(String::as_str).collect()
+}
+
+#[test]
+fn a_paste_reads_back_as_stored() {
+    for backend in backends("read-back") {
+        let store = backend.store.as_ref();
+        let stored = NewPaste {
+            public: false,
+            tags: vec!["rust".to_string(), "code".to_string()],
+            expires_at: Some(store::now() + 3600),
+            creator: Some("creator".to_string()),
+            comments: false,
+            title: "A title".to_string(),
+            ..paste("readback", "line one\nline two\n")
+        };
+        store.insert(&stored).unwrap();
+
+        let read = store.get("readback").unwrap().unwrap_or_else(|| panic!("{}: not found", backend.name));
+        assert_eq!(read.secret, stored.secret, "{}", backend.name);
+        assert_eq!(read.content, stored.content, "{}", backend.name);
+        assert!(!read.public, "{}", backend.name);
+        assert_eq!(read.views, 0, "{}", backend.name);
+        assert!((store::now() - read.created_at).abs() <= 5, "{}", backend.name);
+        assert_eq!(read.expires_at, stored.expires_at, "{}", backend.name);
+        assert_eq!(read.size, Some(stored.size), "{}", backend.name);
+        assert_eq!(read.content_hash.as_deref(), Some(stored.content_hash.as_str()), "{}", backend.name);
+        assert_eq!(read.creator, stored.creator, "{}", backend.name);
+        assert!(!read.comments && !read.pending && !read.redirect && !read.archived, "{}", backend.name);
+        assert_eq!(read.title, "A title", "{}", backend.name);
+        assert_eq!(store.tags("readback").unwrap(), ["code", "rust"], "{}", backend.name);
+        match store.open_content("readback").unwrap() {
+            Some((_, store::Content::Inline(text))) => assert_eq!(text, stored.content, "{}", backend.name),
+            _ => panic!("{}: not inline", backend.name),
+        }
+
+        let binary = NewPaste {
+            content: String::new(),
+            data: Some(vec![0, 159, 146, 150]),
+            ..paste("binary", "")
+        };
+        store.insert(&binary).unwrap();
+        assert_eq!(store.get("binary").unwrap().unwrap().data, binary.data, "{}", backend.name);
+
+        assert!(store.exists("readback").unwrap(), "{}", backend.name);
+        assert!(!store.exists("nosuchpaste").unwrap(), "{}", backend.name);
+        assert!(store.get("nosuchpaste").unwrap().is_none(), "{}", backend.name);
+        let taken = store.insert(&paste("readback", "another")).unwrap_err();
+        assert!(taken.is_conflict(), "{}: {}", backend.name, taken);
+        assert_eq!(store.get("readback").unwrap().unwrap().content, stored.content, "{}", backend.name);
+    }
+}
+
+#[test]
+fn expired_pastes_are_gone_until_purged() {
+    for backend in backends("expiry") {
+        let store = backend.store.as_ref();
+        let creator = Some("creator".to_string());
+        let tags = vec!["old".to_string()];
+        for (token, expires_at) in [("expired", Some(store::now() - 1)), ("later", Some(store::now() + 3600)), ("forever", None)] {
+            let stored = NewPaste {
+                expires_at,
+                creator: creator.clone(),
+                tags: tags.clone(),
+                ..paste(token, token)
+            };
+            store.insert(&stored).unwrap();
+            store.record_view(token).unwrap();
+        }
+
+        assert!(store.get("expired").unwrap().is_none(), "{}", backend.name);
+        assert!(store.open_content("expired").unwrap().is_none(), "{}", backend.name);
+        assert!(store.get("later").unwrap().is_some(), "{}", backend.name);
+        // Its token stays taken until it is purged
+        assert!(store.exists("expired").unwrap(), "{}", backend.name);
+        assert!(store.insert(&paste("expired", "again")).unwrap_err().is_conflict(), "{}", backend.name);
+
+        let created = store.list_created("creator", 10).unwrap();
+        let mut created = tokens(created.iter().map(|created| &created.token));
+        created.sort();
+        assert_eq!(created, ["forever", "later"], "{}", backend.name);
+        let popular = store.list_popular(7, None, 10).unwrap();
+        let mut popular = tokens(popular.iter().map(|listed| &listed.token));
+        popular.sort();
+        assert_eq!(popular, ["forever", "later"], "{}", backend.name);
+        assert_eq!(store.tag_counts().unwrap(), [("old".to_string(), 2)], "{}", backend.name);
+        assert_eq!(store.purge_preview().unwrap().expired_pastes, 1, "{}", backend.name);
+
+        assert_eq!(store.purge_expired().unwrap(), 1, "{}", backend.name);
+        assert!(!store.exists("expired").unwrap(), "{}", backend.name);
+        assert!(store.tags("expired").unwrap().is_empty(), "{}", backend.name);
+        assert_eq!(tokens(&store.paste_tokens("", 10).unwrap()), ["forever", "later"], "{}", backend.name);
+        assert_eq!(store.purge_expired().unwrap(), 0, "{}", backend.name);
+        store.insert(&paste("expired", "again")).unwrap();
+    }
+}
+
+#[test]
+fn listings_show_public_published_pastes() {
+    for backend in backends("listings") {
+        let store = backend.store.as_ref();
+        let tagged = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+        for (token, public, pending, tags, views) in [
+            ("most", true, false, &["rust"][..], 3),
+            ("second", true, false, &["rust", "web"][..], 2),
+            ("tied", true, false, &["web"][..], 2),
+            ("unseen", true, false, &["rust"][..], 0),
+            ("private", false, false, &["rust"][..], 5),
+            ("pending", true, true, &["rust"][..], 5),
+        ] {
+            let stored = NewPaste {
+                public,
+                pending,
+                tags: tagged(tags),
+                creator: Some("creator".to_string()),
+                ..paste(token, &format!("content of {}", token))
+            };
+            store.insert(&stored).unwrap();
+            for _ in 0..views {
+                store.record_view(token).unwrap();
+            }
+        }
+
+        // By views in the window then token, only pastes viewed in it
+        let popular = store.list_popular(7, None, 10).unwrap();
+        assert_eq!(tokens(popular.iter().map(|listed| &listed.token)), ["most", "second", "tied"], "{}", backend.name);
+        assert_eq!(popular.iter().map(|listed| listed.views).collect::<Vec<_>>(), [3, 2, 2], "{}", backend.name);
+        assert_eq!(popular[0].preview, "content of most", "{}", backend.name);
+        let rust = store.list_popular(7, Some("rust"), 10).unwrap();
+        assert_eq!(tokens(rust.iter().map(|listed| &listed.token)), ["most", "second"], "{}", backend.name);
+        assert_eq!(store.list_popular(7, None, 1).unwrap().len(), 1, "{}", backend.name);
+        assert_eq!(store.get("most").unwrap().unwrap().views, 3, "{}", backend.name);
+        let days = store.daily_views("most", 7).unwrap();
+        assert_eq!(days.len(), 7, "{}", backend.name);
+        assert_eq!(days.iter().map(|(_, views)| views).sum::<i64>(), 3, "{}", backend.name);
+
+        let counts = store.tag_counts().unwrap();
+        assert_eq!(counts, [("rust".to_string(), 3), ("web".to_string(), 2)], "{}", backend.name);
+
+        // The creator sees all of theirs, newest first
+        let created = store.list_created("creator", 10).unwrap();
+        assert_eq!(created.len(), 6, "{}", backend.name);
+        assert!(
+            created.windows(2).all(|pair| (-pair[0].created_at, &pair[0].token) < (-pair[1].created_at, &pair[1].token)),
+            "{}: {:?}",
+            backend.name,
+            created.iter().map(|created| (&created.token, created.created_at)).collect::<Vec<_>>()
+        );
+        assert!(created.iter().find(|created| created.token == "pending").unwrap().pending, "{}", backend.name);
+        assert!(store.list_created("somebody else", 10).unwrap().is_empty(), "{}", backend.name);
+        assert_eq!(store.list_created("creator", 2).unwrap().len(), 2, "{}", backend.name);
+
+        let pending = store.pending_pastes(10).unwrap();
+        assert_eq!(pending.len(), 1, "{}", backend.name);
+        assert!(store.approve("pending", None).unwrap(), "{}", backend.name);
+        let popular = store.list_popular(7, None, 1).unwrap();
+        assert_eq!(tokens(popular.iter().map(|listed| &listed.token)), ["pending"], "{}", backend.name);
+
+        // Every token, in order, a page at a time
+        assert_eq!(tokens(&store.paste_tokens("", 3).unwrap()), ["most", "pending", "private"], "{}", backend.name);
+        assert_eq!(tokens(&store.paste_tokens("private", 3).unwrap()), ["second", "tied", "unseen"], "{}", backend.name);
+        assert!(store.paste_tokens("unseen", 3).unwrap().is_empty(), "{}", backend.name);
+    }
+}
+
+#[test]
+fn a_deleted_paste_leaves_nothing() {
+    for backend in backends("delete") {
+        let store = backend.store.as_ref();
+        let stored = NewPaste {
+            tags: vec!["gone".to_string()],
+            ..paste("deleted", "soon gone")
+        };
+        store.insert(&stored).unwrap();
+        store.record_view("deleted").unwrap();
+
+        assert!(store.delete("deleted", None).unwrap(), "{}", backend.name);
+        assert!(!store.delete("deleted", None).unwrap(), "{}", backend.name);
+        assert!(store.get("deleted").unwrap().is_none(), "{}", backend.name);
+        assert!(!store.exists("deleted").unwrap(), "{}", backend.name);
+        assert!(store.tags("deleted").unwrap().is_empty(), "{}", backend.name);
+        assert!(store.list_popular(7, None, 10).unwrap().is_empty(), "{}", backend.name);
+        assert!(store.tag_counts().unwrap().is_empty(), "{}", backend.name);
+        store.insert(&stored).unwrap();
+        assert_eq!(store.get("deleted").unwrap().unwrap().views, 0, "{}", backend.name);
+    }
+}
+
+#[test]
+fn a_failed_transaction_stores_nothing() {
+    for backend in backends("transaction") {
+        let store = backend.store.as_ref();
+        let failed = store.with_tx(&mut || {
+            store.insert(&paste("first", "first"))?;
+            store.record_view("first")?;
+            Err(StoreError::Config("stop".to_string()))
+        });
+        assert!(matches!(failed, Err(StoreError::Config(_))), "{}", backend.name);
+        assert!(!store.exists("first").unwrap(), "{}", backend.name);
+        assert!(store.list_popular(7, None, 10).unwrap().is_empty(), "{}", backend.name);
+
+        // A transaction inside another one that fails takes only its own calls back with it
+        store
+            .with_tx(&mut || {
+                store.insert(&paste("outer", "outer"))?;
+                let inner = store.with_tx(&mut || {
+                    store.insert(&paste("inner", "inner"))?;
+                    store.insert(&paste("outer", "taken"))
+                });
+                assert!(inner.unwrap_err().is_conflict());
+                Ok(())
+            })
+            .unwrap();
+        assert!(store.exists("outer").unwrap(), "{}", backend.name);
+        assert!(!store.exists("inner").unwrap(), "{}", backend.name);
+        assert_eq!(store.get("outer").unwrap().unwrap().content, "outer", "{}", backend.name);
+
+        // A batch with a taken token stores none of its pastes
+        let batch = [paste("one", "one"), paste("two", "two"), paste("outer", "again")];
+        assert!(store.insert_batch(&batch).unwrap_err().is_conflict(), "{}", backend.name);
+        assert!(!store.exists("one").unwrap() && !store.exists("two").unwrap(), "{}", backend.name);
+        store.insert_batch(&batch[..2]).unwrap();
+        assert_eq!(tokens(&store.paste_tokens("", 10).unwrap()), ["one", "outer", "two"], "{}", backend.name);
+    }
+}
+
+#[test]
+fn search_hits_carry_their_title() {
+    for backend in backends("search-titles") {
+        let store = backend.store.as_ref();
+        store.insert(&NewPaste { title: "Fox notes".to_string(), ..paste("titled", "the quick brown fox") }).unwrap();
+        store.insert(&paste("untitled", "a lazy fox")).unwrap();
+        let query = store::SearchQuery {
+            terms: vec!["fox".to_string()],
+            tag: None,
+            since: None,
+            until: None,
+            limit: 10,
+            offset: 0,
+        };
+        let mut hits: Vec<(String, String)> =
+            store.search(&query).unwrap().into_iter().map(|hit| (hit.token, hit.title)).collect();
+        hits.sort();
+        let expected = [("titled".to_string(), "Fox notes".to_string()), ("untitled".to_string(), String::new())];
+        assert_eq!(hits, expected, "{}", backend.name);
+    }
+}
@@ -0,0 +1,158 @@
+// Submitting the form of the index page and creating pastes through the API: the page the creator lands on,
+// requests sent twice, and the form without its scripts.
+
+use super::*;
+
+#[actix_rt::test]
+async fn the_secret_is_only_shown_once() {
+    let data = state();
+    let answer = call(&data, submit_request(&[("content", "hello")])).await;
+    assert_eq!(answer.status, StatusCode::SEE_OTHER);
+    let location = answer.header("Location").unwrap().to_string();
+    assert!(location.contains("/created?key="), "{}", location);
+    let token = location.trim_start_matches("/paste/").split('/').next().unwrap().to_string();
+    let secret = data.store.get(&token).unwrap().unwrap().secret;
+    // Nowhere in the redirect
+    assert!(!location.contains(&secret));
+
+    let first = call(&data, request().uri(&location)).await;
+    assert_eq!(first.status, StatusCode::OK);
+    assert!(first.text().contains(&secret));
+
+    // A refresh, or the link copied from the address bar
+    let again = call(&data, request().uri(&location)).await;
+    assert_eq!(again.status, StatusCode::OK);
+    assert!(!again.text().contains(&secret));
+    assert!(again.text().contains(&format!("/paste/{}", token)));
+
+    let without_key = call(&data, request().uri(&format!("/paste/{}/created", token))).await;
+    assert!(!without_key.text().contains(&secret));
+}
+
+#[actix_rt::test]
+async fn a_key_only_opens_its_own_paste() {
+    let data = state();
+    let first = call(&data, submit_request(&[("content", "one")])).await.header("Location").unwrap().to_string();
+    let second = call(&data, submit_request(&[("content", "two")])).await.header("Location").unwrap().to_string();
+    let key = first.split("key=").nth(1).unwrap();
+    let second_token = second.trim_start_matches("/paste/").split('/').next().unwrap().to_string();
+    let secret = data.store.get(&second_token).unwrap().unwrap().secret;
+    let answer = call(&data, request().uri(&format!("/paste/{}/created?key={}", second_token, key))).await;
+    assert!(!answer.text().contains(&secret));
+}
+
+#[actix_rt::test]
+async fn a_form_sent_twice_creates_one_paste() {
+    let data = state();
+    let form = [("content", "hello"), ("nonce", "nonce of the page")];
+    let first = call(&data, submit_request(&form)).await;
+    assert_eq!(first.status, StatusCode::SEE_OTHER);
+    let again = call(&data, submit_request(&form)).await;
+    assert_eq!(again.status, StatusCode::SEE_OTHER);
+    assert_eq!(again.header("Location"), first.header("Location"));
+    assert_eq!(again.header("Idempotent-Replayed"), Some("true"));
+    assert_eq!(data.store.paste_tokens("", 10).unwrap().len(), 1);
+
+    // The same page, sent again with other content
+    let other = call(&data, submit_request(&[("content", "changed"), ("nonce", "nonce of the page")])).await;
+    assert_eq!(other.status, StatusCode::CONFLICT);
+    assert_eq!(data.store.paste_tokens("", 10).unwrap().len(), 1);
+}
+
+#[actix_rt::test]
+async fn an_api_request_sent_twice_creates_one_paste() {
+    let data = state();
+    let keyed = |content: &str| create_request(serde_json::json!({ "content": content })).header("Idempotency-Key", "retry-1");
+    let first = call(&data, keyed("hello")).await;
+    assert_eq!(first.status, StatusCode::CREATED);
+    let again = call(&data, keyed("hello")).await;
+    assert_eq!(again.status, StatusCode::CREATED);
+    assert_eq!(again.header("Idempotent-Replayed"), Some("true"));
+    assert_eq!(again.json(), first.json());
+    assert_eq!(data.store.paste_tokens("", 10).unwrap().len(), 1);
+
+    let other = call(&data, keyed("different")).await;
+    assert_eq!(other.status, StatusCode::CONFLICT);
+    assert_eq!(other.json()["error"]["code"], "conflict");
+
+    // Keys belong to their caller: the same key from another address is another request
+    let elsewhere = keyed("hello").peer_addr("198.51.100.1:5000".parse().unwrap());
+    let answer = call(&data, elsewhere).await;
+    assert_eq!(answer.status, StatusCode::CREATED);
+    assert!(answer.header("Idempotent-Replayed").is_none());
+    assert_ne!(answer.json()["token"], first.json()["token"]);
+}
+
+#[actix_rt::test]
+async fn a_failed_request_can_be_sent_again() {
+    let data = state_with(Config {
+        daily_paste_quota: Some(1),
+        ..Config::default()
+    });
+    create(&data, serde_json::json!({ "content": "uses the quota" })).await;
+    let keyed = || create_request(serde_json::json!({ "content": "hello" })).header("Idempotency-Key", "retry-2");
+    assert_eq!(call(&data, keyed()).await.status, StatusCode::TOO_MANY_REQUESTS);
+    // Refused again rather than replayed or in progress
+    assert_eq!(call(&data, keyed()).await.status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+// The value of the attribute `name` of the HTML tag `tag`.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    Some(tag[start..start + tag[start..].find('"')?].to_string())
+}
+
+// The fields the form `form` of a page sends as a browser without scripts sends them: its inputs with their values,
+// save unticked checkboxes, its textareas with their text and its selects with their first option.
+fn form_fields(form: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for (index, _) in form.match_indices('<') {
+        let tag = &form[index..index + form[index..].find('>').unwrap() + 1];
+        let name = match attribute(tag, "name") {
+            Some(name) => name,
+            None => continue,
+        };
+        if tag.starts_with("<input") {
+            let checkbox = attribute(tag, "type").as_deref() == Some("checkbox");
+            if !checkbox || tag.contains(" checked") {
+                fields.push((name, attribute(tag, "value").unwrap_or_default()));
+            }
+        } else if tag.starts_with("<textarea") {
+            let text = &form[index + tag.len()..];
+            fields.push((name, text[..text.find("</textarea>").unwrap()].to_string()));
+        } else if tag.starts_with("<select") {
+            let option = &form[index + form[index..].find("<option").unwrap()..];
+            fields.push((name, attribute(option, "value").unwrap()));
+        }
+    }
+    fields
+}
+
+// The form of the index page is a plain HTML form: what it sends as it is, with only its text filled in, creates the
+// paste, and what the editor script adds stays hidden until the script shows it.
+#[actix_rt::test]
+async fn the_form_posts_without_the_script() {
+    let data = state();
+    let page = call(&data, request().uri("/")).await.text();
+    let start = page.find("<form id=\"paste-form\"").unwrap();
+    let form = &page[start..start + page[start..].find("</form>").unwrap()];
+    let tag = &form[..form.find('>').unwrap()];
+    assert_eq!(attribute(tag, "action").as_deref(), Some("/submit"));
+    assert_eq!(attribute(tag, "method").as_deref(), Some("post"));
+    for id in ["tabs", "counter", "preview"] {
+        let element = &form[form.find(&format!("id=\"{}\"", id)).unwrap()..];
+        assert!(element[..element.find('>').unwrap()].contains(" hidden"), "#{} shows without the script", id);
+    }
+
+    let mut fields = form_fields(form);
+    let names: Vec<&str> = fields.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["content", "title", "tags", "expires", "nonce"]);
+    fields[0].1 = "typed without scripts".to_string();
+    let fields: Vec<(&str, &str)> = fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+    let answer = call(&data, submit_request(&fields)).await;
+    assert_eq!(answer.status, StatusCode::SEE_OTHER, "{}", answer.text());
+    let location = answer.header("Location").unwrap();
+    let token = location.trim_start_matches("/paste/").split('/').next().unwrap();
+    let raw = call(&data, request().uri(&format!("/paste/{}/raw", token))).await;
+    assert_eq!(raw.text(), "typed without scripts");
+}
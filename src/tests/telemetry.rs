@@ -0,0 +1,67 @@
+// The spans of `telemetry.rs`, with the `otel` feature: those of a request, in the trace of its `traceparent`, and
+// their children. They are recorded by an exporter of the test, for a subscriber of the thread of the test only, so
+// the requests of the other tests aren't in them.
+
+use super::*;
+use opentelemetry::trace::{SpanId, TraceId, TracerProvider as _};
+use opentelemetry::Value;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, SpanData, SpanExporter};
+use tracing_subscriber::layer::SubscriberExt;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const CALLER_SPAN_ID: &str = "00f067aa0ba902b7";
+
+// Keeps the spans it is sent.
+#[derive(Clone, Debug, Default)]
+struct Recorder(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for Recorder {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        self.0.lock().unwrap().extend(batch);
+        Ok(())
+    }
+}
+
+fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+    span.attributes.iter().find(|attribute| attribute.key.as_str() == key).map(|attribute| &attribute.value)
+}
+
+#[actix_rt::test]
+async fn a_request_is_traced() {
+    let recorder = Recorder::default();
+    let provider = SdkTracerProvider::builder().with_simple_exporter(recorder.clone()).build();
+    let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("pastry")));
+    let _subscribed = tracing::subscriber::set_default(subscriber);
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let data = state();
+    let token = create(&data, serde_json::json!({ "content": "traced" })).await["token"].as_str().unwrap().to_string();
+    data.cache.remove(&token);
+    let traceparent = format!("00-{}-{}-01", TRACE_ID, CALLER_SPAN_ID);
+    let answer = call(&data, request().uri(&format!("/paste/{}", token)).header("traceparent", traceparent)).await;
+    assert_eq!(answer.status, StatusCode::OK);
+
+    let spans = recorder.0.lock().unwrap();
+    let trace: Vec<&SpanData> = spans
+        .iter()
+        .filter(|span| span.span_context.trace_id() == TraceId::from_hex(TRACE_ID).unwrap())
+        .collect();
+    let request = trace
+        .iter()
+        .find(|span| span.name == "GET /paste/{token}")
+        .unwrap_or_else(|| panic!("no span of the request in {:?}", trace.iter().map(|span| &span.name).collect::<Vec<_>>()));
+    assert_eq!(request.parent_span_id, SpanId::from_hex(CALLER_SPAN_ID).unwrap());
+    assert_eq!(attribute(request, "http.route"), Some(&Value::from("/paste/{token}")));
+    assert_eq!(attribute(request, "http.response.status_code"), Some(&Value::I64(200)));
+    assert_eq!(attribute(request, "paste.token"), Some(&Value::from(token.clone())));
+
+    let children: Vec<&str> = trace
+        .iter()
+        .filter(|span| span.parent_span_id == request.span_context.span_id())
+        .map(|span| span.name.as_ref())
+        .collect();
+    assert!(children.contains(&"view_paste.html"), "{:?}", children);
+    assert!(children.contains(&"open_content"), "{:?}", children);
+}
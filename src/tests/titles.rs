@@ -0,0 +1,100 @@
+// The titles of pastes where they are found and saved: the results of “/search” and “/api/search”, the zip archive
+// and its “METADATA.json”, and the file name templates of downloads.
+
+use super::*;
+use flate2::read::DeflateDecoder;
+use std::io::Read;
+
+fn u16_at(bytes: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> usize {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as usize
+}
+
+// The names and contents of the entries of `zip`, in order, read from its central directory.
+fn entries(zip: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let end = zip.len() - 22;
+    assert_eq!(u32_at(zip, end), 0x0605_4b50, "no end of central directory");
+    let mut at = u32_at(zip, end + 16);
+    (0..u16_at(zip, end + 10))
+        .map(|_| {
+            assert_eq!(u32_at(zip, at), 0x0201_4b50, "no central directory entry at {}", at);
+            let compressed = u32_at(zip, at + 20);
+            let name_len = u16_at(zip, at + 28);
+            let name = String::from_utf8(zip[at + 46..at + 46 + name_len].to_vec()).unwrap();
+            let local = u32_at(zip, at + 42);
+            let data = local + 30 + u16_at(zip, local + 26) + u16_at(zip, local + 28);
+            let mut content = Vec::new();
+            DeflateDecoder::new(&zip[data..data + compressed]).read_to_end(&mut content).unwrap();
+            at += 46 + name_len + u16_at(zip, at + 30) + u16_at(zip, at + 32);
+            (name, content)
+        })
+        .collect()
+}
+
+#[actix_rt::test]
+async fn search_results_show_the_title() {
+    let data = state();
+    let titled = serde_json::json!({ "content": "the quick brown fox", "public": true, "title": "Fox notes" });
+    let titled = create(&data, titled).await;
+    let titled = titled["token"].as_str().unwrap();
+    // Without one, the title is made of the content
+    let made = create(&data, serde_json::json!({ "content": "a lazy fox\njumps", "public": true })).await;
+    let made = made["token"].as_str().unwrap();
+
+    let page = call(&data, request().uri("/search?q=fox")).await.text();
+    assert!(page.contains(&format!("<a href=\"/paste/{}\">Fox notes</a>", titled)), "{}", page);
+    assert!(page.contains(&format!("<a href=\"/paste/{}\">a lazy fox</a>", made)), "{}", page);
+
+    let api = call(&data, request().uri("/api/search?q=fox")).await.json();
+    let titles: Vec<(&str, &str)> = api["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| (hit["token"].as_str().unwrap(), hit["title"].as_str().unwrap()))
+        .collect();
+    assert!(titles.contains(&(titled, "Fox notes")), "{}", api);
+    assert!(titles.contains(&(made, "a lazy fox")), "{}", api);
+}
+
+#[actix_rt::test]
+async fn the_archive_is_named_from_the_title() {
+    let data = state();
+    let titled = create(&data, serde_json::json!({ "content": "step one\n", "title": "Deploy notes: v2" })).await;
+    let titled = titled["token"].as_str().unwrap();
+    let answer = call(&data, request().uri(&format!("/paste/{}/archive.zip", titled))).await;
+    assert_eq!(answer.status, StatusCode::OK, "{}", answer.text());
+    let files = entries(&answer.body);
+    let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["Deploy_notes_v2.txt", "METADATA.json"]);
+    assert_eq!(files[0].1, b"step one\n");
+    let metadata: serde_json::Value = serde_json::from_slice(&files[1].1).unwrap();
+    assert_eq!(metadata["token"], titled);
+    assert_eq!(metadata["title"], "Deploy notes: v2");
+
+    // Binary pastes get no title, the token names them
+    let binary = create(&data, serde_json::json!({ "content": "AAEC/w==", "encoding": "base64" })).await;
+    let binary = binary["token"].as_str().unwrap();
+    let answer = call(&data, request().uri(&format!("/paste/{}/archive.zip", binary))).await;
+    let files = entries(&answer.body);
+    assert_eq!(files[0].0, format!("{}.bin", binary));
+    let metadata: serde_json::Value = serde_json::from_slice(&files[1].1).unwrap();
+    assert_eq!(metadata["title"], serde_json::Value::Null);
+}
+
+#[actix_rt::test]
+async fn downloads_can_be_named_from_the_title() {
+    let data = state_with(Config {
+        download_filename: Some("{title|token}.{ext}".to_string()),
+        ..Config::default()
+    });
+    let titled = create(&data, serde_json::json!({ "content": "step one\n", "title": "Deploy notes" })).await;
+    let answer = call(&data, request().uri(&format!("/paste/{}/download", titled["token"].as_str().unwrap()))).await;
+    assert_eq!(answer.header("Content-Disposition"), Some("attachment; filename=\"Deploy_notes.txt\""));
+    let binary = create(&data, serde_json::json!({ "content": "AAEC/w==", "encoding": "base64" })).await;
+    let binary = binary["token"].as_str().unwrap();
+    let answer = call(&data, request().uri(&format!("/paste/{}/download", binary))).await;
+    assert_eq!(answer.header("Content-Disposition"), Some(format!("attachment; filename=\"{}.bin\"", binary).as_str()));
+}
@@ -0,0 +1,183 @@
+// Operations stored whole or not at all (see `PasteStore`), on SQLite: each one is made to fail at every row it
+// writes in turn and must leave nothing of it behind, until it gets through.
+
+use super::*;
+use crate::store::sqlite::SqliteStore;
+use rusqlite::{params, Connection};
+use std::path::PathBuf;
+
+// Operations writing more rows than this are taken for a loop of the test.
+const MAX_WRITES: i64 = 100;
+
+// A SQLite database whose Nth row written fails: triggers on each of its tables count down the row of
+// `failure_injection`, and fail the write reaching zero. The error is a plain one of SQLite, neither a conflict nor a
+// busy database, which the handlers would take for something else.
+struct FailingDatabase {
+    path: PathBuf,
+    conn: Connection,
+}
+
+impl FailingDatabase {
+    // A new database named after the test, and the store of the server over it.
+    fn new(name: &str) -> (FailingDatabase, Box<dyn PasteStore>) {
+        let path = std::env::temp_dir().join(format!("pastry-tests-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteStore::open(&path, Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        conn.busy_timeout(Duration::from_secs(5)).unwrap();
+
+        let names = |query: &str| -> Vec<String> {
+            let mut statement = conn.prepare(query).unwrap();
+            let names = statement.query_map(params![], |row| row.get(0)).unwrap();
+            names.map(Result::unwrap).collect()
+        };
+        let tables = names("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'");
+        // Not the search index, which SQLite writes behind its own tables
+        let virtual_tables = names("SELECT name FROM sqlite_master WHERE sql LIKE 'CREATE VIRTUAL TABLE%'");
+
+        conn.execute_batch("CREATE TABLE failure_injection (remaining INTEGER); INSERT INTO failure_injection VALUES (NULL);")
+            .unwrap();
+        for table in tables {
+            if virtual_tables.iter().any(|name| table == *name || table.starts_with(&format!("{}_", name))) {
+                continue;
+            }
+            for write in ["INSERT", "UPDATE", "DELETE"] {
+                // `abs` of the smallest integer fails with an integer overflow
+                conn.execute_batch(&format!(
+                    "CREATE TRIGGER fail_{write}_{table} BEFORE {write} ON {table}
+                     WHEN (SELECT remaining FROM failure_injection) IS NOT NULL
+                     BEGIN
+                         UPDATE failure_injection SET remaining = remaining - 1;
+                         SELECT abs(remaining - 9223372036854775807 - 1) FROM failure_injection WHERE remaining = 0;
+                     END;",
+                    write = write,
+                    table = table,
+                ))
+                .unwrap();
+            }
+        }
+        (FailingDatabase { path, conn }, Box::new(store))
+    }
+
+    // Fails the `nth` row written from now on, none with `None`.
+    fn fail_at(&self, nth: Option<i64>) {
+        self.conn.execute("UPDATE failure_injection SET remaining = ?", params![nth]).unwrap();
+    }
+
+    // The rows of `table` matching `condition`.
+    fn rows(&self, table: &str, condition: &str) -> i64 {
+        let query = format!("SELECT COUNT(*) FROM {} WHERE {}", table, condition);
+        self.conn.query_row(&query, params![], |row| row.get(0)).unwrap()
+    }
+
+    // How many rows each table of the pastes has, with the audit log.
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        let tables = [
+            "pastes",
+            "paste_tags",
+            "paste_search_tokens",
+            "paste_search",
+            "paste_views_daily",
+            "ip_quota",
+            "banned_ips",
+            "audit_log",
+        ];
+        tables.iter().map(|table| (*table, self.rows(table, "1"))).collect()
+    }
+
+    // Sends `request` with each row it writes failing in turn. Each time it fails, checks with `unchanged` that it
+    // left the database as it was; returns what it answered when it got through.
+    async fn fail_each_write(
+        &self,
+        data: &web::Data<AppState>,
+        request: impl Fn() -> TestRequest,
+        unchanged: impl Fn(&FailingDatabase),
+    ) -> Answer {
+        for nth in 1..MAX_WRITES {
+            self.fail_at(Some(nth));
+            let answer = call(data, request()).await;
+            if answer.status != StatusCode::INTERNAL_SERVER_ERROR {
+                self.fail_at(None);
+                assert!(nth > 1, "no row was written: {}", answer.text());
+                return answer;
+            }
+            unchanged(self);
+        }
+        panic!("still failing after {} rows", MAX_WRITES);
+    }
+}
+
+impl Drop for FailingDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[actix_rt::test]
+async fn a_failed_create_leaves_no_rows() {
+    let (database, store) = FailingDatabase::new("failed-create");
+    let data = state_with_store(Config::default(), store);
+    let before = database.counts();
+    let paste = serde_json::json!({ "content": "words to search for", "public": true, "tags": ["one", "two"] });
+
+    let answer = database
+        .fail_each_write(&data, || create_request(paste.clone()), |database| assert_eq!(database.counts(), before))
+        .await;
+    assert_eq!(answer.status, StatusCode::CREATED, "{}", answer.text());
+    for table in ["pastes", "paste_search_tokens", "paste_search"] {
+        assert_eq!(database.rows(table, "1"), 1, "{}", table);
+    }
+    assert_eq!(database.rows("paste_tags", "1"), 2);
+}
+
+#[actix_rt::test]
+async fn a_failed_rejection_bans_nobody() {
+    let (database, store) = FailingDatabase::new("failed-rejection");
+    let config = Config {
+        review_pastes: Some(true),
+        ip_salt: Some("salt".to_string()),
+        ..admin_config()
+    };
+    let data = state_with_store(config, store);
+    let token = create(&data, serde_json::json!({ "content": "spam" })).await["token"].as_str().unwrap().to_string();
+    let before = database.counts();
+
+    let reject = || admin_request().method(Method::POST).uri(&format!("/admin/review/{}/reject?ban=true", token));
+    let answer = database.fail_each_write(&data, reject, |database| assert_eq!(database.counts(), before)).await;
+    assert_eq!(answer.status, StatusCode::NO_CONTENT, "{}", answer.text());
+    assert_eq!(database.rows("pastes", "1"), 0);
+    assert_eq!(database.rows("banned_ips", "1"), 1);
+}
+
+#[actix_rt::test]
+async fn a_failed_purge_deletes_nothing() {
+    let (database, store) = FailingDatabase::new("failed-purge");
+    let config = Config {
+        daily_paste_quota: Some(10),
+        ..admin_config()
+    };
+    let data = state_with_store(config, store);
+    for content in ["first", "second"] {
+        create(&data, serde_json::json!({ "content": content, "tags": ["old"] })).await;
+    }
+    // Expired and created on a day past the retention, and views of a paste past theirs, which go with the paste
+    // when it is purged
+    database
+        .conn
+        .execute_batch(
+            "UPDATE pastes SET expires_at = 1;
+             UPDATE ip_quota SET day = '2000-01-01';
+             INSERT INTO paste_views_daily (token, day, views) VALUES ('gone', '2000-01-01', 1);",
+        )
+        .unwrap();
+    let before = database.counts();
+    assert_eq!(database.rows("ip_quota", "1"), 1);
+
+    let purge = || admin_request().method(Method::POST).uri("/admin/purge");
+    let answer = database.fail_each_write(&data, purge, |database| assert_eq!(database.counts(), before)).await;
+    assert_eq!(answer.status, StatusCode::OK, "{}", answer.text());
+    assert_eq!(answer.json()["expired_pastes"], 2);
+    for table in ["pastes", "paste_tags", "paste_views_daily", "ip_quota"] {
+        assert_eq!(database.rows(table, "1"), 0, "{}", table);
+    }
+}
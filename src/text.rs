@@ -0,0 +1,212 @@
+// Clean-up of the text of new pastes, so what gets stored (and served back by raw/download)
+// is plain UTF-8 that looks the same on every platform, and the links of the paste page.
+
+use crate::images;
+use percent_encoding::percent_decode;
+
+// Cleans up the content of a text paste before it is stored:
+// drops a leading byte order mark, turns "\r\n" and lone "\r" into "\n" when `line_endings` is set,
+// and refuses NUL characters, which have no business in text and break many tools.
+pub fn normalize(content: String, line_endings: bool) -> Result<String, String> {
+    if content.contains('\0') {
+        return Err("Text pastes can't contain NUL characters, submit the file as a binary paste instead".to_string());
+    }
+
+    let content = match content.strip_prefix('\u{feff}') {
+        Some(rest) => rest.to_string(),
+        None => content,
+    };
+
+    if line_endings && content.contains('\r') {
+        Ok(content.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Ok(content)
+    }
+}
+
+// Checks that every field of an urlencoded form body decodes to valid UTF-8.
+// The form extractor replaces broken bytes with U+FFFD without telling, which would store mojibake.
+pub fn check_urlencoded_utf8(body: &[u8]) -> Result<(), String> {
+    for field in body.split(|byte| *byte == b'&') {
+        if percent_decode(field).decode_utf8().is_err() {
+            return Err("The form is not valid UTF-8, check the encoding of the page or file it was sent from".to_string());
+        }
+    }
+    Ok(())
+}
+
+// Turns the http(s) URLs of already escaped text into links, for the paste page.
+// Working on the escaped text keeps it safe: a URL ends at whitespace or at the entity of `<`, `>`, `"` or `'`,
+// so it can't leave the `href` attribute, and its text is put in the page in the escaped form it already has.
+// Punctuation right after a URL ("see https://example.com." or "(https://example.com)") isn't part of it:
+// trailing `.,;:!?`, a trailing `&` and closing brackets without their opening one are left out of the link.
+// The link of a URL of an image is followed by the image when the image policy shows it (see `images.rs`).
+pub fn linkify(escaped: &str, images: images::Policy) -> String {
+    let mut linked = String::with_capacity(escaped.len());
+    let mut copied = 0;
+    let mut from = 0;
+
+    while let Some(start) = next_url_start(escaped, from) {
+        let end = trim_url_end(escaped, start, url_end(escaped, start));
+        let scheme_end = start + escaped[start..].find("://").unwrap_or(0) + 3;
+        if end <= scheme_end {
+            from = scheme_end;
+            continue;
+        }
+
+        let url = &escaped[start..end];
+        linked.push_str(&escaped[copied..start]);
+        linked.push_str(&format!(
+            "<a href=\"{url}\" rel=\"noopener noreferrer nofollow\" target=\"_blank\">{url}</a>",
+            url = url
+        ));
+        if let Some(source) = images::image_source(url, images) {
+            linked.push_str(&format!(
+                "<img class=\"image\" src=\"{}\" alt=\"\" loading=\"lazy\" referrerpolicy=\"no-referrer\">",
+                source
+            ));
+        }
+        copied = end;
+        from = end;
+    }
+
+    linked.push_str(&escaped[copied..]);
+    linked
+}
+
+// Where the next "http://" or "https://" starting a word is, at or after `from`.
+fn next_url_start(text: &str, from: usize) -> Option<usize> {
+    text[from..]
+        .match_indices("http")
+        .map(|(index, _)| from + index)
+        .find(|&start| {
+            let rest = &text[start + 4..];
+            let after_word = text[..start]
+                .chars()
+                .next_back()
+                .map(|c| !c.is_alphanumeric())
+                .unwrap_or(true);
+            after_word && (rest.starts_with("://") || rest.starts_with("s://"))
+        })
+}
+
+// The end of the URL starting at `start`: the first whitespace or escaped `<`, `>`, `"` or `'`.
+fn url_end(text: &str, start: usize) -> usize {
+    const STOPS: &[&str] = &["&lt;", "&gt;", "&quot;", "&#39;"];
+    text[start..]
+        .char_indices()
+        .find(|&(index, c)| {
+            c.is_whitespace() || c.is_control() || STOPS.iter().any(|stop| text[start + index..].starts_with(stop))
+        })
+        .map(|(index, _)| start + index)
+        .unwrap_or(text.len())
+}
+
+// Drops the punctuation that ends the sentence a URL is in rather than the URL.
+fn trim_url_end(text: &str, start: usize, mut end: usize) -> usize {
+    loop {
+        let url = &text[start..end];
+        if url.ends_with("&amp;") {
+            end -= "&amp;".len();
+        } else if url.ends_with(['.', ',', ';', ':', '!', '?'])
+            || (url.ends_with(')') && url.matches(')').count() > url.matches('(').count())
+            || (url.ends_with(']') && url.matches(']').count() > url.matches('[').count())
+        {
+            end -= 1;
+        } else {
+            return end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linked(escaped: &str) -> String {
+        linkify(escaped, images::Policy::Strip)
+    }
+
+    fn link(url: &str) -> String {
+        format!("<a href=\"{url}\" rel=\"noopener noreferrer nofollow\" target=\"_blank\">{url}</a>", url = url)
+    }
+
+    #[test]
+    fn links_urls() {
+        assert_eq!(linked("see https://example.com/a?b=1 now"), format!("see {} now", link("https://example.com/a?b=1")));
+        assert_eq!(linked("http://example.com"), link("http://example.com"));
+        assert_eq!(linked("no links here"), "no links here");
+        // Only at the start of a word, and with something after the scheme
+        assert_eq!(linked("xhttp://example.com"), "xhttp://example.com");
+        assert_eq!(linked("https:// and http://"), "https:// and http://");
+        assert_eq!(linked("ftp://example.com"), "ftp://example.com");
+    }
+
+    #[test]
+    fn urls_stop_at_escaped_quotes_and_brackets() {
+        assert_eq!(
+            linked("href=&quot;https://example.com/x&quot;"),
+            format!("href=&quot;{}&quot;", link("https://example.com/x"))
+        );
+        assert_eq!(linked("&#39;https://example.com&#39;"), format!("&#39;{}&#39;", link("https://example.com")));
+        assert_eq!(linked("&lt;https://example.com&gt;"), format!("&lt;{}&gt;", link("https://example.com")));
+        assert_eq!(linked("https://example.com&lt;script"), format!("{}&lt;script", link("https://example.com")));
+    }
+
+    #[test]
+    fn trailing_punctuation_is_left_out() {
+        assert_eq!(linked("go to https://example.com."), format!("go to {}.", link("https://example.com")));
+        assert_eq!(linked("https://example.com/?!,;:"), format!("{}?!,;:", link("https://example.com/")));
+        assert_eq!(linked("a https://example.com/x&amp; b"), format!("a {}&amp; b", link("https://example.com/x")));
+        assert_eq!(linked("https://example.com/?a=1&amp;b=2"), link("https://example.com/?a=1&amp;b=2"));
+    }
+
+    #[test]
+    fn brackets_without_their_opening_are_left_out() {
+        assert_eq!(linked("(https://example.com)"), format!("({})", link("https://example.com")));
+        assert_eq!(linked("(see https://example.com/)."), format!("(see {}).", link("https://example.com/")));
+        assert_eq!(
+            linked("https://en.wikipedia.org/wiki/Rust_(language)"),
+            link("https://en.wikipedia.org/wiki/Rust_(language)")
+        );
+        assert_eq!(linked("[https://example.com/a[1]]"), format!("[{}]", link("https://example.com/a[1]")));
+    }
+
+    #[test]
+    fn several_urls() {
+        assert_eq!(
+            linked("https://a.example\nhttps://b.example"),
+            format!("{}\n{}", link("https://a.example"), link("https://b.example"))
+        );
+    }
+
+    #[test]
+    fn images_as_the_policy_says() {
+        let url = "https://example.com/cat.png?size=2&amp;x=1";
+        assert_eq!(linkify(url, images::Policy::Strip), link(url));
+        assert_eq!(
+            linkify(url, images::Policy::Render),
+            format!(
+                "{}<img class=\"image\" src=\"{}\" alt=\"\" loading=\"lazy\" referrerpolicy=\"no-referrer\">",
+                link(url),
+                url
+            )
+        );
+        assert!(linkify(url, images::Policy::Proxy)
+            .contains("src=\"/imgproxy?url=https%3A%2F%2Fexample%2Ecom%2Fcat%2Epng%3Fsize%3D2%26x%3D1\""));
+        assert_eq!(linkify("https://example.com/cat.html", images::Policy::Proxy), link("https://example.com/cat.html"));
+    }
+
+    #[test]
+    fn normalizes_text() {
+        assert_eq!(normalize("\u{feff}a\r\nb\rc".to_string(), true).unwrap(), "a\nb\nc");
+        assert_eq!(normalize("a\r\nb".to_string(), false).unwrap(), "a\r\nb");
+        assert!(normalize("a\0b".to_string(), true).is_err());
+    }
+
+    #[test]
+    fn urlencoded_must_be_utf8() {
+        assert!(check_urlencoded_utf8(b"content=%C3%A9&title=x").is_ok());
+        assert!(check_urlencoded_utf8(b"content=%E9").is_err());
+    }
+}
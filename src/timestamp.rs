@@ -0,0 +1,149 @@
+// The one way timestamps are shown. They are stored as Unix seconds (UTC) and leave the server as:
+// - RFC 3339 in UTC, “2024-01-31T12:00:00Z”, in JSON (`serialize` and `serialize_option` for the API structs);
+// - on pages, a `<time>` with that value in `datetime` (for browsers and extensions to localize),
+//   “2024-01-31 12:00 UTC” in `title` and “42 minutes ago” / “in 3 days” as its text;
+// - “2024-01-31 12:00 UTC” in plain text: error messages, the admin reports, the print view;
+// - “2024-01-31” in file names.
+
+use crate::i18n::Texts;
+use chrono::DateTime;
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+const MONTH: i64 = 30 * DAY;
+const YEAR: i64 = 365 * DAY;
+
+// “2024-01-31T12:00:00Z”, and `serialize` and `serialize_option`, are those of the wire format of the API.
+pub use pastry_crust::api::timestamp::{rfc3339, serialize, serialize_option};
+
+// “2024-01-31”, in the names of downloaded files
+pub fn date(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(time) => time.format("%Y-%m-%d").to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+// “2024-01-31 12:00 UTC”
+pub fn absolute(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(time) => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+// How far `timestamp` is from `now`, in its largest whole unit, in the words of `texts`: “42 minutes ago”, “in 3 days”.
+// Less than a minute is “just now” behind and “in less than a minute” ahead; months are 30 days, years 365.
+pub fn relative(timestamp: i64, now: i64, texts: Texts) -> String {
+    let distance = (timestamp - now).abs();
+    if distance < MINUTE {
+        return texts.text(if timestamp > now { "time.soon" } else { "time.just-now" }).to_string();
+    }
+    let (count, unit) = if distance < HOUR {
+        (distance / MINUTE, "time.minute")
+    } else if distance < DAY {
+        (distance / HOUR, "time.hour")
+    } else if distance < MONTH {
+        (distance / DAY, "time.day")
+    } else if distance < YEAR {
+        (distance / MONTH, "time.month")
+    } else {
+        (distance / YEAR, "time.year")
+    };
+    let amount = texts.plural(unit, count);
+    texts.fill(if timestamp > now { "time.in" } else { "time.ago" }, &[("amount", &amount)])
+}
+
+// The `<time>` of a page, relative to now.
+pub fn html(timestamp: i64, texts: Texts) -> String {
+    time_element(timestamp, &relative(timestamp, crate::store::now(), texts))
+}
+
+// The `<time>` of a page that outlives the moment, as the print view does on paper: the absolute time as its text.
+pub fn html_absolute(timestamp: i64) -> String {
+    time_element(timestamp, &absolute(timestamp))
+}
+
+fn time_element(timestamp: i64, text: &str) -> String {
+    format!(
+        "<time datetime=\"{}\" title=\"{}\">{}</time>",
+        rfc3339(timestamp),
+        absolute(timestamp),
+        crate::escape_html(text)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_706_702_400;
+
+    fn english(offset: i64) -> String {
+        relative(NOW + offset, NOW, Texts::english())
+    }
+
+    #[test]
+    fn less_than_a_minute() {
+        assert_eq!(english(0), "just now");
+        assert_eq!(english(-59), "just now");
+        assert_eq!(english(1), "in less than a minute");
+        assert_eq!(english(59), "in less than a minute");
+    }
+
+    #[test]
+    fn minute_and_hour_boundaries() {
+        assert_eq!(english(-MINUTE), "1 minute ago");
+        assert_eq!(english(MINUTE), "in 1 minute");
+        assert_eq!(english(-2 * MINUTE + 1), "1 minute ago");
+        assert_eq!(english(-HOUR + 1), "59 minutes ago");
+        assert_eq!(english(-HOUR), "1 hour ago");
+        assert_eq!(english(3 * HOUR), "in 3 hours");
+    }
+
+    #[test]
+    fn day_boundaries() {
+        assert_eq!(english(-DAY + 1), "23 hours ago");
+        assert_eq!(english(-DAY), "1 day ago");
+        assert_eq!(english(DAY - 1), "in 23 hours");
+        assert_eq!(english(DAY), "in 1 day");
+        assert_eq!(english(-2 * DAY), "2 days ago");
+    }
+
+    #[test]
+    fn months_and_years() {
+        assert_eq!(english(-29 * DAY), "29 days ago");
+        assert_eq!(english(-30 * DAY), "1 month ago");
+        assert_eq!(english(-364 * DAY), "12 months ago");
+        assert_eq!(english(-365 * DAY), "1 year ago");
+        assert_eq!(english(800 * DAY), "in 2 years");
+    }
+
+    #[test]
+    fn in_other_locales() {
+        let german = Texts::asked(&header("de"));
+        assert_eq!(relative(NOW - 3 * DAY, NOW, german), "vor 3 Tagen");
+        assert_eq!(relative(NOW + HOUR, NOW, german), "in 1 Stunde");
+        assert_eq!(relative(NOW, NOW, german), "gerade eben");
+    }
+
+    fn header(language: &str) -> actix_web::http::HeaderMap {
+        let mut headers = actix_web::http::HeaderMap::new();
+        headers.insert(actix_web::http::header::ACCEPT_LANGUAGE, language.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn absolute_times() {
+        assert_eq!(absolute(NOW), "2024-01-31 12:00 UTC");
+        assert_eq!(absolute(0), "1970-01-01 00:00 UTC");
+        assert_eq!(date(NOW), "2024-01-31");
+        assert_eq!(absolute(i64::MAX), "unknown");
+        assert_eq!(date(i64::MAX), "unknown");
+        assert_eq!(
+            html_absolute(NOW),
+            "<time datetime=\"2024-01-31T12:00:00Z\" title=\"2024-01-31 12:00 UTC\">2024-01-31 12:00 UTC</time>"
+        );
+    }
+}
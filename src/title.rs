@@ -0,0 +1,214 @@
+// The titles of pastes, shown on their page and in the listings instead of the bare token.
+// A paste gets the title given with it, from the form or the API; without one, it is made from the content:
+// - Markdown, whose first line is a heading (“# Notes” or one underlined with “===”): that heading;
+// - a script starting with a shebang line: its first line that isn't a comment, as its interpreter writes them;
+// - anything else: its first line that isn't a comment (“//”, “/* … */”, “<!-- … -->”, “-- ”, “;;”), or its first
+//   line when they all are.
+// A made up title is cut to `AUTO_CHARS` and marked as such (`title_auto` of the row), so that replacing the paste
+// makes it again from the new content, while a title someone gave stays. Binary pastes and short links get none.
+// Titles are plain text: control characters are left out, and pages escape them like anything else.
+
+pub const MAX_CHARS: usize = 100;
+
+// Longest title made from the content, in characters, the ellipsis included.
+pub const AUTO_CHARS: usize = 80;
+
+// The comment prefixes of scripts by their interpreter, the first one its name starts with, “python3” being a
+// “python”; any other, sh, python, ruby, perl…, writes them with “#”.
+const SCRIPT_COMMENTS: &[(&str, &[&str])] = &[
+    ("node", &["//", "/*", "*"]),
+    ("deno", &["//", "/*", "*"]),
+    ("bun", &["//", "/*", "*"]),
+    ("lua", &["--"]),
+    ("php", &["//", "#", "/*", "*"]),
+];
+
+// Comments that go on until their end marker, whatever the lines in between.
+const BLOCK_COMMENTS: &[(&str, &str)] = &[("/*", "*/"), ("<!--", "-->")];
+
+const LINE_COMMENTS: &[&str] = &["//", "-- ", ";;"];
+
+// A title given with a paste, trimmed; empty for none. Returns a message suitable for showing to the user when it is
+// not acceptable.
+pub fn check(title: &str) -> Result<String, String> {
+    let title = title.trim();
+    if title.chars().count() > MAX_CHARS {
+        return Err(format!("Titles are limited to {} characters", MAX_CHARS));
+    }
+    if title.chars().any(char::is_control) {
+        return Err("Titles can't contain control characters".to_string());
+    }
+    Ok(title.to_string())
+}
+
+// The title made from the text of a paste, `None` when it has no line to make one of.
+pub fn derive(content: &str) -> Option<String> {
+    let mut lines = content.lines().map(str::trim).filter(|line| !line.is_empty()).peekable();
+    let first = *lines.peek()?;
+
+    let title = if let Some(interpreter) = first.strip_prefix("#!").map(interpreter) {
+        let comments = SCRIPT_COMMENTS
+            .iter()
+            .find(|(name, _)| interpreter.starts_with(name))
+            .map_or(&["#"][..], |(_, comments)| comments);
+        lines.skip(1).find(|line| !comments.iter().any(|comment| line.starts_with(comment)))?.to_string()
+    } else if let Some(heading) = heading(first, content) {
+        heading
+    } else {
+        first_code_line(lines).unwrap_or_else(|| without_comment(first).to_string())
+    };
+    let title = clean(&title);
+    (!title.is_empty()).then_some(title)
+}
+
+// The name of the interpreter of a shebang line, what follows “env” for “#!/usr/bin/env python3”.
+fn interpreter(shebang: &str) -> &str {
+    let mut words = shebang.split_whitespace().map(|word| word.rsplit('/').next().unwrap_or(word));
+    match words.next() {
+        Some("env") => words.find(|word| !word.starts_with('-')).unwrap_or(""),
+        Some(name) => name,
+        None => "",
+    }
+}
+
+// The text of `first` when it is a Markdown heading: “# Notes” (up to six “#”, the closing ones left out), or a line
+// that the next one of `content` underlines with “=” or “-”.
+fn heading(first: &str, content: &str) -> Option<String> {
+    let hashes = first.len() - first.trim_start_matches('#').len();
+    if (1..=6).contains(&hashes) {
+        let text = first[hashes..].strip_prefix(|c: char| c.is_whitespace())?;
+        return Some(text.trim_end_matches('#').trim().to_string());
+    }
+    let mut lines = content.lines().map(str::trim).skip_while(|line| line.is_empty());
+    lines.next()?;
+    let underline = lines.next()?;
+    let setext = underline.len() >= 2 && (underline.chars().all(|c| c == '=') || underline.chars().all(|c| c == '-'));
+    setext.then(|| first.to_string())
+}
+
+// The first line of code that isn't in a comment, `None` when every line is.
+fn first_code_line<'a>(lines: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut block_end: Option<&str> = None;
+    for line in lines {
+        let mut rest = line;
+        if let Some(end) = block_end {
+            match rest.find(end) {
+                Some(at) => {
+                    rest = rest[at + end.len()..].trim();
+                    block_end = None;
+                }
+                None => continue,
+            }
+        }
+        if let Some((start, end)) = BLOCK_COMMENTS.iter().find(|(start, _)| rest.starts_with(start)) {
+            match rest[start.len()..].find(end) {
+                Some(at) => rest = rest[start.len() + at + end.len()..].trim(),
+                None => {
+                    block_end = Some(end);
+                    continue;
+                }
+            }
+        }
+        if rest.is_empty() || LINE_COMMENTS.iter().any(|comment| rest.starts_with(comment)) || rest == "--" {
+            continue;
+        }
+        return Some(rest.to_string());
+    }
+    None
+}
+
+// A line with the comment marker it starts with left out, for a paste that is all comments.
+fn without_comment(line: &str) -> &str {
+    BLOCK_COMMENTS
+        .iter()
+        .map(|(start, _)| *start)
+        .chain(LINE_COMMENTS.iter().copied())
+        .find_map(|comment| line.strip_prefix(comment.trim_end()))
+        .map_or(line, |rest| {
+            let rest = BLOCK_COMMENTS.iter().find_map(|(_, end)| rest.trim_end().strip_suffix(end)).unwrap_or(rest);
+            rest.trim()
+        })
+}
+
+// A title on one line: control characters left out, whitespace runs made one space, at most `AUTO_CHARS`.
+fn clean(title: &str) -> String {
+    let title = title
+        .split(|c: char| c.is_whitespace())
+        .map(|word| word.chars().filter(|c| !c.is_control()).collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if title.chars().count() <= AUTO_CHARS {
+        return title;
+    }
+    let cut: String = title.chars().take(AUTO_CHARS - 1).collect();
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_headings() {
+        assert_eq!(derive("# Notes\n\nsome text").as_deref(), Some("Notes"));
+        assert_eq!(derive("### Release 1.2 ###\n").as_deref(), Some("Release 1.2"));
+        assert_eq!(derive("Notes\n=====\ntext").as_deref(), Some("Notes"));
+        assert_eq!(derive("Notes\n--\ntext").as_deref(), Some("Notes"));
+        // Seven “#” or none followed by a space are no heading, nor is a “-” underline of one character
+        assert_eq!(derive("####### deep").as_deref(), Some("####### deep"));
+        assert_eq!(derive("#hashtag\nmore").as_deref(), Some("#hashtag"));
+        assert_eq!(derive("a - b\n-\nc").as_deref(), Some("a - b"));
+    }
+
+    #[test]
+    fn shebangs() {
+        assert_eq!(derive("#!/bin/sh\n# comment\necho hello\n").as_deref(), Some("echo hello"));
+        assert_eq!(derive("#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\nimport sys").as_deref(), Some("import sys"));
+        assert_eq!(derive("#!/usr/bin/env -S node --harmony\n// a script\nconsole.log(1)").as_deref(), Some("console.log(1)"));
+        assert_eq!(derive("#!/usr/bin/lua\n-- comment\nprint(1)").as_deref(), Some("print(1)"));
+        // A script of comments only has none
+        assert_eq!(derive("#!/bin/sh\n# only comments"), None);
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        assert_eq!(derive("// header\n/* licence\n   text */\nfn main() {}").as_deref(), Some("fn main() {}"));
+        assert_eq!(derive("<!-- generated --> <html>").as_deref(), Some("<html>"));
+        assert_eq!(derive("-- schema\n;; lisp\nSELECT 1;").as_deref(), Some("SELECT 1;"));
+        // All comments: the first one, without its marker
+        assert_eq!(derive("// just a note\n// another").as_deref(), Some("just a note"));
+        assert_eq!(derive("/* a block */").as_deref(), Some("a block"));
+    }
+
+    #[test]
+    fn leading_blank_lines_are_skipped() {
+        assert_eq!(derive("\n\n   \n  first line  \nsecond").as_deref(), Some("first line"));
+        assert_eq!(derive("\r\n\r\n# Title\r\n").as_deref(), Some("Title"));
+        assert_eq!(derive("\n\nNotes\n=====").as_deref(), Some("Notes"));
+    }
+
+    #[test]
+    fn whitespace_only_has_none() {
+        assert_eq!(derive(""), None);
+        assert_eq!(derive(" \n\t\n  \r\n"), None);
+    }
+
+    #[test]
+    fn titles_are_one_clean_line() {
+        assert_eq!(derive("a\tb   c\u{7}d").as_deref(), Some("a b cd"));
+        let long = derive(&"word ".repeat(40)).unwrap();
+        assert_eq!(long.chars().count(), AUTO_CHARS);
+        assert!(long.ends_with("word…"), "{}", long);
+        assert_eq!(derive(&"é".repeat(AUTO_CHARS)).unwrap().chars().count(), AUTO_CHARS);
+    }
+
+    #[test]
+    fn given_titles_are_checked() {
+        assert_eq!(check("  My paste  ").unwrap(), "My paste");
+        assert_eq!(check("").unwrap(), "");
+        assert!(check(&"a".repeat(MAX_CHARS)).is_ok());
+        assert!(check(&"a".repeat(MAX_CHARS + 1)).is_err());
+        assert!(check("two\nlines").is_err());
+    }
+}
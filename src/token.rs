@@ -0,0 +1,117 @@
+// How the tokens of new pastes are generated, picked at startup with `PASTRY_TOKEN_STRATEGY`:
+// - `alphanumeric`: 10 random letters and digits, the original scheme (e.g. "aZ3kP9qLm2")
+// - `uuid-v4`: a random UUID (e.g. "0b5c6a51-2f0e-4c1a-9d3e-7f2a9b8c1d4e")
+// - `words`: five random words joined by hyphens (e.g. "amber-otter-meadow-maple-river")
+// An unlisted paste is kept private by its token alone, and reads aren't rate-limited, so a token has to be too many
+// to try: three words of the 234 of `words.txt` were 23.6 bits, swept in hours. Five are 39.3 bits, about the three
+// words of a 7776-word list, at the price of tokens of 30 characters or so instead of 18.
+// Changing the strategy only affects new pastes, the routes accept any token,
+// so a database holding a mix of them keeps working.
+
+use rand::distributions::Alphanumeric;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use std::str::FromStr;
+
+const ALPHANUMERIC_LEN: usize = 10;
+
+const CHOSEN_MIN_LEN: usize = 3;
+const CHOSEN_MAX_LEN: usize = 64;
+const WORD_COUNT: usize = 5;
+
+// One word per line, lowercase letters only.
+const WORDS: &str = include_str!("words.txt");
+
+#[derive(Clone, Copy, Debug)]
+pub enum TokenGenerator {
+    Alphanumeric,
+    UuidV4,
+    Words,
+}
+
+impl TokenGenerator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenGenerator::Alphanumeric => "alphanumeric",
+            TokenGenerator::UuidV4 => "uuid-v4",
+            TokenGenerator::Words => "words",
+        }
+    }
+
+    // A new random token. Collisions are possible (mostly with `words`), callers check the token is free.
+    pub fn generate(&self) -> String {
+        let mut rng = thread_rng();
+        match self {
+            TokenGenerator::Alphanumeric => (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(ALPHANUMERIC_LEN)
+                .map(char::from)
+                .collect(),
+            TokenGenerator::UuidV4 => {
+                let mut bytes: [u8; 16] = rng.gen();
+                // Version 4 and the RFC 4122 variant
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+            }
+            TokenGenerator::Words => {
+                let words: Vec<&str> = WORDS.lines().filter(|word| !word.is_empty()).collect();
+                (0..WORD_COUNT)
+                    .map(|_| *words.choose(&mut rng).expect("the word list is not empty"))
+                    .collect::<Vec<&str>>()
+                    .join("-")
+            }
+        }
+    }
+}
+
+// Checks a token picked by a client: 3 to 64 ASCII letters, digits, `-` and `_`, which stay readable in a URL
+// and can't be mistaken for a route, and none of `reserved::RESERVED`.
+pub fn check_chosen(token: &str) -> Result<(), String> {
+    if token.len() < CHOSEN_MIN_LEN || token.len() > CHOSEN_MAX_LEN {
+        return Err(format!(
+            "A token must be {} to {} characters long",
+            CHOSEN_MIN_LEN, CHOSEN_MAX_LEN
+        ));
+    }
+    if !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("A token can only hold letters, digits, \"-\" and \"_\"".to_string());
+    }
+    if crate::reserved::is_reserved(token) {
+        return Err(format!("The token \"{}\" is reserved", token));
+    }
+    Ok(())
+}
+
+impl FromStr for TokenGenerator {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<TokenGenerator, String> {
+        match value {
+            "alphanumeric" => Ok(TokenGenerator::Alphanumeric),
+            "uuid-v4" => Ok(TokenGenerator::UuidV4),
+            "words" => Ok(TokenGenerator::Words),
+            _ => Err(format!("Unknown token strategy \"{}\", expected alphanumeric, uuid-v4 or words", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn word_tokens_are_too_many_to_sweep() {
+        let words: HashSet<&str> = WORDS.lines().filter(|word| !word.is_empty()).collect();
+        assert_eq!(words.len(), WORDS.lines().filter(|word| !word.is_empty()).count(), "a word is listed twice");
+        let bits = WORD_COUNT as f64 * (words.len() as f64).log2();
+        assert!(bits >= 38.0, "{} bits", bits);
+
+        let token = TokenGenerator::Words.generate();
+        assert_eq!(token.split('-').count(), WORD_COUNT, "{}", token);
+        assert!(token.split('-').all(|word| words.contains(word)), "{}", token);
+        assert!(check_chosen(&token).is_ok(), "{}", token);
+    }
+}
@@ -0,0 +1,35 @@
+// Build information baked into the binary by `build.rs`, served by `/version` and `/healthz`
+// and shown in the footer of the index page.
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("PASTRY_GIT_HASH");
+pub const BUILD_TIMESTAMP: &str = env!("PASTRY_BUILD_TIMESTAMP");
+pub const RUSTC_VERSION: &str = env!("PASTRY_RUSTC_VERSION");
+
+#[derive(serde::Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_timestamp: &'static str,
+    pub rustc_version: &'static str,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_hash: GIT_HASH,
+        build_timestamp: BUILD_TIMESTAMP,
+        rustc_version: RUSTC_VERSION,
+    }
+}
+
+// Short one-line form for page footers, e.g. "pastry_crust 0.1.0 (1a2b3c4d5e6f, built 2024-01-31T12:00:00Z)".
+pub fn footer() -> String {
+    format!(
+        "{} {} ({}, built {})",
+        env!("CARGO_PKG_NAME"),
+        VERSION,
+        GIT_HASH,
+        BUILD_TIMESTAMP
+    )
+}
@@ -0,0 +1,59 @@
+// Whether the long lines of a paste wrap on its page or scroll sideways, as the visitor last picked with the toggle
+// of the page. The choice is kept in the `wrap` cookie and applied by the server as a class of the `<pre>` of the
+// lines, so it holds from page to page and needs no JavaScript: the toggle is a link to “/paste/{token}/wrap”,
+// which sets the cookie and sends back to the page. The print view always wraps.
+
+use actix_web::HttpRequest;
+
+pub const COOKIE_NAME: &str = "wrap";
+
+// A year, every use of the toggle sets it again.
+const MAX_AGE_SECS: i64 = 365 * 24 * 60 * 60;
+
+#[derive(Clone, Copy, PartialEq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    #[default]
+    Wrap,
+    Scroll,
+}
+
+impl Wrap {
+    // The choice in the cookie of `req`, wrapping without one (or with one this version doesn't know).
+    pub fn from_request(req: &HttpRequest) -> Wrap {
+        crate::client::cookies(req.headers(), COOKIE_NAME)
+            .find_map(|value| match value {
+                "wrap" => Some(Wrap::Wrap),
+                "scroll" => Some(Wrap::Scroll),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    // Its class on the `<pre>`, and its value in the cookie.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Wrap::Wrap => "wrap",
+            Wrap::Scroll => "scroll",
+        }
+    }
+
+    pub fn other(self) -> Wrap {
+        match self {
+            Wrap::Wrap => Wrap::Scroll,
+            Wrap::Scroll => Wrap::Wrap,
+        }
+    }
+
+    // The `Set-Cookie` header keeping this choice, `Secure` when the request came over HTTPS.
+    // Scripts may read it, it says nothing about the visitor.
+    pub fn set_cookie(self, secure: bool) -> String {
+        format!(
+            "{}={}; Max-Age={}; Path=/; SameSite=Lax{}",
+            COOKIE_NAME,
+            self.as_str(),
+            MAX_AGE_SECS,
+            if secure { "; Secure" } else { "" }
+        )
+    }
+}
@@ -0,0 +1,293 @@
+// Zip archives of pastes, for “/paste/{token}/archive.zip”.
+// The archive is written as it is sent: every entry is deflated chunk by chunk and its CRC and sizes follow it
+// in a data descriptor, so neither the archive nor an entry is ever held whole, and nothing goes to disk.
+// The central directory at the end only keeps a few numbers per entry.
+// Entries are limited to 4 GiB and archives to 65535 entries, there is no Zip64.
+
+use actix_web::web;
+use chrono::{DateTime, Datelike, Timelike};
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use futures_util::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+// How much of a file is read at once.
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+
+// Bit 3: CRC and sizes in a descriptor after the data; bit 11: the name is UTF-8.
+const FLAGS: u16 = 0x0808;
+const DEFLATED: u16 = 8;
+// Version 2.0 of the format, the first with deflate, made on Unix so the permissions below are read.
+const VERSION_NEEDED: u16 = 20;
+const VERSION_MADE_BY: u16 = 0x0300 | VERSION_NEEDED;
+// A regular file, rw-r--r--
+const FILE_MODE: u32 = 0o100644;
+
+// Where the content of an entry comes from.
+pub enum Source {
+    Bytes(web::Bytes),
+    File(File),
+}
+
+pub struct Entry {
+    pub name: String,
+    // Unix timestamp, kept to the two seconds of DOS times
+    pub modified: i64,
+    pub source: Source,
+}
+
+// Turns a stored name into one every unzip is willing to write where it was asked to:
+// no directories, no “..”, no control characters, and something left at the end.
+pub fn entry_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c == ':' || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim_start_matches('.').trim();
+    if name.is_empty() {
+        "paste".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// The archive of `entries`, in order, as a stream of chunks for a response body.
+pub fn stream(entries: Vec<Entry>) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> + Unpin {
+    let archive = Archive {
+        writer: ZipWriter::default(),
+        pending: entries.into(),
+        reading: None,
+        done: false,
+    };
+    Box::pin(stream::unfold(archive, |mut archive| async move {
+        match archive.next_chunk().await {
+            Ok(Some(chunk)) => Some((Ok(web::Bytes::from(chunk)), archive)),
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!("Zip archive cut short: {}", e);
+                archive.done = true;
+                archive.pending.clear();
+                archive.reading = None;
+                Some((Err(actix_web::error::ErrorInternalServerError(e)), archive))
+            }
+        }
+    }))
+}
+
+struct Archive {
+    writer: ZipWriter,
+    pending: VecDeque<Entry>,
+    reading: Option<Source>,
+    done: bool,
+}
+
+impl Archive {
+    // The next bytes of the archive, none once it's complete.
+    async fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(source) = self.reading.take() {
+                let (source, data) = read_chunk(source).await?;
+                if data.is_empty() {
+                    return Ok(Some(self.writer.finish_file()?));
+                }
+                self.reading = Some(source);
+                let compressed = self.writer.write(&data)?;
+                if !compressed.is_empty() {
+                    return Ok(Some(compressed));
+                }
+            } else if let Some(entry) = self.pending.pop_front() {
+                self.reading = Some(entry.source);
+                return Ok(Some(self.writer.start_file(&entry.name, entry.modified)));
+            } else if !self.done {
+                self.done = true;
+                return Ok(Some(self.writer.finish()));
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+// Takes the next chunk off a source, empty at its end. Files are read on the blocking thread pool.
+async fn read_chunk(source: Source) -> io::Result<(Source, Vec<u8>)> {
+    match source {
+        Source::Bytes(mut bytes) => {
+            let chunk = bytes.split_to(bytes.len().min(READ_CHUNK_BYTES));
+            Ok((Source::Bytes(bytes), chunk.to_vec()))
+        }
+        Source::File(mut file) => {
+            let result = web::block(move || {
+                let mut chunk = vec![0; READ_CHUNK_BYTES];
+                let read = file.read(&mut chunk)?;
+                chunk.truncate(read);
+                Ok::<_, io::Error>((file, chunk))
+            })
+            .await;
+            match result {
+                Ok((file, chunk)) => Ok((Source::File(file), chunk)),
+                Err(actix_web::error::BlockingError::Error(e)) => Err(e),
+                Err(actix_web::error::BlockingError::Canceled) => Err(io::Error::other("the read was canceled")),
+            }
+        }
+    }
+}
+
+// What the central directory needs of an entry.
+struct Written {
+    name: String,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u32,
+    size: u32,
+    offset: u32,
+}
+
+struct Current {
+    written: Written,
+    crc: Crc,
+    encoder: DeflateEncoder<Vec<u8>>,
+    compressed: u64,
+}
+
+// The bytes of a zip file, one entry after the other; what it returns is sent as it is.
+#[derive(Default)]
+struct ZipWriter {
+    offset: u64,
+    entries: Vec<Written>,
+    current: Option<Current>,
+}
+
+impl ZipWriter {
+    // The local header of a new entry.
+    fn start_file(&mut self, name: &str, modified: i64) -> Vec<u8> {
+        let (time, date) = dos_time(modified);
+        let mut header = Vec::with_capacity(30 + name.len());
+        put_u32(&mut header, 0x04034b50);
+        put_u16(&mut header, VERSION_NEEDED);
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, DEFLATED);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        // CRC and sizes are in the descriptor
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, 0);
+        header.extend_from_slice(name.as_bytes());
+
+        self.current = Some(Current {
+            written: Written {
+                name: name.to_string(),
+                time,
+                date,
+                crc: 0,
+                compressed: 0,
+                size: 0,
+                offset: self.offset as u32,
+            },
+            crc: Crc::new(),
+            encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+            compressed: 0,
+        });
+        self.offset += header.len() as u64;
+        header
+    }
+
+    // Deflates the next part of the current entry, returns what the encoder let go of so far.
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let current = self.current.as_mut().expect("an entry was started");
+        current.crc.update(data);
+        current.encoder.write_all(data)?;
+        let compressed = std::mem::take(current.encoder.get_mut());
+        current.compressed += compressed.len() as u64;
+        self.offset += compressed.len() as u64;
+        Ok(compressed)
+    }
+
+    // The rest of the deflated data of the current entry and its descriptor.
+    fn finish_file(&mut self) -> io::Result<Vec<u8>> {
+        let current = self.current.take().expect("an entry was started");
+        let mut out = current.encoder.finish()?;
+        let compressed = current.compressed + out.len() as u64;
+        let size = current.crc.amount() as u64;
+        if compressed > u32::MAX as u64 || size > u32::MAX as u64 || self.offset > u32::MAX as u64 {
+            return Err(io::Error::other("zip archives without Zip64 are limited to 4 GiB"));
+        }
+
+        let mut written = current.written;
+        written.crc = current.crc.sum();
+        written.compressed = compressed as u32;
+        written.size = size as u32;
+        put_u32(&mut out, 0x08074b50);
+        put_u32(&mut out, written.crc);
+        put_u32(&mut out, written.compressed);
+        put_u32(&mut out, written.size);
+
+        self.offset += out.len() as u64;
+        self.entries.push(written);
+        Ok(out)
+    }
+
+    // The central directory and its end record, the last bytes of the archive.
+    fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for entry in &self.entries {
+            put_u32(&mut out, 0x02014b50);
+            put_u16(&mut out, VERSION_MADE_BY);
+            put_u16(&mut out, VERSION_NEEDED);
+            put_u16(&mut out, FLAGS);
+            put_u16(&mut out, DEFLATED);
+            put_u16(&mut out, entry.time);
+            put_u16(&mut out, entry.date);
+            put_u32(&mut out, entry.crc);
+            put_u32(&mut out, entry.compressed);
+            put_u32(&mut out, entry.size);
+            put_u16(&mut out, entry.name.len() as u16);
+            // extra field, comment, disk and internal attributes
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0);
+            put_u32(&mut out, FILE_MODE << 16);
+            put_u32(&mut out, entry.offset);
+            out.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let entries = self.entries.len() as u16;
+        let directory_size = out.len() as u32;
+        put_u32(&mut out, 0x06054b50);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, entries);
+        put_u16(&mut out, entries);
+        put_u32(&mut out, directory_size);
+        put_u32(&mut out, self.offset as u32);
+        put_u16(&mut out, 0);
+        out
+    }
+}
+
+// A Unix timestamp as the DOS time and date of zip headers, in UTC; DOS dates start in 1980.
+fn dos_time(timestamp: i64) -> (u16, u16) {
+    let time = DateTime::from_timestamp(timestamp, 0).unwrap_or_default().naive_utc();
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = time.year().min(2107) as u16 - 1980;
+    (
+        (time.hour() << 11 | time.minute() << 5 | (time.second() / 2)) as u16,
+        year << 9 | (time.month() << 5) as u16 | time.day() as u16,
+    )
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
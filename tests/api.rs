@@ -0,0 +1,42 @@
+// The API of a server started with `--ephemeral`, over its socket: what `src/tests` can't see, the answers of the
+// server itself, outside of the app.
+
+mod common;
+
+use common::Server;
+
+#[test]
+fn failures_are_json_envelopes_on_the_wire() {
+    let server = Server::start("api-failures", &["--ephemeral"], &[]);
+    let json = [("Content-Type", "application/json")];
+    for (method, path, headers, body, status) in [
+        ("GET", "/api/pastes/nosuchpaste", &[][..], "", 404),
+        ("GET", "/api/nothing/here", &[][..], "", 404),
+        ("POST", "/api/pastes", &json[..], "{\"content\":", 400),
+        ("POST", "/api/pastes", &[("Content-Type", "application/json"), ("Content-Encoding", "br")][..], "{}", 415),
+    ] {
+        let response = server.request(method, path, headers, body.as_bytes());
+        assert_eq!(response.status, status, "{} {}", method, path);
+        assert!(response.header("content-type").unwrap_or("").starts_with("application/json"), "{} {}", method, path);
+        let envelope = response.json();
+        assert!(envelope["error"]["code"].is_string(), "{}", response.text());
+    }
+}
+
+#[test]
+fn head_failures_have_no_body() {
+    let server = Server::start("api-head", &["--ephemeral"], &[]);
+    let response = server.request("HEAD", "/api/pastes/nosuchpaste", &[], b"");
+    assert_eq!(response.status, 404);
+    assert!(response.header("content-type").unwrap_or("").starts_with("application/json"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn too_big_bodies_are_refused_before_reading_them() {
+    let server = Server::start("api-too-big", &["--ephemeral"], &[("PASTRY_MAX_PASTE_BYTES", "1000")]);
+    let body = format!("{{\"content\":\"{}\"}}", "a".repeat(100_000));
+    let response = server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], body.as_bytes());
+    assert_eq!(response.status, 413);
+    assert_eq!(response.json()["error"]["code"], "payload_too_large");
+}
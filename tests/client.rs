@@ -0,0 +1,154 @@
+// `client::PastryClient` against a server started with `--ephemeral` on a port of its own: each call goes over the
+// socket and back through the structs of `api`, the errors through the envelope. Needs the `client` feature.
+
+mod common;
+
+use common::Server;
+use pastry_crust::api::{ApiBatchResult, ApiNewBatch, ApiNewPaste, SearchParams};
+use pastry_crust::client::{ClientError, PastryClient};
+
+const ADMIN_TOKEN: &str = "client test admin token";
+
+fn paste(content: &str) -> ApiNewPaste {
+    ApiNewPaste {
+        content: content.to_string(),
+        ..Default::default()
+    }
+}
+
+fn server(name: &str) -> (Server, String) {
+    let server = Server::start(name, &["--ephemeral"], &[("PASTRY_ADMIN_TOKEN", ADMIN_TOKEN)]);
+    let url = format!("http://{}", server.addr);
+    (server, url)
+}
+
+// The status and code of `error`, which has to be one of the envelope.
+fn envelope<T: std::fmt::Debug>(result: Result<T, ClientError>) -> (u16, String) {
+    match result {
+        Err(ClientError::Api { status, code, .. }) => (status, code),
+        other => panic!("Not an error of the API: {:?}", other),
+    }
+}
+
+#[actix_rt::test]
+async fn pastes_make_the_round_trip() {
+    let (_server, url) = server("client-round-trip");
+    let client = PastryClient::new(&format!("{}/", url));
+
+    let new = ApiNewPaste {
+        public: true,
+        tags: vec!["Rust".to_string(), "client".to_string()],
+        title: Some("Round trip".to_string()),
+        ..paste("fn main() {\n    println!(\"round trip\");\n}\n")
+    };
+    let created = client.create(&new).await.unwrap();
+    assert!(created.secret.is_some());
+    assert_eq!(created.url, format!("/paste/{}", created.token));
+    let fetched = client.get(&created.token).await.unwrap();
+    assert_eq!(fetched.token, created.token);
+    assert_eq!(fetched.content.as_deref(), Some(&new.content[..]));
+    assert_eq!(fetched.title.as_deref(), Some("Round trip"));
+    assert_eq!(fetched.tags, ["client", "rust"]);
+    assert!(fetched.public);
+    assert_eq!((fetched.lines, fetched.bytes), (3, new.content.len() as i64));
+    assert_eq!(fetched.expires_at, created.expires_at);
+
+    let results = client.search(&SearchParams { q: "println".to_string(), ..Default::default() }).await.unwrap();
+    let tokens: Vec<&str> = results.results.iter().map(|hit| hit.token.as_str()).collect();
+    assert_eq!(tokens, [created.token.as_str()]);
+    assert!(results.results[0].snippet.contains("println"), "{}", results.results[0].snippet);
+    let results = client
+        .search(&SearchParams {
+            q: "println".to_string(),
+            tag: Some("python".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert!(results.results.is_empty());
+
+    // Deleting takes the key, then the paste is gone
+    assert_eq!(envelope(client.delete(&created.token, None).await), (403, "forbidden".to_string()));
+    client.delete(&created.token, created.secret.as_deref()).await.unwrap();
+    assert_eq!(envelope(client.get(&created.token).await), (404, "not_found".to_string()));
+}
+
+#[actix_rt::test]
+async fn a_chosen_token_is_created_then_replaced_with_its_key() {
+    let (_server, url) = server("client-update");
+    let client = PastryClient::new(&url);
+
+    let created = client.update("client-notes", None, &paste("first")).await.unwrap();
+    assert_eq!(created.token, "client-notes");
+    let secret = created.secret.unwrap();
+    // The same paste again is left alone, another one needs the key
+    assert!(client.update("client-notes", None, &paste("first")).await.unwrap().secret.is_none());
+    assert_eq!(envelope(client.update("client-notes", None, &paste("second")).await), (409, "conflict".to_string()));
+    let replaced = client.update("client-notes", Some(&secret), &paste("second")).await.unwrap();
+    assert_eq!(replaced.secret.as_deref(), Some(&secret[..]));
+    assert_eq!(client.get("client-notes").await.unwrap().content.as_deref(), Some("second"));
+
+    assert_eq!(envelope(client.update("admin", None, &paste("reserved")).await).0, 400);
+}
+
+#[actix_rt::test]
+async fn a_batch_answers_for_each_paste() {
+    let (_server, url) = server("client-batch");
+    let client = PastryClient::new(&url);
+    let batch = ApiNewBatch {
+        pastes: vec![
+            paste("one"),
+            ApiNewPaste {
+                expires: Some("forever and a day".to_string()),
+                ..paste("two")
+            },
+            paste("three"),
+        ],
+        atomic: false,
+    };
+    let results = client.batch(&batch).await.unwrap();
+    assert_eq!(results.len(), 3);
+    for (result, content) in results.iter().zip([Some("one"), None, Some("three")]) {
+        match (result, content) {
+            (ApiBatchResult::Created(created), Some(content)) => {
+                assert_eq!(client.get(&created.token).await.unwrap().content.as_deref(), Some(content));
+            }
+            (ApiBatchResult::Failed { error }, None) => assert_eq!(error.code, "bad_request", "{}", error.message),
+            (result, _) => panic!("{:?} for {:?}", result, content),
+        }
+    }
+
+    let atomic = ApiNewBatch { atomic: true, ..batch };
+    assert_eq!(envelope(client.batch(&atomic).await), (400, "bad_request".to_string()));
+}
+
+#[actix_rt::test]
+async fn errors_are_typed() {
+    let (server, url) = server("client-errors");
+    let response = server.request(
+        "POST",
+        "/admin/tokens",
+        &[("Authorization", &format!("Bearer {}", ADMIN_TOKEN)), ("Content-Type", "application/json")],
+        br#"{"label": "reader", "scopes": ["read"]}"#,
+    );
+    assert_eq!(response.status, 201, "{}", response.text());
+    let reader = response.json()["token"].as_str().unwrap().to_string();
+
+    // A token without the scope is told which one it misses
+    match PastryClient::new(&url).with_api_key(&reader).create(&paste("refused")).await {
+        Err(ClientError::Api { status, code, scope, .. }) => {
+            assert_eq!((status, code.as_str(), scope.as_deref()), (403, "forbidden", Some("write")));
+        }
+        other => panic!("{:?}", other),
+    }
+    let error = PastryClient::new(&url).with_api_key("pst_nosuchtoken").get("anything").await.unwrap_err();
+    assert_eq!((error.status(), error.code()), (Some(401), Some("unauthorized")), "{}", error);
+
+    // Something else than the API, and no server at all
+    let error = PastryClient::new(&format!("{}/static", url)).get("anything").await.unwrap_err();
+    assert!(matches!(error, ClientError::Decode { status: 404, .. }), "{:?}", error);
+    drop(server);
+    let error = PastryClient::new(&url).get("anything").await.unwrap_err();
+    assert!(matches!(error, ClientError::Http(_)), "{:?}", error);
+    assert_eq!(error.status(), None);
+}
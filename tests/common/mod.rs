@@ -0,0 +1,174 @@
+// A server run as its own process, for the tests that need the real thing: the sockets, the command line, the
+// startup. Each one gets a directory of its own, where its backups and blobs go, and listens on a port of its own.
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+pub const BINARY: &str = env!("CARGO_BIN_EXE_pastry_crust");
+
+// A new empty directory under the target directory, removed first if an earlier run left it.
+pub fn directory(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// `pastry_crust` in `dir` with `args` and the variables `env`, the others of the environment removed so the
+// defaults apply.
+pub fn command(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Command {
+    let mut command = Command::new(BINARY);
+    command.current_dir(dir).args(args);
+    for (name, _) in std::env::vars().filter(|(name, _)| name.starts_with("PASTRY_")) {
+        command.env_remove(name);
+    }
+    command.envs(env.iter().copied());
+    command
+}
+
+// Runs a subcommand to its end.
+pub fn run(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    command(dir, args, env).output().unwrap()
+}
+
+pub struct Server {
+    pub addr: SocketAddr,
+    pub dir: PathBuf,
+    process: Child,
+}
+
+impl Server {
+    // Starts a server with `args` in the directory `name`, and waits for it to answer “/healthz”.
+    pub fn start(name: &str, args: &[&str], env: &[(&str, &str)]) -> Server {
+        Server::start_in(directory(name), args, env)
+    }
+
+    pub fn start_in(dir: PathBuf, args: &[&str], env: &[(&str, &str)]) -> Server {
+        let addr = free_port();
+        let bind = addr.to_string();
+        let mut env = env.to_vec();
+        env.push(("PASTRY_BIND", &bind));
+        let process = command(&dir, args, &env).stdout(Stdio::null()).stderr(Stdio::null()).spawn().unwrap();
+        let mut server = Server { addr, dir, process };
+        let started = Instant::now();
+        while server.try_request(&format!("GET /healthz HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", addr)).is_none() {
+            if let Some(status) = server.process.try_wait().unwrap() {
+                panic!("The server exited at startup with {}", status);
+            }
+            assert!(started.elapsed() < Duration::from_secs(20), "The server didn't start");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        server
+    }
+
+    // The memory the process of the server holds now, in bytes (`VmRSS`).
+    pub fn resident_bytes(&self) -> u64 {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.process.id())).unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmRSS:")).unwrap();
+        let kilobytes: u64 = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+        kilobytes * 1024
+    }
+
+    // Sends `method path` with `headers` and `body`, and returns the answer.
+    pub fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Response {
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, path, self.addr).into_bytes();
+        for (name, value) in headers {
+            request.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        request.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+        request.extend_from_slice(body);
+        self.send(&request)
+    }
+
+    pub fn get(&self, path: &str) -> Response {
+        self.request("GET", path, &[], b"")
+    }
+
+    // Sends the raw `request`, for the ones `request` can't write.
+    pub fn send(&self, request: &[u8]) -> Response {
+        self.try_request_bytes(request).expect("The server didn't answer")
+    }
+
+    fn try_request(&self, request: &str) -> Option<Response> {
+        self.try_request_bytes(request.as_bytes())
+    }
+
+    fn try_request_bytes(&self, request: &[u8]) -> Option<Response> {
+        let mut stream = TcpStream::connect(self.addr).ok()?;
+        stream.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+        stream.write_all(request).ok()?;
+        let mut answer = Vec::new();
+        stream.read_to_end(&mut answer).ok()?;
+        Response::parse(&answer)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+// A port nothing listens on, for a moment at least.
+fn free_port() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+pub struct Response {
+    pub status: u16,
+    // The names in lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn parse(answer: &[u8]) -> Option<Response> {
+        let end = answer.windows(4).position(|window| window == b"\r\n\r\n")?;
+        let head = std::str::from_utf8(&answer[..end]).ok()?;
+        let mut lines = head.split("\r\n");
+        let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        let mut response = Response {
+            status,
+            headers,
+            body: answer[end + 4..].to_vec(),
+        };
+        if response.header("transfer-encoding") == Some("chunked") {
+            response.body = unchunk(&response.body);
+        }
+        Some(response)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| panic!("{}: {}", e, self.text()))
+    }
+}
+
+fn unchunk(mut body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    while let Some(end) = body.windows(2).position(|window| window == b"\r\n") {
+        let size = usize::from_str_radix(std::str::from_utf8(&body[..end]).unwrap().trim(), 16).unwrap();
+        if size == 0 {
+            break;
+        }
+        decoded.extend_from_slice(&body[end + 2..end + 2 + size]);
+        body = &body[end + 2 + size + 2..];
+    }
+    decoded
+}
@@ -0,0 +1,125 @@
+// A server started on a database file cut short, the fixture of a damaged disk: the check at startup finds it, and
+// `PASTRY_DB_CHECK_FAILURE` decides whether the server refuses to start, serves it read-only or serves it as usual.
+// Either way a query reaching the missing pages answers a 500 and turns `/healthz` to 503, the workers keep serving.
+
+mod common;
+
+use common::Server;
+use rusqlite::Connection;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+const PASTES: usize = 200;
+
+// The fixture: a directory whose `pastes.db` had `PASTES` pastes, of several pages each, and lost the last half of
+// its bytes. Returns it with the tokens of the pastes, oldest first.
+// SQLite doesn't open a file shorter than the size in its header at all, with `keep_header` that is the fixture;
+// without, the size is taken out of the header like a file written by an older SQLite, and the tables are there with
+// pages missing, which the check finds.
+fn truncated_database(name: &str, keep_header: bool) -> (PathBuf, Vec<String>) {
+    let server = Server::start(name, &[], &[("PASTRY_DAILY_PASTE_QUOTA", "1000")]);
+    let tokens = (0..PASTES)
+        .map(|n| {
+            let body = format!("{{\"content\":\"paste {} {}\"}}", n, "filler ".repeat(1500));
+            let response = server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], body.as_bytes());
+            assert_eq!(response.status, 201, "{}", response.text());
+            response.json()["token"].as_str().unwrap().to_string()
+        })
+        .collect();
+    let dir = server.dir.clone();
+    drop(server);
+
+    // Everything in the file itself, then half of it gone
+    let path = dir.join("pastes.db");
+    let database = Connection::open(&path).unwrap();
+    database.execute_batch("PRAGMA wal_checkpoint(TRUNCATE); PRAGMA journal_mode = DELETE").unwrap();
+    drop(database);
+    let length = fs::metadata(&path).unwrap().len();
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(length / 2).unwrap();
+    if !keep_header {
+        // The size in pages, at offset 28
+        file.seek(SeekFrom::Start(28)).unwrap();
+        file.write_all(&[0; 4]).unwrap();
+    }
+    (dir, tokens)
+}
+
+// Reads every paste, with a few of the missing pages among them: each answer is the paste or a 500, and the corrupt
+// pages make `/healthz` answer 503 from the first one on.
+fn read_every_paste(server: &Server, tokens: &[String]) {
+    let healthz = server.get("/healthz");
+    assert_eq!(healthz.status, 200, "{}", healthz.text());
+    let mut failed = 0;
+    for (n, token) in tokens.iter().enumerate() {
+        let response = server.get(&format!("/paste/{}/raw", token));
+        match response.status {
+            200 => assert!(response.text().starts_with(&format!("paste {} ", n)), "{}", token),
+            500 => failed += 1,
+            status => panic!("{} for {}: {}", status, token, response.text()),
+        }
+    }
+    assert!(failed > 0, "every paste was read from a truncated database");
+    let healthz = server.get("/healthz");
+    assert_eq!(healthz.status, 503, "{}", healthz.text());
+    assert_eq!(healthz.json()["database"], "corrupt");
+    // Still serving what doesn't need the database
+    assert_eq!(server.get("/static/base.css").status, 200);
+}
+
+#[test]
+fn a_damaged_database_can_keep_the_server_from_starting() {
+    let (dir, _) = truncated_database("corrupt-refuse", false);
+    let mut process = common::command(&dir, &[], &[("PASTRY_DB_CHECK_FAILURE", "refuse")])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = process.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed() > Duration::from_secs(20) {
+            let _ = process.kill();
+            panic!("The server started on a damaged database");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(status.code(), Some(1));
+    let mut stderr = String::new();
+    process.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+    assert!(stderr.contains("the database is damaged"), "{}", stderr);
+    assert!(stderr.contains("Not starting on a damaged database"), "{}", stderr);
+}
+
+#[test]
+fn a_damaged_database_is_served_read_only() {
+    let (dir, tokens) = truncated_database("corrupt-read-only", false);
+    let env = [("PASTRY_DB_CHECK_FAILURE", "read-only"), ("PASTRY_CACHE_PRIME", "0")];
+    let server = Server::start_in(dir, &[], &env);
+    let response = server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], b"{\"content\":\"new\"}");
+    assert_eq!(response.status, 503, "{}", response.text());
+    assert_eq!(response.json()["error"]["code"], "unavailable");
+    read_every_paste(&server, &tokens);
+}
+
+#[test]
+fn a_damaged_database_is_served_as_usual_with_a_warning() {
+    let (dir, tokens) = truncated_database("corrupt-warn", false);
+    let env = [("PASTRY_DB_CHECK", "full"), ("PASTRY_CACHE_PRIME", "0")];
+    let server = Server::start_in(dir, &[], &env);
+    read_every_paste(&server, &tokens);
+}
+
+#[test]
+fn a_database_shorter_than_its_header_does_not_start() {
+    let (dir, _) = truncated_database("corrupt-header", true);
+    let output = common::run(&dir, &[], &[("PASTRY_DB_CHECK_FAILURE", "warn")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("database disk image is malformed"), "{}", stderr);
+}
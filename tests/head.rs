@@ -0,0 +1,82 @@
+// HEAD over the socket: the headers of GET, no body, and no view counted. The short forms of raw and download too.
+
+mod common;
+
+use common::{Response, Server};
+
+// The headers of a response but those that change from one to the next.
+fn lasting_headers(response: &Response) -> Vec<(String, String)> {
+    let mut headers: Vec<_> = response
+        .headers
+        .iter()
+        .filter(|(name, _)| name != "date" && name != "set-cookie" && name != "x-request-id")
+        .cloned()
+        .collect();
+    headers.sort();
+    headers
+}
+
+// The views of the paste, from its stats, which count none.
+fn views(server: &Server, token: &str, secret: &str) -> i64 {
+    let stats = server.get(&format!("/api/pastes/{}/stats?key={}", token, secret)).json();
+    stats["days"].as_array().unwrap().iter().map(|day| day["views"].as_i64().unwrap()).sum()
+}
+
+#[test]
+fn head_answers_the_headers_of_get() {
+    let server = Server::start("head", &["--ephemeral"], &[]);
+    let created = server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], br#"{"content":"hello\nworld\n"}"#);
+    assert_eq!(created.status, 201);
+    let token = created.json()["token"].as_str().unwrap().to_string();
+    let secret = created.json()["secret"].as_str().unwrap().to_string();
+
+    assert_eq!(views(&server, &token, &secret), 0);
+    for path in [
+        format!("/paste/{}", token),
+        format!("/paste/{}/raw", token),
+        format!("/paste/{}/download", token),
+        format!("/raw/{}", token),
+        format!("/download/{}", token),
+        format!("/api/pastes/{}", token),
+        "/paste/nosuchpaste".to_string(),
+        "/paste/nosuchpaste/raw".to_string(),
+        "/raw/nosuchpaste".to_string(),
+    ] {
+        // HEAD first: it counts no view, so the GET after it renders the views HEAD saw
+        let before = views(&server, &token, &secret);
+        let head = server.request("HEAD", &path, &[], b"");
+        assert_eq!(views(&server, &token, &secret), before, "{}: HEAD counted a view", path);
+        let get = server.get(&path);
+        assert_eq!(head.status, get.status, "{}", path);
+        assert_eq!(lasting_headers(&head), lasting_headers(&get), "{}", path);
+        assert!(head.body.is_empty(), "{}", path);
+        if get.status == 200 {
+            assert!(!get.body.is_empty(), "{}", path);
+            assert_eq!(head.header("content-length"), Some(get.body.len().to_string().as_str()), "{}", path);
+        }
+    }
+}
+
+#[test]
+fn get_counts_a_view() {
+    let server = Server::start("head-views", &["--ephemeral"], &[]);
+    let created = server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], br#"{"content":"hello"}"#);
+    let token = created.json()["token"].as_str().unwrap().to_string();
+    let secret = created.json()["secret"].as_str().unwrap().to_string();
+    server.get(&format!("/paste/{}", token));
+    assert_eq!(views(&server, &token, &secret), 1);
+}
+
+#[test]
+fn the_short_routes_answer_like_the_long_ones() {
+    let server = Server::start("head-short", &["--ephemeral"], &[]);
+    let created = server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], br#"{"content":"hello"}"#);
+    let token = created.json()["token"].as_str().unwrap().to_string();
+    for route in ["raw", "download"] {
+        let short = server.get(&format!("/{}/{}", route, token));
+        let long = server.get(&format!("/paste/{}/{}", token, route));
+        assert_eq!((short.status, &short.body), (200, &long.body), "{}", route);
+        assert_eq!(short.header("content-type"), long.header("content-type"));
+        assert_eq!(short.header("content-disposition"), long.header("content-disposition"));
+    }
+}
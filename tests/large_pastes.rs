@@ -0,0 +1,62 @@
+// A paste far bigger than what the server should hold per request: raw and download stream it from its blob file,
+// the memory of the server staying about the same however big the paste, and its page only says it is too large.
+
+mod common;
+
+use common::Server;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+const PASTE_BYTES: usize = 48 * 1024 * 1024;
+
+// How much the memory of the server may grow while it sends the paste, a fraction of the paste.
+const GROWTH_BYTES: u64 = 12 * 1024 * 1024;
+
+#[test]
+fn a_large_paste_is_streamed() {
+    let max = (PASTE_BYTES * 2).to_string();
+    let server = Server::start("large-pastes", &[], &[("PASTRY_MAX_PASTE_BYTES", &max)]);
+    let line = "0123456789abcdefghijklmnopqrstuvwxyz0123456789abcdefghijklmnopqrstuvwxyz0123456789abcdefghijklmnopqrstuvw\n";
+    let content = line.repeat(PASTE_BYTES / line.len());
+    let body = serde_json::json!({ "content": content }).to_string();
+    let created = server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], body.as_bytes());
+    assert_eq!(created.status, 201, "{}", created.text());
+    let token = created.json()["token"].as_str().unwrap().to_string();
+    drop(body);
+
+    for route in ["raw", "download"] {
+        let before = server.resident_bytes();
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        let request = format!("GET /paste/{}/{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", token, route, server.addr);
+        stream.write_all(request.as_bytes()).unwrap();
+
+        // Read slowly, so what the server holds of the paste at any time is the largest it gets
+        let (mut received, mut peak) = (0, before);
+        let mut chunk = vec![0; 256 * 1024];
+        loop {
+            let read = stream.read(&mut chunk).unwrap();
+            if read == 0 {
+                break;
+            }
+            received += read;
+            if received % (4 * 1024 * 1024) < read {
+                peak = peak.max(server.resident_bytes());
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+        }
+        assert!(received > content.len(), "/{}: {} bytes", route, received);
+        assert!(
+            peak < before + GROWTH_BYTES,
+            "/{} of {} bytes grew the server from {} to {} bytes",
+            route,
+            content.len(),
+            before,
+            peak
+        );
+    }
+
+    let page = server.get(&format!("/paste/{}", token));
+    assert_eq!(page.status, 200);
+    assert!(page.body.len() < 64 * 1024, "the page has {} bytes", page.body.len());
+    assert!(page.text().contains(&format!("/paste/{}/raw", token)));
+}
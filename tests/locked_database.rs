@@ -0,0 +1,48 @@
+// A server on a database file that another connection holds locked, like a `pastry_crust purge` or a backup running
+// beside it: the writes wait for the lock on the blocking pool, every one gets through once it is released, and the
+// workers keep serving what doesn't need the database meanwhile.
+
+mod common;
+
+use common::Server;
+use rusqlite::{params, Connection};
+use std::time::{Duration, Instant};
+
+const SUBMITS: usize = 48;
+const LOCKED: Duration = Duration::from_secs(2);
+
+#[test]
+fn concurrent_submits_wait_for_a_locked_database() {
+    let server = Server::start("locked-database", &[], &[("PASTRY_DAILY_PASTE_QUOTA", "1000")]);
+    let database = Connection::open(server.dir.join("pastes.db")).unwrap();
+    database.execute_batch("BEGIN IMMEDIATE").unwrap();
+    let locked_at = Instant::now();
+
+    std::thread::scope(|scope| {
+        let submits: Vec<_> = (0..SUBMITS)
+            .map(|n| {
+                let server = &server;
+                scope.spawn(move || {
+                    let body = format!("{{\"content\":\"paste number {}\"}}", n);
+                    server.request("POST", "/api/pastes", &[("Content-Type", "application/json")], body.as_bytes())
+                })
+            })
+            .collect();
+
+        // The workers aren't waiting on the lock with the submits
+        std::thread::sleep(Duration::from_millis(300));
+        let asked = Instant::now();
+        assert_eq!(server.get("/static/base.css").status, 200);
+        assert!(asked.elapsed() < Duration::from_secs(1), "a request without the database waited {:?}", asked.elapsed());
+        assert!(locked_at.elapsed() < LOCKED);
+
+        std::thread::sleep(LOCKED.saturating_sub(locked_at.elapsed()));
+        database.execute_batch("COMMIT").unwrap();
+        for submit in submits {
+            let response = submit.join().unwrap();
+            assert_eq!(response.status, 201, "{}", response.text());
+        }
+    });
+    let stored: i64 = database.query_row("SELECT COUNT(*) FROM pastes", params![], |row| row.get(0)).unwrap();
+    assert_eq!(stored, SUBMITS as i64);
+}
@@ -0,0 +1,48 @@
+// “/static/” over the socket, with the embedded assets and with `PASTRY_ASSETS_DIR`: nothing outside of the assets can
+// be read through it, whatever the path is written as. The server runs in a directory holding its database.
+
+mod common;
+
+use common::Server;
+
+const TRAVERSALS: &[&str] = &[
+    "/static/../pastes.db",
+    "/static/%2e%2e/pastes.db",
+    "/static/%2E%2E/pastes.db",
+    "/static/..%2fpastes.db",
+    "/static/%2e%2e%2fpastes.db",
+    "/static/.%2e/pastes.db",
+    "/static/..%5cpastes.db",
+    "/static/....//pastes.db",
+    "/static//../pastes.db",
+    "/static/base.css/../../pastes.db",
+    "/static/%252e%252e/pastes.db",
+];
+
+fn assert_refused(server: &Server) {
+    for path in TRAVERSALS {
+        let response = server.get(path);
+        assert!(response.status == 404 || response.status == 400, "{}: {}", path, response.status);
+        assert!(!response.body.starts_with(b"SQLite format 3"), "{} served the database", path);
+    }
+}
+
+#[test]
+fn embedded_assets_serve_nothing_else() {
+    let server = Server::start("static-embedded", &[], &[]);
+    assert!(server.dir.join("pastes.db").exists());
+    assert_eq!(server.get("/static/base.css").status, 200);
+    assert_refused(&server);
+}
+
+#[test]
+fn the_assets_directory_serves_nothing_else() {
+    let dir = common::directory("static-dir");
+    std::fs::create_dir(dir.join("assets")).unwrap();
+    std::fs::write(dir.join("assets/base.css"), "body { color: red }").unwrap();
+    let server = Server::start_in(dir, &[], &[("PASTRY_ASSETS_DIR", "assets")]);
+    let response = server.get("/static/base.css");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "body { color: red }");
+    assert_refused(&server);
+}